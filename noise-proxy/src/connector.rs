@@ -36,15 +36,23 @@ pub struct Error(#[from] ii_stratum::error::Error);
 pub struct Connector {
     /// Upstream authority public key that will be used to authenticate the endpoint
     upstream_authority_public_key: v2::noise::AuthorityPublicKey,
+    /// Optional pre-shared key required by the upstream
+    psk: Option<auth::PreSharedKey>,
 }
 
 impl Connector {
     pub fn with_key(key: auth::EncodedEd25519PublicKey) -> Self {
         Self {
             upstream_authority_public_key: key.into_inner(),
+            psk: None,
         }
     }
 
+    pub fn with_psk(mut self, psk: Option<auth::PreSharedKey>) -> Self {
+        self.psk = psk;
+        self
+    }
+
     /// Build framed tcp stream using l2-codec `C` producing frames `F`
     pub async fn connect<C, F>(
         self,
@@ -57,7 +65,8 @@ impl Connector {
         let noise_initiator = ii_stratum::v2::noise::Initiator::new(
             self.upstream_authority_public_key,
             vec![negotiation::EncryptionAlgorithm::AESGCM],
-        );
+        )
+        .with_psk(self.psk);
         trace!(
             "Stratum V2 noise connector: {:?}, {:?}",
             connection,
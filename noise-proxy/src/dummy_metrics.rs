@@ -35,4 +35,8 @@ impl NoiseProxyMetrics {
     pub fn account_failed_tcp_open(&self) {}
 
     pub fn account_tcp_close_in_stage(&self, _: &str) {}
+
+    pub fn set_certificate_remaining_validity(&self, _: i64) {}
+
+    pub fn account_rejected_handshake(&self) {}
 }
@@ -0,0 +1,100 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Periodic monitoring of the noise certificate expiry
+
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+use ii_async_utils::{Spawnable, Tripwire};
+use tokio::task::JoinHandle;
+
+use crate::{metrics, SecurityContext};
+
+/// How often the certificate is checked
+const CHECK_INTERVAL: Duration = Duration::from_secs(3600);
+
+/// Periodically warns about the approaching expiry of the certificate in `SecurityContext` and
+/// reports the remaining validity as a metric
+pub struct CertificateExpiryMonitor {
+    security_context: Arc<SecurityContext>,
+    /// Start warning when the certificate expires in less than this period
+    warning_period: Duration,
+    metrics: Arc<metrics::NoiseProxyMetrics>,
+}
+
+impl CertificateExpiryMonitor {
+    pub fn new(
+        security_context: Arc<SecurityContext>,
+        warning_period: Duration,
+        metrics: Arc<metrics::NoiseProxyMetrics>,
+    ) -> Self {
+        Self {
+            security_context,
+            warning_period,
+            metrics,
+        }
+    }
+
+    fn check(&self, now: SystemTime) {
+        match self.security_context.remaining_validity(now) {
+            Some(remaining) => {
+                self.metrics
+                    .set_certificate_remaining_validity(remaining.as_secs() as i64);
+                if remaining < self.warning_period {
+                    warn!(
+                        "Noise certificate expires in {} days ({:?}), renew it",
+                        remaining.as_secs() / (24 * 3600),
+                        self.security_context.not_valid_after()
+                    );
+                }
+            }
+            None => {
+                let expired_for = now
+                    .duration_since(self.security_context.not_valid_after())
+                    .unwrap_or_default();
+                self.metrics
+                    .set_certificate_remaining_validity(-(expired_for.as_secs() as i64));
+                error!(
+                    "Noise certificate expired at {:?}, renew it",
+                    self.security_context.not_valid_after()
+                );
+            }
+        }
+    }
+
+    async fn main_loop(self, tripwire: Tripwire) {
+        let mut interval = tokio::time::interval(CHECK_INTERVAL);
+        loop {
+            tokio::select! {
+                _ = interval.tick() => self.check(SystemTime::now()),
+                _ = tripwire.clone() => break,
+            }
+        }
+    }
+}
+
+impl Spawnable for CertificateExpiryMonitor {
+    fn run(self, tripwire: Tripwire) -> JoinHandle<()> {
+        tokio::spawn(self.main_loop(tripwire))
+    }
+}
@@ -24,18 +24,23 @@ use std::fmt;
 
 use std::convert::TryFrom;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 
+use bytes::{Bytes, BytesMut};
 use ii_stratum::v2::{
     self,
     noise::{
         auth::{Certificate, EncodedEd25519PublicKey, StaticSecretKeyFormat},
         negotiation::EncryptionAlgorithm::{ChaChaPoly, AESGCM},
-        CompoundCodec, Responder, StaticKeypair,
+        CompoundCodec, HandshakePermit, Responder, StaticKeypair,
     },
 };
-use tokio::{fs::File, io::AsyncReadExt, net::TcpStream};
-use tokio_util::codec::{Decoder, Encoder, Framed, FramedParts};
+use tokio::{
+    fs::File,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
+    net::TcpStream,
+};
+use tokio_util::codec::{Decoder, Encoder, Framed};
 
 #[derive(thiserror::Error, Debug)]
 pub enum Error {
@@ -53,14 +58,33 @@ pub enum Error {
 }
 pub type Result<T> = std::result::Result<T, Error>;
 
+/// Specifies how to treat a certificate that is outside of its validity period upon startup
+#[derive(serde::Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ExpiredCertificatePolicy {
+    /// Refuse to start
+    #[default]
+    Refuse,
+    /// Only log a warning and continue
+    Warn,
+}
+
 /// Security context is held by the server and provided to each (noise secured) connection so
 /// that it can successfully perform the noise handshake and authenticate itself to the client
 /// NOTE: this struct intentionally implements Debug manually to prevent leakage of the secure key
 /// into log messages
 pub struct SecurityContext {
-    /// Serialized Signature noise message that contains the necessary part of the certificate for
-    /// succesfully authenticating with the Initiator. We store it as Bytes as it will be shared
-    /// to among all incoming connections
+    /// Certificates with their secret keys in the order of preference. The Initiator cannot
+    /// announce which authority it trusts before the Responder presents its static key in the
+    /// noise NX handshake, therefore each handshake is served with the first certificate that is
+    /// valid at that time. This allows loading a renewed certificate ahead of its validity period
+    certified_keys: Vec<CertifiedKey>,
+    /// Optional pre-shared key that the Initiator has to know to complete the handshake
+    psk: Option<v2::noise::auth::PreSharedKey>,
+}
+
+/// Certificate and the secret key of the static public key that it certifies
+struct CertifiedKey {
     certificate: v2::noise::auth::Certificate,
     secret_key: v2::noise::auth::StaticSecretKeyFormat,
 }
@@ -96,7 +120,7 @@ pub struct SecurityContext {
 impl fmt::Debug for SecurityContext {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let certificate_authority = self.authority_pubkey();
-        let expiry_timestamp = self.certificate.validate(SystemTime::now).map_or_else(
+        let expiry_timestamp = self.validate_by_time(SystemTime::now).map_or_else(
             |_| "certificate is invalid".to_owned(),
             |t| {
                 let expiration_time = t
@@ -116,20 +140,136 @@ impl SecurityContext {
     fn from_certificate_and_secret_key(
         certificate: v2::noise::auth::Certificate,
         secret_key: v2::noise::auth::StaticSecretKeyFormat,
-    ) -> Self {
-        // certificate
-        //     .validate(SystemTime::now)
-        //     .map_err(|e| Error::NoiseInitError(e.to_string()))?;
-        // TODO secret key validation is currently not possible
-        // let public_key = certificate.validate_secret_key(&secret_key)?;
-        Self {
-            certificate,
-            secret_key,
+    ) -> Result<Self> {
+        Self::from_certificates_and_secret_keys(vec![(certificate, secret_key)])
+    }
+
+    /// Builds the context from certificates and their secret keys ordered by preference
+    pub fn from_certificates_and_secret_keys(
+        certificates_and_secret_keys: Vec<(
+            v2::noise::auth::Certificate,
+            v2::noise::auth::StaticSecretKeyFormat,
+        )>,
+    ) -> Result<Self> {
+        if certificates_and_secret_keys.is_empty() {
+            return Err(Error::NoiseInitError("No certificate provided".to_owned()));
         }
+        let certified_keys = certificates_and_secret_keys
+            .into_iter()
+            .map(|(certificate, secret_key)| {
+                certificate
+                    .validate_secret_key(&secret_key)
+                    .map_err(|e| Error::NoiseInitError(e.to_string()))?;
+                Ok(CertifiedKey {
+                    certificate,
+                    secret_key,
+                })
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self {
+            certified_keys,
+            psk: None,
+        })
+    }
+
+    /// Requires the Initiator to mix `psk` into the handshake (noise `psk0` modifier)
+    pub fn with_psk(mut self, psk: Option<v2::noise::auth::PreSharedKey>) -> Self {
+        self.psk = psk;
+        self
+    }
+
+    /// Appends certificates of `other` with lower preference, typically a renewed certificate
+    /// that is to be served once the current one expires
+    /// ```
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use ii_noise_proxy::SecurityContext;
+    /// let secret_key = r#"{
+    ///   "noise_secret_key": "2owBcKCGg7k46rTUYEwNEKJsnT2TqYDtFsMAuicrsLXhi3VwK4"
+    /// }"#;
+    /// let ctx = SecurityContext::read_from_strings(r#"{
+    ///   "signed_part_header": {
+    ///     "version": 0,
+    ///     "valid_from": 1612897727,
+    ///     "not_valid_after": 1612954827
+    ///   },
+    ///   "public_key": {
+    ///     "noise_public_key": "2Nki8zRNjrYLdcGbRLFrTbwLsDfKSiDMsiK3UWGTJNJpaPjAZW"
+    ///   },
+    ///   "authority_public_key": {
+    ///     "ed25519_public_key": "2eMjqMKXXFjhY1eAdvnmhk3xuWYdPpawYSWXXabPxVmCdeuWx"
+    ///   },
+    ///   "signature": {
+    ///     "ed25519_signature": "ZAefGhUNHn6u26Vob5T4UM32mH9Wujx7oDR1bmf4ei6cVNvrFtbaNkSvdRyJz13KdU92tK3DrdcG4AwfSAuj7MXRFdKLE"
+    ///   }
+    /// }"#.to_owned(), secret_key.to_owned()).expect("BUG: Failed to parse certificate");
+    /// let renewed_ctx = SecurityContext::read_from_strings(r#"{
+    ///   "signed_part_header": {
+    ///     "version": 0,
+    ///     "valid_from": 1613145976,
+    ///     "not_valid_after": 2477145976
+    ///   },
+    ///   "public_key": {
+    ///     "noise_public_key": "2Nki8zRNjrYLdcGbRLFrTbwLsDfKSiDMsiK3UWGTJNJpaPjAZW"
+    ///   },
+    ///   "authority_public_key": {
+    ///     "ed25519_public_key": "2eMjqMKXXFjhY1eAdvnmhk3xuWYdPpawYSWXXabPxVmCdeuWx"
+    ///   },
+    ///   "signature": {
+    ///     "ed25519_signature": "AdrgZxKNM3wCQmv5q3aTn8T96DV6egAYYFQRgcxuQjfiKvraR2xp3pNLRuDTvwQApYZc6YXnwbxXzUdHbGxaxSMq4g67c"
+    ///   }
+    /// }"#.to_owned(), secret_key.to_owned()).expect("BUG: Failed to parse certificate");
+    ///
+    /// let ctx = ctx.with_rollover(renewed_ctx);
+    /// assert_eq!(
+    ///     ctx.validate_by_time(|| UNIX_EPOCH + Duration::from_secs(1612954826)).ok(),
+    ///     Some(UNIX_EPOCH + Duration::from_secs(1612954827)),
+    ///     "BUG: Current certificate should be served"
+    /// );
+    /// assert_eq!(
+    ///     ctx.validate_by_time(|| UNIX_EPOCH + Duration::from_secs(1613145977)).ok(),
+    ///     Some(UNIX_EPOCH + Duration::from_secs(2477145976)),
+    ///     "BUG: Renewed certificate should be served"
+    /// );
+    /// assert_eq!(ctx.not_valid_after(), UNIX_EPOCH + Duration::from_secs(2477145976));
+    /// ```
+    pub fn with_rollover(mut self, other: Self) -> Self {
+        self.certified_keys.extend(other.certified_keys);
+        self
+    }
+
+    /// Selects the first certificate that is valid at `now`. The most preferred certificate is
+    /// used when none is valid so that the handshake fails on the Initiator side with a proper
+    /// error
+    fn active_certified_key(&self, now: SystemTime) -> &CertifiedKey {
+        self.certified_keys
+            .iter()
+            .find(|certified_key| certified_key.certificate.validate(|| now).is_ok())
+            .unwrap_or_else(|| {
+                self.certified_keys
+                    .first()
+                    .expect("BUG: security context without certificate")
+            })
     }
 
     fn authority_pubkey(&self) -> EncodedEd25519PublicKey {
-        EncodedEd25519PublicKey::new(self.certificate.authority_public_key.clone().into_inner())
+        let certificate = &self.active_certified_key(SystemTime::now()).certificate;
+        EncodedEd25519PublicKey::new(certificate.authority_public_key.clone().into_inner())
+    }
+
+    /// Builds the signature noise message and the static keypair for a single handshake
+    fn responder_credentials(&self) -> Result<(Bytes, StaticKeypair)> {
+        let certified_key = self.active_certified_key(SystemTime::now());
+        let signature_noise_message = certified_key
+            .certificate
+            .build_noise_message()
+            .serialize_to_bytes_mut()
+            .map_err(|e| Error::KeySerializationError(e.to_string()))?
+            .freeze();
+        let static_key_pair = StaticKeypair {
+            private: certified_key.secret_key.clone().into_inner(),
+            public: certified_key.certificate.public_key.clone().into_inner(),
+        };
+        Ok((signature_noise_message, static_key_pair))
     }
 
     /// Returns remaining time of certificate validity or error if the certificate has expired
@@ -171,11 +311,99 @@ impl SecurityContext {
     where
         FN: FnOnce() -> SystemTime,
     {
-        self.certificate
-            .validate(get_current_time)
+        let now = get_current_time();
+        self.active_certified_key(now)
+            .certificate
+            .validate(|| now)
             .map_err(|_| Error::TimeValidationError)
     }
 
+    /// Start of the validity period of the earliest certificate
+    pub fn not_valid_before(&self) -> SystemTime {
+        self.certified_keys
+            .iter()
+            .map(|certified_key| certified_key.certificate.signed_part_header.valid_from())
+            .min()
+            .expect("BUG: security context without certificate")
+    }
+
+    /// End of the validity period of the latest certificate
+    pub fn not_valid_after(&self) -> SystemTime {
+        self.certified_keys
+            .iter()
+            .map(|certified_key| {
+                certified_key
+                    .certificate
+                    .signed_part_header
+                    .not_valid_after()
+            })
+            .max()
+            .expect("BUG: security context without certificate")
+    }
+
+    /// Returns the time that remains until the certificate expires or `None` if it has already
+    /// expired at `now`
+    pub fn remaining_validity(&self, now: SystemTime) -> Option<Duration> {
+        self.not_valid_after().duration_since(now).ok()
+    }
+
+    /// Checks the certificate validity period at `now` and applies `policy` when the certificate
+    /// is not valid
+    /// ```
+    /// use std::time::{Duration, UNIX_EPOCH};
+    /// use ii_noise_proxy::{ExpiredCertificatePolicy, SecurityContext};
+    /// let ctx = SecurityContext::read_from_strings(r#"{
+    ///   "signed_part_header": {
+    ///     "version": 0,
+    ///     "valid_from": 1612897727,
+    ///     "not_valid_after": 1612954827
+    ///   },
+    ///   "public_key": {
+    ///     "noise_public_key": "2Nki8zRNjrYLdcGbRLFrTbwLsDfKSiDMsiK3UWGTJNJpaPjAZW"
+    ///   },
+    ///   "authority_public_key": {
+    ///     "ed25519_public_key": "2eMjqMKXXFjhY1eAdvnmhk3xuWYdPpawYSWXXabPxVmCdeuWx"
+    ///   },
+    ///   "signature": {
+    ///     "ed25519_signature": "ZAefGhUNHn6u26Vob5T4UM32mH9Wujx7oDR1bmf4ei6cVNvrFtbaNkSvdRyJz13KdU92tK3DrdcG4AwfSAuj7MXRFdKLE"
+    ///   }
+    /// }"#.to_owned(), r#"{
+    ///   "noise_secret_key": "2owBcKCGg7k46rTUYEwNEKJsnT2TqYDtFsMAuicrsLXhi3VwK4"
+    /// }"#.to_owned()).expect("BUG: Failed to parse certificate");
+    ///
+    /// let after_expiration = UNIX_EPOCH + Duration::from_secs(1612954828);
+    ///
+    /// assert_eq!(
+    ///     ctx.remaining_validity(UNIX_EPOCH + Duration::from_secs(1612954727)),
+    ///     Some(Duration::from_secs(100))
+    /// );
+    /// assert_eq!(ctx.remaining_validity(after_expiration), None);
+    /// assert!(
+    ///     ctx.check_validity(ExpiredCertificatePolicy::Refuse, after_expiration).is_err(),
+    ///     "BUG: Expired certificate should be refused"
+    /// );
+    /// assert!(
+    ///     ctx.check_validity(ExpiredCertificatePolicy::Warn, after_expiration).is_ok(),
+    ///     "BUG: Expired certificate should only be reported"
+    /// );
+    /// ```
+    pub fn check_validity(&self, policy: ExpiredCertificatePolicy, now: SystemTime) -> Result<()> {
+        match self.validate_by_time(|| now) {
+            Ok(_) => Ok(()),
+            Err(e) => match policy {
+                ExpiredCertificatePolicy::Refuse => Err(e),
+                ExpiredCertificatePolicy::Warn => {
+                    warn!(
+                        "Noise certificate is not valid (valid from: {:?}, not valid after: {:?})",
+                        self.not_valid_before(),
+                        self.not_valid_after()
+                    );
+                    Ok(())
+                }
+            },
+        }
+    }
+
     pub fn read_from_strings(certificate: String, secret_key: String) -> Result<Self> {
         let cert = Certificate::try_from(certificate)
             .map_err(|e| Error::KeySerializationError(e.to_string()))?;
@@ -183,7 +411,7 @@ impl SecurityContext {
         let key = StaticSecretKeyFormat::try_from(secret_key)
             .map_err(|e| Error::KeySerializationError(e.to_string()))?;
 
-        Ok(SecurityContext::from_certificate_and_secret_key(cert, key))
+        SecurityContext::from_certificate_and_secret_key(cert, key)
     }
 
     pub async fn read_from_file(certificate_file: &Path, secret_key_file: &Path) -> Result<Self> {
@@ -206,61 +434,37 @@ impl SecurityContext {
         C: Default + Decoder + Encoder<F>,
         <C as tokio_util::codec::Encoder<F>>::Error: Into<ii_stratum::error::Error>,
     {
-        // TODO: consolidate the two functions build_framed_tcp and build_framed_tcp_from_parts
-        // Note that Responder construction cannot be moved to a separate function because
-        // it contains reference to a static_key_pair
-        let signature_noise_message = self
-            .certificate
-            .build_noise_message()
-            .serialize_to_bytes_mut()
-            .map_err(|e| Error::KeySerializationError(e.to_string()))?
-            .freeze();
-        let static_key_pair = StaticKeypair {
-            private: self.secret_key.clone().into_inner(),
-            public: self.certificate.public_key.clone().into_inner(),
-        };
-        let responder = Responder::new(
-            &static_key_pair,
-            signature_noise_message,
-            vec![AESGCM, ChaChaPoly],
-        );
-        responder
-            .accept_with_codec(tcp_stream, |noise_codec| {
-                CompoundCodec::<C>::new(Some(noise_codec))
-            })
-            .await
-            .map_err(|e| Error::NoiseInitError(e.to_string()))
+        self.build_framed(tcp_stream, None, None).await
     }
 
-    pub async fn build_framed_tcp_from_parts<C, F, P>(
+    /// Runs noise handshake over `stream` and provides a framed stream with codec `C`.
+    /// `read_buf` - data already read from the stream, e.g. when parsing a PROXY protocol header
+    /// `handshake_permit` - permit from a `HandshakeLimiter` that is held during the handshake
+    pub async fn build_framed<T, C, F>(
         &self,
-        parts: P,
-    ) -> Result<Framed<TcpStream, CompoundCodec<C>>>
+        stream: T,
+        read_buf: Option<BytesMut>,
+        handshake_permit: Option<HandshakePermit>,
+    ) -> Result<Framed<T, CompoundCodec<C>>>
     where
+        T: AsyncRead + AsyncWrite + Unpin,
         C: Default + Decoder + Encoder<F>,
         <C as tokio_util::codec::Encoder<F>>::Error: Into<ii_stratum::error::Error>,
-        P: Into<FramedParts<TcpStream, v2::noise::Codec>>,
     {
-        let signature_noise_message = self
-            .certificate
-            .build_noise_message()
-            .serialize_to_bytes_mut()
-            .map_err(|e| Error::KeySerializationError(e.to_string()))?
-            .freeze();
-        let static_key_pair = StaticKeypair {
-            private: self.secret_key.clone().into_inner(),
-            public: self.certificate.public_key.clone().into_inner(),
-        };
-        let responder = Responder::new(
+        // Note that Responder construction cannot be moved to a separate function because
+        // it contains reference to a static_key_pair
+        let (signature_noise_message, static_key_pair) = self.responder_credentials()?;
+        let mut responder = Responder::new(
             &static_key_pair,
             signature_noise_message,
             vec![AESGCM, ChaChaPoly],
         );
+        responder = responder.with_psk(self.psk.clone());
+        if let Some(handshake_permit) = handshake_permit {
+            responder = responder.with_handshake_permit(handshake_permit);
+        }
         responder
-            // TODO this needs refactoring there is no point of passing the codec
-            // type, we should be able to run noise just with anything that
-            // implements AsyncRead/AsyncWrite
-            .accept_parts_with_codec(parts, |noise_codec| {
+            .accept_buffered_with_codec(stream, read_buf, |noise_codec| {
                 CompoundCodec::<C>::new(Some(noise_codec))
             })
             .await
@@ -30,6 +30,7 @@ use anyhow::{anyhow, Result};
 use futures::{sink::SinkExt, stream::StreamExt};
 use ii_async_utils::{Spawnable, Tripwire};
 use ii_stratum::v1;
+use ii_stratum::v2::noise::HandshakeLimiter;
 use ii_wire::proxy::{self, Connector, ProxyInfo, WithProxyInfo};
 use tokio::{
     net::{TcpListener, TcpStream, ToSocketAddrs},
@@ -38,12 +39,14 @@ use tokio::{
 use tokio_util::codec::{Decoder, Encoder, Framed};
 
 pub mod connector;
+mod expiry;
 mod framing;
 mod frontend;
 #[cfg_attr(not(feature = "prometheus_metrics"), path = "dummy_metrics.rs")]
 pub mod metrics;
 
-pub use frontend::{Error, SecurityContext};
+pub use expiry::CertificateExpiryMonitor;
+pub use frontend::{Error, ExpiredCertificatePolicy, SecurityContext};
 
 pub struct NoiseProxy {
     upstream: SocketAddr,
@@ -54,6 +57,8 @@ pub struct NoiseProxy {
     /// Server will use this version for talking to upstream server (when defined)
     proxy_protocol_upstream_version: Option<proxy::ProtocolVersion>,
     metrics: Arc<metrics::NoiseProxyMetrics>,
+    /// Optional cap on the number of concurrently running handshakes
+    handshake_limiter: Option<HandshakeLimiter>,
 }
 
 impl NoiseProxy {
@@ -85,9 +90,17 @@ impl NoiseProxy {
             ),
             proxy_protocol_upstream_version,
             metrics,
+            handshake_limiter: None,
         })
     }
 
+    /// Connections that arrive while `handshake_limiter` has no permits left are closed before
+    /// the noise handshake starts
+    pub fn with_handshake_limiter(mut self, handshake_limiter: Option<HandshakeLimiter>) -> Self {
+        self.handshake_limiter = handshake_limiter;
+        self
+    }
+
     pub async fn main_loop(mut self, tripwire: Tripwire) {
         let listener = self.listener.take().expect("BUG: missing tcp listener");
         info!(
@@ -119,6 +132,7 @@ impl NoiseProxy {
                         self.upstream,
                         tripwire.clone(),
                         self.metrics.clone(),
+                        self.handshake_limiter.clone(),
                     );
                     tokio::spawn(connection.handle(self.proxy_protocol_acceptor_builder.build(tcp_stream)));
                 }
@@ -145,6 +159,7 @@ struct NoiseProxyConnection {
     upstream: SocketAddr,
     tripwire: Tripwire,
     metrics: Arc<metrics::NoiseProxyMetrics>,
+    handshake_limiter: Option<HandshakeLimiter>,
 }
 
 impl NoiseProxyConnection {
@@ -155,6 +170,7 @@ impl NoiseProxyConnection {
         upstream: SocketAddr,
         tripwire: Tripwire,
         metrics: Arc<metrics::NoiseProxyMetrics>,
+        handshake_limiter: Option<HandshakeLimiter>,
     ) -> Self {
         Self {
             proxy_protocol_upstream_version,
@@ -163,6 +179,7 @@ impl NoiseProxyConnection {
             upstream,
             tripwire,
             metrics,
+            handshake_limiter,
         }
     }
 
@@ -182,10 +199,28 @@ impl NoiseProxyConnection {
         // Allows access to the peer address from both tasks
         let direct_downstream_peer_addr = self.direct_downstream_peer_addr;
 
+        let handshake_permit = match self.handshake_limiter.as_ref() {
+            Some(handshake_limiter) => match handshake_limiter.try_acquire() {
+                Some(handshake_permit) => Some(handshake_permit),
+                None => {
+                    self.metrics.account_rejected_handshake();
+                    self.metrics.account_tcp_close_in_stage("handshake_limit");
+                    return Err(anyhow!(
+                        "Too many handshakes in progress ({}), closing connection from {}",
+                        handshake_limiter.max_in_flight(),
+                        direct_downstream_peer_addr
+                    ));
+                }
+            },
+            None => None,
+        };
+        let (downstream_stream, read_buf) = proxy_stream.into_inner_with_buf();
         let downstream_framed = self
             .security_context
-            .build_framed_tcp_from_parts::<v1::Codec, v1::Frame, _>(
-                proxy_stream.into_framed_parts(),
+            .build_framed::<_, v1::Codec, v1::Frame>(
+                downstream_stream,
+                Some(read_buf),
+                handshake_permit,
             )
             .await
             .map_err(|e| {
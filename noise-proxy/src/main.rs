@@ -27,7 +27,11 @@ use clap::{App, Arg};
 use futures::TryFutureExt;
 use ii_async_utils::HaltHandle;
 use ii_logging::macros::*;
-use ii_noise_proxy::{metrics::NoiseProxyMetrics, NoiseProxy, SecurityContext};
+use ii_noise_proxy::{
+    metrics::NoiseProxyMetrics, CertificateExpiryMonitor, ExpiredCertificatePolicy, NoiseProxy,
+    SecurityContext,
+};
+use ii_stratum::v2::noise::{auth::PreSharedKey, HandshakeLimiter};
 use ii_wire::proxy;
 use tokio::io::AsyncReadExt;
 
@@ -37,6 +41,20 @@ struct Configuration {
     upstream: String,
     certificate: PathBuf,
     server_key: PathBuf,
+    /// What to do when the certificate is not valid upon startup
+    #[serde(default)]
+    expired_certificate: ExpiredCertificatePolicy,
+    /// Number of days before the certificate expiry when warnings start to be emitted
+    #[serde(default = "default_certificate_expiry_warning_days")]
+    certificate_expiry_warning_days: u64,
+    /// Maximum number of noise handshakes in progress, connections above the limit are closed
+    max_in_flight_handshakes: Option<usize>,
+    /// Hex encoded pre-shared key that clients have to mix into the noise handshake
+    noise_psk: Option<PreSharedKey>,
+}
+
+fn default_certificate_expiry_warning_days() -> u64 {
+    30
 }
 
 #[tokio::main]
@@ -71,13 +89,22 @@ async fn main() -> Result<()> {
 
     let cert_path = Path::new(&config.certificate);
     let key_path = Path::new(&config.server_key);
-    let ctx = SecurityContext::read_from_file(cert_path, key_path).await?;
+    let ctx = SecurityContext::read_from_file(cert_path, key_path)
+        .await?
+        .with_psk(config.noise_psk);
+    ctx.check_validity(config.expired_certificate, std::time::SystemTime::now())?;
+    let ctx = std::sync::Arc::new(ctx);
     let halt_handle = HaltHandle::arc();
     let (metrics, _) = NoiseProxyMetrics::new();
+    let expiry_monitor = CertificateExpiryMonitor::new(
+        ctx.clone(),
+        std::time::Duration::from_secs(config.certificate_expiry_warning_days * 24 * 3600),
+        metrics.clone(),
+    );
     let noise_proxy = NoiseProxy::new(
         config.listen,
         config.upstream,
-        std::sync::Arc::new(ctx),
+        ctx,
         proxy::ProtocolConfig::new(
             false,
             vec![proxy::ProtocolVersion::V1, proxy::ProtocolVersion::V2],
@@ -85,8 +112,14 @@ async fn main() -> Result<()> {
         None,
         metrics,
     )
-    .await?;
+    .await?
+    .with_handshake_limiter(
+        config
+            .max_in_flight_handshakes
+            .map(|limit| HandshakeLimiter::new(limit).with_preallocated_buffers(limit)),
+    );
     halt_handle.spawn_object(noise_proxy);
+    halt_handle.spawn_object(expiry_monitor);
     halt_handle.ready();
     halt_handle.clone().halt_on_signal();
     halt_handle
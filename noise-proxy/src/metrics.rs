@@ -23,11 +23,13 @@
 use std::sync::Arc;
 
 use ii_metrics::MetricsRegistry;
-use prometheus::IntCounterVec;
+use prometheus::{IntCounter, IntCounterVec, IntGauge};
 
 pub struct NoiseProxyMetrics {
     tcp_connection_open_total: IntCounterVec,
     tcp_connection_close_stage: IntCounterVec,
+    certificate_remaining_validity: IntGauge,
+    handshake_rejected_total: IntCounter,
 }
 
 impl NoiseProxyMetrics {
@@ -49,6 +51,14 @@ impl NoiseProxyMetrics {
                 "Number of TCP-close events",
                 &["result"],
             ),
+            certificate_remaining_validity: registry.register_generic_gauge(
+                "noise_certificate_remaining_validity_seconds",
+                "Time until the noise certificate expires (negative when already expired)",
+            ),
+            handshake_rejected_total: registry.register_generic_counter(
+                "noise_handshake_rejected",
+                "Number of handshakes refused due to the limit of handshakes in progress",
+            ),
         })
     }
 }
@@ -71,4 +81,12 @@ impl NoiseProxyMetrics {
             .with_label_values(&[stage])
             .inc();
     }
+
+    pub fn set_certificate_remaining_validity(&self, seconds: i64) {
+        self.certificate_remaining_validity.set(seconds);
+    }
+
+    pub fn account_rejected_handshake(&self) {
+        self.handshake_rejected_total.inc();
+    }
 }
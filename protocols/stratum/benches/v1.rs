@@ -0,0 +1,111 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Parsing and serialization of the V1 messages that dominate the traffic of a proxy
+
+use bytes::BytesMut;
+use criterion::{black_box, criterion_group, criterion_main, Criterion, Throughput};
+use std::convert::{TryFrom, TryInto};
+
+use ii_stratum::test_utils::v1::*;
+use ii_stratum::v1::framing::Frame;
+use ii_stratum::v1::messages::{Notify, Submit};
+use ii_stratum::v1::rpc::Rpc;
+
+/// Messages that are parsed by the proxy for every job and share
+const PARSED_MESSAGES: &[(&str, &str)] = &[
+    ("mining.notify", MINING_NOTIFY_JSON),
+    ("mining.submit", MINING_SUBMIT_JSON),
+    ("submit_response", MINING_SUBMIT_OK_JSON),
+];
+
+fn bench_parse(c: &mut Criterion) {
+    let mut group = c.benchmark_group("v1_parse");
+    for (method, json) in PARSED_MESSAGES {
+        group.throughput(Throughput::Bytes(json.len() as u64));
+        group.bench_function(*method, |b| {
+            b.iter(|| Rpc::try_from(black_box(json.as_bytes())).expect("BUG: Cannot parse"))
+        });
+    }
+    group.bench_function("mining.notify_message", |b| {
+        b.iter(|| {
+            let rpc =
+                Rpc::try_from(black_box(MINING_NOTIFY_JSON.as_bytes())).expect("BUG: Cannot parse");
+            Notify::try_from(rpc).expect("BUG: Cannot build notify")
+        })
+    });
+    group.bench_function("mining.submit_message", |b| {
+        b.iter(|| {
+            let rpc =
+                Rpc::try_from(black_box(MINING_SUBMIT_JSON.as_bytes())).expect("BUG: Cannot parse");
+            Submit::try_from(rpc).expect("BUG: Cannot build submit")
+        })
+    });
+    group.finish();
+}
+
+/// Same messages parsed by simd-json for comparison, enabled by the `simd-json` feature. The
+/// parser works in place, therefore, the copy of the input is part of the measurement as it
+/// would be in the codec.
+#[cfg(feature = "simd-json")]
+fn bench_parse_simd_json(c: &mut Criterion) {
+    let mut group = c.benchmark_group("v1_parse_simd_json");
+    for (method, json) in PARSED_MESSAGES {
+        group.throughput(Throughput::Bytes(json.len() as u64));
+        group.bench_function(*method, |b| {
+            b.iter(|| {
+                let mut buf = black_box(json.as_bytes()).to_vec();
+                simd_json::serde::from_slice::<Rpc>(&mut buf).expect("BUG: Cannot parse")
+            })
+        });
+    }
+    group.finish();
+}
+
+#[cfg(not(feature = "simd-json"))]
+fn bench_parse_simd_json(_c: &mut Criterion) {}
+
+fn bench_serialize(c: &mut Criterion) {
+    let mut group = c.benchmark_group("v1_serialize");
+    for (method, build) in &[
+        (
+            "mining.notify",
+            build_mining_notify_request_message as fn() -> Rpc,
+        ),
+        ("mining.submit", build_mining_submit_request_message),
+        ("submit_response", build_mining_submit_ok_response_message),
+    ] {
+        let rpc = build();
+        group.bench_function(*method, |b| {
+            b.iter(|| {
+                let frame: Frame = rpc.clone().try_into().expect("BUG: Cannot build frame");
+                let mut dst = BytesMut::new();
+                frame.serialize(&mut dst).expect("BUG: Cannot serialize");
+                dst
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_parse, bench_parse_simd_json, bench_serialize);
+criterion_main!(benches);
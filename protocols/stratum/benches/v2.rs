@@ -0,0 +1,134 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Encoding and decoding of the V2 messages that are exchanged for every job and share
+
+use bytes::BytesMut;
+use criterion::measurement::WallTime;
+use criterion::{
+    black_box, criterion_group, criterion_main, BenchmarkGroup, Criterion, Throughput,
+};
+use std::convert::{TryFrom, TryInto};
+use tokio_util::codec::{Decoder, Encoder};
+
+use ii_stratum::error::Error;
+use ii_stratum::test_utils::v2::*;
+use ii_stratum::v2::{Codec, Frame};
+
+/// Serializes `message` into a standalone frame as it would appear on the wire
+fn encode<M>(message: M) -> BytesMut
+where
+    M: TryInto<Frame, Error = Error>,
+{
+    let frame = message.try_into().expect("BUG: Cannot build frame");
+    let mut dst = BytesMut::new();
+    Codec::default()
+        .encode(frame, &mut dst)
+        .expect("BUG: Cannot encode frame");
+    dst
+}
+
+fn bench_message<M>(group: &mut BenchmarkGroup<WallTime>, name: &str, build: fn() -> M)
+where
+    M: TryInto<Frame, Error = Error> + TryFrom<Frame, Error = Error>,
+{
+    let wire = encode(build());
+    group.throughput(Throughput::Bytes(wire.len() as u64));
+
+    group.bench_function(format!("encode/{}", name), |b| {
+        let mut codec = Codec::default();
+        let mut dst = BytesMut::with_capacity(wire.len());
+        b.iter(|| {
+            let frame: Frame = black_box(build())
+                .try_into()
+                .expect("BUG: Cannot build frame");
+            dst.clear();
+            codec
+                .encode(frame, &mut dst)
+                .expect("BUG: Cannot encode frame");
+        })
+    });
+    group.bench_function(format!("decode/{}", name), |b| {
+        let mut codec = Codec::default();
+        b.iter(|| {
+            let mut src = BytesMut::from(black_box(&wire[..]));
+            let frame = codec
+                .decode(&mut src)
+                .expect("BUG: Cannot decode frame")
+                .expect("BUG: Incomplete frame");
+            M::try_from(frame).expect("BUG: Cannot deserialize message")
+        })
+    });
+}
+
+fn bench_codec(c: &mut Criterion) {
+    let mut group = c.benchmark_group("v2_codec");
+    bench_message(&mut group, "SetupConnection", build_setup_connection);
+    bench_message(&mut group, "OpenStandardMiningChannel", build_open_channel);
+    bench_message(&mut group, "NewMiningJob", build_new_mining_job);
+    bench_message(&mut group, "SetNewPrevHash", build_set_new_prev_hash);
+    bench_message(&mut group, "SubmitSharesStandard", build_submit_shares);
+    bench_message(
+        &mut group,
+        "SubmitSharesExtended",
+        build_submit_shares_extended,
+    );
+    bench_message(
+        &mut group,
+        "SubmitSharesSuccess",
+        build_submit_shares_success,
+    );
+    group.finish();
+}
+
+/// Decodes a stream of frames that mimics the downstream traffic of a busy channel: one job
+/// announcement followed by a burst of shares
+fn bench_corpus(c: &mut Criterion) {
+    const SHARES_PER_JOB: usize = 32;
+
+    let mut wire = BytesMut::new();
+    wire.unsplit(encode(build_new_mining_job()));
+    wire.unsplit(encode(build_set_new_prev_hash()));
+    for _ in 0..SHARES_PER_JOB {
+        wire.unsplit(encode(build_submit_shares()));
+    }
+    let frame_count = SHARES_PER_JOB + 2;
+
+    let mut group = c.benchmark_group("v2_corpus");
+    group.throughput(Throughput::Elements(frame_count as u64));
+    group.bench_function("decode", |b| {
+        let mut codec = Codec::default();
+        b.iter(|| {
+            let mut src = BytesMut::from(black_box(&wire[..]));
+            let mut frames = 0;
+            while let Some(frame) = codec.decode(&mut src).expect("BUG: Cannot decode frame") {
+                black_box(frame);
+                frames += 1;
+            }
+            assert_eq!(frames, frame_count, "BUG: Corpus not fully decoded");
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_codec, bench_corpus);
+criterion_main!(benches);
@@ -65,7 +65,7 @@ async fn main() -> anyhow::Result<()> {
         vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
     );
     let mut framed = initiator
-        .connect_with_codec::<String, _, _>(stream, |noise| {
+        .connect_with_codec::<_, String, _, _>(stream, |noise| {
             CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
         })
         .await?;
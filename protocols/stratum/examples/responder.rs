@@ -78,7 +78,7 @@ async fn main() -> anyhow::Result<()> {
     );
     let (downstream, _) = tcp_listener.accept().await?;
     let mut framed = responder
-        .accept_with_codec::<String, _, _>(downstream, |noise| {
+        .accept_with_codec::<_, String, _, _>(downstream, |noise| {
             CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
         })
         .await?;
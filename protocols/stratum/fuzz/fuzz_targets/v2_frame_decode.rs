@@ -0,0 +1,45 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Feeds untrusted bytes into the V2 codec the same way a proxy connection does and re-encodes
+//! every frame that has been decoded successfully
+
+#![no_main]
+
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use tokio_util::codec::{Decoder, Encoder};
+
+use ii_stratum::v2::Codec;
+
+fuzz_target!(|data: &[u8]| {
+    let mut codec = Codec::new()
+        .with_max_frame_size(1 << 16)
+        .with_chunked_messages(1 << 20);
+    let mut buf = BytesMut::from(data);
+    while let Ok(Some(frame)) = codec.decode(&mut buf) {
+        let mut encoded = BytesMut::new();
+        codec
+            .encode(frame, &mut encoded)
+            .expect("BUG: Cannot encode decoded frame");
+    }
+});
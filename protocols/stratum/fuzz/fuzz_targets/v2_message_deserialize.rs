@@ -0,0 +1,102 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Deserializes untrusted bytes as one of the V2 messages, the first byte of the input selects
+//! the message. Messages that deserialize successfully have to survive a serialization round
+//! trip.
+
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use serde::{de::DeserializeOwned, Serialize};
+
+use ii_stratum::v2::messages::{self, job_negotiation, template_distribution};
+use ii_stratum::v2::{monitoring, serialization, telemetry};
+
+fn check<T: Serialize + DeserializeOwned>(payload: &[u8]) {
+    if let Ok(msg) = serialization::from_slice::<T>(payload) {
+        let bytes = serialization::to_vec(&msg).expect("BUG: Cannot serialize message");
+        let msg: T = serialization::from_slice(&bytes).expect("BUG: Cannot deserialize message");
+        assert_eq!(
+            serialization::to_vec(&msg).expect("BUG: Cannot serialize message"),
+            bytes
+        );
+    }
+}
+
+const CHECKS: &[fn(&[u8])] = &[
+    check::<messages::SetupConnection>,
+    check::<messages::SetupConnectionSuccess>,
+    check::<messages::SetupConnectionError>,
+    check::<messages::ChannelEndpointChanged>,
+    check::<messages::OpenStandardMiningChannel>,
+    check::<messages::OpenStandardMiningChannelSuccess>,
+    check::<messages::OpenExtendedMiningChannel>,
+    check::<messages::OpenExtendedMiningChannelSuccess>,
+    check::<messages::OpenMiningChannelError>,
+    check::<messages::UpdateChannel>,
+    check::<messages::UpdateChannelError>,
+    check::<messages::CloseChannel>,
+    check::<messages::SubmitSharesStandard>,
+    check::<messages::SubmitSharesExtended>,
+    check::<messages::SubmitSharesSuccess>,
+    check::<messages::SubmitSharesError>,
+    check::<messages::NewMiningJob>,
+    check::<messages::NewExtendedMiningJob>,
+    check::<messages::SetNewPrevHash>,
+    check::<messages::SetCustomMiningJob>,
+    check::<messages::SetCustomMiningJobSuccess>,
+    check::<messages::SetCustomMiningJobError>,
+    check::<messages::SetTarget>,
+    check::<messages::Reconnect>,
+    check::<messages::SetGroupChannel>,
+    check::<job_negotiation::AllocateMiningJobToken>,
+    check::<job_negotiation::AllocateMiningJobTokenSuccess>,
+    check::<job_negotiation::CommitMiningJob>,
+    check::<job_negotiation::CommitMiningJobSuccess>,
+    check::<job_negotiation::CommitMiningJobError>,
+    check::<job_negotiation::IdentifyTransactions>,
+    check::<job_negotiation::IdentifyTransactionsSuccess>,
+    check::<job_negotiation::ProvideMissingTransactions>,
+    check::<job_negotiation::ProvideMissingTransactionsSuccess>,
+    check::<template_distribution::CoinbaseOutputDataSize>,
+    check::<template_distribution::NewTemplate>,
+    check::<template_distribution::SetNewPrevHash>,
+    check::<template_distribution::RequestTransactionData>,
+    check::<template_distribution::RequestTransactionDataSuccess>,
+    check::<template_distribution::RequestTransactionDataError>,
+    check::<template_distribution::SubmitSolution>,
+    check::<telemetry::messages::OpenTelemetryChannel>,
+    check::<telemetry::messages::OpenTelemetryChannelSuccess>,
+    check::<telemetry::messages::OpenTelemetryChannelError>,
+    check::<telemetry::messages::SubmitTelemetryData>,
+    check::<telemetry::messages::SubmitTelemetryDataSuccess>,
+    check::<telemetry::messages::SubmitTelemetryDataError>,
+    check::<monitoring::messages::SubmitDeviceStatus>,
+    check::<monitoring::messages::SubmitDeviceStatusSuccess>,
+];
+
+fuzz_target!(|data: &[u8]| {
+    if let Some((selector, payload)) = data.split_first() {
+        CHECKS[*selector as usize % CHECKS.len()](payload);
+    }
+});
@@ -0,0 +1,126 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Encodes arbitrary V2 messages into frames and checks that decoding the frames and encoding
+//! them again produces identical bytes
+
+#![no_main]
+
+use arbitrary::Arbitrary;
+use bytes::BytesMut;
+use libfuzzer_sys::fuzz_target;
+use std::convert::TryFrom;
+use tokio_util::codec::{Decoder, Encoder};
+
+use ii_stratum::v2::messages::{self, job_negotiation, template_distribution};
+use ii_stratum::v2::{monitoring, telemetry, Codec, Frame};
+
+macro_rules! messages {
+    ($($variant:ident: $message:ty),+ $(,)?) => {
+        #[derive(Arbitrary, Debug)]
+        enum Message {
+            $($variant($message)),+
+        }
+
+        impl Message {
+            fn into_frame(self) -> Frame {
+                match self {
+                    $(Message::$variant(msg) => Frame::try_from(msg)),+
+                }
+                .expect("BUG: Cannot build frame")
+            }
+        }
+    };
+}
+
+messages! {
+    SetupConnection: messages::SetupConnection,
+    SetupConnectionSuccess: messages::SetupConnectionSuccess,
+    SetupConnectionError: messages::SetupConnectionError,
+    ChannelEndpointChanged: messages::ChannelEndpointChanged,
+    OpenStandardMiningChannel: messages::OpenStandardMiningChannel,
+    OpenStandardMiningChannelSuccess: messages::OpenStandardMiningChannelSuccess,
+    OpenExtendedMiningChannel: messages::OpenExtendedMiningChannel,
+    OpenExtendedMiningChannelSuccess: messages::OpenExtendedMiningChannelSuccess,
+    OpenMiningChannelError: messages::OpenMiningChannelError,
+    UpdateChannel: messages::UpdateChannel,
+    UpdateChannelError: messages::UpdateChannelError,
+    CloseChannel: messages::CloseChannel,
+    SubmitSharesStandard: messages::SubmitSharesStandard,
+    SubmitSharesExtended: messages::SubmitSharesExtended,
+    SubmitSharesSuccess: messages::SubmitSharesSuccess,
+    SubmitSharesError: messages::SubmitSharesError,
+    NewMiningJob: messages::NewMiningJob,
+    NewExtendedMiningJob: messages::NewExtendedMiningJob,
+    SetNewPrevHash: messages::SetNewPrevHash,
+    SetCustomMiningJob: messages::SetCustomMiningJob,
+    SetCustomMiningJobSuccess: messages::SetCustomMiningJobSuccess,
+    SetCustomMiningJobError: messages::SetCustomMiningJobError,
+    SetTarget: messages::SetTarget,
+    Reconnect: messages::Reconnect,
+    SetGroupChannel: messages::SetGroupChannel,
+    AllocateMiningJobToken: job_negotiation::AllocateMiningJobToken,
+    AllocateMiningJobTokenSuccess: job_negotiation::AllocateMiningJobTokenSuccess,
+    CommitMiningJob: job_negotiation::CommitMiningJob,
+    CommitMiningJobSuccess: job_negotiation::CommitMiningJobSuccess,
+    CommitMiningJobError: job_negotiation::CommitMiningJobError,
+    IdentifyTransactions: job_negotiation::IdentifyTransactions,
+    IdentifyTransactionsSuccess: job_negotiation::IdentifyTransactionsSuccess,
+    ProvideMissingTransactions: job_negotiation::ProvideMissingTransactions,
+    ProvideMissingTransactionsSuccess: job_negotiation::ProvideMissingTransactionsSuccess,
+    CoinbaseOutputDataSize: template_distribution::CoinbaseOutputDataSize,
+    NewTemplate: template_distribution::NewTemplate,
+    TdSetNewPrevHash: template_distribution::SetNewPrevHash,
+    RequestTransactionData: template_distribution::RequestTransactionData,
+    RequestTransactionDataSuccess: template_distribution::RequestTransactionDataSuccess,
+    RequestTransactionDataError: template_distribution::RequestTransactionDataError,
+    SubmitSolution: template_distribution::SubmitSolution,
+    OpenTelemetryChannel: telemetry::messages::OpenTelemetryChannel,
+    OpenTelemetryChannelSuccess: telemetry::messages::OpenTelemetryChannelSuccess,
+    OpenTelemetryChannelError: telemetry::messages::OpenTelemetryChannelError,
+    SubmitTelemetryData: telemetry::messages::SubmitTelemetryData,
+    SubmitTelemetryDataSuccess: telemetry::messages::SubmitTelemetryDataSuccess,
+    SubmitTelemetryDataError: telemetry::messages::SubmitTelemetryDataError,
+    SubmitDeviceStatus: monitoring::messages::SubmitDeviceStatus,
+    SubmitDeviceStatusSuccess: monitoring::messages::SubmitDeviceStatusSuccess,
+}
+
+fuzz_target!(|message: Message| {
+    let mut codec = Codec::new().with_chunked_messages(1 << 26);
+    let mut encoded = BytesMut::new();
+    codec
+        .encode(message.into_frame(), &mut encoded)
+        .expect("BUG: Cannot encode frame");
+
+    let mut buf = encoded.clone();
+    let frame = codec
+        .decode(&mut buf)
+        .expect("BUG: Cannot decode frame")
+        .expect("BUG: Incomplete frame");
+    assert!(buf.is_empty());
+
+    let mut reencoded = BytesMut::new();
+    codec
+        .encode(frame, &mut reencoded)
+        .expect("BUG: Cannot encode decoded frame");
+    assert_eq!(encoded, reencoded);
+});
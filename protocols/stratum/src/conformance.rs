@@ -0,0 +1,72 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Conformance tool that runs the checks of `ii_stratum::v2::conformance` against a Stratum V2
+//! endpoint and prints the report. The tool exits with an error when any of the checks fails.
+
+use anyhow::{anyhow, bail, Result};
+use ii_stratum::v2::conformance::{ConformanceSuite, TcpEndpoint};
+use ii_stratum::v2::noise::auth::EncodedEd25519PublicKey;
+use std::convert::TryFrom;
+use std::time::Duration;
+use structopt::StructOpt;
+
+#[derive(Debug, StructOpt)]
+#[structopt(
+    name = "ii-stratum-conformance",
+    about = "Tool for checking conformance of Stratum V2 endpoints with the protocol specification"
+)]
+struct Args {
+    /// Address of the endpoint, e.g. "stratum.example.com:3336"
+    #[structopt(short, long)]
+    endpoint: String,
+    /// Base58 encoded public key of the authority that signed the endpoint certificate. The
+    /// connection is not secured by the noise protocol when the key is omitted.
+    #[structopt(short, long)]
+    authority_public_key: Option<String>,
+    /// How long to wait for a response of the endpoint [milliseconds]
+    #[structopt(short, long, default_value = "5000")]
+    response_timeout: u64,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::from_args();
+
+    let mut endpoint = TcpEndpoint::new(args.endpoint);
+    if let Some(key) = args.authority_public_key {
+        let key = EncodedEd25519PublicKey::try_from(key)
+            .map_err(|e| anyhow!("Cannot parse authority public key: {}", e))?;
+        endpoint = endpoint.with_authority_public_key(key.into_inner());
+    }
+
+    let report = ConformanceSuite::new(endpoint)
+        .with_response_timeout(Duration::from_millis(args.response_timeout))
+        .run()
+        .await;
+    println!("{}", report);
+
+    if !report.is_success() {
+        bail!("Endpoint is not conforming");
+    }
+    Ok(())
+}
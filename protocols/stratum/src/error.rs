@@ -22,10 +22,114 @@
 
 //! Module that represents stratum protocol errors
 
-use std::{self, fmt, io};
+use std::{self, fmt, io, net::SocketAddr};
 use thiserror::Error;
 
+/// Category of an error. It allows callers to decide how to handle the error without
+/// inspecting particular variants, e.g. whether to retry the failed operation.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum ErrorKind {
+    /// Peer violated the protocol or sent malformed data
+    Protocol,
+    /// Network or other I/O failure
+    Io,
+    /// Operation didn't complete in time
+    Timeout,
+    /// Authentication or authorization of a peer failed
+    Auth,
+    /// Invalid configuration or credentials provided by the operator
+    Config,
+    /// Failure that indicates a bug or an exhausted internal resource
+    Internal,
+}
+
+impl ErrorKind {
+    /// Errors of this kind are transient and the failed operation (e.g. connecting to the
+    /// peer) may succeed when attempted again
+    pub fn is_retryable(self) -> bool {
+        matches!(self, Self::Io | Self::Timeout)
+    }
+}
+
+impl fmt::Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let kind = match self {
+            Self::Protocol => "protocol",
+            Self::Io => "io",
+            Self::Timeout => "timeout",
+            Self::Auth => "auth",
+            Self::Config => "config",
+            Self::Internal => "internal",
+        };
+        f.write_str(kind)
+    }
+}
+
+/// Direction of communication in which an error occurred
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Direction {
+    /// Communication with the server side
+    Upstream,
+    /// Communication with the client side
+    Downstream,
+}
+
+impl fmt::Display for Direction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Upstream => f.write_str("upstream"),
+            Self::Downstream => f.write_str("downstream"),
+        }
+    }
+}
+
+/// Circumstances in which an error occurred, all items are optional
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub peer: Option<SocketAddr>,
+    pub direction: Option<Direction>,
+    /// Identifier of the message being processed (V1 request ID or V2 request ID)
+    pub message_id: Option<u32>,
+}
+
+impl ErrorContext {
+    pub fn with_peer(mut self, peer: SocketAddr) -> Self {
+        self.peer = Some(peer);
+        self
+    }
+
+    pub fn with_direction(mut self, direction: Direction) -> Self {
+        self.direction = Some(direction);
+        self
+    }
+
+    pub fn with_message_id(mut self, message_id: u32) -> Self {
+        self.message_id = Some(message_id);
+        self
+    }
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut separator = "";
+        if let Some(direction) = self.direction {
+            write!(f, "{}", direction)?;
+            separator = ", ";
+        }
+        if let Some(peer) = self.peer {
+            write!(f, "{}peer: {}", separator, peer)?;
+            separator = ", ";
+        }
+        if let Some(message_id) = self.message_id {
+            write!(f, "{}message id: {}", separator, message_id)?;
+        }
+        Ok(())
+    }
+}
+
 #[derive(Error, Debug)] //TODO: We lost Clone PartialEq and Eq, is this important?
+#[non_exhaustive]
 pub enum Error {
     /// Input/Output error.
     #[error("I/O error: {0}")]
@@ -34,6 +138,12 @@ pub enum Error {
     #[error("Handshake error: {0}")]
     Handshake(String),
 
+    #[error("Noise handshake timeout in {0} stage")]
+    HandshakeTimeout(crate::v2::noise::HandshakeStage),
+
+    #[error("Noise handshake failed in {0} stage: {1}")]
+    HandshakeFailed(crate::v2::noise::HandshakeStage, #[source] Box<Error>),
+
     /// Line Codec error.
     #[error("Lines Codec error: {0}")]
     LinesCodec(#[from] tokio_util::codec::LinesCodecError),
@@ -93,6 +203,60 @@ pub enum Error {
     /// Utf8 error
     #[error("Error decoding UTF-8 string: {0}")]
     Utf8(#[from] std::str::Utf8Error),
+
+    /// Error annotated with the circumstances in which it occurred
+    #[error("{source} ({context})")]
+    Context {
+        context: ErrorContext,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Annotates the error with `context`
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        Self::Context {
+            context,
+            source: Box::new(self),
+        }
+    }
+
+    /// Provides the context of an annotated error
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::Io(_) => ErrorKind::Io,
+            Self::LinesCodec(tokio_util::codec::LinesCodecError::Io(_)) => ErrorKind::Io,
+            Self::LinesCodec(_) => ErrorKind::Protocol,
+            Self::HandshakeTimeout(_) | Self::Timeout(_) => ErrorKind::Timeout,
+            Self::HandshakeFailed(_, source) => source.kind(),
+            Self::NoiseProtocol(_) | Self::NoiseSignature(_) => ErrorKind::Auth,
+            Self::NoiseEncoding(_) => ErrorKind::Config,
+            Self::V1(e) => e.kind(),
+            Self::V2(e) => e.kind(),
+            Self::Handshake(_)
+            | Self::Serde(_)
+            | Self::UnexpectedVersion(..)
+            | Self::Noise(_)
+            | Self::V2Serialization(_)
+            | Self::HexDecode(_)
+            | Self::BitcoinHash(_)
+            | Self::Utf8(_) => ErrorKind::Protocol,
+            Self::General(_) | Self::Format(_) => ErrorKind::Internal,
+            Self::Context { source, .. } => source.kind(),
+        }
+    }
+
+    /// See `ErrorKind::is_retryable()`
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
 }
 
 impl From<&str> for Error {
@@ -107,6 +271,13 @@ impl From<String> for Error {
     }
 }
 
+/// Allows treating infallible conversions the same way as fallible ones in generic code
+impl From<std::convert::Infallible> for Error {
+    fn from(e: std::convert::Infallible) -> Self {
+        match e {}
+    }
+}
+
 /// A specialized `Result` type bound to [`Error`].
 pub type Result<T> = std::result::Result<T, Error>;
 
@@ -123,4 +294,36 @@ mod test {
         let msg = err.to_string();
         assert!(msg.contains(inner_msg));
     }
+
+    #[test]
+    fn test_error_kind() {
+        let err = Error::from(io::Error::new(io::ErrorKind::ConnectionReset, "reset"));
+        assert_eq!(err.kind(), ErrorKind::Io);
+        assert!(err.is_retryable());
+
+        let err = Error::V2(V2Error::CertificateExpired(1, 2));
+        assert_eq!(err.kind(), ErrorKind::Auth);
+        assert!(!err.is_retryable());
+
+        let err = Error::V2(V2Error::UnknownMessage("0x77".into()));
+        assert_eq!(err.kind(), ErrorKind::Protocol);
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn test_error_context() {
+        let context = ErrorContext::default()
+            .with_peer("10.0.0.1:3333".parse().expect("BUG: invalid address"))
+            .with_direction(Direction::Upstream)
+            .with_message_id(7);
+        let err = Error::from(io::Error::new(io::ErrorKind::TimedOut, "timed out"))
+            .with_context(context.clone());
+
+        assert_eq!(err.context(), Some(&context));
+        assert_eq!(err.kind(), ErrorKind::Io);
+        assert_eq!(
+            err.to_string(),
+            "I/O error: timed out (upstream, peer: 10.0.0.1:3333, message id: 7)"
+        );
+    }
 }
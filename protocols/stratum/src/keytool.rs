@@ -27,13 +27,12 @@
 
 use anyhow::{anyhow, Context, Result};
 use ii_stratum::v2::noise;
-use ii_stratum::v2::noise::auth::{ServerSecurityBundle, StaticPublicKeyFormat};
-use rand::rngs::OsRng;
+use ii_stratum::v2::noise::auth::ServerSecurityBundle;
 use std::convert::{TryFrom, TryInto};
 use std::fs::{File, OpenOptions};
 use std::io::{Read, Write};
 use std::path::PathBuf;
-use std::time::Duration;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
 /// All commands recognized by the keytool
@@ -79,8 +78,7 @@ impl GenCAKeyCommand {
     fn execute(self) -> Result<()> {
         print!("Generating ED25519 keypair...");
 
-        let mut csprng = OsRng {};
-        let keypair = ed25519_dalek::Keypair::generate(&mut csprng);
+        let keypair = noise::auth::generate_authority_keypair();
 
         write_to_file(
             &self.public_key_file,
@@ -155,6 +153,9 @@ struct SignBundleCommand {
     /// How many days the generated certificate should be valid for
     #[structopt(short, long, default_value = "90")]
     valid_for_days: usize,
+    /// Start of the certificate validity as unix timestamp, defaults to now
+    #[structopt(long)]
+    valid_from: Option<u64>,
 }
 
 impl SignBundleCommand {
@@ -199,13 +200,10 @@ impl SignBundleCommand {
             "static secret key to sign",
         )?;
 
-        // FIXME: this breaks layers of abstraction of noise protocol. Certificate should be generated
-        // from existing public key.
-        let mut raw_secret_key = [0_u8; 32];
-        raw_secret_key.copy_from_slice(&secret_key.clone().into_inner());
-        let inner_public_key =
-            x25519_dalek::x25519(raw_secret_key, x25519_dalek::X25519_BASEPOINT_BYTES).to_vec();
-        let public_key = StaticPublicKeyFormat::new(inner_public_key);
+        let public_key = secret_key
+            .public_key()
+            .map_err(|e| anyhow!("{}", e))
+            .context("Deriving static public key")?;
 
         let authority_secret_key = Self::read_from_file::<noise::auth::Ed25519SecretKeyFormat>(
             &self.signing_key,
@@ -213,28 +211,14 @@ impl SignBundleCommand {
         )?
         .into_inner();
 
-        // Dalek crate requires the full Keypair for signing
-        let authority_keypair = ed25519_dalek::Keypair {
-            // Derive the public key from the secret key
-            public: (&authority_secret_key).into(),
-            secret: authority_secret_key,
-        };
-
-        let header = noise::auth::SignedPartHeader::with_duration(Duration::from_secs(
-            (self.valid_for_days * 24 * 60 * 60) as u64,
-        ))
-        .map_err(|e| anyhow!("{}", e))?;
-
-        let signed_part =
-            noise::auth::SignedPart::new(header, public_key.into_inner(), authority_keypair.public);
+        let authority_keypair =
+            noise::auth::authority_keypair_from_secret_key(authority_secret_key);
+        let header = validity_header(self.valid_from, self.valid_for_days)?;
 
-        let signature = signed_part
-            .sign_with(&authority_keypair)
-            .map_err(|e| anyhow!("{}", e))
-            .context("Signing certificate")?;
-
-        // Final step is to compose the certificate from all components and serialize it into a file
-        let certificate = noise::auth::Certificate::new(signed_part, signature);
+        let certificate =
+            noise::auth::Certificate::sign(header, public_key.into_inner(), &authority_keypair)
+                .map_err(|e| anyhow!("{}", e))
+                .context("Signing certificate")?;
         let bundle = ServerSecurityBundle::new(certificate, secret_key)
             .expect("BUG: Inconsistent server security bundle has been generated");
         let bundle_string =
@@ -260,6 +244,9 @@ struct SignKeyCommand {
     /// How many days the generated certificate should be valid for
     #[structopt(short, long, default_value = "90")]
     valid_for_days: usize,
+    /// Start of the certificate validity as unix timestamp, defaults to now
+    #[structopt(long)]
+    valid_from: Option<u64>,
 }
 
 impl SignKeyCommand {
@@ -310,28 +297,14 @@ impl SignKeyCommand {
         )?
         .into_inner();
 
-        // Dalek crate requires the full Keypair for signing
-        let authority_keypair = ed25519_dalek::Keypair {
-            // Derive the public key from the secret key
-            public: (&authority_secret_key).into(),
-            secret: authority_secret_key,
-        };
-
-        let header = noise::auth::SignedPartHeader::with_duration(Duration::from_secs(
-            (self.valid_for_days * 24 * 60 * 60) as u64,
-        ))
-        .map_err(|e| anyhow!("{}", e))?;
-
-        let signed_part =
-            noise::auth::SignedPart::new(header, public_key.into_inner(), authority_keypair.public);
-
-        let signature = signed_part
-            .sign_with(&authority_keypair)
-            .map_err(|e| anyhow!("{}", e))
-            .context("Signing certificate")?;
+        let authority_keypair =
+            noise::auth::authority_keypair_from_secret_key(authority_secret_key);
+        let header = validity_header(self.valid_from, self.valid_for_days)?;
 
-        // Final step is to compose the certificate from all components and serialize it into a file
-        let certificate = noise::auth::Certificate::new(signed_part, signature);
+        let certificate =
+            noise::auth::Certificate::sign(header, public_key.into_inner(), &authority_keypair)
+                .map_err(|e| anyhow!("{}", e))
+                .context("Signing certificate")?;
         // Derive the certificate file name from the public key filename
         let mut cert_file = self.public_key_to_sign;
         cert_file.set_extension("cert");
@@ -340,6 +313,22 @@ impl SignKeyCommand {
     }
 }
 
+/// Builds certificate header for a validity period of `valid_for_days` that starts at unix
+/// timestamp `valid_from` or now
+fn validity_header(
+    valid_from: Option<u64>,
+    valid_for_days: usize,
+) -> Result<noise::auth::SignedPartHeader> {
+    let valid_from = valid_from.map_or_else(SystemTime::now, |timestamp| {
+        UNIX_EPOCH + Duration::from_secs(timestamp)
+    });
+    noise::auth::SignedPartHeader::with_validity(
+        valid_from,
+        Duration::from_secs((valid_for_days * 24 * 60 * 60) as u64),
+    )
+    .map_err(|e| anyhow!("{}", e))
+}
+
 /// Helper that opens a new file for writing or emits an error with specified context description
 /// if the file already exists. This is important to prevent overwriting already generated files.
 fn open_new_file(file: &PathBuf, descr: &str) -> Result<File> {
@@ -41,6 +41,14 @@ pub trait Protocol {
 pub trait AnyPayload<P: Protocol>: Sync + Send {
     /// The payload is serialized to a specified `writer`
     fn serialize_to_writer(&self, writer: &mut dyn std::io::Write) -> Result<()>;
+
+    /// The payload is serialized to a specified `writer` as JSON. Only protocols that support
+    /// JSON as an alternative encoding (V2 for debugging purposes) need to provide this.
+    fn serialize_json_to_writer(&self, _writer: &mut dyn std::io::Write) -> Result<()> {
+        Err(error::Error::General(
+            "Payload doesn't support JSON serialization".to_string(),
+        ))
+    }
 }
 
 // This is here because some test utilities need to be shared between
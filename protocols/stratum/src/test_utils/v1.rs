@@ -47,6 +47,9 @@ pub enum TestMessage {
     MsgNotify(MessageId, Notify),
     MsgSetVersionMask(MessageId, SetVersionMask),
     MsgClientReconnect(MessageId, ClientReconnect),
+    MsgSuggestDifficulty(MessageId, SuggestDifficulty),
+    MsgSuggestTarget(MessageId, SuggestTarget),
+    MsgPing(MessageId, Ping),
     MsgStratumResult(MessageId, StratumResult),
 }
 
@@ -94,6 +97,13 @@ impl TestMessage {
     impl_unwrap!(unwrap_notify, MsgNotify, Notify);
     impl_unwrap!(unwrap_set_version_mask, MsgSetVersionMask, SetVersionMask);
     impl_unwrap!(unwrap_client_reconnect, MsgClientReconnect, ClientReconnect);
+    impl_unwrap!(
+        unwrap_suggest_difficulty,
+        MsgSuggestDifficulty,
+        SuggestDifficulty
+    );
+    impl_unwrap!(unwrap_suggest_target, MsgSuggestTarget, SuggestTarget);
+    impl_unwrap!(unwrap_ping, MsgPing, Ping);
 
     impl_unwrap_result!(unwrap_subscribe_result, SubscribeResult);
     impl_unwrap_result!(unwrap_configure_result, ConfigureResult);
@@ -168,6 +178,9 @@ impl_conversions!(Submit, MsgSubmit);
 impl_conversions!(Notify, MsgNotify);
 impl_conversions!(SetVersionMask, MsgSetVersionMask);
 impl_conversions!(ClientReconnect, MsgClientReconnect);
+impl_conversions!(SuggestDifficulty, MsgSuggestDifficulty);
+impl_conversions!(SuggestTarget, MsgSuggestTarget);
+impl_conversions!(Ping, MsgPing);
 
 impl_from_msg_to_enum!(StratumResult, MsgStratumResult);
 impl_try_from_result_to_msg!(SubscribeResult);
@@ -221,6 +234,18 @@ impl TestCollectorHandler {
         self.messages.push_back(id_msg.into());
     }
 
+    async fn handle_suggest_difficulty(&mut self, id_msg: (MessageId, SuggestDifficulty)) {
+        self.messages.push_back(id_msg.into());
+    }
+
+    async fn handle_suggest_target(&mut self, id_msg: (MessageId, SuggestTarget)) {
+        self.messages.push_back(id_msg.into());
+    }
+
+    async fn handle_ping(&mut self, id_msg: (MessageId, Ping)) {
+        self.messages.push_back(id_msg.into());
+    }
+
     async fn handle_stratum_result(&mut self, id_msg: (MessageId, StratumResult)) {
         self.messages.push_back(id_msg.into());
     }
@@ -360,6 +385,23 @@ pub const FULLY_BROKEN_RESPONSE_JSON: &str = concat!(
     r#"[21, "Job not found (=stale)", null]}"#
 );
 
+/// Difficulty sent as a string, accepted only by lenient parsing
+pub const LENIENT_SET_DIFFICULTY_JSON: &str =
+    r#"{"id":null,"method":"mining.set_difficulty","params":["1024.5"]}"#;
+
+/// String ID and plain string error, accepted only by lenient parsing
+pub const LENIENT_STRING_ERROR_JSON: &str =
+    r#"{"id":"3","result":null,"error":"Job not found (=stale)"}"#;
+
+/// Error as an object with a string code, accepted only by lenient parsing
+pub const LENIENT_OBJECT_ERROR_JSON: &str = concat!(
+    r#"{"id":4,"result":null,"#,
+    r#""error":{"code":"21","message":"Job not found (=stale)","data":{"job":"ahoj"}}}"#
+);
+
+/// Successful result with `false` in place of the error, accepted only by lenient parsing
+pub const LENIENT_FALSE_ERROR_JSON: &str = r#"{"id":5,"result":true,"error":false}"#;
+
 /// Random broken request
 pub const MINING_BROKEN_REQ_JSON: &str = concat!(
     r#"{"id":1,"method":"mining.none_existing","#,
@@ -409,6 +451,9 @@ pub fn build_mining_submit_ok_response_message() -> Rpc {
     build_ok_response_message(3)
 }
 
+/// Serialized form of `build_mining_submit_ok_response_message()`
+pub const MINING_SUBMIT_OK_JSON: &str = r#"{"id":3,"result":true,"error":null}"#;
+
 pub fn build_subscribe_ok_result() -> SubscribeResult {
     SubscribeResult(
         vec![],
@@ -534,6 +579,54 @@ pub fn build_client_reconnect() -> ClientReconnect {
     }
 }
 
+pub const MINING_EXTRANONCE_SUBSCRIBE_JSON: &str =
+    r#"{"id":2,"method":"mining.extranonce.subscribe","params":[]}"#;
+
+pub fn build_extranonce_subscribe() -> ExtranonceSubscribe {
+    ExtranonceSubscribe
+}
+
+pub const MINING_SET_EXTRANONCE_JSON: &str =
+    r#"{"id":null,"method":"mining.set_extranonce","params":["08000003",8]}"#;
+
+pub fn build_set_extranonce_request_message() -> Rpc {
+    build_request_message(None, build_set_extranonce())
+}
+
+pub fn build_set_extranonce() -> SetExtranonce {
+    SetExtranonce {
+        extra_nonce1: ExtraNonce1(
+            HexBytes::try_from("08000003").expect("BUG: Cannot parse extra nonce 1"),
+        ),
+        extra_nonce2_size: 8,
+    }
+}
+
+pub const MINING_SUGGEST_DIFFICULTY_JSON: &str =
+    r#"{"id":4,"method":"mining.suggest_difficulty","params":[512.0]}"#;
+
+pub fn build_suggest_difficulty() -> SuggestDifficulty {
+    SuggestDifficulty::from(512f32)
+}
+
+pub const MINING_SUGGEST_TARGET_JSON: &str = concat!(
+    r#"{"id":5,"method":"mining.suggest_target","#,
+    r#""params":["0000000000ffff00000000000000000000000000000000000000000000000000"]}"#,
+);
+
+pub fn build_suggest_target() -> SuggestTarget {
+    let mut target = [0u8; 32];
+    target[5] = 0xff;
+    target[6] = 0xff;
+    SuggestTarget::new(&target)
+}
+
+pub const MINING_PING_JSON: &str = r#"{"id":6,"method":"mining.ping","params":[]}"#;
+
+pub fn build_ping() -> Ping {
+    Ping::new()
+}
+
 /// Performs 2 checks:
 /// - if the provided message payload matches the one expected by the test (`expected_payload`)
 /// - whether the `full_message` after serialization matches the expected `json_message` JSON
@@ -630,6 +723,41 @@ impl TestIdentityHandler {
         message_request_check(id, &msg, build_mining_submit(), MINING_SUBMIT_JSON);
     }
 
+    async fn handle_extranonce_subscribe(&mut self, id_msg: (MessageId, ExtranonceSubscribe)) {
+        let (id, msg) = id_msg;
+        message_request_check(
+            id,
+            &msg,
+            build_extranonce_subscribe(),
+            MINING_EXTRANONCE_SUBSCRIBE_JSON,
+        );
+    }
+
+    async fn handle_set_extranonce(&mut self, id_msg: (MessageId, SetExtranonce)) {
+        let (id, msg) = id_msg;
+        message_request_check(id, &msg, build_set_extranonce(), MINING_SET_EXTRANONCE_JSON);
+    }
+
+    async fn handle_suggest_difficulty(&mut self, id_msg: (MessageId, SuggestDifficulty)) {
+        let (id, msg) = id_msg;
+        message_request_check(
+            id,
+            &msg,
+            build_suggest_difficulty(),
+            MINING_SUGGEST_DIFFICULTY_JSON,
+        );
+    }
+
+    async fn handle_suggest_target(&mut self, id_msg: (MessageId, SuggestTarget)) {
+        let (id, msg) = id_msg;
+        message_request_check(id, &msg, build_suggest_target(), MINING_SUGGEST_TARGET_JSON);
+    }
+
+    async fn handle_ping(&mut self, id_msg: (MessageId, Ping)) {
+        let (id, msg) = id_msg;
+        message_request_check(id, &msg, build_ping(), MINING_PING_JSON);
+    }
+
     #[handle(_)]
     async fn handle_rest(&mut self, rpc: Result<Rpc>) {
         panic!("Unexpected v1 message: {:?}", rpc);
@@ -645,4 +773,9 @@ pub const V1_TEST_REQUESTS: &[&str] = &[
     MINING_SUBSCRIBE_REQ_JSON,
     MINING_SET_DIFFICULTY_JSON,
     MINING_SUBMIT_JSON,
+    MINING_EXTRANONCE_SUBSCRIBE_JSON,
+    MINING_SET_EXTRANONCE_JSON,
+    MINING_SUGGEST_DIFFICULTY_JSON,
+    MINING_SUGGEST_TARGET_JSON,
+    MINING_PING_JSON,
 ];
@@ -43,18 +43,25 @@ pub enum TestMessage {
     MsgChannelEndpointChanged(ChannelEndpointChanged),
     MsgOpenStandardMiningChannel(OpenStandardMiningChannel),
     MsgOpenStandardMiningChannelSuccess(OpenStandardMiningChannelSuccess),
+    MsgOpenExtendedMiningChannel(OpenExtendedMiningChannel),
+    MsgOpenExtendedMiningChannelSuccess(OpenExtendedMiningChannelSuccess),
     MsgOpenMiningChannelError(OpenMiningChannelError),
     MsgUpdateChannel(UpdateChannel),
     MsgUpdateChannelError(UpdateChannelError),
     MsgCloseChannel(CloseChannel),
     MsgSubmitSharesStandard(SubmitSharesStandard),
+    MsgSubmitSharesExtended(SubmitSharesExtended),
     MsgSubmitSharesSuccess(SubmitSharesSuccess),
     MsgSubmitSharesError(SubmitSharesError),
     MsgNewMiningJob(NewMiningJob),
     MsgNewExtendedMiningJob(NewExtendedMiningJob),
     MsgSetNewPrevHash(SetNewPrevHash),
     MsgSetTarget(SetTarget),
+    MsgSetCustomMiningJob(SetCustomMiningJob),
+    MsgSetCustomMiningJobSuccess(SetCustomMiningJobSuccess),
+    MsgSetCustomMiningJobError(SetCustomMiningJobError),
     MsgReconnect(Reconnect),
+    MsgSetGroupChannel(SetGroupChannel),
 }
 
 macro_rules! impl_unwrap {
@@ -95,6 +102,16 @@ impl TestMessage {
         MsgOpenStandardMiningChannelSuccess,
         OpenStandardMiningChannelSuccess
     );
+    impl_unwrap!(
+        unwrap_open_extended_mining_channel,
+        MsgOpenExtendedMiningChannel,
+        OpenExtendedMiningChannel
+    );
+    impl_unwrap!(
+        unwrap_open_extended_mining_channel_success,
+        MsgOpenExtendedMiningChannelSuccess,
+        OpenExtendedMiningChannelSuccess
+    );
     impl_unwrap!(
         unwrap_open_mining_channel_error,
         MsgOpenMiningChannelError,
@@ -112,6 +129,11 @@ impl TestMessage {
         MsgSubmitSharesStandard,
         SubmitSharesStandard
     );
+    impl_unwrap!(
+        unwrap_submit_shares_extended,
+        MsgSubmitSharesExtended,
+        SubmitSharesExtended
+    );
     impl_unwrap!(
         unwrap_submit_shares_success,
         MsgSubmitSharesSuccess,
@@ -130,7 +152,27 @@ impl TestMessage {
     );
     impl_unwrap!(unwrap_set_new_prev_hash, MsgSetNewPrevHash, SetNewPrevHash);
     impl_unwrap!(unwrap_set_target, MsgSetTarget, SetTarget);
+    impl_unwrap!(
+        unwrap_set_custom_mining_job,
+        MsgSetCustomMiningJob,
+        SetCustomMiningJob
+    );
+    impl_unwrap!(
+        unwrap_set_custom_mining_job_success,
+        MsgSetCustomMiningJobSuccess,
+        SetCustomMiningJobSuccess
+    );
+    impl_unwrap!(
+        unwrap_set_custom_mining_job_error,
+        MsgSetCustomMiningJobError,
+        SetCustomMiningJobError
+    );
     impl_unwrap!(unwrap_reconnect, MsgReconnect, Reconnect);
+    impl_unwrap!(
+        unwrap_set_group_channel,
+        MsgSetGroupChannel,
+        SetGroupChannel
+    );
 }
 
 macro_rules! impl_from_msg_to_enum {
@@ -174,18 +216,28 @@ impl_conversions!(
     OpenStandardMiningChannelSuccess,
     MsgOpenStandardMiningChannelSuccess
 );
+impl_conversions!(OpenExtendedMiningChannel, MsgOpenExtendedMiningChannel);
+impl_conversions!(
+    OpenExtendedMiningChannelSuccess,
+    MsgOpenExtendedMiningChannelSuccess
+);
 impl_conversions!(OpenMiningChannelError, MsgOpenMiningChannelError);
 impl_conversions!(UpdateChannel, MsgUpdateChannel);
 impl_conversions!(UpdateChannelError, MsgUpdateChannelError);
 impl_conversions!(CloseChannel, MsgCloseChannel);
 impl_conversions!(SubmitSharesStandard, MsgSubmitSharesStandard);
+impl_conversions!(SubmitSharesExtended, MsgSubmitSharesExtended);
 impl_conversions!(SubmitSharesSuccess, MsgSubmitSharesSuccess);
 impl_conversions!(SubmitSharesError, MsgSubmitSharesError);
 impl_conversions!(NewMiningJob, MsgNewMiningJob);
 impl_conversions!(NewExtendedMiningJob, MsgNewExtendedMiningJob);
 impl_conversions!(SetNewPrevHash, MsgSetNewPrevHash);
 impl_conversions!(SetTarget, MsgSetTarget);
+impl_conversions!(SetCustomMiningJob, MsgSetCustomMiningJob);
+impl_conversions!(SetCustomMiningJobSuccess, MsgSetCustomMiningJobSuccess);
+impl_conversions!(SetCustomMiningJobError, MsgSetCustomMiningJobError);
 impl_conversions!(Reconnect, MsgReconnect);
+impl_conversions!(SetGroupChannel, MsgSetGroupChannel);
 
 #[derive(Default)]
 pub struct TestCollectorHandler {
@@ -221,6 +273,17 @@ impl TestCollectorHandler {
         self.messages.push_back(msg.into());
     }
 
+    async fn handle_open_extended_mining_channel(&mut self, msg: OpenExtendedMiningChannel) {
+        self.messages.push_back(msg.into());
+    }
+
+    async fn handle_open_extended_mining_channel_success(
+        &mut self,
+        msg: OpenExtendedMiningChannelSuccess,
+    ) {
+        self.messages.push_back(msg.into());
+    }
+
     async fn handle_open_mining_channel_error(&mut self, msg: OpenMiningChannelError) {
         self.messages.push_back(msg.into());
     }
@@ -241,6 +304,10 @@ impl TestCollectorHandler {
         self.messages.push_back(msg.into());
     }
 
+    async fn handle_submit_shares_extended(&mut self, msg: SubmitSharesExtended) {
+        self.messages.push_back(msg.into());
+    }
+
     async fn handle_submit_shares_success(&mut self, msg: SubmitSharesSuccess) {
         self.messages.push_back(msg.into());
     }
@@ -265,10 +332,26 @@ impl TestCollectorHandler {
         self.messages.push_back(msg.into());
     }
 
+    async fn handle_set_custom_mining_job(&mut self, msg: SetCustomMiningJob) {
+        self.messages.push_back(msg.into());
+    }
+
+    async fn handle_set_custom_mining_job_success(&mut self, msg: SetCustomMiningJobSuccess) {
+        self.messages.push_back(msg.into());
+    }
+
+    async fn handle_set_custom_mining_job_error(&mut self, msg: SetCustomMiningJobError) {
+        self.messages.push_back(msg.into());
+    }
+
     async fn handle_reconnect(&mut self, msg: Reconnect) {
         self.messages.push_back(msg.into());
     }
 
+    async fn handle_set_group_channel(&mut self, msg: SetGroupChannel) {
+        self.messages.push_back(msg.into());
+    }
+
     #[handle(_)]
     async fn handle_everything(&mut self, frame: Result<framing::Frame>) {
         let frame = frame.expect("BUG: Message parsing failed");
@@ -362,6 +445,10 @@ impl TestIdentityHandler {
         message_check(msg, build_submit_shares());
     }
 
+    async fn handle_submit_shares_extended(&mut self, msg: SubmitSharesExtended) {
+        message_check(msg, build_submit_shares_extended());
+    }
+
     async fn handle_submit_shares_success(&mut self, msg: SubmitSharesSuccess) {
         message_check(msg, build_submit_shares_success());
     }
@@ -377,16 +464,15 @@ impl TestIdentityHandler {
     }
 }
 
-#[cfg(not(feature = "v2json"))]
 pub const SETUP_CONNECTION_SERIALIZED: &[u8] =
     b"\x00\x02\x00\x02\x00\x00\x00\x00\x00\x15stratum.slushpool.com\x05\x0d\x07Braiins\x011\x15Braiins OS 2019-06-05\x03xyz";
-#[cfg(feature = "v2json")]
-pub const SETUP_CONNECTION_SERIALIZED: &[u8] =
-    br#"{"max_version":2,"min_version":2,"flags":0,"expected_pubkey":[],"endpoint_host":"stratum.slushpool.com","endpoint_port":3333,"device":{"vendor":"Braiins","hw_rev":"1","fw_ver":"Braiins OS 2019-06-05","dev_id":"xyz"}}"#;
+/// JSON serialized variant of `SETUP_CONNECTION_SERIALIZED`
+pub const SETUP_CONNECTION_JSON: &[u8] =
+    br#"{"protocol":0,"min_version":2,"max_version":2,"flags":0,"endpoint_host":"stratum.slushpool.com","endpoint_port":3333,"device":{"vendor":"Braiins","hw_rev":"1","fw_ver":"Braiins OS 2019-06-05","dev_id":"xyz"}}"#;
 
 pub fn build_setup_connection() -> SetupConnection {
     SetupConnection {
-        protocol: 0,
+        protocol: SubProtocol::Mining,
         max_version: 2,
         min_version: 2,
         flags: 0,
@@ -402,23 +488,23 @@ pub fn build_setup_connection() -> SetupConnection {
     }
 }
 
-pub const SETUP_CONNECTION_SUCCESS_SERIALIZED: &[u8] =
-    br#"{"protocol_version":0,"connection_url":"stratum.slushpool.com","required_extranonce_size":0}"#;
+pub const SETUP_CONNECTION_SUCCESS_SERIALIZED: &[u8] = b"\x02\x00\x00\x00\x00\x00";
 
 pub fn build_setup_connection_success() -> SetupConnectionSuccess {
     SetupConnectionSuccess {
-        used_version: 0,
+        used_version: 2,
         flags: 0,
     }
 }
 
 pub fn build_open_channel() -> OpenStandardMiningChannel {
-    OpenStandardMiningChannel {
-        req_id: 10,
-        user: Str0_255::try_from(USER_CREDENTIALS).expect("BUG: cannot convert from string"),
-        nominal_hashrate: 1e9,
-        max_target: ii_bitcoin::Target::default().into(),
-    }
+    OpenStandardMiningChannel::builder()
+        .req_id(10)
+        .user(USER_CREDENTIALS)
+        .nominal_hashrate(1e9)
+        .max_target(Uint256Bytes::from(ii_bitcoin::Target::default()))
+        .build()
+        .expect("BUG: cannot build OpenStandardMiningChannel")
 }
 
 pub fn build_open_channel_success() -> OpenStandardMiningChannelSuccess {
@@ -440,19 +526,43 @@ pub fn build_open_channel_success() -> OpenStandardMiningChannelSuccess {
     }
 }
 
+pub fn build_open_extended_channel() -> OpenExtendedMiningChannel {
+    let open_channel = build_open_channel();
+    OpenExtendedMiningChannel {
+        req_id: open_channel.req_id,
+        user: open_channel.user,
+        nominal_hashrate: open_channel.nominal_hashrate,
+        max_target: open_channel.max_target,
+        min_extranonce_size: 4,
+    }
+}
+
+pub fn build_open_extended_channel_success() -> OpenExtendedMiningChannelSuccess {
+    let open_channel_success = build_open_channel_success();
+    OpenExtendedMiningChannelSuccess {
+        request_id: open_channel_success.req_id,
+        channel_id: open_channel_success.channel_id,
+        target: open_channel_success.target,
+        extranonce_size: 4,
+        extranonce_prefix: Bytes0_32::try_from(vec![0x00, 0x01, 0x02, 0x03])
+            .expect("BUG: cannot convert from vector"),
+    }
+}
+
 /// TODO: see test_utils::v1::MINING_NOTIFY_JSON that defines a stratum v1 job.
 /// The merkle root below has been calculated by the integration test and cannot be trusted...
 /// We need a V1 mining job with verified merkle root that is to be copied
 pub fn build_new_mining_job() -> NewMiningJob {
     let expected_merkle_root =
         sha256d::Hash::from_hex(v1::MINING_NOTIFY_MERKLE_ROOT).expect("BUG: from_hex");
-    NewMiningJob {
-        channel_id: 0,
-        job_id: 0,
-        future_job: true,
-        merkle_root: Uint256Bytes(expected_merkle_root.into_inner()),
-        version: MINING_WORK_VERSION,
-    }
+    NewMiningJob::builder()
+        .channel_id(0)
+        .job_id(0)
+        .future_job(true)
+        .merkle_root(Uint256Bytes(expected_merkle_root.into_inner()))
+        .version(MINING_WORK_VERSION)
+        .build()
+        .expect("BUG: cannot build NewMiningJob")
 }
 
 pub fn build_set_new_prev_hash() -> SetNewPrevHash {
@@ -484,6 +594,21 @@ pub fn build_submit_shares() -> SubmitSharesStandard {
     }
 }
 
+pub fn build_submit_shares_extended() -> SubmitSharesExtended {
+    let submit_shares = build_submit_shares();
+
+    SubmitSharesExtended {
+        channel_id: submit_shares.channel_id,
+        seq_num: submit_shares.seq_num,
+        job_id: submit_shares.job_id,
+        nonce: submit_shares.nonce,
+        ntime: submit_shares.ntime,
+        version: submit_shares.version,
+        extranonce: Bytes0_32::try_from(vec![0x10, 0x20, 0x30, 0x40])
+            .expect("BUG: cannot convert from vector"),
+    }
+}
+
 pub fn build_submit_shares_success() -> SubmitSharesSuccess {
     SubmitSharesSuccess {
         channel_id: 0,
@@ -501,6 +626,55 @@ pub fn build_submit_shares_error() -> SubmitSharesError {
     }
 }
 
+pub fn build_set_custom_mining_job() -> SetCustomMiningJob {
+    let set_new_prev_hash = build_set_new_prev_hash();
+    let mining_job = build_new_mining_job();
+
+    SetCustomMiningJob {
+        channel_id: 0,
+        request_id: 1,
+        mining_job_token: Bytes0_255::try_from(vec![0xde, 0xad, 0xbe, 0xef])
+            .expect("BUG: cannot convert from vector"),
+        version: MINING_WORK_VERSION,
+        prev_hash: set_new_prev_hash.prev_hash,
+        min_ntime: set_new_prev_hash.min_ntime,
+        nbits: set_new_prev_hash.nbits,
+        coinbase_tx_version: 1,
+        coinbase_prefix: Bytes0_255::try_from(vec![0x03, 0x12, 0x34, 0x56])
+            .expect("BUG: cannot convert from vector"),
+        coinbase_tx_input_n_sequence: 0xffff_ffff,
+        coinbase_tx_value_remaining: 625_000_000,
+        coinbase_tx_outputs: Bytes0_64k::try_from(vec![0u8; 43])
+            .expect("BUG: cannot convert from vector"),
+        coinbase_tx_locktime: 0,
+        merkle_path: Seq0_255::try_from(vec![mining_job.merkle_root])
+            .expect("BUG: cannot convert from vector"),
+        extranonce_size: 8,
+        future_job: false,
+    }
+}
+
+pub fn build_set_custom_mining_job_success() -> SetCustomMiningJobSuccess {
+    SetCustomMiningJobSuccess {
+        channel_id: 0,
+        request_id: 1,
+        job_id: 0,
+        coinbase_tx_prefix: Bytes0_64k::try_from(vec![0x01, 0x02, 0x03])
+            .expect("BUG: cannot convert from vector"),
+        coinbase_tx_suffix: Bytes0_64k::try_from(vec![0x04, 0x05])
+            .expect("BUG: cannot convert from vector"),
+    }
+}
+
+pub fn build_set_custom_mining_job_error() -> SetCustomMiningJobError {
+    SetCustomMiningJobError {
+        channel_id: 0,
+        request_id: 1,
+        error_code: Str0_255::try_from("invalid-mining-job-token")
+            .expect("BUG: cannot convert from string"),
+    }
+}
+
 pub fn build_reconnect() -> Reconnect {
     Reconnect {
         new_host: Str0_255::try_from(POOL_URL).expect("BUG: cannot convert from string"),
@@ -508,6 +682,13 @@ pub fn build_reconnect() -> Reconnect {
     }
 }
 
+pub fn build_set_group_channel() -> SetGroupChannel {
+    SetGroupChannel {
+        group_channel_id: 1,
+        channel_ids: Seq0_64k::try_from(vec![2, 3, 5]).expect("BUG: cannot convert from vector"),
+    }
+}
+
 pub fn build_open_telemetry_channel() -> telemetry::messages::OpenTelemetryChannel {
     telemetry::messages::OpenTelemetryChannel {
         req_id: 0,
@@ -547,3 +728,38 @@ pub fn build_submit_telemetry_data_error() -> telemetry::messages::SubmitTelemet
         code: Default::default(),
     }
 }
+pub fn build_submit_device_status() -> telemetry::messages::SubmitDeviceStatus {
+    use telemetry::messages::{ChainReading, FanReading, TemperatureReading};
+
+    telemetry::messages::SubmitDeviceStatus {
+        seq_num: 1,
+        dev_id: Str0_255::try_from("xyz").expect("BUG: cannot convert from string"),
+        temperatures: Seq0_255::try_from(vec![
+            TemperatureReading {
+                sensor_id: 0,
+                celsius: 65.5,
+            },
+            TemperatureReading {
+                sensor_id: 1,
+                celsius: 71.0,
+            },
+        ])
+        .expect("BUG: cannot build temperatures"),
+        fans: Seq0_255::try_from(vec![FanReading {
+            fan_id: 0,
+            rpm: 4200,
+        }])
+        .expect("BUG: cannot build fans"),
+        chains: Seq0_255::try_from(vec![
+            ChainReading {
+                chain_id: 6,
+                hashrate: 4.5e12,
+            },
+            ChainReading {
+                chain_id: 7,
+                hashrate: 4.6e12,
+            },
+        ])
+        .expect("BUG: cannot build chains"),
+    }
+}
@@ -20,10 +20,12 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+pub mod correlation;
 pub mod error;
 pub mod framing;
 pub mod messages;
 pub mod rpc;
+pub mod server;
 
 use std::convert::TryFrom;
 use std::fmt::{self, Debug, Formatter};
@@ -41,8 +43,9 @@ pub use self::framing::codec::Codec;
 pub use self::framing::{Frame, Framing};
 use crate::error::Result;
 
-/// Tcp stream that produces/consumes V1 frames
-pub type Framed = tokio_util::codec::Framed<TcpStream, crate::v2::noise::CompoundCodec<Codec>>;
+/// Stream (TCP by default) that produces/consumes V1 frames
+pub type Framed<T = TcpStream> =
+    tokio_util::codec::Framed<T, crate::v2::noise::CompoundCodec<Codec>>;
 
 pub trait FramedSink:
     Sink<<Framing as ii_wire::Framing>::Tx, Error = <Framing as ii_wire::Framing>::Error>
@@ -0,0 +1,328 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Correlation of V1 responses with the requests that have been sent out. Each request gets a
+//! unique ID and an optional deadline, responses are paired with the original method and
+//! a caller provided context (e.g. a response handler).
+
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::time::{Duration, Instant};
+
+use super::error::Error;
+use super::rpc::{Method, RequestPayload, Rpc};
+use super::MessageId;
+use crate::error::Result;
+
+/// Request that is waiting for a response
+#[derive(Debug)]
+struct PendingRequest<T> {
+    method: Method,
+    context: T,
+    submitted: Instant,
+    deadline: Option<Instant>,
+}
+
+/// Request that has been paired with its response
+#[derive(Debug)]
+pub struct CompletedRequest<T> {
+    pub id: u32,
+    /// Method of the original request
+    pub method: Method,
+    /// Context provided upon registration of the request
+    pub context: T,
+    /// Time it took to receive the response
+    pub elapsed: Duration,
+}
+
+/// Tracks outstanding V1 requests. `T` is an arbitrary context that is associated with each
+/// request and returned back when the response arrives.
+#[derive(Debug)]
+pub struct RequestTracker<T> {
+    next_id: u32,
+    pending: HashMap<u32, PendingRequest<T>>,
+    /// Timeout for requests that don't have a method specific timeout, `None` means that such
+    /// requests never time out
+    default_timeout: Option<Duration>,
+    method_timeouts: HashMap<Method, Duration>,
+}
+
+impl<T> Default for RequestTracker<T> {
+    fn default() -> Self {
+        Self {
+            next_id: 0,
+            pending: HashMap::new(),
+            default_timeout: None,
+            method_timeouts: HashMap::new(),
+        }
+    }
+}
+
+impl<T> RequestTracker<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Unanswered requests time out after `timeout` (unless there is a method specific timeout)
+    pub fn with_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.default_timeout = timeout;
+        self
+    }
+
+    /// Requests of the specified `method` time out after `timeout`
+    pub fn with_method_timeout(mut self, method: Method, timeout: Duration) -> Self {
+        self.method_timeouts.insert(method, timeout);
+        self
+    }
+
+    /// Assigns a unique ID to a new request of `method` and starts tracking it
+    pub fn register(&mut self, method: Method, context: T) -> u32 {
+        // Skip IDs of requests that are still pending in the unlikely event of ID wrap around
+        while self.pending.contains_key(&self.next_id) {
+            self.next_id = self.next_id.wrapping_add(1);
+        }
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+
+        let submitted = Instant::now();
+        let deadline = self
+            .method_timeouts
+            .get(&method)
+            .copied()
+            .or(self.default_timeout)
+            .map(|timeout| submitted + timeout);
+        self.pending.insert(
+            id,
+            PendingRequest {
+                method,
+                context,
+                submitted,
+                deadline,
+            },
+        );
+        id
+    }
+
+    /// Builds an RPC request from `message` with a newly registered ID
+    pub fn build_request<M>(&mut self, message: M, context: T) -> Result<(u32, Rpc)>
+    where
+        M: TryInto<RequestPayload, Error = crate::error::Error>,
+    {
+        let payload = message.try_into()?;
+        let id = self.register(payload.method, context);
+        Ok((
+            id,
+            Rpc::Request(super::rpc::Request {
+                id: Some(id),
+                payload,
+            }),
+        ))
+    }
+
+    /// Pairs a response with `id` with the original request and stops tracking it
+    pub fn complete(&mut self, id: MessageId) -> Result<CompletedRequest<T>> {
+        let id = id.ok_or(Error::MissingRequestId)?;
+        let request = self
+            .pending
+            .remove(&id)
+            .ok_or(Error::UnknownRequestId(id))?;
+        Ok(CompletedRequest {
+            id,
+            method: request.method,
+            context: request.context,
+            elapsed: request.submitted.elapsed(),
+        })
+    }
+
+    /// Removes all requests that haven't been answered before their deadline and reports the
+    /// oldest one as an error
+    pub fn check_timeouts(&mut self, now: Instant) -> Result<()> {
+        let expired = self.remove_expired(now);
+        match expired.iter().min_by_key(|(_, request)| request.submitted) {
+            Some((id, request)) => Err(Error::RequestTimeout {
+                id: *id,
+                method: request.method,
+                elapsed: now.saturating_duration_since(request.submitted),
+            }
+            .into()),
+            None => Ok(()),
+        }
+    }
+
+    /// Stops tracking requests of `method` that haven't been answered before their deadline.
+    /// Unlike `check_timeouts()`, the expiration is not an error, the caller handles each expired
+    /// request on its own. Returns IDs of the expired requests from the oldest one.
+    pub fn take_expired(&mut self, method: Method, now: Instant) -> Vec<u32> {
+        let mut expired = self.remove_expired_by(now, |request| request.method == method);
+        expired.sort_by_key(|(_, request)| request.submitted);
+        expired.into_iter().map(|(id, _)| id).collect()
+    }
+
+    fn remove_expired(&mut self, now: Instant) -> Vec<(u32, PendingRequest<T>)> {
+        self.remove_expired_by(now, |_| true)
+    }
+
+    fn remove_expired_by<F>(&mut self, now: Instant, filter: F) -> Vec<(u32, PendingRequest<T>)>
+    where
+        F: Fn(&PendingRequest<T>) -> bool,
+    {
+        let expired_ids: Vec<u32> = self
+            .pending
+            .iter()
+            .filter(|(_, request)| matches!(request.deadline, Some(deadline) if deadline <= now))
+            .filter(|(_, request)| filter(request))
+            .map(|(id, _)| *id)
+            .collect();
+        expired_ids
+            .into_iter()
+            .filter_map(|id| self.pending.remove(&id).map(|request| (id, request)))
+            .collect()
+    }
+
+    /// Method of a pending request with `id`
+    pub fn method(&self, id: u32) -> Option<Method> {
+        self.pending.get(&id).map(|request| request.method)
+    }
+
+    /// Number of requests waiting for a response
+    pub fn len(&self) -> usize {
+        self.pending.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pending.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::v1::messages::{Authorize, Submit};
+
+    #[test]
+    fn complete_request() {
+        let mut tracker = RequestTracker::new();
+        let subscribe_id = tracker.register(Method::Subscribe, "subscribe");
+        let (authorize_id, rpc) = tracker
+            .build_request(
+                Authorize {
+                    name: "user".to_string(),
+                    password: "".to_string(),
+                },
+                "authorize",
+            )
+            .expect("BUG: cannot build request");
+        assert_ne!(subscribe_id, authorize_id);
+        assert!(matches!(rpc, Rpc::Request(request) if request.id == Some(authorize_id)));
+        assert_eq!(tracker.method(authorize_id), Some(Method::Authorize));
+        assert_eq!(tracker.len(), 2);
+
+        let completed = tracker
+            .complete(Some(authorize_id))
+            .expect("BUG: request not paired");
+        assert_eq!(completed.method, Method::Authorize);
+        assert_eq!(completed.context, "authorize");
+        assert_eq!(tracker.len(), 1);
+
+        // Each request can be completed only once
+        match tracker.complete(Some(authorize_id)) {
+            Err(crate::error::Error::V1(Error::UnknownRequestId(id))) => {
+                assert_eq!(id, authorize_id)
+            }
+            result => panic!("BUG: unexpected result {:?}", result),
+        }
+        assert!(matches!(
+            tracker.complete(None),
+            Err(crate::error::Error::V1(Error::MissingRequestId))
+        ));
+    }
+
+    #[test]
+    fn request_timeout() {
+        let mut tracker = RequestTracker::new()
+            .with_timeout(Some(Duration::from_secs(10)))
+            .with_method_timeout(Method::Submit, Duration::from_secs(1));
+        let now = Instant::now();
+        let subscribe_id = tracker.register(Method::Subscribe, ());
+        let (submit_id, _) = tracker
+            .build_request(
+                Submit::new(
+                    "user".to_string(),
+                    "job".parse().expect("BUG: cannot parse job ID"),
+                    &[0; 4],
+                    0,
+                    0,
+                    0,
+                ),
+                (),
+            )
+            .expect("BUG: cannot build request");
+
+        tracker
+            .check_timeouts(now)
+            .expect("BUG: requests timed out prematurely");
+        match tracker.check_timeouts(now + Duration::from_secs(5)) {
+            Err(crate::error::Error::V1(Error::RequestTimeout { id, method, .. })) => {
+                assert_eq!(id, submit_id);
+                assert_eq!(method, Method::Submit);
+            }
+            result => panic!("BUG: unexpected result {:?}", result),
+        }
+        // Timed out request is no longer tracked
+        assert_eq!(tracker.len(), 1);
+        assert!(tracker.complete(Some(submit_id)).is_err());
+        tracker
+            .complete(Some(subscribe_id))
+            .expect("BUG: request not paired");
+    }
+
+    #[test]
+    fn take_expired_requests() {
+        let mut tracker = RequestTracker::new()
+            .with_timeout(Some(Duration::from_secs(1)))
+            .with_method_timeout(Method::Submit, Duration::from_secs(1));
+        let now = Instant::now();
+        let subscribe_id = tracker.register(Method::Subscribe, ());
+        let submit_ids: Vec<_> = (0..3)
+            .map(|_| tracker.register(Method::Submit, ()))
+            .collect();
+        tracker
+            .complete(Some(submit_ids[1]))
+            .expect("BUG: request not paired");
+
+        assert!(tracker.take_expired(Method::Submit, now).is_empty());
+        let expired = tracker.take_expired(Method::Submit, now + Duration::from_secs(5));
+        assert_eq!(expired, vec![submit_ids[0], submit_ids[2]]);
+        // Requests of other methods are kept
+        assert_eq!(tracker.len(), 1);
+        assert_eq!(tracker.method(subscribe_id), Some(Method::Subscribe));
+    }
+
+    #[test]
+    fn no_timeout_by_default() {
+        let mut tracker = RequestTracker::new();
+        tracker.register(Method::Subscribe, ());
+        tracker
+            .check_timeouts(Instant::now() + Duration::from_secs(3600))
+            .expect("BUG: request without deadline timed out");
+    }
+}
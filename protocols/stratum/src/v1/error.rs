@@ -24,7 +24,10 @@
 
 use thiserror::Error;
 
+use crate::error::ErrorKind;
+
 #[derive(Error, Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
 pub enum Error {
     /// Json error.
     #[error("JSON error: {0}")]
@@ -38,4 +41,35 @@ pub enum Error {
 
     #[error("Submit error: {0}")]
     Submit(String),
+
+    #[error("Invalid mining job: {0}")]
+    MiningJob(String),
+
+    #[error("Response without request ID")]
+    MissingRequestId,
+
+    #[error("Response to an unknown request ID {0}")]
+    UnknownRequestId(u32),
+
+    #[error("Line exceeds the maximum length of {0} bytes")]
+    LineTooLong(usize),
+
+    #[error("Message rate exceeds {0} messages per second")]
+    MessageRateExceeded(u32),
+
+    #[error("Request {method:?} (id={id}) timed out after {elapsed:?}")]
+    RequestTimeout {
+        id: u32,
+        method: super::rpc::Method,
+        elapsed: std::time::Duration,
+    },
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::RequestTimeout { .. } => ErrorKind::Timeout,
+            _ => ErrorKind::Protocol,
+        }
+    }
 }
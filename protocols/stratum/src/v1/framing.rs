@@ -37,9 +37,14 @@ use crate::AnyPayload;
 pub struct Frame(Payload<Protocol>);
 
 impl Frame {
-    /// TODO use this constant
+    /// Default limit of the line length enforced by the codec
     pub const MAX_FRAME_LENGTH: usize = 16384;
 
+    /// Capacity reserved in the destination buffer before serializing a frame. It covers
+    /// common messages including `mining.notify` with a full merkle branch so that the
+    /// serializer doesn't have to grow the buffer repeatedly.
+    const SERIALIZE_RESERVE: usize = 1024;
+
     /// Builds a frame from `src`. No copying occurs as `BytesMut` allows us splitting off
     /// the payload part.
     pub(crate) fn deserialize(src: &mut BytesMut) -> Self {
@@ -61,8 +66,7 @@ impl Frame {
     /// Serializes a frame into a specified `dst` buffer. The method either copies the already
     /// serialized payload into the buffer or runs the on-demand serializer of the payload.
     pub fn serialize(&self, dst: &mut BytesMut) -> Result<()> {
-        // TODO reserve a reasonable chunk in the buffer - make it a constant
-        dst.reserve(128);
+        dst.reserve(Self::SERIALIZE_RESERVE);
         let mut payload_writer = dst.split().writer();
 
         self.0.serialize_to_writer(&mut payload_writer)?;
@@ -21,23 +21,89 @@
 // contact us at opensource@braiins.com.
 
 use bytes::{BufMut, BytesMut};
-use tokio_util::codec::{Decoder, Encoder, LinesCodec};
+use std::time::{Duration, Instant};
+use tokio_util::codec::{Decoder, Encoder, LinesCodec, LinesCodecError};
 
 use super::Frame;
 use crate::error::Error;
+use crate::v1::error::Error as V1Error;
 
 // FIXME: check bytesmut capacity when encoding (use BytesMut::remaining_mut())
 
+/// Counts received messages in one second windows
+#[derive(Debug)]
+struct RateLimit {
+    max_messages_per_sec: u32,
+    window_start: Instant,
+    messages: u32,
+}
+
+impl RateLimit {
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    fn new(max_messages_per_sec: u32) -> Self {
+        Self {
+            max_messages_per_sec,
+            window_start: Instant::now(),
+            messages: 0,
+        }
+    }
+
+    fn check(&mut self, now: Instant) -> Result<(), V1Error> {
+        if now.duration_since(self.window_start) >= Self::WINDOW {
+            self.window_start = now;
+            self.messages = 0;
+        }
+        self.messages += 1;
+        if self.messages > self.max_messages_per_sec {
+            return Err(V1Error::MessageRateExceeded(self.max_messages_per_sec));
+        }
+        Ok(())
+    }
+}
+
+/// Line based codec of V1 frames. Received lines are limited in length (see
+/// `Frame::MAX_FRAME_LENGTH`) and optionally also in rate so that the peer cannot force
+/// unbounded buffering.
 /// TODO consider generalizing the codec
 #[derive(Debug)]
-pub struct Codec(LinesCodec);
+pub struct Codec {
+    lines: LinesCodec,
+    max_line_length: usize,
+    rate_limit: Option<RateLimit>,
+}
+
+impl Codec {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Lines longer than `max_line_length` bytes (excluding the newline) fail decoding
+    pub fn with_max_line_length(mut self, max_line_length: usize) -> Self {
+        self.lines = LinesCodec::new_with_max_length(max_line_length);
+        self.max_line_length = max_line_length;
+        self
+    }
+
+    /// Decoding fails when the peer sends more than `max_messages_per_sec` messages within
+    /// a second
+    pub fn with_max_messages_per_sec(mut self, max_messages_per_sec: u32) -> Self {
+        self.rate_limit = Some(RateLimit::new(max_messages_per_sec));
+        self
+    }
+}
 
 impl Decoder for Codec {
     type Item = Frame;
     type Error = Error;
 
     fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
-        let frame_str = self.0.decode(src)?;
+        let frame_str = self.lines.decode(src).map_err(|e| match e {
+            LinesCodecError::MaxLineLengthExceeded => {
+                V1Error::LineTooLong(self.max_line_length).into()
+            }
+            e => Error::from(e),
+        })?;
         let mut bytes = match frame_str {
             // Note, creating `BytesMut` instance this way creates another copy of the incoming
             // data. We would have to implement a custom decode that would buffer the data
@@ -46,6 +112,9 @@ impl Decoder for Codec {
             Some(frame_str) => BytesMut::from(frame_str.as_bytes()),
             None => return Ok(None),
         };
+        if let Some(rate_limit) = self.rate_limit.as_mut() {
+            rate_limit.check(Instant::now())?;
+        }
         Ok(Some(Frame::deserialize(&mut bytes)))
     }
 }
@@ -62,8 +131,73 @@ impl Encoder<Frame> for Codec {
 
 impl Default for Codec {
     fn default() -> Self {
-        // TODO: limit line length with new_with_max_length() ?
-        // !!!! MUST BE LIMITED!!! 16kB seems about right
-        Codec(LinesCodec::new())
+        Self {
+            lines: LinesCodec::new_with_max_length(Frame::MAX_FRAME_LENGTH),
+            max_line_length: Frame::MAX_FRAME_LENGTH,
+            rate_limit: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_line_too_long() {
+        let mut codec = Codec::new().with_max_line_length(8);
+        let mut src = BytesMut::from(&b"{\"id\":1}\n0123456789\n"[..]);
+
+        assert!(codec
+            .decode(&mut src)
+            .expect("BUG: Short line should be decoded")
+            .is_some());
+        match codec.decode(&mut src) {
+            Err(Error::V1(V1Error::LineTooLong(8))) => (),
+            res => panic!("BUG: Unexpected result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_incomplete_line_too_long() {
+        // The limit applies to data without newline, too
+        let mut codec = Codec::default();
+        let mut src = BytesMut::from(&vec![b'x'; Frame::MAX_FRAME_LENGTH + 1][..]);
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(Error::V1(V1Error::LineTooLong(Frame::MAX_FRAME_LENGTH)))
+        ));
+    }
+
+    #[test]
+    fn test_message_rate_exceeded() {
+        let mut codec = Codec::new().with_max_messages_per_sec(2);
+        let mut src = BytesMut::from(&b"[]\n[]\n[]\n"[..]);
+
+        for _ in 0..2 {
+            assert!(codec
+                .decode(&mut src)
+                .expect("BUG: Message within limit should be decoded")
+                .is_some());
+        }
+        assert!(matches!(
+            codec.decode(&mut src),
+            Err(Error::V1(V1Error::MessageRateExceeded(2)))
+        ));
+    }
+
+    #[test]
+    fn test_rate_limit_window() {
+        let mut rate_limit = RateLimit::new(1);
+        let start = rate_limit.window_start;
+        rate_limit
+            .check(start)
+            .expect("BUG: First message rejected");
+        rate_limit
+            .check(start)
+            .expect_err("BUG: Second message within the window accepted");
+        rate_limit
+            .check(start + RateLimit::WINDOW)
+            .expect("BUG: Message in the next window rejected");
     }
 }
@@ -26,6 +26,7 @@ use std::convert::{TryFrom, TryInto};
 use std::result::Result as StdResult;
 
 use bitcoin_hashes::{sha256d, Hash, HashEngine};
+use bytes::Bytes;
 use serde::de::Deserializer;
 use serde::ser::Serializer;
 use serde::{Deserialize, Serialize};
@@ -203,6 +204,8 @@ pub struct VersionRolling {
 }
 
 impl VersionRolling {
+    pub const NAME: &'static str = "version-rolling";
+
     pub fn new(mask: u32, min_bit_count: usize) -> Self {
         Self {
             mask: VersionMask(HexU32Be(mask)),
@@ -215,7 +218,48 @@ impl TryInto<(String, serde_json::Value)> for VersionRolling {
     type Error = crate::error::Error;
 
     fn try_into(self) -> Result<(String, serde_json::Value)> {
-        Ok(("version-rolling".to_string(), serde_json::to_value(self)?))
+        Ok((Self::NAME.to_string(), serde_json::to_value(self)?))
+    }
+}
+
+/// Minimum difficulty extension (BIP310), the miner requests that the server never sets
+/// difficulty below `value`
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct MinimumDifficulty {
+    #[serde(rename = "minimum-difficulty.value")]
+    pub value: f64,
+}
+
+impl MinimumDifficulty {
+    pub const NAME: &'static str = "minimum-difficulty";
+
+    pub fn new(value: f64) -> Self {
+        Self { value }
+    }
+}
+
+impl TryInto<(String, serde_json::Value)> for MinimumDifficulty {
+    type Error = crate::error::Error;
+
+    fn try_into(self) -> Result<(String, serde_json::Value)> {
+        Ok((Self::NAME.to_string(), serde_json::to_value(self)?))
+    }
+}
+
+/// Subscribe extranonce extension (BIP310), the miner indicates that it handles
+/// `mining.set_extranonce`. This is an alternative to sending `mining.extranonce.subscribe`.
+#[derive(PartialEq, Clone, Debug)]
+pub struct SubscribeExtranonce;
+
+impl SubscribeExtranonce {
+    pub const NAME: &'static str = "subscribe-extranonce";
+}
+
+impl TryInto<(String, serde_json::Value)> for SubscribeExtranonce {
+    type Error = crate::error::Error;
+
+    fn try_into(self) -> Result<(String, serde_json::Value)> {
+        Ok((Self::NAME.to_string(), serde_json::Map::new().into()))
     }
 }
 
@@ -257,6 +301,36 @@ impl Configure {
 
         Ok(())
     }
+
+    pub fn features(&self) -> &[String] {
+        &self.features
+    }
+
+    pub fn has_feature(&self, name: &str) -> bool {
+        self.features.iter().any(|feature| feature == name)
+    }
+
+    /// Parameters of the feature `name` that is deserialized from the configuration map
+    fn feature<T: serde::de::DeserializeOwned>(&self, name: &str) -> Result<Option<T>> {
+        if !self.has_feature(name) {
+            return Ok(None);
+        }
+        serde_json::from_value(self.configure_map.clone())
+            .map(Some)
+            .map_err(|e| Error::Json(format!("Invalid {} parameters: {}", name, e)).into())
+    }
+
+    pub fn version_rolling(&self) -> Result<Option<VersionRolling>> {
+        self.feature(VersionRolling::NAME)
+    }
+
+    pub fn minimum_difficulty(&self) -> Result<Option<MinimumDifficulty>> {
+        self.feature(MinimumDifficulty::NAME)
+    }
+
+    pub fn subscribe_extranonce(&self) -> bool {
+        self.has_feature(SubscribeExtranonce::NAME)
+    }
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -264,10 +338,105 @@ pub struct ConfigureResult(pub serde_json::Value);
 
 impl_response!(ConfigureResult);
 
-/// Extranonce subscriptionMessage
-#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+impl ConfigureResult {
+    /// Builds the server answer to the `requested` features, only features that the client has
+    /// requested are answered
+    pub fn negotiated(requested: &Configure, negotiated: &NegotiatedFeatures) -> Self {
+        let mut result = serde_json::Map::new();
+        if requested.has_feature(VersionRolling::NAME) {
+            result.insert(
+                VersionRolling::NAME.into(),
+                negotiated.version_rolling_mask.is_some().into(),
+            );
+            if let Some(mask) = negotiated.version_rolling_mask {
+                result.insert(
+                    "version-rolling.mask".into(),
+                    Into::<String>::into(HexU32Be(mask)).into(),
+                );
+            }
+        }
+        if requested.has_feature(MinimumDifficulty::NAME) {
+            result.insert(
+                MinimumDifficulty::NAME.into(),
+                negotiated.minimum_difficulty.into(),
+            );
+        }
+        if requested.subscribe_extranonce() {
+            result.insert(
+                SubscribeExtranonce::NAME.into(),
+                negotiated.subscribe_extranonce.into(),
+            );
+        }
+        Self(result.into())
+    }
+}
+
+/// Outcome of `mining.configure`, features that the server hasn't answered are not enabled
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct NegotiatedFeatures {
+    /// Version rolling mask granted by the server, `None` if version rolling is not enabled
+    pub version_rolling_mask: Option<u32>,
+    pub minimum_difficulty: bool,
+    pub subscribe_extranonce: bool,
+}
+
+impl TryFrom<&ConfigureResult> for NegotiatedFeatures {
+    type Error = crate::error::Error;
+
+    fn try_from(result: &ConfigureResult) -> Result<Self> {
+        let result = result
+            .0
+            .as_object()
+            .ok_or_else(|| Error::Json(format!("Invalid configure result: {}", result.0)))?;
+        // Features are enabled with `true`, the server may also explain a refusal with a string
+        let is_enabled =
+            |name: &str| matches!(result.get(name), Some(serde_json::Value::Bool(true)));
+
+        let version_rolling_mask = if is_enabled(VersionRolling::NAME) {
+            let mask = result
+                .get("version-rolling.mask")
+                .and_then(serde_json::Value::as_str)
+                .ok_or_else(|| Error::Json("Missing version-rolling.mask".into()))?;
+            Some(HexU32Be::try_from(mask)?.0)
+        } else {
+            None
+        };
+
+        Ok(Self {
+            version_rolling_mask,
+            minimum_difficulty: is_enabled(MinimumDifficulty::NAME),
+            subscribe_extranonce: is_enabled(SubscribeExtranonce::NAME),
+        })
+    }
+}
+
+/// Extranonce subscription message that signals the server that the client is able to handle
+/// `SetExtranonce` (NiceHash convention, also referred to as #xnsub)
+#[derive(PartialEq, Clone, Debug)]
 pub struct ExtranonceSubscribe;
 
+/// The request has no parameters, however, the server expects an empty array and not `null` (that
+/// a unit struct would be serialized into)
+impl Serialize for ExtranonceSubscribe {
+    fn serialize<S>(&self, serializer: S) -> StdResult<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        Vec::<serde_json::Value>::new().serialize(serializer)
+    }
+}
+
+/// Any parameters are ignored
+impl<'de> Deserialize<'de> for ExtranonceSubscribe {
+    fn deserialize<D>(deserializer: D) -> StdResult<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        Option::<Vec<serde_json::Value>>::deserialize(deserializer)?;
+        Ok(Self)
+    }
+}
+
 impl_request!(ExtranonceSubscribe, Method::ExtranonceSubscribe);
 
 declare_request!(
@@ -326,6 +495,30 @@ impl SubscribeResult {
     pub fn extra_nonce_2_size(&self) -> usize {
         self.2
     }
+
+    /// Session ID that can be passed to `Subscribe` on reconnect so that the server may resume
+    /// the session (extranonce, difficulty). By convention, it's the ID of the `mining.notify`
+    /// subscription. Servers differ in whether they send a list of subscriptions or just
+    /// a single one.
+    pub fn session_id(&self) -> Option<ExtraNonce1> {
+        let is_notify = |subscription: &[serde_json::Value]| {
+            matches!(subscription.first(), Some(serde_json::Value::String(method))
+                if method == "mining.notify")
+        };
+        let notify_subscription = if is_notify(&self.0) {
+            Some(&self.0)
+        } else {
+            self.0
+                .iter()
+                .filter_map(serde_json::Value::as_array)
+                .find(|subscription| is_notify(subscription))
+        };
+        notify_subscription
+            .and_then(|subscription| subscription.get(1))
+            .and_then(serde_json::Value::as_str)
+            .and_then(|session_id| HexBytes::try_from(session_id).ok())
+            .map(ExtraNonce1)
+    }
 }
 
 // TODO write a test case for parsing incorrect response
@@ -368,6 +561,47 @@ impl From<f32> for SetDifficulty {
     }
 }
 
+/// Difficulty hint that the client sends to the server, it is usually sent before
+/// `mining.authorize`. The server is free to ignore it.
+/// Same as with `SetDifficulty`, the single element array keeps the 'params' JSON array intact
+#[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
+pub struct SuggestDifficulty(pub [f32; 1]);
+
+impl_request!(SuggestDifficulty, Method::SuggestDifficulty);
+
+impl SuggestDifficulty {
+    pub fn value(&self) -> f32 {
+        self.0[0]
+    }
+}
+
+impl From<f32> for SuggestDifficulty {
+    fn from(f: f32) -> Self {
+        Self([f])
+    }
+}
+
+declare_request!(
+    "Target hint that the client sends to the server as an alternative to `SuggestDifficulty`.
+    The target is a 256-bit big endian number encoded as hex string",
+    Method::SuggestTarget,
+    struct SuggestTarget {
+        target: HexBytes,
+    }
+);
+
+impl SuggestTarget {
+    pub fn new(target: &[u8]) -> Self {
+        Self {
+            target: HexBytes(target.into()),
+        }
+    }
+
+    pub fn target(&self) -> &[u8] {
+        &(self.target).0
+    }
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct JobId(String);
 
@@ -418,14 +652,44 @@ declare_request!(
     }
 );
 
-impl MerkleBranch {
-    pub fn fold_branch_into_merkle_root(&self, cb_tx_hash: sha256d::Hash) -> sha256d::Hash {
-        self.0.iter().fold(cb_tx_hash, |curr_merkle_root, tx_hash| {
+/// Hash of the coinbase transaction assembled from both parts of the coinbase and the extra
+/// nonces in between
+fn coin_base_hash(
+    coin_base_1: &[u8],
+    extranonce1: &[u8],
+    extranonce2: &[u8],
+    coin_base_2: &[u8],
+) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(coin_base_1);
+    engine.input(extranonce1);
+    engine.input(extranonce2);
+    engine.input(coin_base_2);
+    sha256d::Hash::from_engine(engine)
+}
+
+/// Folds transaction hashes of a merkle branch into the coinbase transaction hash and yields
+/// the merkle root
+fn fold_merkle_branch<'a, I>(cb_tx_hash: sha256d::Hash, merkle_branch: I) -> sha256d::Hash
+where
+    I: IntoIterator<Item = &'a [u8]>,
+{
+    merkle_branch
+        .into_iter()
+        .fold(cb_tx_hash, |curr_merkle_root, tx_hash| {
             let mut engine = sha256d::Hash::engine();
             engine.input(&curr_merkle_root.into_inner());
-            engine.input(tx_hash.as_ref().as_slice());
+            engine.input(tx_hash);
             sha256d::Hash::from_engine(engine)
         })
+}
+
+impl MerkleBranch {
+    pub fn fold_branch_into_merkle_root(&self, cb_tx_hash: sha256d::Hash) -> sha256d::Hash {
+        fold_merkle_branch(
+            cb_tx_hash,
+            self.0.iter().map(|tx_hash| tx_hash.as_ref().as_slice()),
+        )
     }
 
     pub fn v2_encode(&self) -> Result<v2::types::Seq0_255<v2::types::Uint256Bytes>> {
@@ -461,18 +725,12 @@ impl Notify {
     }
 
     pub fn merkle_root(&self, extranonce1: &[u8], extranonce2: &[u8]) -> sha256d::Hash {
-        let mut coin_base = Vec::with_capacity(
-            self.coin_base_1().len()
-                + extranonce1.len()
-                + extranonce2.len()
-                + self.coin_base_2().len(),
+        let cb_tx_hash = coin_base_hash(
+            self.coin_base_1(),
+            extranonce1,
+            extranonce2,
+            self.coin_base_2(),
         );
-        coin_base.extend_from_slice(self.coin_base_1());
-        coin_base.extend_from_slice(extranonce1);
-        coin_base.extend_from_slice(extranonce2);
-        coin_base.extend_from_slice(self.coin_base_2());
-
-        let cb_tx_hash = sha256d::Hash::hash(&coin_base);
         self.merkle_branch()
             .fold_branch_into_merkle_root(cb_tx_hash)
     }
@@ -494,6 +752,86 @@ impl Notify {
     }
 }
 
+/// Mining job from `Notify` with validated fields of fixed size
+#[derive(PartialEq, Clone, Debug)]
+pub struct MiningJob {
+    pub job_id: String,
+    /// Previous block hash in the byte order of the block header
+    pub prev_hash: [u8; 32],
+    pub coin_base_1: Bytes,
+    pub coin_base_2: Bytes,
+    /// Transaction hashes of the merkle branch leading to the coinbase
+    pub merkle_branch: Vec<[u8; 32]>,
+    pub version: u32,
+    pub bits: u32,
+    pub time: u32,
+    pub clean_jobs: bool,
+}
+
+impl MiningJob {
+    /// Calculates the merkle root of the block with coinbase that contains the extra nonces
+    pub fn merkle_root(&self, extranonce1: &[u8], extranonce2: &[u8]) -> sha256d::Hash {
+        let cb_tx_hash = coin_base_hash(
+            &self.coin_base_1,
+            extranonce1,
+            extranonce2,
+            &self.coin_base_2,
+        );
+        fold_merkle_branch(
+            cb_tx_hash,
+            self.merkle_branch.iter().map(|tx_hash| &tx_hash[..]),
+        )
+    }
+}
+
+impl TryFrom<&Notify> for MiningJob {
+    type Error = crate::error::Error;
+
+    fn try_from(notify: &Notify) -> Result<Self> {
+        let prev_hash = <[u8; 32]>::try_from(notify.prev_hash()).map_err(|_| {
+            Error::MiningJob(format!(
+                "job {}: invalid prev hash length {}",
+                notify.job_id(),
+                notify.prev_hash().len()
+            ))
+        })?;
+        let merkle_branch = (notify.merkle_branch().0)
+            .iter()
+            .enumerate()
+            .map(|(i, tx_hash)| {
+                <[u8; 32]>::try_from(tx_hash.as_ref().as_slice()).map_err(|_| {
+                    Error::MiningJob(format!(
+                        "job {}: invalid length {} of merkle branch {}",
+                        notify.job_id(),
+                        tx_hash.len(),
+                        i
+                    ))
+                })
+            })
+            .collect::<StdResult<_, _>>()?;
+
+        Ok(Self {
+            job_id: notify.job_id().to_string(),
+            prev_hash,
+            coin_base_1: Bytes::copy_from_slice(notify.coin_base_1()),
+            coin_base_2: Bytes::copy_from_slice(notify.coin_base_2()),
+            merkle_branch,
+            version: notify.version(),
+            bits: notify.bits(),
+            time: notify.time(),
+            clean_jobs: notify.clean_jobs(),
+        })
+    }
+}
+
+impl TryFrom<Notify> for MiningJob {
+    type Error = crate::error::Error;
+
+    fn try_from(notify: Notify) -> Result<Self> {
+        Self::try_from(&notify)
+    }
+}
+
 declare_request!(
     "Server may arbitrarily adjust version mask",
     Method::SetVersionMask,
@@ -599,10 +937,36 @@ impl ClientReconnect {
     }
 }
 
+/// Keep alive request, it has no parameters
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct Ping(pub Vec<serde_json::Value>);
 impl_request!(Ping, Method::Ping);
 
+impl Default for Ping {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Ping {
+    pub fn new() -> Self {
+        Self(vec![])
+    }
+}
+
+/// Result of `Ping`
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct Pong(pub String);
 impl_response!(Pong);
+
+impl Default for Pong {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Pong {
+    pub fn new() -> Self {
+        Self("pong".to_string())
+    }
+}
@@ -115,3 +115,181 @@ fn test_subscribe_malformed_result_json() {
         Rpc::Request(_) => (),
     }
 }
+
+#[test]
+fn test_build_suggest_messages_from_rpc_request() {
+    if let Rpc::Request(req) =
+        Rpc::from_str(MINING_SUGGEST_DIFFICULTY_JSON).expect("BUG: Cannot parse request")
+    {
+        let suggest_difficulty = SuggestDifficulty::try_from(req).expect("BUG: Conversion failed");
+        assert_eq!(suggest_difficulty.value(), 512f32);
+    } else {
+        panic!("BUG: Request expected");
+    }
+
+    if let Rpc::Request(req) =
+        Rpc::from_str(MINING_SUGGEST_TARGET_JSON).expect("BUG: Cannot parse request")
+    {
+        let suggest_target = SuggestTarget::try_from(req).expect("BUG: Conversion failed");
+        assert_eq!(suggest_target.target().len(), 32);
+        assert_eq!(suggest_target, build_suggest_target());
+    } else {
+        panic!("BUG: Request expected");
+    }
+}
+
+#[test]
+fn test_mining_job_from_notify() {
+    let notify = build_mining_notify();
+    let job = MiningJob::try_from(&notify).expect("BUG: Cannot build mining job");
+
+    assert_eq!(job.job_id, "ahoj");
+    assert_eq!(&job.prev_hash[..], notify.prev_hash());
+    assert_eq!(job.coin_base_1.as_ref(), notify.coin_base_1());
+    assert_eq!(job.coin_base_2.as_ref(), notify.coin_base_2());
+    assert!(job.merkle_branch.is_empty());
+    assert_eq!(job.version, 0x20000000);
+    assert_eq!(job.bits, 0x1d00ffff);
+    assert_eq!(job.time, 0x5d10bc0a);
+    assert!(!job.clean_jobs);
+
+    let extranonce1 = [0u8; 4];
+    let extranonce2 = [0u8; 4];
+    assert_eq!(
+        job.merkle_root(&extranonce1, &extranonce2),
+        notify.merkle_root(&extranonce1, &extranonce2)
+    );
+}
+
+#[test]
+fn test_mining_job_rejects_malformed_notify() {
+    let mut notify = build_mining_notify();
+    notify.prev_hash = PrevHash(vec![0; 31]);
+    match MiningJob::try_from(&notify) {
+        Err(crate::error::Error::V1(Error::MiningJob(_))) => (),
+        res => panic!("BUG: Unexpected result {:?}", res),
+    }
+
+    let mut notify = build_mining_notify();
+    notify.merkle_branch = MerkleBranch(vec![HexBytes(vec![0; 32]), HexBytes(vec![0; 16])]);
+    match MiningJob::try_from(&notify) {
+        Err(crate::error::Error::V1(Error::MiningJob(msg))) => assert!(msg.contains("branch 1")),
+        res => panic!("BUG: Unexpected result {:?}", res),
+    }
+}
+
+#[test]
+fn test_subscribe_result_session_id() {
+    let extra_nonce1 = build_subscribe_ok_result().extra_nonce_1().clone();
+    let session_id = ExtraNonce1(HexBytes::try_from("ae6812eb").expect("BUG: Invalid hex"));
+
+    // List of subscriptions
+    let subscriptions = serde_json::json!([
+        ["mining.set_difficulty", "b4b6693b"],
+        ["mining.notify", "ae6812eb"]
+    ]);
+    let result = SubscribeResult(
+        serde_json::from_value(subscriptions).expect("BUG: Invalid subscriptions"),
+        extra_nonce1.clone(),
+        4,
+    );
+    assert_eq!(result.session_id(), Some(session_id.clone()));
+
+    // Single subscription
+    let subscriptions = serde_json::json!(["mining.notify", "ae6812eb"]);
+    let result = SubscribeResult(
+        serde_json::from_value(subscriptions).expect("BUG: Invalid subscriptions"),
+        extra_nonce1.clone(),
+        4,
+    );
+    assert_eq!(result.session_id(), Some(session_id));
+
+    // No subscription details
+    assert_eq!(build_subscribe_ok_result().session_id(), None);
+}
+
+#[test]
+fn test_configure_features() {
+    let mut configure = build_configure();
+    configure
+        .add_feature(MinimumDifficulty::new(2048.0))
+        .expect("BUG: Cannot add minimum difficulty");
+    configure
+        .add_feature(SubscribeExtranonce)
+        .expect("BUG: Cannot add subscribe extranonce");
+
+    let rpc = build_request_message(Some(0), configure.clone());
+    let configure = Configure::try_from(rpc).expect("BUG: Cannot parse configure");
+    assert_eq!(
+        configure.features(),
+        &[
+            VersionRolling::NAME,
+            MinimumDifficulty::NAME,
+            SubscribeExtranonce::NAME
+        ]
+    );
+    assert_eq!(
+        configure
+            .version_rolling()
+            .expect("BUG: Invalid version rolling"),
+        Some(VersionRolling::new(
+            crate::BIP320_N_VERSION_MASK,
+            crate::BIP320_N_VERSION_MAX_BITS
+        ))
+    );
+    assert_eq!(
+        configure
+            .minimum_difficulty()
+            .expect("BUG: Invalid minimum difficulty"),
+        Some(MinimumDifficulty::new(2048.0))
+    );
+    assert!(configure.subscribe_extranonce());
+
+    // Features that haven't been requested are not present
+    assert_eq!(
+        Configure::new()
+            .version_rolling()
+            .expect("BUG: Invalid version rolling"),
+        None
+    );
+}
+
+#[test]
+fn test_negotiated_features() {
+    let negotiated = NegotiatedFeatures::try_from(&build_configure_ok_result())
+        .expect("BUG: Cannot parse configure result");
+    assert_eq!(
+        negotiated,
+        NegotiatedFeatures {
+            version_rolling_mask: Some(crate::BIP320_N_VERSION_MASK),
+            ..Default::default()
+        }
+    );
+
+    // Only requested features are answered
+    let result = ConfigureResult::negotiated(&build_configure(), &negotiated);
+    assert_eq!(result, build_configure_ok_result());
+
+    let mut configure = build_configure();
+    configure
+        .add_feature(SubscribeExtranonce)
+        .expect("BUG: Cannot add subscribe extranonce");
+    let negotiated = NegotiatedFeatures {
+        version_rolling_mask: None,
+        minimum_difficulty: false,
+        subscribe_extranonce: true,
+    };
+    let result = ConfigureResult::negotiated(&configure, &negotiated);
+    assert_eq!(
+        result.0,
+        serde_json::json!({"version-rolling": false, "subscribe-extranonce": true})
+    );
+    assert_eq!(
+        NegotiatedFeatures::try_from(&result).expect("BUG: Cannot parse configure result"),
+        negotiated
+    );
+
+    // Version rolling without mask is invalid
+    let result = ConfigureResult(serde_json::json!({"version-rolling": true}));
+    NegotiatedFeatures::try_from(&result).expect_err("BUG: Missing mask should be rejected");
+}
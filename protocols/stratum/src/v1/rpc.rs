@@ -40,7 +40,7 @@ use crate::AnyPayload;
 use ii_logging::macros::*;
 use ii_unvariant::{id, GetId, Id};
 
-#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq)]
+#[derive(Clone, Copy, Serialize, Deserialize, Debug, PartialEq, Eq, Hash)]
 pub enum Method {
     #[serde(rename = "mining.subscribe")]
     Subscribe,
@@ -64,6 +64,10 @@ pub enum Method {
     ClientReconnect,
     #[serde(rename = "mining.ping")]
     Ping,
+    #[serde(rename = "mining.suggest_difficulty")]
+    SuggestDifficulty,
+    #[serde(rename = "mining.suggest_target")]
+    SuggestTarget,
     // Extensions so that Method can be used as an Id by Rpc's GetId
     #[serde(skip)]
     Result,
@@ -258,13 +262,66 @@ impl TryFrom<Response> for ResponsePayload {
     }
 }
 
+/// Strictness of parsing of received V1 messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum ParseMode {
+    /// Messages have to conform to the protocol exactly
+    #[default]
+    Strict,
+    /// Known deviations of real-world stratum servers are corrected before parsing:
+    /// - difficulty sent as a string
+    /// - error sent as a string, an object, `false` or an array of unexpected length
+    /// - numeric message ID sent as a string
+    Lenient,
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
-#[serde(untagged)]
+#[serde(untagged, try_from = "RawRpc")]
 pub enum Rpc {
     Request(Request),
     Response(Response),
 }
 
+/// Flat representation of any RPC that is used for deserialization. It is a fast path
+/// compared to deserializing the untagged `Rpc` enum directly, which would buffer the whole
+/// message and attempt deserializing it as every variant in turn.
+#[derive(Deserialize)]
+struct RawRpc {
+    #[serde(default)]
+    id: MessageId,
+    #[serde(default)]
+    method: Option<Method>,
+    #[serde(default)]
+    params: Option<Value>,
+    #[serde(default)]
+    result: Option<StratumResult>,
+    #[serde(default)]
+    error: Option<StratumError>,
+}
+
+impl TryFrom<RawRpc> for Rpc {
+    type Error = String;
+
+    fn try_from(raw: RawRpc) -> StdResult<Self, Self::Error> {
+        match raw.method {
+            Some(method) => Ok(Rpc::Request(Request {
+                id: raw.id,
+                payload: RequestPayload {
+                    method,
+                    params: raw
+                        .params
+                        .ok_or_else(|| "missing field `params`".to_string())?,
+                },
+            })),
+            None => Ok(Rpc::Response(Response {
+                id: raw.id.ok_or_else(|| "missing field `id`".to_string())?,
+                stratum_result: raw.result,
+                stratum_error: raw.error,
+            })),
+        }
+    }
+}
+
 impl GetId for Rpc {
     type Id = Method;
 
@@ -282,6 +339,99 @@ impl GetId for Rpc {
     }
 }
 
+impl Rpc {
+    /// Normalizes an RPC response to support various broken stratum dialects
+    fn normalize(self) -> Result<Self> {
+        match self {
+            Rpc::Response(response) => Ok(Rpc::Response(response.json_rpc_normalize()?)),
+            request => Ok(request),
+        }
+    }
+
+    /// Deserializes a single RPC from JSON `value`, the value is corrected first in lenient
+    /// `mode`
+    fn from_value(mut value: Value, mode: ParseMode) -> StdResult<Self, serde_json::Error> {
+        if mode == ParseMode::Lenient {
+            quirks::correct_rpc(&mut value);
+        }
+        serde_json::from_value(value)
+    }
+
+    /// Parses a single RPC from `frame` with the specified strictness
+    pub fn parse(frame: &[u8], mode: ParseMode) -> Result<Self> {
+        match mode {
+            ParseMode::Strict => Self::try_from(frame),
+            ParseMode::Lenient => serde_json::from_slice::<Value>(frame)
+                .and_then(|value| Self::from_value(value, mode))
+                .map_err(|e| Self::invalid_message_error(e, frame))?
+                .normalize(),
+        }
+    }
+
+    /// Parses all RPCs from `frame` that contains either a single RPC or a JSON-RPC batch (JSON
+    /// array of RPCs). Each element of a batch is deserialized individually so that a malformed
+    /// element doesn't prevent handling of the remaining ones. The outer error is reported only
+    /// when the frame cannot be parsed at all, i.e. it's neither a valid RPC nor a JSON array.
+    pub fn parse_batch(frame: &[u8], mode: ParseMode) -> Result<Vec<Result<Self>>> {
+        let first_char = frame.iter().find(|c| !c.is_ascii_whitespace());
+        if !matches!(first_char, Some(b'[')) {
+            return Ok(vec![Ok(Self::parse(frame, mode)?)]);
+        }
+        let elements = serde_json::from_slice::<Vec<Value>>(frame)
+            .map_err(|e| Self::invalid_message_error(e, frame))?;
+        Ok(elements
+            .into_iter()
+            .map(|element| {
+                Self::from_value(element, mode)
+                    .map_err(|e| V1Error::Json(format!("Invalid V1 batch element: {}", e)))?
+                    .normalize()
+            })
+            .collect())
+    }
+
+    /// Same as `parse_batch()` for a received `frame`
+    pub fn from_frame_batch(frame: framing::Frame, mode: ParseMode) -> Result<Vec<Result<Self>>> {
+        let payload = frame.into_inner().into_bytes_mut()?;
+        Self::parse_batch(&payload[..], mode)
+    }
+
+    fn invalid_message_error(e: serde_json::Error, frame: &[u8]) -> Error {
+        let (frame, suffix) = if frame.len() > 256 {
+            (&frame[..256], "[snip]")
+        } else {
+            (frame, "")
+        };
+        let frame = String::from_utf8_lossy(frame);
+
+        V1Error::Json(format!("Invalid V1 message: {}\n{}{}", e, frame, suffix)).into()
+    }
+}
+
+/// Multiple RPCs that are sent in a single frame as a JSON array (JSON-RPC batch)
+#[derive(Serialize, PartialEq, Debug, Clone, Default)]
+#[serde(transparent)]
+pub struct RpcBatch(pub Vec<Rpc>);
+
+impl From<Vec<Rpc>> for RpcBatch {
+    fn from(rpcs: Vec<Rpc>) -> Self {
+        Self(rpcs)
+    }
+}
+
+impl TryFrom<RpcBatch> for framing::Frame {
+    type Error = Error;
+
+    fn try_from(batch: RpcBatch) -> Result<Self> {
+        Ok(framing::Frame::from_serializable_payload(batch))
+    }
+}
+
+impl AnyPayload<Protocol> for RpcBatch {
+    fn serialize_to_writer(&self, writer: &mut dyn std::io::Write) -> Result<()> {
+        serde_json::to_writer(writer, self).map_err(Into::into)
+    }
+}
+
 impl From<Request> for Rpc {
     fn from(req: Request) -> Self {
         Rpc::Request(req)
@@ -298,23 +448,9 @@ impl TryFrom<&[u8]> for Rpc {
     type Error = Error;
 
     fn try_from(frame: &[u8]) -> Result<Self> {
-        let rpc_cmd = serde_json::from_slice::<Self>(&frame).map_err(|e| {
-            let (frame, suffix) = if frame.len() > 256 {
-                (&frame[..256], "[snip]")
-            } else {
-                (&frame[..], "")
-            };
-            let frame = String::from_utf8_lossy(frame);
-
-            V1Error::Json(format!("Invalid V1 message: {}\n{}{}", e, frame, suffix))
-        })?;
-        // Normalize an RPC response to support various broken stratum dialects
-        let rpc = if let Rpc::Response(response) = rpc_cmd {
-            Rpc::Response(response.json_rpc_normalize()?)
-        } else {
-            rpc_cmd
-        };
-        Ok(rpc)
+        serde_json::from_slice::<Self>(frame)
+            .map_err(|e| Self::invalid_message_error(e, frame))?
+            .normalize()
     }
 }
 
@@ -357,10 +493,101 @@ impl AnyPayload<Protocol> for Rpc {
     }
 }
 
+/// Corrections of nonstandard JSON produced by some stratum servers. Anything that cannot be
+/// corrected is left intact so that the regular deserialization reports it.
+mod quirks {
+    use serde_json::{Number, Value};
+
+    use crate::v1::server::error_codes;
+
+    pub(super) fn correct_rpc(rpc: &mut Value) {
+        let rpc = match rpc.as_object_mut() {
+            Some(rpc) => rpc,
+            None => return,
+        };
+        if let Some(id) = rpc.get_mut("id") {
+            correct_id(id);
+        }
+        if let Some(error) = rpc.get_mut("error") {
+            correct_error(error);
+        }
+        let is_difficulty = matches!(
+            rpc.get("method").and_then(Value::as_str),
+            Some("mining.set_difficulty") | Some("mining.suggest_difficulty")
+        );
+        if is_difficulty {
+            if let Some(Value::Array(params)) = rpc.get_mut("params") {
+                params.iter_mut().for_each(correct_float);
+            }
+        }
+    }
+
+    fn correct_id(id: &mut Value) {
+        if let Some(parsed) = id.as_str().and_then(|s| s.trim().parse::<u32>().ok()) {
+            *id = parsed.into();
+        }
+    }
+
+    fn correct_float(value: &mut Value) {
+        if let Some(parsed) = value
+            .as_str()
+            .and_then(|s| s.trim().parse::<f64>().ok())
+            .and_then(Number::from_f64)
+        {
+            *value = Value::Number(parsed);
+        }
+    }
+
+    fn correct_code(code: &mut Value) {
+        if code.is_null() {
+            *code = error_codes::OTHER.into();
+        } else if let Some(parsed) = code.as_str().and_then(|s| s.trim().parse::<i32>().ok()) {
+            *code = parsed.into();
+        }
+    }
+
+    /// Converts the error into the `[code, message, traceback]` triplet
+    fn correct_error(error: &mut Value) {
+        *error = match error.take() {
+            Value::Bool(false) => Value::Null,
+            Value::String(msg) if msg.is_empty() => Value::Null,
+            Value::String(msg) => Value::Array(vec![Value::Null, msg.into(), Value::Null]),
+            Value::Array(parts) if parts.is_empty() => Value::Null,
+            Value::Object(mut fields) => {
+                let code = fields.remove("code").unwrap_or(Value::Null);
+                let msg = fields
+                    .remove("message")
+                    .or_else(|| fields.remove("msg"))
+                    .unwrap_or(Value::Null);
+                let traceback = fields
+                    .remove("data")
+                    .or_else(|| fields.remove("traceback"))
+                    .unwrap_or(Value::Null);
+                Value::Array(vec![code, msg, traceback])
+            }
+            other => other,
+        };
+
+        if let Value::Array(parts) = error {
+            parts.resize(3, Value::Null);
+            correct_code(&mut parts[0]);
+            for part in &mut parts[1..] {
+                if !part.is_string() && !part.is_null() {
+                    *part = part.to_string().into();
+                }
+            }
+            if parts[1].is_null() {
+                parts[1] = String::new().into();
+            }
+        }
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
     use crate::test_utils::v1::*;
+    use crate::v1::messages::SetDifficulty;
     use bytes::BytesMut;
     use std::convert::TryInto;
 
@@ -406,6 +633,21 @@ mod test {
             .expect_err("BUG: Deserializing a broken request should've failed");
     }
 
+    #[test]
+    fn deserialize_incomplete_rpc() {
+        // Request without parameters
+        Rpc::try_from(r#"{"id": 1, "method": "mining.submit"}"#.as_bytes())
+            .expect_err("BUG: Deserializing a request without params should've failed");
+        // Response without an ID
+        Rpc::try_from(r#"{"result": true, "error": null}"#.as_bytes())
+            .expect_err("BUG: Deserializing a response without ID should've failed");
+        // Notification (request with null ID)
+        let rpc =
+            Rpc::try_from(r#"{"id": null, "method": "mining.ping", "params": []}"#.as_bytes())
+                .expect("BUG: Deserializing a notification should succeed");
+        assert!(matches!(rpc, Rpc::Request(Request { id: None, .. })));
+    }
+
     fn test_deserialize_response(serialized_response: &str, expected_rpc: Rpc) {
         let deserialized_response = Rpc::try_from(serialized_response.as_bytes())
             .expect("BUG: Cannot deserialize JSON request");
@@ -458,4 +700,145 @@ mod test {
     fn serialize_err_response() {
         test_serialize_response(build_stratum_err_response(), STRATUM_ERROR_JSON);
     }
+
+    #[test]
+    fn parse_batch_of_single_rpc() {
+        let rpcs = Rpc::parse_batch(
+            MINING_SUBSCRIBE_OK_RESULT_JSON.as_bytes(),
+            ParseMode::Strict,
+        )
+        .expect("BUG: Cannot parse frame");
+        assert_eq!(rpcs.len(), 1);
+        assert_eq!(
+            rpcs.into_iter()
+                .next()
+                .expect("BUG: missing rpc")
+                .expect("BUG: Cannot deserialize rpc"),
+            build_subscribe_ok_response_message()
+        );
+        assert!(Rpc::parse_batch(MINING_BROKEN_REQ_JSON.as_bytes(), ParseMode::Strict).is_err());
+    }
+
+    #[test]
+    fn parse_batch_elements_individually() {
+        let batch = format!(
+            " [{}, {}, {}]",
+            MINING_SUBSCRIBE_OK_RESULT_JSON, MINING_BROKEN_REQ_JSON, STRATUM_ERROR_JSON
+        );
+        let mut rpcs = Rpc::parse_batch(batch.as_bytes(), ParseMode::Strict)
+            .expect("BUG: Cannot parse batch")
+            .into_iter();
+        assert_eq!(
+            rpcs.next()
+                .expect("BUG: missing rpc")
+                .expect("BUG: Cannot deserialize rpc"),
+            build_subscribe_ok_response_message()
+        );
+        assert!(rpcs.next().expect("BUG: missing rpc").is_err());
+        assert_eq!(
+            rpcs.next()
+                .expect("BUG: missing rpc")
+                .expect("BUG: Cannot deserialize rpc"),
+            build_stratum_err_response()
+        );
+        assert!(rpcs.next().is_none());
+
+        // Batch that is not even a valid JSON array fails as a whole
+        assert!(Rpc::parse_batch(b"[{\"id\": 1,", ParseMode::Strict).is_err());
+    }
+
+    #[test]
+    fn serialize_batch() {
+        let batch = RpcBatch::from(vec![
+            build_subscribe_ok_response_message(),
+            build_stratum_err_response(),
+        ]);
+        let batch_frame: framing::Frame = batch.try_into().expect("BUG: Failed to serialize");
+        let mut serialized_batch = BytesMut::new();
+        batch_frame
+            .serialize(&mut serialized_batch)
+            .expect("BUG: Cannot serialize frame");
+
+        let rpcs = Rpc::parse_batch(&serialized_batch[..], ParseMode::Strict)
+            .expect("BUG: Cannot parse batch")
+            .into_iter()
+            .collect::<Result<Vec<_>>>()
+            .expect("BUG: Cannot deserialize serialized batch");
+        assert_eq!(
+            rpcs,
+            vec![
+                build_subscribe_ok_response_message(),
+                build_stratum_err_response()
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_lenient_quirks() {
+        for &json in &[
+            LENIENT_STRING_ERROR_JSON,
+            LENIENT_OBJECT_ERROR_JSON,
+            LENIENT_FALSE_ERROR_JSON,
+        ] {
+            Rpc::parse(json.as_bytes(), ParseMode::Strict)
+                .expect_err("BUG: Strict parsing should have failed");
+        }
+
+        // Params are validated only when converting into a specific message
+        let strict = Rpc::parse(LENIENT_SET_DIFFICULTY_JSON.as_bytes(), ParseMode::Strict)
+            .expect("BUG: Strict parsing failed");
+        SetDifficulty::try_from(strict).expect_err("BUG: String difficulty should be rejected");
+        let lenient = Rpc::parse(LENIENT_SET_DIFFICULTY_JSON.as_bytes(), ParseMode::Lenient)
+            .expect("BUG: Lenient parsing failed");
+        let set_difficulty =
+            SetDifficulty::try_from(lenient).expect("BUG: Cannot build set difficulty");
+        assert_eq!(set_difficulty.value(), 1024.5);
+
+        let expected_errors = [
+            (
+                LENIENT_STRING_ERROR_JSON,
+                3,
+                StratumError(20, "Job not found (=stale)".into(), None),
+            ),
+            (
+                LENIENT_OBJECT_ERROR_JSON,
+                4,
+                StratumError(
+                    21,
+                    "Job not found (=stale)".into(),
+                    Some(r#"{"job":"ahoj"}"#.into()),
+                ),
+            ),
+        ];
+        for (json, id, expected_error) in expected_errors.iter().cloned() {
+            let rpc = Rpc::parse(json.as_bytes(), ParseMode::Lenient)
+                .expect("BUG: Lenient parsing failed");
+            let (msg_id, error) =
+                <(MessageId, StratumError)>::try_from(rpc).expect("BUG: Stratum error expected");
+            assert_eq!(msg_id, Some(id));
+            assert_eq!(error, expected_error);
+        }
+
+        let rpc = Rpc::parse(LENIENT_FALSE_ERROR_JSON.as_bytes(), ParseMode::Lenient)
+            .expect("BUG: Lenient parsing failed");
+        let (_, result) =
+            <(MessageId, StratumResult)>::try_from(rpc).expect("BUG: Stratum result expected");
+        assert_eq!(result, StratumResult(Value::Bool(true)));
+    }
+
+    #[test]
+    fn parse_lenient_batch() {
+        let batch = format!(
+            "[{}, {}]",
+            LENIENT_OBJECT_ERROR_JSON, LENIENT_STRING_ERROR_JSON
+        );
+        assert!(Rpc::parse_batch(batch.as_bytes(), ParseMode::Strict)
+            .expect("BUG: Cannot parse batch")
+            .iter()
+            .all(|rpc| rpc.is_err()));
+        assert!(Rpc::parse_batch(batch.as_bytes(), ParseMode::Lenient)
+            .expect("BUG: Cannot parse batch")
+            .iter()
+            .all(|rpc| rpc.is_ok()));
+    }
 }
@@ -0,0 +1,322 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Server side of the V1 protocol. A pool endpoint or the V1 downstream side of a translator
+//! implements [`V1Server`] and feeds it with RPCs received from the client. Each request is
+//! dispatched to the corresponding `on_*` method and the outcome is turned into a response.
+
+use async_trait::async_trait;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::result::Result as StdResult;
+
+use ii_logging::macros::*;
+
+use super::messages::*;
+use super::rpc::{Method, Request, RequestPayload, Response, ResponsePayload, Rpc, StratumError};
+use super::MessageId;
+use crate::error::Result;
+
+/// Error codes that are commonly used by stratum servers
+pub mod error_codes {
+    pub const OTHER: i32 = 20;
+    pub const JOB_NOT_FOUND: i32 = 21;
+    pub const DUPLICATE_SHARE: i32 = 22;
+    pub const LOW_DIFFICULTY_SHARE: i32 = 23;
+    pub const UNAUTHORIZED_WORKER: i32 = 24;
+    pub const NOT_SUBSCRIBED: i32 = 25;
+}
+
+/// Outcome of a client request, the error is sent to the client as is
+pub type RequestResult<T> = StdResult<T, StratumError>;
+
+impl StratumError {
+    pub fn new<T: Into<String>>(code: i32, msg: T) -> Self {
+        Self(code, msg.into(), None)
+    }
+
+    pub fn other<T: Into<String>>(msg: T) -> Self {
+        Self::new(error_codes::OTHER, msg)
+    }
+
+    pub fn job_not_found() -> Self {
+        Self::new(error_codes::JOB_NOT_FOUND, "Job not found (=stale)")
+    }
+
+    pub fn duplicate_share() -> Self {
+        Self::new(error_codes::DUPLICATE_SHARE, "Duplicate share")
+    }
+
+    pub fn low_difficulty_share() -> Self {
+        Self::new(error_codes::LOW_DIFFICULTY_SHARE, "Low difficulty share")
+    }
+
+    pub fn unauthorized_worker() -> Self {
+        Self::new(error_codes::UNAUTHORIZED_WORKER, "Unauthorized worker")
+    }
+
+    pub fn not_subscribed() -> Self {
+        Self::new(error_codes::NOT_SUBSCRIBED, "Not subscribed")
+    }
+}
+
+/// Builds a response with `result` for request `id`
+pub fn result_response<T>(id: u32, result: T) -> Result<Rpc>
+where
+    T: TryInto<ResponsePayload, Error = crate::error::Error>,
+{
+    Ok(response(id, result.try_into()?))
+}
+
+/// Builds an error response for request `id`
+pub fn error_response(id: u32, error: StratumError) -> Rpc {
+    response(id, Err(error))
+}
+
+/// Builds a response for request `id` from the response `payload`
+pub fn response(id: u32, payload: ResponsePayload) -> Rpc {
+    let (stratum_result, stratum_error) = match payload {
+        Ok(result) => (Some(result), None),
+        Err(error) => (None, Some(error)),
+    };
+    Rpc::Response(Response {
+        id,
+        stratum_result,
+        stratum_error,
+    })
+}
+
+/// Builds a notification (request without ID) that the server pushes to the client, e.g.
+/// `mining.notify` or `mining.set_difficulty`
+pub fn notification<T>(message: T) -> Result<Rpc>
+where
+    T: TryInto<RequestPayload, Error = crate::error::Error>,
+{
+    Ok(Rpc::Request(Request {
+        id: None,
+        payload: message.try_into()?,
+    }))
+}
+
+fn unsupported<T: fmt::Debug>(msg: T) -> StratumError {
+    debug!("V1Server: unsupported request: {:?}", msg);
+    StratumError::other("Unsupported request")
+}
+
+fn into_response_payload<T>(result: RequestResult<T>) -> Result<ResponsePayload>
+where
+    T: TryInto<ResponsePayload, Error = crate::error::Error>,
+{
+    match result {
+        Ok(result) => result.try_into(),
+        Err(error) => Ok(Err(error)),
+    }
+}
+
+/// Handler of requests that a V1 client sends to the server. Subscribe, authorize and submit
+/// have to be implemented, the remaining requests are rejected by default.
+#[async_trait]
+pub trait V1Server: Send {
+    async fn on_subscribe(&mut self, msg: Subscribe) -> RequestResult<SubscribeResult>;
+
+    async fn on_authorize(&mut self, msg: Authorize) -> RequestResult<BooleanResult>;
+
+    async fn on_submit(&mut self, msg: Submit) -> RequestResult<BooleanResult>;
+
+    async fn on_configure(&mut self, msg: Configure) -> RequestResult<ConfigureResult> {
+        Err(unsupported(msg))
+    }
+
+    async fn on_extranonce_subscribe(
+        &mut self,
+        msg: ExtranonceSubscribe,
+    ) -> RequestResult<BooleanResult> {
+        Err(unsupported(msg))
+    }
+
+    /// Difficulty hints are accepted and ignored by default
+    async fn on_suggest_difficulty(
+        &mut self,
+        msg: SuggestDifficulty,
+    ) -> RequestResult<BooleanResult> {
+        debug!("V1Server: ignoring difficulty hint: {:?}", msg);
+        Ok(BooleanResult(true))
+    }
+
+    async fn on_suggest_target(&mut self, msg: SuggestTarget) -> RequestResult<BooleanResult> {
+        debug!("V1Server: ignoring target hint: {:?}", msg);
+        Ok(BooleanResult(true))
+    }
+
+    /// Handles responses to requests that the server has sent to the client (e.g. `mining.ping`)
+    async fn on_response(&mut self, response: Response) -> Result<()> {
+        debug!("V1Server: ignoring response: {:?}", response);
+        Ok(())
+    }
+
+    /// Dispatches `rpc` received from the client to the corresponding handler method. The
+    /// returned response is to be sent back to the client, there is none for notifications
+    /// (requests without ID) and for responses.
+    async fn handle_rpc(&mut self, rpc: Rpc) -> Result<Option<Rpc>> {
+        let request = match rpc {
+            Rpc::Request(request) => request,
+            Rpc::Response(response) => {
+                self.on_response(response).await?;
+                return Ok(None);
+            }
+        };
+        let id: MessageId = request.id;
+
+        // Malformed parameters are reported to the client, they don't terminate the session
+        macro_rules! dispatch {
+            ($method:ident, $msg_type:ty) => {
+                match <$msg_type>::try_from(request) {
+                    Ok(msg) => into_response_payload(self.$method(msg).await)?,
+                    Err(e) => Err(StratumError::other(format!("Invalid params: {}", e))),
+                }
+            };
+        }
+        let payload = match request.payload.method {
+            Method::Subscribe => dispatch!(on_subscribe, Subscribe),
+            Method::Authorize => dispatch!(on_authorize, Authorize),
+            Method::Submit => dispatch!(on_submit, Submit),
+            Method::Configure => dispatch!(on_configure, Configure),
+            Method::ExtranonceSubscribe => {
+                dispatch!(on_extranonce_subscribe, ExtranonceSubscribe)
+            }
+            Method::SuggestDifficulty => dispatch!(on_suggest_difficulty, SuggestDifficulty),
+            Method::SuggestTarget => dispatch!(on_suggest_target, SuggestTarget),
+            _ => Err(unsupported(request)),
+        };
+
+        Ok(id.map(|id| response(id, payload)))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::v1::*;
+
+    /// Server that accepts everything and counts submitted shares
+    #[derive(Default)]
+    struct TestServer {
+        submits: usize,
+    }
+
+    #[async_trait]
+    impl V1Server for TestServer {
+        async fn on_subscribe(&mut self, _msg: Subscribe) -> RequestResult<SubscribeResult> {
+            Ok(build_subscribe_ok_result())
+        }
+
+        async fn on_authorize(&mut self, _msg: Authorize) -> RequestResult<BooleanResult> {
+            Ok(BooleanResult(true))
+        }
+
+        async fn on_submit(&mut self, _msg: Submit) -> RequestResult<BooleanResult> {
+            self.submits += 1;
+            Err(StratumError::job_not_found())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_requests() {
+        let mut server = TestServer::default();
+
+        let response = server
+            .handle_rpc(build_subscribe_request_frame())
+            .await
+            .expect("BUG: Handling subscribe failed");
+        assert_eq!(response, Some(build_subscribe_ok_response_message()));
+
+        let response = server
+            .handle_rpc(build_request_message(Some(3), build_mining_submit()))
+            .await
+            .expect("BUG: Handling submit failed");
+        assert_eq!(
+            response,
+            Some(build_err_response_message(
+                3,
+                error_codes::JOB_NOT_FOUND,
+                "Job not found (=stale)"
+            ))
+        );
+        assert_eq!(server.submits, 1);
+    }
+
+    #[tokio::test]
+    async fn test_default_handlers() {
+        let mut server = TestServer::default();
+
+        // Unimplemented request is rejected
+        let response = server
+            .handle_rpc(build_configure_request())
+            .await
+            .expect("BUG: Handling configure failed")
+            .expect("BUG: Missing response");
+        assert!(matches!(
+            response,
+            Rpc::Response(Response {
+                stratum_error: Some(StratumError(error_codes::OTHER, _, _)),
+                ..
+            })
+        ));
+
+        // Notifications and responses are never answered
+        let response = server
+            .handle_rpc(build_request_message(None, build_suggest_difficulty()))
+            .await
+            .expect("BUG: Handling suggest difficulty failed");
+        assert_eq!(response, None);
+        let response = server
+            .handle_rpc(build_ok_response_message(7))
+            .await
+            .expect("BUG: Handling response failed");
+        assert_eq!(response, None);
+    }
+
+    #[tokio::test]
+    async fn test_invalid_params() {
+        let mut server = TestServer::default();
+        let request = Rpc::Request(Request {
+            id: Some(5),
+            payload: RequestPayload {
+                method: Method::Authorize,
+                params: serde_json::json!([1, 2, 3]),
+            },
+        });
+        let response = server
+            .handle_rpc(request)
+            .await
+            .expect("BUG: Invalid params should be reported to the client")
+            .expect("BUG: Missing response");
+        assert!(matches!(
+            response,
+            Rpc::Response(Response {
+                id: 5,
+                stratum_error: Some(_),
+                ..
+            })
+        ));
+    }
+}
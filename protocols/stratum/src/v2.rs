@@ -21,14 +21,22 @@
 // contact us at opensource@braiins.com.
 
 //! Stratum version 2 top level module
+pub mod conformance;
 pub mod error;
+pub mod error_codes;
 pub mod framing;
+pub mod handler;
+pub mod id_allocator;
+pub mod job;
 #[macro_use]
 pub mod macros;
 pub mod extensions;
+pub mod extranonce;
 pub mod messages;
 pub mod noise;
 pub mod serialization;
+#[cfg(test)]
+pub(crate) mod strategies;
 pub mod telemetry;
 pub mod types;
 
@@ -38,9 +46,11 @@ use futures::prelude::*;
 
 pub use self::framing::codec::Codec;
 pub use self::framing::{Frame, Framing};
+pub use self::handler::V2Handler;
+pub use self::serialization::SerializationMode;
 
-/// Tcp stream that produces/consumes V2 frames
-pub type Framed = tokio_util::codec::Framed<TcpStream, self::noise::CompoundCodec<Codec>>;
+/// Stream (TCP by default) that produces/consumes V2 frames
+pub type Framed<T = TcpStream> = tokio_util::codec::Framed<T, self::noise::CompoundCodec<Codec>>;
 
 pub trait FramedSink:
     Sink<<Framing as ii_wire::Framing>::Tx, Error = <Framing as ii_wire::Framing>::Error>
@@ -0,0 +1,610 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Conformance checks of V2 endpoints. Each check runs on a fresh connection to the endpoint,
+//! sends a (possibly malformed or out of order) sequence of messages and verifies the reaction of
+//! the endpoint. The results are collected into a [`Report`].
+
+use async_trait::async_trait;
+use bytes::BytesMut;
+use futures::prelude::*;
+use std::convert::{TryFrom, TryInto};
+use std::fmt;
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+
+use ii_unvariant::Id;
+
+use super::error_codes::SetupConnectionErrorCode;
+use super::framing::{ExtType, Frame, MsgType};
+use super::messages::{
+    OpenStandardMiningChannel, OpenStandardMiningChannelSuccess, SetupConnection,
+    SetupConnectionError, SetupConnectionSuccess, SubmitSharesError, SubmitSharesStandard,
+};
+use super::noise::{negotiation::EncryptionAlgorithm, AuthorityPublicKey, Initiator};
+use super::types::{DeviceInfo, Str0_255, SubProtocol, Uint256Bytes};
+use super::{serialization, Framed};
+use crate::error::Result;
+
+/// Endpoint under test that provides a new connection for each check
+#[async_trait]
+pub trait Endpoint: Send + Sync {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send;
+
+    async fn connect(&self) -> Result<Framed<Self::Stream>>;
+}
+
+/// Endpoint reachable via TCP, optionally secured by the noise protocol
+#[derive(Clone, Debug)]
+pub struct TcpEndpoint {
+    address: String,
+    authority_public_key: Option<AuthorityPublicKey>,
+}
+
+impl TcpEndpoint {
+    pub fn new(address: String) -> Self {
+        Self {
+            address,
+            authority_public_key: None,
+        }
+    }
+
+    /// Run the noise handshake with the endpoint authenticated by `authority_public_key`
+    pub fn with_authority_public_key(mut self, authority_public_key: AuthorityPublicKey) -> Self {
+        self.authority_public_key = Some(authority_public_key);
+        self
+    }
+}
+
+#[async_trait]
+impl Endpoint for TcpEndpoint {
+    type Stream = TcpStream;
+
+    async fn connect(&self) -> Result<Framed<TcpStream>> {
+        let stream = TcpStream::connect(self.address.as_str()).await?;
+        match self.authority_public_key {
+            Some(key) => {
+                Initiator::new(key, vec![EncryptionAlgorithm::AESGCM])
+                    .connect(stream)
+                    .await
+            }
+            None => Ok(Framed::new(stream, Default::default())),
+        }
+    }
+}
+
+/// Individual checks of the suite
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Check {
+    SetupConnection,
+    UnknownSetupConnectionFlags,
+    UnsupportedProtocolVersion,
+    OversizedString,
+    OpenChannelBeforeSetup,
+    SubmitOnUnknownChannel,
+    UnknownExtension,
+}
+
+impl Check {
+    pub const ALL: [Check; 7] = [
+        Check::SetupConnection,
+        Check::UnknownSetupConnectionFlags,
+        Check::UnsupportedProtocolVersion,
+        Check::OversizedString,
+        Check::OpenChannelBeforeSetup,
+        Check::SubmitOnUnknownChannel,
+        Check::UnknownExtension,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::SetupConnection => "setup-connection",
+            Self::UnknownSetupConnectionFlags => "unknown-setup-connection-flags",
+            Self::UnsupportedProtocolVersion => "unsupported-protocol-version",
+            Self::OversizedString => "oversized-string",
+            Self::OpenChannelBeforeSetup => "open-channel-before-setup",
+            Self::SubmitOnUnknownChannel => "submit-on-unknown-channel",
+            Self::UnknownExtension => "unknown-extension",
+        }
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Self::SetupConnection => "valid SetupConnection is accepted",
+            Self::UnknownSetupConnectionFlags => {
+                "SetupConnection with unknown feature flags is refused"
+            }
+            Self::UnsupportedProtocolVersion => {
+                "SetupConnection with unsupported protocol version is refused"
+            }
+            Self::OversizedString => {
+                "string with length exceeding the message payload terminates the connection"
+            }
+            Self::OpenChannelBeforeSetup => "channel cannot be opened before SetupConnection",
+            Self::SubmitOnUnknownChannel => "shares submitted to an unknown channel are rejected",
+            Self::UnknownExtension => "messages of unknown extensions are ignored",
+        }
+    }
+}
+
+/// Result of a single check
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    /// The endpoint reacted in a way that violates the protocol
+    Failed(String),
+    /// The check couldn't be completed, e.g. the endpoint is not reachable
+    Error(String),
+}
+
+#[derive(Clone, Debug)]
+pub struct CheckResult {
+    pub check: Check,
+    pub outcome: Outcome,
+    pub duration: Duration,
+}
+
+/// Results of all checks of a suite run
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub results: Vec<CheckResult>,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.count(|outcome| *outcome == Outcome::Passed)
+    }
+
+    pub fn failed(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Failed(_)))
+    }
+
+    pub fn errors(&self) -> usize {
+        self.count(|outcome| matches!(outcome, Outcome::Error(_)))
+    }
+
+    /// All checks have passed
+    pub fn is_success(&self) -> bool {
+        self.passed() == self.results.len()
+    }
+
+    pub fn outcome(&self, check: Check) -> Option<&Outcome> {
+        self.results
+            .iter()
+            .find(|result| result.check == check)
+            .map(|result| &result.outcome)
+    }
+
+    fn count(&self, predicate: impl Fn(&Outcome) -> bool) -> usize {
+        self.results
+            .iter()
+            .filter(|result| predicate(&result.outcome))
+            .count()
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for result in self.results.iter() {
+            let (status, detail) = match &result.outcome {
+                Outcome::Passed => ("PASS", None),
+                Outcome::Failed(reason) => ("FAIL", Some(reason)),
+                Outcome::Error(reason) => ("ERROR", Some(reason)),
+            };
+            write!(
+                f,
+                "{:<5} {} ({:.1} ms): {}",
+                status,
+                result.check.name(),
+                result.duration.as_secs_f64() * 1000.0,
+                result.check.description()
+            )?;
+            if let Some(detail) = detail {
+                write!(f, " - {}", detail)?;
+            }
+            writeln!(f)?;
+        }
+        write!(
+            f,
+            "{} passed, {} failed, {} errors",
+            self.passed(),
+            self.failed(),
+            self.errors()
+        )
+    }
+}
+
+/// Reaction of the endpoint to the last message
+#[derive(Debug)]
+enum Reaction {
+    Frame(Frame),
+    /// Connection has been closed or the endpoint sent data that cannot be decoded
+    Closed,
+    /// Nothing has arrived within the response timeout
+    Silence,
+}
+
+impl Reaction {
+    fn is<M: Id<MsgType>>(&self) -> bool {
+        match self {
+            Self::Frame(frame) => is_message::<M>(frame),
+            _ => false,
+        }
+    }
+}
+
+/// `frame` carries message `M` of the base protocol
+fn is_message<M: Id<MsgType>>(frame: &Frame) -> bool {
+    frame.header.extension_type == 0 && frame.header.msg_type == M::ID
+}
+
+/// Runs the checks against an endpoint
+pub struct ConformanceSuite<E> {
+    endpoint: E,
+    response_timeout: Duration,
+}
+
+impl<E: Endpoint> ConformanceSuite<E> {
+    /// Extension type that is not expected to be implemented by any endpoint
+    pub const UNKNOWN_EXTENSION_TYPE: ExtType = 0x7ff0;
+
+    pub fn new(endpoint: E) -> Self {
+        Self {
+            endpoint,
+            response_timeout: Duration::from_secs(5),
+        }
+    }
+
+    /// Time to wait for a reaction of the endpoint. Checks that expect the endpoint to ignore a
+    /// message wait the whole timeout.
+    pub fn with_response_timeout(mut self, response_timeout: Duration) -> Self {
+        self.response_timeout = response_timeout;
+        self
+    }
+
+    pub async fn run(&self) -> Report {
+        let mut report = Report::default();
+        for check in Check::ALL.iter() {
+            report.results.push(self.run_check(*check).await);
+        }
+        report
+    }
+
+    pub async fn run_check(&self, check: Check) -> CheckResult {
+        let started = Instant::now();
+        let outcome = match self.endpoint.connect().await {
+            Ok(mut conn) => match self.execute(check, &mut conn).await {
+                Ok(outcome) => outcome,
+                Err(e) => Outcome::Error(e.to_string()),
+            },
+            Err(e) => Outcome::Error(format!("Cannot connect: {}", e)),
+        };
+        CheckResult {
+            check,
+            outcome,
+            duration: started.elapsed(),
+        }
+    }
+
+    async fn execute(&self, check: Check, conn: &mut Framed<E::Stream>) -> Result<Outcome> {
+        let outcome = match check {
+            Check::SetupConnection => {
+                let reaction = self.request(conn, setup_connection().try_into()?).await?;
+                expect(
+                    reaction.is::<SetupConnectionSuccess>(),
+                    "SetupConnectionSuccess",
+                    &reaction,
+                )
+            }
+            Check::UnknownSetupConnectionFlags => {
+                let message = SetupConnection {
+                    flags: u32::MAX,
+                    ..setup_connection()
+                };
+                let reaction = self.request(conn, message.try_into()?).await?;
+                refused(reaction, SetupConnectionErrorCode::UnsupportedFeatureFlags)?
+            }
+            Check::UnsupportedProtocolVersion => {
+                let message = SetupConnection {
+                    min_version: u16::MAX,
+                    max_version: u16::MAX,
+                    ..setup_connection()
+                };
+                let reaction = self.request(conn, message.try_into()?).await?;
+                refused(reaction, SetupConnectionErrorCode::ProtocolVersionMismatch)?
+            }
+            Check::OversizedString => {
+                let mut payload = serialization::to_vec(&setup_connection())?;
+                // Length of `endpoint_host` that follows protocol, versions and flags
+                payload[9] = u8::MAX;
+                let frame = Frame::from_serialized_payload(
+                    false,
+                    0,
+                    <SetupConnection as Id<MsgType>>::ID,
+                    BytesMut::from(&payload[..]),
+                );
+                let reaction = self.request(conn, frame).await?;
+                expect(
+                    matches!(reaction, Reaction::Closed) || reaction.is::<SetupConnectionError>(),
+                    "connection closed or SetupConnectionError",
+                    &reaction,
+                )
+            }
+            Check::OpenChannelBeforeSetup => {
+                let reaction = self.request(conn, open_channel().try_into()?).await?;
+                expect(
+                    !matches!(reaction, Reaction::Silence)
+                        && !reaction.is::<OpenStandardMiningChannelSuccess>(),
+                    "connection closed or an error",
+                    &reaction,
+                )
+            }
+            Check::SubmitOnUnknownChannel => match self.setup(conn).await? {
+                Some(outcome) => outcome,
+                None => {
+                    let share = SubmitSharesStandard {
+                        channel_id: 0x7fff_ffff,
+                        seq_num: 0,
+                        job_id: 0,
+                        nonce: 0,
+                        ntime: 0,
+                        version: 0,
+                    };
+                    let reaction = self.request(conn, share.try_into()?).await?;
+                    expect(
+                        matches!(reaction, Reaction::Closed) || reaction.is::<SubmitSharesError>(),
+                        "connection closed or SubmitSharesError",
+                        &reaction,
+                    )
+                }
+            },
+            Check::UnknownExtension => match self.setup(conn).await? {
+                Some(outcome) => outcome,
+                None => {
+                    let frame = Frame::from_serialized_payload(
+                        false,
+                        Self::UNKNOWN_EXTENSION_TYPE,
+                        0x00,
+                        BytesMut::from(&b"conformance"[..]),
+                    );
+                    let reaction = self.request(conn, frame).await?;
+                    expect(
+                        !matches!(reaction, Reaction::Closed),
+                        "the connection to stay open",
+                        &reaction,
+                    )
+                }
+            },
+        };
+        Ok(outcome)
+    }
+
+    /// Completes the setup of the connection, returns the outcome of the check if it failed
+    async fn setup(&self, conn: &mut Framed<E::Stream>) -> Result<Option<Outcome>> {
+        let reaction = self.request(conn, setup_connection().try_into()?).await?;
+        if reaction.is::<SetupConnectionSuccess>() {
+            Ok(None)
+        } else {
+            Ok(Some(Outcome::Error(format!(
+                "SetupConnection not accepted: {:?}",
+                reaction
+            ))))
+        }
+    }
+
+    /// Sends `frame` and waits for the reaction of the endpoint
+    async fn request(&self, conn: &mut Framed<E::Stream>, frame: Frame) -> Result<Reaction> {
+        if conn.send(frame).await.is_err() {
+            return Ok(Reaction::Closed);
+        }
+        Ok(
+            match tokio::time::timeout(self.response_timeout, conn.next()).await {
+                Ok(Some(Ok(frame))) => Reaction::Frame(frame),
+                Ok(Some(Err(_))) | Ok(None) => Reaction::Closed,
+                Err(_) => Reaction::Silence,
+            },
+        )
+    }
+}
+
+fn setup_connection() -> SetupConnection {
+    SetupConnection {
+        protocol: SubProtocol::Mining,
+        min_version: 2,
+        max_version: 2,
+        flags: 0,
+        endpoint_host: Str0_255::try_from("conformance.test").expect("BUG: invalid string"),
+        endpoint_port: 3336,
+        device: DeviceInfo {
+            vendor: Str0_255::try_from("ii-stratum").expect("BUG: invalid string"),
+            hw_rev: Str0_255::try_from("1").expect("BUG: invalid string"),
+            fw_ver: Str0_255::try_from("conformance").expect("BUG: invalid string"),
+            dev_id: Str0_255::try_from("0").expect("BUG: invalid string"),
+        },
+    }
+}
+
+fn open_channel() -> OpenStandardMiningChannel {
+    OpenStandardMiningChannel {
+        req_id: 0,
+        user: Str0_255::try_from("conformance").expect("BUG: invalid string"),
+        nominal_hashrate: 1e12,
+        max_target: Uint256Bytes([0xff; 32]),
+    }
+}
+
+fn expect(condition: bool, expected: &str, reaction: &Reaction) -> Outcome {
+    if condition {
+        Outcome::Passed
+    } else {
+        Outcome::Failed(format!("Expected {}, got {:?}", expected, reaction))
+    }
+}
+
+/// SetupConnection has to be refused by closing the connection or by `SetupConnectionError`
+/// with the `expected_code`
+fn refused(reaction: Reaction, expected_code: SetupConnectionErrorCode) -> Result<Outcome> {
+    Ok(match reaction {
+        Reaction::Closed => Outcome::Passed,
+        Reaction::Frame(frame) if is_message::<SetupConnectionError>(&frame) => {
+            let error = SetupConnectionError::try_from(frame)?;
+            match SetupConnectionErrorCode::try_from(&error.code) {
+                Ok(code) if code == expected_code => Outcome::Passed,
+                _ => Outcome::Failed(format!(
+                    "Expected error code {}, got {}",
+                    expected_code,
+                    error.code.as_str()
+                )),
+            }
+        }
+        reaction => expect(false, "SetupConnectionError", &reaction),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::v2::error_codes::SubmitSharesErrorCode;
+    use tokio::io::DuplexStream;
+
+    /// In-memory endpoint, each connection is served by a toy server
+    struct DuplexEndpoint {
+        conforming: bool,
+    }
+
+    #[async_trait]
+    impl Endpoint for DuplexEndpoint {
+        type Stream = DuplexStream;
+
+        async fn connect(&self) -> Result<Framed<DuplexStream>> {
+            let (client, server) = tokio::io::duplex(1024);
+            let server = Framed::new(server, Default::default());
+            if self.conforming {
+                tokio::spawn(serve_conforming(server));
+            } else {
+                tokio::spawn(serve_accept_all(server));
+            }
+            Ok(Framed::new(client, Default::default()))
+        }
+    }
+
+    async fn reply<M: TryInto<Frame, Error = crate::error::Error>>(
+        conn: &mut Framed<DuplexStream>,
+        message: M,
+    ) -> Result<()> {
+        conn.send(message.try_into()?).await
+    }
+
+    /// Minimal server that follows the specification in all aspects covered by the suite
+    async fn serve_conforming(mut conn: Framed<DuplexStream>) -> Result<()> {
+        let mut is_setup = false;
+        while let Some(frame) = conn.next().await {
+            let frame = frame?;
+            if frame.header.extension_type != 0 {
+                continue;
+            }
+            if is_message::<SetupConnection>(&frame) {
+                let setup = SetupConnection::try_from(frame)?;
+                let code = if setup.flags != 0 {
+                    SetupConnectionErrorCode::UnsupportedFeatureFlags
+                } else if setup.min_version > 2 || setup.max_version < 2 {
+                    SetupConnectionErrorCode::ProtocolVersionMismatch
+                } else {
+                    is_setup = true;
+                    let success = SetupConnectionSuccess {
+                        used_version: 2,
+                        flags: 0,
+                    };
+                    reply(&mut conn, success).await?;
+                    continue;
+                };
+                let error = SetupConnectionError {
+                    flags: 0,
+                    code: code.into(),
+                };
+                reply(&mut conn, error).await?;
+            } else if is_setup && is_message::<SubmitSharesStandard>(&frame) {
+                let share = SubmitSharesStandard::try_from(frame)?;
+                let error = SubmitSharesError {
+                    channel_id: share.channel_id,
+                    seq_num: share.seq_num,
+                    code: SubmitSharesErrorCode::InvalidChannelId.into(),
+                };
+                reply(&mut conn, error).await?;
+            } else {
+                // Anything else before the setup is a protocol violation
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Broken server that confirms the setup of the connection whatever it receives
+    async fn serve_accept_all(mut conn: Framed<DuplexStream>) -> Result<()> {
+        while conn.next().await.is_some() {
+            let success = SetupConnectionSuccess {
+                used_version: 2,
+                flags: 0,
+            };
+            reply(&mut conn, success).await?;
+        }
+        Ok(())
+    }
+
+    fn suite(conforming: bool) -> ConformanceSuite<DuplexEndpoint> {
+        ConformanceSuite::new(DuplexEndpoint { conforming })
+            .with_response_timeout(Duration::from_millis(100))
+    }
+
+    #[tokio::test]
+    async fn test_conforming_endpoint() {
+        let report = suite(true).run().await;
+        assert!(report.is_success(), "Unexpected failures:\n{}", report);
+        assert_eq!(report.passed(), Check::ALL.len());
+    }
+
+    #[tokio::test]
+    async fn test_accept_all_endpoint() {
+        let report = suite(false).run().await;
+        assert!(!report.is_success());
+        for check in [
+            Check::UnknownSetupConnectionFlags,
+            Check::UnsupportedProtocolVersion,
+            Check::OversizedString,
+            Check::SubmitOnUnknownChannel,
+        ]
+        .iter()
+        {
+            assert!(
+                matches!(report.outcome(*check), Some(Outcome::Failed(_))),
+                "Check {} should fail:\n{}",
+                check.name(),
+                report
+            );
+        }
+        assert_eq!(
+            report.outcome(Check::SetupConnection),
+            Some(&Outcome::Passed)
+        );
+    }
+}
@@ -24,7 +24,10 @@
 
 use thiserror::Error;
 
+use crate::error::ErrorKind;
+
 #[derive(Error, Clone, Eq, PartialEq, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Unknown message error: {0}")]
     UnknownMessage(String),
@@ -34,4 +37,71 @@ pub enum Error {
 
     #[error("Type length is out of the permitted range: {0}, max: {1}")]
     DataTypeOverflow(usize, usize),
+
+    #[error("Unknown protocol in connection setup: {0}")]
+    UnknownProtocol(u8),
+
+    #[error("Unknown error code: {0}")]
+    UnknownErrorCode(String),
+
+    #[error("Invalid target: {0}")]
+    InvalidTarget(String),
+
+    #[error("No handler for extension: {0:#06x}")]
+    UnknownExtension(u16),
+
+    #[error("Message field `{0}` has not been set")]
+    MissingField(String),
+
+    #[error("Invalid value of message field `{0}`: {1}")]
+    InvalidFieldValue(String, String),
+
+    #[error("Invalid extranonce configuration: {0}")]
+    InvalidExtranonceConfig(String),
+
+    #[error("Extranonce space exhausted, all {0} channel prefixes are allocated")]
+    ExtranonceExhausted(u64),
+
+    #[error("Extranonce prefix {0:x?} is not allocated")]
+    ExtranonceNotAllocated(Vec<u8>),
+
+    #[error("All ids in range {0}..={1} are allocated")]
+    IdsExhausted(u32, u32),
+
+    #[error("Id {0} is not allocated")]
+    IdNotAllocated(u32),
+
+    #[error("Frame length {0} exceeds the limit of {1} bytes")]
+    FrameTooLarge(usize, usize),
+
+    #[error("Message size {0} exceeds the limit of {1} bytes")]
+    MessageTooLarge(usize, usize),
+
+    #[error("Chunk of a multi-frame message doesn't match its first frame: {0}")]
+    ChunkMismatch(String),
+
+    #[error("Certificate is not signed by any of the {0} trusted authorities")]
+    UntrustedCertificate(usize),
+
+    #[error("Certificate is not valid before {0} (unix time), now: {1}")]
+    CertificateNotYetValid(u32, u32),
+
+    #[error("Certificate expired at {0} (unix time), now: {1}")]
+    CertificateExpired(u32, u32),
+}
+
+impl Error {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::UntrustedCertificate(_)
+            | Self::CertificateNotYetValid(..)
+            | Self::CertificateExpired(..) => ErrorKind::Auth,
+            Self::InvalidExtranonceConfig(_) => ErrorKind::Config,
+            Self::ExtranonceExhausted(_)
+            | Self::ExtranonceNotAllocated(_)
+            | Self::IdsExhausted(..)
+            | Self::IdNotAllocated(_) => ErrorKind::Internal,
+            _ => ErrorKind::Protocol,
+        }
+    }
 }
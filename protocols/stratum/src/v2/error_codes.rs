@@ -0,0 +1,181 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Error codes defined by the specification for the `*Error` messages of the mining protocol.
+//! Each message has its own set of codes represented by an enum that converts from/to the string
+//! field of the message. Codes not defined by the specification fail to convert with
+//! [`Error::UnknownErrorCode`].
+
+use std::convert::TryFrom;
+use std::fmt;
+use std::str::FromStr;
+
+use super::error::Error;
+use super::types::{Str0_255, Str0_32};
+
+/// Generates an error code enum with conversions from/to its string representation
+macro_rules! error_code_enum {
+    (
+        $(#[$meta:meta])*
+        $name:ident {
+            $($(#[$variant_meta:meta])* $variant:ident => $code:expr,)+
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+        pub enum $name {
+            $($(#[$variant_meta])* $variant,)+
+        }
+
+        impl $name {
+            /// String representation of the code as it is sent on the wire
+            pub fn as_str(&self) -> &'static str {
+                match self {
+                    $(Self::$variant => $code,)+
+                }
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+                f.write_str(self.as_str())
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                match s {
+                    $($code => Ok(Self::$variant),)+
+                    _ => Err(Error::UnknownErrorCode(s.to_string())),
+                }
+            }
+        }
+
+        impl From<$name> for Str0_32 {
+            fn from(code: $name) -> Self {
+                Str0_32::try_from(code.as_str()).expect("BUG: error code too long")
+            }
+        }
+
+        impl From<$name> for Str0_255 {
+            fn from(code: $name) -> Self {
+                Str0_255::try_from(code.as_str()).expect("BUG: error code too long")
+            }
+        }
+
+        impl TryFrom<&Str0_32> for $name {
+            type Error = Error;
+
+            fn try_from(code: &Str0_32) -> Result<Self, Self::Error> {
+                code.parse()
+            }
+        }
+
+        impl TryFrom<&Str0_255> for $name {
+            type Error = Error;
+
+            fn try_from(code: &Str0_255) -> Result<Self, Self::Error> {
+                code.parse()
+            }
+        }
+    };
+}
+
+error_code_enum! {
+    /// Codes of `SetupConnectionError`
+    SetupConnectionErrorCode {
+        UnsupportedFeatureFlags => "unsupported-feature-flags",
+        UnsupportedProtocol => "unsupported-protocol",
+        ProtocolVersionMismatch => "protocol-version-mismatch",
+    }
+}
+
+error_code_enum! {
+    /// Codes of `OpenMiningChannelError`
+    OpenMiningChannelErrorCode {
+        UnknownUser => "unknown-user",
+        MaxTargetOutOfRange => "max-target-out-of-range",
+    }
+}
+
+error_code_enum! {
+    /// Codes of `UpdateChannelError`
+    UpdateChannelErrorCode {
+        MaxTargetOutOfRange => "max-target-out-of-range",
+        InvalidChannelId => "invalid-channel-id",
+    }
+}
+
+error_code_enum! {
+    /// Codes of `SubmitSharesError`
+    SubmitSharesErrorCode {
+        InvalidChannelId => "invalid-channel-id",
+        StaleShare => "stale-share",
+        DifficultyTooLow => "difficulty-too-low",
+        InvalidJobId => "invalid-job-id",
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_error_code_round_trip() {
+        for code in [
+            SubmitSharesErrorCode::InvalidChannelId,
+            SubmitSharesErrorCode::StaleShare,
+            SubmitSharesErrorCode::DifficultyTooLow,
+            SubmitSharesErrorCode::InvalidJobId,
+        ]
+        .iter()
+        {
+            let wire_code = Str0_32::from(*code);
+            assert_eq!(
+                SubmitSharesErrorCode::try_from(&wire_code).expect("BUG: cannot parse code"),
+                *code
+            );
+        }
+
+        let wire_code = Str0_255::from(SetupConnectionErrorCode::UnsupportedProtocol);
+        assert_eq!(wire_code.as_str(), "unsupported-protocol");
+        assert_eq!(
+            SetupConnectionErrorCode::try_from(&wire_code).expect("BUG: cannot parse code"),
+            SetupConnectionErrorCode::UnsupportedProtocol
+        );
+    }
+
+    #[test]
+    fn test_unknown_error_code() {
+        let wire_code = Str0_32::try_from("ShareRjct").expect("BUG: cannot build code");
+        assert_eq!(
+            SubmitSharesErrorCode::try_from(&wire_code),
+            Err(Error::UnknownErrorCode("ShareRjct".to_string()))
+        );
+        assert_eq!(
+            "invalid-channel-id".parse::<OpenMiningChannelErrorCode>(),
+            Err(Error::UnknownErrorCode("invalid-channel-id".to_string()))
+        );
+    }
+}
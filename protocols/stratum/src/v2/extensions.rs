@@ -20,9 +20,153 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-//! This module lists all official extensions
+//! This module lists all official extensions and provides a registry for dispatching frames
+//! of extensions to their handlers
+
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use super::{error, framing};
+use crate::error::Result;
 
 /// Base protocol covered in the main specification
 pub const BASE: u16 = 0x0000;
 /// Telemetry extension
 pub const TELEMETRY: u16 = 0x0001;
+
+/// Handler of frames that belong to a particular extension. Handlers are shared by all clones of
+/// [`ExtensionRegistry`], any per-connection state has to be kept behind interior mutability.
+#[async_trait]
+pub trait ExtensionHandler: Send + Sync {
+    async fn handle_extension_frame(&self, frame: framing::Frame) -> Result<()>;
+}
+
+/// Dispatches frames to handlers registered for their `extension_type`. Frames of extensions
+/// without a handler go to the fallback handler (if any).
+#[derive(Clone, Default)]
+pub struct ExtensionRegistry {
+    handlers: HashMap<framing::ExtType, Arc<dyn ExtensionHandler>>,
+    fallback: Option<Arc<dyn ExtensionHandler>>,
+}
+
+impl ExtensionRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `handler` for `extension_type`, a previously registered handler is returned
+    pub fn register(
+        &mut self,
+        extension_type: framing::ExtType,
+        handler: Arc<dyn ExtensionHandler>,
+    ) -> Option<Arc<dyn ExtensionHandler>> {
+        self.handlers.insert(extension_type, handler)
+    }
+
+    /// Removes handler of `extension_type`
+    pub fn unregister(
+        &mut self,
+        extension_type: framing::ExtType,
+    ) -> Option<Arc<dyn ExtensionHandler>> {
+        self.handlers.remove(&extension_type)
+    }
+
+    /// Sets handler for all extensions that have no handler registered
+    pub fn set_fallback(&mut self, handler: Arc<dyn ExtensionHandler>) {
+        self.fallback = Some(handler);
+    }
+
+    /// Returns true when a frame of `extension_type` would be dispatched to some handler
+    pub fn handles(&self, extension_type: framing::ExtType) -> bool {
+        self.fallback.is_some() || self.handlers.contains_key(&extension_type)
+    }
+
+    /// Dispatches `frame` to the handler of its extension, fails with
+    /// [`error::Error::UnknownExtension`] when there is no suitable handler
+    pub async fn handle_frame(&self, frame: framing::Frame) -> Result<()> {
+        let extension_type = frame.header.extension_type;
+        match self
+            .handlers
+            .get(&extension_type)
+            .or(self.fallback.as_ref())
+        {
+            Some(handler) => handler.handle_extension_frame(frame).await,
+            None => Err(error::Error::UnknownExtension(extension_type).into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::v2::build_setup_connection;
+    use crate::v2::telemetry::messages::OpenTelemetryChannel;
+    use crate::v2::types::Str0_255;
+    use std::convert::{TryFrom, TryInto};
+    use std::sync::Mutex;
+
+    /// Collects extension types of all frames it receives
+    #[derive(Default)]
+    struct CollectingHandler {
+        received: Mutex<Vec<framing::ExtType>>,
+    }
+
+    #[async_trait]
+    impl ExtensionHandler for CollectingHandler {
+        async fn handle_extension_frame(&self, frame: framing::Frame) -> Result<()> {
+            self.received
+                .lock()
+                .expect("BUG: poisoned lock")
+                .push(frame.header.extension_type);
+            Ok(())
+        }
+    }
+
+    fn build_telemetry_frame() -> framing::Frame {
+        OpenTelemetryChannel {
+            req_id: 1,
+            dev_id: Str0_255::try_from("device").expect("BUG: cannot build string"),
+        }
+        .try_into()
+        .expect("BUG: Cannot create test frame")
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_registered_extension() {
+        let telemetry = Arc::new(CollectingHandler::default());
+        let mut registry = ExtensionRegistry::new();
+        registry.register(TELEMETRY, telemetry.clone());
+
+        assert!(registry.handles(TELEMETRY));
+        assert!(!registry.handles(BASE));
+        registry
+            .handle_frame(build_telemetry_frame())
+            .await
+            .expect("BUG: Frame dispatch failed");
+        assert_eq!(*telemetry.received.lock().unwrap(), vec![TELEMETRY]);
+
+        let frame: framing::Frame = build_setup_connection()
+            .try_into()
+            .expect("BUG: Cannot create test frame");
+        match registry.handle_frame(frame).await {
+            Err(crate::error::Error::V2(error::Error::UnknownExtension(BASE))) => (),
+            result => panic!("BUG: Unexpected dispatch result: {:?}", result),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_dispatch_fallback() {
+        let fallback = Arc::new(CollectingHandler::default());
+        let mut registry = ExtensionRegistry::new();
+        registry.set_fallback(fallback.clone());
+
+        assert!(registry.handles(TELEMETRY));
+        registry
+            .clone()
+            .handle_frame(build_telemetry_frame())
+            .await
+            .expect("BUG: Frame dispatch failed");
+        assert_eq!(*fallback.received.lock().unwrap(), vec![TELEMETRY]);
+    }
+}
@@ -0,0 +1,251 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Allocation of extranonce prefixes to channels
+//!
+//! The extranonce space provided by upstream (its prefix and the number of bytes that follow it)
+//! is partitioned into per-channel prefixes. Each channel prefix consists of the upstream prefix
+//! followed by a channel specific part of configurable size; the remaining bytes are left for the
+//! channel to roll.
+
+use std::collections::HashSet;
+
+use super::error;
+use super::types::Bytes0_32;
+use crate::error::Result;
+
+/// Maximum total size of extranonce as permitted by the protocol (see `Bytes0_32`)
+pub const MAX_EXTRANONCE_SIZE: usize = 32;
+
+/// Maximum size of the channel specific part of the prefix (it is backed by a `u64` counter)
+pub const MAX_CHANNEL_PREFIX_SIZE: usize = 8;
+
+/// Extranonce prefix assigned to a single channel. It has to be returned to the allocator via
+/// [`ExtranonceAllocator::release()`] when the channel is closed.
+#[derive(Debug, PartialEq, Eq)]
+pub struct ExtranonceAllocation {
+    index: u64,
+    prefix: Vec<u8>,
+    extranonce_size: usize,
+}
+
+impl ExtranonceAllocation {
+    /// Full extranonce prefix of the channel (including the upstream prefix)
+    pub fn prefix(&self) -> &[u8] {
+        &self.prefix
+    }
+
+    /// Number of extranonce bytes the channel may use after its prefix
+    pub fn extranonce_size(&self) -> usize {
+        self.extranonce_size
+    }
+
+    /// Prefix in a form suitable for `OpenExtendedMiningChannelSuccess`
+    pub fn prefix_bytes(&self) -> Bytes0_32 {
+        Bytes0_32::from_vec(self.prefix.clone())
+    }
+}
+
+/// Partitions the upstream extranonce space into per-channel prefixes. Released prefixes are
+/// reused before any new prefix is handed out.
+#[derive(Debug)]
+pub struct ExtranonceAllocator {
+    upstream_prefix: Vec<u8>,
+    channel_prefix_size: usize,
+    channel_extranonce_size: usize,
+    /// Highest index that fits into `channel_prefix_size` bytes
+    max_index: u64,
+    /// Next never allocated index, `None` when the whole index range has been handed out
+    next_index: Option<u64>,
+    released: Vec<u64>,
+    allocated: HashSet<u64>,
+}
+
+impl ExtranonceAllocator {
+    /// Creates allocator for the space of `upstream_extranonce_size` bytes that follows
+    /// `upstream_prefix`. Each channel gets `channel_prefix_size` of these bytes as part of its
+    /// prefix.
+    pub fn new(
+        upstream_prefix: Vec<u8>,
+        upstream_extranonce_size: usize,
+        channel_prefix_size: usize,
+    ) -> Result<Self> {
+        if channel_prefix_size == 0 || channel_prefix_size > MAX_CHANNEL_PREFIX_SIZE {
+            return Err(error::Error::InvalidExtranonceConfig(format!(
+                "channel prefix size {} is out of range 1..={}",
+                channel_prefix_size, MAX_CHANNEL_PREFIX_SIZE
+            ))
+            .into());
+        }
+        if channel_prefix_size > upstream_extranonce_size {
+            return Err(error::Error::InvalidExtranonceConfig(format!(
+                "channel prefix size {} exceeds upstream extranonce size {}",
+                channel_prefix_size, upstream_extranonce_size
+            ))
+            .into());
+        }
+        if upstream_prefix.len() + upstream_extranonce_size > MAX_EXTRANONCE_SIZE {
+            return Err(error::Error::InvalidExtranonceConfig(format!(
+                "total extranonce size {} exceeds {}",
+                upstream_prefix.len() + upstream_extranonce_size,
+                MAX_EXTRANONCE_SIZE
+            ))
+            .into());
+        }
+
+        Ok(Self {
+            upstream_prefix,
+            channel_prefix_size,
+            channel_extranonce_size: upstream_extranonce_size - channel_prefix_size,
+            max_index: u64::MAX >> (8 * (MAX_CHANNEL_PREFIX_SIZE - channel_prefix_size)),
+            next_index: Some(0),
+            released: Vec::new(),
+            allocated: HashSet::new(),
+        })
+    }
+
+    /// Total number of channel prefixes this allocator is able to provide (saturated at
+    /// `u64::MAX`)
+    pub fn capacity(&self) -> u64 {
+        self.max_index.saturating_add(1)
+    }
+
+    /// Number of currently allocated prefixes
+    pub fn allocated_count(&self) -> usize {
+        self.allocated.len()
+    }
+
+    /// Number of extranonce bytes that each channel is left with
+    pub fn channel_extranonce_size(&self) -> usize {
+        self.channel_extranonce_size
+    }
+
+    /// Allocates a new channel prefix, fails with [`error::Error::ExtranonceExhausted`] when
+    /// no prefix is available
+    pub fn allocate(&mut self) -> Result<ExtranonceAllocation> {
+        let index = match self.released.pop() {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .next_index
+                    .ok_or(error::Error::ExtranonceExhausted(self.capacity()))?;
+                self.next_index = index.checked_add(1).filter(|next| *next <= self.max_index);
+                index
+            }
+        };
+        self.allocated.insert(index);
+
+        Ok(ExtranonceAllocation {
+            index,
+            prefix: self.build_prefix(index),
+            extranonce_size: self.channel_extranonce_size,
+        })
+    }
+
+    /// Returns `allocation` back to the allocator so that its prefix can be reused
+    pub fn release(&mut self, allocation: ExtranonceAllocation) -> Result<()> {
+        if allocation.prefix != self.build_prefix(allocation.index)
+            || !self.allocated.remove(&allocation.index)
+        {
+            return Err(error::Error::ExtranonceNotAllocated(allocation.prefix).into());
+        }
+        self.released.push(allocation.index);
+        Ok(())
+    }
+
+    /// Channel part of the prefix is the big endian representation of `index` so that prefixes
+    /// sort in allocation order
+    fn build_prefix(&self, index: u64) -> Vec<u8> {
+        let mut prefix = self.upstream_prefix.clone();
+        prefix.extend_from_slice(
+            &index.to_be_bytes()[MAX_CHANNEL_PREFIX_SIZE - self.channel_prefix_size..],
+        );
+        prefix
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocate_prefixes() {
+        let mut allocator =
+            ExtranonceAllocator::new(vec![0xaa, 0xbb], 8, 2).expect("BUG: cannot create allocator");
+        assert_eq!(allocator.capacity(), 0x10000);
+        assert_eq!(allocator.channel_extranonce_size(), 6);
+
+        let first = allocator.allocate().expect("BUG: allocation failed");
+        let second = allocator.allocate().expect("BUG: allocation failed");
+        assert_eq!(first.prefix(), &[0xaa, 0xbb, 0x00, 0x00]);
+        assert_eq!(second.prefix(), &[0xaa, 0xbb, 0x00, 0x01]);
+        assert_eq!(second.extranonce_size(), 6);
+        assert_eq!(second.prefix_bytes().as_ref(), second.prefix());
+        assert_eq!(allocator.allocated_count(), 2);
+    }
+
+    #[test]
+    fn test_exhaustion_and_release() {
+        let mut allocator =
+            ExtranonceAllocator::new(vec![], 1, 1).expect("BUG: cannot create allocator");
+        let mut allocations = (0..256)
+            .map(|_| allocator.allocate().expect("BUG: allocation failed"))
+            .collect::<Vec<_>>();
+        match allocator.allocate() {
+            Err(crate::error::Error::V2(error::Error::ExtranonceExhausted(256))) => (),
+            res => panic!("BUG: unexpected result {:?}", res),
+        }
+
+        let released = allocations.remove(42);
+        assert_eq!(released.prefix(), &[42]);
+        allocator.release(released).expect("BUG: release failed");
+        assert_eq!(allocator.allocated_count(), 255);
+
+        let reused = allocator.allocate().expect("BUG: allocation failed");
+        assert_eq!(reused.prefix(), &[42]);
+        assert_eq!(reused.extranonce_size(), 0);
+    }
+
+    #[test]
+    fn test_release_foreign_allocation() {
+        let mut allocator =
+            ExtranonceAllocator::new(vec![0x01], 4, 1).expect("BUG: cannot create allocator");
+        let mut other =
+            ExtranonceAllocator::new(vec![0x02], 4, 1).expect("BUG: cannot create allocator");
+        allocator.allocate().expect("BUG: allocation failed");
+
+        let foreign = other.allocate().expect("BUG: allocation failed");
+        assert!(allocator.release(foreign).is_err());
+        assert_eq!(allocator.allocated_count(), 1);
+    }
+
+    #[test]
+    fn test_invalid_config() {
+        assert!(ExtranonceAllocator::new(vec![], 4, 0).is_err());
+        assert!(ExtranonceAllocator::new(vec![], 4, 5).is_err());
+        assert!(ExtranonceAllocator::new(vec![], 16, 9).is_err());
+        assert!(ExtranonceAllocator::new(vec![0; 30], 4, 2).is_err());
+
+        let allocator = ExtranonceAllocator::new(vec![], 16, 8).expect("BUG: cannot create");
+        assert_eq!(allocator.capacity(), u64::MAX);
+    }
+}
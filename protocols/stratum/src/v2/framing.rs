@@ -23,10 +23,12 @@
 //! This module defines basic framing and all protocol message types
 
 use bytes::{Buf, BufMut, BytesMut};
+use serde::de::DeserializeOwned;
 
 use ii_logging::macros::*;
 use ii_unvariant::GetId;
 
+use super::serialization::{self, SerializationMode};
 use super::{noise, Protocol};
 use crate::payload::Payload;
 use crate::{
@@ -126,12 +128,15 @@ pub struct Frame {
     pub header: Header,
     /// Keep payload
     pub payload: Payload<Protocol>,
+    /// Encoding of already serialized payload, it is determined by the codec that has received
+    /// the frame
+    serialization_mode: SerializationMode,
 }
 
 impl Frame {
     /// Builds a frame from `src`. No copying occurs as `BytesMut` allows us splitting off
     /// the payload part. The method panics if  `src` doesn't contain exactly one frame.
-    fn deserialize(src: &mut BytesMut) -> Self {
+    fn deserialize(src: &mut BytesMut, serialization_mode: SerializationMode) -> Self {
         let header = Header::deserialize(src);
         // Missing length is considered a bug
         let msg_len: u32 = header.msg_length.expect("BUG: missing header length field");
@@ -151,6 +156,7 @@ impl Frame {
         Self {
             header,
             payload: payload.into(),
+            serialization_mode,
         }
     }
 
@@ -169,6 +175,22 @@ impl Frame {
         Self {
             header,
             payload: payload.into(),
+            serialization_mode: SerializationMode::Binary,
+        }
+    }
+
+    /// Same as `from_serialized_payload()` for `payload` that has been serialized using
+    /// `serialization_mode`
+    pub fn from_serialized_payload_with_mode(
+        is_channel_msg: bool,
+        ext_type: ExtType,
+        msg_type: MsgType,
+        payload: BytesMut,
+        serialization_mode: SerializationMode,
+    ) -> Self {
+        Self {
+            serialization_mode,
+            ..Self::from_serialized_payload(is_channel_msg, ext_type, msg_type, payload)
         }
     }
 
@@ -186,16 +208,48 @@ impl Frame {
         Self {
             header,
             payload: Payload::from_serializable(payload),
+            serialization_mode: SerializationMode::Binary,
         }
     }
 
-    /// Serializes a frame into a specified `dst` buffer. The method either copies the already
-    /// serialized payload into the buffer or runs the on-demand serializer of the payload.
-    fn serialize(&self, dst: &mut BytesMut) -> Result<()> {
+    /// Encoding of the payload. Payload that has not been serialized yet can be serialized in any
+    /// mode, however, `TryFrom<Frame>` conversions of messages deserialize it as binary.
+    pub fn serialization_mode(&self) -> SerializationMode {
+        if self.payload.is_serializable() {
+            SerializationMode::Binary
+        } else {
+            self.serialization_mode
+        }
+    }
+
+    /// Deserializes the payload respecting its serialization mode. Byte sequences in `T` refer
+    /// directly to the payload buffer
+    pub fn deserialize_payload<T: DeserializeOwned>(self) -> Result<T> {
+        let serialization_mode = self.serialization_mode();
+        let payload = self.payload.into_bytes_mut()?.freeze();
+        serialization::with_source(&payload, |payload| serialization_mode.from_slice(payload))
+    }
+
+    /// Serializes a frame at the end of a specified `dst` buffer. The method either copies the already
+    /// serialized payload into the buffer or runs the on-demand serializer of the payload in the
+    /// requested `serialization_mode`. Already serialized payload cannot be converted to
+    /// a different mode.
+    fn serialize(&self, dst: &mut BytesMut, serialization_mode: SerializationMode) -> Result<()> {
         // TODO reserve a reasonable chunk in the buffer - make it a constant
         dst.reserve(128);
-        let mut payload_writer = dst.split_off(Header::SIZE).writer();
-        self.payload.serialize_to_writer(&mut payload_writer)?;
+        let mut payload_writer = dst.split_off(dst.len() + Header::SIZE).writer();
+        match (&self.payload, serialization_mode) {
+            (Payload::LazyBytes(payload), SerializationMode::Json) => {
+                payload.serialize_json_to_writer(&mut payload_writer)?
+            }
+            (Payload::SerializedBytes(_), mode) if mode != self.serialization_mode => {
+                return Err(Error::General(format!(
+                    "Cannot encode {:?} payload of frame {:?} as {:?}",
+                    self.serialization_mode, self.header, mode
+                )))
+            }
+            _ => self.payload.serialize_to_writer(&mut payload_writer)?,
+        }
         // Writer not needed anymore, the underlying BytesMut now contains the serialized payload
         let payload_buf = payload_writer.into_inner();
         // Serialize the header since now can determine the actual payload length
@@ -215,11 +269,13 @@ impl Frame {
     }
 }
 
+/// Frames are identified by the extension type together with the message type as message
+/// types of different extensions overlap
 impl GetId for Frame {
-    type Id = u8;
+    type Id = (ExtType, MsgType);
 
     fn get_id(&self) -> Self::Id {
-        self.header.msg_type
+        (self.header.extension_type, self.header.msg_type)
     }
 }
 
@@ -286,7 +342,7 @@ pub(crate) mod test {
         expected_payload.extend_from_slice(&frame_bytes[frame_bytes.len() - 4..]);
         let expected_frame = Frame::from_serialized_payload(true, 0, 0x16, expected_payload);
 
-        let frame = Frame::deserialize(&mut frame_bytes_buf);
+        let frame = Frame::deserialize(&mut frame_bytes_buf, SerializationMode::Binary);
 
         assert_eq!(expected_frame, frame, "Frames don't match");
     }
@@ -312,10 +368,10 @@ pub(crate) mod test {
         let expected_frame =
             Frame::from_serialized_payload(true, 0, 0x16, BytesMut::from(&payload[..]));
         expected_frame
-            .serialize(&mut frame_bytes_buf)
+            .serialize(&mut frame_bytes_buf, SerializationMode::Binary)
             .expect("BUG: Expected frame serialization failed");
 
-        let frame = Frame::deserialize(&mut frame_bytes_buf);
+        let frame = Frame::deserialize(&mut frame_bytes_buf, SerializationMode::Binary);
 
         assert_eq!(expected_frame, frame, "Frames don't match");
     }
@@ -344,7 +400,9 @@ pub(crate) mod test {
         let frame = Frame::from_serializable_payload(true, 0, 0x16, TestPayload);
 
         let mut dst_frame_bytes = BytesMut::new();
-        assert!(frame.serialize(&mut dst_frame_bytes).is_ok());
+        assert!(frame
+            .serialize(&mut dst_frame_bytes, SerializationMode::Binary)
+            .is_ok());
         assert_eq!(
             BytesMut::from(EXPECTED_FRAME_BYTES),
             dst_frame_bytes,
@@ -20,21 +20,43 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-use bytes::BytesMut;
+use bytes::{Buf, BytesMut};
 use tokio_util::codec::length_delimited::{self, LengthDelimitedCodec};
 use tokio_util::codec::{Decoder, Encoder};
 
 use super::{Frame, Header};
 use crate::error::Error;
+use crate::v2::{error, serialization::SerializationMode};
 
+/// Size of the header part that identifies the message (extension type + message type)
+const MSG_ID_SIZE: usize = Header::LEN_OFFSET;
+
+/// V2 codec with optional support for messages that don't fit into a single frame.
+///
+/// Multi-frame (chunked) messages are split into frames of maximum length (`Header::MAX_LEN`)
+/// followed by a final frame that is shorter (possibly empty). All frames of a message carry the
+/// same extension and message type. Both sides of the connection have to enable chunking as
+/// there is no negotiation.
 #[derive(Debug)]
 pub struct Codec {
     inner: LengthDelimitedCodec,
+    /// Encoding of frame payloads
+    serialization_mode: SerializationMode,
+    /// Limit of the reassembled message payload, `None` disables chunking
+    max_message_size: Option<usize>,
+    /// Limit of the payload length of incoming frames
+    max_frame_size: usize,
+    /// Header and payload collected so far from the frames of a chunked message
+    pending_chunks: Option<BytesMut>,
 }
 
 impl Codec {
     pub fn new() -> Self {
-        // TODO: limit frame size with max_frame_length() ?
+        Self::with_serialization_mode(SerializationMode::default())
+    }
+
+    /// Builds a codec that encodes and decodes frame payloads using `serialization_mode`
+    pub fn with_serialization_mode(serialization_mode: SerializationMode) -> Self {
         // Note: LengthDelimitedCodec is a bit tricky to coerce into
         // including the header in the final mesasge.
         // .num_skip(0) tells it to not skip the header,
@@ -48,7 +70,109 @@ impl Codec {
                 .num_skip(0)
                 // Actual header length is not counted in the length field
                 .length_adjustment(Header::SIZE as isize)
+                .max_frame_length(Header::SIZE + Header::MAX_LEN as usize)
                 .new_codec(),
+            serialization_mode,
+            max_message_size: None,
+            max_frame_size: Header::MAX_LEN as usize,
+            pending_chunks: None,
+        }
+    }
+
+    /// Incoming frames with payload longer than `max_frame_size` are rejected with
+    /// [`error::Error::FrameTooLarge`] before any buffer is allocated for them. Note that
+    /// multi-frame messages (see `with_chunked_messages()`) consist of frames of maximum length.
+    pub fn with_max_frame_size(mut self, max_frame_size: usize) -> Self {
+        self.set_max_frame_size(max_frame_size);
+        self
+    }
+
+    /// Same as `with_max_frame_size()` for a codec that is already in use
+    pub fn set_max_frame_size(&mut self, max_frame_size: usize) {
+        self.max_frame_size = max_frame_size.min(Header::MAX_LEN as usize);
+        self.inner
+            .set_max_frame_length(Header::SIZE + self.max_frame_size);
+    }
+
+    /// Enables splitting/reassembling of messages that don't fit into a single frame. Messages
+    /// larger than `max_message_size` are rejected.
+    pub fn with_chunked_messages(mut self, max_message_size: usize) -> Self {
+        self.max_message_size = Some(max_message_size);
+        self
+    }
+
+    pub fn serialization_mode(&self) -> SerializationMode {
+        self.serialization_mode
+    }
+
+    fn check_message_size(&self, size: usize) -> Result<(), Error> {
+        let max_size = self.max_message_size.unwrap_or(Header::MAX_LEN as usize);
+        if size > max_size {
+            Err(error::Error::MessageTooLarge(size, max_size).into())
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Adds a frame (`chunk`) to the pending chunked message. The reassembled frame is returned
+    /// once the last chunk has been received.
+    fn push_chunk(&mut self, mut chunk: BytesMut) -> Result<Option<Frame>, Error> {
+        let is_last = chunk.len() - Header::SIZE < Header::MAX_LEN as usize;
+        let mut message = match self.pending_chunks.take() {
+            // Prevent copying the common case of a message that fits into a single frame
+            None if is_last => {
+                return Ok(Some(Frame::deserialize(
+                    &mut chunk,
+                    self.serialization_mode,
+                )))
+            }
+            None => chunk,
+            Some(mut message) => {
+                if message[..MSG_ID_SIZE] != chunk[..MSG_ID_SIZE] {
+                    return Err(error::Error::ChunkMismatch(format!(
+                        "expected message id {:x?}, received {:x?}",
+                        &message[..MSG_ID_SIZE],
+                        &chunk[..MSG_ID_SIZE]
+                    ))
+                    .into());
+                }
+                chunk.advance(Header::SIZE);
+                message.extend_from_slice(&chunk);
+                message
+            }
+        };
+        self.check_message_size(message.len() - Header::SIZE)?;
+
+        if !is_last {
+            self.pending_chunks = Some(message);
+            return Ok(None);
+        }
+        // The length field of the first chunk is not valid for the reassembled message
+        let mut header = Header::deserialize(&mut message);
+        header.msg_length = Some(message.len() as u32);
+        Ok(Some(Frame {
+            header,
+            payload: message.into(),
+            serialization_mode: self.serialization_mode,
+        }))
+    }
+
+    /// Writes a serialized frame (`src`) that exceeds the maximum frame length as a series of
+    /// chunks into `dst`
+    fn write_chunks(src: &[u8], dst: &mut BytesMut) {
+        let msg_id = &src[..MSG_ID_SIZE];
+        let payload = &src[Header::SIZE..];
+        let mut offset = 0;
+        // The message is terminated by a chunk shorter than maximum length, possibly an empty one
+        loop {
+            let chunk_len = (payload.len() - offset).min(Header::MAX_LEN as usize);
+            dst.extend_from_slice(msg_id);
+            dst.extend_from_slice(&(chunk_len as u32).to_le_bytes()[..Header::LEN_SIZE]);
+            dst.extend_from_slice(&payload[offset..offset + chunk_len]);
+            offset += chunk_len;
+            if chunk_len < Header::MAX_LEN as usize {
+                break;
+            }
         }
     }
 }
@@ -67,13 +191,35 @@ impl Decoder for Codec {
         &mut self,
         src: &mut BytesMut,
     ) -> std::result::Result<Option<Self::Item>, Self::Error> {
+        // Check the length field early, the inner codec would reserve space for the whole frame
+        // otherwise. Note that the buffer always starts with the header of the next frame.
+        if let Some(len_field) = src.get(Header::LEN_OFFSET..Header::SIZE) {
+            let frame_len = len_field
+                .iter()
+                .rev()
+                .fold(0usize, |len, byte| (len << 8) | *byte as usize);
+            if frame_len > self.max_frame_size {
+                return Err(error::Error::FrameTooLarge(frame_len, self.max_frame_size).into());
+            }
+        }
         let stratum_bytes = self.inner.decode(src)?;
 
         let mut bytes = match stratum_bytes {
             Some(bytes) => bytes,
             None => return Ok(None),
         };
-        Ok(Some(Frame::deserialize(&mut bytes)))
+        if self.max_message_size.is_some() {
+            match self.push_chunk(bytes)? {
+                // Keep on decoding as the rest of the chunked message may be available already
+                None => self.decode(src),
+                frame => Ok(frame),
+            }
+        } else {
+            Ok(Some(Frame::deserialize(
+                &mut bytes,
+                self.serialization_mode,
+            )))
+        }
     }
 }
 
@@ -81,13 +227,210 @@ impl Encoder<Frame> for Codec {
     type Error = Error;
 
     fn encode(&mut self, item: Frame, dst: &mut BytesMut) -> std::result::Result<(), Self::Error> {
-        item.serialize(dst)
+        let frame_start = dst.len();
+        item.serialize(dst, self.serialization_mode)?;
+
+        let payload_len = dst.len() - frame_start - Header::SIZE;
+        if let Err(e) = self.check_message_size(payload_len) {
+            dst.truncate(frame_start);
+            return Err(e);
+        }
+        // Only a message that doesn't fit into a single frame is copied into chunks
+        if payload_len >= Header::MAX_LEN as usize && self.max_message_size.is_some() {
+            let frame_bytes = dst.split_off(frame_start);
+            Self::write_chunks(&frame_bytes, dst);
+        }
+        Ok(())
     }
 }
 
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::test_utils::v2::{build_setup_connection, SETUP_CONNECTION_JSON};
+    use crate::v2::messages::SetupConnection;
+    use crate::v2::Protocol;
+    use crate::{AnyPayload, Result};
+    use std::convert::TryFrom;
+
+    #[test]
+    fn test_codec_json() {
+        let mut codec = Codec::with_serialization_mode(SerializationMode::Json);
+        let frame = Frame::try_from(build_setup_connection())
+            .expect("BUG: Cannot build frame from message");
+
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(frame, &mut buffer)
+            .expect("BUG: Codec failed to encode message");
+        assert_eq!(&buffer[Header::SIZE..], SETUP_CONNECTION_JSON);
+
+        let decoded_frame = codec
+            .decode(&mut buffer)
+            .expect("BUG: Codec failed to decode message")
+            .expect("BUG: No frame provided");
+        assert_eq!(decoded_frame.serialization_mode(), SerializationMode::Json);
+        assert_eq!(
+            SetupConnection::try_from(decoded_frame).expect("BUG: Cannot deserialize message"),
+            build_setup_connection()
+        );
+    }
+
+    /// Verifies that frames are appended to data already pending in the buffer
+    #[test]
+    fn test_codec_appends_frames() {
+        let mut codec = Codec::new();
+        let mut buffer = BytesMut::new();
+        for _ in 0..2 {
+            let frame = Frame::try_from(build_setup_connection())
+                .expect("BUG: Cannot build frame from message");
+            codec
+                .encode(frame, &mut buffer)
+                .expect("BUG: Codec failed to encode message");
+        }
+        for _ in 0..2 {
+            let decoded_frame = codec
+                .decode(&mut buffer)
+                .expect("BUG: Codec failed to decode message")
+                .expect("BUG: No frame provided");
+            assert_eq!(
+                SetupConnection::try_from(decoded_frame).expect("BUG: Cannot deserialize message"),
+                build_setup_connection()
+            );
+        }
+        assert!(buffer.is_empty());
+    }
+
+    /// Payload that is not limited by the maximum frame length
+    struct RawPayload(BytesMut);
+
+    impl AnyPayload<Protocol> for RawPayload {
+        fn serialize_to_writer(&self, writer: &mut dyn std::io::Write) -> Result<()> {
+            writer.write_all(&self.0)?;
+            Ok(())
+        }
+    }
+
+    fn build_large_frame(is_channel_msg: bool, msg_type: u8, payload_len: usize) -> Frame {
+        let payload = (0..payload_len).map(|i| i as u8).collect();
+        Frame::from_serializable_payload(is_channel_msg, 0, msg_type, RawPayload(payload))
+    }
+
+    fn chunked_round_trip(payload_len: usize, expected_chunks: usize) {
+        let max_len = Header::MAX_LEN as usize;
+        let mut codec = Codec::new().with_chunked_messages(3 * max_len);
+        let frame = build_large_frame(true, 0x16, payload_len);
+        let payload = frame
+            .payload
+            .to_bytes_mut()
+            .expect("BUG: Cannot get payload");
+
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(frame, &mut buffer)
+            .expect("BUG: Codec failed to encode message");
+        assert_eq!(buffer.len(), payload_len + expected_chunks * Header::SIZE);
+
+        let decoded_frame = codec
+            .decode(&mut buffer)
+            .expect("BUG: Codec failed to decode message")
+            .expect("BUG: No frame provided");
+        assert!(buffer.is_empty());
+        assert!(decoded_frame.header.is_channel_message);
+        assert_eq!(decoded_frame.header.msg_type, 0x16);
+        assert_eq!(decoded_frame.header.msg_length, Some(payload_len as u32));
+        assert_eq!(
+            decoded_frame
+                .payload
+                .into_bytes_mut()
+                .expect("BUG: Cannot get payload"),
+            payload
+        );
+    }
+
+    #[test]
+    fn test_codec_chunked_message() {
+        let max_len = Header::MAX_LEN as usize;
+        chunked_round_trip(10, 1);
+        chunked_round_trip(max_len + 10, 2);
+        // Terminated by an empty chunk
+        chunked_round_trip(2 * max_len, 3);
+    }
+
+    #[test]
+    fn test_codec_chunked_message_too_large() {
+        let max_len = Header::MAX_LEN as usize;
+        let mut buffer = BytesMut::new();
+
+        Codec::new()
+            .encode(build_large_frame(false, 0x16, max_len + 1), &mut buffer)
+            .expect_err("BUG: Oversized message encoded without chunking");
+
+        Codec::new()
+            .with_chunked_messages(2 * max_len)
+            .encode(build_large_frame(false, 0x16, max_len + 1), &mut buffer)
+            .expect("BUG: Codec failed to encode message");
+        match Codec::new()
+            .with_chunked_messages(max_len)
+            .decode(&mut buffer)
+        {
+            Err(Error::V2(error::Error::MessageTooLarge(size, max_size))) => {
+                assert_eq!((size, max_size), (max_len + 1, max_len))
+            }
+            res => panic!("BUG: Unexpected decoding result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_codec_chunk_mismatch() {
+        let max_len = Header::MAX_LEN as usize;
+        let mut codec = Codec::new().with_chunked_messages(2 * max_len);
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(build_large_frame(false, 0x16, max_len), &mut buffer)
+            .expect("BUG: Codec failed to encode message");
+        // Replace the terminating chunk with a chunk of a different message
+        buffer.truncate(Header::SIZE + max_len);
+        Header::new(false, 0, 0x17, Some(0)).serialize(&mut buffer, None);
+
+        match codec.decode(&mut buffer) {
+            Err(Error::V2(error::Error::ChunkMismatch(_))) => (),
+            res => panic!("BUG: Unexpected decoding result {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_codec_frame_too_large() {
+        let mut buffer = BytesMut::new();
+        Codec::new()
+            .encode(build_large_frame(false, 0x16, 1000), &mut buffer)
+            .expect("BUG: Codec failed to encode message");
+
+        // Only the header is needed to reject the frame
+        let mut header = buffer.split_to(Header::SIZE);
+        match Codec::new().with_max_frame_size(999).decode(&mut header) {
+            Err(Error::V2(error::Error::FrameTooLarge(1000, 999))) => (),
+            res => panic!("BUG: Unexpected decoding result {:?}", res),
+        }
+
+        header.unsplit(buffer);
+        Codec::new()
+            .with_max_frame_size(1000)
+            .decode(&mut header)
+            .expect("BUG: Codec failed to decode message")
+            .expect("BUG: No frame provided");
+    }
+
+    /// Already serialized payload cannot be transcoded to a different serialization mode
+    #[test]
+    fn test_codec_serialization_mode_mismatch() {
+        let mut codec = Codec::with_serialization_mode(SerializationMode::Json);
+        let frame = Frame::from_serialized_payload(false, 0, 0x16, BytesMut::from(&[1, 2][..]));
+
+        codec
+            .encode(frame, &mut BytesMut::new())
+            .expect_err("BUG: Binary payload encoded by JSON codec");
+    }
 
     #[test]
     fn test_codec_no_noise() {
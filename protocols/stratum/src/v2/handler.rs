@@ -0,0 +1,374 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Handler of V2 mining protocol messages. Applications implement only the messages they are
+//! interested in, the remaining ones are logged and ignored by the default implementations.
+
+use async_trait::async_trait;
+use std::convert::TryFrom;
+use std::fmt;
+
+use ii_logging::macros::*;
+use ii_unvariant::{GetId, Id};
+
+use super::framing;
+use super::messages::*;
+use crate::error::Result;
+
+/// Default action for messages that the handler doesn't implement
+fn ignore_message<T: fmt::Debug>(msg: T) {
+    debug!("V2Handler: ignoring message: {:?}", msg);
+}
+
+/// Handler of the mining protocol messages (base extension). Frames are deserialized and
+/// dispatched to the corresponding method by [`V2Handler::handle_frame`].
+#[async_trait]
+pub trait V2Handler: Send {
+    async fn handle_setup_connection(&mut self, msg: SetupConnection) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_setup_connection_success(&mut self, msg: SetupConnectionSuccess) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_setup_connection_error(&mut self, msg: SetupConnectionError) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_channel_endpoint_changed(&mut self, msg: ChannelEndpointChanged) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_open_standard_mining_channel(
+        &mut self,
+        msg: OpenStandardMiningChannel,
+    ) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_open_standard_mining_channel_success(
+        &mut self,
+        msg: OpenStandardMiningChannelSuccess,
+    ) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_open_extended_mining_channel(
+        &mut self,
+        msg: OpenExtendedMiningChannel,
+    ) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_open_extended_mining_channel_success(
+        &mut self,
+        msg: OpenExtendedMiningChannelSuccess,
+    ) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_open_mining_channel_error(
+        &mut self,
+        msg: OpenMiningChannelError,
+    ) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_update_channel(&mut self, msg: UpdateChannel) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_update_channel_error(&mut self, msg: UpdateChannelError) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_close_channel(&mut self, msg: CloseChannel) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_submit_shares_standard(&mut self, msg: SubmitSharesStandard) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_submit_shares_extended(&mut self, msg: SubmitSharesExtended) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_submit_shares_success(&mut self, msg: SubmitSharesSuccess) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_submit_shares_error(&mut self, msg: SubmitSharesError) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_new_mining_job(&mut self, msg: NewMiningJob) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_new_extended_mining_job(&mut self, msg: NewExtendedMiningJob) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_set_new_prev_hash(&mut self, msg: SetNewPrevHash) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_set_custom_mining_job(&mut self, msg: SetCustomMiningJob) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_set_custom_mining_job_success(
+        &mut self,
+        msg: SetCustomMiningJobSuccess,
+    ) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_set_custom_mining_job_error(
+        &mut self,
+        msg: SetCustomMiningJobError,
+    ) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_set_target(&mut self, msg: SetTarget) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_reconnect(&mut self, msg: Reconnect) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    async fn handle_set_group_channel(&mut self, msg: SetGroupChannel) -> Result<()> {
+        ignore_message(msg);
+        Ok(())
+    }
+
+    /// Handles frames of other extensions and frames with an unknown message type
+    async fn handle_unknown_frame(&mut self, frame: framing::Frame) -> Result<()> {
+        debug!("V2Handler: ignoring unknown frame: {:x?}", frame.header);
+        Ok(())
+    }
+
+    /// Deserializes the frame and dispatches it to the corresponding handler method
+    async fn handle_frame(&mut self, frame: framing::Frame) -> Result<()> {
+        match frame.get_id() {
+            <SetupConnection as Id<_>>::ID => {
+                self.handle_setup_connection(SetupConnection::try_from(frame)?)
+                    .await
+            }
+            <SetupConnectionSuccess as Id<_>>::ID => {
+                self.handle_setup_connection_success(SetupConnectionSuccess::try_from(frame)?)
+                    .await
+            }
+            <SetupConnectionError as Id<_>>::ID => {
+                self.handle_setup_connection_error(SetupConnectionError::try_from(frame)?)
+                    .await
+            }
+            <ChannelEndpointChanged as Id<_>>::ID => {
+                self.handle_channel_endpoint_changed(ChannelEndpointChanged::try_from(frame)?)
+                    .await
+            }
+            <OpenStandardMiningChannel as Id<_>>::ID => {
+                self.handle_open_standard_mining_channel(OpenStandardMiningChannel::try_from(
+                    frame,
+                )?)
+                .await
+            }
+            <OpenStandardMiningChannelSuccess as Id<_>>::ID => {
+                self.handle_open_standard_mining_channel_success(
+                    OpenStandardMiningChannelSuccess::try_from(frame)?,
+                )
+                .await
+            }
+            <OpenExtendedMiningChannel as Id<_>>::ID => {
+                self.handle_open_extended_mining_channel(OpenExtendedMiningChannel::try_from(
+                    frame,
+                )?)
+                .await
+            }
+            <OpenExtendedMiningChannelSuccess as Id<_>>::ID => {
+                self.handle_open_extended_mining_channel_success(
+                    OpenExtendedMiningChannelSuccess::try_from(frame)?,
+                )
+                .await
+            }
+            <OpenMiningChannelError as Id<_>>::ID => {
+                self.handle_open_mining_channel_error(OpenMiningChannelError::try_from(frame)?)
+                    .await
+            }
+            <UpdateChannel as Id<_>>::ID => {
+                self.handle_update_channel(UpdateChannel::try_from(frame)?)
+                    .await
+            }
+            <UpdateChannelError as Id<_>>::ID => {
+                self.handle_update_channel_error(UpdateChannelError::try_from(frame)?)
+                    .await
+            }
+            <CloseChannel as Id<_>>::ID => {
+                self.handle_close_channel(CloseChannel::try_from(frame)?)
+                    .await
+            }
+            <SubmitSharesStandard as Id<_>>::ID => {
+                self.handle_submit_shares_standard(SubmitSharesStandard::try_from(frame)?)
+                    .await
+            }
+            <SubmitSharesExtended as Id<_>>::ID => {
+                self.handle_submit_shares_extended(SubmitSharesExtended::try_from(frame)?)
+                    .await
+            }
+            <SubmitSharesSuccess as Id<_>>::ID => {
+                self.handle_submit_shares_success(SubmitSharesSuccess::try_from(frame)?)
+                    .await
+            }
+            <SubmitSharesError as Id<_>>::ID => {
+                self.handle_submit_shares_error(SubmitSharesError::try_from(frame)?)
+                    .await
+            }
+            <NewMiningJob as Id<_>>::ID => {
+                self.handle_new_mining_job(NewMiningJob::try_from(frame)?)
+                    .await
+            }
+            <NewExtendedMiningJob as Id<_>>::ID => {
+                self.handle_new_extended_mining_job(NewExtendedMiningJob::try_from(frame)?)
+                    .await
+            }
+            <SetNewPrevHash as Id<_>>::ID => {
+                self.handle_set_new_prev_hash(SetNewPrevHash::try_from(frame)?)
+                    .await
+            }
+            <SetCustomMiningJob as Id<_>>::ID => {
+                self.handle_set_custom_mining_job(SetCustomMiningJob::try_from(frame)?)
+                    .await
+            }
+            <SetCustomMiningJobSuccess as Id<_>>::ID => {
+                self.handle_set_custom_mining_job_success(SetCustomMiningJobSuccess::try_from(
+                    frame,
+                )?)
+                .await
+            }
+            <SetCustomMiningJobError as Id<_>>::ID => {
+                self.handle_set_custom_mining_job_error(SetCustomMiningJobError::try_from(frame)?)
+                    .await
+            }
+            <SetTarget as Id<_>>::ID => self.handle_set_target(SetTarget::try_from(frame)?).await,
+            <Reconnect as Id<_>>::ID => self.handle_reconnect(Reconnect::try_from(frame)?).await,
+            <SetGroupChannel as Id<_>>::ID => {
+                self.handle_set_group_channel(SetGroupChannel::try_from(frame)?)
+                    .await
+            }
+            _ => self.handle_unknown_frame(frame).await,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_utils::v2::*;
+
+    /// Handler that is interested in `SetupConnection` only
+    #[derive(Default)]
+    struct SetupConnectionHandler {
+        setup_connection: Option<SetupConnection>,
+        unknown_frames: usize,
+    }
+
+    #[async_trait]
+    impl V2Handler for SetupConnectionHandler {
+        async fn handle_setup_connection(&mut self, msg: SetupConnection) -> Result<()> {
+            self.setup_connection = Some(msg);
+            Ok(())
+        }
+
+        async fn handle_unknown_frame(&mut self, _frame: framing::Frame) -> Result<()> {
+            self.unknown_frames += 1;
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handle_implemented_message() {
+        let mut handler = SetupConnectionHandler::default();
+        let frame = framing::Frame::try_from(build_setup_connection())
+            .expect("BUG: Cannot create test frame");
+        handler
+            .handle_frame(frame)
+            .await
+            .expect("BUG: Handling SetupConnection failed");
+        assert_eq!(handler.setup_connection, Some(build_setup_connection()));
+        assert_eq!(handler.unknown_frames, 0);
+    }
+
+    #[tokio::test]
+    async fn test_default_passthrough() {
+        let mut handler = SetupConnectionHandler::default();
+        let frame =
+            framing::Frame::try_from(build_open_channel()).expect("BUG: Cannot create test frame");
+        handler
+            .handle_frame(frame)
+            .await
+            .expect("BUG: Default handler should ignore the message");
+        assert_eq!(handler.setup_connection, None);
+        assert_eq!(handler.unknown_frames, 0);
+    }
+
+    #[tokio::test]
+    async fn test_handle_unknown_frame() {
+        let mut handler = SetupConnectionHandler::default();
+        // Same message type as SetupConnection but a different extension
+        let frame = framing::Frame::try_from(build_submit_device_status())
+            .expect("BUG: Cannot create test frame");
+        handler
+            .handle_frame(frame)
+            .await
+            .expect("BUG: Unknown frame should be ignored");
+        assert_eq!(handler.setup_connection, None);
+        assert_eq!(handler.unknown_frames, 1);
+    }
+}
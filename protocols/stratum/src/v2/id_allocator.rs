@@ -0,0 +1,156 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Allocation of channel and group channel ids
+//!
+//! Group channel ids share the id space with channel ids within a connection, therefore a single
+//! [`IdAllocator`] per connection should be used for both.
+
+use std::collections::HashSet;
+
+use super::error;
+use crate::error::Result;
+
+/// Hands out unique `u32` ids from a configurable range. Released ids are reused before any new
+/// id is handed out, both allocation and release are O(1).
+#[derive(Debug)]
+pub struct IdAllocator {
+    first: u32,
+    last: u32,
+    /// Next never allocated id, `None` when the whole range has been handed out
+    next: Option<u32>,
+    released: Vec<u32>,
+    allocated: HashSet<u32>,
+}
+
+impl Default for IdAllocator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl IdAllocator {
+    /// Allocator for the complete `u32` range
+    pub fn new() -> Self {
+        Self::with_range(0, u32::MAX)
+    }
+
+    /// Allocator for ids in range `first..=last`
+    pub fn with_range(first: u32, last: u32) -> Self {
+        assert!(first <= last, "BUG: empty id range {}..={}", first, last);
+        Self {
+            first,
+            last,
+            next: Some(first),
+            released: Vec::new(),
+            allocated: HashSet::new(),
+        }
+    }
+
+    /// Allocates a new id, fails with [`error::Error::IdsExhausted`] when the whole range is in
+    /// use
+    pub fn allocate(&mut self) -> Result<u32> {
+        let id = match self.released.pop() {
+            Some(id) => id,
+            None => {
+                let id = self
+                    .next
+                    .ok_or(error::Error::IdsExhausted(self.first, self.last))?;
+                self.next = id.checked_add(1).filter(|next| *next <= self.last);
+                id
+            }
+        };
+        self.allocated.insert(id);
+        Ok(id)
+    }
+
+    /// Returns `id` back to the allocator so that it can be reused
+    pub fn release(&mut self, id: u32) -> Result<()> {
+        if !self.allocated.remove(&id) {
+            return Err(error::Error::IdNotAllocated(id).into());
+        }
+        self.released.push(id);
+        Ok(())
+    }
+
+    pub fn is_allocated(&self, id: u32) -> bool {
+        self.allocated.contains(&id)
+    }
+
+    /// Number of ids currently in use
+    pub fn allocated_count(&self) -> usize {
+        self.allocated.len()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_allocate_and_reuse() {
+        let mut allocator = IdAllocator::new();
+        assert_eq!(allocator.allocate().expect("BUG: allocation failed"), 0);
+        assert_eq!(allocator.allocate().expect("BUG: allocation failed"), 1);
+        assert_eq!(allocator.allocate().expect("BUG: allocation failed"), 2);
+
+        allocator.release(1).expect("BUG: release failed");
+        assert!(!allocator.is_allocated(1));
+        assert_eq!(allocator.allocated_count(), 2);
+        assert_eq!(allocator.allocate().expect("BUG: allocation failed"), 1);
+        assert_eq!(allocator.allocate().expect("BUG: allocation failed"), 3);
+    }
+
+    #[test]
+    fn test_exhaustion() {
+        let mut allocator = IdAllocator::with_range(u32::MAX - 1, u32::MAX);
+        assert_eq!(
+            allocator.allocate().expect("BUG: allocation failed"),
+            u32::MAX - 1
+        );
+        assert_eq!(
+            allocator.allocate().expect("BUG: allocation failed"),
+            u32::MAX
+        );
+        match allocator.allocate() {
+            Err(crate::error::Error::V2(error::Error::IdsExhausted(first, last))) => {
+                assert_eq!((first, last), (u32::MAX - 1, u32::MAX))
+            }
+            res => panic!("BUG: unexpected result {:?}", res),
+        }
+
+        allocator.release(u32::MAX).expect("BUG: release failed");
+        assert_eq!(
+            allocator.allocate().expect("BUG: allocation failed"),
+            u32::MAX
+        );
+    }
+
+    #[test]
+    fn test_release_unallocated() {
+        let mut allocator = IdAllocator::with_range(10, 20);
+        assert!(allocator.release(10).is_err());
+        let id = allocator.allocate().expect("BUG: allocation failed");
+        allocator.release(id).expect("BUG: release failed");
+        assert!(allocator.release(id).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Helpers for assembling mining jobs: coinbase transaction hashing, merkle root computation and
+//! block header construction. These allow validating shares locally without going through V1.
+
+use bitcoin_hashes::{sha256d, Hash, HashEngine};
+
+use super::messages::{NewExtendedMiningJob, SetNewPrevHash, SubmitSharesExtended};
+use super::types::{Seq0_255, Uint256Bytes};
+
+/// Computes the double SHA256 hash of the coinbase transaction assembled from
+/// `prefix` + `extranonce` + `suffix`. The `extranonce` is expected to contain the full
+/// extranonce (i.e. extranonce prefix assigned by upstream followed by the miner's part).
+pub fn coinbase_tx_hash(prefix: &[u8], extranonce: &[u8], suffix: &[u8]) -> sha256d::Hash {
+    let mut engine = sha256d::Hash::engine();
+    engine.input(prefix);
+    engine.input(extranonce);
+    engine.input(suffix);
+    sha256d::Hash::from_engine(engine)
+}
+
+/// Folds the `merkle_path` (ordered from the deepest level) into the coinbase transaction hash
+/// and yields the resulting merkle root
+pub fn fold_merkle_path(
+    cb_tx_hash: sha256d::Hash,
+    merkle_path: &Seq0_255<Uint256Bytes>,
+) -> Uint256Bytes {
+    let merkle_root = merkle_path
+        .iter()
+        .fold(cb_tx_hash, |curr_merkle_root, tx_hash| {
+            let mut engine = sha256d::Hash::engine();
+            engine.input(&curr_merkle_root.into_inner());
+            engine.input(tx_hash.as_ref());
+            sha256d::Hash::from_engine(engine)
+        });
+    Uint256Bytes(merkle_root.into_inner())
+}
+
+/// Calculates merkle root of a job from the coinbase transaction parts, the full extranonce and
+/// the merkle path
+pub fn merkle_root(
+    coinbase_tx_prefix: &[u8],
+    extranonce: &[u8],
+    coinbase_tx_suffix: &[u8],
+    merkle_path: &Seq0_255<Uint256Bytes>,
+) -> Uint256Bytes {
+    fold_merkle_path(
+        coinbase_tx_hash(coinbase_tx_prefix, extranonce, coinbase_tx_suffix),
+        merkle_path,
+    )
+}
+
+/// Assembles a block header from its individual fields
+pub fn block_header(
+    version: u32,
+    prev_hash: &Uint256Bytes,
+    merkle_root: &Uint256Bytes,
+    ntime: u32,
+    nbits: u32,
+    nonce: u32,
+) -> ii_bitcoin::BlockHeader {
+    ii_bitcoin::BlockHeader {
+        version,
+        previous_hash: prev_hash.0,
+        merkle_root: merkle_root.0,
+        time: ntime,
+        bits: nbits,
+        nonce,
+    }
+}
+
+/// Reconstructs the block header that has been mined by the extended channel `share`.
+/// `extranonce_prefix` is the prefix that has been assigned to the channel upon opening, the
+/// `share` provides the rest of the extranonce.
+pub fn extended_share_block_header(
+    job: &NewExtendedMiningJob,
+    prev_hash: &SetNewPrevHash,
+    extranonce_prefix: &[u8],
+    share: &SubmitSharesExtended,
+) -> ii_bitcoin::BlockHeader {
+    let mut extranonce = Vec::with_capacity(extranonce_prefix.len() + share.extranonce.len());
+    extranonce.extend_from_slice(extranonce_prefix);
+    extranonce.extend_from_slice(&share.extranonce);
+
+    let merkle_root = merkle_root(
+        &job.coinbase_tx_prefix,
+        &extranonce,
+        &job.coinbase_tx_suffix,
+        &job.merkle_path,
+    );
+    block_header(
+        share.version,
+        &prev_hash.prev_hash,
+        &merkle_root,
+        share.ntime,
+        prev_hash.nbits,
+        share.nonce,
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use bitcoin_hashes::hex::{FromHex, ToHex};
+
+    /// Genesis block coinbase transaction, split around the extranonce (the `0x04ffff001d`
+    /// push in the coinbase script is treated as the extranonce)
+    const GENESIS_CB_PREFIX: &str =
+        "01000000010000000000000000000000000000000000000000000000000000000000000000ffffffff4d";
+    const GENESIS_CB_EXTRANONCE: &str = "04ffff001d";
+    const GENESIS_CB_SUFFIX: &str = concat!(
+        "0104455468652054696d65732030332f4a616e2f32303039204368616e63656c6c6f72206f6e20627269",
+        "6e6b206f66207365636f6e64206261696c6f757420666f722062616e6b73ffffffff0100f2052a010000",
+        "00434104678afdb0fe5548271967f1a67130b7105cd6a828e03909a67962e0ea1f61deb649f6bc3f4cef",
+        "38c4f35504e51ec112de5c384df7ba0b8d578a4c702b6bf11d5fac00000000"
+    );
+    const GENESIS_MERKLE_ROOT: &str =
+        "4a5e1e4baab89f3a32518a88c31bc87f618f76673e2cc77ab2127b7afdeda33b";
+    const GENESIS_HASH: &str = "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f";
+
+    fn bytes(hex: &str) -> Vec<u8> {
+        Vec::from_hex(hex).expect("BUG: invalid hex")
+    }
+
+    fn genesis_merkle_root() -> Uint256Bytes {
+        merkle_root(
+            &bytes(GENESIS_CB_PREFIX),
+            &bytes(GENESIS_CB_EXTRANONCE),
+            &bytes(GENESIS_CB_SUFFIX),
+            &Seq0_255::from_vec(vec![]),
+        )
+    }
+
+    #[test]
+    fn test_genesis_merkle_root() {
+        let expected = sha256d::Hash::from_hex(GENESIS_MERKLE_ROOT).expect("BUG: from_hex");
+        assert_eq!(genesis_merkle_root(), Uint256Bytes(expected.into_inner()));
+    }
+
+    #[test]
+    fn test_genesis_block_header() {
+        let header = block_header(
+            1,
+            &Uint256Bytes([0; 32]),
+            &genesis_merkle_root(),
+            1231006505,
+            0x1d00ffff,
+            2083236893,
+        );
+        assert_eq!(header.hash().to_hex(), GENESIS_HASH);
+    }
+
+    #[test]
+    fn test_fold_merkle_path() {
+        let cb_tx_hash = coinbase_tx_hash(b"prefix", b"extranonce", b"suffix");
+        let path = Seq0_255::from_vec(vec![Uint256Bytes([0x11; 32]), Uint256Bytes([0x22; 32])]);
+
+        let level1 = sha256d::Hash::hash(&[&cb_tx_hash[..], &[0x11; 32]].concat());
+        let level2 = sha256d::Hash::hash(&[&level1[..], &[0x22; 32]].concat());
+        assert_eq!(
+            fold_merkle_path(cb_tx_hash, &path),
+            Uint256Bytes(level2.into_inner())
+        );
+        assert_eq!(
+            fold_merkle_path(cb_tx_hash, &Seq0_255::from_vec(vec![])),
+            Uint256Bytes(cb_tx_hash.into_inner())
+        );
+    }
+}
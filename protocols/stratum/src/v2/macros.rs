@@ -41,7 +41,7 @@ macro_rules! impl_message_conversion {
                 Ok(framing::Frame::from_serializable_payload(
                     $is_channel_msg,
                     $extension_id,
-                    $message::ID as framing::MsgType,
+                    <$message as Id<framing::MsgType>>::ID,
                     m,
                 ))
             }
@@ -58,10 +58,10 @@ macro_rules! impl_message_conversion {
         impl TryFrom<framing::Frame> for $message {
             type Error = Error;
 
+            /// Byte sequences of the message refer directly to the frame payload, no copying
+            /// takes place
             fn try_from(frame: framing::Frame) -> Result<Self> {
-                let (_header, payload) = frame.split();
-                let payload = payload.into_bytes_mut()?;
-                Self::try_from(&payload[..])
+                frame.deserialize_payload()
             }
         }
 
@@ -74,7 +74,19 @@ macro_rules! impl_message_conversion {
         }
 
         impl Id<u8> for Box<$message> {
-            const ID: u8 = $message::ID;
+            const ID: u8 = <$message as Id<u8>>::ID;
+        }
+
+        /// Message type is unique only within its extension, the scoped id is used for
+        /// dispatching frames (see `GetId` for `Frame`)
+        impl Id<(framing::ExtType, framing::MsgType)> for $message {
+            const ID: (framing::ExtType, framing::MsgType) =
+                ($extension_id, <$message as Id<framing::MsgType>>::ID);
+        }
+
+        impl Id<(framing::ExtType, framing::MsgType)> for Box<$message> {
+            const ID: (framing::ExtType, framing::MsgType) =
+                <$message as Id<(framing::ExtType, framing::MsgType)>>::ID;
         }
 
         /// Each message is a `AnyPayload/SerializablePayload` object that can be serialized into
@@ -83,6 +95,10 @@ macro_rules! impl_message_conversion {
             fn serialize_to_writer(&self, writer: &mut dyn std::io::Write) -> Result<()> {
                 serialization::to_writer(writer, self).map_err(Into::into)
             }
+
+            fn serialize_json_to_writer(&self, writer: &mut dyn std::io::Write) -> Result<()> {
+                $crate::v2::serialization::SerializationMode::Json.to_writer(writer, self)
+            }
         }
     };
 }
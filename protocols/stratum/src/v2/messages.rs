@@ -27,17 +27,20 @@ use std::convert::TryFrom;
 
 use super::extensions;
 use super::framing;
-#[cfg(not(feature = "v2json"))]
 use super::serialization;
 use super::types::*;
 use super::Protocol;
 use crate::error::{Error, Result};
 use crate::AnyPayload;
-#[cfg(feature = "v2json")]
-use serde_json as serialization;
 
 use ii_unvariant::{id, Id};
 
+pub mod builder;
+pub mod job_negotiation;
+pub mod template_distribution;
+
+pub use builder::*;
+
 #[cfg(test)]
 mod test;
 
@@ -56,12 +59,10 @@ macro_rules! impl_base_message_conversion {
 /// string describing, at least, the particular hardware/software package in use.
 #[id(0x00u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SetupConnection {
-    /// 0 = Mining Protocol
-    /// 1 = Job Negotiation Protocol
-    /// 2 = Template Distribution Protocol
-    /// 3 = Job Distribution Protocol
-    pub protocol: u8,
+    /// Subprotocol to be used on the connection
+    pub protocol: SubProtocol,
     /// The minimum protocol version the client supports (currently must be 2).
     pub min_version: u16,
     /// The maximum protocol version the client supports (currently must be 2).
@@ -81,6 +82,7 @@ pub struct SetupConnection {
 /// to verify the set of feature flags that the server supports and act accordingly.
 #[id(0x01u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SetupConnectionSuccess {
     /// Selected version proposed by the connecting node that the upstream node supports. This version will be used on the connection for the rest of its life.
     pub used_version: u16,
@@ -89,20 +91,23 @@ pub struct SetupConnectionSuccess {
 }
 
 #[id(0x02u8)]
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SetupConnectionError {
     pub flags: u32,
     pub code: Str0_255,
 }
 
 #[id(0x03u8)]
-#[derive(Serialize, Deserialize, Clone, Debug)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct ChannelEndpointChanged {
     pub channel_id: u32,
 }
 
 #[id(0x10u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenStandardMiningChannel {
     /// Client-specified identifier for matching responses from upstream server.
     /// The value MUST be connection-wide unique and is not interpreted by the server.
@@ -126,6 +131,7 @@ pub struct OpenStandardMiningChannel {
 /// standard channel.
 #[id(0x13u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenExtendedMiningChannel {
     /// Client-specified identifier for matching responses from upstream server.
     /// The value MUST be connection-wide unique and is not interpreted by the server.
@@ -149,6 +155,7 @@ pub struct OpenExtendedMiningChannel {
 
 #[id(0x14u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenExtendedMiningChannelSuccess {
     /// Client-specified request ID from [`OpenExtendedMiningChannel`] message, so that the client can
     /// pair responses with open channel requests.
@@ -166,6 +173,7 @@ pub struct OpenExtendedMiningChannelSuccess {
 
 #[id(0x11u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenStandardMiningChannelSuccess {
     pub req_id: u32,
     pub channel_id: u32,
@@ -178,6 +186,7 @@ pub struct OpenStandardMiningChannelSuccess {
 
 #[id(0x12u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenMiningChannelError {
     pub req_id: u32,
     pub code: Str0_32,
@@ -185,6 +194,7 @@ pub struct OpenMiningChannelError {
 
 #[id(0x16u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct UpdateChannel {
     pub channel_id: u32,
     pub nominal_hash_rate: f32,
@@ -193,6 +203,7 @@ pub struct UpdateChannel {
 
 #[id(0x17u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct UpdateChannelError {
     pub channel_id: u32,
     pub error_code: Str0_32,
@@ -200,6 +211,7 @@ pub struct UpdateChannelError {
 
 #[id(0x18u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct CloseChannel {
     pub channel_id: u32,
     pub reason_code: Str0_32,
@@ -207,6 +219,7 @@ pub struct CloseChannel {
 
 #[id(0x1au8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SubmitSharesStandard {
     /// Channel identification.
     pub channel_id: u32,
@@ -226,6 +239,7 @@ pub struct SubmitSharesStandard {
 
 #[id(0x1bu8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SubmitSharesExtended {
     /// Channel identification.
     pub channel_id: u32,
@@ -253,6 +267,7 @@ pub struct SubmitSharesExtended {
 /// provided for multiple SubmitShare messages aggregated together.
 #[id(0x1cu8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SubmitSharesSuccess {
     /// Channel identifier.
     pub channel_id: u32,
@@ -270,6 +285,7 @@ pub struct SubmitSharesSuccess {
 /// than the server does (see NewPrevHash message for details).
 #[id(0x1du8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SubmitSharesError {
     /// Channel identifier.
     pub channel_id: u32,
@@ -281,6 +297,7 @@ pub struct SubmitSharesError {
 
 #[id(0x1eu8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct NewMiningJob {
     pub channel_id: u32,
     pub job_id: u32,
@@ -291,6 +308,7 @@ pub struct NewMiningJob {
 
 #[id(0x1fu8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct NewExtendedMiningJob {
     /// For a group channel, the message is broadcasted to all standard channels belonging to the
     /// group. Otherwise, it is addressed to the specified extended channel.
@@ -318,6 +336,7 @@ pub struct NewExtendedMiningJob {
 
 #[id(0x20u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SetNewPrevHash {
     pub channel_id: u32,
     pub job_id: u32,
@@ -326,11 +345,91 @@ pub struct SetNewPrevHash {
     pub nbits: u32,
 }
 
-pub struct SetCustomMiningJob;
-pub struct SetCustomMiningJobSuccess;
+/// Can be sent only on extended channel. [`SetupConnection`] flags MUST contain
+/// REQUIRES_WORK_SELECTION flag (work selection feature successfully declared).
+/// The downstream node has a custom job negotiated by a trusted external Job Negotiator. The
+/// mining_job_token provides the information for the pool to authorize the custom job that has
+/// been or will be negotiated between the Job Negotiator and Pool.
+#[id(0x22u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SetCustomMiningJob {
+    /// Extended channel identifier.
+    pub channel_id: u32,
+    /// Client-specified identifier for pairing responses.
+    pub request_id: u32,
+    /// Token provided by the pool which uniquely identifies the job that the Job Negotiator has
+    /// negotiated with the pool.
+    pub mining_job_token: Bytes0_255,
+    /// Valid version field that reflects the current network consensus. The general purpose bits
+    /// (as specified in BIP320) can be freely manipulated by the downstream node.
+    pub version: u32,
+    /// Previous block’s hash, found in the block header field.
+    pub prev_hash: Uint256Bytes,
+    /// Smallest nTime value available for hashing.
+    pub min_ntime: u32,
+    /// Block header field.
+    pub nbits: u32,
+    /// The coinbase transaction nVersion field.
+    pub coinbase_tx_version: u32,
+    /// Up to 8 bytes (not including the length byte) which are to be placed at the beginning of
+    /// the coinbase field in the coinbase transaction.
+    pub coinbase_prefix: Bytes0_255,
+    /// The coinbase transaction input’s nSequence field.
+    pub coinbase_tx_input_n_sequence: u32,
+    /// The value, in satoshis, available for spending in coinbase outputs added by the client.
+    /// Includes both transaction fees and block subsidy.
+    pub coinbase_tx_value_remaining: u64,
+    /// Bitcoin transaction outputs to be included as the last outputs in the coinbase
+    /// transaction.
+    pub coinbase_tx_outputs: Bytes0_64k,
+    /// The locktime field in the coinbase transaction.
+    pub coinbase_tx_locktime: u32,
+    /// Merkle path hashes ordered from deepest.
+    pub merkle_path: Seq0_255<Uint256Bytes>,
+    /// Size of extranonce in bytes that will be provided by the downstream node.
+    pub extranonce_size: u16,
+    /// True if the job is intended for a future SetNewPrevHash message sent on the channel.
+    pub future_job: bool,
+}
+
+/// Response from the server when it accepts the custom mining job. Client can start to mine on
+/// the job immediately (by using the job_id provided within this response).
+#[id(0x23u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SetCustomMiningJobSuccess {
+    /// Extended channel identifier.
+    pub channel_id: u32,
+    /// Client-specified identifier for pairing responses. Value from the request MUST be
+    /// provided by upstream in the response message.
+    pub request_id: u32,
+    /// Server’s identification of the mining job.
+    pub job_id: u32,
+    /// Prefix part of the coinbase transaction.
+    pub coinbase_tx_prefix: Bytes0_64k,
+    /// Suffix part of the coinbase transaction.
+    pub coinbase_tx_suffix: Bytes0_64k,
+}
+
+/// Response from the server when it rejects the custom mining job, the error code is one of:
+/// invalid-channel-id, invalid-mining-job-token, invalid-job-param-value-{field_name}
+#[id(0x24u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SetCustomMiningJobError {
+    /// Extended channel identifier.
+    pub channel_id: u32,
+    /// Client-specified identifier for pairing responses. Value from the request MUST be
+    /// provided by upstream in the response message.
+    pub request_id: u32,
+    /// Reason why the custom job has been rejected.
+    pub error_code: Str0_255,
+}
 
 #[id(0x21u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SetTarget {
     pub channel_id: u32,
     pub max_target: Uint256Bytes,
@@ -338,12 +437,26 @@ pub struct SetTarget {
 
 #[id(0x25u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Reconnect {
     pub new_host: Str0_255,
     pub new_port: u16,
 }
 
-pub struct SetGroupChannel;
+/// Every standard channel is a member of a group of standard channels, addressed by the upstream
+/// server’s provided identifier. The group channel is used mainly for efficient job distribution
+/// to multiple standard channels at once. This message associates the specified standard channels
+/// with the group channel.
+#[id(0x26u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SetGroupChannel {
+    /// Identifier of the group where the standard channel belongs.
+    pub group_channel_id: u32,
+    /// A sequence of opened standard channel IDs, for which the group channel is being
+    /// redefined.
+    pub channel_ids: Seq0_64k<u32>,
+}
 
 impl_base_message_conversion!(SetupConnection, false);
 impl_base_message_conversion!(SetupConnectionSuccess, false);
@@ -366,4 +479,8 @@ impl_base_message_conversion!(NewMiningJob, true);
 impl_base_message_conversion!(NewExtendedMiningJob, true);
 impl_base_message_conversion!(SetNewPrevHash, true);
 impl_base_message_conversion!(Reconnect, false);
+impl_base_message_conversion!(SetGroupChannel, false);
 impl_base_message_conversion!(SetTarget, true);
+impl_base_message_conversion!(SetCustomMiningJob, true);
+impl_base_message_conversion!(SetCustomMiningJobSuccess, true);
+impl_base_message_conversion!(SetCustomMiningJobError, true);
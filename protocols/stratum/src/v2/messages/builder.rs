@@ -0,0 +1,382 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Builders for the mining protocol messages. Each message provides a `builder()` method that
+//! returns a builder with one setter per field. Setters of string, byte array and target fields
+//! accept anything that converts to the field type (e.g. `&str` for `Str0_32`, `Vec<u8>` for
+//! `Bytes0_32`, `U256` for `Uint256Bytes`). Conversion errors, missing fields and field values
+//! violating the protocol constraints are reported by `build()`.
+//!
+//! ```
+//! use ii_stratum::v2::{messages::NewMiningJob, types::Uint256Bytes};
+//!
+//! let job = NewMiningJob::builder()
+//!     .channel_id(1)
+//!     .job_id(2)
+//!     .future_job(false)
+//!     .version(0x2000_0000)
+//!     .merkle_root(Uint256Bytes([0xaa; 32]))
+//!     .build()
+//!     .expect("BUG: cannot build job");
+//! assert_eq!(job.job_id, 2);
+//! ```
+
+use std::convert::TryInto;
+
+use super::*;
+use crate::v2::error;
+
+/// Generates a builder for `message`. Fields listed in the first block are set directly, fields
+/// in the `try` block are converted into the field type. An optional `validate` function checks
+/// the assembled message.
+macro_rules! impl_message_builder {
+    (
+        $message:ident, $builder:ident {
+            $($field:ident: $ty:ty),* $(,)?
+        }
+        $(try {
+            $($try_field:ident: $try_ty:ty),* $(,)?
+        })?
+        $(validate $validate:path)?
+    ) => {
+        #[doc = concat!("Builder of [`", stringify!($message), "`] message")]
+        #[derive(Default, Debug)]
+        pub struct $builder {
+            $($field: Option<$ty>,)*
+            $($($try_field: Option<$try_ty>,)*)?
+            /// First conversion error encountered by the setters
+            error: Option<Error>,
+        }
+
+        impl $builder {
+            $(
+                pub fn $field(mut self, value: $ty) -> Self {
+                    self.$field = Some(value);
+                    self
+                }
+            )*
+
+            $($(
+                pub fn $try_field<T>(mut self, value: T) -> Self
+                where
+                    T: TryInto<$try_ty>,
+                    Error: From<T::Error>,
+                {
+                    match value.try_into() {
+                        Ok(value) => self.$try_field = Some(value),
+                        Err(e) => {
+                            self.error.get_or_insert(e.into());
+                        }
+                    }
+                    self
+                }
+            )*)?
+
+            /// Assembles the message, fails when any field is missing or invalid
+            pub fn build(self) -> Result<$message> {
+                if let Some(e) = self.error {
+                    return Err(e);
+                }
+                let message = $message {
+                    $($field: self.$field.ok_or_else(|| missing_field(stringify!($field)))?,)*
+                    $($($try_field: self
+                        .$try_field
+                        .ok_or_else(|| missing_field(stringify!($try_field)))?,)*)?
+                };
+                $($validate(&message)?;)?
+                Ok(message)
+            }
+        }
+
+        impl $message {
+            pub fn builder() -> $builder {
+                $builder::default()
+            }
+        }
+    };
+}
+
+fn missing_field(field: &str) -> Error {
+    error::Error::MissingField(field.to_string()).into()
+}
+
+/// Zero target would make it impossible to submit any share
+fn check_target(field: &str, target: &Uint256Bytes) -> Result<()> {
+    if target.0.iter().all(|b| *b == 0) {
+        Err(error::Error::InvalidFieldValue(field.to_string(), "zero target".to_string()).into())
+    } else {
+        Ok(())
+    }
+}
+
+fn check_hashrate(field: &str, hashrate: f32) -> Result<()> {
+    if hashrate.is_finite() && hashrate >= 0.0 {
+        Ok(())
+    } else {
+        Err(error::Error::InvalidFieldValue(field.to_string(), hashrate.to_string()).into())
+    }
+}
+
+fn validate_open_standard_mining_channel(msg: &OpenStandardMiningChannel) -> Result<()> {
+    check_hashrate("nominal_hashrate", msg.nominal_hashrate)?;
+    check_target("max_target", &msg.max_target)
+}
+
+fn validate_open_extended_mining_channel(msg: &OpenExtendedMiningChannel) -> Result<()> {
+    check_hashrate("nominal_hashrate", msg.nominal_hashrate)?;
+    check_target("max_target", &msg.max_target)
+}
+
+fn validate_open_standard_mining_channel_success(
+    msg: &OpenStandardMiningChannelSuccess,
+) -> Result<()> {
+    check_target("target", &msg.target)
+}
+
+fn validate_open_extended_mining_channel_success(
+    msg: &OpenExtendedMiningChannelSuccess,
+) -> Result<()> {
+    check_target("target", &msg.target)
+}
+
+fn validate_update_channel(msg: &UpdateChannel) -> Result<()> {
+    check_hashrate("nominal_hash_rate", msg.nominal_hash_rate)?;
+    check_target("maximum_target", &msg.maximum_target)
+}
+
+fn validate_set_target(msg: &SetTarget) -> Result<()> {
+    check_target("max_target", &msg.max_target)
+}
+
+impl_message_builder!(SetupConnection, SetupConnectionBuilder {
+    protocol: SubProtocol,
+    min_version: u16,
+    max_version: u16,
+    flags: u32,
+    endpoint_port: u16,
+    device: DeviceInfo,
+} try {
+    endpoint_host: Str0_255,
+});
+
+impl_message_builder!(
+    SetupConnectionSuccess,
+    SetupConnectionSuccessBuilder {
+        used_version: u16,
+        flags: u32,
+    }
+);
+
+impl_message_builder!(SetupConnectionError, SetupConnectionErrorBuilder {
+    flags: u32,
+} try {
+    code: Str0_255,
+});
+
+impl_message_builder!(
+    ChannelEndpointChanged,
+    ChannelEndpointChangedBuilder { channel_id: u32 }
+);
+
+impl_message_builder!(OpenStandardMiningChannel, OpenStandardMiningChannelBuilder {
+    req_id: u32,
+    nominal_hashrate: f32,
+} try {
+    user: Str0_255,
+    max_target: Uint256Bytes,
+} validate validate_open_standard_mining_channel);
+
+impl_message_builder!(OpenExtendedMiningChannel, OpenExtendedMiningChannelBuilder {
+    req_id: u32,
+    nominal_hashrate: f32,
+    min_extranonce_size: u16,
+} try {
+    user: Str0_255,
+    max_target: Uint256Bytes,
+} validate validate_open_extended_mining_channel);
+
+impl_message_builder!(OpenStandardMiningChannelSuccess, OpenStandardMiningChannelSuccessBuilder {
+    req_id: u32,
+    channel_id: u32,
+    group_channel_id: u32,
+} try {
+    target: Uint256Bytes,
+    extranonce_prefix: Bytes0_32,
+} validate validate_open_standard_mining_channel_success);
+
+impl_message_builder!(OpenExtendedMiningChannelSuccess, OpenExtendedMiningChannelSuccessBuilder {
+    request_id: u32,
+    channel_id: u32,
+    extranonce_size: u16,
+} try {
+    target: Uint256Bytes,
+    extranonce_prefix: Bytes0_32,
+} validate validate_open_extended_mining_channel_success);
+
+impl_message_builder!(OpenMiningChannelError, OpenMiningChannelErrorBuilder {
+    req_id: u32,
+} try {
+    code: Str0_32,
+});
+
+impl_message_builder!(UpdateChannel, UpdateChannelBuilder {
+    channel_id: u32,
+    nominal_hash_rate: f32,
+} try {
+    maximum_target: Uint256Bytes,
+} validate validate_update_channel);
+
+impl_message_builder!(UpdateChannelError, UpdateChannelErrorBuilder {
+    channel_id: u32,
+} try {
+    error_code: Str0_32,
+});
+
+impl_message_builder!(CloseChannel, CloseChannelBuilder {
+    channel_id: u32,
+} try {
+    reason_code: Str0_32,
+});
+
+impl_message_builder!(
+    SubmitSharesStandard,
+    SubmitSharesStandardBuilder {
+        channel_id: u32,
+        seq_num: u32,
+        job_id: u32,
+        nonce: u32,
+        ntime: u32,
+        version: u32,
+    }
+);
+
+impl_message_builder!(SubmitSharesExtended, SubmitSharesExtendedBuilder {
+    channel_id: u32,
+    seq_num: u32,
+    job_id: u32,
+    nonce: u32,
+    ntime: u32,
+    version: u32,
+} try {
+    extranonce: Bytes0_32,
+});
+
+impl_message_builder!(
+    SubmitSharesSuccess,
+    SubmitSharesSuccessBuilder {
+        channel_id: u32,
+        last_seq_num: u32,
+        new_submits_accepted_count: u32,
+        new_shares_sum: u32,
+    }
+);
+
+impl_message_builder!(SubmitSharesError, SubmitSharesErrorBuilder {
+    channel_id: u32,
+    seq_num: u32,
+} try {
+    code: Str0_32,
+});
+
+impl_message_builder!(NewMiningJob, NewMiningJobBuilder {
+    channel_id: u32,
+    job_id: u32,
+    future_job: bool,
+    version: u32,
+} try {
+    merkle_root: Uint256Bytes,
+});
+
+impl_message_builder!(NewExtendedMiningJob, NewExtendedMiningJobBuilder {
+    channel_id: u32,
+    job_id: u32,
+    future_job: bool,
+    version: u32,
+    version_rolling_allowed: bool,
+} try {
+    merkle_path: Seq0_255<Uint256Bytes>,
+    coinbase_tx_prefix: Bytes0_64k,
+    coinbase_tx_suffix: Bytes0_64k,
+});
+
+impl_message_builder!(SetNewPrevHash, SetNewPrevHashBuilder {
+    channel_id: u32,
+    job_id: u32,
+    min_ntime: u32,
+    nbits: u32,
+} try {
+    prev_hash: Uint256Bytes,
+});
+
+impl_message_builder!(SetCustomMiningJob, SetCustomMiningJobBuilder {
+    channel_id: u32,
+    request_id: u32,
+    version: u32,
+    min_ntime: u32,
+    nbits: u32,
+    coinbase_tx_version: u32,
+    coinbase_tx_input_n_sequence: u32,
+    coinbase_tx_value_remaining: u64,
+    coinbase_tx_locktime: u32,
+    extranonce_size: u16,
+    future_job: bool,
+} try {
+    mining_job_token: Bytes0_255,
+    prev_hash: Uint256Bytes,
+    coinbase_prefix: Bytes0_255,
+    coinbase_tx_outputs: Bytes0_64k,
+    merkle_path: Seq0_255<Uint256Bytes>,
+});
+
+impl_message_builder!(SetCustomMiningJobSuccess, SetCustomMiningJobSuccessBuilder {
+    channel_id: u32,
+    request_id: u32,
+    job_id: u32,
+} try {
+    coinbase_tx_prefix: Bytes0_64k,
+    coinbase_tx_suffix: Bytes0_64k,
+});
+
+impl_message_builder!(SetCustomMiningJobError, SetCustomMiningJobErrorBuilder {
+    channel_id: u32,
+    request_id: u32,
+} try {
+    error_code: Str0_255,
+});
+
+impl_message_builder!(SetTarget, SetTargetBuilder {
+    channel_id: u32,
+} try {
+    max_target: Uint256Bytes,
+} validate validate_set_target);
+
+impl_message_builder!(Reconnect, ReconnectBuilder {
+    new_port: u16,
+} try {
+    new_host: Str0_255,
+});
+
+impl_message_builder!(SetGroupChannel, SetGroupChannelBuilder {
+    group_channel_id: u32,
+} try {
+    channel_ids: Seq0_64k<u32>,
+});
@@ -0,0 +1,344 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Job Negotiation protocol messages. The protocol is spoken between a Job Negotiator and the
+//! pool on a dedicated connection that has been set up with [`PROTOCOL`] in
+//! `SetupConnection.protocol`. Messages are transferred in the base extension and their message
+//! types don't overlap with the mining protocol.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+use crate::error::{Error, Result};
+use crate::v2::serialization;
+use crate::v2::{error, extensions, framing, types::*, Protocol};
+use crate::AnyPayload;
+
+use ii_unvariant::{id, Id};
+
+/// Value of `SetupConnection.protocol` that identifies the Job Negotiation protocol
+pub const PROTOCOL: SubProtocol = SubProtocol::JobNegotiation;
+
+/// Extension that carries the Job Negotiation protocol messages
+pub const EXTENSION_TYPE: framing::ExtType = extensions::BASE;
+
+/// Generates conversion for job negotiation protocol messages
+macro_rules! impl_job_negotiation_message_conversion {
+    ($message:tt) => {
+        impl_message_conversion!(EXTENSION_TYPE, $message, false);
+    };
+}
+
+/// A request to get an identifier for a future-submitted mining job. Rate limited to a rather
+/// slow rate and only available on connections where this has been negotiated.
+#[id(0x50u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AllocateMiningJobToken {
+    /// Unconstrained sequence of bytes. Whatever is needed by the pool to identify/authenticate
+    /// the client. Additional restrictions can be imposed by the pool.
+    pub user_identifier: Str0_255,
+    /// Unique identifier for pairing the response.
+    pub request_id: u32,
+}
+
+/// The Server MUST NOT change the value of `coinbase_output_max_additional_size` in
+/// [`AllocateMiningJobTokenSuccess`] messages unless required for changes to the pool’s
+/// configuration.
+#[id(0x51u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct AllocateMiningJobTokenSuccess {
+    /// Unique identifier for pairing the response.
+    pub request_id: u32,
+    /// Token that makes the client eligible for committing a mining job for approval/transaction
+    /// negotiation or for identifying custom mining job on mining connection.
+    pub mining_job_token: Bytes0_255,
+    /// The maximum additional serialized bytes which the pool will add in coinbase transaction
+    /// outputs.
+    pub coinbase_output_max_additional_size: u32,
+    /// Bitcoin transaction outputs added by the pool.
+    pub coinbase_tx_outputs: Bytes0_64k,
+    /// If true, the mining_job_token can be used immediately on a mining connection in the
+    /// SetCustomMiningJob message, even before CommitMiningJob and CommitMiningJobSuccess
+    /// messages have been sent and received. If false, Job Negotiator MUST use this token for
+    /// CommitMiningJob only.
+    pub async_mining_allowed: bool,
+}
+
+/// A request sent by the Job Negotiator that proposes a selected set of transactions to the
+/// upstream (pool) node.
+#[id(0x57u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct CommitMiningJob {
+    /// Unique identifier for pairing the response.
+    pub request_id: u32,
+    /// Previously reserved mining job token received by AllocateMiningJobTokenSuccess.
+    pub mining_job_token: Bytes0_255,
+    /// Version header field. To be later modified by BIP320-consensus-compatible version rolling.
+    pub version: u32,
+    /// The coinbase transaction nVersion field.
+    pub coinbase_tx_version: u32,
+    /// Up to 8 bytes (not including the length byte) which are to be placed at the beginning of
+    /// the coinbase field in the coinbase transaction.
+    pub coinbase_prefix: Bytes0_255,
+    /// The coinbase transaction input’s nSequence field.
+    pub coinbase_tx_input_n_sequence: u32,
+    /// The value, in satoshis, available for spending in coinbase outputs added by the client.
+    /// Includes both transaction fees and block subsidy.
+    pub coinbase_tx_value_remaining: u64,
+    /// Bitcoin transaction outputs to be included as the last outputs in the coinbase
+    /// transaction.
+    pub coinbase_tx_outputs: Bytes0_64k,
+    /// The locktime field in the coinbase transaction.
+    pub coinbase_tx_locktime: u32,
+    /// Extranonce size requested to be always available for the mining channel when this job is
+    /// used on a mining connection.
+    pub min_extranonce_size: u16,
+    /// A unique nonce used to ensure `tx_short_hash_list` collisions are uncorrelated across the
+    /// network.
+    pub tx_short_hash_nonce: u64,
+    /// Sequence of SipHash-2-4(SHA256(transaction_data), tx_short_hash_nonce)) upper 6 bytes.
+    pub tx_short_hash_list: Seq0_64k<ShortTxId>,
+    /// Hash of the full sequence of SHA256(transaction_data) contained in the
+    /// `tx_short_hash_list`.
+    pub tx_hash_list_hash: Uint256Bytes,
+    /// Extra data which the Pool may require to validate the work (as defined in the Template
+    /// Distribution Protocol).
+    pub excess_data: Bytes0_64k,
+}
+
+/// Response to [`CommitMiningJob`] that accepts the job
+#[id(0x58u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct CommitMiningJobSuccess {
+    /// Identifier of the original request.
+    pub request_id: u32,
+    /// Unique identifier provided by the pool of the job that the Job Negotiator has negotiated
+    /// with the pool. See SetCustomMiningJob message.
+    pub new_mining_job_token: Bytes0_255,
+}
+
+/// Response to [`CommitMiningJob`] that rejects the job
+#[id(0x59u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct CommitMiningJobError {
+    /// Identifier of the original request.
+    pub request_id: u32,
+    /// Reason why the job has been rejected.
+    pub error_code: Str0_255,
+    /// Optional data providing further details to given error.
+    pub error_details: Bytes0_64k,
+}
+
+/// Sent by the Server in response to a [`CommitMiningJob`] message indicating it detected a
+/// collision in the `tx_short_hash_list`, or was unable to reconstruct the `tx_hash_list_hash`.
+#[id(0x53u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct IdentifyTransactions {
+    /// Unique identifier for the pairing response to the CommitMiningJob message.
+    pub request_id: u32,
+}
+
+/// Sent by the Job Negotiator in response to an [`IdentifyTransactions`] message to provide the
+/// full set of transaction data hashes.
+#[id(0x54u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct IdentifyTransactionsSuccess {
+    /// Unique identifier for the pairing response to the CommitMiningJob/IdentifyTransactions
+    /// message.
+    pub request_id: u32,
+    /// The full list of transaction data hashes used to build the mining job in the
+    /// corresponding CommitMiningJob message.
+    pub tx_data_hashes: Seq0_64k<Uint256Bytes>,
+}
+
+/// Sent by the server to request the full transaction data of transactions it couldn't
+/// reconstruct from the `tx_short_hash_list` of a [`CommitMiningJob`].
+#[id(0x55u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ProvideMissingTransactions {
+    /// Identifier of the original CommitMiningJob request.
+    pub request_id: u32,
+    /// A list of unrecognized transactions that need to be supplied by the Job Negotiator in
+    /// full. They are specified by their position in the original CommitMiningJob message,
+    /// 0-indexed not including the coinbase transaction transaction.
+    pub unknown_tx_position_list: Seq0_64k<u16>,
+}
+
+/// Response to [`ProvideMissingTransactions`] with the full data of the requested transactions
+#[id(0x56u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ProvideMissingTransactionsSuccess {
+    /// Identifier of the original CommitMiningJob request.
+    pub request_id: u32,
+    /// List of full transactions as requested by ProvideMissingTransactions, in the order they
+    /// were requested in ProvideMissingTransactions.
+    pub transaction_list: Seq0_64k<Bytes0_16M>,
+}
+
+impl_job_negotiation_message_conversion!(AllocateMiningJobToken);
+impl_job_negotiation_message_conversion!(AllocateMiningJobTokenSuccess);
+impl_job_negotiation_message_conversion!(CommitMiningJob);
+impl_job_negotiation_message_conversion!(CommitMiningJobSuccess);
+impl_job_negotiation_message_conversion!(CommitMiningJobError);
+impl_job_negotiation_message_conversion!(IdentifyTransactions);
+impl_job_negotiation_message_conversion!(IdentifyTransactionsSuccess);
+impl_job_negotiation_message_conversion!(ProvideMissingTransactions);
+impl_job_negotiation_message_conversion!(ProvideMissingTransactionsSuccess);
+
+/// Builds the error reported by default implementations of [`Handler`] methods
+fn unhandled_message(message: &str) -> Error {
+    error::Error::UnknownMessage(format!("Unhandled job negotiation message {}", message)).into()
+}
+
+/// Handler of Job Negotiation protocol messages. Both sides of the protocol implement only the
+/// messages they receive, anything else is reported as an unknown message.
+#[async_trait]
+pub trait Handler: Send {
+    async fn handle_allocate_mining_job_token(
+        &mut self,
+        _msg: AllocateMiningJobToken,
+    ) -> Result<()> {
+        Err(unhandled_message("AllocateMiningJobToken"))
+    }
+
+    async fn handle_allocate_mining_job_token_success(
+        &mut self,
+        _msg: AllocateMiningJobTokenSuccess,
+    ) -> Result<()> {
+        Err(unhandled_message("AllocateMiningJobTokenSuccess"))
+    }
+
+    async fn handle_commit_mining_job(&mut self, _msg: CommitMiningJob) -> Result<()> {
+        Err(unhandled_message("CommitMiningJob"))
+    }
+
+    async fn handle_commit_mining_job_success(
+        &mut self,
+        _msg: CommitMiningJobSuccess,
+    ) -> Result<()> {
+        Err(unhandled_message("CommitMiningJobSuccess"))
+    }
+
+    async fn handle_commit_mining_job_error(&mut self, _msg: CommitMiningJobError) -> Result<()> {
+        Err(unhandled_message("CommitMiningJobError"))
+    }
+
+    async fn handle_identify_transactions(&mut self, _msg: IdentifyTransactions) -> Result<()> {
+        Err(unhandled_message("IdentifyTransactions"))
+    }
+
+    async fn handle_identify_transactions_success(
+        &mut self,
+        _msg: IdentifyTransactionsSuccess,
+    ) -> Result<()> {
+        Err(unhandled_message("IdentifyTransactionsSuccess"))
+    }
+
+    async fn handle_provide_missing_transactions(
+        &mut self,
+        _msg: ProvideMissingTransactions,
+    ) -> Result<()> {
+        Err(unhandled_message("ProvideMissingTransactions"))
+    }
+
+    async fn handle_provide_missing_transactions_success(
+        &mut self,
+        _msg: ProvideMissingTransactionsSuccess,
+    ) -> Result<()> {
+        Err(unhandled_message("ProvideMissingTransactionsSuccess"))
+    }
+
+    /// Deserializes the frame and dispatches it to the corresponding handler method
+    async fn handle_frame(&mut self, frame: framing::Frame) -> Result<()> {
+        if frame.header.extension_type != EXTENSION_TYPE {
+            return Err(error::Error::UnknownMessage(format!(
+                "Unexpected extension type {:#x} for job negotiation protocol",
+                frame.header.extension_type
+            ))
+            .into());
+        }
+        match frame.header.msg_type {
+            AllocateMiningJobToken::ID => {
+                self.handle_allocate_mining_job_token(AllocateMiningJobToken::try_from(frame)?)
+                    .await
+            }
+            AllocateMiningJobTokenSuccess::ID => {
+                self.handle_allocate_mining_job_token_success(
+                    AllocateMiningJobTokenSuccess::try_from(frame)?,
+                )
+                .await
+            }
+            CommitMiningJob::ID => {
+                self.handle_commit_mining_job(CommitMiningJob::try_from(frame)?)
+                    .await
+            }
+            CommitMiningJobSuccess::ID => {
+                self.handle_commit_mining_job_success(CommitMiningJobSuccess::try_from(frame)?)
+                    .await
+            }
+            CommitMiningJobError::ID => {
+                self.handle_commit_mining_job_error(CommitMiningJobError::try_from(frame)?)
+                    .await
+            }
+            IdentifyTransactions::ID => {
+                self.handle_identify_transactions(IdentifyTransactions::try_from(frame)?)
+                    .await
+            }
+            IdentifyTransactionsSuccess::ID => {
+                self.handle_identify_transactions_success(IdentifyTransactionsSuccess::try_from(
+                    frame,
+                )?)
+                .await
+            }
+            ProvideMissingTransactions::ID => {
+                self.handle_provide_missing_transactions(ProvideMissingTransactions::try_from(
+                    frame,
+                )?)
+                .await
+            }
+            ProvideMissingTransactionsSuccess::ID => {
+                self.handle_provide_missing_transactions_success(
+                    ProvideMissingTransactionsSuccess::try_from(frame)?,
+                )
+                .await
+            }
+            msg_type => Err(error::Error::UnknownMessage(format!(
+                "Unknown job negotiation message type {:#x}",
+                msg_type
+            ))
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
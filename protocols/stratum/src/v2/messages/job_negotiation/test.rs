@@ -0,0 +1,236 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use bytes::BufMut;
+use std::convert::TryInto;
+use std::fmt::Debug;
+
+use super::*;
+
+fn build_allocate_mining_job_token() -> AllocateMiningJobToken {
+    AllocateMiningJobToken {
+        user_identifier: Str0_255::try_from("braiins.worker0").expect("BUG: cannot build string"),
+        request_id: 1,
+    }
+}
+
+fn build_allocate_mining_job_token_success() -> AllocateMiningJobTokenSuccess {
+    AllocateMiningJobTokenSuccess {
+        request_id: 1,
+        mining_job_token: Bytes0_255::try_from(vec![0xaa, 0xbb, 0xcc])
+            .expect("BUG: cannot build token"),
+        coinbase_output_max_additional_size: 100,
+        coinbase_tx_outputs: Bytes0_64k::try_from(vec![0x01; 43])
+            .expect("BUG: cannot build outputs"),
+        async_mining_allowed: true,
+    }
+}
+
+fn build_commit_mining_job() -> CommitMiningJob {
+    CommitMiningJob {
+        request_id: 2,
+        mining_job_token: Bytes0_255::try_from(vec![0xaa, 0xbb, 0xcc])
+            .expect("BUG: cannot build token"),
+        version: 0x2000_0000,
+        coinbase_tx_version: 2,
+        coinbase_prefix: Bytes0_255::try_from(vec![0x03, 0x10, 0x20, 0x30])
+            .expect("BUG: cannot build prefix"),
+        coinbase_tx_input_n_sequence: 0xffff_ffff,
+        coinbase_tx_value_remaining: 625_000_000,
+        coinbase_tx_outputs: Bytes0_64k::try_from(vec![0x02; 43])
+            .expect("BUG: cannot build outputs"),
+        coinbase_tx_locktime: 0,
+        min_extranonce_size: 8,
+        tx_short_hash_nonce: 0x0102_0304_0506_0708,
+        tx_short_hash_list: Seq0_64k::try_from(vec![
+            ShortTxId([1, 2, 3, 4, 5, 6]),
+            ShortTxId([7, 8, 9, 10, 11, 12]),
+        ])
+        .expect("BUG: cannot build short hash list"),
+        tx_hash_list_hash: Uint256Bytes([0x11; 32]),
+        excess_data: Bytes0_64k::new(),
+    }
+}
+
+fn build_commit_mining_job_success() -> CommitMiningJobSuccess {
+    CommitMiningJobSuccess {
+        request_id: 2,
+        new_mining_job_token: Bytes0_255::try_from(vec![0xdd, 0xee])
+            .expect("BUG: cannot build token"),
+    }
+}
+
+fn build_commit_mining_job_error() -> CommitMiningJobError {
+    CommitMiningJobError {
+        request_id: 2,
+        error_code: Str0_255::try_from("invalid-mining-job-token")
+            .expect("BUG: cannot build string"),
+        error_details: Bytes0_64k::new(),
+    }
+}
+
+fn build_identify_transactions() -> IdentifyTransactions {
+    IdentifyTransactions { request_id: 2 }
+}
+
+fn build_identify_transactions_success() -> IdentifyTransactionsSuccess {
+    IdentifyTransactionsSuccess {
+        request_id: 2,
+        tx_data_hashes: Seq0_64k::try_from(vec![
+            Uint256Bytes([0x22; 32]),
+            Uint256Bytes([0x33; 32]),
+        ])
+        .expect("BUG: cannot build hash list"),
+    }
+}
+
+fn build_provide_missing_transactions() -> ProvideMissingTransactions {
+    ProvideMissingTransactions {
+        request_id: 2,
+        unknown_tx_position_list: Seq0_64k::try_from(vec![0, 5, 1000])
+            .expect("BUG: cannot build position list"),
+    }
+}
+
+fn build_provide_missing_transactions_success() -> ProvideMissingTransactionsSuccess {
+    ProvideMissingTransactionsSuccess {
+        request_id: 2,
+        transaction_list: Seq0_64k::try_from(vec![
+            Bytes0_16M::try_from(vec![0x44; 250]).expect("BUG: cannot build transaction"),
+            Bytes0_16M::try_from(vec![0x55; 70000]).expect("BUG: cannot build transaction"),
+        ])
+        .expect("BUG: cannot build transaction list"),
+    }
+}
+
+/// Serializes `message` and verifies that deserialization yields the same message
+fn check_serialization_round_trip<M>(message: M)
+where
+    M: AnyPayload<Protocol> + for<'a> TryFrom<&'a [u8], Error = Error> + PartialEq + Debug,
+{
+    let mut writer = bytes::BytesMut::new().writer();
+    message
+        .serialize_to_writer(&mut writer)
+        .expect("BUG: Cannot serialize message");
+    let serialized_message = writer.into_inner();
+
+    let deserialized = M::try_from(&serialized_message[..]).expect("BUG: Deserialization failed");
+    assert_eq!(deserialized, message, "Deserialization is not correct");
+}
+
+#[test]
+fn test_job_negotiation_round_trip() {
+    check_serialization_round_trip(build_allocate_mining_job_token());
+    check_serialization_round_trip(build_allocate_mining_job_token_success());
+    check_serialization_round_trip(build_commit_mining_job());
+    check_serialization_round_trip(build_commit_mining_job_success());
+    check_serialization_round_trip(build_commit_mining_job_error());
+    check_serialization_round_trip(build_identify_transactions());
+    check_serialization_round_trip(build_identify_transactions_success());
+    check_serialization_round_trip(build_provide_missing_transactions());
+    check_serialization_round_trip(build_provide_missing_transactions_success());
+}
+
+#[test]
+fn test_short_tx_id_serialization() {
+    let mut writer = bytes::BytesMut::new().writer();
+    build_commit_mining_job()
+        .serialize_to_writer(&mut writer)
+        .expect("BUG: Cannot serialize message");
+    let serialized_message = writer.into_inner();
+
+    // 2 bytes of length prefix followed by 2 short ids and the 32 byte list hash
+    let expected_tail = [
+        &[2u8, 0][..],
+        &[1, 2, 3, 4, 5, 6],
+        &[7, 8, 9, 10, 11, 12],
+        &[0x11; 32],
+        // empty excess_data
+        &[0, 0],
+    ]
+    .concat();
+    assert!(
+        serialized_message.ends_with(&expected_tail),
+        "Unexpected serialization of short transaction ids"
+    );
+}
+
+/// Pool side handler that collects the received requests
+#[derive(Default)]
+struct PoolHandler {
+    allocate_requests: Vec<AllocateMiningJobToken>,
+    commits: Vec<CommitMiningJob>,
+}
+
+#[async_trait]
+impl Handler for PoolHandler {
+    async fn handle_allocate_mining_job_token(
+        &mut self,
+        msg: AllocateMiningJobToken,
+    ) -> Result<()> {
+        self.allocate_requests.push(msg);
+        Ok(())
+    }
+
+    async fn handle_commit_mining_job(&mut self, msg: CommitMiningJob) -> Result<()> {
+        self.commits.push(msg);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_handler_dispatch() {
+    let mut handler = PoolHandler::default();
+
+    let frame: framing::Frame = build_allocate_mining_job_token()
+        .try_into()
+        .expect("BUG: Cannot create test frame");
+    assert_eq!(frame.header.msg_type, 0x50);
+    assert!(!frame.header.is_channel_message);
+    handler
+        .handle_frame(frame)
+        .await
+        .expect("BUG: V2 frame handling failed");
+
+    let frame: framing::Frame = build_commit_mining_job()
+        .try_into()
+        .expect("BUG: Cannot create test frame");
+    handler
+        .handle_frame(frame)
+        .await
+        .expect("BUG: V2 frame handling failed");
+
+    assert_eq!(
+        handler.allocate_requests,
+        vec![build_allocate_mining_job_token()]
+    );
+    assert_eq!(handler.commits, vec![build_commit_mining_job()]);
+
+    // Messages sent by the pool are not implemented by the pool side handler
+    let frame: framing::Frame = build_commit_mining_job_success()
+        .try_into()
+        .expect("BUG: Cannot create test frame");
+    handler
+        .handle_frame(frame)
+        .await
+        .expect_err("BUG: Unhandled message accepted");
+}
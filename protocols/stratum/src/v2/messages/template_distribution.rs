@@ -0,0 +1,301 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Template Distribution protocol messages. The protocol is used to receive block templates from
+//! a template provider (e.g. bitcoind) on a connection that has been set up with [`PROTOCOL`] in
+//! `SetupConnection.protocol`. Messages are transferred in the base extension and their message
+//! types don't overlap with the other subprotocols.
+
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+use crate::error::{Error, Result};
+use crate::v2::serialization;
+use crate::v2::{error, extensions, framing, types::*, Protocol};
+use crate::AnyPayload;
+
+use ii_unvariant::{id, Id};
+
+/// Value of `SetupConnection.protocol` that identifies the Template Distribution protocol
+pub const PROTOCOL: SubProtocol = SubProtocol::TemplateDistribution;
+
+/// Extension that carries the Template Distribution protocol messages
+pub const EXTENSION_TYPE: framing::ExtType = extensions::BASE;
+
+/// Generates conversion for template distribution protocol messages
+macro_rules! impl_template_distribution_message_conversion {
+    ($message:tt) => {
+        impl_message_conversion!(EXTENSION_TYPE, $message, false);
+    };
+}
+
+/// Ultimately, the pool is responsible for adding coinbase transaction outputs for payouts and
+/// other uses, and thus the Template Provider will need to consider this additional block size
+/// when selecting transactions for inclusion in a block (to not create an invalid, oversized
+/// block). Thus, this message is used to indicate that some additional space in the block/coinbase
+/// transaction be reserved for the pool’s use.
+#[id(0x70u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct CoinbaseOutputDataSize {
+    /// The maximum additional serialized bytes which the pool will add in coinbase transaction
+    /// outputs.
+    pub coinbase_output_max_additional_size: u32,
+}
+
+/// The primary template-providing function. Note that the coinbase_tx_outputs bytes will appear
+/// as is at the end of the coinbase transaction.
+#[id(0x71u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct NewTemplate {
+    /// Server’s identification of the template. Strictly increasing, the current UNIX time may be
+    /// used in place of an ID.
+    pub template_id: u64,
+    /// True if the template is intended for future [`SetNewPrevHash`] message sent on the
+    /// channel. If False, the job relates to the last sent [`SetNewPrevHash`] message on the
+    /// channel and the miner should start to work on the job immediately.
+    pub future_template: bool,
+    /// Valid header version field that reflects the current network consensus. The general
+    /// purpose bits (as specified in BIP320) can be freely manipulated by the downstream node.
+    pub version: u32,
+    /// The coinbase transaction nVersion field.
+    pub coinbase_tx_version: u32,
+    /// Up to 8 bytes (not including the length byte) which are to be placed at the beginning of
+    /// the coinbase field in the coinbase transaction.
+    pub coinbase_prefix: Bytes0_255,
+    /// The coinbase transaction input’s nSequence field.
+    pub coinbase_tx_input_sequence: u32,
+    /// The value, in satoshis, available for spending in coinbase outputs added by the client.
+    /// Includes both transaction fees and block subsidy.
+    pub coinbase_tx_value_remaining: u64,
+    /// The number of transaction outputs included in `coinbase_tx_outputs`.
+    pub coinbase_tx_outputs_count: u32,
+    /// Bitcoin transaction outputs to be included as the last outputs in the coinbase
+    /// transaction.
+    pub coinbase_tx_outputs: Bytes0_64k,
+    /// The locktime field in the coinbase transaction.
+    pub coinbase_tx_locktime: u32,
+    /// Merkle path hashes ordered from deepest.
+    pub merkle_path: Seq0_255<Uint256Bytes>,
+}
+
+/// Upon successful validation of a new best block, the server MUST immediately provide this
+/// message. If a [`NewTemplate`] message has previously been sent with the `future_template`
+/// flag set for the same `template_id`, the client SHOULD start to work on it immediately.
+/// Unlike the mining protocol variant of this message, it carries the full header fields
+/// necessary to build a job.
+#[id(0x72u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SetNewPrevHash {
+    /// template_id referenced in a previous [`NewTemplate`] message.
+    pub template_id: u64,
+    /// Previous block’s hash, as it must appear in the next block’s header.
+    pub prev_hash: Uint256Bytes,
+    /// The nTime field in the block header at which the client should start (usually current
+    /// time). This is NOT the minimum valid nTime value.
+    pub header_timestamp: u32,
+    /// Block header field.
+    pub n_bits: u32,
+    /// The maximum double-SHA256 hash value which would represent a valid block. Note that this
+    /// field is fully determined by the value in the `n_bits` field.
+    pub target: Uint256Bytes,
+}
+
+/// A request sent by the Job Negotiator to the Template Provider which requests the set of
+/// transaction data for all transactions (excluding the coinbase transaction) included in a
+/// block, as well as any additional data which may be required by the Pool to validate the work.
+#[id(0x73u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RequestTransactionData {
+    /// The template_id corresponding to a [`NewTemplate`] message.
+    pub template_id: u64,
+}
+
+/// A response to [`RequestTransactionData`] which contains the set of full transaction data and
+/// excess data required for validation.
+#[id(0x74u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RequestTransactionDataSuccess {
+    /// The template_id corresponding to a [`NewTemplate`] message.
+    pub template_id: u64,
+    /// Extra data which the Pool may require to validate the work.
+    pub excess_data: Bytes0_64k,
+    /// The transaction data, serialized as a series of B0_16M byte arrays.
+    pub transaction_list: Seq0_64k<Bytes0_16M>,
+}
+
+/// Response to [`RequestTransactionData`] when the template provider is unable to provide the
+/// transaction data
+#[id(0x75u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct RequestTransactionDataError {
+    /// The template_id corresponding to a [`NewTemplate`] message.
+    pub template_id: u64,
+    /// Reason why no transaction data has been provided (e.g. "template-id-not-found").
+    pub error_code: Str0_255,
+}
+
+/// Upon finding a coinbase transaction/nonce pair which double-SHA256 hashes at or below
+/// [`SetNewPrevHash::target`], the client MUST immediately send this message, and the server
+/// MUST then immediately construct the corresponding full block and attempt to propagate it to
+/// the Bitcoin network.
+#[id(0x76u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SubmitSolution {
+    /// The template_id field as it appeared in [`NewTemplate`].
+    pub template_id: u64,
+    /// The version field in the block header. Bits not defined by BIP320 as additional nonce MUST
+    /// be the same as they appear in the [`NewTemplate`] message, other bits may be set to any
+    /// value.
+    pub version: u32,
+    /// The nTime field in the block header. This MUST be greater than or equal to the
+    /// `header_timestamp` field in the latest [`SetNewPrevHash`] message and lower than or equal
+    /// to that value plus the number of seconds since the receipt of that message.
+    pub header_timestamp: u32,
+    /// The nonce field in the header.
+    pub header_nonce: u32,
+    /// The full serialized coinbase transaction, meeting all the requirements of the
+    /// [`NewTemplate`] message, above.
+    pub coinbase_tx: Bytes0_64k,
+}
+
+impl_template_distribution_message_conversion!(CoinbaseOutputDataSize);
+impl_template_distribution_message_conversion!(NewTemplate);
+impl_template_distribution_message_conversion!(SetNewPrevHash);
+impl_template_distribution_message_conversion!(RequestTransactionData);
+impl_template_distribution_message_conversion!(RequestTransactionDataSuccess);
+impl_template_distribution_message_conversion!(RequestTransactionDataError);
+impl_template_distribution_message_conversion!(SubmitSolution);
+
+/// Builds the error reported by default implementations of [`Handler`] methods
+fn unhandled_message(message: &str) -> Error {
+    error::Error::UnknownMessage(format!(
+        "Unhandled template distribution message {}",
+        message
+    ))
+    .into()
+}
+
+/// Handler of Template Distribution protocol messages. The template provider and its clients
+/// implement only the messages they receive, anything else is reported as an unknown message.
+#[async_trait]
+pub trait Handler: Send {
+    async fn handle_coinbase_output_data_size(
+        &mut self,
+        _msg: CoinbaseOutputDataSize,
+    ) -> Result<()> {
+        Err(unhandled_message("CoinbaseOutputDataSize"))
+    }
+
+    async fn handle_new_template(&mut self, _msg: NewTemplate) -> Result<()> {
+        Err(unhandled_message("NewTemplate"))
+    }
+
+    async fn handle_set_new_prev_hash(&mut self, _msg: SetNewPrevHash) -> Result<()> {
+        Err(unhandled_message("SetNewPrevHash"))
+    }
+
+    async fn handle_request_transaction_data(
+        &mut self,
+        _msg: RequestTransactionData,
+    ) -> Result<()> {
+        Err(unhandled_message("RequestTransactionData"))
+    }
+
+    async fn handle_request_transaction_data_success(
+        &mut self,
+        _msg: RequestTransactionDataSuccess,
+    ) -> Result<()> {
+        Err(unhandled_message("RequestTransactionDataSuccess"))
+    }
+
+    async fn handle_request_transaction_data_error(
+        &mut self,
+        _msg: RequestTransactionDataError,
+    ) -> Result<()> {
+        Err(unhandled_message("RequestTransactionDataError"))
+    }
+
+    async fn handle_submit_solution(&mut self, _msg: SubmitSolution) -> Result<()> {
+        Err(unhandled_message("SubmitSolution"))
+    }
+
+    /// Deserializes the frame and dispatches it to the corresponding handler method
+    async fn handle_frame(&mut self, frame: framing::Frame) -> Result<()> {
+        if frame.header.extension_type != EXTENSION_TYPE {
+            return Err(error::Error::UnknownMessage(format!(
+                "Unexpected extension type {:#x} for template distribution protocol",
+                frame.header.extension_type
+            ))
+            .into());
+        }
+        match frame.header.msg_type {
+            CoinbaseOutputDataSize::ID => {
+                self.handle_coinbase_output_data_size(CoinbaseOutputDataSize::try_from(frame)?)
+                    .await
+            }
+            NewTemplate::ID => {
+                self.handle_new_template(NewTemplate::try_from(frame)?)
+                    .await
+            }
+            SetNewPrevHash::ID => {
+                self.handle_set_new_prev_hash(SetNewPrevHash::try_from(frame)?)
+                    .await
+            }
+            RequestTransactionData::ID => {
+                self.handle_request_transaction_data(RequestTransactionData::try_from(frame)?)
+                    .await
+            }
+            RequestTransactionDataSuccess::ID => {
+                self.handle_request_transaction_data_success(
+                    RequestTransactionDataSuccess::try_from(frame)?,
+                )
+                .await
+            }
+            RequestTransactionDataError::ID => {
+                self.handle_request_transaction_data_error(RequestTransactionDataError::try_from(
+                    frame,
+                )?)
+                .await
+            }
+            SubmitSolution::ID => {
+                self.handle_submit_solution(SubmitSolution::try_from(frame)?)
+                    .await
+            }
+            msg_type => Err(error::Error::UnknownMessage(format!(
+                "Unknown template distribution message type {:#x}",
+                msg_type
+            ))
+            .into()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
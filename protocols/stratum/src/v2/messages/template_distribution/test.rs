@@ -0,0 +1,199 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+use bytes::BufMut;
+use std::convert::TryInto;
+use std::fmt::Debug;
+
+use super::*;
+
+const TEMPLATE_ID: u64 = 1_600_000_000;
+
+fn build_coinbase_output_data_size() -> CoinbaseOutputDataSize {
+    CoinbaseOutputDataSize {
+        coinbase_output_max_additional_size: 100,
+    }
+}
+
+fn build_new_template() -> NewTemplate {
+    NewTemplate {
+        template_id: TEMPLATE_ID,
+        future_template: true,
+        version: 0x2000_0000,
+        coinbase_tx_version: 2,
+        coinbase_prefix: Bytes0_255::try_from(vec![0x03, 0x10, 0x20, 0x30])
+            .expect("BUG: cannot build prefix"),
+        coinbase_tx_input_sequence: 0xffff_ffff,
+        coinbase_tx_value_remaining: 625_000_000,
+        coinbase_tx_outputs_count: 1,
+        coinbase_tx_outputs: Bytes0_64k::try_from(vec![0x01; 43])
+            .expect("BUG: cannot build outputs"),
+        coinbase_tx_locktime: 0,
+        merkle_path: Seq0_255::try_from(vec![Uint256Bytes([0x11; 32]), Uint256Bytes([0x22; 32])])
+            .expect("BUG: cannot build merkle path"),
+    }
+}
+
+fn build_set_new_prev_hash() -> SetNewPrevHash {
+    SetNewPrevHash {
+        template_id: TEMPLATE_ID,
+        prev_hash: Uint256Bytes([0x33; 32]),
+        header_timestamp: 1_600_000_001,
+        n_bits: 0x1703_4219,
+        target: Uint256Bytes([0xff; 32]),
+    }
+}
+
+fn build_request_transaction_data() -> RequestTransactionData {
+    RequestTransactionData {
+        template_id: TEMPLATE_ID,
+    }
+}
+
+fn build_request_transaction_data_success() -> RequestTransactionDataSuccess {
+    RequestTransactionDataSuccess {
+        template_id: TEMPLATE_ID,
+        excess_data: Bytes0_64k::new(),
+        transaction_list: Seq0_64k::try_from(vec![
+            Bytes0_16M::try_from(vec![0x44; 250]).expect("BUG: cannot build transaction"),
+            Bytes0_16M::try_from(vec![0x55; 70000]).expect("BUG: cannot build transaction"),
+        ])
+        .expect("BUG: cannot build transaction list"),
+    }
+}
+
+fn build_request_transaction_data_error() -> RequestTransactionDataError {
+    RequestTransactionDataError {
+        template_id: TEMPLATE_ID,
+        error_code: Str0_255::try_from("template-id-not-found").expect("BUG: cannot build string"),
+    }
+}
+
+fn build_submit_solution() -> SubmitSolution {
+    SubmitSolution {
+        template_id: TEMPLATE_ID,
+        version: 0x2000_0000,
+        header_timestamp: 1_600_000_002,
+        header_nonce: 0xdead_beef,
+        coinbase_tx: Bytes0_64k::try_from(vec![0x66; 120])
+            .expect("BUG: cannot build coinbase transaction"),
+    }
+}
+
+/// Serializes `message` and verifies that deserialization yields the same message
+fn check_serialization_round_trip<M>(message: M)
+where
+    M: AnyPayload<Protocol> + for<'a> TryFrom<&'a [u8], Error = Error> + PartialEq + Debug,
+{
+    let mut writer = bytes::BytesMut::new().writer();
+    message
+        .serialize_to_writer(&mut writer)
+        .expect("BUG: Cannot serialize message");
+    let serialized_message = writer.into_inner();
+
+    let deserialized = M::try_from(&serialized_message[..]).expect("BUG: Deserialization failed");
+    assert_eq!(deserialized, message, "Deserialization is not correct");
+}
+
+#[test]
+fn test_template_distribution_round_trip() {
+    check_serialization_round_trip(build_coinbase_output_data_size());
+    check_serialization_round_trip(build_new_template());
+    check_serialization_round_trip(build_set_new_prev_hash());
+    check_serialization_round_trip(build_request_transaction_data());
+    check_serialization_round_trip(build_request_transaction_data_success());
+    check_serialization_round_trip(build_request_transaction_data_error());
+    check_serialization_round_trip(build_submit_solution());
+}
+
+#[test]
+fn test_serialize_set_new_prev_hash() {
+    let mut writer = bytes::BytesMut::new().writer();
+    build_set_new_prev_hash()
+        .serialize_to_writer(&mut writer)
+        .expect("BUG: Cannot serialize message");
+    let serialized_message = writer.into_inner();
+
+    let expected = [
+        &TEMPLATE_ID.to_le_bytes()[..],
+        &[0x33; 32],
+        &1_600_000_001u32.to_le_bytes(),
+        &0x1703_4219u32.to_le_bytes(),
+        &[0xff; 32],
+    ]
+    .concat();
+    assert_eq!(&serialized_message[..], &expected[..]);
+}
+
+/// Client side handler that keeps track of the most recent template and previous block hash
+#[derive(Default)]
+struct TemplateClient {
+    template: Option<NewTemplate>,
+    prev_hash: Option<SetNewPrevHash>,
+}
+
+#[async_trait]
+impl Handler for TemplateClient {
+    async fn handle_new_template(&mut self, msg: NewTemplate) -> Result<()> {
+        self.template = Some(msg);
+        Ok(())
+    }
+
+    async fn handle_set_new_prev_hash(&mut self, msg: SetNewPrevHash) -> Result<()> {
+        self.prev_hash = Some(msg);
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_handler_dispatch() {
+    let mut handler = TemplateClient::default();
+
+    let frame: framing::Frame = build_new_template()
+        .try_into()
+        .expect("BUG: Cannot create test frame");
+    assert_eq!(frame.header.msg_type, 0x71);
+    handler
+        .handle_frame(frame)
+        .await
+        .expect("BUG: V2 frame handling failed");
+
+    let frame: framing::Frame = build_set_new_prev_hash()
+        .try_into()
+        .expect("BUG: Cannot create test frame");
+    handler
+        .handle_frame(frame)
+        .await
+        .expect("BUG: V2 frame handling failed");
+
+    assert_eq!(handler.template, Some(build_new_template()));
+    assert_eq!(handler.prev_hash, Some(build_set_new_prev_hash()));
+
+    // The client doesn't serve solutions
+    let frame: framing::Frame = build_submit_solution()
+        .try_into()
+        .expect("BUG: Cannot create test frame");
+    handler
+        .handle_frame(frame)
+        .await
+        .expect_err("BUG: Unhandled message accepted");
+}
@@ -21,9 +21,11 @@
 // contact us at opensource@braiins.com.
 
 use bytes::{BufMut, BytesMut};
+use std::fmt::Debug;
 
 use super::*;
 use crate::test_utils::v2::*;
+use crate::v2::serialization::SerializationMode;
 use crate::AnyPayload;
 
 #[test]
@@ -53,3 +55,301 @@ fn test_serialize_setup_connection() {
         serialized_message
     );
 }
+
+#[test]
+fn test_serialize_setup_connection_success() {
+    let message = build_setup_connection_success();
+    let mut writer = bytes::BytesMut::new().writer();
+    message
+        .serialize_to_writer(&mut writer)
+        .expect("BUG: Cannot serialize message");
+    assert_eq!(
+        BytesMut::from(SETUP_CONNECTION_SUCCESS_SERIALIZED),
+        writer.into_inner()
+    );
+
+    let deserialized = SetupConnectionSuccess::try_from(SETUP_CONNECTION_SUCCESS_SERIALIZED)
+        .expect("BUG: Deserialization failed");
+    assert_eq!(deserialized, build_setup_connection_success());
+}
+
+#[test]
+fn test_serialize_setup_connection_json() {
+    let message = build_setup_connection();
+    let mut writer = bytes::BytesMut::new().writer();
+    message
+        .serialize_json_to_writer(&mut writer)
+        .expect("BUG: Cannot serialize message");
+
+    assert_eq!(
+        BytesMut::from(SETUP_CONNECTION_JSON),
+        writer.into_inner()
+    );
+}
+
+#[test]
+fn test_deserialize_setup_connection_json_frame() {
+    let frame = framing::Frame::from_serialized_payload_with_mode(
+        false,
+        extensions::BASE,
+        SetupConnection::ID,
+        BytesMut::from(SETUP_CONNECTION_JSON),
+        SerializationMode::Json,
+    );
+    assert_eq!(frame.serialization_mode(), SerializationMode::Json);
+
+    let deserialized = SetupConnection::try_from(frame).expect("BUG: Deserialization failed");
+    assert_eq!(deserialized, build_setup_connection());
+}
+
+#[test]
+fn test_deserialize_setup_connection_unknown_protocol() {
+    let mut serialized = SETUP_CONNECTION_SERIALIZED.to_vec();
+    serialized[0] = 4;
+
+    SetupConnection::try_from(&serialized[..])
+        .expect_err("BUG: Unknown protocol accepted by deserialization");
+}
+
+#[test]
+fn test_sub_protocol_conversion() {
+    for protocol in [
+        SubProtocol::Mining,
+        SubProtocol::JobNegotiation,
+        SubProtocol::TemplateDistribution,
+        SubProtocol::JobDistribution,
+    ]
+    .iter()
+    {
+        let value = u8::from(*protocol);
+        assert_eq!(
+            SubProtocol::try_from(value).expect("BUG: conversion failed"),
+            *protocol
+        );
+    }
+    match SubProtocol::try_from(4u8) {
+        Err(crate::error::Error::V2(super::super::error::Error::UnknownProtocol(4))) => (),
+        result => panic!("BUG: Unexpected conversion result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_builder() {
+    let job = NewMiningJob::builder()
+        .channel_id(0)
+        .job_id(0)
+        .future_job(true)
+        .version(crate::test_utils::common::MINING_WORK_VERSION)
+        .merkle_root(build_new_mining_job().merkle_root)
+        .build()
+        .expect("BUG: cannot build message");
+    assert_eq!(job, build_new_mining_job());
+
+    let error = SubmitSharesError::builder()
+        .channel_id(1)
+        .seq_num(2)
+        .code(crate::v2::error_codes::SubmitSharesErrorCode::StaleShare)
+        .build()
+        .expect("BUG: cannot build message");
+    assert_eq!(error.code.as_str(), "stale-share");
+}
+
+#[test]
+fn test_builder_missing_field() {
+    match SetTarget::builder().channel_id(1).build() {
+        Err(crate::error::Error::V2(super::super::error::Error::MissingField(field))) => {
+            assert_eq!(field, "max_target")
+        }
+        result => panic!("BUG: Unexpected build result: {:?}", result),
+    }
+}
+
+#[test]
+fn test_builder_invalid_field() {
+    // Code exceeds the 32 character limit of Str0_32
+    SubmitSharesError::builder()
+        .channel_id(1)
+        .seq_num(2)
+        .code("x".repeat(33).as_str())
+        .build()
+        .expect_err("BUG: overlong code accepted");
+
+    match SetTarget::builder()
+        .channel_id(1)
+        .max_target(Uint256Bytes([0; 32]))
+        .build()
+    {
+        Err(crate::error::Error::V2(super::super::error::Error::InvalidFieldValue(field, _))) => {
+            assert_eq!(field, "max_target")
+        }
+        result => panic!("BUG: Unexpected build result: {:?}", result),
+    }
+
+    OpenStandardMiningChannel::builder()
+        .req_id(1)
+        .user("user")
+        .nominal_hashrate(-1.0)
+        .max_target(Uint256Bytes([0xff; 32]))
+        .build()
+        .expect_err("BUG: negative hashrate accepted");
+}
+
+/// Serializes `message` and verifies that deserialization yields the same message
+fn check_serialization_round_trip<M>(message: M)
+where
+    M: AnyPayload<Protocol> + for<'a> TryFrom<&'a [u8], Error = Error> + PartialEq + Debug,
+{
+    let mut writer = bytes::BytesMut::new().writer();
+    message
+        .serialize_to_writer(&mut writer)
+        .expect("BUG: Cannot serialize message");
+    let serialized_message = writer.into_inner();
+
+    let deserialized = M::try_from(&serialized_message[..]).expect("BUG: Deserialization failed");
+    assert_eq!(deserialized, message, "Deserialization is not correct");
+}
+
+#[test]
+fn test_set_custom_mining_job_round_trip() {
+    check_serialization_round_trip(build_set_custom_mining_job());
+    check_serialization_round_trip(build_set_custom_mining_job_success());
+    check_serialization_round_trip(build_set_custom_mining_job_error());
+}
+
+#[test]
+fn test_set_group_channel_round_trip() {
+    check_serialization_round_trip(build_set_group_channel());
+}
+
+#[tokio::test]
+async fn test_set_group_channel_frame() {
+    let frame = framing::Frame::try_from(build_set_group_channel())
+        .expect("BUG: Cannot build frame from message");
+    assert_eq!(
+        frame.header.msg_type,
+        <SetGroupChannel as Id<framing::MsgType>>::ID
+    );
+    assert!(!frame.header.is_channel_message);
+
+    let mut handler = TestCollectorHandler::default();
+    handler.handle_v2(frame).await;
+    assert_eq!(
+        handler
+            .next()
+            .expect("BUG: No message collected")
+            .unwrap_set_group_channel(),
+        build_set_group_channel()
+    );
+}
+
+#[test]
+fn test_open_extended_mining_channel_round_trip() {
+    check_serialization_round_trip(build_open_extended_channel());
+    check_serialization_round_trip(build_open_extended_channel_success());
+}
+
+#[tokio::test]
+async fn test_open_extended_mining_channel_frame() {
+    let frame = framing::Frame::try_from(build_open_extended_channel())
+        .expect("BUG: Cannot build frame from message");
+    assert_eq!(frame.header.msg_type, 0x13);
+    assert!(!frame.header.is_channel_message);
+    let success_frame = framing::Frame::try_from(build_open_extended_channel_success())
+        .expect("BUG: Cannot build frame from message");
+    assert_eq!(success_frame.header.msg_type, 0x14);
+
+    let mut handler = TestCollectorHandler::default();
+    handler.handle_v2(frame).await;
+    handler.handle_v2(success_frame).await;
+    assert_eq!(
+        handler
+            .next()
+            .expect("BUG: No message collected")
+            .unwrap_open_extended_mining_channel(),
+        build_open_extended_channel()
+    );
+    assert_eq!(
+        handler
+            .next()
+            .expect("BUG: No message collected")
+            .unwrap_open_extended_mining_channel_success(),
+        build_open_extended_channel_success()
+    );
+}
+
+#[test]
+fn test_submit_shares_extended_round_trip() {
+    check_serialization_round_trip(build_submit_shares_extended());
+}
+
+/// Byte sequences of a message deserialized from a frame have to refer to the frame buffer
+#[test]
+fn test_submit_shares_extended_zero_copy() {
+    let payload = serialization::to_vec(&build_submit_shares_extended())
+        .expect("BUG: Cannot serialize message");
+    let payload = BytesMut::from(&payload[..]);
+    let payload_range = payload.as_ptr_range();
+    let frame = framing::Frame::from_serialized_payload(true, extensions::BASE, 0x1b, payload);
+
+    let msg = SubmitSharesExtended::try_from(frame).expect("BUG: Cannot deserialize message");
+    assert_eq!(msg, build_submit_shares_extended());
+    assert!(payload_range.contains(&msg.extranonce.as_ptr()));
+}
+
+#[tokio::test]
+async fn test_submit_shares_extended_frame() {
+    let frame = framing::Frame::try_from(build_submit_shares_extended())
+        .expect("BUG: Cannot build frame from message");
+    assert_eq!(frame.header.msg_type, 0x1b);
+    assert!(frame.header.is_channel_message);
+
+    TestIdentityHandler.handle_v2(frame).await;
+}
+
+/// Arbitrary messages have to respect size limits of all their fields, otherwise the fuzzing
+/// targets would report bogus serialization failures
+#[cfg(feature = "arbitrary")]
+#[test]
+fn test_arbitrary_messages_round_trip() {
+    use arbitrary::{Arbitrary, Unstructured};
+
+    fn check<T>(data: &[u8])
+    where
+        T: for<'a> Arbitrary<'a> + Serialize + for<'de> Deserialize<'de>,
+    {
+        let msg = T::arbitrary(&mut Unstructured::new(data))
+            .expect("BUG: Cannot build arbitrary message");
+        let bytes = serialization::to_vec(&msg).expect("BUG: Cannot serialize message");
+        let msg: T = serialization::from_slice(&bytes).expect("BUG: Cannot deserialize message");
+        // Compare serialized forms as arbitrary float fields may be NaN
+        assert_eq!(
+            serialization::to_vec(&msg).expect("BUG: Cannot serialize message"),
+            bytes
+        );
+    }
+
+    // Simple xorshift generator provides deterministic input for the arbitrary instances
+    let mut state = 0x2545_f491_u32;
+    for len in [0, 1, 17, 256, 4096].iter() {
+        let data: Vec<u8> = (0..*len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state as u8
+            })
+            .collect();
+        check::<SetupConnection>(&data);
+        check::<OpenStandardMiningChannel>(&data);
+        check::<OpenExtendedMiningChannelSuccess>(&data);
+        check::<SubmitSharesExtended>(&data);
+        check::<NewExtendedMiningJob>(&data);
+        check::<SetCustomMiningJob>(&data);
+        check::<SetGroupChannel>(&data);
+        check::<job_negotiation::CommitMiningJob>(&data);
+        check::<job_negotiation::ProvideMissingTransactionsSuccess>(&data);
+        check::<template_distribution::NewTemplate>(&data);
+        check::<crate::v2::telemetry::messages::SubmitDeviceStatus>(&data);
+        check::<crate::v2::telemetry::messages::SubmitTelemetryData>(&data);
+    }
+}
@@ -23,12 +23,19 @@
 //! Noise protocol implementation for Stratum V2. This module provides helper objects that process
 //! the selected handshake pattern on initiator as well as on responder and eventually provide a
 //! TransportState of the noise, that will be used for running the AEAD communnication.
+//!
+//! The handshake itself doesn't depend on any I/O - `HandshakeMachine` can be driven over any
+//! transport that delivers whole messages, `Initiator`/`Responder` methods just run it over
+//! a length delimited noise framed stream.
 
+use bitcoin_hashes::{sha256, Hash, HashEngine};
 use bytes::{Bytes, BytesMut};
 use ii_logging::macros::*;
 use snow::{HandshakeState, TransportState};
 use std::convert::TryFrom;
+use std::fmt;
 
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_util::codec::{Encoder, Framed, FramedParts};
 
@@ -36,10 +43,13 @@ use crate::error::{Error, Result};
 use crate::v2;
 
 pub mod codec;
-pub use codec::{Codec, CompoundCodec};
+pub use codec::{ByteCounter, Codec, CompoundCodec};
 
 pub mod auth;
 mod handshake;
+pub mod limiter;
+pub use handshake::{HandshakeAction, HandshakeMachine, HandshakeStage, DEFAULT_HANDSHAKE_TIMEOUT};
+pub use limiter::{HandshakeLimiter, HandshakePermit};
 
 #[macro_use]
 pub mod negotiation;
@@ -76,8 +86,22 @@ impl ii_wire::Framing for Framing {
     type Codec = codec::Codec;
 }
 
-/// Tcp stream that produces/consumes noise frames
-type NoiseFramedTcpStream = Framed<TcpStream, <Framing as ii_wire::Framing>::Codec>;
+/// Stream that produces/consumes noise frames
+type NoiseFramedStream<T = TcpStream> = Framed<T, <Framing as ii_wire::Framing>::Codec>;
+
+/// Provides handshake builder for the `algorithm`, the pre-shared key (if any) is mixed into the
+/// first handshake message
+fn handshake_builder(
+    algorithm: EncryptionAlgorithm,
+    psk: Option<&auth::PreSharedKey>,
+) -> snow::Builder<'_> {
+    match psk {
+        Some(psk) => NoiseParamsBuilder::new_with_psk(algorithm)
+            .get_builder()
+            .psk(0, psk.as_bytes()),
+        None => NoiseParamsBuilder::new(algorithm).get_builder(),
+    }
+}
 
 /// Generates noise specific static keypair specific for the current params
 pub fn generate_keypair() -> Result<StaticKeypair> {
@@ -89,27 +113,94 @@ pub fn generate_keypair() -> Result<StaticKeypair> {
 pub struct Initiator {
     stage: usize,
     handshake_state: Option<HandshakeState>,
+    /// Supported algorithms in order of preference, the Responder chooses the first one it
+    /// supports
     algorithms: Vec<EncryptionAlgorithm>,
-    /// Public key that the Initiatior will use to construct a 'Certificate' on the fly from
-    /// the SignatureNoiseMessage and of the static public key of the `Responder` and will verify
-    /// the authenticity of the static public key of the Responder
-    authority_public_key: ed25519_dalek::PublicKey,
+    /// Algorithm agreed upon during negotiation
+    encryption_algorithm: Option<EncryptionAlgorithm>,
+    /// Deadline for completing the whole handshake
+    handshake_timeout: std::time::Duration,
+    /// Rekeying policy of the resulting transport mode
+    rekey_policy: RekeyPolicy,
+    /// Optional pre-shared key mixed into the handshake
+    psk: Option<auth::PreSharedKey>,
+    /// Public keys of trusted authorities. The Initiatior uses them to construct a 'Certificate'
+    /// on the fly from the SignatureNoiseMessage and the static public key of the `Responder`.
+    /// The static public key of the Responder is authentic if any of the authorities signed it
+    authority_public_keys: Vec<ed25519_dalek::PublicKey>,
 }
 
 impl Initiator {
     pub fn new(
         authority_public_key: ed25519_dalek::PublicKey,
         algorithms: Vec<EncryptionAlgorithm>,
+    ) -> Self {
+        Self::with_authority_public_keys(vec![authority_public_key], algorithms)
+    }
+
+    /// Builds an initiator that accepts the `Responder` certificate signed by any of
+    /// `authority_public_keys` (e.g. during rotation of the authority key)
+    pub fn with_authority_public_keys(
+        authority_public_keys: Vec<ed25519_dalek::PublicKey>,
+        algorithms: Vec<EncryptionAlgorithm>,
     ) -> Self {
         Self {
             stage: 0,
             handshake_state: None,
             algorithms,
-            authority_public_key,
+            encryption_algorithm: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            rekey_policy: RekeyPolicy::default(),
+            psk: None,
+            authority_public_keys,
         }
     }
 
-    pub async fn connect(self, connection: TcpStream) -> Result<v2::Framed> {
+    /// Sets deadline for completing the whole handshake
+    pub fn with_handshake_timeout(mut self, handshake_timeout: std::time::Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Sets rekeying policy of the transport, the counter party has to use the same policy
+    pub fn with_rekey_policy(mut self, rekey_policy: RekeyPolicy) -> Self {
+        self.rekey_policy = rekey_policy;
+        self
+    }
+
+    /// Mixes `psk` into the handshake, the counter party has to use the same key
+    pub fn with_psk(mut self, psk: Option<auth::PreSharedKey>) -> Self {
+        self.psk = psk;
+        self
+    }
+
+    /// Replaces the offered encryption algorithms, the first one being the most preferred.
+    /// The Responder falls back to the next algorithm in the list when it doesn't support
+    /// the preferred one
+    pub fn with_algorithm_preference(mut self, algorithms: Vec<EncryptionAlgorithm>) -> Self {
+        self.algorithms = algorithms;
+        self
+    }
+
+    /// Offers `algorithm` only, the handshake fails when the Responder doesn't support it
+    pub fn with_algorithm_restricted_to(self, algorithm: EncryptionAlgorithm) -> Self {
+        self.with_algorithm_preference(vec![algorithm])
+    }
+
+    /// Algorithms offered to the Responder in order of preference
+    pub fn algorithm_preference(&self) -> &[EncryptionAlgorithm] {
+        &self.algorithms
+    }
+
+    /// Algorithm chosen by the Responder, available once the negotiation is complete
+    pub fn chosen_algorithm(&self) -> Option<EncryptionAlgorithm> {
+        self.encryption_algorithm.clone()
+    }
+
+    pub async fn connect<T>(self, connection: T) -> Result<v2::Framed<T>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
         self.connect_with_codec(connection, |noise_codec| {
             <v2::framing::Framing as ii_wire::Framing>::Codec::new(Some(noise_codec))
         })
@@ -118,16 +209,17 @@ impl Initiator {
 
     /// Connect and run noise handshake and produce a `Framed` that internally
     /// runs a codec provided by `build_codec`
-    pub async fn connect_with_codec<I, F, U>(
+    pub async fn connect_with_codec<T, I, F, U>(
         self,
-        connection: TcpStream,
+        connection: T,
         build_codec: F,
-    ) -> Result<Framed<TcpStream, U>>
+    ) -> Result<Framed<T, U>>
     where
+        T: AsyncRead + AsyncWrite + Unpin,
         F: FnOnce(Codec) -> U,
         U: Encoder<I>,
     {
-        let mut noise_framed_stream = ii_wire::Connection::<Framing>::new(connection).into_inner();
+        let mut noise_framed_stream = Framed::new(connection, Codec::default());
 
         let handshake = handshake::Handshake::new(self);
         let transport_mode = handshake.run(&mut noise_framed_stream).await?;
@@ -135,16 +227,40 @@ impl Initiator {
         Ok(transport_mode.into_framed(noise_framed_stream, build_codec))
     }
 
-    pub async fn connect_with_codec_and_cert<I, F, U>(
+    /// Same as `connect_with_codec()` and additionally provides summary of the handshake
+    pub async fn connect_with_codec_and_summary<T, I, F, U>(
+        self,
+        connection: T,
+        build_codec: F,
+    ) -> Result<(Framed<T, U>, HandshakeSummary)>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(Codec) -> U,
+        U: Encoder<I>,
+    {
+        let mut noise_framed_stream = Framed::new(connection, Codec::default());
+
+        let handshake = handshake::Handshake::new(self);
+        let (transport_mode, summary) =
+            handshake.run_with_summary(&mut noise_framed_stream).await?;
+
+        Ok((
+            transport_mode.into_framed(noise_framed_stream, build_codec),
+            summary,
+        ))
+    }
+
+    pub async fn connect_with_codec_and_cert<T, I, F, U>(
         self,
-        connection: TcpStream,
+        connection: T,
         build_codec: F,
-    ) -> Result<(Framed<TcpStream, U>, auth::Certificate)>
+    ) -> Result<(Framed<T, U>, auth::Certificate)>
     where
+        T: AsyncRead + AsyncWrite + Unpin,
         F: FnOnce(Codec) -> U,
         U: Encoder<I>,
     {
-        let mut noise_framed_stream = ii_wire::Connection::<Framing>::new(connection).into_inner();
+        let mut noise_framed_stream = Framed::new(connection, Codec::default());
 
         let mut handshake = handshake::Handshake::new(self);
         let certificate = handshake
@@ -159,10 +275,8 @@ impl Initiator {
         ))
     }
 
-    /// Verify the signature of the remote static key
-    /// TODO: verify the signature of the remote static public key based on:
-    ///  - remote central authority public key (must be provided to Initiator instance upon
-    ///    creation)
+    /// Verify the signature of the remote static key by any of the trusted authorities and the
+    /// validity period of the resulting certificate
     fn verify_remote_static_key_signature(
         &mut self,
         signature_noise_message: BytesMut,
@@ -177,18 +291,16 @@ impl Initiator {
         let signature_noise_message =
             auth::SignatureNoiseMessage::try_from(&signature_noise_message[..])?;
 
-        let certificate = auth::Certificate::from_noise_message(
-            signature_noise_message,
+        signature_noise_message.verify(
             remote_static_key,
-            self.authority_public_key,
-        );
-        certificate.validate(std::time::SystemTime::now)?;
-
-        Ok(certificate)
+            &self.authority_public_keys,
+            std::time::SystemTime::now(),
+        )
     }
 
     fn build_handshake_state(&mut self, negotiation: EncryptionNegotiation) -> Result<()> {
-        let builder = NoiseParamsBuilder::new(negotiation.chosen_algorithm).get_builder();
+        self.encryption_algorithm = Some(negotiation.chosen_algorithm.clone());
+        let builder = handshake_builder(negotiation.chosen_algorithm, self.psk.as_ref());
         let prologue = v2::serialization::to_vec(&negotiation.prologue)?;
 
         self.handshake_state = Some(
@@ -207,6 +319,25 @@ impl handshake::Step for Initiator {
             .expect("BUG: into_handshake_state shouldn't be called before negotiation")
     }
 
+    fn encryption_algorithm(&self) -> Option<EncryptionAlgorithm> {
+        self.encryption_algorithm.clone()
+    }
+
+    fn stage(&self) -> HandshakeStage {
+        match self.stage {
+            0 | 1 => HandshakeStage::Negotiation,
+            _ => HandshakeStage::KeyExchange,
+        }
+    }
+
+    fn handshake_timeout(&self) -> std::time::Duration {
+        self.handshake_timeout
+    }
+
+    fn rekey_policy(&self) -> RekeyPolicy {
+        self.rekey_policy
+    }
+
     fn step(
         &mut self,
         in_msg: Option<handshake::Message>,
@@ -218,6 +349,9 @@ impl handshake::Step for Initiator {
         let result = match self.stage {
             0 => {
                 // -> list supported algorithms
+                if self.algorithms.is_empty() {
+                    return Err(Error::Noise("No encryption algorithm to offer".to_string()));
+                }
                 let msg = NegotiationMessage::new(self.algorithms.clone());
                 noise_bytes.extend_from_slice(&v2::serialization::to_vec(&msg)?[..]);
                 trace!(
@@ -248,6 +382,12 @@ impl handshake::Step for Initiator {
                     .first()
                     .expect("BUG: encryption algorithm list empty")
                     .to_owned();
+                if !self.algorithms.contains(&chosen_algorithm) {
+                    return Err(Error::Noise(format!(
+                        "Responder chose encryption algorithm that has not been offered: {:?}",
+                        chosen_algorithm
+                    )));
+                }
                 prologue.responder_msg = Some(negotiation_message);
                 let negotiation = EncryptionNegotiation::new(prologue, chosen_algorithm);
                 self.build_handshake_state(negotiation)?;
@@ -295,10 +435,20 @@ pub struct Responder<'a> {
     stage: usize,
     static_keypair: &'a StaticKeypair,
     algorithms: Vec<EncryptionAlgorithm>,
+    /// Algorithm agreed upon during negotiation
+    encryption_algorithm: Option<EncryptionAlgorithm>,
+    /// Deadline for completing the whole handshake
+    handshake_timeout: std::time::Duration,
+    /// Rekeying policy of the resulting transport mode
+    rekey_policy: RekeyPolicy,
+    /// Optional pre-shared key mixed into the handshake
+    psk: Option<auth::PreSharedKey>,
     handshake_state: Option<HandshakeState>,
     /// Serialized signature noise message that can be directly provided as part of the
     /// handshake - see `step()`
     signature_noise_message: Bytes,
+    /// Buffer for handshake messages, pooled buffer is used when the handshake has a permit
+    buffer: limiter::HandshakeBuffer,
 }
 
 impl<'a> Responder<'a> {
@@ -311,13 +461,47 @@ impl<'a> Responder<'a> {
             stage: 0,
             static_keypair,
             algorithms,
+            encryption_algorithm: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+            rekey_policy: RekeyPolicy::default(),
+            psk: None,
             handshake_state: None,
             signature_noise_message,
+            buffer: Default::default(),
         }
     }
 
+    /// Sets deadline for completing the whole handshake, a client that doesn't complete the
+    /// handshake in time is disconnected
+    pub fn with_handshake_timeout(mut self, handshake_timeout: std::time::Duration) -> Self {
+        self.handshake_timeout = handshake_timeout;
+        self
+    }
+
+    /// Sets rekeying policy of the transport, the counter party has to use the same policy
+    pub fn with_rekey_policy(mut self, rekey_policy: RekeyPolicy) -> Self {
+        self.rekey_policy = rekey_policy;
+        self
+    }
+
+    /// Mixes `psk` into the handshake, the counter party has to use the same key
+    pub fn with_psk(mut self, psk: Option<auth::PreSharedKey>) -> Self {
+        self.psk = psk;
+        self
+    }
+
+    /// Runs the handshake under `permit` acquired from a `HandshakeLimiter`, the handshake
+    /// then uses buffer of the permit. The permit is released once the handshake is over
+    pub fn with_handshake_permit(mut self, permit: HandshakePermit) -> Self {
+        self.buffer = limiter::HandshakeBuffer::Pooled(permit);
+        self
+    }
+
     /// Executes noise protocol handshake on provided connection
-    pub async fn accept(self, connection: TcpStream) -> Result<v2::Framed> {
+    pub async fn accept<T>(self, connection: T) -> Result<v2::Framed<T>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
         self.accept_with_codec(connection, |noise_codec| {
             <v2::framing::Framing as ii_wire::Framing>::Codec::new(Some(noise_codec))
         })
@@ -326,22 +510,82 @@ impl<'a> Responder<'a> {
 
     /// Accept new connection and run noise handshake and produce a `Framed` that internally runs
     /// a codec provided by `build_codec`
-    pub async fn accept_with_codec<I, F, U>(
+    pub async fn accept_with_codec<T, I, F, U>(
+        self,
+        connection: T,
+        build_codec: F,
+    ) -> Result<Framed<T, U>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(Codec) -> U,
+        U: Encoder<I>,
+    {
+        self.accept_with_codec_and_summary(connection, build_codec)
+            .await
+            .map(|(framed, _)| framed)
+    }
+
+    /// Same as `accept_with_codec()` and additionally provides summary of the handshake
+    pub async fn accept_with_codec_and_summary<T, I, F, U>(
         self,
-        connection: TcpStream,
+        connection: T,
         build_codec: F,
-    ) -> Result<Framed<TcpStream, U>>
+    ) -> Result<(Framed<T, U>, HandshakeSummary)>
     where
+        T: AsyncRead + AsyncWrite + Unpin,
         F: FnOnce(Codec) -> U,
         U: Encoder<I>,
     {
+        self.accept_buffered_with_codec_and_summary(connection, None, build_codec)
+            .await
+    }
+
+    /// Executes noise protocol handshake on provided connection whose beginning has already been
+    /// read into `read_buf` by a previous phase (PROXY protocol etc.)
+    /// `build_codec` - custom codec builder that wraps the noise codec into custom codec
+    pub async fn accept_buffered_with_codec<T, I, F, U>(
+        self,
+        connection: T,
+        read_buf: Option<BytesMut>,
+        build_codec: F,
+    ) -> Result<Framed<T, U>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(Codec) -> U,
+        U: Encoder<I>,
+    {
+        self.accept_buffered_with_codec_and_summary(connection, read_buf, build_codec)
+            .await
+            .map(|(framed, _)| framed)
+    }
+
+    /// Same as `accept_buffered_with_codec()` and additionally provides summary of the handshake
+    pub async fn accept_buffered_with_codec_and_summary<T, I, F, U>(
+        self,
+        connection: T,
+        read_buf: Option<BytesMut>,
+        build_codec: F,
+    ) -> Result<(Framed<T, U>, HandshakeSummary)>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(Codec) -> U,
+        U: Encoder<I>,
+    {
+        let mut parts = FramedParts::new::<BytesMut>(connection, Codec::default());
+        if let Some(read_buf) = read_buf {
+            parts.read_buf = read_buf;
+        }
         // Run the handshake and switch to transport mode
-        let mut noise_framed_stream = ii_wire::Connection::<Framing>::new(connection).into_inner();
+        let mut noise_framed_stream = Framed::from_parts(parts);
 
         let handshake = handshake::Handshake::new(self);
-        let transport_mode = handshake.run(&mut noise_framed_stream).await?;
+        let (transport_mode, summary) =
+            handshake.run_with_summary(&mut noise_framed_stream).await?;
 
-        Ok(transport_mode.into_framed(noise_framed_stream, build_codec))
+        Ok((
+            transport_mode.into_framed(noise_framed_stream, build_codec),
+            summary,
+        ))
     }
 
     /// Executes noise protocol handshake on provided `FramedParts` - e.g. on stream and buffers returned
@@ -350,26 +594,51 @@ impl<'a> Responder<'a> {
     /// be transformed into a `Framed` with noise codec. And once the noise handshake
     /// is complete it will provide `Framed` with the desired codec yielded by `build_codec`
     /// `build_codec` - custom codec builder that wraps the noise codec into custom codec
-    pub async fn accept_parts_with_codec<F, I, P, U>(
+    ///
+    /// Prefer `accept_buffered_with_codec()` that doesn't require any specific codec type
+    pub async fn accept_parts_with_codec<T, F, I, P, U>(
+        self,
+        parts: P,
+        build_codec: F,
+    ) -> Result<Framed<T, U>>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+        F: FnOnce(Codec) -> U,
+        U: Encoder<I>,
+        P: Into<FramedParts<T, Codec>>,
+    {
+        self.accept_parts_with_codec_and_summary(parts, build_codec)
+            .await
+            .map(|(framed, _)| framed)
+    }
+
+    /// Same as `accept_parts_with_codec()` and additionally provides summary of the handshake
+    pub async fn accept_parts_with_codec_and_summary<T, F, I, P, U>(
         self,
         parts: P,
         build_codec: F,
-    ) -> Result<Framed<TcpStream, U>>
+    ) -> Result<(Framed<T, U>, HandshakeSummary)>
     where
+        T: AsyncRead + AsyncWrite + Unpin,
         F: FnOnce(Codec) -> U,
         U: Encoder<I>,
-        P: Into<FramedParts<TcpStream, Codec>>,
+        P: Into<FramedParts<T, Codec>>,
     {
         let mut noise_framed_stream = Framed::from_parts(parts.into());
 
         let handshake = handshake::Handshake::new(self);
-        let transport_mode = handshake.run(&mut noise_framed_stream).await?;
+        let (transport_mode, summary) =
+            handshake.run_with_summary(&mut noise_framed_stream).await?;
 
-        Ok(transport_mode.into_framed(noise_framed_stream, build_codec))
+        Ok((
+            transport_mode.into_framed(noise_framed_stream, build_codec),
+            summary,
+        ))
     }
 
     fn build_handshake_state(&mut self, negotiation: EncryptionNegotiation) -> Result<()> {
-        let builder = NoiseParamsBuilder::new(negotiation.chosen_algorithm).get_builder();
+        self.encryption_algorithm = Some(negotiation.chosen_algorithm.clone());
+        let builder = handshake_builder(negotiation.chosen_algorithm, self.psk.as_ref());
 
         let prologue = match negotiation.prologue {
             // Legacy negotiation has no prologue
@@ -397,12 +666,30 @@ impl<'a> handshake::Step for Responder<'a> {
             .expect("BUG: into_handshake_state shouldn't be called before negotiation")
     }
 
+    fn encryption_algorithm(&self) -> Option<EncryptionAlgorithm> {
+        self.encryption_algorithm.clone()
+    }
+
+    fn stage(&self) -> HandshakeStage {
+        match self.stage {
+            0 | 1 => HandshakeStage::Negotiation,
+            _ => HandshakeStage::KeyExchange,
+        }
+    }
+
+    fn handshake_timeout(&self) -> std::time::Duration {
+        self.handshake_timeout
+    }
+
+    fn rekey_policy(&self) -> RekeyPolicy {
+        self.rekey_policy
+    }
+
     fn step(
         &mut self,
         in_msg: Option<handshake::Message>,
         mut noise_bytes: BytesMut,
     ) -> Result<handshake::StepResult> {
-        let mut buf = vec![0u8; MAX_MESSAGE_SIZE];
         let mut prologue = Prologue::default();
 
         let result = match self.stage {
@@ -418,14 +705,14 @@ impl<'a> handshake::Step for Responder<'a> {
                     // If list of algorithms is provided, go on with negotiation
                     let algs: Vec<EncryptionAlgorithm> = m.encryption_algos.into();
 
-                    // If AES is present choose AES, otherwise choose the first supported one
-                    let chosen_algorithm = if algs.contains(&EncryptionAlgorithm::AESGCM) {
-                        EncryptionAlgorithm::AESGCM
-                    } else {
-                        algs.into_iter()
-                            .find(|x| self.algorithms.contains(x))
-                            .ok_or_else(|| Error::Noise("No algorithms provided".to_string()))?
-                    };
+                    // The initiator lists the algorithms in order of its preference, choose the
+                    // first one that is supported
+                    let chosen_algorithm = algs
+                        .into_iter()
+                        .find(|x| self.algorithms.contains(x))
+                        .ok_or_else(|| {
+                        Error::Noise("No supported encryption algorithm provided".to_string())
+                    })?;
 
                     let negotiation_message =
                         NegotiationMessage::new(vec![chosen_algorithm.clone()]);
@@ -460,17 +747,18 @@ impl<'a> handshake::Step for Responder<'a> {
                     "Noise Handshake Responder: step 2: Received e: {:02x}",
                     in_msg.inner
                 );
+                let buf = self.buffer.get();
                 self.handshake_state
                     .as_mut()
                     .ok_or_else(|| Error::Noise("Handshake state shouldn't be None".to_string()))?
-                    .read_message(&in_msg.inner, &mut buf)?;
+                    .read_message(&in_msg.inner, buf)?;
                 // Send the signature along this message
                 // -> e, ee, s, es [encrypted signature]
                 let len_written = self
                     .handshake_state
                     .as_mut()
                     .ok_or_else(|| Error::Noise("Handshake state shouldn't be None".to_string()))?
-                    .write_message(&self.signature_noise_message, &mut buf)?;
+                    .write_message(&self.signature_noise_message, buf)?;
                 trace!(
                     "Noise Handshake Responder: step 2: Sent e, ee, s, es, cert: {:02x}",
                     self.signature_noise_message
@@ -492,30 +780,196 @@ impl<'a> handshake::Step for Responder<'a> {
     }
 }
 
+/// Parameters of a completed handshake, e.g. for logging or channel binding
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandshakeSummary {
+    /// Algorithm agreed upon during negotiation
+    pub encryption_algorithm: EncryptionAlgorithm,
+    /// Static public key of the remote party. The Responder never learns any as the Initiator
+    /// doesn't have a static key in the NX handshake
+    pub remote_static_key: Option<StaticPublicKey>,
+    /// Hash of the handshake transcript, it is the same on both sides of the connection
+    pub handshake_hash: Vec<u8>,
+}
+
+/// Specifies when the transport rekeys its cipher states (as defined by the noise `REKEY()`
+/// function). Rekeying is not signaled to the counter party, both parties rekey each direction
+/// independently after the same amount of traffic and thus have to use the same policy.
+/// The default policy never rekeys.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct RekeyPolicy {
+    /// Rekey after this number of messages has been sent (received resp.) with the current key
+    pub max_messages: Option<u64>,
+    /// Rekey after this number of encrypted bytes has been sent (received resp.) with the current
+    /// key
+    pub max_bytes: Option<u64>,
+}
+
+impl RekeyPolicy {
+    /// Policy that doesn't rekey at all
+    pub fn never() -> Self {
+        Self::default()
+    }
+
+    pub fn after_messages(max_messages: u64) -> Self {
+        Self {
+            max_messages: Some(max_messages),
+            max_bytes: None,
+        }
+    }
+
+    pub fn after_bytes(max_bytes: u64) -> Self {
+        Self {
+            max_messages: None,
+            max_bytes: Some(max_bytes),
+        }
+    }
+
+    fn is_exceeded(&self, counter: &TrafficCounter) -> bool {
+        matches!(self.max_messages, Some(max_messages) if counter.messages >= max_messages)
+            || matches!(self.max_bytes, Some(max_bytes) if counter.bytes >= max_bytes)
+    }
+}
+
+/// Traffic passed in one direction of the transport since the last rekey
+#[derive(Debug, Default)]
+struct TrafficCounter {
+    messages: u64,
+    bytes: u64,
+}
+
+impl TrafficCounter {
+    /// Accounts a message of `len` bytes and returns true (and resets the counter) when
+    /// `rekey_policy` demands rekeying
+    fn update(&mut self, len: usize, rekey_policy: &RekeyPolicy) -> bool {
+        self.messages += 1;
+        self.bytes += len as u64;
+        if rekey_policy.is_exceeded(self) {
+            *self = Self::default();
+            true
+        } else {
+            false
+        }
+    }
+}
+
+type HmacEngine = bitcoin_hashes::HmacEngine<sha256::Hash>;
+type Hmac = bitcoin_hashes::Hmac<sha256::Hash>;
+
+/// HKDF pseudorandom key of the keying material exporter
+struct ExporterSecret(Hmac);
+
+impl ExporterSecret {
+    /// HKDF-Extract from the split keys of a completed `handshake_state`, salted with the
+    /// handshake hash. Unlike the hash, the split keys are known only to the parties.
+    fn new(handshake_state: &mut HandshakeState) -> Self {
+        let (initiator_key, responder_key) = handshake_state.dangerously_get_raw_split();
+        Self(Self::extract(
+            handshake_state.get_handshake_hash(),
+            &[&initiator_key[..], &responder_key[..]],
+        ))
+    }
+
+    fn extract(salt: &[u8], input_key_material: &[&[u8]]) -> Hmac {
+        let mut engine = HmacEngine::new(salt);
+        for part in input_key_material {
+            engine.input(part);
+        }
+        Hmac::from_engine(engine)
+    }
+
+    /// HKDF-Expand, `len` must not exceed 255 blocks
+    fn expand(&self, info: &[u8], len: usize) -> Vec<u8> {
+        let mut keying_material = Vec::with_capacity(len);
+        let mut previous_block: Option<Hmac> = None;
+        let mut counter = 1u8;
+        while keying_material.len() < len {
+            let mut engine = HmacEngine::new(&self.0[..]);
+            if let Some(previous_block) = previous_block.as_ref() {
+                engine.input(&previous_block[..]);
+            }
+            engine.input(info);
+            engine.input(&[counter]);
+            let block = Hmac::from_engine(engine);
+            let missing = len - keying_material.len();
+            keying_material.extend_from_slice(&block[..missing.min(sha256::Hash::LEN)]);
+            previous_block = Some(block);
+            // Cannot overflow as `len` is limited to 255 blocks
+            counter = counter.wrapping_add(1);
+        }
+        keying_material
+    }
+}
+
+impl fmt::Debug for ExporterSecret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("ExporterSecret(..)")
+    }
+}
+
 /// Helper struct that wraps the transport state and provides convenient interface to read/write
 /// messages
 #[derive(Debug)]
 pub struct TransportMode {
     inner: TransportState,
+    /// Secret from which all exported keying material is expanded
+    exporter_secret: ExporterSecret,
+    rekey_policy: RekeyPolicy,
+    /// Traffic encrypted with the current sending key
+    sent: TrafficCounter,
+    /// Traffic decrypted with the current receiving key
+    received: TrafficCounter,
 }
 
 impl TransportMode {
-    pub fn new(inner: TransportState) -> Self {
-        Self { inner }
+    /// Switches a completed `handshake_state` into transport mode
+    pub fn from_handshake_state(mut handshake_state: HandshakeState) -> Result<Self> {
+        let exporter_secret = ExporterSecret::new(&mut handshake_state);
+        Ok(Self {
+            inner: handshake_state.into_transport_mode()?,
+            exporter_secret,
+            rekey_policy: RekeyPolicy::default(),
+            sent: TrafficCounter::default(),
+            received: TrafficCounter::default(),
+        })
+    }
+
+    /// Enables automatic rekeying of both directions of the transport as specified by
+    /// `rekey_policy`
+    pub fn with_rekey_policy(mut self, rekey_policy: RekeyPolicy) -> Self {
+        self.rekey_policy = rekey_policy;
+        self
+    }
+
+    /// Derives `len` bytes of keying material bound to this particular noise session. Both
+    /// parties get the same result for the same `label`, so it can serve e.g. as a channel binding
+    /// token for application level credentials.
+    ///
+    /// The material is derived by HKDF-SHA256 (RFC 5869) from the secret split keys of the
+    /// handshake, the handshake hash is only the salt and `label` is used as the HKDF info. An
+    /// observer of the handshake cannot compute it. At most 255 * 32 bytes can be exported.
+    pub fn export_keying_material(&self, label: &str, len: usize) -> Result<Vec<u8>> {
+        if len > 255 * sha256::Hash::LEN {
+            return Err(Error::Noise(format!(
+                "Cannot export {} bytes of keying material, the limit is {}",
+                len,
+                255 * sha256::Hash::LEN
+            )));
+        }
+        Ok(self.exporter_secret.expand(label.as_bytes(), len))
     }
 
     /// Consumes the noise transport mode instance and converts it into a Framed stream that can
     /// consume/produce frames with encryption. The codec inside the Framed stream is provided by
     /// `build_codec`.
-    pub fn into_framed<I, F, U>(
+    pub fn into_framed<T, I, F, U>(
         self,
-        noise_framed_stream: NoiseFramedTcpStream,
+        noise_framed_stream: NoiseFramedStream<T>,
         build_codec: F,
-    ) -> Framed<TcpStream, U>
+    ) -> Framed<T, U>
     where
+        T: AsyncRead + AsyncWrite,
         F: FnOnce(Codec) -> U,
-        // TODO: replace TcpStream with
-        // T: AsyncRead+AsyncWrite,
         U: Encoder<I>,
     {
         // Take apart the noise framed stream and build a new Framed stream that  uses
@@ -541,6 +995,14 @@ impl TransportMode {
         let msg_len = self.inner.read_message(&encrypted_msg[..], &mut out_vec)?;
         decrypted_msg.extend_from_slice(&out_vec[..msg_len]);
 
+        if self
+            .received
+            .update(encrypted_msg.len(), &self.rekey_policy)
+        {
+            trace!("Noise: rekeying incoming cipher state");
+            self.inner.rekey_incoming();
+        }
+
         Ok(())
     }
 
@@ -553,6 +1015,11 @@ impl TransportMode {
         let msg_len = self.inner.write_message(&plain_msg[..], &mut out_vec)?;
         encrypted_msg.extend_from_slice(&out_vec[..msg_len]);
 
+        if self.sent.update(msg_len, &self.rekey_policy) {
+            trace!("Noise: rekeying outgoing cipher state");
+            self.inner.rekey_outgoing();
+        }
+
         Ok(())
     }
 }
@@ -662,18 +1129,12 @@ pub(crate) mod test {
                 handshake::StepResult::Done(_) => break,
             };
         }
-        let initiator_transport_mode = TransportMode::new(
-            initiator
-                .into_handshake_state()
-                .into_transport_mode()
-                .expect("BUG: cannot convert initiator into transport mode"),
-        );
-        let responder_transport_mode = TransportMode::new(
-            responder
-                .into_handshake_state()
-                .into_transport_mode()
-                .expect("BUG: cannot convert responder into transport mode"),
-        );
+        let initiator_transport_mode =
+            TransportMode::from_handshake_state(initiator.into_handshake_state())
+                .expect("BUG: cannot convert initiator into transport mode");
+        let responder_transport_mode =
+            TransportMode::from_handshake_state(responder.into_handshake_state())
+                .expect("BUG: cannot convert responder into transport mode");
 
         (initiator_transport_mode, responder_transport_mode)
     }
@@ -698,6 +1159,362 @@ pub(crate) mod test {
         assert_eq!(&message[..], &decrypted_msg, "Messages don't match");
     }
 
+    /// Drives the machine with messages from `inbox` and puts its outgoing messages to `outbox`
+    /// until it needs more input. Returns true when the handshake is done.
+    fn pump_handshake_machine<T: handshake::Step>(
+        machine: &mut HandshakeMachine<T>,
+        inbox: &mut std::collections::VecDeque<BytesMut>,
+        outbox: &mut std::collections::VecDeque<BytesMut>,
+    ) -> Result<bool> {
+        let mut in_msg = None;
+        loop {
+            match machine.advance(in_msg.take())? {
+                HandshakeAction::Send(out_msg) => outbox.push_back(out_msg),
+                HandshakeAction::SendAndReceive(out_msg) => {
+                    outbox.push_back(out_msg);
+                    match inbox.pop_front() {
+                        Some(msg) => in_msg = Some(msg),
+                        None => return Ok(false),
+                    }
+                }
+                HandshakeAction::Receive => match inbox.pop_front() {
+                    Some(msg) => in_msg = Some(msg),
+                    None => return Ok(false),
+                },
+                HandshakeAction::Done => return Ok(true),
+            }
+        }
+    }
+
+    /// Runs the handshake between both machines passing messages through in-memory queues
+    fn run_handshake_machines<T: handshake::Step, U: handshake::Step>(
+        initiator: &mut HandshakeMachine<T>,
+        responder: &mut HandshakeMachine<U>,
+    ) -> Result<()> {
+        let mut to_responder = std::collections::VecDeque::new();
+        let mut to_initiator = std::collections::VecDeque::new();
+        // The responder waits for the first message, then both parties take turns
+        let mut responder_done =
+            pump_handshake_machine(responder, &mut to_responder, &mut to_initiator)?;
+        let mut initiator_done = false;
+        for _ in 0..10 {
+            if initiator_done && responder_done {
+                break;
+            }
+            if !initiator_done {
+                initiator_done =
+                    pump_handshake_machine(initiator, &mut to_initiator, &mut to_responder)?;
+            }
+            if !responder_done {
+                responder_done =
+                    pump_handshake_machine(responder, &mut to_responder, &mut to_initiator)?;
+            }
+        }
+        assert!(initiator_done && responder_done, "BUG: handshake got stuck");
+        assert!(to_responder.is_empty() && to_initiator.is_empty());
+        Ok(())
+    }
+
+    /// Verifies that the handshake can be performed without any I/O by passing messages between
+    /// initiator and responder machines through in-memory queues
+    #[test]
+    fn sans_io_handshake() {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let rekey_policy = RekeyPolicy::after_messages(1);
+        let mut initiator = HandshakeMachine::new(
+            Initiator::new(authority_keypair.public, vec![EncryptionAlgorithm::AESGCM])
+                .with_rekey_policy(rekey_policy),
+        );
+        let mut responder = HandshakeMachine::new(
+            Responder::new(
+                &static_keypair,
+                signature_noise_message,
+                vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
+            )
+            .with_rekey_policy(rekey_policy),
+        );
+        assert_eq!(initiator.stage(), HandshakeStage::Negotiation);
+
+        run_handshake_machines(&mut initiator, &mut responder).expect("BUG: handshake failed");
+        assert!(initiator.certificate().is_some());
+        assert!(initiator.advance(None).is_err());
+
+        let (mut initiator_transport_mode, initiator_summary) = initiator
+            .into_transport_mode_with_summary()
+            .expect("BUG: cannot convert initiator into transport mode");
+        let mut responder_transport_mode = responder
+            .into_transport_mode()
+            .expect("BUG: cannot convert responder into transport mode");
+        assert_eq!(
+            initiator_summary.encryption_algorithm,
+            EncryptionAlgorithm::AESGCM
+        );
+
+        // Every message is encrypted with a new key
+        let message = b"sans-io message";
+        for _ in 0..3 {
+            let mut encrypted_msg = BytesMut::new();
+            let mut decrypted_msg = BytesMut::new();
+            responder_transport_mode
+                .write(BytesMut::from(&message[..]), &mut encrypted_msg)
+                .expect("BUG: responder failed to write message");
+            initiator_transport_mode
+                .read(encrypted_msg, &mut decrypted_msg)
+                .expect("BUG: initiator failed to read transport message");
+            assert_eq!(&message[..], &decrypted_msg, "Messages don't match");
+        }
+    }
+
+    /// Runs handshake of an initiator with `initiator_algorithms` and a responder with
+    /// `responder_algorithms` and provides the algorithm chosen
+    fn negotiate_algorithm(
+        initiator_algorithms: Vec<EncryptionAlgorithm>,
+        responder_algorithms: Vec<EncryptionAlgorithm>,
+    ) -> Result<EncryptionAlgorithm> {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let mut initiator = HandshakeMachine::new(
+            Initiator::new(authority_keypair.public, vec![EncryptionAlgorithm::AESGCM])
+                .with_algorithm_preference(initiator_algorithms),
+        );
+        let mut responder = HandshakeMachine::new(Responder::new(
+            &static_keypair,
+            signature_noise_message,
+            responder_algorithms,
+        ));
+        run_handshake_machines(&mut initiator, &mut responder)?;
+        assert_eq!(
+            initiator.encryption_algorithm(),
+            responder.encryption_algorithm()
+        );
+        Ok(initiator
+            .encryption_algorithm()
+            .expect("BUG: missing chosen algorithm"))
+    }
+
+    /// Verifies that the responder honors preference of the initiator and falls back to the next
+    /// algorithm if it doesn't support the preferred one
+    #[test]
+    fn initiator_algorithm_preference() {
+        use EncryptionAlgorithm::{ChaChaPoly, AESGCM};
+
+        assert_eq!(
+            negotiate_algorithm(vec![ChaChaPoly, AESGCM], vec![AESGCM, ChaChaPoly])
+                .expect("BUG: No common algorithm"),
+            ChaChaPoly
+        );
+        assert_eq!(
+            negotiate_algorithm(vec![AESGCM, ChaChaPoly], vec![ChaChaPoly, AESGCM])
+                .expect("BUG: No common algorithm"),
+            AESGCM
+        );
+        assert_eq!(
+            negotiate_algorithm(vec![AESGCM, ChaChaPoly], vec![ChaChaPoly])
+                .expect("BUG: No common algorithm"),
+            ChaChaPoly
+        );
+    }
+
+    /// Verifies that an initiator restricted to a single algorithm doesn't accept anything else
+    #[test]
+    fn initiator_algorithm_restricted() {
+        use EncryptionAlgorithm::{ChaChaPoly, AESGCM};
+
+        let (_, authority_keypair, _) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let initiator = Initiator::new(authority_keypair.public, vec![ChaChaPoly, AESGCM])
+            .with_algorithm_restricted_to(AESGCM);
+        assert_eq!(initiator.algorithm_preference(), &[AESGCM]);
+        assert_eq!(initiator.chosen_algorithm(), None);
+
+        assert_eq!(
+            negotiate_algorithm(vec![AESGCM], vec![ChaChaPoly, AESGCM])
+                .expect("BUG: No common algorithm"),
+            AESGCM
+        );
+        assert!(negotiate_algorithm(vec![AESGCM], vec![ChaChaPoly]).is_err());
+        assert!(negotiate_algorithm(vec![], vec![ChaChaPoly, AESGCM]).is_err());
+    }
+
+    /// Verifies that the initiator refuses an algorithm it hasn't offered
+    #[test]
+    fn initiator_refuses_unoffered_algorithm() {
+        let (_, authority_keypair, _) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let mut initiator = Initiator::new(
+            authority_keypair.public,
+            vec![EncryptionAlgorithm::ChaChaPoly],
+        );
+        initiator
+            .step(None, BytesMut::new())
+            .expect("BUG: initiator failed to start negotiation");
+        let response = NegotiationMessage::new(vec![EncryptionAlgorithm::AESGCM]);
+        let response = BytesMut::from(
+            &v2::serialization::to_vec(&response).expect("BUG: serialization failed")[..],
+        );
+        assert!(initiator
+            .step(Some(handshake::Message::new(response)), BytesMut::new())
+            .is_err());
+    }
+
+    /// Runs the handshake with optional pre-shared keys of both parties
+    fn handshake_with_psk(
+        initiator_psk: Option<auth::PreSharedKey>,
+        responder_psk: Option<auth::PreSharedKey>,
+    ) -> Result<()> {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let mut initiator = HandshakeMachine::new(
+            Initiator::new(authority_keypair.public, vec![EncryptionAlgorithm::AESGCM])
+                .with_psk(initiator_psk),
+        );
+        let mut responder = HandshakeMachine::new(
+            Responder::new(
+                &static_keypair,
+                signature_noise_message,
+                vec![EncryptionAlgorithm::AESGCM],
+            )
+            .with_psk(responder_psk),
+        );
+        run_handshake_machines(&mut initiator, &mut responder)?;
+
+        let mut initiator_transport_mode = initiator.into_transport_mode()?;
+        let mut responder_transport_mode = responder.into_transport_mode()?;
+        let mut encrypted_msg = BytesMut::new();
+        let mut decrypted_msg = BytesMut::new();
+        initiator_transport_mode.write(BytesMut::from(&b"psk"[..]), &mut encrypted_msg)?;
+        responder_transport_mode.read(encrypted_msg, &mut decrypted_msg)?;
+        assert_eq!(&decrypted_msg[..], b"psk");
+        Ok(())
+    }
+
+    /// Verifies that both parties export the same keying material that depends on the label
+    #[test]
+    fn export_keying_material() {
+        let (initiator_transport_mode, responder_transport_mode) = perform_handshake();
+        let initiator_material = initiator_transport_mode
+            .export_keying_material("test label", 100)
+            .expect("BUG: cannot export keying material");
+        assert_eq!(initiator_material.len(), 100);
+        assert_eq!(
+            responder_transport_mode
+                .export_keying_material("test label", 100)
+                .expect("BUG: cannot export keying material"),
+            initiator_material
+        );
+        assert_eq!(
+            initiator_transport_mode
+                .export_keying_material("test label", 16)
+                .expect("BUG: cannot export keying material")[..],
+            initiator_material[..16]
+        );
+        assert_ne!(
+            initiator_transport_mode
+                .export_keying_material("other label", 100)
+                .expect("BUG: cannot export keying material"),
+            initiator_material
+        );
+        assert!(initiator_transport_mode
+            .export_keying_material("test label", 255 * 32 + 1)
+            .is_err());
+
+        // Keying material is unique for each session
+        let (other_transport_mode, _) = perform_handshake();
+        assert_ne!(
+            other_transport_mode
+                .export_keying_material("test label", 100)
+                .expect("BUG: cannot export keying material"),
+            initiator_material
+        );
+    }
+
+    /// Verifies that an observer who knows only the handshake transcript (and thus the handshake
+    /// hash) cannot derive the exported keying material
+    #[test]
+    fn exported_keying_material_is_secret() {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let mut initiator = HandshakeMachine::new(Initiator::new(
+            authority_keypair.public,
+            vec![EncryptionAlgorithm::AESGCM],
+        ));
+        let mut responder = HandshakeMachine::new(Responder::new(
+            &static_keypair,
+            signature_noise_message,
+            vec![EncryptionAlgorithm::AESGCM],
+        ));
+        run_handshake_machines(&mut initiator, &mut responder).expect("BUG: handshake failed");
+        let (transport_mode, summary) = initiator
+            .into_transport_mode_with_summary()
+            .expect("BUG: cannot convert initiator into transport mode");
+        let keying_material = transport_mode
+            .export_keying_material("test label", 100)
+            .expect("BUG: cannot export keying material");
+
+        let public_hash = &summary.handshake_hash[..];
+        for (salt, input_key_material) in [
+            (public_hash, &[][..]),
+            (public_hash, &[public_hash][..]),
+            (&b"stratum v2 noise exporter"[..], &[public_hash][..]),
+        ]
+        .iter()
+        {
+            let observer_secret = ExporterSecret(ExporterSecret::extract(salt, input_key_material));
+            assert_ne!(
+                observer_secret.expand(b"test label", 100),
+                keying_material,
+                "BUG: keying material derived from public data"
+            );
+        }
+    }
+
+    /// Verifies that the handshake succeeds only when both parties use the same pre-shared key
+    #[test]
+    fn psk_handshake() {
+        let psk = auth::PreSharedKey::generate();
+        handshake_with_psk(Some(psk.clone()), Some(psk.clone()))
+            .expect("BUG: handshake with the same pre-shared key failed");
+        assert!(
+            handshake_with_psk(Some(auth::PreSharedKey::generate()), Some(psk.clone())).is_err()
+        );
+        assert!(handshake_with_psk(None, Some(psk.clone())).is_err());
+        assert!(handshake_with_psk(Some(psk), None).is_err());
+    }
+
+    /// Verifies that the responder runs the handshake with the buffer of the permit and
+    /// releases the permit once the handshake is over
+    #[test]
+    fn responder_with_handshake_permit() {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let limiter = HandshakeLimiter::new(1);
+        let permit = limiter.try_acquire().expect("BUG: permit refused");
+        assert!(limiter.try_acquire().is_none());
+
+        let mut initiator = HandshakeMachine::new(Initiator::new(
+            authority_keypair.public,
+            vec![EncryptionAlgorithm::AESGCM],
+        ));
+        let mut responder = HandshakeMachine::new(
+            Responder::new(
+                &static_keypair,
+                signature_noise_message,
+                vec![EncryptionAlgorithm::AESGCM],
+            )
+            .with_handshake_permit(permit),
+        );
+        run_handshake_machines(&mut initiator, &mut responder).expect("BUG: handshake failed");
+        assert_eq!(limiter.in_flight(), 1);
+
+        responder
+            .into_transport_mode()
+            .expect("BUG: cannot convert responder into transport mode");
+        assert_eq!(limiter.in_flight(), 0);
+        assert_eq!(limiter.pooled_buffers(), 1);
+        assert_eq!(limiter.rejected(), 1);
+    }
+
     /// Legacy version of the initiator. Useful for testing that handshake still works even with
     /// legacy clients.
     #[derive(Debug)]
@@ -750,6 +1567,14 @@ pub(crate) mod test {
             self.handshake_state
         }
 
+        fn encryption_algorithm(&self) -> Option<EncryptionAlgorithm> {
+            Some(EncryptionAlgorithm::ChaChaPoly)
+        }
+
+        fn stage(&self) -> HandshakeStage {
+            HandshakeStage::KeyExchange
+        }
+
         fn step(
             &mut self,
             in_msg: Option<handshake::Message>,
@@ -861,18 +1686,12 @@ pub(crate) mod test {
                 handshake::StepResult::Done(_) => break,
             };
         }
-        let initiator_transport_mode = TransportMode::new(
-            initiator
-                .into_handshake_state()
-                .into_transport_mode()
-                .expect("BUG: cannot convert initiator into transport mode"),
-        );
-        let responder_transport_mode = TransportMode::new(
-            responder
-                .into_handshake_state()
-                .into_transport_mode()
-                .expect("BUG: cannot convert responder into transport mode"),
-        );
+        let initiator_transport_mode =
+            TransportMode::from_handshake_state(initiator.into_handshake_state())
+                .expect("BUG: cannot convert initiator into transport mode");
+        let responder_transport_mode =
+            TransportMode::from_handshake_state(responder.into_handshake_state())
+                .expect("BUG: cannot convert responder into transport mode");
 
         (initiator_transport_mode, responder_transport_mode)
     }
@@ -918,7 +1737,7 @@ pub(crate) mod test {
             .await
             .expect("BUG: Failed to accept tcp connection");
         let mut framed = responder
-            .accept_with_codec::<String, _, _>(downstream, |noise| {
+            .accept_with_codec::<_, String, _, _>(downstream, |noise| {
                 CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
             })
             .await
@@ -947,7 +1766,7 @@ pub(crate) mod test {
             vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
         );
         let mut framed = initiator
-            .connect_with_codec::<String, _, _>(stream, |noise| {
+            .connect_with_codec::<_, String, _, _>(stream, |noise| {
                 CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
             })
             .await
@@ -968,6 +1787,213 @@ pub(crate) mod test {
         jh1.await.expect("BUG: Initiator failed");
     }
 
+    /// Both sides of the handshake run over an in-memory stream without any socket
+    #[tokio::test]
+    async fn initiator_and_responder_over_duplex() {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let (client, server) = tokio::io::duplex(1024);
+        let responder = Responder::new(
+            &static_keypair,
+            signature_noise_message,
+            vec![EncryptionAlgorithm::AESGCM],
+        );
+        let initiator = Initiator::new(authority_keypair.public, vec![EncryptionAlgorithm::AESGCM]);
+
+        let (server, client) = future::join(
+            responder.accept_with_codec::<_, String, _, _>(server, |noise| {
+                CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
+            }),
+            initiator.connect_with_codec::<_, String, _, _>(client, |noise| {
+                CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
+            }),
+        )
+        .await;
+        let mut server = server.expect("BUG: Responder failed to finish noise handshake");
+        let mut client = client.expect("BUG: Initiator failed to finish noise handshake");
+
+        client
+            .send(TEST_MESSAGE)
+            .await
+            .expect("BUG: Failed to send test message");
+        let msg = server
+            .next()
+            .await
+            .expect("BUG: Failed to receive test message")
+            .expect("BUG: Failed to decode test message");
+        assert_eq!(TEST_MESSAGE, msg);
+    }
+
+    #[tokio::test]
+    async fn handshake_summary() {
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let tcp_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("BUG: Failed to bind the test server");
+        let server_addr = tcp_listener
+            .local_addr()
+            .expect("BUG: Missing test server address");
+        let static_public_key = static_keypair.public.clone();
+        let responder_task = tokio::spawn(async move {
+            let (downstream, _) = tcp_listener
+                .accept()
+                .await
+                .expect("BUG: Failed to accept tcp connection");
+            let responder = Responder::new(
+                &static_keypair,
+                signature_noise_message,
+                vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
+            );
+            let (_framed, summary) = responder
+                .accept_with_codec_and_summary::<_, String, _, _>(downstream, |noise| {
+                    CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
+                })
+                .await
+                .expect("BUG: Responder failed to finish noise handshake");
+            summary
+        });
+
+        let stream = TcpStream::connect(server_addr)
+            .await
+            .expect("BUG: Failed to connect to the test server");
+        let initiator = Initiator::new(
+            authority_keypair.public,
+            vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
+        );
+        let (_framed, initiator_summary) = initiator
+            .connect_with_codec_and_summary::<_, String, _, _>(stream, |noise| {
+                CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
+            })
+            .await
+            .expect("BUG: Initiator failed to finish noise handshake");
+        let responder_summary = responder_task.await.expect("BUG: Responder panicked");
+
+        // The initiator prefers ChaChaPoly
+        assert_eq!(
+            initiator_summary.encryption_algorithm,
+            EncryptionAlgorithm::ChaChaPoly
+        );
+        assert_eq!(
+            initiator_summary.encryption_algorithm,
+            responder_summary.encryption_algorithm
+        );
+        assert_eq!(
+            initiator_summary.handshake_hash, responder_summary.handshake_hash,
+            "BUG: Handshake hashes don't match"
+        );
+        assert_eq!(initiator_summary.remote_static_key, Some(static_public_key));
+        assert_eq!(responder_summary.remote_static_key, None);
+    }
+
+    /// Verifies that the responder continues the handshake with data that have already been read
+    /// from the connection (e.g. along with a PROXY protocol header)
+    #[tokio::test]
+    async fn responder_accept_buffered() {
+        use tokio::io::AsyncReadExt as _;
+
+        let (signature_noise_message, authority_keypair, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let tcp_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("BUG: Failed to bind the test server");
+        let server_addr = tcp_listener
+            .local_addr()
+            .expect("BUG: Missing test server address");
+        let responder_task = tokio::spawn(async move {
+            let (mut downstream, _) = tcp_listener
+                .accept()
+                .await
+                .expect("BUG: Failed to accept tcp connection");
+            // Consume the beginning of the first handshake message
+            let mut read_buf = BytesMut::new();
+            while read_buf.len() < 3 {
+                downstream
+                    .read_buf(&mut read_buf)
+                    .await
+                    .expect("BUG: Failed to read from the connection");
+            }
+            let responder = Responder::new(
+                &static_keypair,
+                signature_noise_message,
+                vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
+            );
+            let mut framed = responder
+                .accept_buffered_with_codec::<_, String, _, _>(
+                    downstream,
+                    Some(read_buf),
+                    |noise| CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise)),
+                )
+                .await
+                .expect("BUG: Responder failed to finish noise handshake");
+            framed
+                .next()
+                .await
+                .expect("BUG: Connection closed")
+                .expect("BUG: Failed to receive line")
+        });
+
+        let stream = TcpStream::connect(server_addr)
+            .await
+            .expect("BUG: Failed to connect to the test server");
+        let initiator = Initiator::new(
+            authority_keypair.public,
+            vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
+        );
+        let mut framed = initiator
+            .connect_with_codec::<_, String, _, _>(stream, |noise| {
+                CompoundCodec::<tokio_util::codec::LinesCodec>::new(Some(noise))
+            })
+            .await
+            .expect("BUG: Initiator failed to finish noise handshake");
+        framed
+            .send("test line".to_string())
+            .await
+            .expect("BUG: Failed to send line");
+
+        assert_eq!(
+            responder_task.await.expect("BUG: Responder panicked"),
+            "test line"
+        );
+    }
+
+    /// Verifies that a client that never completes the handshake is disconnected
+    #[tokio::test]
+    async fn responder_handshake_timeout() {
+        let (signature_noise_message, _, static_keypair) =
+            build_deterministic_serialized_signature_noise_message_and_keypairs();
+        let tcp_listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .await
+            .expect("BUG: Failed to bind the test server");
+        let _silent_client = TcpStream::connect(
+            tcp_listener
+                .local_addr()
+                .expect("BUG: Missing test server address"),
+        )
+        .await
+        .expect("BUG: Failed to connect to the test server");
+        let (downstream, _) = tcp_listener
+            .accept()
+            .await
+            .expect("BUG: Failed to accept tcp connection");
+
+        let responder = Responder::new(
+            &static_keypair,
+            signature_noise_message,
+            vec![EncryptionAlgorithm::ChaChaPoly, EncryptionAlgorithm::AESGCM],
+        )
+        .with_handshake_timeout(std::time::Duration::from_millis(100));
+        let result = responder.accept(downstream).await;
+        assert!(
+            matches!(
+                result,
+                Err(Error::HandshakeTimeout(HandshakeStage::Negotiation))
+            ),
+            "BUG: Unexpected handshake result: {:?}",
+            result.map(|_| ())
+        );
+    }
+
     /// This is usefull if the counterparts are run on separate machines.
     #[tokio::test]
     #[ignore]
@@ -24,8 +24,10 @@
 
 use bytes::{BufMut, BytesMut};
 use ed25519_dalek::{ed25519::signature::Signature, Signer};
+use rand::RngCore;
 use serde::{de, Deserialize, Serialize, Serializer};
 use std::convert::TryFrom;
+use std::fmt;
 use std::time::{Duration, SystemTime};
 
 use crate::error::{Error, Result};
@@ -56,7 +58,11 @@ impl SignedPartHeader {
     }
 
     pub fn with_duration(valid_for: Duration) -> Result<Self> {
-        let valid_from = SystemTime::now();
+        Self::with_validity(SystemTime::now(), valid_for)
+    }
+
+    /// Builds a header for a validity period that starts at `valid_from` and lasts `valid_for`
+    pub fn with_validity(valid_from: SystemTime, valid_for: Duration) -> Result<Self> {
         let not_valid_after = valid_from + valid_for;
         Ok(Self::new(
             Self::system_time_to_unix_time_u32(&valid_from)?,
@@ -77,16 +83,14 @@ impl SignedPartHeader {
     pub fn verify_expiration(&self, now: SystemTime) -> Result<SystemTime> {
         let now_timestamp = Self::system_time_to_unix_time_u32(&now)?;
         if now_timestamp < self.valid_from {
-            return Err(Error::Noise(format!(
-                "Certificate not yet valid, valid from: {:?}, now: {:?}",
-                self.valid_from, now
-            )));
+            return Err(
+                v2::error::Error::CertificateNotYetValid(self.valid_from, now_timestamp).into(),
+            );
         }
         if now_timestamp > self.not_valid_after {
-            return Err(Error::Noise(format!(
-                "Certificate expired, not valid after: {:?}, now: {:?}",
-                self.valid_from, now
-            )));
+            return Err(
+                v2::error::Error::CertificateExpired(self.not_valid_after, now_timestamp).into(),
+            );
         }
         Ok(self.not_valid_after())
     }
@@ -114,6 +118,22 @@ impl SignedPartHeader {
     }
 }
 
+/// Generates a new keypair for a certification authority that signs server certificates
+pub fn generate_authority_keypair() -> ed25519_dalek::Keypair {
+    ed25519_dalek::Keypair::generate(&mut rand::rngs::OsRng {})
+}
+
+/// Restores the full keypair of a certification authority from its `secret_key` as signing
+/// requires the public key, too
+pub fn authority_keypair_from_secret_key(
+    secret_key: ed25519_dalek::SecretKey,
+) -> ed25519_dalek::Keypair {
+    ed25519_dalek::Keypair {
+        public: (&secret_key).into(),
+        secret: secret_key,
+    }
+}
+
 /// Helper struct for performing the actual signature of the relevant parts of the certificate
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
 pub struct SignedPart {
@@ -223,6 +243,33 @@ impl SignatureNoiseMessage {
 }
 
 impl SignatureNoiseMessage {
+    /// Builds the `Certificate` of `remote_static_key` from this message and validates it
+    /// against `authority_public_keys`. The first authority whose signature verifies is recorded
+    /// in the resulting certificate, which must also be valid at time `now`.
+    pub fn verify(
+        self,
+        remote_static_key: StaticPublicKey,
+        authority_public_keys: &[ed25519_dalek::PublicKey],
+        now: SystemTime,
+    ) -> Result<Certificate> {
+        let certificate = authority_public_keys
+            .iter()
+            .map(|authority_public_key| {
+                Certificate::from_noise_message(
+                    self.clone(),
+                    remote_static_key.clone(),
+                    *authority_public_key,
+                )
+            })
+            .find(|certificate| certificate.verify_signature().is_ok())
+            .ok_or(v2::error::Error::UntrustedCertificate(
+                authority_public_keys.len(),
+            ))?;
+        certificate.validate(|| now)?;
+
+        Ok(certificate)
+    }
+
     pub fn serialize_to_writer<T: std::io::Write>(&self, writer: &mut T) -> Result<()> {
         v2::serialization::to_writer(writer, self)?;
         Ok(())
@@ -249,6 +296,67 @@ impl TryFrom<&[u8]> for SignatureNoiseMessage {
     }
 }
 
+/// Pre-shared key that is optionally mixed into the handshake. Both parties have to use the same
+/// key, otherwise the handshake fails. The key is represented as a hex string in configuration.
+#[derive(Clone, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct PreSharedKey([u8; PreSharedKey::LEN]);
+
+impl PreSharedKey {
+    pub const LEN: usize = 32;
+
+    pub fn new(key: [u8; Self::LEN]) -> Self {
+        Self(key)
+    }
+
+    pub fn generate() -> Self {
+        let mut key = [0u8; Self::LEN];
+        rand::rngs::OsRng.fill_bytes(&mut key);
+        Self(key)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0[..]
+    }
+}
+
+/// The key is secret, never print it
+impl fmt::Debug for PreSharedKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("PreSharedKey(..)")
+    }
+}
+
+impl TryFrom<&[u8]> for PreSharedKey {
+    type Error = Error;
+
+    fn try_from(key: &[u8]) -> Result<Self> {
+        <[u8; Self::LEN]>::try_from(key).map(Self).map_err(|_| {
+            Error::Noise(format!(
+                "Pre-shared key must have {} bytes, got {}",
+                Self::LEN,
+                key.len()
+            ))
+        })
+    }
+}
+
+impl TryFrom<String> for PreSharedKey {
+    type Error = Error;
+
+    fn try_from(key: String) -> Result<Self> {
+        let key = hex::decode(key.trim())
+            .map_err(|e| Error::Noise(format!("Invalid pre-shared key: {}", e)))?;
+        Self::try_from(&key[..])
+    }
+}
+
+impl From<PreSharedKey> for String {
+    fn from(key: PreSharedKey) -> Self {
+        hex::encode(key.0)
+    }
+}
+
 #[cfg(test)]
 pub(crate) mod test {
     use super::{super::StaticKeypair, *};
@@ -338,7 +446,10 @@ pub(crate) mod test {
             .expect("BUG: cannot build certificate header");
         let result = header.verify_expiration(SystemTime::now() - Duration::from_secs(10));
         assert!(
-            result.is_err(),
+            matches!(
+                result,
+                Err(Error::V2(v2::error::Error::CertificateNotYetValid(..)))
+            ),
             "BUG: Certificate not evaluated as not valid yet: {:?}",
             result
         );
@@ -351,12 +462,101 @@ pub(crate) mod test {
         let result = header
             .verify_expiration(SystemTime::now() + TEST_CERT_VALIDITY + Duration::from_secs(10));
         assert!(
-            result.is_err(),
+            matches!(
+                result,
+                Err(Error::V2(v2::error::Error::CertificateExpired(not_valid_after, _)))
+                    if not_valid_after == header.not_valid_after
+            ),
             "BUG: Certificate not evaluated as expired: {:?}",
             result
         );
     }
 
+    /// Builds a signature noise message and the static key of the responder along with an
+    /// authority that has signed them and an unrelated authority
+    fn build_test_noise_message_and_authorities() -> (
+        SignatureNoiseMessage,
+        StaticPublicKey,
+        ed25519_dalek::PublicKey,
+        ed25519_dalek::PublicKey,
+    ) {
+        let (signed_part, authority_keypair, static_keypair, signature) =
+            build_test_signed_part_and_auth();
+        let noise_message = Certificate::new(signed_part, signature).build_noise_message();
+        let unknown_secret_key =
+            ed25519_dalek::SecretKey::from_bytes(&[7; 32]).expect("BUG: cannot build secret key");
+        (
+            noise_message,
+            static_keypair.public,
+            authority_keypair.public,
+            ed25519_dalek::PublicKey::from(&unknown_secret_key),
+        )
+    }
+
+    #[test]
+    fn signature_noise_message_verify_any_authority() {
+        let (noise_message, static_public_key, authority_public_key, unknown_public_key) =
+            build_test_noise_message_and_authorities();
+
+        let certificate = noise_message
+            .verify(
+                static_public_key,
+                &[unknown_public_key, authority_public_key],
+                SystemTime::now(),
+            )
+            .expect("BUG: certificate signed by a trusted authority rejected");
+        assert_eq!(
+            certificate.authority_public_key.into_inner(),
+            authority_public_key,
+            "BUG: certificate doesn't refer to the authority that signed it"
+        );
+    }
+
+    #[test]
+    fn signature_noise_message_verify_untrusted_authority() {
+        let (noise_message, static_public_key, _authority_public_key, unknown_public_key) =
+            build_test_noise_message_and_authorities();
+
+        let result =
+            noise_message.verify(static_public_key, &[unknown_public_key], SystemTime::now());
+        assert!(
+            matches!(
+                result,
+                Err(Error::V2(v2::error::Error::UntrustedCertificate(1)))
+            ),
+            "BUG: certificate of an untrusted authority accepted: {:?}",
+            result
+        );
+    }
+
+    #[test]
+    fn signature_noise_message_verify_expired() {
+        let (_, authority_keypair, static_keypair, _) = build_test_signed_part_and_auth();
+        let signed_part = SignedPart::new(
+            SignedPartHeader::new(0, 1),
+            static_keypair.public.clone(),
+            authority_keypair.public,
+        );
+        let signature = signed_part
+            .sign_with(&authority_keypair)
+            .expect("BUG: Failed to sign certificate");
+        let noise_message = Certificate::new(signed_part, signature).build_noise_message();
+
+        let result = noise_message.verify(
+            static_keypair.public,
+            &[authority_keypair.public],
+            SystemTime::now(),
+        );
+        assert!(
+            matches!(
+                result,
+                Err(Error::V2(v2::error::Error::CertificateExpired(1, _)))
+            ),
+            "BUG: expired certificate accepted: {:?}",
+            result
+        );
+    }
+
     #[test]
     fn signature_noise_message_serialization() {
         let (signed_part, authority_keypair, _static_keypair, _signature) =
@@ -384,4 +584,19 @@ pub(crate) mod test {
             "Signature noise messages don't match each other after serialization cycle"
         )
     }
+
+    #[test]
+    fn pre_shared_key_string_conversion() {
+        let psk = PreSharedKey::generate();
+        let psk_string = String::from(psk.clone());
+        assert_eq!(psk_string.len(), 2 * PreSharedKey::LEN);
+        assert!(!format!("{:?}", psk).contains(&psk_string));
+        assert_eq!(
+            PreSharedKey::try_from(psk_string).expect("BUG: cannot parse pre-shared key"),
+            psk
+        );
+
+        assert!(PreSharedKey::try_from("00112233".to_string()).is_err());
+        assert!(PreSharedKey::try_from("not hex".to_string()).is_err());
+    }
 }
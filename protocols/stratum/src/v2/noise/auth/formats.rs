@@ -29,6 +29,7 @@ use std::fmt;
 use std::time::SystemTime;
 
 use ed25519_dalek::ed25519::signature::Signature;
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
 use tokio_util::codec::{Decoder, Encoder, Framed};
 
@@ -223,6 +224,28 @@ generate_noise_keypair_structs!(
     "noise_secret_key"
 );
 
+impl StaticSecretKeyFormat {
+    /// Derives the static public key that corresponds to this secret key.
+    ///
+    /// NOTE: this relies on noise using the x25519 Diffie-Hellman function as `snow` doesn't
+    /// provide any API for deriving the public key
+    pub fn public_key(&self) -> Result<StaticPublicKeyFormat> {
+        let secret_key = &self.inner.inner;
+        let mut raw_secret_key = [0_u8; 32];
+        if secret_key.len() != raw_secret_key.len() {
+            return Err(Error::Noise(format!(
+                "Invalid length of static secret key: {}, expected: {}",
+                secret_key.len(),
+                raw_secret_key.len()
+            )));
+        }
+        raw_secret_key.copy_from_slice(secret_key);
+        let raw_public_key =
+            x25519_dalek::x25519(raw_secret_key, x25519_dalek::X25519_BASEPOINT_BYTES);
+        Ok(StaticPublicKeyFormat::new(raw_public_key.to_vec()))
+    }
+}
+
 /// Certificate is intended to be serialized and deserialized from/into a file and loaded on the
 /// stratum server.
 /// Second use of the certificate is to build it from `SignatureNoiseMessage` and check its
@@ -245,6 +268,18 @@ impl Certificate {
         }
     }
 
+    /// Builds a certificate of static `public_key` for the validity period described by `header`
+    /// and signs it with `authority_keypair`
+    pub fn sign(
+        header: SignedPartHeader,
+        public_key: StaticPublicKey,
+        authority_keypair: &ed25519_dalek::Keypair,
+    ) -> Result<Self> {
+        let signed_part = SignedPart::new(header, public_key, authority_keypair.public);
+        let signature = signed_part.sign_with(authority_keypair)?;
+        Ok(Self::new(signed_part, signature))
+    }
+
     /// See  https://docs.rs/ed25519-dalek/1.0.1/ed25519_dalek/struct.PublicKey.html on
     /// details for the strict verification.
     /// Returns expiration timestamp stated in certificate represented as SystemTime
@@ -252,13 +287,35 @@ impl Certificate {
     where
         FN: FnOnce() -> SystemTime,
     {
-        let signed_part = SignedPart::new(
+        let signed_part = self.signed_part();
+        signed_part.verify(&self.signature.clone().into_inner())?;
+        signed_part.verify_expiration(get_current_time())
+    }
+
+    /// Verifies only the signature of the certificate by its authority, regardless of the
+    /// validity period
+    pub fn verify_signature(&self) -> Result<()> {
+        self.signed_part()
+            .verify(&self.signature.clone().into_inner())
+    }
+
+    fn signed_part(&self) -> SignedPart {
+        SignedPart::new(
             self.signed_part_header.clone(),
             self.public_key.clone().into_inner(),
             self.authority_public_key.clone().into_inner(),
-        );
-        signed_part.verify(&self.signature.clone().into_inner())?;
-        signed_part.verify_expiration(get_current_time())
+        )
+    }
+
+    /// Verifies that `secret_key` is the counterpart of the public key in this certificate
+    pub fn validate_secret_key(&self, secret_key: &StaticSecretKeyFormat) -> Result<()> {
+        if secret_key.public_key()? == self.public_key {
+            Ok(())
+        } else {
+            Err(Error::Noise(
+                "Static secret key doesn't match the public key in certificate".to_owned(),
+            ))
+        }
     }
 
     pub fn from_noise_message(
@@ -315,23 +372,8 @@ impl ServerSecurityBundle {
         Ok(bundle)
     }
 
-    // FIXME: This breaks layers of abstraction. We are using external library to validate
-    // keys for noise protocol internal structures. Unfortunately snow is unlikely to implement
-    // mechanisms for secret key validation.
-    // TODO: Consider moving it onto Certificate structure
     fn validate_secret_key(&self) -> Result<()> {
-        let mut raw_secret_key = [0_u8; 32];
-        raw_secret_key.copy_from_slice(&self.secret_key.inner.inner);
-        let raw_public_key =
-            x25519_dalek::x25519(raw_secret_key, x25519_dalek::X25519_BASEPOINT_BYTES);
-        let calculated_public_key = StaticPublicKeyFormat::new(raw_public_key.to_vec());
-        if calculated_public_key == self.certificate.public_key {
-            Ok(())
-        } else {
-            Err(Error::Noise(
-                "Inconsistent secret and public key in security bundle".to_owned(),
-            ))
-        }
+        self.certificate.validate_secret_key(&self.secret_key)
     }
 
     fn authority_pubkey(&self) -> EncodedEd25519PublicKey {
@@ -432,14 +474,15 @@ impl ServerSecurityBundle {
             .await
     }
 
-    pub async fn build_framed_tcp_from_parts<C, F, P>(
+    pub async fn build_framed_tcp_from_parts<T, C, F, P>(
         &self,
         parts: P,
-    ) -> Result<Framed<TcpStream, noise::CompoundCodec<C>>>
+    ) -> Result<Framed<T, noise::CompoundCodec<C>>>
     where
+        T: AsyncRead + AsyncWrite + Unpin,
         C: Default + Decoder + Encoder<F>,
         <C as Encoder<F>>::Error: Into<Error>,
-        P: Into<tokio_util::codec::FramedParts<TcpStream, noise::Codec>>,
+        P: Into<tokio_util::codec::FramedParts<T, noise::Codec>>,
     {
         let signature_noise_message = self
             .certificate
@@ -520,6 +563,7 @@ impl fmt::Debug for ServerSecurityBundle {
 pub mod test {
     use super::super::test::build_test_signed_part_and_auth;
     use super::*;
+    use std::time::Duration;
 
     #[test]
     fn certificate_validate() {
@@ -557,6 +601,52 @@ pub mod test {
             .expect_err("BUG: Validation passed for inconsistent server security bundle");
     }
 
+    #[test]
+    fn certificate_sign() {
+        let authority_keypair = super::super::generate_authority_keypair();
+        let static_keypair = noise::generate_keypair().expect("BUG: cannot generate keypair");
+        let header = SignedPartHeader::with_validity(
+            SystemTime::now() - Duration::from_secs(60),
+            Duration::from_secs(3600),
+        )
+        .expect("BUG: cannot build certificate header");
+
+        let certificate = Certificate::sign(header, static_keypair.public, &authority_keypair)
+            .expect("BUG: cannot sign certificate");
+        certificate
+            .validate(SystemTime::now)
+            .expect("BUG: Signed certificate not valid!");
+        certificate
+            .validate_secret_key(&StaticSecretKeyFormat::new(static_keypair.private))
+            .expect("BUG: Certificate doesn't match the static key");
+        assert_eq!(
+            certificate.authority_public_key.into_inner(),
+            super::super::authority_keypair_from_secret_key(authority_keypair.secret).public,
+            "BUG: Certificate not signed by the authority"
+        );
+    }
+
+    #[test]
+    fn certificate_validate_secret_key() {
+        let (signed_part, _authority_keypair, static_keypair, signature) =
+            build_test_signed_part_and_auth();
+        let certificate = Certificate::new(signed_part, signature);
+
+        certificate
+            .validate_secret_key(&StaticSecretKeyFormat::new(static_keypair.private.clone()))
+            .expect("BUG: Validation failed for matching secret key");
+
+        let other_keypair = noise::generate_keypair().expect("BUG: cannot generate keypair");
+        certificate
+            .validate_secret_key(&StaticSecretKeyFormat::new(other_keypair.private))
+            .expect_err("BUG: Validation passed for unrelated secret key");
+
+        let truncated_secret_key = static_keypair.private[..16].to_vec();
+        certificate
+            .validate_secret_key(&StaticSecretKeyFormat::new(truncated_secret_key))
+            .expect_err("BUG: Validation passed for secret key of invalid length");
+    }
+
     #[test]
     fn certificate_serialization() {
         let (signed_part, _authority_keypair, _static_keypair, signature) =
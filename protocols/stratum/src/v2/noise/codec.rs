@@ -23,10 +23,12 @@
 //! Noise protocol codec implementation that takes care of framing
 
 use bytes::BytesMut;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
 use tokio_util::codec::length_delimited::{self, LengthDelimitedCodec};
 use tokio_util::codec::{Decoder, Encoder};
 
-use crate::error::Error;
+use crate::error::{Error, Result};
 
 use super::TransportMode;
 
@@ -37,7 +39,7 @@ enum State {
     HandShake,
     /// Transport mode where AEAD is fully operational. The `TransportMode` object in this variant
     /// as able to perform encryption and decryption resp.
-    Transport(TransportMode),
+    Transport(Box<TransportMode>),
 }
 
 /// Noise codec compound object that stacks length delimited codec and stratum codec
@@ -58,12 +60,22 @@ impl Codec {
         if let State::Transport(_) = &self.state {
             panic!("BUG: codec is already in transport mode!");
         }
-        self.state = State::Transport(transport_mode);
+        self.state = State::Transport(Box::new(transport_mode));
     }
 
     pub fn is_in_transport_mode(&self) -> bool {
         matches!(self.state, State::Transport(_))
     }
+
+    /// See `TransportMode::export_keying_material()`, fails when the handshake is not complete
+    pub fn export_keying_material(&self, label: &str, len: usize) -> Result<Vec<u8>> {
+        match &self.state {
+            State::HandShake => Err(Error::Noise(
+                "Cannot export keying material before the handshake is complete".to_string(),
+            )),
+            State::Transport(transport_mode) => transport_mode.export_keying_material(label, len),
+        }
+    }
 }
 
 impl Decoder for Codec {
@@ -131,6 +143,34 @@ impl Default for Codec {
     }
 }
 
+/// Counts bytes that have been received and sent by a codec. Clones of the counter share the
+/// counts so that they can be read while the codec is owned by a `Framed` stream.
+#[derive(Clone, Debug, Default)]
+pub struct ByteCounter {
+    rx_bytes: Arc<AtomicU64>,
+    tx_bytes: Arc<AtomicU64>,
+}
+
+impl ByteCounter {
+    /// Number of bytes decoded from the underlying stream
+    pub fn rx_bytes(&self) -> u64 {
+        self.rx_bytes.load(Relaxed)
+    }
+
+    /// Number of bytes encoded for the underlying stream
+    pub fn tx_bytes(&self) -> u64 {
+        self.tx_bytes.load(Relaxed)
+    }
+
+    fn account_rx(&self, bytes: usize) {
+        self.rx_bytes.fetch_add(bytes as u64, Relaxed);
+    }
+
+    fn account_tx(&self, bytes: usize) {
+        self.tx_bytes.fetch_add(bytes as u64, Relaxed);
+    }
+}
+
 /// This codec allows stacking of:
 /// - noise codec
 /// - user specified codec
@@ -148,14 +188,23 @@ pub struct CompoundCodec<U> {
     noise_codec: Option<Codec>,
     /// User specified codec that is provided with decrypted data
     l2_codec: U,
+    /// Optional accounting of the traffic on the wire (including noise encryption overhead)
+    byte_counter: Option<ByteCounter>,
 }
 
 impl<U> CompoundCodec<U>
 where
     U: Default,
 {
-    /// TODO add l2_codec as parameter
     pub fn new(noise_codec: Option<Codec>) -> Self {
+        Self::with_l2_codec(noise_codec, U::default())
+    }
+}
+
+impl<U> CompoundCodec<U> {
+    /// Builds the codec with explicitly configured `l2_codec` (e.g. a V2 codec with
+    /// non-default serialization mode)
+    pub fn with_l2_codec(noise_codec: Option<Codec>, l2_codec: U) -> Self {
         if let Some(codec) = noise_codec.as_ref() {
             assert!(
                 codec.is_in_transport_mode(),
@@ -164,9 +213,29 @@ where
         }
         Self {
             noise_codec,
-            l2_codec: U::default(),
+            l2_codec,
+            byte_counter: None,
         }
     }
+
+    /// Codec of the frames carried by the (optionally encrypted) connection
+    pub fn l2_codec_mut(&mut self) -> &mut U {
+        &mut self.l2_codec
+    }
+
+    /// Accounts all subsequently received and sent bytes in `byte_counter`
+    pub fn set_byte_counter(&mut self, byte_counter: ByteCounter) {
+        self.byte_counter = Some(byte_counter);
+    }
+
+    /// Keying material of the noise session, fails when the codec doesn't use noise encryption.
+    /// See `TransportMode::export_keying_material()`
+    pub fn export_keying_material(&self, label: &str, len: usize) -> Result<Vec<u8>> {
+        self.noise_codec
+            .as_ref()
+            .ok_or_else(|| Error::Noise("Connection is not noise encrypted".to_string()))?
+            .export_keying_material(label, len)
+    }
 }
 
 /// Default is required e.g. by ii_wire::Framing, TODO: consider refactoring ii-wire to drop this
@@ -192,6 +261,7 @@ where
         &mut self,
         src: &mut BytesMut,
     ) -> std::result::Result<Option<Self::Item>, Self::Error> {
+        let src_len = src.len();
         let frame_result = match self.noise_codec {
             Some(ref mut noise_codec) => noise_codec
                 .decode(src)?
@@ -202,6 +272,9 @@ where
                 .transpose(),
             None => self.l2_codec.decode(src),
         };
+        if let Some(byte_counter) = self.byte_counter.as_ref() {
+            byte_counter.account_rx(src_len - src.len());
+        }
         // TODO not sure, why the compiler cannot see that there actually is From<E> for Error
         // that the Into has been tailored from.
         frame_result.map_err(Into::into)
@@ -223,10 +296,14 @@ where
             .encode(item, &mut l2_encoded_frame)
             .map_err(Into::into)?;
 
+        let dst_len = dst.len();
         match self.noise_codec {
             Some(ref mut noise_codec) => noise_codec.encode(l2_encoded_frame, dst)?,
             None => dst.unsplit(l2_encoded_frame),
         }
+        if let Some(byte_counter) = self.byte_counter.as_ref() {
+            byte_counter.account_tx(dst.len() - dst_len);
+        }
         Ok(())
     }
 }
@@ -293,6 +370,123 @@ mod test {
         );
     }
 
+    /// Keying material is available only from a noise codec in transport mode
+    #[test]
+    fn compound_codec_export_keying_material() {
+        assert!(Codec::default()
+            .export_keying_material("label", 32)
+            .is_err());
+        assert!(CompoundCodec::<v2::framing::codec::Codec>::new(None)
+            .export_keying_material("label", 32)
+            .is_err());
+
+        let (initiator_codec, responder_codec) = build_transport_codecs(
+            super::super::RekeyPolicy::never(),
+            super::super::RekeyPolicy::never(),
+        );
+        let initiator_codec =
+            CompoundCodec::<v2::framing::codec::Codec>::new(Some(initiator_codec));
+        let responder_codec =
+            CompoundCodec::<v2::framing::codec::Codec>::new(Some(responder_codec));
+        assert_eq!(
+            initiator_codec
+                .export_keying_material("label", 32)
+                .expect("BUG: cannot export keying material"),
+            responder_codec
+                .export_keying_material("label", 32)
+                .expect("BUG: cannot export keying material")
+        );
+    }
+
+    /// Builds initiator and responder codecs in transport mode with the specified rekey policies
+    fn build_transport_codecs(
+        initiator_rekey_policy: super::super::RekeyPolicy,
+        responder_rekey_policy: super::super::RekeyPolicy,
+    ) -> (Codec, Codec) {
+        let mut initiator_codec = Codec::default();
+        let mut responder_codec = Codec::default();
+
+        let (initiator_transport_mode, responder_transport_mode) =
+            super::super::test::perform_handshake();
+
+        initiator_codec
+            .set_transport_mode(initiator_transport_mode.with_rekey_policy(initiator_rekey_policy));
+        responder_codec
+            .set_transport_mode(responder_transport_mode.with_rekey_policy(responder_rekey_policy));
+        (initiator_codec, responder_codec)
+    }
+
+    /// Encodes a message of `len` bytes with `sender` and decodes it with `receiver`
+    fn transfer_message(
+        sender: &mut Codec,
+        receiver: &mut Codec,
+        len: usize,
+    ) -> std::result::Result<(), Error> {
+        let expected_frame = BytesMut::from(&vec![0x5a; len][..]);
+        let mut buffer = BytesMut::new();
+        sender
+            .encode(expected_frame.clone(), &mut buffer)
+            .expect("BUG: codec failed to encode message");
+        let decoded_frame = receiver
+            .decode(&mut buffer)?
+            .expect("BUG: codec provided incomplete message");
+        assert_eq!(expected_frame, decoded_frame, "Frames don't match");
+        Ok(())
+    }
+
+    /// Verify that both parties rekey each direction independently and keep communicating
+    #[test]
+    fn noise_codec_rekey_after_messages() {
+        let rekey_policy = super::super::RekeyPolicy::after_messages(3);
+        let (mut initiator_codec, mut responder_codec) =
+            build_transport_codecs(rekey_policy, rekey_policy);
+
+        for i in 0..10 {
+            transfer_message(&mut initiator_codec, &mut responder_codec, 4)
+                .expect("BUG: responder failed to decode message");
+            // Make the responder direction rekey at different points in time
+            if i % 2 == 0 {
+                transfer_message(&mut responder_codec, &mut initiator_codec, 4)
+                    .expect("BUG: initiator failed to decode message");
+            }
+        }
+    }
+
+    /// Verify that rekeying based on the amount of encrypted bytes works for messages of various
+    /// sizes
+    #[test]
+    fn noise_codec_rekey_after_bytes() {
+        let rekey_policy = super::super::RekeyPolicy::after_bytes(100);
+        let (mut initiator_codec, mut responder_codec) =
+            build_transport_codecs(rekey_policy, rekey_policy);
+
+        for len in &[1, 50, 120, 7, 33, 99, 0, 300] {
+            transfer_message(&mut initiator_codec, &mut responder_codec, *len)
+                .expect("BUG: responder failed to decode message");
+            transfer_message(&mut responder_codec, &mut initiator_codec, *len)
+                .expect("BUG: initiator failed to decode message");
+        }
+    }
+
+    /// Verify that a party that doesn't rekey cannot decrypt messages once the counter party has
+    /// rekeyed
+    #[test]
+    fn noise_codec_rekey_policy_mismatch() {
+        let (mut initiator_codec, mut responder_codec) = build_transport_codecs(
+            super::super::RekeyPolicy::after_messages(2),
+            super::super::RekeyPolicy::never(),
+        );
+
+        for _ in 0..2 {
+            transfer_message(&mut initiator_codec, &mut responder_codec, 4)
+                .expect("BUG: responder failed to decode message");
+        }
+        assert!(
+            transfer_message(&mut initiator_codec, &mut responder_codec, 4).is_err(),
+            "BUG: message encrypted with a new key has been decrypted with the old one"
+        );
+    }
+
     /// Attempt to build a V2 CompoundCodec that is still in handshake mode (=contains
     /// no noise transport) must result in panic
     #[test]
@@ -366,4 +560,29 @@ mod test {
         );
         run_compound_codec_with_noise(payload);
     }
+
+    /// Verify that `ByteCounter` accounts the bytes encoded/decoded by `CompoundCodec`
+    #[test]
+    fn compound_codec_byte_counter() {
+        let byte_counter = ByteCounter::default();
+        let mut codec = CompoundCodec::<v2::framing::codec::Codec>::new(None);
+        codec.set_byte_counter(byte_counter.clone());
+
+        let mut payload = BytesMut::new();
+        payload.extend_from_slice(&[1, 2, 3, 4]);
+        let frame = v2::framing::Frame::from_serialized_payload(true, 0, 0x16, payload);
+        let mut buffer = BytesMut::new();
+        codec
+            .encode(frame, &mut buffer)
+            .expect("BUG: Codec failed to encode message");
+        let frame_len = buffer.len() as u64;
+        assert_eq!(byte_counter.tx_bytes(), frame_len);
+        assert_eq!(byte_counter.rx_bytes(), 0);
+
+        codec
+            .decode(&mut buffer)
+            .expect("BUG: Codec failed to decode message")
+            .expect("BUG: Codec provided incomplete message");
+        assert_eq!(byte_counter.rx_bytes(), frame_len);
+    }
 }
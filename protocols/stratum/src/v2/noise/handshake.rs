@@ -25,16 +25,39 @@
 use bytes::BytesMut;
 use snow::HandshakeState;
 use std::convert::{TryFrom, TryInto};
-use std::time;
+use std::{fmt, time};
 
 use futures::prelude::*;
 use ii_async_utils::FutureExt;
+use tokio::io::{AsyncRead, AsyncWrite};
 
+use super::negotiation::EncryptionAlgorithm;
 use crate::error::{Error, Result};
 
+/// Default deadline for completing the whole handshake
+pub const DEFAULT_HANDSHAKE_TIMEOUT: time::Duration = time::Duration::from_secs(10);
+
+/// Stage of the handshake, used for reporting where the handshake failed
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HandshakeStage {
+    /// Negotiation of the encryption algorithm
+    Negotiation,
+    /// Exchange of the noise handshake messages including authentication of the Responder
+    KeyExchange,
+}
+
+impl fmt::Display for HandshakeStage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Negotiation => write!(f, "negotiation"),
+            Self::KeyExchange => write!(f, "key exchange"),
+        }
+    }
+}
+
 /// Handshake message
 #[derive(Debug, Clone, PartialEq)]
-pub(super) struct Message {
+pub struct Message {
     pub(super) inner: BytesMut,
 }
 
@@ -48,7 +71,7 @@ impl Message {
 /// provided message (if any)
 #[derive(Debug, PartialEq)]
 #[allow(clippy::large_enum_variant)]
-pub(super) enum StepResult {
+pub enum StepResult {
     /// The object should receive a noise message and pass it for processing in the next step
     ReceiveMessage,
     /// Go immediately to the next step with this message
@@ -64,8 +87,9 @@ pub(super) enum StepResult {
     Done(Option<super::auth::Certificate>),
 }
 
-/// Objects that can perform 1 handshake step implement this trait
-pub(super) trait Step {
+/// Objects that can perform 1 handshake step implement this trait. The trait is public only to
+/// allow its use in bounds of `HandshakeMachine`, it cannot be implemented outside of this crate
+pub trait Step {
     /// Proceeds with the handshake and processes an optional incoming message - `in_msg` and
     /// generates a new handshake message to be sent out
     ///
@@ -76,95 +100,305 @@ pub(super) trait Step {
 
     /// Transforms step into the handshake state
     fn into_handshake_state(self) -> HandshakeState;
+
+    /// Algorithm agreed upon during negotiation, `None` if the negotiation hasn't happened yet
+    fn encryption_algorithm(&self) -> Option<EncryptionAlgorithm>;
+
+    /// Stage that the handshake is currently in
+    fn stage(&self) -> HandshakeStage;
+
+    /// Deadline for completing the whole handshake
+    fn handshake_timeout(&self) -> time::Duration {
+        DEFAULT_HANDSHAKE_TIMEOUT
+    }
+
+    /// Rekeying policy of the transport mode resulting from this handshake
+    fn rekey_policy(&self) -> super::RekeyPolicy {
+        super::RekeyPolicy::default()
+    }
 }
 
-/// The purpose of this object is to interpret the `StepResult` instructions while driving the
-/// inner handshake step object. This typically requires sending results down the noise stream
-/// and receiving handshake messages. This is done until the handshake is complete or fails
-pub(super) struct Handshake<T> {
+/// Action that the caller of `HandshakeMachine::advance()` has to perform
+#[derive(Debug, PartialEq)]
+pub enum HandshakeAction {
+    /// Send the message to the counter party and call `advance(None)`
+    Send(BytesMut),
+    /// Send the message to the counter party and call `advance()` with its reply
+    SendAndReceive(BytesMut),
+    /// Call `advance()` with the next message from the counter party
+    Receive,
+    /// The handshake is complete, the machine can be switched to transport mode
+    Done,
+}
+
+/// Sans-IO noise handshake. The machine only processes handshake messages and tells the caller
+/// what to do next, it's up to the caller to deliver the messages over any transport. Each
+/// message has to be delivered as a whole, e.g. with length delimited framing used by `Codec`
+/// or as a single WebSocket message.
+///
+/// Once the handshake is done, the resulting `TransportMode` can encrypt/decrypt messages
+/// without any I/O, too.
+pub struct HandshakeMachine<T> {
     handshake_step: T,
+    /// Certificate of the remote party, available once the Initiator completes the handshake
+    certificate: Option<super::auth::Certificate>,
+    /// The last action requested a message from the counter party
+    awaiting_message: bool,
+    done: bool,
+}
+
+impl<T> HandshakeMachine<T>
+where
+    T: Step,
+{
+    /// Builds the machine for a handshake party (`Initiator` or `Responder`)
+    pub fn new(handshake_step: T) -> Self {
+        Self {
+            handshake_step,
+            certificate: None,
+            awaiting_message: false,
+            done: false,
+        }
+    }
+
+    /// Processes an optional incoming message `in_msg` and yields the next action. Advancing
+    /// without a message while the machine awaits one just repeats `HandshakeAction::Receive`
+    pub fn advance(&mut self, in_msg: Option<BytesMut>) -> Result<HandshakeAction> {
+        if self.done {
+            return Err(Error::Noise(
+                "Noise handshake is already complete".to_string(),
+            ));
+        }
+        if self.awaiting_message && in_msg.is_none() {
+            return Ok(HandshakeAction::Receive);
+        }
+        self.awaiting_message = false;
+        let mut in_msg = in_msg.map(Message::new);
+        loop {
+            match self.handshake_step.step(in_msg.take(), BytesMut::new())? {
+                StepResult::ReceiveMessage => {
+                    self.awaiting_message = true;
+                    return Ok(HandshakeAction::Receive);
+                }
+                StepResult::NextStep(message) => in_msg = Some(message),
+                StepResult::ExpectReply(out_msg) => {
+                    self.awaiting_message = true;
+                    return Ok(HandshakeAction::SendAndReceive(out_msg.inner));
+                }
+                StepResult::NoMoreReply(out_msg) => {
+                    return Ok(HandshakeAction::Send(out_msg.inner))
+                }
+                StepResult::Done(certificate) => {
+                    self.certificate = certificate;
+                    self.done = true;
+                    return Ok(HandshakeAction::Done);
+                }
+            }
+        }
+    }
+
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    /// Stage that the handshake is currently in
+    pub fn stage(&self) -> HandshakeStage {
+        self.handshake_step.stage()
+    }
+
+    /// Encryption algorithm agreed upon during negotiation
+    pub fn encryption_algorithm(&self) -> Option<super::negotiation::EncryptionAlgorithm> {
+        self.handshake_step.encryption_algorithm()
+    }
+
+    /// Certificate of the Responder, only the Initiator receives it
+    pub fn certificate(&self) -> Option<&super::auth::Certificate> {
+        self.certificate.as_ref()
+    }
+
+    fn handshake_timeout(&self) -> time::Duration {
+        self.handshake_step.handshake_timeout()
+    }
+
+    fn check_done(&self) -> Result<()> {
+        if self.done {
+            Ok(())
+        } else {
+            Err(Error::Noise("Noise handshake is not complete".to_string()))
+        }
+    }
+
+    /// Switches the completed handshake into transport mode
+    pub fn into_transport_mode(self) -> Result<super::TransportMode> {
+        self.check_done()?;
+        let rekey_policy = self.handshake_step.rekey_policy();
+        super::TransportMode::from_handshake_state(self.handshake_step.into_handshake_state())
+            .map(|transport_mode| transport_mode.with_rekey_policy(rekey_policy))
+    }
+
+    /// Same as `into_transport_mode()` and additionally provides summary of the handshake
+    pub fn into_transport_mode_with_summary(
+        self,
+    ) -> Result<(super::TransportMode, super::HandshakeSummary)> {
+        self.check_done()?;
+        let encryption_algorithm = self
+            .handshake_step
+            .encryption_algorithm()
+            .expect("BUG: handshake completed without negotiation");
+        let rekey_policy = self.handshake_step.rekey_policy();
+        let handshake_state = self.handshake_step.into_handshake_state();
+        let summary = super::HandshakeSummary {
+            encryption_algorithm,
+            remote_static_key: handshake_state.get_remote_static().map(<[u8]>::to_vec),
+            handshake_hash: handshake_state.get_handshake_hash().to_vec(),
+        };
+        let transport_mode = super::TransportMode::from_handshake_state(handshake_state)?
+            .with_rekey_policy(rekey_policy);
+        Ok((transport_mode, summary))
+    }
+}
+
+/// The purpose of this object is to drive the sans-IO `HandshakeMachine` over a noise framed
+/// stream. This requires sending results down the noise stream and receiving handshake messages.
+/// This is done until the handshake is complete or fails
+pub(super) struct Handshake<T> {
+    machine: HandshakeMachine<T>,
 }
 
 impl<T> Handshake<T>
 where
     T: Step,
 {
+    /// Timeout for sending or receiving a single handshake message
     const HANDSHAKE_TIMEOUT: time::Duration = time::Duration::from_secs(2);
 
     pub(super) fn new(handshake_step: T) -> Self {
-        Self { handshake_step }
+        Self {
+            machine: HandshakeMachine::new(handshake_step),
+        }
     }
 
     /// Helper that receives 1 handshake message
-    async fn receive_message(
+    async fn receive_message<S>(
         &self,
-        handshake_stream: &mut super::NoiseFramedTcpStream,
-    ) -> Result<Message> {
-        let handshake_frame: BytesMut = handshake_stream
+        handshake_stream: &mut super::NoiseFramedStream<S>,
+    ) -> Result<BytesMut>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        handshake_stream
             .next()
             .timeout(Self::HANDSHAKE_TIMEOUT)
-            .await?
+            .await
+            .map_err(|_| self.timeout_error())?
             // Convert optional frame into an error, unwrap it, and unwrap the
             // payload, too
-            .ok_or_else(|| Error::Handshake("Noise handshake Connection shutdown".to_string()))??;
-        Ok(Message::new(handshake_frame))
+            .ok_or_else(|| Error::Handshake("Noise handshake Connection shutdown".to_string()))
+            .map_err(|e| self.stage_error(e))?
+            .map_err(|e| self.stage_error(e))
+    }
+
+    /// Helper that sends 1 handshake message
+    async fn send_message<S>(
+        &self,
+        handshake_stream: &mut super::NoiseFramedStream<S>,
+        message: BytesMut,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        handshake_stream
+            .send(message)
+            .timeout(Self::HANDSHAKE_TIMEOUT)
+            .await
+            .map_err(|_| self.timeout_error())?
+            .map_err(|e| self.stage_error(e))
+    }
+
+    fn timeout_error(&self) -> Error {
+        Error::HandshakeTimeout(self.machine.stage())
+    }
+
+    fn stage_error(&self, e: Error) -> Error {
+        Error::HandshakeFailed(self.machine.stage(), Box::new(e))
     }
 
-    pub(super) async fn complete_handshake(
+    /// Drives the handshake until it is complete, the whole handshake has to finish within the
+    /// timeout provided by the handshake step
+    pub(super) async fn complete_handshake<S>(
         &mut self,
-        handshake_stream: &mut super::NoiseFramedTcpStream,
-    ) -> Result<Option<super::auth::Certificate>> {
-        let mut in_msg: Option<Message> = None;
+        handshake_stream: &mut super::NoiseFramedStream<S>,
+    ) -> Result<Option<super::auth::Certificate>>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let handshake_timeout = self.machine.handshake_timeout();
+        match self
+            .drive_handshake(handshake_stream)
+            .timeout(handshake_timeout)
+            .await
+        {
+            Ok(result) => result?,
+            Err(_) => return Err(self.timeout_error()),
+        };
+        Ok(self.machine.certificate().cloned())
+    }
 
-        let certificate = loop {
-            let handshake_buf: BytesMut = BytesMut::new();
+    async fn drive_handshake<S>(
+        &mut self,
+        handshake_stream: &mut super::NoiseFramedStream<S>,
+    ) -> Result<()>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut in_msg: Option<BytesMut> = None;
 
-            match self
-                .handshake_step
-                .step((&mut in_msg).take(), handshake_buf)?
-            {
-                // Just wait for an incoming handshake message
-                StepResult::ReceiveMessage => {
-                    let handshake_message = self.receive_message(handshake_stream).await?;
-                    (&mut in_msg).replace(handshake_message);
-                }
-                StepResult::NextStep(message) => {
-                    (&mut in_msg).replace(message);
+        loop {
+            let action = self
+                .machine
+                .advance(in_msg.take())
+                .map_err(|e| self.stage_error(e))?;
+            match action {
+                HandshakeAction::Send(out_msg) => {
+                    self.send_message(handshake_stream, out_msg).await?;
                 }
                 // Send out specified messages and wait for response
-                StepResult::ExpectReply(out_msg) => {
-                    handshake_stream
-                        .send(out_msg.inner)
-                        .timeout(Self::HANDSHAKE_TIMEOUT)
-                        .await??;
-
-                    let handshake_message = self.receive_message(handshake_stream).await?;
-                    (&mut in_msg).replace(handshake_message);
-                }
-                StepResult::NoMoreReply(out_msg) => {
-                    handshake_stream
-                        .send(out_msg.inner)
-                        .timeout(Self::HANDSHAKE_TIMEOUT)
-                        .await??;
+                HandshakeAction::SendAndReceive(out_msg) => {
+                    self.send_message(handshake_stream, out_msg).await?;
+                    in_msg = Some(self.receive_message(handshake_stream).await?);
                 }
-                // Initiator is now finalized
-                StepResult::Done(certificate) => {
-                    break (certificate);
+                // Just wait for an incoming handshake message
+                HandshakeAction::Receive => {
+                    in_msg = Some(self.receive_message(handshake_stream).await?);
                 }
-            };
-        };
-
-        Ok(certificate)
+                HandshakeAction::Done => return Ok(()),
+            }
+        }
     }
 
     /// Completes the handshake and consumes it transforming it into transport mode
-    pub(super) async fn run(
+    pub(super) async fn run<S>(
         mut self,
-        handshake_stream: &mut super::NoiseFramedTcpStream,
-    ) -> Result<super::TransportMode> {
+        handshake_stream: &mut super::NoiseFramedStream<S>,
+    ) -> Result<super::TransportMode>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
         self.complete_handshake(handshake_stream).await?;
         self.try_into()
     }
+
+    /// Same as `run()` and additionally provides summary of the completed handshake
+    pub(super) async fn run_with_summary<S>(
+        mut self,
+        handshake_stream: &mut super::NoiseFramedStream<S>,
+    ) -> Result<(super::TransportMode, super::HandshakeSummary)>
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.complete_handshake(handshake_stream).await?;
+        self.machine.into_transport_mode_with_summary()
+    }
 }
 
 impl<T> TryFrom<Handshake<T>> for super::TransportMode
@@ -174,11 +408,6 @@ where
     type Error = crate::error::Error;
 
     fn try_from(handshake: Handshake<T>) -> std::result::Result<Self, Self::Error> {
-        handshake
-            .handshake_step
-            .into_handshake_state()
-            .into_transport_mode()
-            .map_err(Into::into)
-            .map(super::TransportMode::new)
+        handshake.machine.into_transport_mode()
     }
 }
@@ -0,0 +1,217 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Protection against handshake floods. The limiter caps the number of handshakes in progress
+//! and lends each of them a message buffer from a pool, so that the memory used by handshakes
+//! doesn't grow with the number of connecting clients.
+
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+
+use super::MAX_MESSAGE_SIZE;
+
+#[derive(Debug)]
+struct LimiterState {
+    max_in_flight: usize,
+    in_flight: AtomicUsize,
+    rejected: AtomicU64,
+    /// Buffers returned by completed handshakes, at most `max_in_flight` of them are retained
+    buffer_pool: Mutex<Vec<Vec<u8>>>,
+}
+
+/// Limits the number of concurrently running handshakes. Clones share the same limit.
+#[derive(Debug, Clone)]
+pub struct HandshakeLimiter {
+    state: Arc<LimiterState>,
+}
+
+impl HandshakeLimiter {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            state: Arc::new(LimiterState {
+                max_in_flight,
+                in_flight: AtomicUsize::new(0),
+                rejected: AtomicU64::new(0),
+                buffer_pool: Mutex::new(Vec::new()),
+            }),
+        }
+    }
+
+    /// Allocates `count` buffers (at most `max_in_flight`) upfront so that no allocation takes
+    /// place when handshakes start
+    pub fn with_preallocated_buffers(self, count: usize) -> Self {
+        {
+            let mut buffer_pool = self.lock_buffer_pool();
+            let count = count.min(self.state.max_in_flight);
+            while buffer_pool.len() < count {
+                buffer_pool.push(vec![0u8; MAX_MESSAGE_SIZE]);
+            }
+        }
+        self
+    }
+
+    /// Provides a permit for running 1 handshake or `None` when the limit has been reached. The
+    /// rejection is accounted in `rejected()`
+    pub fn try_acquire(&self) -> Option<HandshakePermit> {
+        let max_in_flight = self.state.max_in_flight;
+        let acquired = self
+            .state
+            .in_flight
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |in_flight| {
+                if in_flight < max_in_flight {
+                    Some(in_flight + 1)
+                } else {
+                    None
+                }
+            })
+            .is_ok();
+        if !acquired {
+            self.state.rejected.fetch_add(1, Ordering::Relaxed);
+            return None;
+        }
+        let buffer = self
+            .lock_buffer_pool()
+            .pop()
+            .unwrap_or_else(|| vec![0u8; MAX_MESSAGE_SIZE]);
+        Some(HandshakePermit {
+            state: self.state.clone(),
+            buffer,
+        })
+    }
+
+    pub fn max_in_flight(&self) -> usize {
+        self.state.max_in_flight
+    }
+
+    /// Number of handshakes that currently hold a permit
+    pub fn in_flight(&self) -> usize {
+        self.state.in_flight.load(Ordering::Acquire)
+    }
+
+    /// Total number of handshakes that have been refused a permit
+    pub fn rejected(&self) -> u64 {
+        self.state.rejected.load(Ordering::Relaxed)
+    }
+
+    /// Number of buffers ready for reuse
+    pub fn pooled_buffers(&self) -> usize {
+        self.lock_buffer_pool().len()
+    }
+
+    fn lock_buffer_pool(&self) -> std::sync::MutexGuard<'_, Vec<Vec<u8>>> {
+        self.state
+            .buffer_pool
+            .lock()
+            .expect("BUG: handshake buffer pool lock poisoned")
+    }
+}
+
+/// Permit for running 1 handshake, dropping it releases the slot and returns the buffer back to
+/// the pool
+#[derive(Debug)]
+pub struct HandshakePermit {
+    state: Arc<LimiterState>,
+    buffer: Vec<u8>,
+}
+
+impl HandshakePermit {
+    pub(super) fn buffer(&mut self) -> &mut [u8] {
+        &mut self.buffer[..]
+    }
+}
+
+impl Drop for HandshakePermit {
+    fn drop(&mut self) {
+        if let Ok(mut buffer_pool) = self.state.buffer_pool.lock() {
+            if buffer_pool.len() < self.state.max_in_flight {
+                buffer_pool.push(std::mem::take(&mut self.buffer));
+            }
+        }
+        self.state.in_flight.fetch_sub(1, Ordering::AcqRel);
+    }
+}
+
+/// Buffer for processing handshake messages, either owned by the handshake or borrowed from
+/// the pool of `HandshakeLimiter`
+#[derive(Debug)]
+pub(super) enum HandshakeBuffer {
+    Owned(Vec<u8>),
+    Pooled(HandshakePermit),
+}
+
+impl HandshakeBuffer {
+    /// Provides the buffer, the owned variant is allocated on first use
+    pub(super) fn get(&mut self) -> &mut [u8] {
+        match self {
+            Self::Owned(buffer) => {
+                if buffer.is_empty() {
+                    buffer.resize(MAX_MESSAGE_SIZE, 0);
+                }
+                &mut buffer[..]
+            }
+            Self::Pooled(permit) => permit.buffer(),
+        }
+    }
+}
+
+impl Default for HandshakeBuffer {
+    fn default() -> Self {
+        Self::Owned(Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn limiter_caps_in_flight_handshakes() {
+        let limiter = HandshakeLimiter::new(2);
+        let first = limiter.try_acquire().expect("BUG: first permit refused");
+        let _second = limiter.try_acquire().expect("BUG: second permit refused");
+        assert_eq!(limiter.in_flight(), 2);
+        assert!(limiter.try_acquire().is_none());
+        assert!(limiter.try_acquire().is_none());
+        assert_eq!(limiter.rejected(), 2);
+
+        drop(first);
+        assert_eq!(limiter.in_flight(), 1);
+        assert!(limiter.try_acquire().is_some());
+        assert_eq!(limiter.rejected(), 2);
+    }
+
+    #[test]
+    fn limiter_reuses_buffers() {
+        let limiter = HandshakeLimiter::new(2).with_preallocated_buffers(5);
+        assert_eq!(limiter.pooled_buffers(), 2);
+
+        let mut permit = limiter.try_acquire().expect("BUG: permit refused");
+        assert_eq!(limiter.pooled_buffers(), 1);
+        assert_eq!(permit.buffer().len(), MAX_MESSAGE_SIZE);
+        let buffer_ptr = permit.buffer().as_ptr();
+        drop(permit);
+        assert_eq!(limiter.pooled_buffers(), 2);
+
+        let mut permit = limiter.try_acquire().expect("BUG: permit refused");
+        assert_eq!(permit.buffer().as_ptr(), buffer_ptr);
+    }
+}
@@ -36,8 +36,18 @@ pub struct NoiseParamsBuilder {
 
 impl NoiseParamsBuilder {
     pub fn new(chosen_algorithm: EncryptionAlgorithm) -> Self {
+        Self::with_pattern("NX", chosen_algorithm)
+    }
+
+    /// Params for the handshake that mixes a pre-shared key into the first message (psk0
+    /// modifier). The responder thus verifies that the initiator knows the key right away
+    pub fn new_with_psk(chosen_algorithm: EncryptionAlgorithm) -> Self {
+        Self::with_pattern("NXpsk0", chosen_algorithm)
+    }
+
+    fn with_pattern(pattern: &str, chosen_algorithm: EncryptionAlgorithm) -> Self {
         Self {
-            params: format!("Noise_NX_25519_{:?}_BLAKE2s", chosen_algorithm)
+            params: format!("Noise_{}_25519_{:?}_BLAKE2s", pattern, chosen_algorithm)
                 .parse()
                 .expect("BUG: cannot parse noise parameters"),
         }
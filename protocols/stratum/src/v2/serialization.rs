@@ -1,5 +1,6 @@
 //! Stratum V2 binary (de)serializers with Serde
 
+use std::cell::RefCell;
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::fmt;
@@ -8,12 +9,17 @@ use std::marker::PhantomData;
 use std::result::Result as StdResult;
 use std::slice;
 
+use bytes::Bytes;
 use serde::de::Deserializer as _;
-use serde::de::{DeserializeSeed, EnumAccess, IntoDeserializer, SeqAccess, VariantAccess};
+use serde::de::{
+    DeserializeOwned, DeserializeSeed, EnumAccess, IntoDeserializer, SeqAccess, VariantAccess,
+};
 use serde::ser::Impossible;
 use serde::{de, ser, Deserialize, Serialize};
 use thiserror::Error;
 
+use super::types::U24;
+
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("Sequence too long")]
@@ -81,6 +87,10 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
     type SerializeStruct = Self;
     type SerializeStructVariant = Self;
 
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
     fn serialize_bool(self, v: bool) -> Result<()> {
         let byte = if v { &[1u8] } else { &[0u8] };
         self.write(byte)
@@ -185,6 +195,7 @@ impl<'a, W: io::Write> ser::Serializer for &'a mut Serializer<W> {
             "Bytes1_255" => value.serialize(SizedSeqEmitter::<W, u8>::new(self)),
             "Bytes0_64k" => value.serialize(SizedSeqEmitter::<W, u16>::new(self)),
             "Bytes1_64k" => value.serialize(SizedSeqEmitter::<W, u16>::new(self)),
+            "Bytes0_16M" => value.serialize(SizedSeqEmitter::<W, U24>::new(self)),
 
             "Seq0_255" => value.serialize(SizedSeqEmitter::<W, u8>::new(self)),
             "Seq0_64k" => value.serialize(SizedSeqEmitter::<W, u16>::new(self)),
@@ -600,6 +611,11 @@ impl<'de> Deserializer<'de> {
         Ok(u16::from_le_bytes(bytes))
     }
 
+    #[inline]
+    fn read_u24(&mut self) -> Result<U24> {
+        U24::deserialize(&mut *self)
+    }
+
     #[inline]
     fn read_u32(&mut self) -> Result<u32> {
         let bytes = self.read_bytes(4)?;
@@ -670,6 +686,10 @@ impl<'de> Deserializer<'de> {
 impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
     type Error = Error;
 
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
     fn deserialize_any<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
         Err(Error::Unsupported("Any / Dynamic"))
     }
@@ -797,6 +817,9 @@ impl<'de, 'a> de::Deserializer<'de> for &'a mut Deserializer<'de> {
             "Bytes1_255" => self.deserialize_sized_seq(1, 255, Deserializer::read_u8, visitor),
             "Bytes0_64k" => self.deserialize_sized_seq(0, 65535, Deserializer::read_u16, visitor),
             "Bytes1_64k" => self.deserialize_sized_seq(1, 65535, Deserializer::read_u16, visitor),
+            "Bytes0_16M" => {
+                self.deserialize_sized_seq(0, U24::MAX as usize, Deserializer::read_u24, visitor)
+            }
 
             "Seq0_255" => self.deserialize_sized_seq(0, 255, Deserializer::read_u8, visitor),
             "Seq0_64k" => self.deserialize_sized_seq(0, 65535, Deserializer::read_u16, visitor),
@@ -1002,8 +1025,9 @@ impl<'a, 'de> de::Deserializer<'de> for SizedSeqDeserializer<'a, 'de> {
         visitor.visit_string(s.into())
     }
 
-    fn deserialize_bytes<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
-        unreachable!()
+    fn deserialize_bytes<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value> {
+        let bytes = self.deserializer.read_bytes(self.size)?;
+        visitor.visit_borrowed_bytes(bytes)
     }
 
     fn deserialize_byte_buf<V: de::Visitor<'de>>(self, _visitor: V) -> Result<V::Value> {
@@ -1092,6 +1116,81 @@ pub fn from_slice<'a, T: Deserialize<'a>>(bytes: &'a [u8]) -> Result<T> {
     }
 }
 
+/// Encoding of V2 frame payloads. Binary encoding is mandated by the specification, JSON is
+/// intended for debugging purposes only.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SerializationMode {
+    #[default]
+    Binary,
+    Json,
+}
+
+impl SerializationMode {
+    /// Serializes `value` into `writer` using this mode
+    pub fn to_writer<W, T>(self, writer: W, value: &T) -> crate::error::Result<()>
+    where
+        W: io::Write,
+        T: ?Sized + Serialize,
+    {
+        match self {
+            Self::Binary => to_writer(writer, value).map_err(Into::into),
+            Self::Json => serde_json::to_writer(writer, value).map_err(Into::into),
+        }
+    }
+
+    /// Deserializes `T` from `bytes` using this mode
+    pub fn from_slice<'a, T: Deserialize<'a>>(self, bytes: &'a [u8]) -> crate::error::Result<T> {
+        match self {
+            Self::Binary => from_slice(bytes).map_err(Into::into),
+            Self::Json => serde_json::from_slice(bytes).map_err(Into::into),
+        }
+    }
+}
+
+thread_local! {
+    /// Buffer that is currently being deserialized, see `with_source()`
+    static SOURCE: RefCell<Option<Bytes>> = const { RefCell::new(None) };
+}
+
+/// Runs `f` on contents of `source` while `source` is registered as the buffer being
+/// deserialized. Byte sequences (e.g. `Bytes0_64k`) deserialized from it within `f` share memory
+/// with `source` instead of being copied out.
+pub fn with_source<R>(source: &Bytes, f: impl FnOnce(&[u8]) -> R) -> R {
+    /// Restores the previously registered source even if `f` panics
+    struct Restore(Option<Bytes>);
+
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            let previous = self.0.take();
+            SOURCE.with(|source| *source.borrow_mut() = previous);
+        }
+    }
+
+    let _restore = Restore(SOURCE.with(|current| current.borrow_mut().replace(source.clone())));
+    f(&source[..])
+}
+
+/// Zero-copy variant of `from_slice()`, see `with_source()`
+pub fn from_bytes<T: DeserializeOwned>(bytes: &Bytes) -> Result<T> {
+    with_source(bytes, |bytes| from_slice(bytes))
+}
+
+/// Provides `slice` as a reference counted view into the currently registered source buffer,
+/// returns `None` if `slice` doesn't point into it
+pub(crate) fn source_slice(slice: &[u8]) -> Option<Bytes> {
+    SOURCE.with(|source| {
+        source.borrow().as_ref().and_then(|source| {
+            let source_range = source.as_ptr() as usize..source.as_ptr() as usize + source.len();
+            let start = slice.as_ptr() as usize;
+            if source_range.contains(&start) && start + slice.len() <= source_range.end {
+                Some(source.slice_ref(slice))
+            } else {
+                None
+            }
+        })
+    })
+}
+
 // Tests
 
 #[cfg(test)]
@@ -1246,6 +1345,21 @@ mod test {
         assert_eq!(&bytes[..2], &[0xff, 0xff]);
     }
 
+    #[test]
+    fn v2_deserialize_bytes_zero_copy() {
+        let source = Bytes::from_static(&[3, 0, 1, 2, 3, 2, 4, 5]);
+        let (first, second): (Bytes1_64k, Bytes0_32) =
+            from_bytes(&source).expect("BUG: Deserialization failure");
+        assert_eq!(&*first, &[1, 2, 3]);
+        assert_eq!(&*second, &[4, 5]);
+        assert_eq!(Bytes::from(first).as_ptr(), source[2..].as_ptr());
+        assert_eq!(Bytes::from(second).as_ptr(), source[6..].as_ptr());
+
+        // Without registered source, the data is copied out
+        let bytes: Bytes1_64k = from_slice(&source[..5]).expect("BUG: Deserialization failure");
+        assert_ne!(bytes.as_ptr(), source[2..].as_ptr());
+    }
+
     #[test]
     fn v2_deserialize_bytes() {
         let bytes = [3, 0, 1, 2, 3];
@@ -1448,4 +1562,118 @@ mod test {
         let my_data_2: MyData = from_slice(&bytes).expect("BUG: Deserialization failed");
         assert_eq!(my_data, my_data_2);
     }
+
+    #[test]
+    fn v2_serialize_u24() {
+        let value = U24::try_from(0x0a0b0c_u32).expect("BUG: U24 c-tor failed");
+        let bytes = to_vec(&value).expect("BUG: Serialization failed");
+        assert_eq!(&bytes, &[0x0c, 0x0b, 0x0a]);
+        let value_2: U24 = from_slice(&bytes).expect("BUG: Deserialization failed");
+        assert_eq!(value, value_2);
+
+        assert_eq!(
+            serde_json::to_string(&value).expect("BUG: JSON serialization failed"),
+            "658188"
+        );
+
+        assert!(
+            U24::try_from(U24::MAX + 1).is_err(),
+            "BUG: U24 c-tor didn't fail but should have"
+        );
+        assert!(
+            serde_json::from_str::<U24>("16777216").is_err(),
+            "BUG: U24 JSON deserialization didn't fail but should have"
+        );
+    }
+
+    #[test]
+    fn v2_serialize_bytes_16m() {
+        let data = vec![0x5a; 0x10203];
+        let value = Bytes0_16M::try_from(data.clone()).expect("BUG: Bytes0_16M c-tor failed");
+        let bytes = to_vec(&value).expect("BUG: Serialization failed");
+        assert_eq!(&bytes[..3], &[0x03, 0x02, 0x01]);
+        assert_eq!(&bytes[3..], data.as_slice());
+
+        let value_2: Bytes0_16M = from_slice(&bytes).expect("BUG: Deserialization failed");
+        assert_eq!(value, value_2);
+
+        // Length prefix pointing past the end of the input
+        match from_slice::<Bytes0_16M>(&bytes[..bytes.len() - 1]) {
+            Err(Error::EOF) => {}
+            Err(err) => panic!(
+                "Deserialization failed with unexpected error value: {:?}",
+                err
+            ),
+            Ok(_) => panic!("Deserialization didn't fail but should have"),
+        }
+    }
+
+    mod prop {
+        use super::*;
+        use proptest::collection::vec;
+        use proptest::prelude::*;
+
+        fn roundtrip<T>(value: &T)
+        where
+            T: Serialize + serde::de::DeserializeOwned + PartialEq + fmt::Debug,
+        {
+            let bytes = to_vec(value).expect("BUG: Serialization failed");
+            let value_2: T = from_slice(&bytes).expect("BUG: Deserialization failed");
+            assert_eq!(value, &value_2);
+        }
+
+        proptest! {
+            #[test]
+            fn u24_roundtrip(value in 0..=U24::MAX) {
+                let value = U24::try_from(value).expect("BUG: U24 c-tor failed");
+                roundtrip(&value);
+                prop_assert_eq!(to_vec(&value).expect("BUG: Serialization failed").len(), 3);
+            }
+
+            #[test]
+            fn u24_overflow(value in (U24::MAX + 1)..) {
+                prop_assert!(U24::try_from(value).is_err());
+            }
+
+            #[test]
+            fn bytes_roundtrip(data in vec(any::<u8>(), 0..1024)) {
+                roundtrip(&Bytes0_16M::try_from(data.clone()).expect("BUG: Bytes0_16M c-tor failed"));
+                roundtrip(&Bytes0_64k::try_from(data.clone()).expect("BUG: Bytes0_64k c-tor failed"));
+                if data.len() <= 255 {
+                    roundtrip(&Bytes0_255::try_from(data.clone()).expect("BUG: Bytes0_255 c-tor failed"));
+                }
+                if data.len() <= 32 {
+                    roundtrip(&Bytes0_32::try_from(data.clone()).expect("BUG: Bytes0_32 c-tor failed"));
+                }
+                if !data.is_empty() && data.len() <= 32 {
+                    roundtrip(&Bytes1_32::try_from(data).expect("BUG: Bytes1_32 c-tor failed"));
+                }
+            }
+
+            #[test]
+            fn str_roundtrip(s in "\\PC{0,64}") {
+                prop_assume!(s.len() <= 255);
+                roundtrip(&Str0_255::try_from(s).expect("BUG: Str0_255 c-tor failed"));
+            }
+
+            #[test]
+            fn seq_roundtrip(items in vec(any::<u32>(), 0..300)) {
+                roundtrip(&Seq0_64k::try_from(items.clone()).expect("BUG: Seq0_64k c-tor failed"));
+                if items.len() <= 255 {
+                    roundtrip(&Seq0_255::try_from(items).expect("BUG: Seq0_255 c-tor failed"));
+                }
+            }
+
+            #[test]
+            fn nested_seq_roundtrip(items in vec(vec(any::<u8>(), 0..64), 0..16)) {
+                let seq: Seq0_64k<Bytes0_16M> = items
+                    .into_iter()
+                    .map(|item| Bytes0_16M::try_from(item).expect("BUG: Bytes0_16M c-tor failed"))
+                    .collect::<Vec<_>>()
+                    .try_into()
+                    .expect("BUG: Seq0_64k c-tor failed");
+                roundtrip(&seq);
+            }
+        }
+    }
 }
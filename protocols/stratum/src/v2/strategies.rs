@@ -0,0 +1,1136 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Proptest strategies that generate valid instances of all V2 messages. Variable length fields
+//! respect the bounds of their types, long byte sequences and lists are kept short to keep the
+//! tests fast.
+
+use proptest::collection::vec;
+use proptest::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use super::types::*;
+
+/// Upper bound of generated byte sequences that would otherwise allow up to megabytes of data
+const MAX_GENERATED_BYTES: usize = 1024;
+/// Upper bound of generated lists
+const MAX_GENERATED_ITEMS: usize = 64;
+
+/// Finite values only as NaN doesn't compare equal to itself
+pub fn finite_f32() -> impl Strategy<Value = f32> {
+    prop::num::f32::NORMAL | prop::num::f32::SUBNORMAL | prop::num::f32::ZERO
+}
+
+/// Strings of at most `max_len` bytes (not characters)
+fn sized_string(max_len: usize) -> impl Strategy<Value = String> {
+    vec(any::<char>(), 0..=max_len).prop_map(move |chars| {
+        let mut s = String::with_capacity(max_len);
+        for c in chars.into_iter() {
+            if s.len() + c.len_utf8() > max_len {
+                break;
+            }
+            s.push(c);
+        }
+        s
+    })
+}
+
+pub fn str0_32() -> impl Strategy<Value = Str0_32> {
+    sized_string(32).prop_map(Str0_32::from_string)
+}
+
+pub fn str0_255() -> impl Strategy<Value = Str0_255> {
+    sized_string(255).prop_map(Str0_255::from_string)
+}
+
+pub fn bytes0_32() -> impl Strategy<Value = Bytes0_32> {
+    vec(any::<u8>(), 0..=32).prop_map(Bytes0_32::from_vec)
+}
+
+pub fn bytes0_255() -> impl Strategy<Value = Bytes0_255> {
+    vec(any::<u8>(), 0..=255).prop_map(Bytes0_255::from_vec)
+}
+
+pub fn bytes0_64k() -> impl Strategy<Value = Bytes0_64k> {
+    vec(any::<u8>(), 0..=MAX_GENERATED_BYTES).prop_map(Bytes0_64k::from_vec)
+}
+
+pub fn bytes0_16m() -> impl Strategy<Value = Bytes0_16M> {
+    vec(any::<u8>(), 0..=MAX_GENERATED_BYTES).prop_map(Bytes0_16M::from_vec)
+}
+
+pub fn seq0_255<S>(element: S) -> impl Strategy<Value = Seq0_255<S::Value>>
+where
+    S: Strategy,
+    S::Value: Serialize + for<'de> Deserialize<'de>,
+{
+    vec(element, 0..=MAX_GENERATED_ITEMS).prop_map(Seq0_255::from_vec)
+}
+
+pub fn seq0_64k<S>(element: S) -> impl Strategy<Value = Seq0_64k<S::Value>>
+where
+    S: Strategy,
+    S::Value: Serialize + for<'de> Deserialize<'de>,
+{
+    vec(element, 0..=MAX_GENERATED_ITEMS).prop_map(Seq0_64k::from_vec)
+}
+
+pub fn uint256_bytes() -> impl Strategy<Value = Uint256Bytes> {
+    any::<[u8; 32]>().prop_map(Uint256Bytes)
+}
+
+pub fn short_tx_id() -> impl Strategy<Value = ShortTxId> {
+    any::<[u8; 6]>().prop_map(ShortTxId)
+}
+
+pub fn sub_protocol() -> impl Strategy<Value = SubProtocol> {
+    prop_oneof![
+        Just(SubProtocol::Mining),
+        Just(SubProtocol::JobNegotiation),
+        Just(SubProtocol::TemplateDistribution),
+        Just(SubProtocol::JobDistribution),
+    ]
+}
+
+prop_compose! {
+    pub fn device_info()(
+        vendor in str0_255(),
+        hw_rev in str0_255(),
+        fw_ver in str0_255(),
+        dev_id in str0_255(),
+    ) -> DeviceInfo {
+        DeviceInfo { vendor, hw_rev, fw_ver, dev_id }
+    }
+}
+
+pub mod mining {
+    use super::*;
+    use crate::v2::messages::*;
+
+    prop_compose! {
+        pub fn setup_connection()(
+            protocol in sub_protocol(),
+            min_version in any::<u16>(),
+            max_version in any::<u16>(),
+            flags in any::<u32>(),
+            endpoint_host in str0_255(),
+            endpoint_port in any::<u16>(),
+            device in device_info(),
+        ) -> SetupConnection {
+            SetupConnection {
+                protocol,
+                min_version,
+                max_version,
+                flags,
+                endpoint_host,
+                endpoint_port,
+                device,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn setup_connection_success()(
+            used_version in any::<u16>(),
+            flags in any::<u32>(),
+        ) -> SetupConnectionSuccess {
+            SetupConnectionSuccess { used_version, flags }
+        }
+    }
+
+    prop_compose! {
+        pub fn setup_connection_error()(
+            flags in any::<u32>(),
+            code in str0_255(),
+        ) -> SetupConnectionError {
+            SetupConnectionError { flags, code }
+        }
+    }
+
+    prop_compose! {
+        pub fn channel_endpoint_changed()(
+            channel_id in any::<u32>(),
+        ) -> ChannelEndpointChanged {
+            ChannelEndpointChanged { channel_id }
+        }
+    }
+
+    prop_compose! {
+        pub fn open_standard_mining_channel()(
+            req_id in any::<u32>(),
+            user in str0_255(),
+            nominal_hashrate in finite_f32(),
+            max_target in uint256_bytes(),
+        ) -> OpenStandardMiningChannel {
+            OpenStandardMiningChannel { req_id, user, nominal_hashrate, max_target }
+        }
+    }
+
+    prop_compose! {
+        pub fn open_extended_mining_channel()(
+            req_id in any::<u32>(),
+            user in str0_255(),
+            nominal_hashrate in finite_f32(),
+            max_target in uint256_bytes(),
+            min_extranonce_size in any::<u16>(),
+        ) -> OpenExtendedMiningChannel {
+            OpenExtendedMiningChannel {
+                req_id,
+                user,
+                nominal_hashrate,
+                max_target,
+                min_extranonce_size,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn open_extended_mining_channel_success()(
+            request_id in any::<u32>(),
+            channel_id in any::<u32>(),
+            target in uint256_bytes(),
+            extranonce_size in any::<u16>(),
+            extranonce_prefix in bytes0_32(),
+        ) -> OpenExtendedMiningChannelSuccess {
+            OpenExtendedMiningChannelSuccess {
+                request_id,
+                channel_id,
+                target,
+                extranonce_size,
+                extranonce_prefix,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn open_standard_mining_channel_success()(
+            req_id in any::<u32>(),
+            channel_id in any::<u32>(),
+            target in uint256_bytes(),
+            extranonce_prefix in bytes0_32(),
+            group_channel_id in any::<u32>(),
+        ) -> OpenStandardMiningChannelSuccess {
+            OpenStandardMiningChannelSuccess {
+                req_id,
+                channel_id,
+                target,
+                extranonce_prefix,
+                group_channel_id,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn open_mining_channel_error()(
+            req_id in any::<u32>(),
+            code in str0_32(),
+        ) -> OpenMiningChannelError {
+            OpenMiningChannelError { req_id, code }
+        }
+    }
+
+    prop_compose! {
+        pub fn update_channel()(
+            channel_id in any::<u32>(),
+            nominal_hash_rate in finite_f32(),
+            maximum_target in uint256_bytes(),
+        ) -> UpdateChannel {
+            UpdateChannel { channel_id, nominal_hash_rate, maximum_target }
+        }
+    }
+
+    prop_compose! {
+        pub fn update_channel_error()(
+            channel_id in any::<u32>(),
+            error_code in str0_32(),
+        ) -> UpdateChannelError {
+            UpdateChannelError { channel_id, error_code }
+        }
+    }
+
+    prop_compose! {
+        pub fn close_channel()(
+            channel_id in any::<u32>(),
+            reason_code in str0_32(),
+        ) -> CloseChannel {
+            CloseChannel { channel_id, reason_code }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_shares_standard()(
+            channel_id in any::<u32>(),
+            seq_num in any::<u32>(),
+            job_id in any::<u32>(),
+            nonce in any::<u32>(),
+            ntime in any::<u32>(),
+            version in any::<u32>(),
+        ) -> SubmitSharesStandard {
+            SubmitSharesStandard { channel_id, seq_num, job_id, nonce, ntime, version }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_shares_extended()(
+            channel_id in any::<u32>(),
+            seq_num in any::<u32>(),
+            job_id in any::<u32>(),
+            nonce in any::<u32>(),
+            ntime in any::<u32>(),
+            version in any::<u32>(),
+            extranonce in bytes0_32(),
+        ) -> SubmitSharesExtended {
+            SubmitSharesExtended { channel_id, seq_num, job_id, nonce, ntime, version, extranonce }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_shares_success()(
+            channel_id in any::<u32>(),
+            last_seq_num in any::<u32>(),
+            new_submits_accepted_count in any::<u32>(),
+            new_shares_sum in any::<u32>(),
+        ) -> SubmitSharesSuccess {
+            SubmitSharesSuccess {
+                channel_id,
+                last_seq_num,
+                new_submits_accepted_count,
+                new_shares_sum,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_shares_error()(
+            channel_id in any::<u32>(),
+            seq_num in any::<u32>(),
+            code in str0_32(),
+        ) -> SubmitSharesError {
+            SubmitSharesError { channel_id, seq_num, code }
+        }
+    }
+
+    prop_compose! {
+        pub fn new_mining_job()(
+            channel_id in any::<u32>(),
+            job_id in any::<u32>(),
+            future_job in any::<bool>(),
+            version in any::<u32>(),
+            merkle_root in uint256_bytes(),
+        ) -> NewMiningJob {
+            NewMiningJob { channel_id, job_id, future_job, version, merkle_root }
+        }
+    }
+
+    prop_compose! {
+        pub fn new_extended_mining_job()(
+            channel_id in any::<u32>(),
+            job_id in any::<u32>(),
+            future_job in any::<bool>(),
+            version in any::<u32>(),
+            version_rolling_allowed in any::<bool>(),
+            merkle_path in seq0_255(uint256_bytes()),
+            coinbase_tx_prefix in bytes0_64k(),
+            coinbase_tx_suffix in bytes0_64k(),
+        ) -> NewExtendedMiningJob {
+            NewExtendedMiningJob {
+                channel_id,
+                job_id,
+                future_job,
+                version,
+                version_rolling_allowed,
+                merkle_path,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn set_new_prev_hash()(
+            channel_id in any::<u32>(),
+            job_id in any::<u32>(),
+            prev_hash in uint256_bytes(),
+            min_ntime in any::<u32>(),
+            nbits in any::<u32>(),
+        ) -> SetNewPrevHash {
+            SetNewPrevHash { channel_id, job_id, prev_hash, min_ntime, nbits }
+        }
+    }
+
+    prop_compose! {
+        pub fn set_custom_mining_job()(
+            channel_id in any::<u32>(),
+            request_id in any::<u32>(),
+            mining_job_token in bytes0_255(),
+            version in any::<u32>(),
+            prev_hash in uint256_bytes(),
+            min_ntime in any::<u32>(),
+            nbits in any::<u32>(),
+            coinbase_tx_version in any::<u32>(),
+            coinbase_prefix in bytes0_255(),
+            coinbase_tx_input_n_sequence in any::<u32>(),
+            coinbase_tx_value_remaining in any::<u64>(),
+            coinbase_tx_outputs in bytes0_64k(),
+            coinbase_tx_locktime in any::<u32>(),
+            merkle_path in seq0_255(uint256_bytes()),
+            extranonce_size in any::<u16>(),
+            future_job in any::<bool>(),
+        ) -> SetCustomMiningJob {
+            SetCustomMiningJob {
+                channel_id,
+                request_id,
+                mining_job_token,
+                version,
+                prev_hash,
+                min_ntime,
+                nbits,
+                coinbase_tx_version,
+                coinbase_prefix,
+                coinbase_tx_input_n_sequence,
+                coinbase_tx_value_remaining,
+                coinbase_tx_outputs,
+                coinbase_tx_locktime,
+                merkle_path,
+                extranonce_size,
+                future_job,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn set_custom_mining_job_success()(
+            channel_id in any::<u32>(),
+            request_id in any::<u32>(),
+            job_id in any::<u32>(),
+            coinbase_tx_prefix in bytes0_64k(),
+            coinbase_tx_suffix in bytes0_64k(),
+        ) -> SetCustomMiningJobSuccess {
+            SetCustomMiningJobSuccess {
+                channel_id,
+                request_id,
+                job_id,
+                coinbase_tx_prefix,
+                coinbase_tx_suffix,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn set_custom_mining_job_error()(
+            channel_id in any::<u32>(),
+            request_id in any::<u32>(),
+            error_code in str0_255(),
+        ) -> SetCustomMiningJobError {
+            SetCustomMiningJobError { channel_id, request_id, error_code }
+        }
+    }
+
+    prop_compose! {
+        pub fn set_target()(
+            channel_id in any::<u32>(),
+            max_target in uint256_bytes(),
+        ) -> SetTarget {
+            SetTarget { channel_id, max_target }
+        }
+    }
+
+    prop_compose! {
+        pub fn reconnect()(
+            new_host in str0_255(),
+            new_port in any::<u16>(),
+        ) -> Reconnect {
+            Reconnect { new_host, new_port }
+        }
+    }
+
+    prop_compose! {
+        pub fn set_group_channel()(
+            group_channel_id in any::<u32>(),
+            channel_ids in seq0_64k(any::<u32>()),
+        ) -> SetGroupChannel {
+            SetGroupChannel { group_channel_id, channel_ids }
+        }
+    }
+}
+
+pub mod job_negotiation {
+    use super::*;
+    use crate::v2::messages::job_negotiation::*;
+
+    prop_compose! {
+        pub fn allocate_mining_job_token()(
+            user_identifier in str0_255(),
+            request_id in any::<u32>(),
+        ) -> AllocateMiningJobToken {
+            AllocateMiningJobToken { user_identifier, request_id }
+        }
+    }
+
+    prop_compose! {
+        pub fn allocate_mining_job_token_success()(
+            request_id in any::<u32>(),
+            mining_job_token in bytes0_255(),
+            coinbase_output_max_additional_size in any::<u32>(),
+            coinbase_tx_outputs in bytes0_64k(),
+            async_mining_allowed in any::<bool>(),
+        ) -> AllocateMiningJobTokenSuccess {
+            AllocateMiningJobTokenSuccess {
+                request_id,
+                mining_job_token,
+                coinbase_output_max_additional_size,
+                coinbase_tx_outputs,
+                async_mining_allowed,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn commit_mining_job()(
+            request_id in any::<u32>(),
+            mining_job_token in bytes0_255(),
+            version in any::<u32>(),
+            coinbase_tx_version in any::<u32>(),
+            coinbase_prefix in bytes0_255(),
+            coinbase_tx_input_n_sequence in any::<u32>(),
+            coinbase_tx_value_remaining in any::<u64>(),
+            coinbase_tx_outputs in bytes0_64k(),
+            coinbase_tx_locktime in any::<u32>(),
+            min_extranonce_size in any::<u16>(),
+            tx_short_hash_nonce in any::<u64>(),
+            tx_short_hash_list in seq0_64k(short_tx_id()),
+            tx_hash_list_hash in uint256_bytes(),
+            excess_data in bytes0_64k(),
+        ) -> CommitMiningJob {
+            CommitMiningJob {
+                request_id,
+                mining_job_token,
+                version,
+                coinbase_tx_version,
+                coinbase_prefix,
+                coinbase_tx_input_n_sequence,
+                coinbase_tx_value_remaining,
+                coinbase_tx_outputs,
+                coinbase_tx_locktime,
+                min_extranonce_size,
+                tx_short_hash_nonce,
+                tx_short_hash_list,
+                tx_hash_list_hash,
+                excess_data,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn commit_mining_job_success()(
+            request_id in any::<u32>(),
+            new_mining_job_token in bytes0_255(),
+        ) -> CommitMiningJobSuccess {
+            CommitMiningJobSuccess { request_id, new_mining_job_token }
+        }
+    }
+
+    prop_compose! {
+        pub fn commit_mining_job_error()(
+            request_id in any::<u32>(),
+            error_code in str0_255(),
+            error_details in bytes0_64k(),
+        ) -> CommitMiningJobError {
+            CommitMiningJobError { request_id, error_code, error_details }
+        }
+    }
+
+    prop_compose! {
+        pub fn identify_transactions()(
+            request_id in any::<u32>(),
+        ) -> IdentifyTransactions {
+            IdentifyTransactions { request_id }
+        }
+    }
+
+    prop_compose! {
+        pub fn identify_transactions_success()(
+            request_id in any::<u32>(),
+            tx_data_hashes in seq0_64k(uint256_bytes()),
+        ) -> IdentifyTransactionsSuccess {
+            IdentifyTransactionsSuccess { request_id, tx_data_hashes }
+        }
+    }
+
+    prop_compose! {
+        pub fn provide_missing_transactions()(
+            request_id in any::<u32>(),
+            unknown_tx_position_list in seq0_64k(any::<u16>()),
+        ) -> ProvideMissingTransactions {
+            ProvideMissingTransactions { request_id, unknown_tx_position_list }
+        }
+    }
+
+    prop_compose! {
+        pub fn provide_missing_transactions_success()(
+            request_id in any::<u32>(),
+            transaction_list in seq0_64k(bytes0_16m()),
+        ) -> ProvideMissingTransactionsSuccess {
+            ProvideMissingTransactionsSuccess { request_id, transaction_list }
+        }
+    }
+}
+
+pub mod template_distribution {
+    use super::*;
+    use crate::v2::messages::template_distribution::*;
+
+    prop_compose! {
+        pub fn coinbase_output_data_size()(
+            coinbase_output_max_additional_size in any::<u32>(),
+        ) -> CoinbaseOutputDataSize {
+            CoinbaseOutputDataSize { coinbase_output_max_additional_size }
+        }
+    }
+
+    prop_compose! {
+        pub fn new_template()(
+            template_id in any::<u64>(),
+            future_template in any::<bool>(),
+            version in any::<u32>(),
+            coinbase_tx_version in any::<u32>(),
+            coinbase_prefix in bytes0_255(),
+            coinbase_tx_input_sequence in any::<u32>(),
+            coinbase_tx_value_remaining in any::<u64>(),
+            coinbase_tx_outputs_count in any::<u32>(),
+            coinbase_tx_outputs in bytes0_64k(),
+            coinbase_tx_locktime in any::<u32>(),
+            merkle_path in seq0_255(uint256_bytes()),
+        ) -> NewTemplate {
+            NewTemplate {
+                template_id,
+                future_template,
+                version,
+                coinbase_tx_version,
+                coinbase_prefix,
+                coinbase_tx_input_sequence,
+                coinbase_tx_value_remaining,
+                coinbase_tx_outputs_count,
+                coinbase_tx_outputs,
+                coinbase_tx_locktime,
+                merkle_path,
+            }
+        }
+    }
+
+    prop_compose! {
+        pub fn set_new_prev_hash()(
+            template_id in any::<u64>(),
+            prev_hash in uint256_bytes(),
+            header_timestamp in any::<u32>(),
+            n_bits in any::<u32>(),
+            target in uint256_bytes(),
+        ) -> SetNewPrevHash {
+            SetNewPrevHash { template_id, prev_hash, header_timestamp, n_bits, target }
+        }
+    }
+
+    prop_compose! {
+        pub fn request_transaction_data()(
+            template_id in any::<u64>(),
+        ) -> RequestTransactionData {
+            RequestTransactionData { template_id }
+        }
+    }
+
+    prop_compose! {
+        pub fn request_transaction_data_success()(
+            template_id in any::<u64>(),
+            excess_data in bytes0_64k(),
+            transaction_list in seq0_64k(bytes0_16m()),
+        ) -> RequestTransactionDataSuccess {
+            RequestTransactionDataSuccess { template_id, excess_data, transaction_list }
+        }
+    }
+
+    prop_compose! {
+        pub fn request_transaction_data_error()(
+            template_id in any::<u64>(),
+            error_code in str0_255(),
+        ) -> RequestTransactionDataError {
+            RequestTransactionDataError { template_id, error_code }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_solution()(
+            template_id in any::<u64>(),
+            version in any::<u32>(),
+            header_timestamp in any::<u32>(),
+            header_nonce in any::<u32>(),
+            coinbase_tx in bytes0_64k(),
+        ) -> SubmitSolution {
+            SubmitSolution { template_id, version, header_timestamp, header_nonce, coinbase_tx }
+        }
+    }
+}
+
+pub mod telemetry {
+    use super::*;
+    use crate::v2::telemetry::messages::*;
+
+    prop_compose! {
+        pub fn open_telemetry_channel()(
+            req_id in any::<u32>(),
+            dev_id in str0_255(),
+        ) -> OpenTelemetryChannel {
+            OpenTelemetryChannel { req_id, dev_id }
+        }
+    }
+
+    prop_compose! {
+        pub fn open_telemetry_channel_success()(
+            req_id in any::<u32>(),
+            channel_id in any::<u32>(),
+        ) -> OpenTelemetryChannelSuccess {
+            OpenTelemetryChannelSuccess { req_id, channel_id }
+        }
+    }
+
+    prop_compose! {
+        pub fn open_telemetry_channel_error()(
+            req_id in any::<u32>(),
+            code in str0_32(),
+        ) -> OpenTelemetryChannelError {
+            OpenTelemetryChannelError { req_id, code }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_telemetry_data()(
+            channel_id in any::<u32>(),
+            seq_num in any::<u32>(),
+            telemetry_payload in bytes0_64k(),
+        ) -> SubmitTelemetryData {
+            SubmitTelemetryData { channel_id, seq_num, telemetry_payload }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_telemetry_data_success()(
+            channel_id in any::<u32>(),
+            last_seq_num in any::<u32>(),
+        ) -> SubmitTelemetryDataSuccess {
+            SubmitTelemetryDataSuccess { channel_id, last_seq_num }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_telemetry_data_error()(
+            channel_id in any::<u32>(),
+            seq_num in any::<u32>(),
+            code in str0_32(),
+        ) -> SubmitTelemetryDataError {
+            SubmitTelemetryDataError { channel_id, seq_num, code }
+        }
+    }
+
+    prop_compose! {
+        pub fn temperature_reading()(
+            sensor_id in any::<u8>(),
+            celsius in finite_f32(),
+        ) -> TemperatureReading {
+            TemperatureReading { sensor_id, celsius }
+        }
+    }
+
+    prop_compose! {
+        pub fn fan_reading()(
+            fan_id in any::<u8>(),
+            rpm in any::<u32>(),
+        ) -> FanReading {
+            FanReading { fan_id, rpm }
+        }
+    }
+
+    prop_compose! {
+        pub fn chain_reading()(
+            chain_id in any::<u8>(),
+            hashrate in finite_f32(),
+        ) -> ChainReading {
+            ChainReading { chain_id, hashrate }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_device_status()(
+            seq_num in any::<u32>(),
+            dev_id in str0_255(),
+            temperatures in seq0_255(temperature_reading()),
+            fans in seq0_255(fan_reading()),
+            chains in seq0_255(chain_reading()),
+        ) -> SubmitDeviceStatus {
+            SubmitDeviceStatus { seq_num, dev_id, temperatures, fans, chains }
+        }
+    }
+
+    prop_compose! {
+        pub fn submit_device_status_success()(
+            last_seq_num in any::<u32>(),
+        ) -> SubmitDeviceStatusSuccess {
+            SubmitDeviceStatusSuccess { last_seq_num }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::convert::TryFrom;
+    use std::fmt::Debug;
+
+    use bytes::BytesMut;
+    use tokio_util::codec::{Decoder, Encoder};
+
+    use super::*;
+    use crate::error::Error;
+    use crate::v2::{framing::Frame, serialization, Codec};
+
+    /// Serializes the message and passes it through the codec as a frame, the message has to
+    /// come out intact
+    fn check_round_trip<M>(message: M)
+    where
+        M: Serialize + for<'de> Deserialize<'de> + Clone + PartialEq + Debug,
+        Frame: TryFrom<M, Error = Error>,
+        M: TryFrom<Frame, Error = Error>,
+    {
+        let bytes = serialization::to_vec(&message).expect("BUG: Cannot serialize message");
+        let deserialized: M =
+            serialization::from_slice(&bytes).expect("BUG: Cannot deserialize message");
+        assert_eq!(deserialized, message);
+
+        let mut codec = Codec::new();
+        let mut buf = BytesMut::new();
+        let frame = Frame::try_from(message.clone()).expect("BUG: Cannot build frame");
+        codec
+            .encode(frame, &mut buf)
+            .expect("BUG: Cannot encode frame");
+        let frame = codec
+            .decode(&mut buf)
+            .expect("BUG: Cannot decode frame")
+            .expect("BUG: Incomplete frame");
+        assert!(buf.is_empty());
+        assert_eq!(
+            M::try_from(frame).expect("BUG: Cannot parse frame"),
+            message
+        );
+    }
+
+    proptest! {
+        #[test]
+        fn setup_connection_round_trip(msg in mining::setup_connection()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn setup_connection_success_round_trip(msg in mining::setup_connection_success()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn setup_connection_error_round_trip(msg in mining::setup_connection_error()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn channel_endpoint_changed_round_trip(msg in mining::channel_endpoint_changed()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn open_standard_mining_channel_round_trip(msg in mining::open_standard_mining_channel()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn open_extended_mining_channel_round_trip(msg in mining::open_extended_mining_channel()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn open_extended_mining_channel_success_round_trip(
+            msg in mining::open_extended_mining_channel_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn open_standard_mining_channel_success_round_trip(
+            msg in mining::open_standard_mining_channel_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn open_mining_channel_error_round_trip(msg in mining::open_mining_channel_error()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn update_channel_round_trip(msg in mining::update_channel()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn update_channel_error_round_trip(msg in mining::update_channel_error()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn close_channel_round_trip(msg in mining::close_channel()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn submit_shares_standard_round_trip(msg in mining::submit_shares_standard()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn submit_shares_extended_round_trip(msg in mining::submit_shares_extended()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn submit_shares_success_round_trip(msg in mining::submit_shares_success()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn submit_shares_error_round_trip(msg in mining::submit_shares_error()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn new_mining_job_round_trip(msg in mining::new_mining_job()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn new_extended_mining_job_round_trip(msg in mining::new_extended_mining_job()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn set_new_prev_hash_round_trip(msg in mining::set_new_prev_hash()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn set_custom_mining_job_round_trip(msg in mining::set_custom_mining_job()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn set_custom_mining_job_success_round_trip(
+            msg in mining::set_custom_mining_job_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn set_custom_mining_job_error_round_trip(msg in mining::set_custom_mining_job_error()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn set_target_round_trip(msg in mining::set_target()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn reconnect_round_trip(msg in mining::reconnect()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn set_group_channel_round_trip(msg in mining::set_group_channel()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_allocate_mining_job_token_round_trip(
+            msg in job_negotiation::allocate_mining_job_token(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_allocate_mining_job_token_success_round_trip(
+            msg in job_negotiation::allocate_mining_job_token_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_commit_mining_job_round_trip(
+            msg in job_negotiation::commit_mining_job(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_commit_mining_job_success_round_trip(
+            msg in job_negotiation::commit_mining_job_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_commit_mining_job_error_round_trip(
+            msg in job_negotiation::commit_mining_job_error(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_identify_transactions_round_trip(
+            msg in job_negotiation::identify_transactions(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_identify_transactions_success_round_trip(
+            msg in job_negotiation::identify_transactions_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_provide_missing_transactions_round_trip(
+            msg in job_negotiation::provide_missing_transactions(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn job_negotiation_provide_missing_transactions_success_round_trip(
+            msg in job_negotiation::provide_missing_transactions_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn template_distribution_coinbase_output_data_size_round_trip(
+            msg in template_distribution::coinbase_output_data_size(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn template_distribution_new_template_round_trip(
+            msg in template_distribution::new_template(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn template_distribution_set_new_prev_hash_round_trip(
+            msg in template_distribution::set_new_prev_hash(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn template_distribution_request_transaction_data_round_trip(
+            msg in template_distribution::request_transaction_data(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn template_distribution_request_transaction_data_success_round_trip(
+            msg in template_distribution::request_transaction_data_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn template_distribution_request_transaction_data_error_round_trip(
+            msg in template_distribution::request_transaction_data_error(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn template_distribution_submit_solution_round_trip(
+            msg in template_distribution::submit_solution(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_open_telemetry_channel_round_trip(msg in telemetry::open_telemetry_channel()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_open_telemetry_channel_success_round_trip(
+            msg in telemetry::open_telemetry_channel_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_open_telemetry_channel_error_round_trip(
+            msg in telemetry::open_telemetry_channel_error(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_submit_telemetry_data_round_trip(msg in telemetry::submit_telemetry_data()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_submit_telemetry_data_success_round_trip(
+            msg in telemetry::submit_telemetry_data_success(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_submit_telemetry_data_error_round_trip(
+            msg in telemetry::submit_telemetry_data_error(),
+        ) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_submit_device_status_round_trip(msg in telemetry::submit_device_status()) {
+            check_round_trip(msg);
+        }
+
+        #[test]
+        fn telemetry_submit_device_status_success_round_trip(
+            msg in telemetry::submit_device_status_success(),
+        ) {
+            check_round_trip(msg);
+        }
+    }
+}
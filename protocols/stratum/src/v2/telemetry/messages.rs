@@ -20,7 +20,6 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-#[cfg(not(feature = "v2json"))]
 use crate::v2::serialization;
 use crate::{
     error::{Error, Result},
@@ -41,6 +40,7 @@ macro_rules! impl_telemetry_message_conversion {
 
 #[id(0x00u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenTelemetryChannel {
     pub req_id: u32,
     pub dev_id: Str0_255,
@@ -51,6 +51,7 @@ pub struct OpenTelemetryChannel {
 
 #[id(0x01u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenTelemetryChannelSuccess {
     pub req_id: u32,
     pub channel_id: u32,
@@ -58,6 +59,7 @@ pub struct OpenTelemetryChannelSuccess {
 
 #[id(0x02u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct OpenTelemetryChannelError {
     pub req_id: u32,
     pub code: Str0_32,
@@ -65,6 +67,7 @@ pub struct OpenTelemetryChannelError {
 
 #[id(0x03u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SubmitTelemetryData {
     pub channel_id: u32,
     pub seq_num: u32,
@@ -73,6 +76,7 @@ pub struct SubmitTelemetryData {
 
 #[id(0x04u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SubmitTelemetryDataSuccess {
     pub channel_id: u32,
     pub last_seq_num: u32,
@@ -80,15 +84,70 @@ pub struct SubmitTelemetryDataSuccess {
 
 #[id(0x05u8)]
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct SubmitTelemetryDataError {
     pub channel_id: u32,
     pub seq_num: u32,
     pub code: Str0_32,
 }
 
+/// Single temperature sensor reading
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct TemperatureReading {
+    /// Vendor specific identification of the sensor
+    pub sensor_id: u8,
+    /// Temperature in degrees Celsius
+    pub celsius: f32,
+}
+
+/// Single fan reading
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct FanReading {
+    pub fan_id: u8,
+    /// Revolutions per minute, 0 for stopped or failed fan
+    pub rpm: u32,
+}
+
+/// Status of a single hashing chain (hashboard)
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ChainReading {
+    pub chain_id: u8,
+    /// [h/s] Actual hashrate of the chain
+    pub hashrate: f32,
+}
+
+/// Periodic report of the device status (temperatures, fan speeds, per-chain hashrate) that is
+/// sent without opening a telemetry channel. The upstream node doesn't have to respond, the reports
+/// are considered best effort.
+#[id(0x06u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SubmitDeviceStatus {
+    /// Sequence number of the report, increases with every report sent on the connection
+    pub seq_num: u32,
+    /// Unique identifier of the device as defined by the vendor (see `DeviceInfo::dev_id`)
+    pub dev_id: Str0_255,
+    pub temperatures: Seq0_255<TemperatureReading>,
+    pub fans: Seq0_255<FanReading>,
+    pub chains: Seq0_255<ChainReading>,
+}
+
+/// Optional acknowledgement of all reports up to `last_seq_num`
+#[id(0x07u8)]
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct SubmitDeviceStatusSuccess {
+    pub last_seq_num: u32,
+}
+
 impl_telemetry_message_conversion!(OpenTelemetryChannel, false);
 impl_telemetry_message_conversion!(OpenTelemetryChannelSuccess, false);
 impl_telemetry_message_conversion!(OpenTelemetryChannelError, false);
 impl_telemetry_message_conversion!(SubmitTelemetryData, false);
 impl_telemetry_message_conversion!(SubmitTelemetryDataSuccess, false);
 impl_telemetry_message_conversion!(SubmitTelemetryDataError, false);
+impl_telemetry_message_conversion!(SubmitDeviceStatus, false);
+impl_telemetry_message_conversion!(SubmitDeviceStatusSuccess, false);
@@ -6,9 +6,9 @@ use crate::v2::messages;
 use crate::v2::telemetry;
 use crate::v2::types::{Seq0_255, Uint256Bytes};
 
-use ii_unvariant::{handler, GetId};
+use ii_unvariant::{handler, unvariant, GetId, Id};
 
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 
 // TODO: Remove once async traits are removed
 /// This test demonstrates an actual implementation of protocol handler (aka visitor to a set of
@@ -77,7 +77,10 @@ impl TelemetryHandler {
         let frame = frame.unwrap_or_else(|e| panic!("BUG: Message parsing failed: {:?}", e));
 
         Err(crate::error::Error::V2(error::Error::UnknownMessage(
-            format!("BUG: Unimplemented handler for message {}", frame.get_id()),
+            format!(
+                "BUG: Unimplemented handler for message {:?}",
+                frame.get_id()
+            ),
         )))
     }
 }
@@ -154,6 +157,13 @@ impl FullMiningHandler {
         Ok(())
     }
 
+    async fn handle_submit_shares_extended(
+        &mut self,
+        _msg: messages::SubmitSharesExtended,
+    ) -> Result<()> {
+        Ok(())
+    }
+
     async fn handle_submit_shares_success(
         &mut self,
         _msg: messages::SubmitSharesSuccess,
@@ -197,7 +207,7 @@ impl FullMiningHandler {
 
         Err(crate::error::Error::V2(error::Error::UnknownMessage(
             format!(
-                "BUG: Handler unimplemented handler for message {}",
+                "BUG: Handler unimplemented handler for message {:?}",
                 frame.get_id()
             ),
         )))
@@ -438,7 +448,7 @@ async fn test_partially_implemented_mining_handler() {
 
             Err(crate::error::Error::V2(error::Error::UnknownMessage(
                 format!(
-                    "BUG: Handler unimplemented handler for message {}",
+                    "BUG: Handler unimplemented handler for message {:?}",
                     frame.get_id()
                 ),
             )))
@@ -463,3 +473,48 @@ async fn test_partially_implemented_mining_handler() {
         .await
         .expect_err("BUG: Handling message should've failed because handler was not implemented");
 }
+
+#[test]
+fn test_submit_device_status_frame() {
+    let frame: framing::Frame = build_submit_device_status()
+        .try_into()
+        .expect("BUG: Cannot create test frame");
+    assert_eq!(frame.header.extension_type, extensions::TELEMETRY);
+    assert_eq!(frame.get_id(), (extensions::TELEMETRY, 0x06));
+
+    let msg = telemetry::messages::SubmitDeviceStatus::try_from(frame)
+        .expect("BUG: Cannot deserialize frame");
+    assert_eq!(msg, build_submit_device_status());
+}
+
+/// Message types of different extensions overlap, dispatching must take the extension type into
+/// account
+#[test]
+fn test_dispatch_scoped_message_ids() {
+    assert_eq!(
+        <messages::SetupConnection as Id<framing::MsgType>>::ID,
+        <telemetry::messages::OpenTelemetryChannel as Id<framing::MsgType>>::ID
+    );
+
+    let dispatch = |frame: framing::Frame| {
+        unvariant!(try frame {
+            msg: messages::SetupConnection => {
+                msg.expect("BUG: Cannot deserialize SetupConnection");
+                "SetupConnection"
+            },
+            msg: telemetry::messages::OpenTelemetryChannel => {
+                msg.expect("BUG: Cannot deserialize OpenTelemetryChannel");
+                "OpenTelemetryChannel"
+            },
+            id: _ => panic!("BUG: Unexpected message {:?}", id),
+        })
+    };
+
+    let frame =
+        framing::Frame::try_from(build_setup_connection()).expect("BUG: Cannot create test frame");
+    assert_eq!(dispatch(frame), "SetupConnection");
+
+    let frame = framing::Frame::try_from(build_open_telemetry_channel())
+        .expect("BUG: Cannot create test frame");
+    assert_eq!(dispatch(frame), "OpenTelemetryChannel");
+}
@@ -26,16 +26,23 @@ pub use std::convert::{TryFrom, TryInto};
 use std::fmt::{self, Debug, Formatter};
 use std::ops::Deref;
 
-use serde::{Deserialize, Serialize};
+use bytes::Bytes;
+use serde::de::{self, Deserializer, SeqAccess, Visitor};
+use serde::{Deserialize, Serialize, Serializer};
+use serde_repr::{Deserialize_repr, Serialize_repr};
 
 use crate::v1::HexBytes;
 use primitive_types::U256;
 
+mod target;
+pub use target::*;
+
 // TODO consolidate the u8;32 copied all over the place into an alias
 type Uint256Inner = [u8; 32];
 
 /// Custom type for serializing the sha256 values
 #[derive(Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct Uint256Bytes(pub Uint256Inner);
 
 /// Little endian bytes
@@ -187,13 +194,90 @@ macro_rules! sized_string_type {
                 &self.0
             }
         }
+
+        #[cfg(feature = "arbitrary")]
+        impl<'a> arbitrary::Arbitrary<'a> for $name {
+            fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+                let len = u.int_in_range(Self::MIN_LEN..=Self::MAX_LEN)?;
+                let mut s = String::with_capacity(len);
+                while s.len() < len {
+                    let c = <char as arbitrary::Arbitrary>::arbitrary(u)?;
+                    // Multi-byte characters that would overflow the length are replaced
+                    s.push(if s.len() + c.len_utf8() <= len {
+                        c
+                    } else {
+                        '?'
+                    });
+                }
+                Ok(Self(s))
+            }
+        }
     };
 }
 
+/// Deserializes contents of sized bytes types. When the input is a `Bytes` buffer registered
+/// via [`serialization::with_source()`](super::serialization::with_source) the resulting
+/// `Bytes` share memory with the input instead of copying it.
+struct SizedBytesVisitor;
+
+impl<'de> Visitor<'de> for SizedBytesVisitor {
+    type Value = Bytes;
+
+    fn expecting(&self, f: &mut Formatter) -> fmt::Result {
+        f.write_str("byte sequence")
+    }
+
+    fn visit_newtype_struct<D>(self, deserializer: D) -> Result<Self::Value, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        deserializer.deserialize_bytes(self)
+    }
+
+    fn visit_borrowed_bytes<E: de::Error>(self, v: &'de [u8]) -> Result<Self::Value, E> {
+        Ok(super::serialization::source_slice(v).unwrap_or_else(|| Bytes::copy_from_slice(v)))
+    }
+
+    fn visit_bytes<E: de::Error>(self, v: &[u8]) -> Result<Self::Value, E> {
+        Ok(Bytes::copy_from_slice(v))
+    }
+
+    fn visit_byte_buf<E: de::Error>(self, v: Vec<u8>) -> Result<Self::Value, E> {
+        Ok(Bytes::from(v))
+    }
+
+    fn visit_seq<A>(self, mut seq: A) -> Result<Self::Value, A::Error>
+    where
+        A: SeqAccess<'de>,
+    {
+        let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+        while let Some(byte) = seq.next_element()? {
+            bytes.push(byte);
+        }
+        Ok(Bytes::from(bytes))
+    }
+}
+
 macro_rules! sized_bytes_type {
     ($name:ident, $min_len:expr, $max_len:expr) => {
-        #[derive(PartialEq, Eq, Serialize, Deserialize, Default, Clone)]
-        pub struct $name(Box<[u8]>);
+        /// Backed by `Bytes`, therefore, cloning is cheap and deserialized instances may refer
+        /// directly to the buffer of the received frame
+        #[derive(PartialEq, Eq, Default, Clone)]
+        pub struct $name(Bytes);
+
+        impl Serialize for $name {
+            fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+                serializer.serialize_newtype_struct(stringify!($name), &self.0[..])
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+                let bytes = deserializer
+                    .deserialize_newtype_struct(stringify!($name), SizedBytesVisitor)?;
+                Self::try_from(bytes).map_err(de::Error::custom)
+            }
+        }
 
         impl $name {
             const MIN_LEN: usize = $min_len;
@@ -231,26 +315,35 @@ macro_rules! sized_bytes_type {
             }
         }
 
-        impl TryFrom<Vec<u8>> for $name {
+        impl TryFrom<Bytes> for $name {
             type Error = super::error::Error;
 
             #[inline]
-            fn try_from(v: Vec<u8>) -> Result<Self, Self::Error> {
-                if (Self::MIN_LEN..=Self::MAX_LEN).contains(&v.len()) {
-                    Ok(Self(v.into_boxed_slice()))
+            fn try_from(b: Bytes) -> Result<Self, Self::Error> {
+                if (Self::MIN_LEN..=Self::MAX_LEN).contains(&b.len()) {
+                    Ok(Self(b))
                 } else {
-                    Err(Self::Error::DataTypeOverflow(v.len(), Self::MAX_LEN))
+                    Err(Self::Error::DataTypeOverflow(b.len(), Self::MAX_LEN))
                 }
             }
         }
 
+        impl TryFrom<Vec<u8>> for $name {
+            type Error = super::error::Error;
+
+            #[inline]
+            fn try_from(v: Vec<u8>) -> Result<Self, Self::Error> {
+                Self::try_from(Bytes::from(v))
+            }
+        }
+
         impl<'a> TryFrom<&'a [u8]> for $name {
             type Error = super::error::Error;
 
             #[inline]
             fn try_from(s: &'a [u8]) -> Result<Self, Self::Error> {
                 if (Self::MIN_LEN..=Self::MAX_LEN).contains(&s.len()) {
-                    Ok(Self(s.into()))
+                    Ok(Self(Bytes::copy_from_slice(s)))
                 } else {
                     Err(Self::Error::DataTypeOverflow(s.len(), Self::MAX_LEN))
                 }
@@ -267,13 +360,20 @@ macro_rules! sized_bytes_type {
         impl From<$name> for Vec<u8> {
             #[inline]
             fn from(s: $name) -> Vec<u8> {
-                s.0.into_vec()
+                s.0.to_vec()
             }
         }
 
         impl From<$name> for Box<[u8]> {
             #[inline]
             fn from(s: $name) -> Box<[u8]> {
+                s.0.to_vec().into_boxed_slice()
+            }
+        }
+
+        impl From<$name> for Bytes {
+            #[inline]
+            fn from(s: $name) -> Bytes {
                 s.0
             }
         }
@@ -285,6 +385,19 @@ macro_rules! sized_bytes_type {
                 &*self.0
             }
         }
+
+        #[cfg(feature = "arbitrary")]
+        impl<'a> arbitrary::Arbitrary<'a> for $name {
+            fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+                let len = u
+                    .arbitrary_len::<u8>()?
+                    .max(Self::MIN_LEN)
+                    .min(Self::MAX_LEN);
+                let mut bytes = u.bytes(len.min(u.len()))?.to_vec();
+                bytes.resize(len, 0);
+                Ok(Self(bytes.into()))
+            }
+        }
     };
 }
 
@@ -429,6 +542,23 @@ macro_rules! sized_seq_type {
                 f.debug_tuple(stringify!($name)).field(&self.0).finish()
             }
         }
+
+        #[cfg(feature = "arbitrary")]
+        impl<'a, T> arbitrary::Arbitrary<'a> for $name<T>
+        where
+            T: arbitrary::Arbitrary<'a> + Serialize + for<'dx> Deserialize<'dx>,
+        {
+            fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+                let len = u
+                    .arbitrary_len::<T>()?
+                    .max(Self::MIN_LEN)
+                    .min(Self::MAX_LEN);
+                let items = (0..len)
+                    .map(|_| T::arbitrary(u))
+                    .collect::<arbitrary::Result<Vec<T>>>()?;
+                Ok(Self(items))
+            }
+        }
     };
 }
 
@@ -444,12 +574,140 @@ sized_bytes_type!(Bytes1_255, 1, 255);
 sized_bytes_type!(Bytes0_64k, 0, 65535);
 sized_bytes_type!(Bytes1_64k, 1, 65535);
 
+sized_bytes_type!(Bytes0_16M, 0, U24::MAX as usize);
+
 sized_seq_type!(Seq0_255, 0, 255);
 sized_seq_type!(Seq0_64k, 0, 65535);
 
+/// Unsigned 24-bit integer, it is serialized as 3 little endian bytes in the binary encoding
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct U24(u32);
+
+impl U24 {
+    pub const MAX: u32 = 0xff_ffff;
+    const SIZE: usize = 3;
+}
+
+impl TryFrom<u32> for U24 {
+    type Error = super::error::Error;
+
+    fn try_from(value: u32) -> Result<Self, Self::Error> {
+        if value <= Self::MAX {
+            Ok(Self(value))
+        } else {
+            Err(Self::Error::DataTypeOverflow(
+                value as usize,
+                Self::MAX as usize,
+            ))
+        }
+    }
+}
+
+impl TryFrom<usize> for U24 {
+    type Error = super::error::Error;
+
+    fn try_from(value: usize) -> Result<Self, Self::Error> {
+        u32::try_from(value)
+            .map_err(|_| Self::Error::DataTypeOverflow(value, Self::MAX as usize))
+            .and_then(Self::try_from)
+    }
+}
+
+impl From<u16> for U24 {
+    fn from(value: u16) -> Self {
+        Self(value.into())
+    }
+}
+
+impl From<U24> for u32 {
+    fn from(value: U24) -> Self {
+        value.0
+    }
+}
+
+impl From<U24> for usize {
+    fn from(value: U24) -> Self {
+        value.0 as usize
+    }
+}
+
+impl Serialize for U24 {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if serializer.is_human_readable() {
+            serializer.serialize_u32(self.0)
+        } else {
+            let mut bytes = [0u8; Self::SIZE];
+            bytes.copy_from_slice(&self.0.to_le_bytes()[..Self::SIZE]);
+            bytes.serialize(serializer)
+        }
+    }
+}
+
+#[cfg(feature = "arbitrary")]
+impl<'a> arbitrary::Arbitrary<'a> for U24 {
+    fn arbitrary(u: &mut arbitrary::Unstructured<'a>) -> arbitrary::Result<Self> {
+        Ok(Self(u.int_in_range(0..=Self::MAX)?))
+    }
+}
+
+impl<'de> Deserialize<'de> for U24 {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        if deserializer.is_human_readable() {
+            Self::try_from(u32::deserialize(deserializer)?).map_err(de::Error::custom)
+        } else {
+            let bytes = <[u8; Self::SIZE]>::deserialize(deserializer)?;
+            Ok(Self(
+                bytes
+                    .iter()
+                    .rev()
+                    .fold(0, |value, byte| (value << 8) | u32::from(*byte)),
+            ))
+        }
+    }
+}
+
+/// Subprotocol that the client intends to use on the connection as announced in
+/// `SetupConnection.protocol`
+#[derive(Serialize_repr, Deserialize_repr, Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+#[repr(u8)]
+pub enum SubProtocol {
+    Mining = 0,
+    JobNegotiation = 1,
+    TemplateDistribution = 2,
+    JobDistribution = 3,
+}
+
+impl TryFrom<u8> for SubProtocol {
+    type Error = crate::error::Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(Self::Mining),
+            1 => Ok(Self::JobNegotiation),
+            2 => Ok(Self::TemplateDistribution),
+            3 => Ok(Self::JobDistribution),
+            _ => Err(super::error::Error::UnknownProtocol(value).into()),
+        }
+    }
+}
+
+impl From<SubProtocol> for u8 {
+    fn from(protocol: SubProtocol) -> Self {
+        protocol as u8
+    }
+}
+
+/// SipHash-2-4 based short transaction identifier (6 bytes) as used by the Job Negotiation
+/// protocol, see BIP 152
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Hash, Default)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
+pub struct ShortTxId(pub [u8; 6]);
+
 /// Device specific information - all parts are optional and could be empty strings
 /// TODO: Fix minimal string length in the Stratum V2 specification
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "arbitrary", derive(arbitrary::Arbitrary))]
 pub struct DeviceInfo {
     /// E.g. "Bitmain"
     pub vendor: Str0_255,
@@ -0,0 +1,181 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Conversions between pool difficulty, compact `nbits` representation and targets
+
+use primitive_types::{U256, U512};
+use std::convert::TryFrom;
+
+use super::Uint256Bytes;
+use crate::v2::error::Error;
+
+/// Target of difficulty 1 (`0x00000000ffff0000...`) as defined by Bitcoin
+pub const DIFFICULTY_1_TARGET: U256 = U256([0, 0, 0, 0x0000_0000_ffff_0000]);
+
+/// Splits a positive finite `value` into an integer mantissa and binary exponent such that
+/// `value == mantissa * 2^exponent`
+fn decompose_f64(value: f64) -> (u64, i32) {
+    let bits = value.to_bits();
+    let exponent = ((bits >> 52) & 0x7ff) as i32;
+    let fraction = bits & ((1u64 << 52) - 1);
+    if exponent == 0 {
+        // Subnormal number
+        (fraction, -1074)
+    } else {
+        (fraction | (1u64 << 52), exponent - 1075)
+    }
+}
+
+fn u256_to_f64(value: U256) -> f64 {
+    value.0.iter().rev().fold(0.0, |acc, word| {
+        acc * 18_446_744_073_709_551_616.0 + *word as f64
+    })
+}
+
+impl Uint256Bytes {
+    /// Builds target for pool `difficulty`. Difficulties lower than 1 are allowed and result in
+    /// targets above [`DIFFICULTY_1_TARGET`], the target saturates at the maximum 256-bit value.
+    pub fn from_difficulty(difficulty: f64) -> Result<Self, Error> {
+        if !difficulty.is_finite() || difficulty <= 0.0 {
+            return Err(Error::InvalidTarget(format!(
+                "invalid difficulty {}",
+                difficulty
+            )));
+        }
+        let (mantissa, exponent) = decompose_f64(difficulty);
+        // target = DIFFICULTY_1_TARGET / (mantissa * 2^exponent), the extra 256 bits of the
+        // numerator preserve precision of the division
+        let quotient = (U512::from(DIFFICULTY_1_TARGET) << 256) / U512::from(mantissa);
+        let shift = 256 + exponent;
+        let target = if shift < 0 {
+            None
+        } else if shift >= 512 {
+            Some(U512::zero())
+        } else {
+            Some(quotient >> shift as usize)
+        };
+        let target = target
+            .and_then(|target| U256::try_from(target).ok())
+            .unwrap_or_else(U256::max_value);
+        Ok(target.into())
+    }
+
+    /// Pool difficulty that corresponds to the target, zero target results in infinity
+    pub fn difficulty(&self) -> f64 {
+        let target: U256 = (*self).into();
+        if target.is_zero() {
+            f64::INFINITY
+        } else {
+            u256_to_f64(DIFFICULTY_1_TARGET) / u256_to_f64(target)
+        }
+    }
+
+    /// Builds target from its compact representation (`nbits`) used in block headers
+    pub fn from_compact(bits: u32) -> Result<Self, Error> {
+        ii_bitcoin::Target::from_compact(bits)
+            .map(Into::into)
+            .map_err(|e| Error::InvalidTarget(format!("{:#010x}: {}", bits, e)))
+    }
+
+    /// Compact representation (`nbits`) of the target, the conversion loses precision
+    pub fn to_compact(&self) -> u32 {
+        ii_bitcoin::Target::from(*self).into_compact()
+    }
+}
+
+/// Converts compact representation of a target (`nbits`) directly to pool difficulty
+pub fn compact_to_difficulty(bits: u32) -> Result<f64, Error> {
+    Uint256Bytes::from_compact(bits).map(|target| target.difficulty())
+}
+
+/// Converts pool difficulty to compact representation of its target (`nbits`)
+pub fn difficulty_to_compact(difficulty: f64) -> Result<u32, Error> {
+    Uint256Bytes::from_difficulty(difficulty).map(|target| target.to_compact())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Genesis block nbits which corresponds to difficulty 1
+    const GENESIS_BITS: u32 = 0x1d00ffff;
+
+    #[test]
+    fn test_difficulty_1() {
+        let target = Uint256Bytes::from_difficulty(1.0).expect("BUG: invalid difficulty");
+        assert_eq!(Into::<U256>::into(target), DIFFICULTY_1_TARGET);
+        assert_eq!(target.difficulty(), 1.0);
+        assert_eq!(target.to_compact(), GENESIS_BITS);
+        assert_eq!(
+            Uint256Bytes::from_compact(GENESIS_BITS).expect("BUG: invalid bits"),
+            target
+        );
+    }
+
+    #[test]
+    fn test_difficulty_round_trip() {
+        for difficulty in [0.5, 2.0, 1024.0, 65536.0, 1.5e6, 3.3e12, 2.0e13].iter() {
+            let target =
+                Uint256Bytes::from_difficulty(*difficulty).expect("BUG: invalid difficulty");
+            let round_trip = target.difficulty();
+            assert!(
+                ((round_trip - difficulty) / difficulty).abs() < 1e-9,
+                "difficulty {} converted to {}",
+                difficulty,
+                round_trip
+            );
+        }
+        // Integer difficulties match the integer division
+        let target = Uint256Bytes::from_difficulty(1024.0).expect("BUG: invalid difficulty");
+        assert_eq!(
+            Into::<U256>::into(target),
+            DIFFICULTY_1_TARGET / U256::from(1024)
+        );
+    }
+
+    #[test]
+    fn test_compact_difficulty() {
+        // Block 700000
+        let bits = 0x170e_d0eb;
+        let difficulty = compact_to_difficulty(bits).expect("BUG: invalid bits");
+        assert!((difficulty - 18_997_641_161_758.95).abs() < 1.0);
+        assert_eq!(
+            difficulty_to_compact(difficulty).expect("BUG: invalid difficulty"),
+            bits
+        );
+    }
+
+    #[test]
+    fn test_invalid_values() {
+        assert!(Uint256Bytes::from_difficulty(0.0).is_err());
+        assert!(Uint256Bytes::from_difficulty(-1.0).is_err());
+        assert!(Uint256Bytes::from_difficulty(f64::NAN).is_err());
+        assert!(Uint256Bytes::from_difficulty(f64::INFINITY).is_err());
+        // Negative mantissa
+        assert!(Uint256Bytes::from_compact(0x1d80_0000).is_err());
+
+        // Tiny difficulty saturates the target
+        let target = Uint256Bytes::from_difficulty(1e-80).expect("BUG: invalid difficulty");
+        assert_eq!(Into::<U256>::into(target), U256::max_value());
+        assert_eq!(Uint256Bytes([0; 32]).difficulty(), f64::INFINITY);
+    }
+}
@@ -30,6 +30,9 @@ use std::vec;
 
 use crate::tokio;
 
+use futures::channel::mpsc;
+use futures::stream::{self, Stream};
+use rand::Rng;
 use tokio::net::TcpStream;
 #[cfg(any(feature = "tokio12", feature = "tokio03"))]
 use tokio::time;
@@ -44,8 +47,10 @@ use serde::{de, ser};
 
 use thiserror::Error;
 
+use crate::srv;
+
 #[derive(Error, PartialEq, Eq, Debug)]
-#[error("Invalid endpoint address syntax (host:port)")]
+#[error("Invalid endpoint address syntax (host:port or SRV record name)")]
 pub struct AddressParseError;
 
 /// This is a tuple of a `String` holding a hostname/IP address
@@ -58,6 +63,10 @@ pub struct AddressParseError;
 /// server sockets.
 ///
 /// You can also use `connect()` to create a `Connection` directly.
+///
+/// A DNS SRV record name (e.g. `"_stratum._tcp.pool.example.com"`) without a port is accepted
+/// too. Such an address is represented with port 0 and it has to be resolved to the targets of
+/// the record via `srv::SrvResolver` before connecting, see `is_srv()`.
 #[derive(Clone, PartialEq, Eq, Debug)]
 pub struct Address(pub String, pub u16);
 
@@ -68,8 +77,20 @@ impl Address {
         (self.0.as_str(), self.1)
     }
 
-    /// Create a `TcpStream` connected to this address
+    /// The address is a name of an SRV record, the port comes from the record
+    pub fn is_srv(&self) -> bool {
+        self.1 == 0 && srv::is_srv_name(&self.0)
+    }
+
+    /// Create a `TcpStream` connected to this address. SRV record names are rejected, their
+    /// targets have to be resolved (and connected) individually.
     pub async fn connect(&self) -> io::Result<TcpStream> {
+        if self.is_srv() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("SRV record {} has to be resolved before connecting", self),
+            ));
+        }
         TcpStream::connect(self.as_ref()).await
     }
 }
@@ -110,6 +131,9 @@ impl FromStr for Address {
     type Err = AddressParseError;
 
     fn from_str(src: &str) -> Result<Self, AddressParseError> {
+        if srv::is_srv_name(src) && !src.contains(':') {
+            return Ok(Address(src.to_string(), 0));
+        }
         let col_pos = src.find(':').ok_or(AddressParseError)?;
         if col_pos == 0 {
             return Err(AddressParseError);
@@ -138,7 +162,11 @@ impl<'a> From<&'a Address> for String {
 
 impl fmt::Display for Address {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{}:{}", self.0, self.1)
+        if self.is_srv() {
+            write!(f, "{}", self.0)
+        } else {
+            write!(f, "{}:{}", self.0, self.1)
+        }
     }
 }
 
@@ -158,8 +186,9 @@ impl<'de> de::Deserialize<'de> for Address {
     where
         D: de::Deserializer<'de>,
     {
-        let s: &str = de::Deserialize::deserialize(deserializer)?;
-        Self::try_from(s).map_err(de::Error::custom)
+        // Owned string so that formats that cannot borrow from their input are supported too
+        let s: String = de::Deserialize::deserialize(deserializer)?;
+        Self::try_from(s.as_str()).map_err(de::Error::custom)
     }
 }
 
@@ -173,6 +202,86 @@ pub trait Backoff: Send + fmt::Debug {
     fn reset(&mut self);
 }
 
+/// `Backoff` that always waits the same amount of time
+#[derive(Clone, Debug)]
+pub struct FixedBackoff {
+    delay: Duration,
+}
+
+impl FixedBackoff {
+    pub fn new(delay: Duration) -> Self {
+        Self { delay }
+    }
+}
+
+impl Backoff for FixedBackoff {
+    fn next(&mut self) -> Duration {
+        self.delay
+    }
+
+    fn reset(&mut self) {}
+}
+
+/// `Backoff` that multiplies the delay by `factor` after each failed attempt, the delay is
+/// capped at `max`.
+///
+/// Optional `jitter` (a fraction in the range `0.0..=1.0`) randomly shortens every delay by up to
+/// the given fraction so that many clients that lost connection at the same time don't
+/// reconnect in lockstep.
+#[derive(Clone, Debug)]
+pub struct ExponentialBackoff {
+    initial: Duration,
+    current: Duration,
+    factor: u32,
+    max: Duration,
+    jitter: f64,
+}
+
+impl ExponentialBackoff {
+    pub fn new(initial: Duration, factor: u32, max: Duration) -> Self {
+        Self {
+            initial,
+            current: initial,
+            factor,
+            max,
+            jitter: 0.0,
+        }
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+}
+
+impl Backoff for ExponentialBackoff {
+    fn next(&mut self) -> Duration {
+        let delay = self.current.min(self.max);
+        self.current = self
+            .current
+            .checked_mul(self.factor)
+            .unwrap_or(self.max)
+            .min(self.max);
+
+        if self.jitter > 0.0 {
+            let shortening = rand::thread_rng().gen_range(0.0..=self.jitter);
+            delay.mul_f64(1.0 - shortening)
+        } else {
+            delay
+        }
+    }
+
+    fn reset(&mut self) {
+        self.current = self.initial;
+    }
+}
+
+impl Default for ExponentialBackoff {
+    fn default() -> Self {
+        Self::new(Duration::from_millis(100), 2, Duration::from_secs(30)).with_jitter(0.2)
+    }
+}
+
 /// Default `Backoff` implementation, based on the fibonacci sequence.
 #[derive(Debug)]
 struct DefaultBackoff {
@@ -245,17 +354,27 @@ pub struct AttemptError {
     /// (You can use this to compute how long it has been in total since the connection broke
     /// by subtracting this from `Instant::now()`)
     pub start_time: Instant,
+    /// Set when the maximum number of attempts has been reached, `Client` won't attempt
+    /// to connect again until it's `reset()`
+    pub exhausted: bool,
     /// The I/O error returned by the underlying `Connection`.
     #[source]
     pub error: io::Error,
 }
 
 impl AttemptError {
-    fn new(next_attempt_in: Duration, retries: u32, start_time: Instant, error: io::Error) -> Self {
+    fn new(
+        next_attempt_in: Duration,
+        retries: u32,
+        start_time: Instant,
+        exhausted: bool,
+        error: io::Error,
+    ) -> Self {
         Self {
             next_attempt_in,
             retries,
             start_time,
+            exhausted,
             error,
         }
     }
@@ -263,6 +382,13 @@ impl AttemptError {
 
 impl fmt::Display for AttemptError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.exhausted {
+            return write!(
+                f,
+                "Connection attempt error, attempt #{}, giving up",
+                self.retries
+            );
+        }
         let next_in = self.next_attempt_in.as_millis() as u64;
         write!(
             f,
@@ -272,6 +398,25 @@ impl fmt::Display for AttemptError {
     }
 }
 
+/// Events emitted by `Client` to its subscribers, see `Client::subscribe()`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ClientEvent {
+    /// Connection attempt is about to start, `attempt` is counted from 1 since the last
+    /// successful connection
+    Attempt { addr: Address, attempt: u32 },
+    /// Connection has been established
+    Connected { addr: Address, attempt: u32 },
+    /// Connection attempt has failed, next attempt will be made in `next_attempt_in`
+    Failed {
+        addr: Address,
+        attempt: u32,
+        next_attempt_in: Duration,
+        error_kind: io::ErrorKind,
+    },
+    /// Maximum number of attempts has been reached
+    GaveUp { addr: Address, attempts: u32 },
+}
+
 #[derive(Debug)]
 pub struct Client {
     /// Server address to connect to
@@ -287,6 +432,10 @@ pub struct Client {
     /// Time of the first attempt, reset if the connection is established,
     /// see AttemptError::start_time
     start_time: Option<Instant>,
+    /// Give up after this many consecutive failed attempts, unlimited when `None`
+    max_attempts: Option<u32>,
+    /// Subscribers notified about every connection attempt and its result
+    subscribers: Vec<mpsc::UnboundedSender<ClientEvent>>,
 }
 
 impl Client {
@@ -308,9 +457,17 @@ impl Client {
             next_delay: None,
             retries: 0,
             start_time: None,
+            max_attempts: None,
+            subscribers: vec![],
         }
     }
 
+    /// Limit number of consecutive failed connection attempts
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = Some(max_attempts);
+        self
+    }
+
     pub fn set_addr(&mut self, addr: Address) {
         self.addr = addr;
     }
@@ -319,8 +476,47 @@ impl Client {
         self.backoff = Box::new(backoff);
     }
 
+    pub fn set_max_attempts(&mut self, max_attempts: Option<u32>) {
+        self.max_attempts = max_attempts;
+    }
+
+    /// Returns a stream of events describing every connection attempt made by this client
+    pub fn subscribe(&mut self) -> mpsc::UnboundedReceiver<ClientEvent> {
+        let (tx, rx) = mpsc::unbounded();
+        self.subscribers.push(tx);
+        rx
+    }
+
+    /// Forget all failed attempts so that the client can connect again after it has given up
+    pub fn reset(&mut self) {
+        self.backoff.reset();
+        self.next_delay = None;
+        self.retries = 0;
+        self.start_time = None;
+    }
+
+    fn is_exhausted(&self) -> bool {
+        matches!(self.max_attempts, Some(max_attempts) if self.retries >= max_attempts)
+    }
+
+    fn emit(&mut self, event: ClientEvent) {
+        // Drop subscribers that are no longer interested
+        self.subscribers
+            .retain(|subscriber| subscriber.unbounded_send(event.clone()).is_ok());
+    }
+
     pub async fn next(&mut self) -> Result<TcpStream, AttemptError> {
-        self.start_time.get_or_insert(Instant::now());
+        let start_time = *self.start_time.get_or_insert_with(Instant::now);
+
+        if self.is_exhausted() {
+            return Err(AttemptError::new(
+                Duration::from_secs(0),
+                self.retries,
+                start_time,
+                true,
+                io::Error::other("Maximum number of connection attempts reached"),
+            ));
+        }
 
         if let Some((when, delay)) = self.next_delay.take() {
             let since_last_attempt = Instant::now().duration_since(when);
@@ -329,22 +525,61 @@ impl Client {
             }
         }
 
+        let attempt = self.retries + 1;
+        self.emit(ClientEvent::Attempt {
+            addr: self.addr.clone(),
+            attempt,
+        });
+
         match self.addr.connect().await {
             Ok(conn) => {
-                self.backoff.reset();
-                self.retries = 0;
-                self.start_time = None;
+                self.reset();
+                self.emit(ClientEvent::Connected {
+                    addr: self.addr.clone(),
+                    attempt,
+                });
                 Ok(conn)
             }
             Err(err) => {
-                let backoff = self.backoff.next();
+                self.retries = attempt;
+                let exhausted = self.is_exhausted();
+                let backoff = if exhausted {
+                    Duration::from_secs(0)
+                } else {
+                    self.backoff.next()
+                };
                 self.next_delay = Some((Instant::now(), backoff));
-                self.retries += 1;
-                let start_time = self.start_time.expect("BUG: Missing start time");
-                Err(AttemptError::new(backoff, self.retries, start_time, err))
+                self.emit(ClientEvent::Failed {
+                    addr: self.addr.clone(),
+                    attempt,
+                    next_attempt_in: backoff,
+                    error_kind: err.kind(),
+                });
+                if exhausted {
+                    self.emit(ClientEvent::GaveUp {
+                        addr: self.addr.clone(),
+                        attempts: attempt,
+                    });
+                }
+                Err(AttemptError::new(
+                    backoff, attempt, start_time, exhausted, err,
+                ))
             }
         }
     }
+
+    /// Converts the client into a stream that yields a new connection (or a failed attempt)
+    /// each time it's polled. The stream terminates once the maximum number of attempts
+    /// has been reached.
+    pub fn into_stream(self) -> impl Stream<Item = Result<TcpStream, AttemptError>> {
+        stream::unfold(Some(self), |client| async move {
+            let mut client = client?;
+            match client.next().await {
+                Err(e) if e.exhausted => Some((Err(e), None)),
+                result => Some((result, Some(client))),
+            }
+        })
+    }
 }
 
 #[cfg(test)]
@@ -369,6 +604,134 @@ mod tests {
         assert_eq!(Address::from_str(":123"), Err(AddressParseError));
     }
 
+    #[test]
+    fn wire_address_srv() {
+        let addr = Address::from_str("_stratum._tcp.pool.example.com")
+            .expect("BUG: Cannot parse SRV address");
+        assert_eq!(addr, Address("_stratum._tcp.pool.example.com".into(), 0));
+        assert!(addr.is_srv());
+        assert_eq!(addr.to_string(), "_stratum._tcp.pool.example.com");
+
+        // Explicit port makes it a regular host name
+        let addr = Address::from_str("_stratum._tcp.pool.example.com:3333")
+            .expect("BUG: Cannot parse address");
+        assert!(!addr.is_srv());
+        assert_eq!(addr.to_string(), "_stratum._tcp.pool.example.com:3333");
+        assert!(!Address("127.0.0.1".into(), 0).is_srv());
+        assert_eq!(Address::from_str("_stratum"), Err(AddressParseError));
+    }
+
+    #[test]
+    fn wire_fixed_backoff() {
+        let mut backoff = FixedBackoff::new(Duration::from_millis(300));
+        assert_eq!(backoff.next(), Duration::from_millis(300));
+        assert_eq!(backoff.next(), Duration::from_millis(300));
+    }
+
+    #[test]
+    fn wire_exponential_backoff() {
+        let mut backoff =
+            ExponentialBackoff::new(Duration::from_millis(100), 3, Duration::from_secs(1));
+        let delays: Vec<_> = (0..5).map(|_| backoff.next().as_millis()).collect();
+        assert_eq!(delays, vec![100, 300, 900, 1000, 1000]);
+
+        backoff.reset();
+        assert_eq!(backoff.next(), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn wire_exponential_backoff_jitter() {
+        let mut backoff =
+            ExponentialBackoff::new(Duration::from_millis(1000), 1, Duration::from_secs(1))
+                .with_jitter(0.5);
+        for _ in 0..100 {
+            let delay = backoff.next();
+            assert!(
+                delay >= Duration::from_millis(500) && delay <= Duration::from_millis(1000),
+                "Delay out of jitter range: {:?}",
+                delay
+            );
+        }
+    }
+
+    /// Returns address of a local port that refuses connections
+    async fn closed_port_address() -> Address {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: cannot bind");
+        let port = listener.local_addr().expect("BUG: no local address").port();
+        Address("127.0.0.1".into(), port)
+    }
+
+    #[tokio::test]
+    async fn wire_client_max_attempts() {
+        use futures::StreamExt;
+
+        let addr = closed_port_address().await;
+        let mut client =
+            Client::with_backoff(addr.clone(), FixedBackoff::new(Duration::from_millis(1)))
+                .with_max_attempts(2);
+        let mut events = client.subscribe();
+
+        let results: Vec<_> = client.into_stream().collect().await;
+        assert_eq!(results.len(), 2, "Stream must terminate after max attempts");
+        let last_error = results[1].as_ref().expect_err("BUG: expected failure");
+        assert!(last_error.exhausted);
+        assert_eq!(last_error.retries, 2);
+
+        let events: Vec<_> = events.collect().await;
+        assert_eq!(
+            events,
+            vec![
+                ClientEvent::Attempt {
+                    addr: addr.clone(),
+                    attempt: 1
+                },
+                ClientEvent::Failed {
+                    addr: addr.clone(),
+                    attempt: 1,
+                    next_attempt_in: Duration::from_millis(1),
+                    error_kind: io::ErrorKind::ConnectionRefused,
+                },
+                ClientEvent::Attempt {
+                    addr: addr.clone(),
+                    attempt: 2
+                },
+                ClientEvent::Failed {
+                    addr: addr.clone(),
+                    attempt: 2,
+                    next_attempt_in: Duration::from_secs(0),
+                    error_kind: io::ErrorKind::ConnectionRefused,
+                },
+                ClientEvent::GaveUp { addr, attempts: 2 },
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn wire_client_connected_event() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: cannot bind");
+        let port = listener.local_addr().expect("BUG: no local address").port();
+        let addr = Address("127.0.0.1".into(), port);
+
+        let mut client = Client::new(addr.clone()).with_max_attempts(1);
+        let mut events = client.subscribe();
+        client.next().await.expect("BUG: connection failed");
+        assert_eq!(
+            events.try_next().expect("BUG: no event"),
+            Some(ClientEvent::Attempt {
+                addr: addr.clone(),
+                attempt: 1
+            })
+        );
+        assert_eq!(
+            events.try_next().expect("BUG: no event"),
+            Some(ClientEvent::Connected { addr, attempt: 1 })
+        );
+    }
+
     #[cfg(feature = "serde")]
     #[test]
     fn wire_address_serde() {
@@ -379,5 +742,9 @@ mod tests {
         let deserialized: Address =
             serde_json::from_str(&serialized).expect("BUG: Failed to deserialize Address");
         assert_eq!(deserialized, addr);
+
+        let deserialized: Address = serde_json::from_value(serde_json::json!("localhost:9001"))
+            .expect("BUG: Failed to deserialize Address from an owned value");
+        assert_eq!(deserialized, addr);
     }
 }
@@ -62,3 +62,10 @@ mod framing;
 pub use framing::*;
 
 pub mod proxy;
+
+pub mod srv;
+
+#[cfg(feature = "websocket")]
+mod websocket;
+#[cfg(feature = "websocket")]
+pub use websocket::*;
@@ -414,6 +414,12 @@ impl<T> ProxyStream<T> {
         }
     }
 
+    /// Returns inner stream along with data that have already been read from it (e.g. the
+    /// beginning of the next protocol following the PROXY header)
+    pub fn into_inner_with_buf(self) -> (T, BytesMut) {
+        (self.inner, self.buf)
+    }
+
     /// Direct conversion to FramedParts with arbitrary codec. It eliminates the problem with
     /// `From` implementation that also exists but doesn't simply allow using the 'I' parameter.
     /// See additional notes in `From`
@@ -487,11 +493,11 @@ where
     }
 }
 
-impl<C> From<ProxyStream<TcpStream>> for Framed<TcpStream, C>
+impl<T, C> From<ProxyStream<T>> for Framed<T, C>
 where
     C: Encoder<ProxyInfo> + Decoder + Default,
 {
-    fn from(stream: ProxyStream<TcpStream>) -> Self {
+    fn from(stream: ProxyStream<T>) -> Self {
         let parts = FramedParts::from(stream);
         Framed::from_parts(parts)
     }
@@ -501,11 +507,11 @@ where
 /// ProxyStream::into_framed_parts. The problem here is that we cannot replace ProxyInfo with `I`
 /// generic parameter as it would require adding a phantom generic parameter to ProxyStream
 /// (see E207)
-impl<C> From<ProxyStream<TcpStream>> for FramedParts<TcpStream, C>
+impl<T, C> From<ProxyStream<T>> for FramedParts<T, C>
 where
     C: Encoder<ProxyInfo> + Decoder + Default,
 {
-    fn from(stream: ProxyStream<TcpStream>) -> Self {
+    fn from(stream: ProxyStream<T>) -> Self {
         let mut parts = FramedParts::new(stream.inner, C::default());
         parts.read_buf = stream.buf;
         parts
@@ -0,0 +1,212 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Resolution of DNS SRV records (RFC 2782) that allow a pool to publish a prioritized list of
+//! its endpoints under a single name such as `_stratum._tcp.pool.example.com`
+
+#[cfg(feature = "tokio12")]
+use std::fmt;
+use std::io;
+
+#[cfg(feature = "tokio12")]
+use hickory_resolver::{proto::rr::rdata::SRV, TokioAsyncResolver};
+use rand::Rng;
+
+use crate::Address;
+
+/// Returns true if `name` has the form of an SRV record name, i.e. it starts with the
+/// `_service._proto` labels
+pub fn is_srv_name(name: &str) -> bool {
+    let mut labels = name.split('.');
+    matches!(
+        (labels.next(), labels.next(), labels.next()),
+        (Some(service), Some(proto), Some(_))
+            if service.len() > 1 && service.starts_with('_') && proto.len() > 1 && proto.starts_with('_')
+    )
+}
+
+/// Single target of an SRV record
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct SrvTarget {
+    pub priority: u16,
+    pub weight: u16,
+    pub address: Address,
+}
+
+#[cfg(feature = "tokio12")]
+impl SrvTarget {
+    /// Target "." means that the service is not available under the record name (RFC 2782)
+    fn from_record(record: &SRV) -> Option<Self> {
+        let target = record.target();
+        if target.is_root() {
+            return None;
+        }
+        let host = target.to_utf8();
+        Some(Self {
+            priority: record.priority(),
+            weight: record.weight(),
+            address: Address(host.trim_end_matches('.').to_string(), record.port()),
+        })
+    }
+}
+
+/// Asynchronous resolver of SRV records configured by the system (`/etc/resolv.conf`). Answers
+/// are cached for their TTL, i.e. a lookup reflects changes of the record once the cached
+/// answer expires. Clones share the cache.
+#[cfg(feature = "tokio12")]
+#[derive(Clone)]
+pub struct SrvResolver {
+    resolver: TokioAsyncResolver,
+}
+
+#[cfg(feature = "tokio12")]
+impl SrvResolver {
+    pub fn from_system_conf() -> io::Result<Self> {
+        let resolver = TokioAsyncResolver::tokio_from_system_conf()
+            .map_err(|e| io::Error::other(format!("Cannot set up DNS resolver: {}", e)))?;
+        Ok(Self { resolver })
+    }
+
+    /// Looks up SRV record `name` and returns its targets in the order in which they are to be
+    /// contacted
+    pub async fn resolve(&self, name: &str) -> io::Result<Vec<Address>> {
+        let lookup = self.resolver.srv_lookup(name).await.map_err(|e| {
+            io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("Cannot resolve SRV record {}: {}", name, e),
+            )
+        })?;
+        let targets: Vec<SrvTarget> = lookup.iter().filter_map(SrvTarget::from_record).collect();
+        if targets.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("{} has no SRV targets", name),
+            ));
+        }
+        Ok(order_targets(targets, &mut rand::thread_rng()))
+    }
+}
+
+#[cfg(feature = "tokio12")]
+impl fmt::Debug for SrvResolver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SrvResolver").finish()
+    }
+}
+
+/// Looks up SRV record `name` with a resolver of its own, use `SrvResolver` for repeated
+/// lookups
+#[cfg(feature = "tokio12")]
+pub async fn resolve_srv(name: &str) -> io::Result<Vec<Address>> {
+    SrvResolver::from_system_conf()?.resolve(name).await
+}
+
+/// Orders targets by priority, targets of the same priority are shuffled so that each of them
+/// comes first with a probability proportional to its weight
+pub fn order_targets<R: Rng>(mut targets: Vec<SrvTarget>, rng: &mut R) -> Vec<Address> {
+    targets.sort_by_key(|target| target.priority);
+    let mut ordered = Vec::with_capacity(targets.len());
+    while !targets.is_empty() {
+        let priority = targets[0].priority;
+        let group_len = targets
+            .iter()
+            .take_while(|target| target.priority == priority)
+            .count();
+        let mut group: Vec<SrvTarget> = targets.drain(..group_len).collect();
+        while !group.is_empty() {
+            let total_weight: u32 = group.iter().map(|target| target.weight as u32).sum();
+            // Zero weight targets have a small chance to be selected before the others
+            let mut pick = rng.gen_range(0..=total_weight);
+            let index = group
+                .iter()
+                .position(|target| {
+                    if pick <= target.weight as u32 {
+                        true
+                    } else {
+                        pick -= target.weight as u32;
+                        false
+                    }
+                })
+                .unwrap_or(group.len() - 1);
+            ordered.push(group.remove(index).address);
+        }
+    }
+    ordered
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[cfg(feature = "tokio12")]
+    #[test]
+    fn test_srv_target_from_record() {
+        use hickory_resolver::Name;
+
+        let name = |name: &str| Name::from_ascii(name).expect("BUG: Invalid name");
+        assert_eq!(
+            SrvTarget::from_record(&SRV::new(10, 60, 3333, name("eu.pool.example.com."))),
+            Some(SrvTarget {
+                priority: 10,
+                weight: 60,
+                address: Address("eu.pool.example.com".to_string(), 3333),
+            })
+        );
+        assert_eq!(
+            SrvTarget::from_record(&SRV::new(0, 0, 0, Name::root())),
+            None
+        );
+    }
+
+    #[test]
+    fn test_order_targets() {
+        let target = |priority, weight, host: &str| SrvTarget {
+            priority,
+            weight,
+            address: Address(host.to_string(), 3333),
+        };
+        let mut rng = rand::thread_rng();
+        for _ in 0..100 {
+            let ordered = order_targets(
+                vec![
+                    target(20, 10, "backup"),
+                    target(10, 50, "primary-a"),
+                    target(10, 50, "primary-b"),
+                ],
+                &mut rng,
+            );
+            let hosts: Vec<&str> = ordered.iter().map(|address| address.0.as_str()).collect();
+            assert_eq!(hosts.len(), 3);
+            assert!(hosts[..2].contains(&"primary-a") && hosts[..2].contains(&"primary-b"));
+            assert_eq!(hosts[2], "backup");
+        }
+    }
+
+    #[test]
+    fn test_is_srv_name() {
+        assert!(is_srv_name("_stratum._tcp.pool.example.com"));
+        assert!(!is_srv_name("pool.example.com"));
+        assert!(!is_srv_name("_stratum._tcp"));
+        assert!(!is_srv_name("_._tcp.pool.example.com"));
+        assert!(!is_srv_name("127.0.0.1"));
+    }
+}
@@ -0,0 +1,352 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! WebSocket transport for `Framing` codecs
+//!
+//! Every WebSocket message carries one or more complete frames of the underlying protocol.
+//! Outgoing frames are encoded by the framing codec and sent as individual WebSocket messages.
+//! Incoming messages are fed into the codec; any bytes left undecoded at the message
+//! boundary are flushed through `decode_eof()` so that e.g. stratum V1 JSON messages don't
+//! need to be newline terminated when sent by web miners.
+
+use std::io;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+
+use crate::{bytes, tokio, tokio_util};
+
+use bytes::BytesMut;
+use futures::prelude::*;
+use futures::ready;
+use pin_project::pin_project;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio_tungstenite::tungstenite::client::IntoClientRequest;
+use tokio_tungstenite::tungstenite::{Error as WsError, Message};
+use tokio_tungstenite::WebSocketStream;
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::framing::Framing;
+
+/// Converts WebSocket protocol error into `io::Error` so that it can be passed on as
+/// `Framing::Error`
+fn ws_to_io_error(error: WsError) -> io::Error {
+    match error {
+        WsError::Io(error) => error,
+        WsError::ConnectionClosed | WsError::AlreadyClosed => {
+            io::Error::new(io::ErrorKind::ConnectionAborted, error)
+        }
+        error => io::Error::other(error),
+    }
+}
+
+/// Kind of WebSocket messages used for outgoing frames
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum WsMessageKind {
+    #[default]
+    Binary,
+    Text,
+}
+
+/// Connection that transfers frames of framing `F` over a WebSocket stream `S`
+#[pin_project]
+#[derive(Debug)]
+pub struct WsConnection<F: Framing, S = TcpStream> {
+    #[pin]
+    ws_stream: WebSocketStream<S>,
+    codec: F::Codec,
+    read_buf: BytesMut,
+    /// Set when a complete WebSocket message has been appended to `read_buf` and the codec
+    /// hasn't been notified about the message boundary yet
+    message_boundary: bool,
+    message_kind: WsMessageKind,
+}
+
+impl<F, S> WsConnection<F, S>
+where
+    F: Framing,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    /// Create a new `WsConnection` from an already established WebSocket stream
+    pub fn new(ws_stream: WebSocketStream<S>) -> Self {
+        Self {
+            ws_stream,
+            codec: F::Codec::default(),
+            read_buf: BytesMut::new(),
+            message_boundary: false,
+            message_kind: WsMessageKind::default(),
+        }
+    }
+
+    /// Perform server side WebSocket handshake on `stream`
+    pub async fn accept(stream: S) -> Result<Self, F::Error> {
+        let ws_stream = tokio_tungstenite::accept_async(stream)
+            .await
+            .map_err(ws_to_io_error)?;
+        Ok(Self::new(ws_stream))
+    }
+
+    /// Perform client side WebSocket handshake on `stream`, `url` is used for the handshake
+    /// request only
+    pub async fn connect_with_stream(url: &str, stream: S) -> Result<Self, F::Error> {
+        let (ws_stream, _response) = tokio_tungstenite::client_async(url, stream)
+            .await
+            .map_err(ws_to_io_error)?;
+        Ok(Self::new(ws_stream))
+    }
+
+    /// Set kind of WebSocket messages used for sending frames. Binary messages are used by
+    /// default, text messages are suitable for line based protocols (stratum V1)
+    pub fn with_message_kind(mut self, message_kind: WsMessageKind) -> Self {
+        self.message_kind = message_kind;
+        self
+    }
+
+    pub fn codec_mut(&mut self) -> &mut F::Codec {
+        &mut self.codec
+    }
+
+    pub fn get_ref(&self) -> &S {
+        self.ws_stream.get_ref()
+    }
+
+    pub fn into_inner(self) -> WebSocketStream<S> {
+        self.ws_stream
+    }
+}
+
+impl<F: Framing> WsConnection<F, TcpStream> {
+    /// Connects to a WebSocket server described by `url` (e.g. `ws://pool.example.com:3336`).
+    /// Only plain (non-TLS) WebSocket connections are supported.
+    pub async fn connect(url: &str) -> Result<Self, F::Error> {
+        let request = url.into_client_request().map_err(ws_to_io_error)?;
+        let uri = request.uri();
+        if uri.scheme_str() != Some("ws") {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Unsupported WebSocket URL scheme: {}", url),
+            )
+            .into());
+        }
+        let host = uri
+            .host()
+            .ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    format!("Missing host in WebSocket URL: {}", url),
+                )
+            })?
+            .to_string();
+        let port = uri.port_u16().unwrap_or(80);
+
+        let stream = TcpStream::connect((host.as_str(), port)).await?;
+        Self::connect_with_stream(url, stream).await
+    }
+
+    pub fn local_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.ws_stream.get_ref().local_addr()
+    }
+
+    pub fn peer_addr(&self) -> Result<SocketAddr, io::Error> {
+        self.ws_stream.get_ref().peer_addr()
+    }
+}
+
+impl<F, S> Stream for WsConnection<F, S>
+where
+    F: Framing,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Item = Result<F::Rx, F::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<Self::Item>> {
+        let mut this = self.project();
+        loop {
+            if let Some(frame) = this.codec.decode(this.read_buf)? {
+                return Poll::Ready(Some(Ok(frame)));
+            }
+            if *this.message_boundary {
+                *this.message_boundary = false;
+                if let Some(frame) = this.codec.decode_eof(this.read_buf)? {
+                    return Poll::Ready(Some(Ok(frame)));
+                }
+            }
+
+            let message = match ready!(this.ws_stream.as_mut().poll_next(cx)) {
+                Some(message) => message.map_err(ws_to_io_error)?,
+                None => return Poll::Ready(None),
+            };
+            match message {
+                Message::Binary(payload) => this.read_buf.extend_from_slice(&payload),
+                Message::Text(payload) => this.read_buf.extend_from_slice(payload.as_bytes()),
+                Message::Close(_) => return Poll::Ready(None),
+                // Pings are answered by the WebSocket stream itself
+                Message::Ping(_) | Message::Pong(_) => continue,
+            }
+            *this.message_boundary = true;
+        }
+    }
+}
+
+impl<F, S> Sink<F::Tx> for WsConnection<F, S>
+where
+    F: Framing,
+    S: AsyncRead + AsyncWrite + Unpin,
+{
+    type Error = F::Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .ws_stream
+            .poll_ready(cx)
+            .map_err(|e| ws_to_io_error(e).into())
+    }
+
+    fn start_send(self: Pin<&mut Self>, item: F::Tx) -> Result<(), Self::Error> {
+        let this = self.project();
+        let mut buf = BytesMut::new();
+        this.codec.encode(item, &mut buf)?;
+        let message = match this.message_kind {
+            WsMessageKind::Binary => Message::Binary(buf.to_vec()),
+            WsMessageKind::Text => Message::Text(
+                String::from_utf8(buf.to_vec())
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            ),
+        };
+        this.ws_stream
+            .start_send(message)
+            .map_err(|e| ws_to_io_error(e).into())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .ws_stream
+            .poll_flush(cx)
+            .map_err(|e| ws_to_io_error(e).into())
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Result<(), Self::Error>> {
+        self.project()
+            .ws_stream
+            .poll_close(cx)
+            .map_err(|e| ws_to_io_error(e).into())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tokio;
+    use tokio::net::TcpListener;
+    use tokio_util::codec::LinesCodec;
+
+    #[derive(Debug)]
+    struct LinesFraming;
+
+    #[derive(Debug, Default)]
+    struct TestCodec(LinesCodec);
+
+    impl Decoder for TestCodec {
+        type Item = String;
+        type Error = io::Error;
+
+        fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+            self.0.decode(src).map_err(io::Error::other)
+        }
+
+        fn decode_eof(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+            self.0.decode_eof(src).map_err(io::Error::other)
+        }
+    }
+
+    impl Encoder<String> for TestCodec {
+        type Error = io::Error;
+
+        fn encode(&mut self, item: String, dst: &mut BytesMut) -> Result<(), Self::Error> {
+            self.0.encode(item, dst).map_err(io::Error::other)
+        }
+    }
+
+    impl Framing for LinesFraming {
+        type Tx = String;
+        type Rx = String;
+        type Error = io::Error;
+        type Codec = TestCodec;
+    }
+
+    /// Verify that frames make it through WebSocket in both directions and that a message
+    /// without the trailing frame delimiter is still decoded
+    #[tokio::test]
+    async fn test_ws_connection_roundtrip() {
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: cannot bind");
+        let addr = listener.local_addr().expect("BUG: no local address");
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.expect("BUG: accept failed");
+            let mut conn = WsConnection::<LinesFraming>::accept(stream)
+                .await
+                .expect("BUG: WebSocket accept failed");
+            let request = conn
+                .next()
+                .await
+                .expect("BUG: unexpected end of stream")
+                .expect("BUG: receive failed");
+            conn.send(format!("re: {}", request))
+                .await
+                .expect("BUG: send failed");
+            // Raw message with two frames, the last one isn't terminated
+            conn.into_inner()
+                .send(Message::Text("first\nsecond".into()))
+                .await
+                .expect("BUG: raw send failed");
+        });
+
+        let mut conn = WsConnection::<LinesFraming>::connect(&format!("ws://{}", addr))
+            .await
+            .expect("BUG: WebSocket connect failed")
+            .with_message_kind(WsMessageKind::Text);
+        conn.send("hello".to_string())
+            .await
+            .expect("BUG: send failed");
+
+        let mut received = vec![];
+        for _ in 0..3 {
+            received.push(
+                conn.next()
+                    .await
+                    .expect("BUG: unexpected end of stream")
+                    .expect("BUG: receive failed"),
+            );
+        }
+        assert_eq!(received, vec!["re: hello", "first", "second"]);
+        server.await.expect("BUG: server task failed");
+    }
+
+    #[tokio::test]
+    async fn test_ws_connect_bad_scheme() {
+        let result = WsConnection::<LinesFraming>::connect("wss://127.0.0.1:1").await;
+        assert!(result.is_err(), "TLS WebSocket must be rejected");
+    }
+}
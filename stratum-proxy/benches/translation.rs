@@ -0,0 +1,170 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Throughput of the V2->V1 translation for the traffic of an operational channel, i.e. jobs
+//! coming from the upstream and shares coming from the downstream
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use futures::channel::mpsc;
+use std::convert::TryInto;
+use tokio::runtime::Runtime;
+
+use ii_stratum::test_utils;
+use ii_stratum::v1;
+use ii_stratum::v2;
+use ii_stratum_proxy::translation::V2ToV1Translation;
+
+/// Translation of a single session whose channel has been opened. Both output channels are
+/// drained after each step so that the translation never observes them full.
+struct Session {
+    runtime: Runtime,
+    translation: V2ToV1Translation,
+    v1_receiver: mpsc::Receiver<v1::Frame>,
+    v2_receiver: mpsc::Receiver<v2::Frame>,
+    /// ID of the next V1 request emitted by the translation
+    next_v1_id: u32,
+}
+
+impl Session {
+    const CHANNEL_SIZE: usize = 16;
+
+    /// Runs the same initial sequence as the unit tests of the translation
+    fn open() -> Self {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .build()
+            .expect("BUG: Cannot build runtime");
+        let (v1_sender, v1_receiver) = mpsc::channel(Self::CHANNEL_SIZE);
+        let (v2_sender, v2_receiver) = mpsc::channel(Self::CHANNEL_SIZE);
+        let mut session = Self {
+            runtime,
+            translation: V2ToV1Translation::new(
+                v1_sender,
+                v2_sender,
+                Default::default(),
+                None,
+                Default::default(),
+            ),
+            v1_receiver,
+            v2_receiver,
+            next_v1_id: 0,
+        };
+
+        session.handle_v2(to_v2_frame(test_utils::v2::build_setup_connection()));
+        assert_eq!(session.drain_v1(), 1, "BUG: mining.configure expected");
+        session.handle_v1(test_utils::v1::build_configure_ok_response_message());
+        session.handle_v2(to_v2_frame(test_utils::v2::build_open_channel()));
+        assert_eq!(
+            session.drain_v1(),
+            2,
+            "BUG: subscribe and authorize expected"
+        );
+        session.handle_v1(test_utils::v1::build_subscribe_ok_response_message());
+        session.handle_v1(test_utils::v1::build_authorize_ok_response_message());
+        session.handle_v1(test_utils::v1::build_set_difficulty_request_message());
+        session.handle_v1(test_utils::v1::build_mining_notify_request_message());
+        // SetupConnectionSuccess, OpenStandardMiningChannelSuccess, NewMiningJob, SetNewPrevHash
+        assert_eq!(session.drain_v2(), 4, "BUG: Channel has not been opened");
+        session.next_v1_id = 3;
+        session
+    }
+
+    fn handle_v1(&mut self, rpc: v1::rpc::Rpc) {
+        let translation = &mut self.translation;
+        self.runtime
+            .block_on(translation.handle_v1(rpc))
+            .expect("BUG: V1 message handling failed");
+    }
+
+    fn handle_v2(&mut self, frame: v2::Frame) {
+        let translation = &mut self.translation;
+        self.runtime
+            .block_on(translation.handle_v2(frame))
+            .expect("BUG: V2 message handling failed");
+    }
+
+    fn drain_v1(&mut self) -> usize {
+        drain(&mut self.v1_receiver)
+    }
+
+    fn drain_v2(&mut self) -> usize {
+        drain(&mut self.v2_receiver)
+    }
+}
+
+fn drain<T>(receiver: &mut mpsc::Receiver<T>) -> usize {
+    let mut count = 0;
+    while let Ok(Some(frame)) = receiver.try_next() {
+        black_box(frame);
+        count += 1;
+    }
+    count
+}
+
+fn to_v2_frame<M>(message: M) -> v2::Frame
+where
+    M: TryInto<v2::Frame, Error = ii_stratum::error::Error>,
+{
+    message.try_into().expect("BUG: Cannot build V2 frame")
+}
+
+/// Every `mining.notify` is translated into `NewMiningJob` and `SetNewPrevHash`
+fn bench_jobs(c: &mut Criterion) {
+    let mut session = Session::open();
+    let notify = test_utils::v1::build_mining_notify_request_message();
+
+    let mut group = c.benchmark_group("translation_jobs");
+    group.throughput(Throughput::Elements(1));
+    group.bench_function("mining.notify", |b| {
+        b.iter(|| {
+            session.handle_v1(notify.clone());
+            assert_eq!(session.drain_v2(), 2, "BUG: Job not translated");
+        })
+    });
+    group.finish();
+}
+
+/// Full round trip of a share: `SubmitSharesStandard` is translated into `mining.submit` and the
+/// response of the upstream is translated back into `SubmitSharesSuccess`
+fn bench_shares(c: &mut Criterion) {
+    let mut session = Session::open();
+
+    let mut group = c.benchmark_group("translation_shares");
+    group.throughput(Throughput::Elements(2));
+    group.bench_function("submit_round_trip", |b| {
+        b.iter_batched(
+            || to_v2_frame(test_utils::v2::build_submit_shares()),
+            |frame| {
+                session.handle_v2(frame);
+                assert_eq!(session.drain_v1(), 1, "BUG: Share not translated");
+                let id = session.next_v1_id;
+                session.next_v1_id += 1;
+                session.handle_v1(test_utils::v1::build_ok_response_message(id));
+                assert_eq!(session.drain_v2(), 1, "BUG: Share result not translated");
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.finish();
+}
+
+criterion_group!(benches, bench_jobs, bench_shares);
+criterion_main!(benches);
@@ -0,0 +1,435 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Per-worker share accounting that is periodically flushed into a persistent storage so that
+//! accounting data survives a crash of the proxy
+
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use ii_logging::macros::*;
+
+use crate::error::{Error, Result};
+
+#[cfg(feature = "sqlite")]
+mod sqlite;
+#[cfg(feature = "sqlite")]
+pub use sqlite::SqliteShareStorage;
+
+/// Shares accounted for a single worker since the last flush
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub struct WorkerShares {
+    pub accepted: u64,
+    pub rejected: u64,
+    /// Sum of difficulties of accepted shares
+    pub accepted_difficulty: u128,
+    /// Sum of difficulties of rejected shares
+    pub rejected_difficulty: u128,
+}
+
+impl WorkerShares {
+    fn merge(&mut self, other: &Self) {
+        self.accepted += other.accepted;
+        self.rejected += other.rejected;
+        self.accepted_difficulty += other.accepted_difficulty;
+        self.rejected_difficulty += other.rejected_difficulty;
+    }
+}
+
+/// Share counts of individual workers (keyed by the user name of the mining channel)
+pub type WorkerShareMap = HashMap<String, WorkerShares>;
+
+/// Persistent storage of share accounting. Each call to `store` carries only the shares
+/// accounted since the previous successful call, the storage is expected to append them.
+pub trait ShareStorage: Send + Sync {
+    /// Opens the storage ahead of the first `store`, e.g. while the proxy may still access it
+    /// before dropping privileges. The storage is reopened by `store` after a failure.
+    fn open(&self) -> io::Result<()> {
+        Ok(())
+    }
+
+    fn store(&self, timestamp: SystemTime, shares: &WorkerShareMap) -> io::Result<()>;
+}
+
+/// Appends share counts as CSV records to a file:
+/// `timestamp,worker,accepted,rejected,accepted_difficulty,rejected_difficulty`
+#[derive(Debug)]
+pub struct CsvShareStorage {
+    path: PathBuf,
+    /// Serializes writers so that records of concurrent flushes don't interleave
+    file: Mutex<Option<File>>,
+}
+
+impl CsvShareStorage {
+    const HEADER: &'static str =
+        "timestamp,worker,accepted,rejected,accepted_difficulty,rejected_difficulty";
+
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: Mutex::new(None),
+        }
+    }
+
+    fn open_file(&self) -> io::Result<File> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        if file.metadata()?.len() == 0 {
+            writeln!(file, "{}", Self::HEADER)?;
+        }
+        Ok(file)
+    }
+
+    /// Worker names are provided by downstream devices, quote them whenever they could break
+    /// the record
+    fn escape(field: &str) -> String {
+        if field.contains([',', '"', '\n', '\r']) {
+            format!("\"{}\"", field.replace('"', "\"\""))
+        } else {
+            field.to_string()
+        }
+    }
+}
+
+impl ShareStorage for CsvShareStorage {
+    fn open(&self) -> io::Result<()> {
+        let mut file = self.file.lock().expect("BUG: Poisoned CSV share storage");
+        if file.is_none() {
+            *file = Some(self.open_file()?);
+        }
+        Ok(())
+    }
+
+    fn store(&self, timestamp: SystemTime, shares: &WorkerShareMap) -> io::Result<()> {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut records = String::new();
+        let mut workers: Vec<_> = shares.iter().collect();
+        workers.sort_by_key(|(worker, _)| *worker);
+        for (worker, worker_shares) in workers {
+            records.push_str(&format!(
+                "{},{},{},{},{},{}\n",
+                timestamp,
+                Self::escape(worker),
+                worker_shares.accepted,
+                worker_shares.rejected,
+                worker_shares.accepted_difficulty,
+                worker_shares.rejected_difficulty,
+            ));
+        }
+
+        let mut file = self.file.lock().expect("BUG: Poisoned CSV share storage");
+        if file.is_none() {
+            *file = Some(self.open_file()?);
+        }
+        let result = Self::append(
+            file.as_mut().expect("BUG: CSV share storage not open"),
+            records.as_bytes(),
+        );
+        if result.is_err() {
+            // Reopen the file on next attempt, it may have been removed or rotated
+            *file = None;
+        }
+        result
+    }
+}
+
+impl CsvShareStorage {
+    /// Appends `records` durably or not at all. The caller retries failed records, therefore,
+    /// any part of them that has made it into the file is truncated away.
+    fn append(file: &mut File, records: &[u8]) -> io::Result<()> {
+        let len = file.metadata()?.len();
+        file.write_all(records)
+            .and_then(|()| file.sync_data())
+            .inspect_err(|_| {
+                if let Err(truncate_error) = file.set_len(len).and_then(|()| file.sync_data()) {
+                    error!(
+                        "Cannot remove partially stored share accounting, the shares will be \
+                        stored twice: {}",
+                        truncate_error
+                    );
+                }
+            })
+    }
+}
+
+/// Collects shares of all translation sessions of the proxy. Clones share the accounted data.
+#[derive(Clone, Default, Debug)]
+pub struct ShareAccounting {
+    pending: Arc<Mutex<WorkerShareMap>>,
+}
+
+impl ShareAccounting {
+    pub fn account_accepted_share(&self, worker: &str, difficulty: u128) {
+        self.account(worker, |shares| {
+            shares.accepted += 1;
+            shares.accepted_difficulty += difficulty;
+        });
+    }
+
+    pub fn account_rejected_share(&self, worker: &str, difficulty: u128) {
+        self.account(worker, |shares| {
+            shares.rejected += 1;
+            shares.rejected_difficulty += difficulty;
+        });
+    }
+
+    fn account(&self, worker: &str, update: impl FnOnce(&mut WorkerShares)) {
+        let mut pending = self.pending.lock().expect("BUG: Poisoned share accounting");
+        match pending.get_mut(worker) {
+            Some(shares) => update(shares),
+            None => update(pending.entry(worker.to_string()).or_default()),
+        }
+    }
+
+    /// Writes all shares accounted since the last flush into `storage`. The shares are kept for
+    /// the next flush if the storage fails.
+    pub fn flush(&self, storage: &dyn ShareStorage) -> io::Result<()> {
+        let shares =
+            std::mem::take(&mut *self.pending.lock().expect("BUG: Poisoned share accounting"));
+        if shares.is_empty() {
+            return Ok(());
+        }
+        storage.store(SystemTime::now(), &shares).inspect_err(|_| {
+            let mut pending = self.pending.lock().expect("BUG: Poisoned share accounting");
+            for (worker, worker_shares) in shares.iter() {
+                pending
+                    .entry(worker.clone())
+                    .or_default()
+                    .merge(worker_shares);
+            }
+        })
+    }
+
+    /// Flushes the accounting into `storage` every `interval`. The task runs until it's
+    /// aborted, the owner should `flush` once more on shutdown.
+    pub async fn run_flusher(self, storage: Arc<dyn ShareStorage>, interval: Duration) {
+        let mut ticker = tokio::time::interval(interval);
+        // The first tick completes immediately
+        ticker.tick().await;
+        loop {
+            ticker.tick().await;
+            let accounting = self.clone();
+            let storage = storage.clone();
+            match tokio::task::spawn_blocking(move || accounting.flush(storage.as_ref())).await {
+                Ok(Ok(())) => (),
+                Ok(Err(e)) => error!("Cannot store share accounting: {}", e),
+                Err(e) => error!("Share accounting flush failed: {}", e),
+            }
+        }
+    }
+}
+
+/// Share accounting section of the proxy configuration, exactly one storage has to be specified
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ShareAccountingConfig {
+    /// CSV file that share counts are appended to
+    pub file: Option<PathBuf>,
+    /// SQLite database that share counts are inserted into, requires the `sqlite` feature
+    pub sqlite_database: Option<PathBuf>,
+    #[serde(default = "ShareAccountingConfig::default_flush_interval_secs")]
+    pub flush_interval_secs: u64,
+}
+
+impl ShareAccountingConfig {
+    fn default_flush_interval_secs() -> u64 {
+        10
+    }
+
+    pub fn flush_interval(&self) -> Duration {
+        Duration::from_secs(self.flush_interval_secs)
+    }
+
+    /// Checks constraints that cannot be expressed by the configuration format
+    pub fn validate(&self) -> Result<()> {
+        if self.flush_interval_secs == 0 {
+            return Err(Error::General(
+                "Invalid configuration: share accounting flush_interval_secs must be positive"
+                    .to_string(),
+            ));
+        }
+        match (self.file.as_ref(), self.sqlite_database.as_ref()) {
+            (Some(_), None) | (None, Some(_)) => Ok(()),
+            _ => Err(Error::General(
+                "Invalid configuration: share accounting requires exactly one of file and \
+                sqlite_database"
+                    .to_string(),
+            )),
+        }
+    }
+
+    pub fn build_storage(&self) -> Result<Arc<dyn ShareStorage>> {
+        match (self.file.as_ref(), self.sqlite_database.as_ref()) {
+            (Some(file), None) => Ok(Arc::new(CsvShareStorage::new(file.clone()))),
+            #[cfg(feature = "sqlite")]
+            (None, Some(sqlite_database)) => {
+                Ok(Arc::new(SqliteShareStorage::new(sqlite_database.clone())))
+            }
+            #[cfg(not(feature = "sqlite"))]
+            (None, Some(_)) => Err(Error::General(
+                "SQLite share accounting requires the proxy to be built with the 'sqlite' feature"
+                    .to_string(),
+            )),
+            _ => Err(Error::General(
+                "Share accounting requires exactly one of file and sqlite_database".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Storage that can be switched into failing mode
+    #[derive(Default)]
+    struct TestStorage {
+        failing: Mutex<bool>,
+        stored: Mutex<Vec<WorkerShareMap>>,
+    }
+
+    impl ShareStorage for TestStorage {
+        fn store(&self, _timestamp: SystemTime, shares: &WorkerShareMap) -> io::Result<()> {
+            if *self.failing.lock().unwrap() {
+                return Err(io::Error::other("storage failure"));
+            }
+            self.stored.lock().unwrap().push(shares.clone());
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_flush_keeps_shares_on_failure() {
+        let accounting = ShareAccounting::default();
+        let storage = TestStorage::default();
+        accounting.account_accepted_share("worker1", 100);
+        accounting.account_rejected_share("worker1", 100);
+        accounting.account_accepted_share("worker2", 50);
+
+        *storage.failing.lock().unwrap() = true;
+        accounting
+            .flush(&storage)
+            .expect_err("BUG: Flush should have failed");
+        accounting.account_accepted_share("worker1", 200);
+
+        *storage.failing.lock().unwrap() = false;
+        accounting.flush(&storage).expect("BUG: Flush failed");
+        // Nothing new has been accounted
+        accounting.flush(&storage).expect("BUG: Flush failed");
+
+        let stored = storage.stored.lock().unwrap();
+        assert_eq!(stored.len(), 1);
+        assert_eq!(
+            stored[0]["worker1"],
+            WorkerShares {
+                accepted: 2,
+                rejected: 1,
+                accepted_difficulty: 300,
+                rejected_difficulty: 100,
+            }
+        );
+        assert_eq!(stored[0]["worker2"].accepted, 1);
+    }
+
+    #[test]
+    fn test_csv_storage() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("shares.csv");
+        let storage = CsvShareStorage::new(path.clone());
+        let mut shares = WorkerShareMap::new();
+        shares.insert(
+            "user.worker".to_string(),
+            WorkerShares {
+                accepted: 3,
+                rejected: 1,
+                accepted_difficulty: 3072,
+                rejected_difficulty: 1024,
+            },
+        );
+        shares.insert("odd,\"name\"".to_string(), Default::default());
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1000);
+        storage
+            .store(timestamp, &shares)
+            .expect("BUG: Store failed");
+        // A new storage instance must not repeat the header
+        CsvShareStorage::new(path.clone())
+            .store(timestamp, &shares)
+            .expect("BUG: Store failed");
+
+        let content = std::fs::read_to_string(path).expect("BUG: Cannot read CSV file");
+        let records = "1000,\"odd,\"\"name\"\"\",0,0,0,0\n1000,user.worker,3,1,3072,1024\n";
+        assert_eq!(
+            content,
+            format!("{}\n{}{}", CsvShareStorage::HEADER, records, records)
+        );
+    }
+
+    #[test]
+    fn test_csv_storage_failure_leaves_no_records() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("shares.csv");
+        let storage = CsvShareStorage::new(path.clone());
+        let mut shares = WorkerShareMap::new();
+        shares.insert("user.worker".to_string(), Default::default());
+        storage
+            .store(UNIX_EPOCH, &shares)
+            .expect("BUG: Store failed");
+        let content = std::fs::read_to_string(&path).expect("BUG: Cannot read CSV file");
+
+        // A read-only handle fails on write, nothing may be appended
+        let mut file = File::open(&path).expect("BUG: Cannot open CSV file");
+        CsvShareStorage::append(&mut file, b"1000,partial")
+            .expect_err("BUG: Append should have failed");
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("BUG: Cannot read CSV file"),
+            content
+        );
+    }
+
+    #[test]
+    fn test_config_validation() {
+        let config: ShareAccountingConfig =
+            toml::from_str("file = \"shares.csv\"").expect("BUG: Cannot parse config");
+        assert!(config.validate().is_ok());
+        let config: ShareAccountingConfig =
+            toml::from_str("file = \"shares.csv\"\nflush_interval_secs = 0")
+                .expect("BUG: Cannot parse config");
+        assert!(config.validate().is_err());
+        let config: ShareAccountingConfig =
+            toml::from_str("flush_interval_secs = 10").expect("BUG: Cannot parse config");
+        assert!(config.validate().is_err());
+        let config: ShareAccountingConfig =
+            toml::from_str("file = \"shares.csv\"\nsqlite_database = \"shares.db\"")
+                .expect("BUG: Cannot parse config");
+        assert!(config.validate().is_err());
+    }
+}
@@ -0,0 +1,214 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Share storage in an SQLite database. Shares of each flush are inserted in a single
+//! transaction, therefore, a failed flush leaves no records behind and can be safely retried.
+
+use std::convert::TryFrom;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use rusqlite::{params, Connection};
+
+use super::{ShareStorage, WorkerShareMap};
+
+/// How long to wait for a lock held by another process (e.g. a reader of the accounting data)
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+fn sqlite_error(e: rusqlite::Error) -> io::Error {
+    io::Error::other(format!("SQLite error: {}", e))
+}
+
+/// Inserts share counts into table `shares` of an SQLite database. Difficulty sums saturate at
+/// the maximum SQLite integer.
+#[derive(Debug)]
+pub struct SqliteShareStorage {
+    path: PathBuf,
+    connection: Mutex<Option<Connection>>,
+}
+
+impl SqliteShareStorage {
+    const CREATE_TABLE: &'static str = "CREATE TABLE IF NOT EXISTS shares (
+        timestamp INTEGER NOT NULL,
+        worker TEXT NOT NULL,
+        accepted INTEGER NOT NULL,
+        rejected INTEGER NOT NULL,
+        accepted_difficulty INTEGER NOT NULL,
+        rejected_difficulty INTEGER NOT NULL
+    )";
+    const INSERT: &'static str = "INSERT INTO shares (worker, timestamp, accepted, rejected, \
+        accepted_difficulty, rejected_difficulty) VALUES (?1, ?2, ?3, ?4, ?5, ?6)";
+
+    pub fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            connection: Mutex::new(None),
+        }
+    }
+
+    fn open_connection(&self) -> rusqlite::Result<Connection> {
+        let connection = Connection::open(&self.path)?;
+        connection.busy_timeout(BUSY_TIMEOUT)?;
+        connection.execute(Self::CREATE_TABLE, [])?;
+        Ok(connection)
+    }
+
+    /// The transaction is rolled back when dropped without a commit
+    fn insert(
+        connection: &mut Connection,
+        timestamp: i64,
+        shares: &WorkerShareMap,
+    ) -> rusqlite::Result<()> {
+        let saturate = |value: u128| i64::try_from(value).unwrap_or(i64::MAX);
+        let transaction = connection.transaction()?;
+        {
+            let mut statement = transaction.prepare(Self::INSERT)?;
+            for (worker, worker_shares) in shares.iter() {
+                statement.execute(params![
+                    worker,
+                    timestamp,
+                    saturate(worker_shares.accepted as u128),
+                    saturate(worker_shares.rejected as u128),
+                    saturate(worker_shares.accepted_difficulty),
+                    saturate(worker_shares.rejected_difficulty),
+                ])?;
+            }
+        }
+        transaction.commit()
+    }
+}
+
+impl ShareStorage for SqliteShareStorage {
+    fn open(&self) -> io::Result<()> {
+        let mut connection = self
+            .connection
+            .lock()
+            .expect("BUG: Poisoned SQLite share storage");
+        if connection.is_none() {
+            *connection = Some(self.open_connection().map_err(sqlite_error)?);
+        }
+        Ok(())
+    }
+
+    fn store(&self, timestamp: SystemTime, shares: &WorkerShareMap) -> io::Result<()> {
+        let timestamp = timestamp
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs();
+        let mut connection = self
+            .connection
+            .lock()
+            .expect("BUG: Poisoned SQLite share storage");
+        if connection.is_none() {
+            *connection = Some(self.open_connection().map_err(sqlite_error)?);
+        }
+        let result = Self::insert(
+            connection
+                .as_mut()
+                .expect("BUG: SQLite share storage not open"),
+            i64::try_from(timestamp).unwrap_or(i64::MAX),
+            shares,
+        );
+        if result.is_err() {
+            // Reopen the database on next attempt, it may have been replaced
+            *connection = None;
+        }
+        result.map_err(sqlite_error)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::accounting::WorkerShares;
+    use std::path::Path;
+
+    fn query(path: &Path, sql: &str) -> i64 {
+        Connection::open(path)
+            .expect("BUG: Cannot open database")
+            .query_row(sql, [], |row| row.get(0))
+            .expect("BUG: Query failed")
+    }
+
+    #[test]
+    fn test_sqlite_storage() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("shares.db");
+        let storage = SqliteShareStorage::new(path.clone());
+        let mut shares = WorkerShareMap::new();
+        shares.insert(
+            "user.worker".to_string(),
+            WorkerShares {
+                accepted: 3,
+                rejected: 1,
+                accepted_difficulty: 3072,
+                rejected_difficulty: u128::MAX,
+            },
+        );
+        shares.insert("odd,'name'".to_string(), Default::default());
+        let timestamp = UNIX_EPOCH + Duration::from_secs(1000);
+        storage
+            .store(timestamp, &shares)
+            .expect("BUG: Store failed");
+        // A new storage instance must reuse the table
+        SqliteShareStorage::new(path.clone())
+            .store(timestamp, &shares)
+            .expect("BUG: Store failed");
+
+        assert_eq!(query(&path, "SELECT COUNT(*) FROM shares"), 4);
+        assert_eq!(
+            query(
+                &path,
+                "SELECT SUM(accepted_difficulty) FROM shares WHERE worker = 'user.worker'"
+            ),
+            6144
+        );
+        assert_eq!(
+            query(&path, "SELECT MAX(rejected_difficulty) FROM shares"),
+            i64::MAX
+        );
+        assert_eq!(
+            query(
+                &path,
+                "SELECT COUNT(*) FROM shares WHERE worker = 'odd,''name''' AND timestamp = 1000"
+            ),
+            2
+        );
+
+        // A record that cannot be inserted rolls back the whole flush
+        Connection::open(&path)
+            .expect("BUG: Cannot open database")
+            .execute(
+                "CREATE TRIGGER reject_invalid BEFORE INSERT ON shares \
+                 WHEN NEW.worker = 'invalid' BEGIN SELECT RAISE(ABORT, 'invalid worker'); END",
+                [],
+            )
+            .expect("BUG: Cannot create trigger");
+        shares.insert("invalid".to_string(), Default::default());
+        storage
+            .store(timestamp, &shares)
+            .expect_err("BUG: Store should have failed");
+        assert_eq!(query(&path, "SELECT COUNT(*) FROM shares"), 4);
+    }
+}
@@ -0,0 +1,452 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Authentication of downstream devices that is performed when a device opens a mining channel.
+//! The upstream server isn't contacted before the channel has been authorized.
+
+use async_trait::async_trait;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use hyper::{Body, Client, Method, Request, StatusCode, Uri};
+
+use ii_logging::macros::*;
+use ii_stratum::v2;
+
+use crate::error::{Error, Result};
+
+/// Channel that a downstream device attempts to open
+#[derive(Clone, Debug)]
+pub struct AuthRequest {
+    /// User (worker) name of the channel
+    pub user: String,
+    /// Device as announced in `SetupConnection`
+    pub device: v2::types::DeviceInfo,
+    /// Address of the device, i.e. the original source when connected via PROXY protocol
+    pub source_addr: SocketAddr,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthDecision {
+    Allow,
+    /// The reason is only logged, the device is told that the user is unknown
+    Deny(String),
+}
+
+/// Decides whether a downstream device may open a channel. Failure to decide is treated as a
+/// denial.
+#[async_trait]
+pub trait Authenticator: Send + Sync {
+    async fn authenticate(&self, request: &AuthRequest) -> Result<AuthDecision>;
+}
+
+/// The allowed users file is checked for changes at most this often
+const FILE_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Allowed users loaded from a file along with its modification time
+#[derive(Default)]
+struct AllowedUsers {
+    modified: Option<SystemTime>,
+    /// Time of the last check for a modification of the file
+    checked: Option<Instant>,
+    users: HashSet<String>,
+    /// Accounts whose workers are all allowed (`account.*` entries)
+    accounts: HashSet<String>,
+}
+
+impl AllowedUsers {
+    fn parse(content: &str) -> Self {
+        let mut allowed = Self::default();
+        for line in content.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            match line.strip_suffix(".*") {
+                Some(account) => allowed.accounts.insert(account.to_string()),
+                None => allowed.users.insert(line.to_string()),
+            };
+        }
+        allowed
+    }
+
+    fn contains(&self, user: &str) -> bool {
+        if self.users.contains(user) {
+            return true;
+        }
+        let mut parts = user.splitn(2, '.');
+        match (parts.next(), parts.next()) {
+            (Some(account), Some(_)) => self.accounts.contains(account),
+            _ => false,
+        }
+    }
+}
+
+/// Allows users listed in a file, one user per line. `account.*` allows all workers of the
+/// account, lines starting with `#` are comments. The file is read again whenever it changes.
+pub struct FileAuthenticator {
+    path: PathBuf,
+    allowed: Arc<Mutex<AllowedUsers>>,
+}
+
+impl FileAuthenticator {
+    /// Fails if the file cannot be read so that a misconfigured proxy doesn't start
+    pub fn load(path: PathBuf) -> io::Result<Self> {
+        let authenticator = Self {
+            path,
+            allowed: Default::default(),
+        };
+        Self::reload_if_modified(&authenticator.path, &authenticator.allowed)?;
+        Ok(authenticator)
+    }
+
+    /// Blocks on file system access, therefore, it must not run on the executor threads
+    fn reload_if_modified(path: &Path, allowed: &Mutex<AllowedUsers>) -> io::Result<()> {
+        allowed.lock().expect("BUG: Poisoned allowed users").checked = Some(Instant::now());
+        let modified = fs::metadata(path)?.modified()?;
+        if allowed
+            .lock()
+            .expect("BUG: Poisoned allowed users")
+            .modified
+            == Some(modified)
+        {
+            return Ok(());
+        }
+        let mut reloaded = AllowedUsers::parse(&fs::read_to_string(path)?);
+        reloaded.modified = Some(modified);
+        reloaded.checked = Some(Instant::now());
+        *allowed.lock().expect("BUG: Poisoned allowed users") = reloaded;
+        Ok(())
+    }
+
+    fn is_check_due(&self) -> bool {
+        match self
+            .allowed
+            .lock()
+            .expect("BUG: Poisoned allowed users")
+            .checked
+        {
+            Some(checked) => checked.elapsed() >= FILE_CHECK_INTERVAL,
+            None => true,
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for FileAuthenticator {
+    async fn authenticate(&self, request: &AuthRequest) -> Result<AuthDecision> {
+        if self.is_check_due() {
+            let path = self.path.clone();
+            let allowed = self.allowed.clone();
+            let reloaded =
+                tokio::task::spawn_blocking(move || Self::reload_if_modified(&path, &allowed))
+                    .await
+                    .map_err(|e| Error::General(format!("Reload task failed: {}", e)))?;
+            // Keep using the previous content if the file is temporarily unavailable (e.g. while
+            // being replaced)
+            if let Err(e) = reloaded {
+                warn!("Cannot reload {}: {}", self.path.display(), e);
+            }
+        }
+        let allowed = self.allowed.lock().expect("BUG: Poisoned allowed users");
+        if allowed.contains(&request.user) {
+            Ok(AuthDecision::Allow)
+        } else {
+            Ok(AuthDecision::Deny(format!(
+                "User not listed in {}",
+                self.path.display()
+            )))
+        }
+    }
+}
+
+/// POSTs each request as JSON to an HTTP endpoint: a 2xx status allows the channel, 401 and 403
+/// deny it (the response body is the reason), anything else is an error
+pub struct WebhookAuthenticator {
+    url: Uri,
+    timeout: Duration,
+    client: Client<hyper::client::HttpConnector>,
+}
+
+impl WebhookAuthenticator {
+    /// Longest response body that is read, the body only carries the reason of a denial
+    const MAX_RESPONSE_SIZE: usize = 4 * 1024;
+
+    pub fn new(url: Uri, timeout: Duration) -> Self {
+        Self {
+            url,
+            timeout,
+            client: Client::new(),
+        }
+    }
+
+    fn build_body(request: &AuthRequest) -> String {
+        serde_json::json!({
+            "user": request.user,
+            "vendor": request.device.vendor.to_string(),
+            "hw_rev": request.device.hw_rev.to_string(),
+            "fw_ver": request.device.fw_ver.to_string(),
+            "dev_id": request.device.dev_id.to_string(),
+            "source_addr": request.source_addr.to_string(),
+        })
+        .to_string()
+    }
+
+    async fn post(&self, body: String) -> Result<AuthDecision> {
+        let http_request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .map_err(|e| Error::General(format!("Invalid webhook request: {}", e)))?;
+        let response = self
+            .client
+            .request(http_request)
+            .await
+            .map_err(|e| Error::General(format!("Webhook request failed: {}", e)))?;
+        let status = response.status();
+        let body = hyper::body::to_bytes(http_body::Limited::new(
+            response.into_body(),
+            Self::MAX_RESPONSE_SIZE,
+        ))
+        .await
+        .map_err(|e| Error::General(format!("Cannot read webhook response: {}", e)))?;
+        match status {
+            status if status.is_success() => Ok(AuthDecision::Allow),
+            StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => Ok(AuthDecision::Deny(
+                String::from_utf8_lossy(&body).trim().to_string(),
+            )),
+            status => Err(Error::General(format!(
+                "Unexpected webhook response status: {}",
+                status
+            ))),
+        }
+    }
+}
+
+#[async_trait]
+impl Authenticator for WebhookAuthenticator {
+    async fn authenticate(&self, request: &AuthRequest) -> Result<AuthDecision> {
+        tokio::time::timeout(self.timeout, self.post(Self::build_body(request)))
+            .await
+            .map_err(Error::Timeout)?
+    }
+}
+
+/// Authentication section of the proxy configuration, exactly one of the authenticators has to
+/// be specified
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AuthenticationConfig {
+    /// File with allowed users, see `FileAuthenticator`
+    pub file: Option<PathBuf>,
+    /// HTTP endpoint that decides about each channel, see `WebhookAuthenticator`
+    pub webhook_url: Option<String>,
+    #[serde(default = "AuthenticationConfig::default_webhook_timeout_secs")]
+    pub webhook_timeout_secs: u64,
+}
+
+impl AuthenticationConfig {
+    fn default_webhook_timeout_secs() -> u64 {
+        5
+    }
+
+    pub fn build(&self) -> Result<Arc<dyn Authenticator>> {
+        match (self.file.as_ref(), self.webhook_url.as_ref()) {
+            (Some(file), None) => Ok(Arc::new(
+                FileAuthenticator::load(file.clone()).map_err(Error::Io)?,
+            )),
+            (None, Some(webhook_url)) => {
+                let url = webhook_url.parse::<Uri>().map_err(|e| {
+                    Error::General(format!("Invalid webhook URL {}: {}", webhook_url, e))
+                })?;
+                Ok(Arc::new(WebhookAuthenticator::new(
+                    url,
+                    Duration::from_secs(self.webhook_timeout_secs),
+                )))
+            }
+            _ => Err(Error::General(
+                "Authentication requires either a file or a webhook URL".to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    fn build_request(user: &str) -> AuthRequest {
+        AuthRequest {
+            user: user.to_string(),
+            device: v2::types::DeviceInfo {
+                vendor: "Braiins".try_into().expect("BUG: Invalid vendor"),
+                hw_rev: "1".try_into().expect("BUG: Invalid hw_rev"),
+                fw_ver: "Braiins OS 2021-02"
+                    .try_into()
+                    .expect("BUG: Invalid fw_ver"),
+                dev_id: "xyz".try_into().expect("BUG: Invalid dev_id"),
+            },
+            source_addr: "10.0.0.1:4321"
+                .parse()
+                .expect("BUG: Invalid source address"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_file_authenticator() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("users.txt");
+        fs::write(&path, "# allowed users\nalice.worker1\n\nbob.*\n")
+            .expect("BUG: Cannot write users");
+        let authenticator = FileAuthenticator::load(path.clone()).expect("BUG: Cannot load users");
+
+        for user in &["alice.worker1", "bob.worker1", "bob.worker2"] {
+            assert_eq!(
+                authenticator
+                    .authenticate(&build_request(user))
+                    .await
+                    .expect("BUG: Authentication failed"),
+                AuthDecision::Allow
+            );
+        }
+        for user in &["alice.worker2", "alice", "bob", "carol.worker1"] {
+            assert!(matches!(
+                authenticator.authenticate(&build_request(user)).await,
+                Ok(AuthDecision::Deny(_))
+            ));
+        }
+
+        // Changes are picked up once the check interval elapses
+        fs::write(&path, "carol.worker1\n").expect("BUG: Cannot write users");
+        authenticator
+            .allowed
+            .lock()
+            .expect("BUG: Poisoned allowed users")
+            .checked = None;
+        assert_eq!(
+            authenticator
+                .authenticate(&build_request("carol.worker1"))
+                .await
+                .expect("BUG: Authentication failed"),
+            AuthDecision::Allow
+        );
+
+        assert!(FileAuthenticator::load(dir.path().join("missing.txt")).is_err());
+    }
+
+    /// Answers a single HTTP request with `status` and `reason` in the body and returns the
+    /// request
+    async fn serve_webhook(
+        status: &'static str,
+        reason: String,
+    ) -> (Uri, tokio::task::JoinHandle<String>) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: Cannot bind webhook listener");
+        let url = format!(
+            "http://{}/auth",
+            listener
+                .local_addr()
+                .expect("BUG: Cannot get webhook address")
+        )
+        .parse()
+        .expect("BUG: Invalid webhook URL");
+        let server = tokio::spawn(async move {
+            let (mut stream, _) = listener
+                .accept()
+                .await
+                .expect("BUG: Cannot accept webhook connection");
+            let mut request = vec![];
+            let mut buf = [0u8; 1024];
+            // The request is complete once the JSON body is closed
+            while !request.ends_with(b"}") {
+                let len = stream
+                    .read(&mut buf)
+                    .await
+                    .expect("BUG: Cannot read request");
+                assert_ne!(len, 0, "BUG: Incomplete request");
+                request.extend_from_slice(&buf[..len]);
+            }
+            let response = format!(
+                "HTTP/1.1 {}\r\ncontent-length: {}\r\nconnection: close\r\n\r\n{}",
+                status,
+                reason.len(),
+                reason
+            );
+            // The proxy may hang up before reading an oversized response
+            let _ = stream.write_all(response.as_bytes()).await;
+            String::from_utf8(request).expect("BUG: Request is not UTF-8")
+        });
+        (url, server)
+    }
+
+    #[tokio::test]
+    async fn test_webhook_authenticator() {
+        let (url, server) = serve_webhook("200 OK", String::new()).await;
+        let authenticator = WebhookAuthenticator::new(url, Duration::from_secs(5));
+        assert_eq!(
+            authenticator
+                .authenticate(&build_request("alice.worker1"))
+                .await
+                .expect("BUG: Authentication failed"),
+            AuthDecision::Allow
+        );
+        let request = server.await.expect("BUG: Webhook server failed");
+        assert!(request.starts_with("POST /auth HTTP/1.1"));
+        assert!(request.contains(r#""user":"alice.worker1""#));
+        assert!(request.contains(r#""source_addr":"10.0.0.1:4321""#));
+
+        let (url, _server) = serve_webhook("403 Forbidden", "not allowed".to_string()).await;
+        let authenticator = WebhookAuthenticator::new(url, Duration::from_secs(5));
+        assert_eq!(
+            authenticator
+                .authenticate(&build_request("alice.worker1"))
+                .await
+                .expect("BUG: Authentication failed"),
+            AuthDecision::Deny("not allowed".to_string())
+        );
+
+        let (url, _server) = serve_webhook("500 Internal Server Error", String::new()).await;
+        let authenticator = WebhookAuthenticator::new(url, Duration::from_secs(5));
+        assert!(authenticator
+            .authenticate(&build_request("alice.worker1"))
+            .await
+            .is_err());
+
+        // The response body is not read beyond the limit
+        let reason = "x".repeat(WebhookAuthenticator::MAX_RESPONSE_SIZE + 1);
+        let (url, _server) = serve_webhook("403 Forbidden", reason).await;
+        let authenticator = WebhookAuthenticator::new(url, Duration::from_secs(5));
+        assert!(authenticator
+            .authenticate(&build_request("alice.worker1"))
+            .await
+            .is_err());
+    }
+}
@@ -0,0 +1,848 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Local control socket that allows changing behavior of a running proxy. Each line received
+//! over the socket is a single command, the response consists of optional informational lines
+//! followed by a line with `OK` or `ERROR: <reason>`.
+//!
+//! Supported commands:
+//! - `reload` - read the configuration file again and apply security context and connection
+//!   limit
+//! - `rotate-cert` - read certificate and secret key files specified by the configuration file
+//! - `sessions` - list currently connected downstream sessions including the devices they have
+//!   announced (vendor, hardware revision, firmware version and device ID)
+//! - `top-cpu [count]` - list sessions that consumed the most CPU time (requires
+//!   `cpu_accounting` to be enabled in the configuration)
+//! - `log-level <level>|<filter>` - change the default logging level while keeping levels of
+//!   modules or replace all logging filters
+//! - `module-log-level <module> <level>|default` - change logging level of a module
+//! - `toggle-debug-log` - switch to debug logging or back to the previous levels
+//! - `reconnect <host> <port> [percentage]` - ask downstream devices of all sessions (or the
+//!   given percentage of them, starting from the oldest session) to connect to another host,
+//!   e.g. for migrating hashrate to another proxy instance
+//! - `drain-upstream <upstream>` - stop routing new sessions to the upstream (e.g. during a
+//!   maintenance of the pool) and ask devices of its sessions to reconnect to this proxy so that
+//!   they get routed to the remaining upstreams
+//! - `resume-upstream <upstream>` - route new sessions to a drained upstream again
+//! - `drain` - stop accepting new connections and terminate once all sessions are closed
+//! - `quit` - terminate immediately
+//!
+//! Certificate rotation can also be triggered by sending `SIGHUP` to the process, see
+//! `rotate_certificate_on_sighup()`. Similarly, `SIGUSR1` toggles debug logging, see
+//! `toggle_debug_logging_on_sigusr1()`.
+
+use futures::{SinkExt, StreamExt};
+use serde::Deserialize;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, UnixListener};
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::mpsc;
+use tokio_util::codec::{Framed, LinesCodec, LinesCodecError};
+
+use ii_logging::macros::*;
+use ii_logging::Level;
+use ii_stratum::v2;
+
+use crate::error::{Error, Result};
+use crate::frontend::Config;
+use crate::server::controller::{LoggingCommand, ServerHandle, SessionInfo};
+use crate::server::cpu_time;
+use crate::server::DeviceFingerprint;
+
+/// Address of the control socket, TCP is restricted to loopback addresses only
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ControlSocketAddress {
+    Unix(PathBuf),
+    Tcp(SocketAddr),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ControlCommand {
+    ReloadConfig,
+    RotateCertificate,
+    DumpSessions,
+    /// List of at most this number of sessions with the highest CPU time
+    TopCpu(usize),
+    /// Default logging level, levels of modules are kept
+    SetLogLevel(Level),
+    /// Logging filters in the `RUST_LOG` syntax that replace all levels
+    SetLogFilters(String),
+    SetModuleLogLevel(String, Option<Level>),
+    ToggleDebugLog,
+    /// Send `Reconnect` to downstream devices of this percentage of sessions
+    Reconnect {
+        host: String,
+        port: u16,
+        percentage: u8,
+    },
+    DrainUpstream(String),
+    ResumeUpstream(String),
+    Drain,
+    Quit,
+}
+
+impl ControlCommand {
+    const DEFAULT_TOP_CPU_COUNT: usize = 10;
+
+    fn parse_reconnect<'a, I>(host: &str, mut args: I) -> Result<Self>
+    where
+        I: Iterator<Item = &'a str>,
+    {
+        let port = args
+            .next()
+            .ok_or_else(|| Error::General("Missing reconnect port".to_string()))?;
+        let port = port
+            .parse()
+            .map_err(|_| Error::General(format!("Invalid reconnect port: {}", port)))?;
+        let percentage = match args.next() {
+            Some(percentage) => match percentage.parse() {
+                Ok(percentage) if percentage > 0 && percentage <= 100 => percentage,
+                _ => {
+                    return Err(Error::General(format!(
+                        "Invalid percentage of sessions: {}",
+                        percentage
+                    )))
+                }
+            },
+            None => 100,
+        };
+        Ok(Self::Reconnect {
+            host: host.to_string(),
+            port,
+            percentage,
+        })
+    }
+}
+
+impl FromStr for ControlCommand {
+    type Err = Error;
+
+    fn from_str(line: &str) -> Result<Self> {
+        let mut tokens = line.split_whitespace();
+        let command = match (tokens.next(), tokens.next()) {
+            (Some("module-log-level"), Some(module)) => {
+                let level =
+                    match tokens.next() {
+                        Some("default") => None,
+                        Some(level) => Some(level.parse::<Level>().map_err(|_| {
+                            Error::General(format!("Invalid log level: {}", level))
+                        })?),
+                        None => {
+                            return Err(Error::General(format!(
+                                "Missing log level: {}",
+                                line.trim()
+                            )))
+                        }
+                    };
+                Self::SetModuleLogLevel(module.to_string(), level)
+            }
+            (Some("toggle-debug-log"), None) => Self::ToggleDebugLog,
+            (Some("reload"), None) => Self::ReloadConfig,
+            (Some("rotate-cert"), None) => Self::RotateCertificate,
+            (Some("sessions"), None) => Self::DumpSessions,
+            (Some("top-cpu"), count) => Self::TopCpu(match count {
+                Some(count) => count
+                    .parse()
+                    .map_err(|_| Error::General(format!("Invalid session count: {}", count)))?,
+                None => Self::DEFAULT_TOP_CPU_COUNT,
+            }),
+            (Some("log-level"), Some(filter)) => match filter.parse::<Level>() {
+                Ok(level) => Self::SetLogLevel(level),
+                Err(_) => Self::SetLogFilters(filter.to_string()),
+            },
+            (Some("reconnect"), Some(host)) => Self::parse_reconnect(host, &mut tokens)?,
+            (Some("drain-upstream"), Some(upstream)) => Self::DrainUpstream(upstream.to_string()),
+            (Some("resume-upstream"), Some(upstream)) => Self::ResumeUpstream(upstream.to_string()),
+            (Some("drain"), None) => Self::Drain,
+            (Some("quit"), None) => Self::Quit,
+            _ => return Err(Error::General(format!("Invalid command: {}", line.trim()))),
+        };
+        match tokens.next() {
+            Some(_) => Err(Error::General(format!(
+                "Unexpected arguments: {}",
+                line.trim()
+            ))),
+            None => Ok(command),
+        }
+    }
+}
+
+enum ControlListener {
+    Unix(UnixListener),
+    Tcp(TcpListener),
+}
+
+/// Executes commands received over the control socket
+pub struct ControlServer {
+    listener: ControlListener,
+    /// Configuration file that is read again when reloading configuration or rotating
+    /// certificate
+    config_file: PathBuf,
+    server_handle: ServerHandle,
+    /// Sends logging changes to `LoggingController`
+    log_command_tx: Option<mpsc::Sender<LoggingCommand>>,
+}
+
+impl ControlServer {
+    /// Longer command lines are refused and the connection is closed, the client must not make
+    /// the proxy buffer an unlimited amount of data
+    const MAX_LINE_LENGTH: usize = 4096;
+
+    pub async fn bind(
+        address: ControlSocketAddress,
+        config_file: PathBuf,
+        server_handle: ServerHandle,
+        log_command_tx: Option<mpsc::Sender<LoggingCommand>>,
+    ) -> Result<Self> {
+        let listener = match address {
+            ControlSocketAddress::Unix(path) => {
+                Self::remove_stale_socket(&path)?;
+                ControlListener::Unix(UnixListener::bind(path).map_err(Error::Io)?)
+            }
+            ControlSocketAddress::Tcp(addr) => {
+                if !addr.ip().is_loopback() {
+                    return Err(Error::General(format!(
+                        "Control socket must listen on a loopback address, got {}",
+                        addr
+                    )));
+                }
+                ControlListener::Tcp(TcpListener::bind(addr).await.map_err(Error::Io)?)
+            }
+        };
+        Ok(Self {
+            listener,
+            config_file,
+            server_handle,
+            log_command_tx,
+        })
+    }
+
+    /// Unix socket file left behind by a previous run prevents binding
+    fn remove_stale_socket(path: &std::path::Path) -> Result<()> {
+        use std::os::unix::fs::FileTypeExt;
+
+        match std::fs::symlink_metadata(path) {
+            Ok(metadata) if metadata.file_type().is_socket() => {
+                std::fs::remove_file(path).map_err(Error::Io)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Accepts control connections until the listener fails
+    pub async fn run(self) -> Result<()> {
+        let this = Arc::new(self);
+        loop {
+            match &this.listener {
+                ControlListener::Unix(listener) => {
+                    let (stream, _) = listener.accept().await.map_err(Error::Io)?;
+                    tokio::spawn(this.clone().handle_connection(stream));
+                }
+                ControlListener::Tcp(listener) => {
+                    let (stream, peer_addr) = listener.accept().await.map_err(Error::Io)?;
+                    debug!("Control connection accepted from {}", peer_addr);
+                    tokio::spawn(this.clone().handle_connection(stream));
+                }
+            }
+        }
+    }
+
+    async fn handle_connection<S>(self: Arc<Self>, stream: S)
+    where
+        S: AsyncRead + AsyncWrite + Unpin,
+    {
+        let mut framed = Framed::new(
+            stream,
+            LinesCodec::new_with_max_length(Self::MAX_LINE_LENGTH),
+        );
+        while let Some(line) = framed.next().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(LinesCodecError::MaxLineLengthExceeded) => {
+                    if let Err(e) = framed.send("ERROR: line too long").await {
+                        debug!("Control connection failed: {}", e);
+                    }
+                    return;
+                }
+                Err(e) => {
+                    debug!("Control connection failed: {}", e);
+                    return;
+                }
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            let (mut response, status) = match line.parse::<ControlCommand>() {
+                Ok(command) => {
+                    info!("Control command received: {:?}", command);
+                    match self.execute(command).await {
+                        Ok(response) => (response, "OK".to_string()),
+                        Err(e) => (vec![], format!("ERROR: {}", e)),
+                    }
+                }
+                Err(e) => (vec![], format!("ERROR: {}", e)),
+            };
+            response.push(status);
+            for response_line in response {
+                if let Err(e) = framed.send(response_line).await {
+                    debug!("Control connection failed: {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    async fn send_logging_command(&self, command: LoggingCommand) -> Result<()> {
+        let log_command_tx = self
+            .log_command_tx
+            .as_ref()
+            .ok_or_else(|| Error::General("Logging cannot be controlled".to_string()))?;
+        log_command_tx
+            .send(command)
+            .await
+            .map_err(|_| Error::General("Logging controller is not running".to_string()))
+    }
+
+    /// Executes `command` and returns informational lines for the response
+    pub async fn execute(&self, command: ControlCommand) -> Result<Vec<String>> {
+        match command {
+            ControlCommand::ReloadConfig => {
+                let config = Config::read_from_file(&self.config_file).await?;
+                self.server_handle
+                    .set_security_context(config.read_security_context().await?)?;
+                self.server_handle
+                    .set_connection_limit(config.connection_limit)?;
+            }
+            ControlCommand::RotateCertificate => {
+                rotate_certificate(&self.config_file, &self.server_handle).await?
+            }
+            ControlCommand::DumpSessions => {
+                return Ok(self
+                    .server_handle
+                    .sessions()
+                    .iter()
+                    .map(format_session)
+                    .collect())
+            }
+            ControlCommand::TopCpu(count) => {
+                let sessions = self.server_handle.sessions();
+                // Either all sessions are accounted or none of them
+                if sessions.iter().any(|session| session.cpu_time.is_none()) {
+                    return Err(Error::General("CPU accounting is disabled".to_string()));
+                }
+                let mut sessions: Vec<_> = sessions
+                    .into_iter()
+                    .map(|session| {
+                        let cpu_time = session
+                            .cpu_time
+                            .as_ref()
+                            .map(|cpu_time| cpu_time.cpu_time());
+                        (cpu_time, session)
+                    })
+                    .collect();
+                sessions.sort_by(|(a, _), (b, _)| b.cmp(a));
+                let mut response = vec![format!(
+                    "process cpu:{:.3}secs",
+                    cpu_time::process_cpu_time().as_secs_f64()
+                )];
+                response.extend(
+                    sessions
+                        .iter()
+                        .take(count)
+                        .map(|(_, session)| format_session(session)),
+                );
+                return Ok(response);
+            }
+            ControlCommand::SetLogLevel(level) => {
+                self.send_logging_command(LoggingCommand::SetLevel(level))
+                    .await?
+            }
+            ControlCommand::SetLogFilters(filters) => {
+                self.send_logging_command(LoggingCommand::SetFilters(filters))
+                    .await?
+            }
+            ControlCommand::SetModuleLogLevel(module, level) => {
+                self.send_logging_command(LoggingCommand::SetModuleLevel(module, level))
+                    .await?
+            }
+            ControlCommand::ToggleDebugLog => {
+                self.send_logging_command(LoggingCommand::ToggleDebug)
+                    .await?
+            }
+            ControlCommand::Reconnect {
+                host,
+                port,
+                percentage,
+            } => {
+                let reconnect = v2::messages::Reconnect {
+                    new_host: host
+                        .as_str()
+                        .try_into()
+                        .map_err(|_| Error::General(format!("Invalid reconnect host: {}", host)))?,
+                    new_port: port,
+                };
+                let sessions = self.server_handle.reconnect(reconnect, percentage);
+                return Ok(vec![format!("reconnecting sessions:{}", sessions)]);
+            }
+            ControlCommand::DrainUpstream(upstream) => {
+                let sessions = self.server_handle.drain_upstream(&upstream).await?;
+                return Ok(vec![format!("reconnecting sessions:{}", sessions)]);
+            }
+            ControlCommand::ResumeUpstream(upstream) => {
+                self.server_handle.resume_upstream(&upstream).await?
+            }
+            ControlCommand::Drain => self.server_handle.drain()?,
+            ControlCommand::Quit => self.server_handle.quit()?,
+        }
+        Ok(vec![])
+    }
+}
+
+/// Single line describing a session in responses
+fn format_session(session: &SessionInfo) -> String {
+    let mut line = format!(
+        "{} local:{} duration:{}secs",
+        session.downstream_peer,
+        session.local_addr,
+        session.established.elapsed().as_secs()
+    );
+    if let Some(upstream_name) = session.upstream_name.as_ref() {
+        line.push_str(&format!(" upstream:{}", upstream_name));
+    }
+    if let Some(cpu_time) = session.cpu_time.as_ref() {
+        line.push_str(&format!(
+            " cpu:{:.3}secs",
+            cpu_time.cpu_time().as_secs_f64()
+        ));
+    }
+    if let Some(device) = session.stats.device() {
+        line.push_str(&format!(" {}", DeviceFingerprint(&device)));
+    }
+    line
+}
+
+/// Reads certificate and secret key files specified by `config_file` and replaces the security
+/// context of the server. Established connections keep using the previous security context.
+async fn rotate_certificate(config_file: &Path, server_handle: &ServerHandle) -> Result<()> {
+    let config = Config::read_from_file(config_file).await?;
+    server_handle.set_security_context(config.read_security_context().await?)
+}
+
+/// Rotates the certificate each time the process receives `SIGHUP`. A failed rotation is only
+/// logged and the server keeps using the current certificate.
+pub async fn rotate_certificate_on_sighup(
+    config_file: PathBuf,
+    server_handle: ServerHandle,
+) -> Result<()> {
+    let mut sighup = signal(SignalKind::hangup()).map_err(Error::Io)?;
+    while sighup.recv().await.is_some() {
+        info!("SIGHUP received, rotating certificate");
+        if let Err(e) = rotate_certificate(&config_file, &server_handle).await {
+            error!(
+                "Certificate rotation failed, keeping the current one: {}",
+                e
+            );
+        }
+    }
+    Ok(())
+}
+
+/// Toggles debug logging each time the process receives `SIGUSR1`
+pub async fn toggle_debug_logging_on_sigusr1(
+    log_command_tx: mpsc::Sender<LoggingCommand>,
+) -> Result<()> {
+    let mut sigusr1 = signal(SignalKind::user_defined1()).map_err(Error::Io)?;
+    while sigusr1.recv().await.is_some() {
+        info!("SIGUSR1 received, toggling debug logging");
+        log_command_tx
+            .send(LoggingCommand::ToggleDebug)
+            .await
+            .map_err(|_| Error::General("Logging controller is not running".to_string()))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::{
+        listener::{ChannelListener, IncomingConnection},
+        upstream::ChannelUpstream,
+        ProxyServer, TranslationHandler,
+    };
+    use futures::channel::mpsc::UnboundedSender;
+    use ii_async_utils::{FutureExt, Trigger, Tripwire};
+    use ii_stratum::v2::{
+        self,
+        noise::{
+            self, auth, negotiation::EncryptionAlgorithm, AuthorityPublicKey, Initiator,
+            StaticPublicKey,
+        },
+    };
+    use ii_wire::Address;
+    use std::convert::TryFrom;
+    use std::time::Duration;
+    use tokio::io::DuplexStream;
+    use tokio::net::{TcpStream, UnixStream};
+
+    #[test]
+    fn test_parse_command() {
+        assert_eq!(
+            "reload"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::ReloadConfig
+        );
+        assert_eq!(
+            " log-level debug "
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::SetLogLevel(Level::Debug)
+        );
+        assert_eq!(
+            "log-level info,ii_stratum=debug"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::SetLogFilters("info,ii_stratum=debug".to_string())
+        );
+        assert!("log-level".parse::<ControlCommand>().is_err());
+        assert_eq!(
+            "module-log-level ii_stratum debug"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::SetModuleLogLevel("ii_stratum".to_string(), Some(Level::Debug))
+        );
+        assert_eq!(
+            "module-log-level ii_stratum default"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::SetModuleLogLevel("ii_stratum".to_string(), None)
+        );
+        assert!("module-log-level ii_stratum"
+            .parse::<ControlCommand>()
+            .is_err());
+        assert!("module-log-level ii_stratum loud"
+            .parse::<ControlCommand>()
+            .is_err());
+        assert_eq!(
+            "top-cpu"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::TopCpu(ControlCommand::DEFAULT_TOP_CPU_COUNT)
+        );
+        assert_eq!(
+            "top-cpu 3"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::TopCpu(3)
+        );
+        assert!("top-cpu all".parse::<ControlCommand>().is_err());
+        assert_eq!(
+            "reconnect proxy2.example.com 3336"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::Reconnect {
+                host: "proxy2.example.com".to_string(),
+                port: 3336,
+                percentage: 100,
+            }
+        );
+        assert_eq!(
+            "reconnect 10.0.0.2 3336 25"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::Reconnect {
+                host: "10.0.0.2".to_string(),
+                port: 3336,
+                percentage: 25,
+            }
+        );
+        assert!("reconnect 10.0.0.2".parse::<ControlCommand>().is_err());
+        assert!("reconnect 10.0.0.2 port".parse::<ControlCommand>().is_err());
+        assert!("reconnect 10.0.0.2 3336 0"
+            .parse::<ControlCommand>()
+            .is_err());
+        assert!("reconnect 10.0.0.2 3336 101"
+            .parse::<ControlCommand>()
+            .is_err());
+        assert!("reconnect 10.0.0.2 3336 50 now"
+            .parse::<ControlCommand>()
+            .is_err());
+        assert_eq!(
+            "drain-upstream pool.example.com:3333"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::DrainUpstream("pool.example.com:3333".to_string())
+        );
+        assert_eq!(
+            "resume-upstream pool.example.com:3333"
+                .parse::<ControlCommand>()
+                .expect("BUG: Cannot parse command"),
+            ControlCommand::ResumeUpstream("pool.example.com:3333".to_string())
+        );
+        assert!("drain-upstream".parse::<ControlCommand>().is_err());
+        assert!("quit now".parse::<ControlCommand>().is_err());
+        assert!("restart".parse::<ControlCommand>().is_err());
+    }
+
+    /// Runs a noise handshake over a new connection to the server and returns the static public
+    /// key from the certificate that the server has presented
+    async fn handshake_public_key(
+        connection_tx: &UnboundedSender<IncomingConnection<DuplexStream>>,
+        authority_public_key: AuthorityPublicKey,
+    ) -> StaticPublicKey {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        connection_tx
+            .unbounded_send(IncomingConnection {
+                stream: server_stream,
+                peer_addr: "127.0.0.2:1234".parse().expect("BUG: Invalid address"),
+                local_addr: "127.0.0.1:3336".parse().expect("BUG: Invalid address"),
+            })
+            .expect("BUG: Cannot pass connection to the listener");
+        let (_, certificate) =
+            Initiator::new(authority_public_key, vec![EncryptionAlgorithm::AESGCM])
+                .connect_with_codec_and_cert::<_, v2::Frame, _, _>(client_stream, |noise_codec| {
+                    <v2::Framing as ii_wire::Framing>::Codec::new(Some(noise_codec))
+                })
+                .timeout(Duration::from_secs(5))
+                .await
+                .expect("BUG: Noise handshake timed out")
+                .expect("BUG: Noise handshake failed");
+        certificate.public_key.into_inner()
+    }
+
+    #[tokio::test]
+    async fn test_rotate_certificate() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let authority = auth::generate_authority_keypair();
+        let mut configs = vec![];
+        for name in ["old", "new"].iter() {
+            let keypair = noise::generate_keypair().expect("BUG: Cannot generate keypair");
+            let header = auth::SignedPartHeader::with_duration(Duration::from_secs(3600))
+                .expect("BUG: Cannot build certificate header");
+            let certificate = auth::Certificate::sign(header, keypair.public.clone(), &authority)
+                .expect("BUG: Cannot sign certificate");
+            let certificate_file = dir.path().join(format!("{}.cert", name));
+            let secret_key_file = dir.path().join(format!("{}.key", name));
+            std::fs::write(
+                &certificate_file,
+                String::try_from(certificate).expect("BUG: Cannot serialize certificate"),
+            )
+            .expect("BUG: Cannot write certificate");
+            std::fs::write(
+                &secret_key_file,
+                String::try_from(auth::StaticSecretKeyFormat::new(keypair.private))
+                    .expect("BUG: Cannot serialize secret key"),
+            )
+            .expect("BUG: Cannot write secret key");
+            let config_file = dir.path().join(format!("{}.toml", name));
+            std::fs::write(
+                &config_file,
+                format!(
+                    r#"
+                    listen_address = "127.0.0.1:3336"
+                    upstream_address = "127.0.0.1:3333"
+                    insecure = false
+                    certificate_file = {:?}
+                    secret_key_file = {:?}
+                    "#,
+                    certificate_file, secret_key_file
+                ),
+            )
+            .expect("BUG: Cannot write configuration");
+            configs.push((config_file, keypair.public));
+        }
+        let (old_config, old_public_key) = &configs[0];
+        let (new_config, new_public_key) = &configs[1];
+
+        let security_context = Config::read_from_file(old_config)
+            .await
+            .expect("BUG: Cannot read configuration")
+            .read_security_context()
+            .await
+            .expect("BUG: Cannot read security context");
+        let (connection_tx, listener) = ChannelListener::<DuplexStream>::new(
+            "127.0.0.1:3336".parse().expect("BUG: Invalid address"),
+        );
+        // The upstream connection is established before the handshake, keep accepting it
+        let (upstream, _upstream_rx) =
+            ChannelUpstream::new("127.0.0.1:3333".parse().expect("BUG: Invalid address"));
+        let server = ProxyServer::with_listener_and_upstream(
+            listener,
+            upstream,
+            TranslationHandler::new(None),
+            security_context,
+            Default::default(),
+            None,
+        );
+        let server_handle = server.handle();
+        let (_trigger, tripwire) = Tripwire::new();
+        tokio::spawn(server.main_loop(tripwire));
+
+        assert_eq!(
+            &handshake_public_key(&connection_tx, authority.public).await,
+            old_public_key
+        );
+        rotate_certificate(new_config, &server_handle)
+            .await
+            .expect("BUG: Rotation failed for a valid configuration");
+        // Let the server apply the new security context before the next connection arrives
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        assert_eq!(
+            &handshake_public_key(&connection_tx, authority.public).await,
+            new_public_key
+        );
+
+        assert!(
+            rotate_certificate(Path::new("nonexistent.toml"), &server_handle)
+                .await
+                .is_err(),
+            "BUG: Rotation succeeded without configuration file"
+        );
+        // The failed rotation keeps the current certificate
+        assert_eq!(
+            &handshake_public_key(&connection_tx, authority.public).await,
+            new_public_key
+        );
+    }
+
+    /// Proxy server with a control socket that a test is connected to
+    struct ControlConnection {
+        control: Framed<TcpStream, LinesCodec>,
+        server_task: tokio::task::JoinHandle<()>,
+        /// The server runs as long as the listener and the tripwire are alive
+        _connection_tx: UnboundedSender<IncomingConnection<UnixStream>>,
+        _trigger: Trigger,
+    }
+
+    async fn connect_control_socket() -> ControlConnection {
+        let (connection_tx, listener) = ChannelListener::<UnixStream>::new(
+            "127.0.0.1:3336".parse().expect("BUG: Invalid address"),
+        );
+        let server = ProxyServer::with_listener(
+            listener,
+            Address("127.0.0.1".to_string(), 3333),
+            TranslationHandler::new(None),
+            None,
+            Default::default(),
+            None,
+        );
+        let control_server = ControlServer::bind(
+            ControlSocketAddress::Tcp("127.0.0.1:0".parse().expect("BUG: Invalid address")),
+            PathBuf::from("nonexistent.toml"),
+            server.handle(),
+            None,
+        )
+        .await
+        .expect("BUG: Cannot bind control socket");
+        let control_addr = match &control_server.listener {
+            ControlListener::Tcp(listener) => listener
+                .local_addr()
+                .expect("BUG: Cannot get control socket address"),
+            ControlListener::Unix(_) => unreachable!(),
+        };
+        let (trigger, tripwire) = Tripwire::new();
+        let server_task = tokio::spawn(server.main_loop(tripwire));
+        tokio::spawn(control_server.run());
+
+        let control = Framed::new(
+            TcpStream::connect(control_addr)
+                .await
+                .expect("BUG: Cannot connect to control socket"),
+            LinesCodec::new(),
+        );
+        ControlConnection {
+            control,
+            server_task,
+            _connection_tx: connection_tx,
+            _trigger: trigger,
+        }
+    }
+
+    #[tokio::test]
+    async fn test_control_socket_drain() {
+        let mut connection = connect_control_socket().await;
+        let control = &mut connection.control;
+        for (command, expected_status) in [
+            ("sessions", "OK"),
+            ("restart", "ERROR: General error: Invalid command: restart"),
+            (
+                "log-level info",
+                "ERROR: General error: Logging cannot be controlled",
+            ),
+            (
+                "drain-upstream 10.0.0.1:3333",
+                "ERROR: General error: Unknown upstream: 10.0.0.1:3333",
+            ),
+            ("drain", "OK"),
+        ]
+        .iter()
+        {
+            control
+                .send(*command)
+                .await
+                .expect("BUG: Cannot send command");
+            assert_eq!(
+                control
+                    .next()
+                    .await
+                    .expect("BUG: Control connection closed")
+                    .expect("BUG: Cannot read response"),
+                *expected_status,
+                "Unexpected response to '{}'",
+                command
+            );
+        }
+        connection
+            .server_task
+            .timeout(Duration::from_secs(1))
+            .await
+            .expect("BUG: Server not terminated after drain")
+            .expect("BUG: Server task panicked");
+    }
+
+    /// Verifies that a client cannot make the proxy buffer an endless line
+    #[tokio::test]
+    async fn test_control_socket_line_too_long() {
+        let mut connection = connect_control_socket().await;
+        let control = &mut connection.control;
+        control
+            .send("x".repeat(ControlServer::MAX_LINE_LENGTH + 1))
+            .await
+            .expect("BUG: Cannot send command");
+        assert_eq!(
+            control
+                .next()
+                .await
+                .expect("BUG: Control connection closed")
+                .expect("BUG: Cannot read response"),
+            "ERROR: line too long"
+        );
+        assert!(control.next().await.is_none());
+    }
+}
@@ -0,0 +1,211 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Support for classic init systems: running the proxy in the background and announcing its
+//! process ID in a PID file
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Write};
+use std::os::unix::io::{AsRawFd, FromRawFd, IntoRawFd, RawFd};
+use std::path::{Path, PathBuf};
+
+/// Detaches the process from its terminal by forking twice and starting a new session in
+/// between. Standard input and output are redirected to `/dev/null`, therefore, logging has to
+/// go to a file or syslog. The working directory is kept so that relative paths in the
+/// configuration remain valid.
+///
+/// The process that has called the function doesn't return, it waits until the daemon reports
+/// via the returned `Readiness` that it serves and exits with success. If the daemon exits
+/// first, e.g. due to an invalid configuration, it exits with failure. Standard error is kept
+/// until then so that startup errors reach the terminal.
+///
+/// The function has to be called before any threads are spawned (e.g. by the tokio runtime) as
+/// only the calling thread survives a fork.
+pub fn daemonize() -> io::Result<Readiness> {
+    let (read_end, write_end) = pipe()?;
+    if fork()? {
+        drop(write_end);
+        unsafe { libc::_exit(wait_for_readiness(read_end)) };
+    }
+    drop(read_end);
+    if unsafe { libc::setsid() } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    // The session leader exits so that the daemon can never acquire a controlling terminal
+    if fork()? {
+        // Nothing of the parent is to be cleaned up, the child carries on
+        unsafe { libc::_exit(0) };
+    }
+    redirect_to_dev_null(&[libc::STDIN_FILENO, libc::STDOUT_FILENO])?;
+    Ok(Readiness {
+        pipe: write_end.into_raw_fd(),
+    })
+}
+
+/// Write end of the pipe through which the daemon reports that it is ready to the process
+/// that has started it. Unless notified, the pipe is closed only when the daemon exits so that
+/// the starting process waits until the daemon has reported a startup error.
+#[derive(Debug)]
+pub struct Readiness {
+    pipe: RawFd,
+}
+
+impl Readiness {
+    /// Status byte that is sent once the daemon is ready
+    const READY: u8 = 0;
+
+    /// Lets the starting process exit with success. Standard error is redirected to
+    /// `/dev/null` as the daemon is detached from the terminal from now on.
+    pub fn notify(self) -> io::Result<()> {
+        redirect_to_dev_null(&[libc::STDERR_FILENO])?;
+        unsafe { File::from_raw_fd(self.pipe) }.write_all(&[Self::READY])
+    }
+}
+
+/// Returns true in the parent and false in the child
+fn fork() -> io::Result<bool> {
+    match unsafe { libc::fork() } {
+        -1 => Err(io::Error::last_os_error()),
+        0 => Ok(false),
+        _ => Ok(true),
+    }
+}
+
+fn pipe() -> io::Result<(File, File)> {
+    let mut fds: [RawFd; 2] = [-1; 2];
+    if unsafe { libc::pipe(fds.as_mut_ptr()) } == -1 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { (File::from_raw_fd(fds[0]), File::from_raw_fd(fds[1])) })
+}
+
+/// Exit status of the starting process, a closed pipe without any status means that the
+/// daemon has exited before it got ready
+fn wait_for_readiness(mut pipe: File) -> i32 {
+    let mut status = [0u8; 1];
+    loop {
+        match pipe.read(&mut status) {
+            Ok(1) if status[0] == Readiness::READY => return 0,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            _ => return 1,
+        }
+    }
+}
+
+fn redirect_to_dev_null(fds: &[RawFd]) -> io::Result<()> {
+    let dev_null = OpenOptions::new()
+        .read(true)
+        .write(true)
+        .open("/dev/null")?;
+    for fd in fds {
+        if unsafe { libc::dup2(dev_null.as_raw_fd(), *fd) } == -1 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+/// PID file that is locked for as long as the proxy runs, which prevents starting a second
+/// instance with the same file. The file is removed when dropped.
+#[derive(Debug)]
+pub struct PidFile {
+    path: PathBuf,
+    /// The lock is held by the open file
+    file: File,
+}
+
+impl PidFile {
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        // The file is truncated only once it is locked so that a running instance keeps its PID
+        let mut file = OpenOptions::new()
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        if unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) } != 0 {
+            let e = io::Error::last_os_error();
+            return Err(match e.kind() {
+                io::ErrorKind::WouldBlock => io::Error::new(
+                    e.kind(),
+                    format!("{} is locked by a running instance", path.display()),
+                ),
+                _ => e,
+            });
+        }
+        file.set_len(0)?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self { path, file })
+    }
+}
+
+impl Drop for PidFile {
+    fn drop(&mut self) {
+        // The file may no longer be reachable, e.g. after privileges have been dropped. It's
+        // emptied through the open file instead so that it doesn't refer to a stale process.
+        if std::fs::remove_file(&self.path).is_err() {
+            let _ = self.file.set_len(0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wait_for_readiness() {
+        let (read_end, mut write_end) = pipe().expect("BUG: Cannot create pipe");
+        write_end
+            .write_all(&[Readiness::READY])
+            .expect("BUG: Cannot write status");
+        assert_eq!(wait_for_readiness(read_end), 0);
+
+        // The daemon exits without reporting
+        let (read_end, write_end) = pipe().expect("BUG: Cannot create pipe");
+        drop(write_end);
+        assert_eq!(wait_for_readiness(read_end), 1);
+    }
+
+    #[test]
+    fn test_pid_file() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("proxy.pid");
+        std::fs::write(&path, "stale content of a crashed instance\n")
+            .expect("BUG: Cannot write file");
+
+        let pid_file = PidFile::create(&path).expect("BUG: Cannot create PID file");
+        assert_eq!(
+            std::fs::read_to_string(&path).expect("BUG: Cannot read PID file"),
+            format!("{}\n", std::process::id())
+        );
+        assert_eq!(
+            PidFile::create(&path)
+                .expect_err("BUG: PID file locked twice")
+                .kind(),
+            io::ErrorKind::WouldBlock
+        );
+
+        drop(pid_file);
+        assert!(!path.exists());
+    }
+}
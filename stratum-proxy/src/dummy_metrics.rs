@@ -21,8 +21,14 @@
 // contact us at opensource@braiins.com.
 //! Empty metrics for the case when stratum proxy is compiled with prometheus metrics disabled
 
+use crate::error;
+use crate::server::controller::ConnectionLimitAction;
+use crate::server::SessionSummary;
+use crate::translation::{ChannelDirection, RejectCode};
 use ii_stratum::v1::rpc::Method;
+use ii_stratum::v2::telemetry::messages::SubmitDeviceStatus;
 pub use primitive_types::U256;
+use std::net::SocketAddr;
 use std::time::Instant;
 use tokio::time::Duration;
 
@@ -37,16 +43,48 @@ impl ProxyMetrics {
 
     pub fn account_unsuccessful_tcp_open(&self) {}
 
+    pub fn account_connection_limit_reached(&self, _action: ConnectionLimitAction) {}
+
+    pub fn account_handshake_rejected(&self) {}
+
+    pub fn account_extension_frame_dropped(&self) {}
+
+    pub fn account_translation_channel_full(&self, _direction: ChannelDirection) {}
+
     pub fn observe_v1_request_success(&self, _request_method: Method, _duration: Duration) {}
 
     pub fn observe_v1_request_error(&self, _request_method: Method, _duration: Duration) {}
 
+    pub fn observe_submit_latency(
+        &self,
+        _upstream_addr: Option<SocketAddr>,
+        _duration: Duration,
+        _success: bool,
+    ) {
+    }
+
+    pub fn observe_job_translation_latency(&self, _duration: Duration) {}
+
+    pub fn account_invalid_upstream_message(&self, _error: &error::InvalidUpstreamMessage) {}
+
+    pub fn account_upstream_rejected_share(&self, _code: Option<RejectCode>) {}
+
+    pub fn observe_device_status(&self, _status: &SubmitDeviceStatus) {}
+
     pub fn tcp_connection_timer_observe(&self, _timer: Instant) {}
 
     pub fn tcp_connection_close_ok(&self) {}
 
     pub fn tcp_connection_close_with_error(&self, _error: &crate::error::Error) {}
 
+    pub fn observe_session_summary(&self, _summary: &SessionSummary) {}
+
+    pub fn account_upstream_session_started(&self, _upstream_name: &str) {}
+
+    pub fn account_upstream_session_finished(&self, _upstream_name: &str) {}
+
+    pub fn set_upstream_draining(&self, _upstream_name: &str, _draining: bool) {}
+
     pub fn account_tcp_listener_breakdown(&self) {}
 
     pub fn accounted_spawn<T>(
@@ -59,4 +97,11 @@ impl ProxyMetrics {
     {
         tokio::spawn(future)
     }
+
+    pub fn accounted<T>(self: &std::sync::Arc<Self>, future: T) -> T
+    where
+        T: std::future::Future,
+    {
+        future
+    }
 }
@@ -25,11 +25,16 @@
 use futures::channel::mpsc;
 use std::error::Error as StdError;
 use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
 use thiserror::Error;
 
+use ii_async_utils::RetryError;
+use ii_stratum::error::{ErrorContext, ErrorKind};
 use ii_wire::proxy::error::Error as ProxyError;
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum DownstreamError {
     #[error("Error on sending downstream: {0}")]
     SendError(String),
@@ -44,6 +49,7 @@ pub enum DownstreamError {
 }
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum UpstreamError {
     #[error("Error on sending upstream: {0}")]
     SendError(String),
@@ -55,6 +61,48 @@ pub enum UpstreamError {
     Stratum(ii_stratum::error::Error),
     #[error("Timeout error: {0}")]
     Timeout(tokio::time::error::Elapsed),
+    #[error("Invalid message: {0}")]
+    InvalidMessage(#[from] InvalidUpstreamMessage),
+}
+
+/// Message received from the upstream that doesn't pass sanity checks
+#[derive(Error, Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum InvalidUpstreamMessage {
+    #[error("Invalid difficulty: {0}")]
+    Difficulty(f32),
+    #[error("Invalid prev hash length: {0}")]
+    PrevHashLength(usize),
+    #[error("More than {limit} mining.notify messages within {period:?}")]
+    NotifyStorm { limit: u32, period: Duration },
+}
+
+impl DownstreamError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::SendError(_) | Self::EarlyIo(_) => ErrorKind::Io,
+            Self::ProxyProtocol(_) => ErrorKind::Protocol,
+            Self::Stratum(e) => e.kind(),
+            Self::Timeout(_) => ErrorKind::Timeout,
+        }
+    }
+}
+
+impl UpstreamError {
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::SendError(_) | Self::Io(_) => ErrorKind::Io,
+            Self::ProxyProtocol(_) | Self::InvalidMessage(_) => ErrorKind::Protocol,
+            Self::Stratum(e) => e.kind(),
+            Self::Timeout(_) => ErrorKind::Timeout,
+        }
+    }
+}
+
+impl From<InvalidUpstreamMessage> for Error {
+    fn from(e: InvalidUpstreamMessage) -> Self {
+        Self::Upstream(e.into())
+    }
 }
 
 impl<T> From<mpsc::TrySendError<T>> for UpstreamError {
@@ -69,7 +117,20 @@ impl<T> From<mpsc::TrySendError<T>> for DownstreamError {
     }
 }
 
+/// Invalid combination of parts passed to `ProxyServerBuilder`
+#[derive(Error, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum BuilderError {
+    #[error("Missing {0}")]
+    Missing(&'static str),
+    #[error("Both {0} and {1} specified, only one of them can be used")]
+    Conflict(&'static str, &'static str),
+    #[error("{0} requires {1}")]
+    Requires(&'static str, &'static str),
+}
+
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum V2ProtocolError {
     #[error("V2 Setup Connection error: {0}")]
     SetupConnection(String),
@@ -92,6 +153,7 @@ impl V2ProtocolError {
 }
 
 #[derive(Error, Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[error("Failed to resolved host: {0}")]
     HostNameError(String),
@@ -127,6 +189,10 @@ pub enum Error {
     #[error("Client connection attempt error: {0}")]
     ClientAttempt(#[from] ii_wire::AttemptError),
 
+    /// All attempts to connect to the upstream server have failed
+    #[error("Cannot connect to upstream: {0}")]
+    UpstreamConnect(RetryError<io::Error>),
+
     /// File content error
     #[error("Invalid content of key/certificate file: {0}")]
     InvalidFile(String),
@@ -148,11 +214,86 @@ pub enum Error {
     #[error("Stratum V2 protocol state related error: {0}")]
     Protocol(V2ProtocolError),
 
+    /// The server cannot be built from the specified parts
+    #[error("Invalid server configuration: {0}")]
+    Builder(#[from] BuilderError),
+
     #[error("I/O error: {0}")]
     Io(std::io::Error),
 
+    /// Upstream server has closed the connection
+    #[error("Upstream V1 stratum connection closed by {peer}")]
+    UpstreamClosed { peer: SocketAddr },
+
+    /// Downstream client has closed the connection
+    #[error("Downstream connection closed by {peer}")]
+    DownstreamClosed { peer: SocketAddr },
+
     #[error("Noise security error: {0}")]
     Noise(#[from] ii_noise_proxy::Error),
+
+    /// Session has been cancelled, e.g. due to the proxy shutting down
+    #[error("Session cancelled")]
+    Cancelled,
+
+    /// Error annotated with the circumstances in which it occurred
+    #[error("{source} ({context})")]
+    Context {
+        context: ErrorContext,
+        source: Box<Error>,
+    },
+}
+
+impl Error {
+    /// Annotates the error with `context`
+    pub fn with_context(self, context: ErrorContext) -> Self {
+        Self::Context {
+            context,
+            source: Box::new(self),
+        }
+    }
+
+    /// Provides the context of an annotated error
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            Self::Context { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self {
+            Self::HostNameError(_)
+            | Self::ClientAttempt(_)
+            | Self::UpstreamConnect(_)
+            | Self::Io(_)
+            | Self::UpstreamClosed { .. }
+            | Self::DownstreamClosed { .. } => ErrorKind::Io,
+            Self::General(_) | Self::GeneralWithMetricsLabel(..) | Self::Cancelled => {
+                ErrorKind::Internal
+            }
+            Self::Stratum(e) => e.kind(),
+            Self::BitcoinHashes(_) | Self::Utf8(_) | Self::Json(_) | Self::Protocol(_) => {
+                ErrorKind::Protocol
+            }
+            Self::Timeout(_) => ErrorKind::Timeout,
+            Self::InvalidFile(_) | Self::Builder(_) => ErrorKind::Config,
+            #[cfg(feature = "prometheus_metrics")]
+            Self::Metrics(_) => ErrorKind::Internal,
+            Self::Downstream(e) => e.kind(),
+            Self::Upstream(e) => e.kind(),
+            Self::Noise(ii_noise_proxy::Error::IoError(_)) => ErrorKind::Io,
+            Self::Noise(ii_noise_proxy::Error::TimeValidationError) => ErrorKind::Auth,
+            Self::Noise(_) => ErrorKind::Config,
+            Self::Context { source, .. } => source.kind(),
+        }
+    }
+
+    /// Transient errors (I/O failures and timeouts) may be overcome by attempting the failed
+    /// operation again, e.g. by reconnecting to the upstream
+    pub fn is_retryable(&self) -> bool {
+        self.kind().is_retryable()
+    }
 }
 
 impl From<V2ProtocolError> for Error {
@@ -0,0 +1,493 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Export of connection and share events as JSON so that e.g. farm management software can follow
+//! the activity of the proxy. Events are queued and delivered in batches by a background task,
+//! sessions never wait for the delivery. Events that don't fit into the queue are dropped.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use hyper::{Body, Client, Method, Request, Uri};
+use tokio::io::AsyncWriteExt;
+use tokio::net::UnixStream;
+use tokio::sync::mpsc;
+
+use ii_logging::macros::*;
+use ii_stratum::v2::telemetry::messages::{
+    ChainReading, FanReading, SubmitDeviceStatus, TemperatureReading,
+};
+
+use crate::error::{Error, Result};
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    ConnectionOpened {
+        source_addr: SocketAddr,
+    },
+    ConnectionClosed {
+        source_addr: SocketAddr,
+        duration_secs: f64,
+        shares_accepted: u64,
+        shares_rejected: u64,
+        reason: String,
+    },
+    ShareAccepted {
+        source_addr: SocketAddr,
+        user: String,
+        difficulty: u128,
+    },
+    ShareRejected {
+        source_addr: SocketAddr,
+        user: String,
+        difficulty: u128,
+        reason: String,
+    },
+    DeviceStatus {
+        dev_id: String,
+        seq_num: u32,
+        temperatures: Vec<TemperatureReading>,
+        fans: Vec<FanReading>,
+        chains: Vec<ChainReading>,
+    },
+}
+
+impl From<&SubmitDeviceStatus> for Event {
+    fn from(status: &SubmitDeviceStatus) -> Self {
+        Event::DeviceStatus {
+            dev_id: status.dev_id.to_string(),
+            seq_num: status.seq_num,
+            temperatures: status.temperatures.to_vec(),
+            fans: status.fans.to_vec(),
+            chains: status.chains.to_vec(),
+        }
+    }
+}
+
+/// Event along with the time it has occurred
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct EventRecord {
+    /// Milliseconds since the UNIX epoch
+    pub timestamp_ms: u64,
+    #[serde(flatten)]
+    pub event: Event,
+}
+
+/// Destination of exported events
+#[async_trait]
+pub trait EventSink: Send {
+    /// Delivers a batch of events, the batch is dropped if the delivery fails
+    async fn deliver(&mut self, events: &[EventRecord]) -> Result<()>;
+}
+
+/// POSTs each batch as a JSON array to an HTTP endpoint
+pub struct WebhookSink {
+    url: Uri,
+    timeout: Duration,
+    client: Client<hyper::client::HttpConnector>,
+}
+
+impl WebhookSink {
+    pub fn new(url: Uri, timeout: Duration) -> Self {
+        Self {
+            url,
+            timeout,
+            client: Client::new(),
+        }
+    }
+
+    async fn post(&self, body: String) -> Result<()> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header("content-type", "application/json")
+            .body(Body::from(body))
+            .map_err(|e| Error::General(format!("Invalid event webhook request: {}", e)))?;
+        let response = self
+            .client
+            .request(request)
+            .await
+            .map_err(|e| Error::General(format!("Event webhook request failed: {}", e)))?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::General(format!(
+                "Unexpected event webhook response status: {}",
+                response.status()
+            )))
+        }
+    }
+}
+
+#[async_trait]
+impl EventSink for WebhookSink {
+    async fn deliver(&mut self, events: &[EventRecord]) -> Result<()> {
+        let body = serde_json::to_string(events)?;
+        tokio::time::timeout(self.timeout, self.post(body))
+            .await
+            .map_err(Error::Timeout)?
+    }
+}
+
+/// Writes events as JSON lines to a Unix stream socket, the socket is connected again after a
+/// failure
+pub struct UnixSocketSink {
+    path: PathBuf,
+    stream: Option<UnixStream>,
+}
+
+impl UnixSocketSink {
+    pub fn new(path: PathBuf) -> Self {
+        Self { path, stream: None }
+    }
+}
+
+#[async_trait]
+impl EventSink for UnixSocketSink {
+    async fn deliver(&mut self, events: &[EventRecord]) -> Result<()> {
+        let mut lines = String::new();
+        for event in events {
+            lines.push_str(&serde_json::to_string(event)?);
+            lines.push('\n');
+        }
+        let stream = match self.stream.as_mut() {
+            Some(stream) => stream,
+            None => self
+                .stream
+                .get_or_insert(UnixStream::connect(&self.path).await.map_err(Error::Io)?),
+        };
+        let result = stream.write_all(lines.as_bytes()).await;
+        if result.is_err() {
+            self.stream = None;
+        }
+        result.map_err(Error::Io)
+    }
+}
+
+/// Produces each event as a Kafka message keyed by the source address of the device
+#[cfg(feature = "kafka")]
+pub struct KafkaSink {
+    producer: rdkafka::producer::FutureProducer,
+    topic: String,
+    timeout: Duration,
+}
+
+#[cfg(feature = "kafka")]
+impl KafkaSink {
+    pub fn new(brokers: &str, topic: String, timeout: Duration) -> Result<Self> {
+        let producer = rdkafka::ClientConfig::new()
+            .set("bootstrap.servers", brokers)
+            .create()
+            .map_err(|e| Error::General(format!("Cannot create Kafka producer: {}", e)))?;
+        Ok(Self {
+            producer,
+            topic,
+            timeout,
+        })
+    }
+
+    fn key(event: &Event) -> String {
+        match event {
+            Event::ConnectionOpened { source_addr }
+            | Event::ConnectionClosed { source_addr, .. }
+            | Event::ShareAccepted { source_addr, .. }
+            | Event::ShareRejected { source_addr, .. } => source_addr.to_string(),
+            Event::DeviceStatus { dev_id, .. } => dev_id.clone(),
+        }
+    }
+}
+
+#[cfg(feature = "kafka")]
+#[async_trait]
+impl EventSink for KafkaSink {
+    async fn deliver(&mut self, events: &[EventRecord]) -> Result<()> {
+        for event in events {
+            let payload = serde_json::to_string(event)?;
+            let key = Self::key(&event.event);
+            let record = rdkafka::producer::FutureRecord::to(&self.topic)
+                .payload(&payload)
+                .key(&key);
+            self.producer
+                .send(record, self.timeout)
+                .await
+                .map_err(|(e, _)| Error::General(format!("Kafka delivery failed: {}", e)))?;
+        }
+        Ok(())
+    }
+}
+
+/// Queues events for the export task, clones share the queue
+#[derive(Clone, Debug)]
+pub struct EventExporter {
+    tx: mpsc::Sender<EventRecord>,
+    dropped: Arc<AtomicU64>,
+}
+
+impl EventExporter {
+    /// Maximum number of events delivered at once
+    const MAX_BATCH_SIZE: usize = 100;
+
+    /// Builds the exporter along with the task that delivers the events to `sink`, the task
+    /// finishes once all clones of the exporter are dropped
+    pub fn new(
+        sink: Box<dyn EventSink>,
+        queue_size: usize,
+    ) -> (Self, impl Future<Output = ()> + Send) {
+        let (tx, rx) = mpsc::channel(queue_size);
+        let exporter = Self {
+            tx,
+            dropped: Default::default(),
+        };
+        (exporter, Self::run(rx, sink))
+    }
+
+    pub fn export(&self, event: Event) {
+        let timestamp_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        if self
+            .tx
+            .try_send(EventRecord {
+                timestamp_ms,
+                event,
+            })
+            .is_err()
+        {
+            let dropped = self.dropped.fetch_add(1, Relaxed) + 1;
+            // Drops come in bursts while the sink is slow, warn with decreasing frequency
+            if dropped.is_power_of_two() {
+                warn!(
+                    "Event export queue is full, {} events dropped so far",
+                    dropped
+                );
+            }
+        }
+    }
+
+    /// Number of events that have been dropped because the queue was full
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Relaxed)
+    }
+
+    async fn run(mut rx: mpsc::Receiver<EventRecord>, mut sink: Box<dyn EventSink>) {
+        while let Some(event) = rx.recv().await {
+            let mut batch = vec![event];
+            while batch.len() < Self::MAX_BATCH_SIZE {
+                match rx.recv().now_or_never() {
+                    Some(Some(event)) => batch.push(event),
+                    _ => break,
+                }
+            }
+            if let Err(e) = sink.deliver(&batch).await {
+                warn!("Dropping {} events, export failed: {}", batch.len(), e);
+            }
+        }
+    }
+}
+
+/// Event export section of the proxy configuration, exactly one sink has to be specified
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct EventExportConfig {
+    /// HTTP endpoint that receives batches of events
+    pub webhook_url: Option<String>,
+    /// Unix socket that receives events as JSON lines
+    pub unix_socket: Option<PathBuf>,
+    /// Comma separated Kafka brokers, requires the `kafka` feature
+    pub kafka_brokers: Option<String>,
+    #[serde(default = "EventExportConfig::default_kafka_topic")]
+    pub kafka_topic: String,
+    #[serde(default = "EventExportConfig::default_queue_size")]
+    pub queue_size: usize,
+    #[serde(default = "EventExportConfig::default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+impl EventExportConfig {
+    fn default_kafka_topic() -> String {
+        "stratum-proxy-events".to_string()
+    }
+
+    fn default_queue_size() -> usize {
+        10_000
+    }
+
+    fn default_timeout_secs() -> u64 {
+        5
+    }
+
+    pub fn build_sink(&self) -> Result<Box<dyn EventSink>> {
+        let timeout = Duration::from_secs(self.timeout_secs);
+        match (
+            self.webhook_url.as_ref(),
+            self.unix_socket.as_ref(),
+            self.kafka_brokers.as_ref(),
+        ) {
+            (Some(webhook_url), None, None) => {
+                let url = webhook_url.parse::<Uri>().map_err(|e| {
+                    Error::General(format!("Invalid event webhook URL {}: {}", webhook_url, e))
+                })?;
+                Ok(Box::new(WebhookSink::new(url, timeout)))
+            }
+            (None, Some(unix_socket), None) => {
+                Ok(Box::new(UnixSocketSink::new(unix_socket.clone())))
+            }
+            #[cfg(feature = "kafka")]
+            (None, None, Some(kafka_brokers)) => Ok(Box::new(KafkaSink::new(
+                kafka_brokers,
+                self.kafka_topic.clone(),
+                timeout,
+            )?)),
+            #[cfg(not(feature = "kafka"))]
+            (None, None, Some(_)) => Err(Error::General(
+                "Kafka event export requires the proxy to be built with the 'kafka' feature"
+                    .to_string(),
+            )),
+            _ => Err(Error::General(
+                "Event export requires exactly one of webhook_url, unix_socket and kafka_brokers"
+                    .to_string(),
+            )),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::Mutex;
+    use tokio::io::AsyncBufReadExt;
+
+    /// Sink that keeps delivered batches
+    #[derive(Clone, Default)]
+    struct TestSink {
+        batches: Arc<Mutex<Vec<Vec<EventRecord>>>>,
+    }
+
+    #[async_trait]
+    impl EventSink for TestSink {
+        async fn deliver(&mut self, events: &[EventRecord]) -> Result<()> {
+            self.batches
+                .lock()
+                .expect("BUG: Poisoned batches")
+                .push(events.to_vec());
+            Ok(())
+        }
+    }
+
+    fn build_share_event(user: &str) -> Event {
+        Event::ShareAccepted {
+            source_addr: "10.0.0.1:4321".parse().expect("BUG: Invalid address"),
+            user: user.to_string(),
+            difficulty: 4,
+        }
+    }
+
+    #[test]
+    fn test_event_format() {
+        let record = EventRecord {
+            timestamp_ms: 1000,
+            event: build_share_event("user.worker"),
+        };
+        assert_eq!(
+            serde_json::to_value(&record).expect("BUG: Cannot serialize event"),
+            serde_json::json!({
+                "timestamp_ms": 1000,
+                "type": "share_accepted",
+                "source_addr": "10.0.0.1:4321",
+                "user": "user.worker",
+                "difficulty": 4,
+            })
+        );
+    }
+
+    #[test]
+    fn test_device_status_event_format() {
+        let status = ii_stratum::test_utils::v2::build_submit_device_status();
+        let value = serde_json::to_value(Event::from(&status))
+            .expect("BUG: Cannot serialize device status event");
+        assert_eq!(value["type"], "device_status");
+        assert_eq!(value["dev_id"], "xyz");
+        assert_eq!(value["seq_num"], 1);
+        assert_eq!(
+            value["fans"],
+            serde_json::json!([{"fan_id": 0, "rpm": 4200}])
+        );
+        assert_eq!(value["temperatures"][1]["sensor_id"], 1);
+        assert_eq!(value["chains"][0]["chain_id"], 6);
+    }
+
+    #[tokio::test]
+    async fn test_exporter_drops_events_when_full() {
+        let sink = TestSink::default();
+        let (exporter, task) = EventExporter::new(Box::new(sink.clone()), 2);
+        for i in 0..3 {
+            exporter.export(build_share_event(&format!("user.worker{}", i)));
+        }
+        assert_eq!(exporter.dropped(), 1);
+        drop(exporter);
+        task.await;
+
+        let batches = sink.batches.lock().expect("BUG: Poisoned batches");
+        assert_eq!(batches.len(), 1);
+        assert_eq!(batches[0].len(), 2);
+        assert_eq!(batches[0][1].event, build_share_event("user.worker1"));
+    }
+
+    #[tokio::test]
+    async fn test_unix_socket_sink() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("events.sock");
+        let listener = tokio::net::UnixListener::bind(&path).expect("BUG: Cannot bind socket");
+        let mut sink = UnixSocketSink::new(path);
+        let record = EventRecord {
+            timestamp_ms: 1000,
+            event: build_share_event("user.worker"),
+        };
+        sink.deliver(&[record.clone(), record])
+            .await
+            .expect("BUG: Cannot deliver events");
+
+        let (stream, _) = listener
+            .accept()
+            .await
+            .expect("BUG: Cannot accept connection");
+        let mut lines = tokio::io::BufReader::new(stream).lines();
+        for _ in 0..2 {
+            let line = lines
+                .next_line()
+                .await
+                .expect("BUG: Cannot read line")
+                .expect("BUG: Missing line");
+            let event: serde_json::Value =
+                serde_json::from_str(&line).expect("BUG: Invalid event JSON");
+            assert_eq!(event["user"], "user.worker");
+        }
+    }
+}
@@ -20,23 +20,104 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+pub mod check;
+mod env;
+
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use std::time::Duration;
 use structopt::StructOpt;
 
+use ii_async_utils::WriteCoalescing;
+use ii_logging::SyslogAddress;
 use ii_noise_proxy::SecurityContext;
 use ii_scm::global::Version;
-use ii_wire::Address;
+use ii_stratum::v2::noise::auth::PreSharedKey;
+use ii_wire::{srv::SrvResolver, Address};
 
+use crate::accounting::ShareAccountingConfig;
+use crate::auth::AuthenticationConfig;
+use crate::control::ControlSocketAddress;
 use crate::error::{Error, Result};
-use crate::server::ProxyProtocolConfig;
+use crate::events::EventExportConfig;
+use crate::privileges::{Access, PrivilegesConfig, ReopenedFile};
+use crate::server::{
+    controller::ConnectionLimit, ProxyProtocolConfig, TcpUpstream, UpstreamCredentials,
+    UpstreamRouting,
+};
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = Version::signature().as_str(), version = Version::full().as_str())]
 pub struct Args {
     #[structopt(short = "c", long = "conf", help("Path to configuration file"))]
     pub config_file: PathBuf,
+    #[structopt(
+        long = "json-log",
+        help("Log records as JSON objects to standard error")
+    )]
+    pub json_log: bool,
+    #[structopt(
+        long = "log-file",
+        conflicts_with = "json-log",
+        help("Log to a file that is rotated instead of standard error")
+    )]
+    pub log_file: Option<PathBuf>,
+    #[structopt(
+        long = "log-max-size",
+        requires = "log-file",
+        help("Size in bytes after which the log file is rotated [default: 10485760]")
+    )]
+    pub log_max_size: Option<u64>,
+    #[structopt(
+        long = "log-max-files",
+        requires = "log-file",
+        help("Number of rotated log files to keep [default: 5]")
+    )]
+    pub log_max_files: Option<usize>,
+    #[structopt(
+        long = "log-compress",
+        requires = "log-file",
+        help("Compress rotated log files by gzip")
+    )]
+    pub log_compress: bool,
+    #[structopt(
+        long = "syslog",
+        conflicts_with_all = &["json-log", "log-file", "journald"],
+        help("Log to syslog at the specified UDP address or Unix socket path (e.g. /dev/log)")
+    )]
+    pub syslog: Option<SyslogAddress>,
+    #[structopt(
+        long = "journald",
+        conflicts_with_all = &["json-log", "log-file"],
+        help("Log to the systemd journal")
+    )]
+    pub journald: bool,
+    #[structopt(
+        long = "check-config",
+        help(
+            "Validate the configuration, resolve upstreams, load certificates and try to bind the \
+              listen address, then exit with a report"
+        )
+    )]
+    pub check_config: bool,
+    #[structopt(
+        long = "daemonize",
+        conflicts_with = "check-config",
+        help(
+            "Detach from the terminal and run in the background, standard streams are \
+              redirected to /dev/null. The command exits once the proxy listens, with failure \
+              if the proxy could not start"
+        )
+    )]
+    pub daemonize: bool,
+    #[structopt(
+        long = "pid-file",
+        conflicts_with = "check-config",
+        help("Write the process ID to a file that stays locked while the proxy runs")
+    )]
+    pub pid_file: Option<PathBuf>,
 }
 
 // TODO: Write Deserizlize manually in order to report errors and validate config more properly
@@ -47,11 +128,119 @@ pub struct Args {
 pub struct Config {
     pub listen_address: Address,
     pub upstream_address: Address,
+    /// New sessions are distributed among `upstream_address` and these upstreams as specified
+    /// by `upstream_routing`
+    #[serde(default)]
+    pub additional_upstream_addresses: Vec<Address>,
+    /// Round robin if not specified, sticky routing keeps each worker on the same upstream
+    pub upstream_routing: Option<UpstreamRouting>,
+    /// Pool accounts used instead of the credentials of downstream devices, keyed by the address
+    /// of the upstream (`host:port`)
+    #[serde(default)]
+    pub upstream_credentials: HashMap<String, UpstreamCredentials>,
     #[serde(default)] // Default for bool is "false"
     pub insecure: bool,
+    /// Expand environment variables in string values and table keys of the configuration (see
+    /// `env`). The expansion is opt-in as it changes the meaning of `$` in existing values.
+    #[serde(default)]
+    pub expand_env: bool,
     #[serde(flatten)]
     pub key_and_cert_files: Option<KeyAndCertFiles>,
+    /// Certificates that are served once the primary certificate is no longer valid, e.g. a
+    /// renewed certificate loaded ahead of its validity period
+    #[serde(default)]
+    pub rollover_certificates: Vec<KeyAndCertFiles>,
     pub proxy_protocol_config: Option<ProxyProtocolConfig>,
+    pub connection_limit: Option<ConnectionLimit>,
+    /// Maximum number of noise handshakes in progress
+    pub max_in_flight_handshakes: Option<usize>,
+    /// Downstream connections sending frames with longer payload are terminated
+    pub max_frame_size: Option<usize>,
+    /// Pre-shared key that downstream connections have to mix into the noise handshake
+    pub noise_psk: Option<PreSharedKey>,
+    /// Channels that submit no shares for this number of seconds are closed
+    pub idle_channel_timeout_secs: Option<u64>,
+    /// Session is terminated when the upstream doesn't respond to a request within this number
+    /// of seconds
+    pub v1_request_timeout_secs: Option<u64>,
+    /// Maximum number of shares submitted upstream that haven't been answered yet
+    pub max_submits_in_flight: Option<usize>,
+    /// Share is rejected (while the session is kept) when the upstream doesn't answer its
+    /// submission within this number of seconds
+    pub v1_submit_timeout_secs: Option<u64>,
+    /// Session is terminated when the upstream sends more `mining.notify` messages within a
+    /// minute
+    pub v1_max_notifies_per_minute: Option<u32>,
+    /// Maximum number of jobs per session that shares can be submitted for
+    pub max_tracked_jobs: Option<usize>,
+    /// Jobs of the previous block still accept shares for this number of seconds after the
+    /// upstream switches to a new block
+    pub stale_job_grace_period_secs: Option<u64>,
+    /// Shares with `ntime` rolled more than this number of seconds beyond `min_ntime` of the job
+    /// are rejected by the proxy
+    pub max_ntime_roll_secs: Option<u64>,
+    /// Lowest difficulty assigned to downstream devices regardless of the upstream difficulty
+    pub min_difficulty: Option<u32>,
+    /// Highest difficulty assigned to downstream devices regardless of the upstream difficulty
+    pub max_difficulty: Option<u32>,
+    /// Send `mining.extranonce.subscribe` upstream so that the pool may change extranonce via
+    /// `mining.set_extranonce`
+    #[serde(default)]
+    pub extranonce_subscribe: bool,
+    /// Accept known deviations from the protocol in messages sent by the upstream, e.g.
+    /// difficulty sent as a string
+    #[serde(default)]
+    pub v1_lenient_parsing: bool,
+    /// Pass the session ID of the previous upstream subscription of a reconnecting device to
+    /// `mining.subscribe` so that the pool can resume the session
+    #[serde(default)]
+    pub v1_session_resumption: bool,
+    pub control_socket: Option<ControlSocketAddress>,
+    /// Collect device status reports sent via the telemetry extension and export them as events,
+    /// requires `event_export`
+    #[serde(default)]
+    pub device_monitoring: bool,
+    /// Periodically store per-worker share counts
+    pub share_accounting: Option<ShareAccountingConfig>,
+    /// Authorize each channel before connecting it upstream
+    pub authentication: Option<AuthenticationConfig>,
+    /// Export connection and share events as JSON
+    pub event_export: Option<EventExportConfig>,
+    /// Account CPU time consumed by each session
+    #[serde(default)]
+    pub cpu_accounting: bool,
+    /// Batching of frames written to downstream and upstream connections
+    pub write_coalescing: Option<WriteCoalescingConfig>,
+    /// Switch to an unprivileged user once the listening sockets are bound
+    pub privileges: Option<PrivilegesConfig>,
+    /// V2 endpoint that receives frames of extensions unknown to the proxy over a connection per
+    /// session, the frames are dropped when not specified
+    pub extension_pass_through_address: Option<Address>,
+}
+
+/// Write coalescing section of the proxy configuration
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WriteCoalescingConfig {
+    /// Connection is flushed at the latest after this number of frames
+    #[serde(default = "WriteCoalescingConfig::default_max_frames")]
+    pub max_frames: usize,
+    /// How long to wait for more frames before flushing the connection
+    pub max_delay_ms: Option<u64>,
+}
+
+impl WriteCoalescingConfig {
+    fn default_max_frames() -> usize {
+        WriteCoalescing::default().max_items
+    }
+}
+
+impl From<&WriteCoalescingConfig> for WriteCoalescing {
+    fn from(config: &WriteCoalescingConfig) -> Self {
+        WriteCoalescing::default()
+            .with_max_items(config.max_frames)
+            .with_max_delay(config.max_delay_ms.map(Duration::from_millis))
+    }
 }
 
 #[derive(Debug, Deserialize)]
@@ -60,19 +249,216 @@ pub struct KeyAndCertFiles {
     secret_key_file: PathBuf,
 }
 
+impl KeyAndCertFiles {
+    async fn read_security_context(&self) -> Result<SecurityContext> {
+        SecurityContext::read_from_file(
+            self.certificate_file.as_path(),
+            self.secret_key_file.as_path(),
+        )
+        .await
+        .map_err(|e| Error::InvalidFile(format!("Failed to read certificate and key: {}", e)))
+    }
+}
+
 impl Default for Config {
     fn default() -> Self {
         Self {
             listen_address: Address("0.0.0.0".to_owned(), 3336),
             upstream_address: Address("stratum.slushpool.com".to_owned(), 3333),
+            additional_upstream_addresses: vec![],
+            upstream_routing: None,
+            upstream_credentials: HashMap::new(),
             insecure: true,
+            expand_env: false,
             key_and_cert_files: None,
+            rollover_certificates: vec![],
             proxy_protocol_config: None,
+            connection_limit: None,
+            max_in_flight_handshakes: None,
+            max_frame_size: None,
+            noise_psk: None,
+            idle_channel_timeout_secs: None,
+            v1_request_timeout_secs: None,
+            max_submits_in_flight: None,
+            v1_submit_timeout_secs: None,
+            v1_max_notifies_per_minute: None,
+            max_tracked_jobs: None,
+            stale_job_grace_period_secs: None,
+            max_ntime_roll_secs: None,
+            min_difficulty: None,
+            max_difficulty: None,
+            extranonce_subscribe: false,
+            v1_lenient_parsing: false,
+            v1_session_resumption: false,
+            control_socket: None,
+            device_monitoring: false,
+            share_accounting: None,
+            authentication: None,
+            event_export: None,
+            cpu_accounting: false,
+            write_coalescing: None,
+            privileges: None,
+            extension_pass_through_address: None,
         }
     }
 }
 
 impl Config {
+    /// Files that the running proxy opens by their paths, possibly after privileges have been
+    /// dropped (see `PrivilegesConfig::check_access`)
+    pub fn reopened_files(&self, args: &Args) -> Vec<ReopenedFile> {
+        // Reloaded on SIGHUP and by the control socket
+        let mut files = vec![ReopenedFile::new(&args.config_file, Access::Read)];
+        if !self.insecure {
+            for key_and_cert_files in self
+                .key_and_cert_files
+                .iter()
+                .chain(self.rollover_certificates.iter())
+            {
+                files.push(ReopenedFile::new(
+                    &key_and_cert_files.certificate_file,
+                    Access::Read,
+                ));
+                files.push(ReopenedFile::new(
+                    &key_and_cert_files.secret_key_file,
+                    Access::Read,
+                ));
+            }
+        }
+        if let Some(log_file) = args.log_file.as_ref() {
+            files.push(ReopenedFile::new(log_file, Access::Create));
+        }
+        if let Some(share_accounting) = self.share_accounting.as_ref() {
+            for path in share_accounting
+                .file
+                .iter()
+                .chain(share_accounting.sqlite_database.iter())
+            {
+                files.push(ReopenedFile::new(path, Access::Create));
+            }
+        }
+        if let Some(file) = self
+            .authentication
+            .as_ref()
+            .and_then(|authentication| authentication.file.as_ref())
+        {
+            files.push(ReopenedFile::new(file, Access::Read));
+        }
+        if let Some(unix_socket) = self
+            .event_export
+            .as_ref()
+            .and_then(|event_export| event_export.unix_socket.as_ref())
+        {
+            files.push(ReopenedFile::new(unix_socket, Access::Write));
+        }
+        files
+    }
+
+    pub async fn read_from_file(config_file: &Path) -> Result<Self> {
+        let config_file_string = tokio::fs::read_to_string(config_file)
+            .await
+            .map_err(Error::Io)?;
+        Self::parse(config_file_string.as_str(), &|name| {
+            std::env::var(name).ok()
+        })
+    }
+
+    /// Parses the configuration, environment variables are expanded by `lookup` if enabled by
+    /// `expand_env` (see `env`)
+    fn parse<F>(config_string: &str, lookup: &F) -> Result<Self>
+    where
+        F: Fn(&str) -> Option<String>,
+    {
+        let invalid =
+            |e: &dyn std::fmt::Display| Error::General(format!("Invalid configuration: {}", e));
+        let mut value: toml::Value = toml::from_str(config_string).map_err(|e| invalid(&e))?;
+        if let Some(toml::Value::Boolean(true)) = value.get("expand_env") {
+            env::expand_value(&mut value, lookup).map_err(|e| invalid(&e))?;
+        }
+        let config: Self = value.try_into().map_err(|e| invalid(&e))?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    /// Checks constraints between options that cannot be expressed by the format
+    fn validate(&self) -> Result<()> {
+        if self.listen_address.is_srv() {
+            return Err(Error::General(format!(
+                "Invalid configuration: listen_address {} is missing a port",
+                self.listen_address
+            )));
+        }
+        if let Some(address) = self.extension_pass_through_address.as_ref() {
+            if address.is_srv() {
+                return Err(Error::General(format!(
+                    "Invalid configuration: extension_pass_through_address {} is missing a port",
+                    address
+                )));
+            }
+        }
+        if let (Some(min), Some(max)) = (self.min_difficulty, self.max_difficulty) {
+            if min > max {
+                return Err(Error::General(format!(
+                    "Invalid configuration: min_difficulty {} exceeds max_difficulty {}",
+                    min, max
+                )));
+            }
+        }
+        if self.device_monitoring && self.event_export.is_none() {
+            return Err(Error::General(
+                "Invalid configuration: device_monitoring requires event_export".to_string(),
+            ));
+        }
+        if self.max_tracked_jobs == Some(0) {
+            return Err(Error::General(
+                "Invalid configuration: max_tracked_jobs must be positive".to_string(),
+            ));
+        }
+        if self.max_frame_size == Some(0) {
+            return Err(Error::General(
+                "Invalid configuration: max_frame_size must be positive".to_string(),
+            ));
+        }
+        if let Some(share_accounting) = self.share_accounting.as_ref() {
+            share_accounting.validate()?;
+        }
+        Ok(())
+    }
+
+    /// Builds all configured upstreams along with their credentials. Upstreams given by an SRV
+    /// record share a resolver, the record is resolved again once its TTL expires.
+    pub async fn upstreams(&self) -> Result<Vec<TcpUpstream>> {
+        let addresses: Vec<&Address> = std::iter::once(&self.upstream_address)
+            .chain(self.additional_upstream_addresses.iter())
+            .collect();
+        let names: Vec<String> = addresses.iter().map(ToString::to_string).collect();
+        if let Some(unknown) = self
+            .upstream_credentials
+            .keys()
+            .find(|name| !names.contains(name))
+        {
+            return Err(Error::General(format!(
+                "Invalid configuration: credentials of unknown upstream {}",
+                unknown
+            )));
+        }
+        let srv_resolver = if addresses.iter().any(|address| address.is_srv()) {
+            Some(SrvResolver::from_system_conf().map_err(|e| Error::HostNameError(e.to_string()))?)
+        } else {
+            None
+        };
+        let mut upstreams = vec![];
+        for (address, name) in addresses.into_iter().zip(names) {
+            let mut upstream = TcpUpstream::new(address.clone())
+                .with_credentials(self.upstream_credentials.get(&name).cloned());
+            if let Some(srv_resolver) = srv_resolver.as_ref().filter(|_| address.is_srv()) {
+                upstream = upstream.with_srv_resolver(srv_resolver.clone());
+            }
+            upstreams.push(upstream);
+        }
+        Ok(upstreams)
+    }
+
     /// Read certificates for current configuration and return:
     ///  - `None` if file path configurations are missing and/or `insecure == true` option
     ///  - SecurityContext `Some(SecurityContext)` if files are valid and `insecure == false`
@@ -81,17 +467,12 @@ impl Config {
         if self.insecure {
             Ok(None)
         } else if let Some(key_and_cert_files) = self.key_and_cert_files.as_ref() {
-            let ctx_result = SecurityContext::read_from_file(
-                key_and_cert_files.certificate_file.as_path(),
-                key_and_cert_files.secret_key_file.as_path(),
-            )
-            .await
-            .map_err(|e| Error::InvalidFile(format!("Failed to read certificate and key: {}", e)))
-            .map(Arc::new);
-            if let Ok(ctx) = ctx_result.as_ref() {
-                ctx.validate_by_time(std::time::SystemTime::now)?;
+            let mut ctx = key_and_cert_files.read_security_context().await?;
+            for rollover_files in self.rollover_certificates.iter() {
+                ctx = ctx.with_rollover(rollover_files.read_security_context().await?);
             }
-            ctx_result.map(Some)
+            ctx.validate_by_time(std::time::SystemTime::now)?;
+            Ok(Some(Arc::new(ctx.with_psk(self.noise_psk.clone()))))
         } else {
             Err(Error::InvalidFile(
                 "Certificate and key files are missing".to_owned(),
@@ -99,3 +480,262 @@ impl Config {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_rollover_certificates() {
+        let config = toml::from_str::<Config>(
+            r#"
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "stratum.slushpool.com:3333"
+            certificate_file = "server.cert"
+            secret_key_file = "server-secret.key"
+
+            [[rollover_certificates]]
+            certificate_file = "server-next.cert"
+            secret_key_file = "server-next-secret.key"
+            "#,
+        )
+        .expect("BUG: Cannot parse configuration");
+        assert!(config.key_and_cert_files.is_some());
+        assert_eq!(config.rollover_certificates.len(), 1);
+        assert_eq!(
+            config.rollover_certificates[0].certificate_file,
+            PathBuf::from("server-next.cert")
+        );
+    }
+
+    #[test]
+    fn test_parse_noise_psk() {
+        let config = toml::from_str::<Config>(
+            r#"
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "stratum.slushpool.com:3333"
+            noise_psk = "000102030405060708090a0b0c0d0e0f101112131415161718191a1b1c1d1e1f"
+            "#,
+        )
+        .expect("BUG: Cannot parse configuration");
+        let expected_psk: Vec<u8> = (0..32).collect();
+        assert_eq!(
+            config.noise_psk.as_ref().map(PreSharedKey::as_bytes),
+            Some(&expected_psk[..])
+        );
+
+        assert!(toml::from_str::<Config>(
+            r#"
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "stratum.slushpool.com:3333"
+            noise_psk = "0001"
+            "#,
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_parse_upstream_credentials() {
+        let config = toml::from_str::<Config>(
+            r#"
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "stratum.slushpool.com:3333"
+            additional_upstream_addresses = ["eu.stratum.slushpool.com:3333"]
+
+            [upstream_credentials."eu.stratum.slushpool.com:3333"]
+            user = "farm"
+            append_downstream_user = true
+            "#,
+        )
+        .expect("BUG: Cannot parse configuration");
+        let credentials = &config.upstream_credentials["eu.stratum.slushpool.com:3333"];
+        assert_eq!(credentials.password, None);
+        assert_eq!(credentials.upstream_user("worker"), "farm.worker");
+        assert_eq!(
+            config
+                .upstreams()
+                .await
+                .expect("BUG: Invalid upstreams")
+                .len(),
+            2
+        );
+
+        let config = toml::from_str::<Config>(
+            r#"
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "stratum.slushpool.com:3333"
+
+            [upstream_credentials."eu.stratum.slushpool.com:3333"]
+            user = "farm"
+            "#,
+        )
+        .expect("BUG: Cannot parse configuration");
+        assert!(config.upstreams().await.is_err());
+    }
+
+    #[test]
+    fn test_validate_max_tracked_jobs() {
+        let mut config = Config {
+            max_tracked_jobs: Some(1),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        config.max_tracked_jobs = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_max_frame_size() {
+        let mut config = Config {
+            max_frame_size: Some(1024),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        config.max_frame_size = Some(0);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_listen_address() {
+        let mut config = Config::default();
+        assert!(config.validate().is_ok());
+        config.listen_address = "_stratum._tcp.pool.example.com"
+            .parse()
+            .expect("BUG: Cannot parse SRV name");
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_extension_pass_through_address() {
+        let mut config = Config {
+            extension_pass_through_address: Some(Address("127.0.0.1".to_owned(), 3340)),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        config.extension_pass_through_address = Some(
+            "_stratum._tcp.pool.example.com"
+                .parse()
+                .expect("BUG: Cannot parse SRV name"),
+        );
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_validate_device_monitoring() {
+        let mut config = Config {
+            device_monitoring: true,
+            ..Default::default()
+        };
+        assert!(config.validate().is_err());
+        config.event_export = Some(EventExportConfig {
+            webhook_url: Some("http://127.0.0.1:8080/events".to_string()),
+            unix_socket: None,
+            kafka_brokers: None,
+            kafka_topic: "stratum-proxy-events".to_string(),
+            queue_size: 10,
+            timeout_secs: 5,
+        });
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_validate_difficulty_range() {
+        let mut config = Config {
+            min_difficulty: Some(1024),
+            max_difficulty: Some(65536),
+            ..Default::default()
+        };
+        assert!(config.validate().is_ok());
+        config.max_difficulty = Some(512);
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_parse_env_vars() {
+        let lookup = |name: &str| match name {
+            "PROXY_DIR" => Some("/etc/proxy".to_string()),
+            "POOL_PASSWORD" => Some("secret".to_string()),
+            _ => None,
+        };
+        let config = Config::parse(
+            r#"
+            expand_env = true
+            listen_address = "0.0.0.0:${LISTEN_PORT:-3336}"
+            upstream_address = "${UPSTREAM:-stratum.slushpool.com:3333}"
+            certificate_file = "${PROXY_DIR}/server.cert"
+            secret_key_file = "${PROXY_DIR}/server-secret.key"
+
+            [upstream_credentials."${UPSTREAM:-stratum.slushpool.com:3333}"]
+            user = "farm"
+            password = "${POOL_PASSWORD}"
+            "#,
+            &lookup,
+        )
+        .expect("BUG: Cannot parse configuration");
+        assert_eq!(config.listen_address, Address("0.0.0.0".to_owned(), 3336));
+        assert_eq!(
+            config
+                .key_and_cert_files
+                .as_ref()
+                .map(|files| files.certificate_file.clone()),
+            Some(PathBuf::from("/etc/proxy/server.cert"))
+        );
+        assert_eq!(
+            config.upstream_credentials["stratum.slushpool.com:3333"].password,
+            Some("secret".to_string())
+        );
+        // Expanded secrets must not be logged along with the configuration
+        assert!(!format!("{:?}", config).contains("secret\""));
+
+        assert!(Config::parse(
+            r#"
+            expand_env = true
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "${UPSTREAM}"
+            "#,
+            &lookup,
+        )
+        .is_err());
+
+        // Values are taken literally unless the expansion is enabled
+        let config = Config::parse(
+            r#"
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "stratum.slushpool.com:3333"
+
+            [upstream_credentials."stratum.slushpool.com:3333"]
+            user = "farm"
+            password = "pa$$word${"
+            "#,
+            &lookup,
+        )
+        .expect("BUG: Cannot parse configuration");
+        assert_eq!(
+            config.upstream_credentials["stratum.slushpool.com:3333"].password,
+            Some("pa$$word${".to_string())
+        );
+    }
+
+    #[test]
+    fn test_parse_write_coalescing() {
+        let config = toml::from_str::<Config>(
+            r#"
+            listen_address = "0.0.0.0:3336"
+            upstream_address = "stratum.slushpool.com:3333"
+
+            [write_coalescing]
+            max_delay_ms = 2
+            "#,
+        )
+        .expect("BUG: Cannot parse configuration");
+        let write_coalescing: WriteCoalescing = config
+            .write_coalescing
+            .as_ref()
+            .expect("BUG: Write coalescing not parsed")
+            .into();
+        assert_eq!(
+            write_coalescing,
+            WriteCoalescing::default().with_max_delay(Some(Duration::from_millis(2)))
+        );
+    }
+}
@@ -0,0 +1,230 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Validation of the proxy configuration without starting the proxy (see `Args::check_config`),
+//! e.g. in a deployment pipeline before the service is restarted
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::time::SystemTime;
+
+use tokio::net::TcpListener;
+
+use ii_wire::Address;
+
+use super::{Args, Config};
+use crate::error::{Error, Result};
+
+/// Single check of the report along with details of its outcome
+#[derive(Debug)]
+pub struct CheckItem {
+    pub name: String,
+    pub outcome: Result<String>,
+}
+
+/// Outcome of all checks of a configuration
+#[derive(Debug, Default)]
+pub struct CheckReport {
+    pub items: Vec<CheckItem>,
+}
+
+impl CheckReport {
+    fn push(&mut self, name: String, outcome: Result<String>) {
+        self.items.push(CheckItem { name, outcome });
+    }
+
+    /// All checks have passed
+    pub fn is_ok(&self) -> bool {
+        self.items.iter().all(|item| item.outcome.is_ok())
+    }
+}
+
+impl fmt::Display for CheckReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for item in self.items.iter() {
+            match &item.outcome {
+                Ok(details) => writeln!(f, "[ OK ] {}: {}", item.name, details)?,
+                Err(e) => writeln!(f, "[FAIL] {}: {}", item.name, e)?,
+            }
+        }
+        let failed = self
+            .items
+            .iter()
+            .filter(|item| item.outcome.is_err())
+            .count();
+        write!(f, "{} checks, {} failed", self.items.len(), failed)
+    }
+}
+
+/// Runs all checks of `config` that is used along with `args`. The listen socket is bound and
+/// released immediately, therefore, the check fails if the proxy is already running with the
+/// same listen address.
+pub async fn check_config(config: &Config, args: &Args) -> CheckReport {
+    let mut report = CheckReport::default();
+    report.push(
+        "upstream credentials".to_string(),
+        config
+            .upstreams()
+            .await
+            .map(|upstreams| format!("{} upstreams configured", upstreams.len())),
+    );
+    for address in
+        std::iter::once(&config.upstream_address).chain(config.additional_upstream_addresses.iter())
+    {
+        report.push(
+            format!("upstream {}", address),
+            resolve_upstream(address).await,
+        );
+    }
+    report.push(
+        "security context".to_string(),
+        check_security_context(config).await,
+    );
+    report.push(
+        format!("listen address {}", config.listen_address),
+        check_bind(&config.listen_address).await,
+    );
+    if let Some(privileges) = config.privileges.as_ref() {
+        let reopened_files = config.reopened_files(args);
+        report.push(
+            "privileges".to_string(),
+            privileges.resolve().and_then(|account| {
+                privileges.check_access(&account, &reopened_files)?;
+                Ok(format!(
+                    "uid {}, gid {}, {} files accessible after dropping privileges",
+                    account.uid,
+                    account.gid,
+                    reopened_files.len()
+                ))
+            }),
+        );
+    }
+    report
+}
+
+async fn resolve(address: &Address) -> Result<Vec<SocketAddr>> {
+    let sockets: Vec<SocketAddr> = tokio::net::lookup_host(address.as_ref())
+        .await
+        .map_err(|e| Error::HostNameError(e.to_string()))?
+        .collect();
+    if sockets.is_empty() {
+        return Err(Error::HostNameError(format!(
+            "{} has no addresses",
+            address
+        )));
+    }
+    Ok(sockets)
+}
+
+/// Upstreams given by an SRV record are checked by resolving all targets of the record
+async fn resolve_upstream(address: &Address) -> Result<String> {
+    let targets = if address.is_srv() {
+        ii_wire::srv::resolve_srv(&address.0)
+            .await
+            .map_err(|e| Error::HostNameError(e.to_string()))?
+    } else {
+        vec![address.clone()]
+    };
+    let mut resolved = vec![];
+    for target in targets.iter() {
+        let sockets: Vec<String> = resolve(target)
+            .await?
+            .iter()
+            .map(ToString::to_string)
+            .collect();
+        resolved.push(if address.is_srv() {
+            format!("{} ({})", target, sockets.join(", "))
+        } else {
+            sockets.join(", ")
+        });
+    }
+    Ok(format!("resolved to {}", resolved.join(", ")))
+}
+
+async fn check_security_context(config: &Config) -> Result<String> {
+    match config.read_security_context().await? {
+        None => Ok("insecure, noise is disabled".to_string()),
+        Some(security_context) => {
+            let remaining = security_context
+                .remaining_validity(SystemTime::now())
+                .unwrap_or_default();
+            Ok(format!(
+                "certificate valid for another {} days",
+                remaining.as_secs() / (24 * 60 * 60)
+            ))
+        }
+    }
+}
+
+/// Binds the listen socket the same way the proxy does and releases it right away
+async fn check_bind(address: &Address) -> Result<String> {
+    let listen_socket = resolve(address).await?[0];
+    let listener = TcpListener::bind(listen_socket).await.map_err(Error::Io)?;
+    let local_addr = listener.local_addr().map_err(Error::Io)?;
+    Ok(format!("bound and released {}", local_addr))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_check_config() {
+        use structopt::StructOpt;
+
+        let args = Args::from_iter(&["ii-stratum-proxy", "--conf", "proxy.toml"]);
+        let config = Config {
+            listen_address: Address("127.0.0.1".to_owned(), 0),
+            upstream_address: Address("127.0.0.1".to_owned(), 3333),
+            ..Default::default()
+        };
+        let report = check_config(&config, &args).await;
+        assert!(report.is_ok(), "BUG: Unexpected failure: {}", report);
+        assert_eq!(report.items.len(), 4);
+
+        // Listen address is taken and the certificate is missing
+        let listener = TcpListener::bind("127.0.0.1:0")
+            .await
+            .expect("BUG: Cannot bind");
+        let port = listener.local_addr().expect("BUG: No local address").port();
+        let config = Config {
+            listen_address: Address("127.0.0.1".to_owned(), port),
+            insecure: false,
+            ..config
+        };
+        let report = check_config(&config, &args).await;
+        let failed: Vec<String> = report
+            .items
+            .iter()
+            .filter(|item| item.outcome.is_err())
+            .map(|item| item.name.clone())
+            .collect();
+        assert_eq!(
+            failed,
+            vec![
+                "security context".to_string(),
+                format!("listen address 127.0.0.1:{}", port)
+            ]
+        );
+        assert!(report.to_string().ends_with("4 checks, 2 failed"));
+    }
+}
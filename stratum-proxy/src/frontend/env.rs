@@ -0,0 +1,186 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Expansion of environment variables in string values and table keys of the configuration so
+//! that secrets (e.g. upstream passwords) don't have to be stored in the configuration file. The
+//! expansion is enabled by `expand_env = true` in the configuration:
+//!
+//! - `${NAME}` is replaced with the value of variable `NAME`, which has to be set
+//! - `${NAME:-default}` falls back to `default` if `NAME` is unset or empty
+//! - `$$` stands for a literal `$`
+
+use crate::error::{Error, Result};
+
+/// Expands variables in all strings and table keys of `value` (including nested tables and
+/// arrays), other types of values are kept intact
+pub fn expand_value<F>(value: &mut toml::Value, lookup: &F) -> Result<()>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    match value {
+        toml::Value::String(s) => *s = expand_str(s, lookup)?,
+        toml::Value::Array(items) => {
+            for item in items.iter_mut() {
+                expand_value(item, lookup)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            // Keys have to be expanded too so that e.g. upstream credentials can be assigned to
+            // an upstream address given by a variable
+            let mut expanded = toml::value::Table::new();
+            for (key, mut item) in std::mem::take(table) {
+                expand_value(&mut item, lookup)?;
+                let key = expand_str(&key, lookup)?;
+                if expanded.contains_key(&key) {
+                    return Err(Error::General(format!(
+                        "Duplicate key {:?} after expansion",
+                        key
+                    )));
+                }
+                expanded.insert(key, item);
+            }
+            *table = expanded;
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand_str<F>(s: &str, lookup: &F) -> Result<String>
+where
+    F: Fn(&str) -> Option<String>,
+{
+    let mut expanded = String::with_capacity(s.len());
+    let mut rest = s;
+    while let Some(pos) = rest.find('$') {
+        expanded.push_str(&rest[..pos]);
+        rest = &rest[pos..];
+        if let Some(tail) = rest.strip_prefix("$$") {
+            expanded.push('$');
+            rest = tail;
+        } else if let Some(tail) = rest.strip_prefix("${") {
+            let end = tail.find('}').ok_or_else(|| {
+                Error::General(format!("Unterminated variable reference in {:?}", s))
+            })?;
+            let reference = &tail[..end];
+            let (name, default) = match reference.find(":-") {
+                Some(pos) => (&reference[..pos], Some(&reference[pos + 2..])),
+                None => (reference, None),
+            };
+            if name.is_empty() {
+                return Err(Error::General(format!("Empty variable name in {:?}", s)));
+            }
+            let value = match (lookup(name), default) {
+                (Some(value), Some(default)) if value.is_empty() => default.to_string(),
+                (Some(value), _) => value,
+                (None, Some(default)) => default.to_string(),
+                (None, None) => {
+                    return Err(Error::General(format!(
+                        "Environment variable {} is not set",
+                        name
+                    )))
+                }
+            };
+            expanded.push_str(&value);
+            rest = &tail[end + 1..];
+        } else {
+            // A lone dollar sign is kept as is
+            expanded.push('$');
+            rest = &rest[1..];
+        }
+    }
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn lookup(name: &str) -> Option<String> {
+        match name {
+            "POOL_PASSWORD" => Some("secret".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn test_expand_str() {
+        for (s, expected) in [
+            ("plain", "plain"),
+            ("${POOL_PASSWORD}", "secret"),
+            ("x-${POOL_PASSWORD}-y", "x-secret-y"),
+            ("${UNSET:-/etc/proxy/server.cert}", "/etc/proxy/server.cert"),
+            ("${EMPTY:-fallback}", "fallback"),
+            ("${POOL_PASSWORD:-fallback}", "secret"),
+            ("${UNSET:-}", ""),
+            ("${EMPTY}", ""),
+            ("$$", "$"),
+            ("$${POOL_PASSWORD}", "${POOL_PASSWORD}"),
+            ("cost: 5$", "cost: 5$"),
+        ]
+        .iter()
+        {
+            assert_eq!(
+                expand_str(s, &lookup).expect("BUG: Cannot expand"),
+                *expected,
+                "{}",
+                s
+            );
+        }
+        for s in ["${UNSET}", "${POOL_PASSWORD", "${}"].iter() {
+            assert!(expand_str(s, &lookup).is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_expand_value() {
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            # Comments are not expanded: ${UNSET}
+            listen_address = "0.0.0.0:${UNSET:-3336}"
+            ports = [3333, 3334]
+            [upstream_credentials."${UNSET:-pool}:3333"]
+            user = "farm"
+            password = "${POOL_PASSWORD}"
+            "#,
+        )
+        .expect("BUG: Cannot parse");
+        expand_value(&mut value, &lookup).expect("BUG: Cannot expand");
+        assert_eq!(value["listen_address"].as_str(), Some("0.0.0.0:3336"));
+        assert_eq!(
+            value["upstream_credentials"]["pool:3333"]["password"].as_str(),
+            Some("secret")
+        );
+        assert_eq!(value["ports"][1].as_integer(), Some(3334));
+
+        let mut value: toml::Value = toml::from_str(
+            r#"
+            "${UNSET:-key}" = 1
+            key = 2
+            "#,
+        )
+        .expect("BUG: Cannot parse");
+        assert!(expand_value(&mut value, &lookup).is_err());
+    }
+}
@@ -27,10 +27,17 @@
 // the default recursion limit if more complex statements are used
 #![recursion_limit = "256"]
 
+pub mod accounting;
+pub mod auth;
+pub mod control;
+pub mod daemon;
 pub mod error;
+pub mod events;
 pub mod frontend;
 #[cfg_attr(not(feature = "prometheus_metrics"), path = "dummy_metrics.rs")]
 pub mod metrics;
+pub mod monitoring;
+pub mod privileges;
 pub mod server;
 pub mod translation;
 pub mod util;
@@ -25,35 +25,195 @@
 
 use anyhow::{Context, Result};
 use structopt::StructOpt;
+use tokio::sync::mpsc;
 
 use ii_async_utils::HaltHandle;
 use ii_logging::macros::*;
+use ii_logging::{FileRotation, LoggingConfig, LoggingTarget, SyslogConfig};
 use ii_scm::global::Version;
+use ii_stratum::v2::noise::HandshakeLimiter;
 use ii_stratum_proxy::{
-    frontend::{Args, Config},
+    accounting::ShareAccounting,
+    control::{self, ControlServer},
+    daemon::{self, PidFile},
+    events::EventExporter,
+    frontend::{check, Args, Config},
+    monitoring::DeviceMonitoringCollector,
     server::{self, controller::LoggingController, ProxyProtocolConfig},
+    translation::V2ToV1TranslationOptions,
 };
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     Version::set("StratumProxy", ii_scm::version_full!().as_str());
     ii_async_utils::setup_panic_handling();
 
-    let _logging_controller = LoggingController::new(None);
-
     let args = Args::from_args();
+    // Forking is possible only before the runtime spawns its threads
+    let readiness = if args.daemonize {
+        Some(daemon::daemonize().context("Cannot run in the background")?)
+    } else {
+        None
+    };
+    let _pid_file = args
+        .pid_file
+        .as_ref()
+        .map(PidFile::create)
+        .transpose()
+        .context("Cannot create PID file")?;
+
+    tokio::runtime::Runtime::new()
+        .context("Cannot start the runtime")?
+        .block_on(run(args, readiness))
+}
 
-    let config_file_string = tokio::fs::read_to_string(args.config_file)
+/// Runs the proxy, `readiness` is notified once the proxy serves
+async fn run(args: Args, readiness: Option<daemon::Readiness>) -> Result<()> {
+    let (log_command_tx, log_command_rx) = mpsc::channel(1);
+    let mut logging_config =
+        LoggingConfig::for_app(LoggingController::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE);
+    if args.json_log {
+        logging_config.target = LoggingTarget::StderrJson;
+    }
+    if let Some(path) = &args.log_file {
+        logging_config.target = LoggingTarget::RotatingFile(
+            path.clone(),
+            FileRotation {
+                max_size: args.log_max_size.unwrap_or(FileRotation::DEFAULT_MAX_SIZE),
+                max_files: args
+                    .log_max_files
+                    .unwrap_or(FileRotation::DEFAULT_MAX_FILES),
+                compress: args.log_compress,
+                ..Default::default()
+            },
+        );
+    }
+    if let Some(address) = &args.syslog {
+        logging_config.target = LoggingTarget::Syslog(SyslogConfig {
+            address: address.clone(),
+            ..Default::default()
+        });
+    }
+    if args.journald {
+        logging_config.target = LoggingTarget::Journald;
+    }
+    let _logging_controller = LoggingController::with_config(Some(log_command_rx), logging_config);
+
+    let config = Config::read_from_file(&args.config_file)
         .await
         .context("Proxy configuration file couldn't be read.")?;
-    let config = toml::from_str::<Config>(config_file_string.as_str())?;
+    if args.check_config {
+        let report = check::check_config(&config, &args).await;
+        println!("{}", report);
+        anyhow::ensure!(report.is_ok(), "Configuration check failed");
+        return Ok(());
+    }
     info!("Starting {}: {}", Version::signature(), Version::full(),);
     info!("Config: {:#?}", config);
+    if let Some(privileges) = config.privileges.as_ref() {
+        // Fail right away rather than once e.g. the log file is rotated
+        privileges
+            .resolve()
+            .and_then(|account| privileges.check_access(&account, &config.reopened_files(&args)))
+            .context("Invalid privileges configuration")?;
+    }
 
-    let server = server::ProxyServer::listen(
+    let translation_options = V2ToV1TranslationOptions {
+        try_enable_xnsub: config.extranonce_subscribe,
+        idle_channel_timeout: config
+            .idle_channel_timeout_secs
+            .map(std::time::Duration::from_secs),
+        v1_request_timeout: config
+            .v1_request_timeout_secs
+            .map(std::time::Duration::from_secs),
+        max_submits_in_flight: config.max_submits_in_flight,
+        v1_submit_timeout: config
+            .v1_submit_timeout_secs
+            .map(std::time::Duration::from_secs),
+        v1_max_notifies_per_minute: config.v1_max_notifies_per_minute,
+        max_tracked_jobs: config.max_tracked_jobs,
+        stale_job_grace_period: config
+            .stale_job_grace_period_secs
+            .map(std::time::Duration::from_secs),
+        max_ntime_roll: config
+            .max_ntime_roll_secs
+            .map(std::time::Duration::from_secs),
+        min_difficulty: config.min_difficulty,
+        max_difficulty: config.max_difficulty,
+        v1_parse_mode: if config.v1_lenient_parsing {
+            ii_stratum::v1::rpc::ParseMode::Lenient
+        } else {
+            ii_stratum::v1::rpc::ParseMode::Strict
+        },
+        ..Default::default()
+    };
+    let mut event_exporter = None;
+    if let Some(event_export) = config.event_export.as_ref() {
+        let sink = event_export
+            .build_sink()
+            .context("Cannot set up event export")?;
+        let (exporter, export_task) = EventExporter::new(sink, event_export.queue_size);
+        tokio::spawn(export_task);
+        event_exporter = Some(exporter);
+    }
+    let mut extensions = ii_stratum::v2::extensions::ExtensionRegistry::new();
+    if config.device_monitoring {
+        // Presence of the exporter is ensured by the configuration validation
+        let event_exporter = event_exporter
+            .clone()
+            .expect("BUG: device monitoring without event export");
+        DeviceMonitoringCollector::new(None)
+            .with_event_exporter(event_exporter)
+            .register(&mut extensions);
+    }
+    let mut translation_handler = server::TranslationHandler::new(None)
+        .with_options(translation_options)
+        .with_extensions(extensions);
+    if config.v1_session_resumption {
+        translation_handler = translation_handler.with_v1_session_resumption();
+    }
+    if let Some(address) = config.extension_pass_through_address.clone() {
+        translation_handler = translation_handler
+            .with_unknown_extensions_passed_to(server::ExtensionPassThrough::new(address));
+    }
+    if let Some(write_coalescing) = config.write_coalescing.as_ref() {
+        translation_handler = translation_handler.with_write_coalescing(write_coalescing.into());
+    }
+    if let Some(authentication) = config.authentication.as_ref() {
+        translation_handler = translation_handler.with_authenticator(
+            authentication
+                .build()
+                .context("Cannot set up authentication")?,
+        );
+    }
+    if let Some(event_exporter) = event_exporter {
+        translation_handler = translation_handler.with_event_exporter(event_exporter);
+    }
+    let mut share_accounting = None;
+    if let Some(accounting_config) = config.share_accounting.as_ref() {
+        let accounting = ShareAccounting::default();
+        let storage = accounting_config
+            .build_storage()
+            .context("Cannot set up share accounting")?;
+        storage
+            .open()
+            .context("Cannot open share accounting storage")?;
+        let flusher = tokio::spawn(
+            accounting
+                .clone()
+                .run_flusher(storage.clone(), accounting_config.flush_interval()),
+        );
+        translation_handler = translation_handler.with_share_accounting(accounting.clone());
+        share_accounting = Some((accounting, storage, flusher));
+    }
+    let upstreams = config.upstreams().await?;
+    let server = server::ProxyServer::listen_with_upstream(
         config.listen_address.clone(),
-        config.upstream_address.clone(),
-        server::TranslationHandler::new(None),
+        server::UpstreamSet::new(upstreams).with_routing(
+            config
+                .upstream_routing
+                .unwrap_or(server::UpstreamRouting::RoundRobin),
+        ),
+        translation_handler,
         config.read_security_context().await?,
         config
             .proxy_protocol_config
@@ -61,14 +221,76 @@ async fn main() -> Result<()> {
         None,
     )
     .await
-    .context("Cannot bind the server")?;
+    .context("Cannot bind the server")?
+    .with_connection_limit(config.connection_limit)
+    .with_handshake_limiter(
+        config
+            .max_in_flight_handshakes
+            .map(|limit| HandshakeLimiter::new(limit).with_preallocated_buffers(limit)),
+    )
+    .with_max_frame_size(config.max_frame_size)
+    .with_cpu_accounting(config.cpu_accounting);
+
+    let sighup_handler =
+        control::rotate_certificate_on_sighup(args.config_file.clone(), server.handle());
+    tokio::spawn(async move {
+        if let Err(e) = sighup_handler.await {
+            error!("Cannot handle SIGHUP: {}", e);
+        }
+    });
+    let sigusr1_handler = control::toggle_debug_logging_on_sigusr1(log_command_tx.clone());
+    tokio::spawn(async move {
+        if let Err(e) = sigusr1_handler.await {
+            error!("Cannot handle SIGUSR1: {}", e);
+        }
+    });
+
+    if let Some(control_socket) = config.control_socket {
+        let control_server = ControlServer::bind(
+            control_socket,
+            args.config_file,
+            server.handle(),
+            Some(log_command_tx),
+        )
+        .await
+        .context("Cannot bind the control socket")?;
+        tokio::spawn(async move {
+            if let Err(e) = control_server.run().await {
+                error!("Control socket failed: {}", e);
+            }
+        });
+    }
+
+    // All sockets that may need a privileged port are bound by now
+    if let Some(privileges) = config.privileges.as_ref() {
+        let account = privileges
+            .drop_privileges()
+            .context("Cannot drop privileges")?;
+        info!(
+            "Dropped privileges to uid {}, gid {}",
+            account.uid, account.gid
+        );
+    }
 
     let halt_handle = HaltHandle::arc();
     halt_handle.spawn_object(server);
     halt_handle.ready();
+    if let Some(readiness) = readiness {
+        // The starting process may have been killed meanwhile, which doesn't affect the proxy
+        if let Err(e) = readiness.notify() {
+            warn!("Cannot report readiness to the starting process: {}", e);
+        }
+    }
     halt_handle.halt_on_signal();
-    halt_handle
+    let result = halt_handle
         .join(Some(std::time::Duration::from_secs(5)))
-        .await
-        .map_err(Into::into)
+        .await;
+
+    if let Some((accounting, storage, flusher)) = share_accounting {
+        flusher.abort();
+        if let Err(e) = accounting.flush(storage.as_ref()) {
+            error!("Cannot store share accounting: {}", e);
+        }
+    }
+    result.map_err(Into::into)
 }
@@ -21,18 +21,23 @@
 // contact us at opensource@braiins.com.
 
 use crate::error;
-use crate::translation::V2ToV1Translation;
+use crate::server::controller::ConnectionLimitAction;
+use crate::server::{CloseReason, SessionSummary};
+use crate::translation::{ChannelDirection, RejectCode};
 use ii_metrics::MetricsRegistry;
 use ii_stratum::v1::rpc::Method;
+use ii_stratum::v2::telemetry::messages::SubmitDeviceStatus;
+use ii_stratum::v2::types::Uint256Bytes;
 pub use primitive_types::U256;
-use std::convert::TryInto;
-use std::sync::Arc;
+use std::collections::HashSet;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
 use std::time::Instant;
 use tokio::time::Duration;
 
 pub use prometheus::{
-    histogram_opts, opts, Encoder, Histogram, HistogramTimer, HistogramVec, IntCounter,
-    IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+    core::AtomicF64, histogram_opts, opts, Encoder, GaugeVec, Histogram, HistogramTimer,
+    HistogramVec, IntCounter, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
 };
 
 #[derive(Debug)]
@@ -97,6 +102,76 @@ impl ProxyMetrics {
                 "Total of TCP connections classified by 'accept' result",
                 &["result"], // Successful or Unsuccessful
             ),
+            tcp_connection_limit_reached_total: registry.register_generic_counter_vec(
+                "tcp_connection_limit_reached_total",
+                "Number of times the maximum number of connections has been reached",
+                &["action"], // Pause or Reject
+            ),
+            noise_handshake_rejected_total: registry.register_generic_counter(
+                "noise_handshake_rejected_total",
+                "Number of noise handshakes refused due to too many handshakes in progress",
+            ),
+            extension_frames_dropped_total: registry.register_generic_counter(
+                "extension_frames_dropped_total",
+                "Number of downstream extension frames that couldn't be passed through",
+            ),
+            translation_channel_full_total: registry.register_generic_counter_vec(
+                "translation_channel_full_total",
+                "Number of frames that couldn't be submitted due to a full translation channel",
+                &["direction"], // Upstream or Downstream
+            ),
+            submit_latency_seconds: registry.register_histogram_vec(
+                "submit_latency_seconds",
+                "Histogram of time between forwarding mining.submit upstream and its response",
+                &["upstream", "status"],
+                Self::LATENCY_BUCKETS.to_vec(),
+            ),
+            job_translation_latency_seconds: registry.register_histogram(
+                "job_translation_latency_seconds",
+                "Histogram of time between upstream mining.notify and writing NewMiningJob downstream",
+                Self::LATENCY_BUCKETS.to_vec(),
+            ),
+            upstream_invalid_messages_total: registry.register_generic_counter_vec(
+                "upstream_invalid_messages_total",
+                "Number of upstream messages rejected by sanity checks",
+                &["reason"],
+            ),
+            upstream_rejected_shares_total: registry.register_generic_counter_vec(
+                "upstream_rejected_shares_total",
+                "Number of shares rejected by the upstream",
+                &["reason"],
+            ),
+            device_temperature_celsius: registry.register_generic_gauge_vec::<AtomicF64>(
+                "device_temperature_celsius",
+                "Temperatures reported by devices via the telemetry extension",
+                &["device", "sensor"],
+            ),
+            device_fan_speed_rpm: registry.register_generic_gauge_vec::<AtomicF64>(
+                "device_fan_speed_rpm",
+                "Fan speeds reported by devices via the telemetry extension",
+                &["device", "fan"],
+            ),
+            device_chain_hashrate: registry.register_generic_gauge_vec::<AtomicF64>(
+                "device_chain_hashrate",
+                "Per-chain hashrate [h/s] reported by devices via the telemetry extension",
+                &["device", "chain"],
+            ),
+            monitored_devices: Mutex::new(HashSet::new()),
+            upstream_sessions: registry.register_generic_gauge_vec(
+                "upstream_sessions",
+                "Number of sessions connected to each upstream",
+                &["upstream"],
+            ),
+            upstream_draining: registry.register_generic_gauge_vec(
+                "upstream_draining",
+                "Set to 1 while the upstream is being drained, i.e. no new sessions are routed to it",
+                &["upstream"],
+            ),
+            session_traffic_bytes_total: registry.register_generic_counter_vec(
+                "session_traffic_bytes_total",
+                "Bytes transferred over downstream connections of finished sessions",
+                &["direction"], // In or Out
+            ),
             tcp_socket_failure_threshold: registry.register_histogram_vec(
                 "tcp_socket_failure_threshold",
                 "Number of tcp connection accept events before failure occurs",
@@ -133,19 +208,72 @@ pub struct ProxyMetrics {
     tcp_connection_accepts_per_socket: IntCounterVec,
     /// Number of tcp connection accept events before failure occurs
     tcp_socket_failure_threshold: HistogramVec,
+    /// Number of events when the connection limit has been hit, labels:
+    /// - action = (pause, reject)
+    tcp_connection_limit_reached_total: IntCounterVec,
+    /// Number of noise handshakes refused due to the limit of handshakes in progress
+    noise_handshake_rejected_total: IntCounter,
+    /// Number of extension frames dropped by the pass-through due to a full queue or an
+    /// unreachable endpoint
+    extension_frames_dropped_total: IntCounter,
+    /// Number of frames rejected by a saturated translation channel, labels:
+    /// - direction = (upstream, downstream)
+    translation_channel_full_total: IntCounterVec,
+    /// Traffic of downstream connections accounted when a session ends, labels:
+    /// - direction = (in, out)
+    session_traffic_bytes_total: IntCounterVec,
+    /// Sessions connected to each upstream, a draining upstream is drained once this drops to
+    /// zero, labels:
+    /// - upstream = name of the upstream
+    upstream_sessions: IntGaugeVec,
+    /// Draining status of upstreams, labels:
+    /// - upstream = name of the upstream
+    upstream_draining: IntGaugeVec,
+    /// Latency of share submission, labels:
+    /// - upstream = address of the upstream server
+    /// - status = (success, error)
+    submit_latency_seconds: HistogramVec,
+    /// Processing time of new mining jobs in the proxy
+    job_translation_latency_seconds: Histogram,
+    /// Number of upstream messages that failed the sanity checks, labels:
+    /// - reason = (difficulty, prev_hash, notify_storm)
+    upstream_invalid_messages_total: IntCounterVec,
+    /// Number of shares rejected by the upstream, labels:
+    /// - reason = V2 error code the reject reason translates to or "other"
+    upstream_rejected_shares_total: IntCounterVec,
+    /// Last reported device temperatures, labels:
+    /// - device = device identifier
+    /// - sensor = vendor specific sensor identifier
+    device_temperature_celsius: GaugeVec,
+    /// Last reported fan speeds, labels:
+    /// - device = device identifier
+    /// - fan = fan identifier
+    device_fan_speed_rpm: GaugeVec,
+    /// Last reported hashrate of individual hashing chains, labels:
+    /// - device = device identifier
+    /// - chain = chain identifier
+    device_chain_hashrate: GaugeVec,
+    /// Devices whose status is exported, see `MAX_MONITORED_DEVICES`
+    monitored_devices: Mutex<HashSet<String>>,
 }
 
 impl ProxyMetrics {
     const SUCCESS_LABEL: &'static str = "success";
     const ERROR_LABEL: &'static str = "error";
+    /// Device identifiers are chosen by the devices themselves, status of devices beyond this
+    /// limit is not exported so that they cannot create an unbounded number of time series
+    const MAX_MONITORED_DEVICES: usize = 1024;
+    /// Buckets suitable for estimating latency percentiles from 100us to ~13s
+    const LATENCY_BUCKETS: [f64; 18] = [
+        0.0001, 0.0002, 0.0004, 0.0008, 0.0016, 0.0032, 0.0064, 0.0128, 0.0256, 0.0512, 0.1024,
+        0.2048, 0.4096, 0.8192, 1.6384, 3.2768, 6.5536, 13.1072,
+    ];
 
     /// Helper that accounts a share if `target` is provided among timeseries specified by
     /// `label_values`. If no target is specified only submit is accounted
     fn account_share(&self, target: Option<U256>, label_values: &[&str]) {
         if let Some(tgt) = target {
-            let share_value = V2ToV1Translation::target_to_diff(tgt)
-                .try_into()
-                .expect("BUG: Failed to convert target difficulty");
+            let share_value = Uint256Bytes::from(tgt).difficulty().round() as u64;
             self.shares_total
                 .with_label_values(label_values)
                 .inc_by(share_value);
@@ -192,6 +320,34 @@ impl ProxyMetrics {
             .inc();
     }
 
+    pub fn account_connection_limit_reached(&self, action: ConnectionLimitAction) {
+        let action_label = match action {
+            ConnectionLimitAction::Pause => "pause",
+            ConnectionLimitAction::Reject => "reject",
+        };
+        self.tcp_connection_limit_reached_total
+            .with_label_values(&[action_label])
+            .inc();
+    }
+
+    pub fn account_handshake_rejected(&self) {
+        self.noise_handshake_rejected_total.inc();
+    }
+
+    pub fn account_extension_frame_dropped(&self) {
+        self.extension_frames_dropped_total.inc();
+    }
+
+    pub fn account_translation_channel_full(&self, direction: ChannelDirection) {
+        let direction_label = match direction {
+            ChannelDirection::Upstream => "upstream",
+            ChannelDirection::Downstream => "downstream",
+        };
+        self.translation_channel_full_total
+            .with_label_values(&[direction_label])
+            .inc();
+    }
+
     pub fn observe_v1_request_success(
         &self,
         request_method: ii_stratum::v1::rpc::Method,
@@ -208,6 +364,75 @@ impl ProxyMetrics {
         self.observe_v1_request_duration(request_method, duration, Self::ERROR_LABEL);
     }
 
+    pub fn observe_submit_latency(
+        &self,
+        upstream_addr: Option<SocketAddr>,
+        duration: Duration,
+        success: bool,
+    ) {
+        let upstream_label =
+            upstream_addr.map_or_else(|| String::from("N/A"), |addr| addr.to_string());
+        let status_label = if success {
+            Self::SUCCESS_LABEL
+        } else {
+            Self::ERROR_LABEL
+        };
+        self.submit_latency_seconds
+            .with_label_values(&[upstream_label.as_str(), status_label])
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn observe_job_translation_latency(&self, duration: Duration) {
+        self.job_translation_latency_seconds
+            .observe(duration.as_secs_f64());
+    }
+
+    pub fn account_invalid_upstream_message(&self, error: &error::InvalidUpstreamMessage) {
+        let reason_label = match error {
+            error::InvalidUpstreamMessage::Difficulty(_) => "difficulty",
+            error::InvalidUpstreamMessage::PrevHashLength(_) => "prev_hash",
+            error::InvalidUpstreamMessage::NotifyStorm { .. } => "notify_storm",
+        };
+        self.upstream_invalid_messages_total
+            .with_label_values(&[reason_label])
+            .inc();
+    }
+
+    pub fn account_upstream_rejected_share(&self, code: Option<RejectCode>) {
+        let reason_label = code.map_or("other", |code| code.as_str());
+        self.upstream_rejected_shares_total
+            .with_label_values(&[reason_label])
+            .inc();
+    }
+
+    pub fn observe_device_status(&self, status: &SubmitDeviceStatus) {
+        let device = status.dev_id.as_str();
+        {
+            let mut monitored_devices = self.monitored_devices.lock().expect("BUG: poisoned lock");
+            if !monitored_devices.contains(device) {
+                if monitored_devices.len() >= Self::MAX_MONITORED_DEVICES {
+                    return;
+                }
+                monitored_devices.insert(device.to_string());
+            }
+        }
+        for reading in status.temperatures.iter() {
+            self.device_temperature_celsius
+                .with_label_values(&[device, reading.sensor_id.to_string().as_str()])
+                .set(reading.celsius.into());
+        }
+        for reading in status.fans.iter() {
+            self.device_fan_speed_rpm
+                .with_label_values(&[device, reading.fan_id.to_string().as_str()])
+                .set(reading.rpm.into());
+        }
+        for reading in status.chains.iter() {
+            self.device_chain_hashrate
+                .with_label_values(&[device, reading.chain_id.to_string().as_str()])
+                .set(reading.hashrate.into());
+        }
+    }
+
     pub fn tcp_connection_timer_observe(&self, timer: Instant) {
         self.tcp_connection_duration_seconds
             .observe(timer.elapsed().as_secs_f64());
@@ -221,6 +446,41 @@ impl ProxyMetrics {
         self.tcp_connection_close_stage.inc_by_error(error)
     }
 
+    /// Accounts the close reason and traffic of a finished session
+    pub fn observe_session_summary(&self, summary: &SessionSummary) {
+        match &summary.close_reason {
+            CloseReason::Downstream => self.tcp_connection_close_ok(),
+            CloseReason::Cancelled => {
+                self.tcp_connection_close_with_error(&error::Error::Cancelled)
+            }
+            CloseReason::Error(e) => self.tcp_connection_close_with_error(e),
+        }
+        self.session_traffic_bytes_total
+            .with_label_values(&["in"])
+            .inc_by(summary.bytes_in);
+        self.session_traffic_bytes_total
+            .with_label_values(&["out"])
+            .inc_by(summary.bytes_out);
+    }
+
+    pub fn account_upstream_session_started(&self, upstream_name: &str) {
+        self.upstream_sessions
+            .with_label_values(&[upstream_name])
+            .inc();
+    }
+
+    pub fn account_upstream_session_finished(&self, upstream_name: &str) {
+        self.upstream_sessions
+            .with_label_values(&[upstream_name])
+            .dec();
+    }
+
+    pub fn set_upstream_draining(&self, upstream_name: &str, draining: bool) {
+        self.upstream_draining
+            .with_label_values(&[upstream_name])
+            .set(draining as i64);
+    }
+
     /// Helper for debugging TCP listener issues where it starts spinning for unknown reason
     /// emitting errors. It tracks how many TCP connections have been successfully accepted until
     /// TCP listener needs to be restarted due to the failure
@@ -248,14 +508,23 @@ impl ProxyMetrics {
     where
         T: std::future::Future + Send + 'static,
         T::Output: Send + 'static,
+    {
+        tokio::spawn(self.accounted(future))
+    }
+
+    /// Account `future` as a running task, for futures that are spawned by other means than
+    /// [`accounted_spawn`]
+    pub fn accounted<T>(self: &Arc<Self>, future: T) -> impl std::future::Future<Output = T::Output>
+    where
+        T: std::future::Future,
     {
         let self_clone = self.clone();
-        tokio::spawn(async move {
+        async move {
             self_clone.tokio_tasks.inc();
             let output = future.await;
             self_clone.tokio_tasks.dec();
             output
-        })
+        }
     }
 }
 
@@ -275,7 +544,10 @@ impl ErrorLabeling for error::DownstreamError {
 
 impl ErrorLabeling for error::UpstreamError {
     fn label(&self) -> &str {
-        "upstream"
+        match self {
+            Self::InvalidMessage(_) => "upstream_invalid_message",
+            _ => "upstream",
+        }
     }
 }
 
@@ -292,14 +564,22 @@ impl ErrorLabeling for error::V2ProtocolError {
 impl ErrorLabeling for error::Error {
     fn label(&self) -> &str {
         use ii_stratum::error::Error as StratumError;
+        use ii_stratum::v2::error::Error as V2Error;
         match self {
             Self::HostNameError(_) => "dns",
             Self::GeneralWithMetricsLabel(_, label) => label,
             Self::Stratum(s) => match s {
                 StratumError::Noise(_)
+                | StratumError::HandshakeTimeout(_)
+                | StratumError::HandshakeFailed(..)
                 | StratumError::NoiseEncoding(_)
                 | StratumError::NoiseProtocol(_)
-                | StratumError::NoiseSignature(_) => "noise",
+                | StratumError::NoiseSignature(_)
+                | StratumError::V2(
+                    V2Error::UntrustedCertificate(_)
+                    | V2Error::CertificateNotYetValid(..)
+                    | V2Error::CertificateExpired(..),
+                ) => "noise",
                 StratumError::V2(_) => "downstream",
                 StratumError::V1(_) => "upstream",
                 _ => "stratum_other",
@@ -311,12 +591,17 @@ impl ErrorLabeling for error::Error {
             Self::Protocol(e) => e.label(),
             Self::General(_) => "general",
             Self::Timeout(_) => "timeout",
-            Self::ClientAttempt(_) => "client_attempt",
+            Self::ClientAttempt(_) | Self::UpstreamConnect(_) => "client_attempt",
             Self::BitcoinHashes(_) => "bitcoin_hashes",
             Self::InvalidFile(_) => "invalid_file",
+            Self::Builder(_) => "builder",
             Self::Metrics(_) => "metrics",
             Self::Io(_) => "io",
+            Self::UpstreamClosed { .. } => "upstream",
+            Self::DownstreamClosed { .. } => "downstream",
             Self::Noise(_) => "expired_cert",
+            Self::Cancelled => "cancelled",
+            Self::Context { source, .. } => source.label(),
         }
     }
 }
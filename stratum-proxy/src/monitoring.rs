@@ -0,0 +1,135 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Collection of device status reports that miners send via the telemetry extension. Reports are
+//! exported as metrics and events and handed over to user provided hooks.
+
+use async_trait::async_trait;
+use std::convert::TryFrom;
+use std::sync::Arc;
+
+use ii_logging::macros::*;
+use ii_stratum::v2::{
+    self,
+    extensions::{ExtensionHandler, ExtensionRegistry},
+    telemetry::messages::SubmitDeviceStatus,
+};
+use ii_unvariant::Id;
+
+use crate::events::{Event, EventExporter};
+use crate::metrics::ProxyMetrics;
+
+/// Callback invoked for every device status report received by the proxy
+pub type DeviceStatusHook = Arc<dyn Fn(&SubmitDeviceStatus) + Send + Sync>;
+
+#[derive(Clone, Default)]
+pub struct DeviceMonitoringCollector {
+    metrics: Option<Arc<ProxyMetrics>>,
+    hooks: Vec<DeviceStatusHook>,
+}
+
+impl DeviceMonitoringCollector {
+    pub fn new(metrics: Option<Arc<ProxyMetrics>>) -> Self {
+        Self {
+            metrics,
+            hooks: vec![],
+        }
+    }
+
+    pub fn with_hook(mut self, hook: DeviceStatusHook) -> Self {
+        self.hooks.push(hook);
+        self
+    }
+
+    /// Exports every device status report as `Event::DeviceStatus`
+    pub fn with_event_exporter(self, event_exporter: EventExporter) -> Self {
+        self.with_hook(Arc::new(move |status| {
+            event_exporter.export(Event::from(status))
+        }))
+    }
+
+    /// Registers the collector as handler of the telemetry extension in `registry`. Telemetry
+    /// channels are not supported by the proxy, their messages are ignored.
+    pub fn register(self, registry: &mut ExtensionRegistry) {
+        registry.register(v2::extensions::TELEMETRY, Arc::new(self));
+    }
+}
+
+#[async_trait]
+impl ExtensionHandler for DeviceMonitoringCollector {
+    async fn handle_extension_frame(
+        &self,
+        frame: v2::framing::Frame,
+    ) -> ii_stratum::error::Result<()> {
+        // Telemetry channels, acknowledgements and any messages from newer versions of the
+        // extension are not interesting for the proxy
+        if frame.header.msg_type != <SubmitDeviceStatus as Id<v2::framing::MsgType>>::ID {
+            debug!("Ignoring telemetry extension frame: {:x?}", frame);
+            return Ok(());
+        }
+        let status = SubmitDeviceStatus::try_from(frame)?;
+        trace!("Device status: {:?}", status);
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.observe_device_status(&status);
+        }
+        for hook in self.hooks.iter() {
+            hook(&status);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ii_stratum::test_utils;
+    use std::convert::TryInto;
+    use std::sync::Mutex;
+
+    #[tokio::test]
+    async fn test_collect_device_status() {
+        let reports = Arc::new(Mutex::new(Vec::new()));
+        let reports_clone = reports.clone();
+        let mut registry = ExtensionRegistry::new();
+        DeviceMonitoringCollector::new(None)
+            .with_hook(Arc::new(move |status: &SubmitDeviceStatus| {
+                reports_clone
+                    .lock()
+                    .expect("BUG: poisoned lock")
+                    .push(status.clone())
+            }))
+            .register(&mut registry);
+
+        let frame: v2::framing::Frame = test_utils::v2::build_submit_device_status()
+            .try_into()
+            .expect("BUG: Cannot create test frame");
+        registry
+            .handle_frame(frame)
+            .await
+            .expect("BUG: Frame dispatch failed");
+
+        assert_eq!(
+            *reports.lock().expect("BUG: poisoned lock"),
+            vec![test_utils::v2::build_submit_device_status()]
+        );
+    }
+}
@@ -0,0 +1,362 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Dropping of root privileges once the proxy has bound its listening sockets, so that
+//! privileged ports can be used without running the translation as root
+
+use std::ffi::CString;
+use std::io;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::{fs, mem, ptr};
+
+use serde::Deserialize;
+
+use crate::error::{Error, Result};
+
+/// Size of the buffer for strings of a single account database entry
+const ACCOUNT_BUFFER_SIZE: usize = 16 * 1024;
+
+/// Permission bits of a single class (owner, group, others) of the file mode
+const PERMISSION_READ: u32 = 0o4;
+const PERMISSION_WRITE: u32 = 0o2;
+const PERMISSION_SEARCH: u32 = 0o1;
+
+/// Privileges section of the proxy configuration
+#[derive(Clone, Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PrivilegesConfig {
+    /// Unprivileged user that the proxy switches to
+    pub user: String,
+    /// Primary group of `user` is used if not specified
+    pub group: Option<String>,
+    /// Directory that becomes the root directory of the proxy. All files that the proxy opens
+    /// later (e.g. certificates reloaded on SIGHUP or the rotated log file) have to be
+    /// accessible inside of it, see `check_access`.
+    pub chroot: Option<PathBuf>,
+}
+
+/// User and group IDs of an account
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Account {
+    pub uid: libc::uid_t,
+    pub gid: libc::gid_t,
+}
+
+/// How a file is accessed once privileges have been dropped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    /// The file is read, e.g. a certificate reloaded on SIGHUP
+    Read,
+    /// The existing file is written, e.g. a Unix socket that events are sent to
+    Write,
+    /// The file is (re)created in its directory, e.g. a rotated log file
+    Create,
+}
+
+/// File that the proxy opens by its path after privileges have been dropped
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReopenedFile {
+    pub path: PathBuf,
+    pub access: Access,
+}
+
+impl ReopenedFile {
+    pub fn new<P: Into<PathBuf>>(path: P, access: Access) -> Self {
+        Self {
+            path: path.into(),
+            access,
+        }
+    }
+}
+
+impl PrivilegesConfig {
+    /// Looks up the configured user and group in the account databases
+    pub fn resolve(&self) -> Result<Account> {
+        let (uid, primary_gid) = lookup_user(&self.user)?;
+        let gid = match self.group.as_ref() {
+            Some(group) => lookup_group(group)?,
+            None => primary_gid,
+        };
+        Ok(Account { uid, gid })
+    }
+
+    /// Path that `path` refers to once the root directory has changed. The working directory
+    /// becomes the new root directory, therefore, relative paths are resolved against it, too.
+    fn path_after_drop(&self, path: &Path) -> PathBuf {
+        match self.chroot.as_ref() {
+            Some(chroot) => chroot.join(path.strip_prefix("/").unwrap_or(path)),
+            None => path.to_path_buf(),
+        }
+    }
+
+    /// Checks that `account` will be able to access all `files` once privileges have been
+    /// dropped. The proxy would otherwise fail only later, e.g. when the log file is rotated.
+    pub fn check_access(&self, account: &Account, files: &[ReopenedFile]) -> Result<()> {
+        for file in files.iter() {
+            let path = self.path_after_drop(&file.path);
+            check_file_access(account, &path, self.chroot.as_deref(), file.access).map_err(
+                |e| {
+                    Error::General(format!(
+                        "{} won't be accessible as {} after dropping privileges: {}",
+                        file.path.display(),
+                        path.display(),
+                        e
+                    ))
+                },
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Switches the whole process (all threads) to the configured account and root directory.
+    /// Supplementary groups are dropped.
+    pub fn drop_privileges(&self) -> Result<Account> {
+        // Account databases are not available once the root directory changes
+        let account = self.resolve()?;
+        if let Some(chroot) = self.chroot.as_ref() {
+            let path = CString::new(chroot.as_os_str().as_bytes()).map_err(|_| {
+                Error::General(format!("Invalid chroot directory {}", chroot.display()))
+            })?;
+            check(unsafe { libc::chroot(path.as_ptr()) })?;
+            std::env::set_current_dir("/").map_err(Error::Io)?;
+        }
+        check(unsafe { libc::setgroups(1, &account.gid) })?;
+        check(unsafe { libc::setgid(account.gid) })?;
+        check(unsafe { libc::setuid(account.uid) })?;
+        if account.uid != 0 && unsafe { libc::setuid(0) } == 0 {
+            return Err(Error::General(
+                "BUG: root privileges can be regained".to_string(),
+            ));
+        }
+        Ok(account)
+    }
+}
+
+/// Checks `access` to `path` including search permission of all its directories up to the root
+/// directory `chroot`, failure is described by the error message
+fn check_file_access(
+    account: &Account,
+    path: &Path,
+    chroot: Option<&Path>,
+    access: Access,
+) -> std::result::Result<(), String> {
+    let directory = match path.parent() {
+        Some(directory) if !directory.as_os_str().is_empty() => directory,
+        _ => Path::new("."),
+    };
+    for ancestor in directory.ancestors() {
+        let in_root = match chroot {
+            Some(chroot) => ancestor.starts_with(chroot),
+            None => !ancestor.as_os_str().is_empty(),
+        };
+        if !in_root {
+            break;
+        }
+        check_permission(account, ancestor, PERMISSION_SEARCH)?;
+    }
+    match access {
+        Access::Read => check_permission(account, path, PERMISSION_READ),
+        Access::Write => check_permission(account, path, PERMISSION_WRITE),
+        Access::Create => {
+            check_permission(account, directory, PERMISSION_WRITE)?;
+            if path.exists() {
+                check_permission(account, path, PERMISSION_WRITE)?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Evaluates the file mode the same way the kernel does for a process of `account` without
+/// supplementary groups
+fn check_permission(
+    account: &Account,
+    path: &Path,
+    permission: u32,
+) -> std::result::Result<(), String> {
+    let metadata = fs::metadata(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    if account.uid == 0 {
+        return Ok(());
+    }
+    let mode = if metadata.uid() == account.uid {
+        metadata.mode() >> 6
+    } else if metadata.gid() == account.gid {
+        metadata.mode() >> 3
+    } else {
+        metadata.mode()
+    };
+    if mode & permission == permission {
+        Ok(())
+    } else {
+        Err(format!(
+            "{}: permission denied for uid {}, gid {}",
+            path.display(),
+            account.uid,
+            account.gid
+        ))
+    }
+}
+
+fn check(result: libc::c_int) -> Result<()> {
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(Error::Io(io::Error::last_os_error()))
+    }
+}
+
+fn account_name(name: &str) -> Result<CString> {
+    CString::new(name).map_err(|_| Error::General(format!("Invalid account name {:?}", name)))
+}
+
+/// Returns user ID and primary group ID of user `name`
+fn lookup_user(name: &str) -> Result<(libc::uid_t, libc::gid_t)> {
+    let c_name = account_name(name)?;
+    let mut passwd: libc::passwd = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; ACCOUNT_BUFFER_SIZE];
+    let mut entry = ptr::null_mut();
+    let result = unsafe {
+        libc::getpwnam_r(
+            c_name.as_ptr(),
+            &mut passwd,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut entry,
+        )
+    };
+    if result != 0 {
+        return Err(Error::Io(io::Error::from_raw_os_error(result)));
+    }
+    if entry.is_null() {
+        return Err(Error::General(format!("Unknown user {}", name)));
+    }
+    Ok((passwd.pw_uid, passwd.pw_gid))
+}
+
+fn lookup_group(name: &str) -> Result<libc::gid_t> {
+    let c_name = account_name(name)?;
+    let mut group: libc::group = unsafe { mem::zeroed() };
+    let mut buffer = vec![0 as libc::c_char; ACCOUNT_BUFFER_SIZE];
+    let mut entry = ptr::null_mut();
+    let result = unsafe {
+        libc::getgrnam_r(
+            c_name.as_ptr(),
+            &mut group,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut entry,
+        )
+    };
+    if result != 0 {
+        return Err(Error::Io(io::Error::from_raw_os_error(result)));
+    }
+    if entry.is_null() {
+        return Err(Error::General(format!("Unknown group {}", name)));
+    }
+    Ok(group.gr_gid)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_resolve_account() {
+        let config = PrivilegesConfig {
+            user: "root".to_string(),
+            group: None,
+            chroot: None,
+        };
+        assert_eq!(
+            config.resolve().expect("BUG: Cannot resolve root"),
+            Account { uid: 0, gid: 0 }
+        );
+
+        let config = PrivilegesConfig {
+            group: Some("root".to_string()),
+            ..config
+        };
+        assert_eq!(
+            config.resolve().expect("BUG: Cannot resolve root"),
+            Account { uid: 0, gid: 0 }
+        );
+
+        let config = PrivilegesConfig {
+            user: "no-such-user-of-stratum-proxy".to_string(),
+            ..config
+        };
+        assert!(config.resolve().is_err());
+    }
+
+    #[test]
+    fn test_check_access() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let set_mode = |path: &Path, mode: u32| {
+            fs::set_permissions(path, fs::Permissions::from_mode(mode))
+                .expect("BUG: Cannot set permissions");
+        };
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let chroot = dir.path().join("chroot");
+        fs::create_dir(&chroot).expect("BUG: Cannot create directory");
+        fs::write(chroot.join("proxy.toml"), "").expect("BUG: Cannot write file");
+        set_mode(dir.path(), 0o755);
+        set_mode(&chroot, 0o755);
+        set_mode(&chroot.join("proxy.toml"), 0o600);
+
+        let config = PrivilegesConfig {
+            user: "nobody".to_string(),
+            group: None,
+            chroot: Some(chroot.clone()),
+        };
+        // Account that doesn't own any of the files
+        let account = Account {
+            uid: 65534,
+            gid: 65534,
+        };
+        let check =
+            |path: &str, access| config.check_access(&account, &[ReopenedFile::new(path, access)]);
+        assert!(check("/proxy.toml", Access::Read).is_err());
+        set_mode(&chroot.join("proxy.toml"), 0o644);
+        assert!(check("/proxy.toml", Access::Read).is_ok());
+        // Relative paths are resolved in the new root directory
+        assert!(check("proxy.toml", Access::Read).is_ok());
+        assert!(check("/missing.toml", Access::Read).is_err());
+        assert!(check("/proxy.log", Access::Create).is_err());
+        set_mode(&chroot, 0o777);
+        assert!(check("/proxy.log", Access::Create).is_ok());
+        // Directories outside of the new root directory don't matter
+        set_mode(dir.path(), 0o700);
+        assert!(check("/proxy.log", Access::Create).is_ok());
+        set_mode(&chroot, 0o770);
+        assert!(check("/proxy.log", Access::Create).is_err());
+        // Root is not limited by permissions
+        assert!(config
+            .check_access(
+                &Account { uid: 0, gid: 0 },
+                &[ReopenedFile::new("/proxy.log", Access::Create)]
+            )
+            .is_ok());
+    }
+}
@@ -20,289 +20,690 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+mod builder;
 pub mod controller;
+pub mod cpu_time;
+pub mod listener;
+pub mod pass_through;
 mod peer_address;
+pub mod pump;
+mod summary;
+pub mod upstream;
 
-use std::convert::TryFrom;
-use std::net::{SocketAddr, ToSocketAddrs};
+use std::net::SocketAddr;
 use std::pin::Pin;
 use std::sync::Arc;
 use std::time;
 
-use futures::channel::mpsc;
 use futures::prelude::*;
 use futures::select;
 use serde::Deserialize;
-use tokio::net::{TcpListener, TcpStream};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
 use tokio::time::{Duration, Instant};
 
-use ii_async_utils::{FutureExt, Spawnable, Tripwire};
+use ii_async_utils::{
+    retry_with_backoff, CancellationToken, FailureCause, FutureExt, RetryPolicy, Spawnable,
+    Supervisor, TaskFailure, TimeoutOrCancelError, Tripwire, WriteCoalescing,
+};
 use ii_logging::macros::*;
 use ii_noise_proxy::SecurityContext;
+use ii_stratum::error::{Direction, ErrorContext};
 use ii_stratum::v1;
 use ii_stratum::v2;
+use ii_stratum::v2::noise::HandshakeLimiter;
+use ii_unvariant::Id;
 use ii_wire::{
     proxy::{self, Connector, WithProxyInfo},
-    Address, Client, Connection,
+    Address,
 };
 
+use crate::accounting::ShareAccounting;
+use crate::auth::Authenticator;
 use crate::error::{DownstreamError, Error, Result, UpstreamError};
+use crate::events::{Event, EventExporter};
 use crate::metrics::ProxyMetrics;
-use crate::translation::V2ToV1Translation;
+use crate::translation::{
+    adapter::{SharedTranslation, TranslationAdapter, TranslationPorts, TranslationSink},
+    session::V1SessionStore,
+    V2ToV1Translation, V2ToV1TranslationOptions,
+};
 
+pub use builder::ProxyServerBuilder;
+pub use listener::{IncomingConnection, Listener, TcpSocketListener};
+pub use pass_through::ExtensionPassThrough;
 pub use peer_address::DownstreamPeer;
+pub use pump::{FramePump, JobLatency};
+pub use summary::{CloseReason, DeviceFingerprint, SessionStats, SessionSummary};
+pub use upstream::{
+    TcpUpstream, Upstream, UpstreamConnection, UpstreamCredentials, UpstreamRouting, UpstreamSet,
+};
+
+/// Upstream connection of a session along with the address of the upstream peer and credentials
+/// that the session uses, see `UpstreamConnection`
+pub type SessionUpstreamConnection<U> = (v1::Framed<U>, SocketAddr, Option<UpstreamCredentials>);
+
+/// Opens the upstream connection of a session for a routing key (the worker name)
+pub type UpstreamRouter<U> = Box<
+    dyn FnOnce(String) -> Pin<Box<dyn Future<Output = Result<SessionUpstreamConnection<U>>> + Send>>
+        + Send,
+>;
+
+/// Upstream side of a session that is passed to `ConnectionHandler`
+// There is a single instance per session, boxing the connection wouldn't save anything
+#[allow(clippy::large_enum_variant)]
+pub enum SessionUpstream<U> {
+    /// Connection (along with address of the upstream peer and credentials) established before
+    /// the session has started
+    Connected(v1::Framed<U>, SocketAddr, Option<UpstreamCredentials>),
+    /// The session connects by itself once the worker name is known, see
+    /// `Upstream::routes_by_key()`
+    Routed(UpstreamRouter<U>),
+}
 
 /// Represents a single protocol translation session (one V2 client talking to one V1 server)
-pub struct ConnTranslation {
-    /// Actual protocol translator
-    translation: V2ToV1Translation,
-    /// Upstream connection
-    v1_conn: v1::Framed,
-    /// Address of the v1 upstream peer
-    v1_peer_addr: SocketAddr,
-    // TODO to be removed as the translator may send out items directly via a particular connection
-    // (when treated as a sink)
-    /// Frames from the translator to be sent out via V1 connection
-    v1_translation_rx: mpsc::Receiver<v1::Frame>,
+pub struct ConnTranslation<S = TcpStream, U = TcpStream> {
+    // TODO the translator still queues the translated frames via mpsc channels that back the
+    //  streams of the adapter, they are to be removed once it can send the frames directly (see
+    //  `TranslationStream`)
+    /// Actual protocol translator, it is split into sinks and streams once the session runs
+    translation: TranslationAdapter,
+    /// Upstream connection (or the means to open it)
+    v1_upstream: SessionUpstream<U>,
     /// Downstream connection
-    v2_conn: v2::Framed,
+    v2_conn: v2::Framed<S>,
     /// Address of the v2 peer that has connected
     v2_peer_addr: DownstreamPeer,
-    /// Frames from the translator to be sent out via V2 connection
-    v2_translation_rx: mpsc::Receiver<v2::Frame>,
     metrics: Option<Arc<ProxyMetrics>>,
+    /// Latency of jobs from upstream until written downstream (only if metrics are enabled)
+    job_latency: Option<JobLatency>,
+    /// Terminates the session and its send tasks
+    cancel: CancellationToken,
+    /// The session is terminated when either side stays silent for longer than its timeout
+    timeouts: SessionTimeouts,
+    /// Batching of frames sent to either side
+    write_coalescing: WriteCoalescing,
+    /// Commands sent to the session, e.g. via the control socket
+    commands: Option<controller::SessionCommandReceiver>,
+    /// CPU time of the send tasks is accounted to the session (only if CPU accounting is enabled)
+    cpu_time: Option<cpu_time::CpuTimeCounter>,
 }
 
-impl ConnTranslation {
-    const MAX_TRANSLATION_CHANNEL_SIZE: usize = 10;
-    const V1_UPSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
-    const V2_DOWNSTREAM_TIMEOUT: time::Duration = time::Duration::from_secs(60);
+impl<S, U> ConnTranslation<S, U>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug + 'static,
+    U: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug + 'static,
+{
+    /// Period of checking request timeouts and idle channels of a running session
+    const TIMEOUT_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+    /// Recognizes frames whose write completes the job latency
+    fn is_new_mining_job(frame: &v2::Frame) -> bool {
+        frame.header.extension_type == 0
+            && frame.header.msg_type == <v2::messages::NewMiningJob as Id<v2::framing::MsgType>>::ID
+    }
 
     fn new(
-        v2_conn: v2::Framed,
+        v2_conn: v2::Framed<S>,
         v2_peer_addr: DownstreamPeer,
-        v1_conn: v1::Framed,
-        v1_peer_addr: SocketAddr,
+        v1_upstream: SessionUpstream<U>,
+        options: V2ToV1TranslationOptions,
+        extensions: v2::extensions::ExtensionRegistry,
         metrics: Option<Arc<ProxyMetrics>>,
     ) -> Self {
-        let (v1_translation_tx, v1_translation_rx) =
-            mpsc::channel(Self::MAX_TRANSLATION_CHANNEL_SIZE);
-        let (v2_translation_tx, v2_translation_rx) =
-            mpsc::channel(Self::MAX_TRANSLATION_CHANNEL_SIZE);
-        let translation = V2ToV1Translation::new(
-            v1_translation_tx,
-            v2_translation_tx,
-            Default::default(),
-            metrics.clone(),
-            v2_peer_addr.proxy_info,
-        );
+        let mut translation =
+            TranslationAdapter::new(options, metrics.clone(), v2_peer_addr.proxy_info)
+                .with_extensions(extensions);
+        match &v1_upstream {
+            SessionUpstream::Connected(_, v1_peer_addr, credentials) => {
+                let translation = translation.translation_mut();
+                translation.set_v1_upstream_addr(*v1_peer_addr);
+                translation.set_v1_credentials(credentials.clone());
+            }
+            // Nothing can be sent upstream before the worker name is known
+            SessionUpstream::Routed(_) => translation.translation_mut().defer_v1_configure(),
+        }
+        let job_latency = metrics.clone().map(JobLatency::new);
+        if let Some(job_latency) = job_latency.as_ref() {
+            translation
+                .translation_mut()
+                .set_job_latency(job_latency.clone());
+        }
 
         Self {
             translation,
-            v1_conn,
-            v1_peer_addr,
-            v1_translation_rx,
+            v1_upstream,
             v2_conn,
             v2_peer_addr,
-            v2_translation_rx,
             metrics,
+            job_latency,
+            cancel: CancellationToken::new(),
+            timeouts: Default::default(),
+            write_coalescing: WriteCoalescing::disabled(),
+            commands: None,
+            cpu_time: None,
         }
     }
 
-    async fn v1_handle_frame(
-        translation: &mut V2ToV1Translation,
-        frame: v1::framing::Frame,
-    ) -> Result<()> {
-        let deserialized = v1::rpc::Rpc::try_from(frame)?;
-        translation.handle_v1(deserialized).await
+    fn with_timeouts(mut self, timeouts: SessionTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
     }
 
-    //    async fn handle_frame(&mut self, frame: v2::framing::Frame) -> Result<()> {
-    async fn v2_handle_frame(
-        translation: &mut V2ToV1Translation,
-        frame: v2::framing::Frame,
-    ) -> Result<()> {
-        match frame.header.extension_type {
-            v2::extensions::BASE => {
-                translation.handle_v2(frame).await?;
-            }
-            // Report any other extension down the line
-            _ => {
-                warn!("Unsupported extension frame: {:x?} ", frame);
+    fn with_write_coalescing(mut self, write_coalescing: WriteCoalescing) -> Self {
+        self.write_coalescing = write_coalescing;
+        self
+    }
+
+    /// Terminate the session once `cancel` is cancelled
+    fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = cancel;
+        self
+    }
+
+    fn with_commands(mut self, commands: controller::SessionCommandReceiver) -> Self {
+        self.commands = Some(commands);
+        self
+    }
+
+    /// Collect statistics of the session into `stats`, the CPU time of the send tasks is
+    /// accounted to its counter (if any)
+    fn with_session_stats(mut self, stats: SessionStats) -> Self {
+        self.cpu_time = stats.cpu_time();
+        self.translation.translation_mut().set_session_stats(stats);
+        self
+    }
+
+    /// Account shares of the session per worker into `share_accounting`
+    fn with_share_accounting(mut self, share_accounting: Option<ShareAccounting>) -> Self {
+        if let Some(share_accounting) = share_accounting {
+            self.translation
+                .translation_mut()
+                .set_share_accounting(share_accounting);
+        }
+        self
+    }
+
+    /// Authorize channels of the session by `authenticator`
+    fn with_authenticator(mut self, authenticator: Option<Arc<dyn Authenticator>>) -> Self {
+        if let Some(authenticator) = authenticator {
+            self.translation
+                .translation_mut()
+                .set_authenticator(authenticator, self.v2_peer_addr.source_addr());
+        }
+        self
+    }
+
+    /// Export share events of the session via `event_exporter`
+    fn with_event_exporter(mut self, event_exporter: Option<EventExporter>) -> Self {
+        if let Some(event_exporter) = event_exporter {
+            self.translation
+                .translation_mut()
+                .set_event_exporter(event_exporter, self.v2_peer_addr.source_addr());
+        }
+        self
+    }
+
+    /// Resume upstream sessions with session IDs from `v1_sessions`
+    fn with_v1_session_store(mut self, v1_sessions: Option<V1SessionStore>) -> Self {
+        if let Some(v1_sessions) = v1_sessions {
+            self.translation
+                .translation_mut()
+                .set_v1_session_store(v1_sessions);
+        }
+        self
+    }
+
+    /// Handles the outcome of receiving from the upstream connection within a timeout.
+    /// Returns false when the upstream server has closed the connection.
+    async fn v1_receive(
+        v1_sink: &mut TranslationSink<v1::Frame>,
+        v1_frame: std::result::Result<
+            Option<std::result::Result<v1::Frame, ii_stratum::error::Error>>,
+            TimeoutOrCancelError,
+        >,
+    ) -> Result<bool> {
+        // Unwrap the potentially elapsed timeout
+        let v1_frame = v1_frame.map_err(|e| match e {
+            TimeoutOrCancelError::Elapsed(e) => UpstreamError::Timeout(e).into(),
+            TimeoutOrCancelError::Cancelled => Error::Cancelled,
+        })?;
+        match v1_frame {
+            Some(v1_frame) => {
+                v1_sink
+                    .send(v1_frame.map_err(UpstreamError::Stratum)?)
+                    .await?;
+                Ok(true)
             }
+            None => Ok(false),
         }
-        Ok(())
     }
 
-    /// Attempt to send a frame via a specified connection. Attempt to send 'None' results in an
-    /// error. The intention is to have a single place for sending out frames and handling
-    /// errors/timeouts.
-    pub async fn v2_try_send_frame<S>(
-        connection: &mut S,
-        frame: Option<v2::framing::Frame>,
-        peer_addr: &DownstreamPeer,
-    ) -> Result<()>
-    where
-        S: v2::FramedSink,
-    {
-        let status = match frame {
-            Some(v2_translated_frame) => {
-                trace!("TX:Stratum V2: {}<-{:x?}", peer_addr, v2_translated_frame);
-                connection.send(v2_translated_frame).await
+    /// Handles the outcome of receiving from the downstream connection within a timeout.
+    /// Returns false when the downstream peer has closed the connection.
+    async fn v2_receive(
+        v2_sink: &mut TranslationSink<v2::Frame>,
+        v2_frame: std::result::Result<
+            Option<std::result::Result<v2::Frame, ii_stratum::error::Error>>,
+            TimeoutOrCancelError,
+        >,
+    ) -> Result<bool> {
+        let v2_frame = v2_frame.map_err(|e| match e {
+            TimeoutOrCancelError::Elapsed(e) => DownstreamError::Timeout(e).into(),
+            TimeoutOrCancelError::Cancelled => Error::Cancelled,
+        })?;
+        match v2_frame {
+            Some(v2_frame) => {
+                v2_sink
+                    .send(v2_frame.map_err(DownstreamError::Stratum)?)
+                    .await?;
+                Ok(true)
             }
-            None => return Err(Error::General("No more V2 frames to send".into())),
-        };
-        status.map_err(|e| {
-            debug!("Send error: {} for (peer: {})", e, peer_addr);
-            e.into()
-        })
+            None => Ok(false),
+        }
     }
 
-    pub async fn v1_send_task<S>(
-        mut conn_sender: S,
-        mut translation_receiver: mpsc::Receiver<v1::Frame>,
-        peer_addr: DownstreamPeer,
-    ) where
-        S: v1::FramedSink,
-    {
-        while let Some(frame) = translation_receiver.next().await {
-            trace!("TX:Stratum V1: {} Upstream<-{:?}", peer_addr, frame);
-            if let Err(err) = conn_sender.send(frame).await {
-                warn!("V1 connection failed: {}", err);
-                break;
+    /// Waits for the next session command, there are no more commands once the sender is
+    /// dropped
+    async fn next_command(
+        commands: &mut Option<controller::SessionCommandReceiver>,
+    ) -> Option<controller::SessionCommand> {
+        match commands {
+            Some(commands) => commands.recv().await,
+            None => future::pending().await,
+        }
+    }
+
+    fn execute_command(
+        translation: &mut V2ToV1Translation,
+        command: controller::SessionCommand,
+    ) -> Result<()> {
+        match command {
+            controller::SessionCommand::Reconnect(reconnect) => {
+                translation.reconnect_downstream(reconnect)
             }
         }
     }
 
-    /// Send all V2 frames via the specified V2 connection
-    /// TODO consolidate this method into V2Handler, turn the parameters into fields and
-    /// implement ConnTranslation::split()
-    pub async fn v2_send_task<S>(
-        mut conn_sender: S,
-        mut translation_receiver: mpsc::Receiver<v2::Frame>,
-        peer_addr: DownstreamPeer,
-    ) -> Result<()>
-    where
-        S: v2::FramedSink,
-    {
+    fn send_task_error(failure: TaskFailure<Error>) -> Error {
+        match failure.cause {
+            FailureCause::Error(e) => e,
+            FailureCause::Panic(_) => Error::General(failure.to_string()),
+        }
+    }
+
+    /// Serves the downstream peer until the translation knows the worker name of the channel
+    /// that is being opened
+    async fn receive_routing_key(
+        translation: &SharedTranslation,
+        v2_sink: &mut TranslationSink<v2::Frame>,
+        v2_conn_rx: &mut (impl Stream<Item = std::result::Result<v2::Frame, ii_stratum::error::Error>>
+                  + Unpin),
+        send_tasks: &mut Supervisor<Error>,
+        timeout: time::Duration,
+        cancel: &CancellationToken,
+        v2_peer_addr: DownstreamPeer,
+    ) -> Result<String> {
+        let downstream_context = ErrorContext::default()
+            .with_peer(v2_peer_addr.direct_peer)
+            .with_direction(Direction::Downstream);
         loop {
-            let frame = translation_receiver.next().await;
-            Self::v2_try_send_frame(&mut conn_sender, frame, &peer_addr).await?;
+            if let Some(routing_key) = translation.lock().await.v1_routing_key() {
+                return Ok(routing_key);
+            }
+            select! {
+                v2_frame = v2_conn_rx.next().timeout_or_cancel(timeout, cancel).fuse() => {
+                    let connected = Self::v2_receive(v2_sink, v2_frame)
+                        .await
+                        .map_err(|e| e.with_context(downstream_context.clone()))?;
+                    if !connected {
+                        return Err(Error::DownstreamClosed {
+                            peer: v2_peer_addr.direct_peer,
+                        });
+                    }
+                },
+                failure = send_tasks.next_failure().fuse() => {
+                    return Err(Self::send_task_error(failure));
+                }
+            }
         }
     }
 
     async fn run(self) -> Result<()> {
-        let mut translation = self.translation;
+        let TranslationPorts {
+            translation,
+            mut v2_sink,
+            v1_stream,
+            mut v1_sink,
+            v2_stream,
+        } = self.translation.split();
+        let mut commands = self.commands;
 
-        // TODO make connections 'optional' so that we can remove them from the instance and use
-        //  the rest of the instance in as 'borrowed mutable reference'.
-        let (v1_conn_tx, mut v1_conn_rx) = self.v1_conn.split();
         let (v2_conn_tx, mut v2_conn_rx) = self.v2_conn.split();
+        let downstream_context = ErrorContext::default()
+            .with_peer(self.v2_peer_addr.direct_peer)
+            .with_direction(Direction::Downstream);
 
-        if let Some(metrics) = self.metrics.as_ref() {
-            metrics.accounted_spawn(Self::v1_send_task(
-                v1_conn_tx,
-                self.v1_translation_rx,
-                self.v2_peer_addr,
-            ));
-            metrics.accounted_spawn(Self::v2_send_task(
-                v2_conn_tx,
-                self.v2_translation_rx,
-                self.v2_peer_addr,
-            ));
-        } else {
-            tokio::spawn(Self::v1_send_task(
-                v1_conn_tx,
-                self.v1_translation_rx,
-                self.v2_peer_addr,
-            ));
-            tokio::spawn(Self::v2_send_task(
-                v2_conn_tx,
-                self.v2_translation_rx,
-                self.v2_peer_addr,
-            ));
-        }
+        // Failure of either send task terminates the session. The tasks are not aborted when the
+        // session ends so that they can flush frames that are still queued.
+        let mut send_tasks = Supervisor::new();
+        let v2_pump = FramePump::new(
+            v2_stream,
+            v2_conn_tx,
+            downstream_context.clone(),
+            self.v2_peer_addr,
+            self.cancel.clone(),
+        )
+        .with_write_coalescing(self.write_coalescing)
+        .with_write_timeout(Some(self.timeouts.write))
+        .with_metrics(self.metrics.clone())
+        .with_job_latency(self.job_latency.clone(), Self::is_new_mining_job);
+        send_tasks.spawn_once(
+            "V2 send",
+            cpu_time::instrument(self.cpu_time.as_ref(), v2_pump.run()),
+        );
 
-        // TODO: add cancel handler into the select statement
+        let (v1_conn, v1_peer_addr) = match self.v1_upstream {
+            SessionUpstream::Connected(v1_conn, v1_peer_addr, _) => (v1_conn, v1_peer_addr),
+            SessionUpstream::Routed(router) => {
+                let routing_key = Self::receive_routing_key(
+                    &translation,
+                    &mut v2_sink,
+                    &mut v2_conn_rx,
+                    &mut send_tasks,
+                    self.timeouts.downstream,
+                    &self.cancel,
+                    self.v2_peer_addr,
+                )
+                .await?;
+                let (v1_conn, v1_peer_addr, credentials) = router(routing_key).await?;
+                let mut translation = translation.lock().await;
+                translation.set_v1_upstream_addr(v1_peer_addr);
+                translation.set_v1_credentials(credentials);
+                translation.resume_v1_configure()?;
+                (v1_conn, v1_peer_addr)
+            }
+        };
+        // TODO make connections 'optional' so that we can remove them from the instance and use
+        //  the rest of the instance in as 'borrowed mutable reference'.
+        let (v1_conn_tx, mut v1_conn_rx) = v1_conn.split();
+        let upstream_context = ErrorContext::default()
+            .with_peer(v1_peer_addr)
+            .with_direction(Direction::Upstream);
+        let v1_pump = FramePump::new(
+            v1_stream,
+            v1_conn_tx,
+            upstream_context.clone(),
+            self.v2_peer_addr,
+            self.cancel.clone(),
+        )
+        .with_write_coalescing(self.write_coalescing)
+        .with_write_timeout(Some(self.timeouts.write))
+        .with_metrics(self.metrics.clone());
+        send_tasks.spawn_once(
+            "V1 send",
+            cpu_time::instrument(self.cpu_time.as_ref(), v1_pump.run()),
+        );
+
+        // Request timeouts may expire while neither side sends anything
+        let mut timeout_checks = tokio::time::interval(Self::TIMEOUT_CHECK_INTERVAL);
         loop {
             select! {
                 // Receive V1 frame and translate it to V2 message
-                v1_frame = v1_conn_rx.next().timeout(Self::V1_UPSTREAM_TIMEOUT).fuse()=> {
-                    // Unwrap the potentially elapsed timeout
-                    match v1_frame.map_err(UpstreamError::Timeout)? {
-                        Some(v1_frame) => {
-                            Self::v1_handle_frame(
-                                &mut translation,
-                                v1_frame.map_err(UpstreamError::Stratum)?,
-                            )
-                            .await?;
-                        }
-                        None => {
-                            return Err(format!(
-                                "Upstream V1 stratum connection dropped ({:?})",
-                                self.v1_peer_addr
-                            ).into());
-                        }
+                v1_frame = v1_conn_rx
+                    .next()
+                    .timeout_or_cancel(self.timeouts.upstream, &self.cancel)
+                    .fuse() => {
+                    let connected = Self::v1_receive(&mut v1_sink, v1_frame)
+                        .await
+                        .map_err(|e| e.with_context(upstream_context.clone()))?;
+                    if !connected {
+                        return Err(Error::UpstreamClosed {
+                            peer: v1_peer_addr,
+                        });
                     }
                 },
                 // Receive V2 frame and translate it to V1 message
-                v2_frame = v2_conn_rx.next().timeout(Self::V2_DOWNSTREAM_TIMEOUT).fuse() => {
-                    match v2_frame.map_err(DownstreamError::Timeout)? {
-                        Some(v2_frame) => {
-                            Self::v2_handle_frame(
-                                &mut translation,
-                                v2_frame.map_err(DownstreamError::Stratum)?,
-                            )
-                            .await?;
-                        }
-                        None => {
-                            return Ok(());
+                v2_frame = v2_conn_rx
+                    .next()
+                    .timeout_or_cancel(self.timeouts.downstream, &self.cancel)
+                    .fuse() => {
+                    let connected = Self::v2_receive(&mut v2_sink, v2_frame)
+                        .await
+                        .map_err(|e| e.with_context(downstream_context.clone()))?;
+                    if !connected {
+                        return Err(Error::DownstreamClosed {
+                            peer: self.v2_peer_addr.direct_peer,
+                        });
+                    }
+                },
+                command = Self::next_command(&mut commands).fuse() => {
+                    match command {
+                        Some(command) => {
+                            let mut locked_translation = translation.lock().await;
+                            Self::execute_command(&mut locked_translation, command)
+                                .map_err(|e| e.with_context(downstream_context.clone()))?
                         }
+                        None => commands = None,
                     }
+                },
+                failure = send_tasks.next_failure().fuse() => {
+                    return Err(Self::send_task_error(failure));
                 }
+                _ = timeout_checks.tick().fuse() => {}
             }
+            // Checked after each processed frame and periodically
+            let mut locked_translation = translation.lock().await;
+            locked_translation.check_idle_channel()?;
+            locked_translation.check_v1_request_timeouts()?;
         }
     }
 }
 
-pub trait ConnectionHandler: Clone + Send + Sync + 'static {
+/// Inactivity and write timeouts of a translation session
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct SessionTimeouts {
+    /// Maximum time between two frames from the upstream server
+    pub upstream: time::Duration,
+    /// Maximum time between two frames from the downstream peer
+    pub downstream: time::Duration,
+    /// Maximum time for writing a batch of frames to either connection, a peer that doesn't
+    /// read its connection would otherwise hold the session forever
+    pub write: time::Duration,
+}
+
+impl Default for SessionTimeouts {
+    fn default() -> Self {
+        Self {
+            upstream: time::Duration::from_secs(60),
+            downstream: time::Duration::from_secs(60),
+            write: time::Duration::from_secs(10),
+        }
+    }
+}
+
+/// Handles a single downstream session, `S` is the type of the downstream stream and `U` the
+/// type of the upstream stream. The handler resolves to a summary of the session once the
+/// session ends. The handler accounts the session into `stats` that are shared with the
+/// session registry.
+pub trait ConnectionHandler<S = TcpStream, U = TcpStream>: Clone + Send + Sync + 'static {
     fn handle_connection(
         &mut self,
-        v2_conn: v2::Framed,
+        v2_conn: v2::Framed<S>,
         v2_peer: DownstreamPeer,
-        v1_conn: v1::Framed,
-        v1_peer_addr: SocketAddr,
-    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+        v1_upstream: SessionUpstream<U>,
+        cancel: CancellationToken,
+        commands: controller::SessionCommandReceiver,
+        stats: SessionStats,
+    ) -> Pin<Box<dyn Future<Output = SessionSummary> + Send>>;
+
+    /// Sessions open the upstream connection by themselves (see `SessionUpstream::Routed`)
+    /// instead of receiving an established one, e.g. because the session has to be authorized
+    /// first
+    fn defers_upstream_connection(&self) -> bool {
+        false
+    }
 }
 
 #[derive(Clone, Default)]
 pub struct TranslationHandler {
     metrics: Option<Arc<ProxyMetrics>>,
+    options: V2ToV1TranslationOptions,
+    extensions: v2::extensions::ExtensionRegistry,
+    extension_pass_through: Option<ExtensionPassThrough>,
+    v1_sessions: Option<V1SessionStore>,
+    share_accounting: Option<ShareAccounting>,
+    authenticator: Option<Arc<dyn Authenticator>>,
+    event_exporter: Option<EventExporter>,
+    timeouts: SessionTimeouts,
+    write_coalescing: WriteCoalescing,
 }
 
 impl TranslationHandler {
     pub fn new(metrics: Option<Arc<ProxyMetrics>>) -> Self {
-        Self { metrics }
+        Self {
+            metrics,
+            options: Default::default(),
+            extensions: Default::default(),
+            extension_pass_through: None,
+            v1_sessions: None,
+            share_accounting: None,
+            authenticator: None,
+            event_exporter: None,
+            timeouts: Default::default(),
+            write_coalescing: WriteCoalescing::disabled(),
+        }
+    }
+
+    /// Inactivity and write timeouts used for every translation session started by this handler
+    pub fn with_timeouts(mut self, timeouts: SessionTimeouts) -> Self {
+        self.timeouts = timeouts;
+        self
+    }
+
+    /// Batching of frames written to the downstream and upstream connections of every session
+    /// started by this handler. Batching frames that are queued at the same time reduces the
+    /// number of system calls, e.g. when a new job is broadcast to many sessions. Each frame is
+    /// flushed on its own by default.
+    pub fn with_write_coalescing(mut self, write_coalescing: WriteCoalescing) -> Self {
+        self.write_coalescing = write_coalescing;
+        self
+    }
+
+    /// Remember upstream session IDs so that returning devices can resume their V1 session
+    /// (extranonce, difficulty) by passing the session ID in `mining.subscribe`
+    pub fn with_v1_session_resumption(mut self) -> Self {
+        self.v1_sessions = Some(Default::default());
+        self
+    }
+
+    /// Account shares of all sessions per worker into `share_accounting` that is supposed to be
+    /// flushed into a persistent storage
+    pub fn with_share_accounting(mut self, share_accounting: ShareAccounting) -> Self {
+        self.share_accounting = Some(share_accounting);
+        self
+    }
+
+    /// Every channel has to be authorized by `authenticator`, upstream connections are opened
+    /// only for authorized channels
+    pub fn with_authenticator(mut self, authenticator: Arc<dyn Authenticator>) -> Self {
+        self.authenticator = Some(authenticator);
+        self
+    }
+
+    /// Export connection and share events of all sessions via `event_exporter`
+    pub fn with_event_exporter(mut self, event_exporter: EventExporter) -> Self {
+        self.event_exporter = Some(event_exporter);
+        self
+    }
+
+    /// Options used for every translation session started by this handler
+    pub fn with_options(mut self, options: V2ToV1TranslationOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    /// Handlers of non-base extension frames received from downstream. Frames of extensions
+    /// without a handler are dropped.
+    pub fn with_extensions(mut self, extensions: v2::extensions::ExtensionRegistry) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    /// Passes frames of all extensions that have no handler registered to `pass_through` instead
+    /// of dropping them. The upstream of the translation speaks V1, so it's up to the receiving
+    /// side to deliver the frames to a component that understands the extension.
+    pub fn with_unknown_extensions_passed_to(mut self, pass_through: ExtensionPassThrough) -> Self {
+        self.extension_pass_through = Some(pass_through);
+        self
     }
 }
 
-impl ConnectionHandler for TranslationHandler {
+impl<S, U> ConnectionHandler<S, U> for TranslationHandler
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug + 'static,
+    U: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug + 'static,
+{
     fn handle_connection(
         &mut self,
-        v2_conn: v2::Framed,
+        mut v2_conn: v2::Framed<S>,
         v2_peer: DownstreamPeer,
-        v1_conn: v1::Framed,
-        v1_peer_addr: SocketAddr,
-    ) -> Pin<Box<dyn Future<Output = Result<()>> + Send>> {
+        v1_upstream: SessionUpstream<U>,
+        cancel: CancellationToken,
+        commands: controller::SessionCommandReceiver,
+        stats: SessionStats,
+    ) -> Pin<Box<dyn Future<Output = SessionSummary> + Send>> {
+        v2_conn
+            .codec_mut()
+            .set_byte_counter(stats.downstream_bytes());
+        let mut extensions = self.extensions.clone();
+        if let Some(pass_through) = self.extension_pass_through.as_ref() {
+            extensions.set_fallback(pass_through.start_session(self.metrics.clone()));
+        }
         let translation = ConnTranslation::new(
             v2_conn,
             v2_peer,
-            v1_conn,
-            v1_peer_addr,
+            v1_upstream,
+            self.options,
+            extensions,
             self.metrics.clone(),
-        );
+        )
+        .with_v1_session_store(self.v1_sessions.clone())
+        .with_share_accounting(self.share_accounting.clone())
+        .with_authenticator(self.authenticator.clone())
+        .with_event_exporter(self.event_exporter.clone())
+        .with_session_stats(stats.clone())
+        .with_timeouts(self.timeouts)
+        .with_write_coalescing(self.write_coalescing)
+        .with_cancellation(cancel)
+        .with_commands(commands);
+
+        let event_exporter = self.event_exporter.clone();
+        let source_addr = v2_peer.source_addr();
+
+        async move {
+            if let Some(event_exporter) = event_exporter.as_ref() {
+                event_exporter.export(Event::ConnectionOpened { source_addr });
+            }
+            let started = Instant::now();
+            let result = translation.run().await;
+            let summary = SessionSummary::new(v2_peer, started.elapsed(), &stats, result.into());
+            if let Some(event_exporter) = event_exporter.as_ref() {
+                event_exporter.export(Event::ConnectionClosed {
+                    source_addr,
+                    duration_secs: summary.duration.as_secs_f64(),
+                    shares_accepted: summary.shares_accepted,
+                    shares_rejected: summary.shares_rejected,
+                    reason: summary.close_reason.to_string(),
+                });
+            }
+            summary
+        }
+        .boxed()
+    }
 
-        translation.run().boxed()
+    fn defers_upstream_connection(&self) -> bool {
+        self.authenticator.is_some()
     }
 }
 
@@ -324,135 +725,303 @@ impl Default for ProxyProtocolConfig {
     }
 }
 
-struct ProxyConnection<H> {
+/// Opens the upstream connection of a single session and passes the PROXY protocol header
+/// (if configured)
+struct UpstreamConnector<U> {
+    upstream: U,
+    /// See ProxyServer
+    retry_policy: RetryPolicy,
+    /// Version of the PROXY protocol header along with the source and destination addresses
+    proxy_header: Option<(proxy::ProtocolVersion, SocketAddr, SocketAddr)>,
+    metrics: Option<Arc<ProxyMetrics>>,
+    session_entry: Arc<controller::SessionEntry>,
+    /// Shared with `ProxyConnection` so that the session can be accounted to the upstream
+    upstream_name: Arc<std::sync::Mutex<Option<String>>>,
+    downstream_peer: DownstreamPeer,
+    cancel: CancellationToken,
+}
+
+impl<U: Upstream> UpstreamConnector<U> {
+    /// Connects to the upstream V1 server, each attempt opens a new connection (e.g. the host
+    /// name is resolved again). Use the connection only to build the Framed object with V1
+    /// framing. The upstream is chosen by `routing_key` if present.
+    async fn connect(
+        self,
+        routing_key: Option<String>,
+    ) -> Result<SessionUpstreamConnection<U::Stream>> {
+        let proxy_info = self.downstream_peer.proxy_info;
+        let upstream = &self.upstream;
+        let v1_connected = retry_with_backoff(&self.retry_policy, |_| match &routing_key {
+            Some(routing_key) => upstream.connect_routed(routing_key),
+            None => upstream.connect(),
+        })
+        .cancel(self.cancel.cancelled())
+        .await
+        .map_err(|()| Error::Cancelled)?
+        .map_err(Error::UpstreamConnect)?;
+        if !v1_connected.failed_attempts.is_empty() {
+            debug!(
+                "Connected to upstream {} after {} failed attempts",
+                upstream,
+                v1_connected.failed_attempts.len();
+                proxy_info
+            );
+        }
+        let UpstreamConnection {
+            stream: mut v1_conn,
+            peer_addr: v1_peer_addr,
+            upstream_name,
+            credentials,
+        } = v1_connected.value;
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.account_upstream_session_started(&upstream_name);
+        }
+        self.session_entry.set_upstream_name(upstream_name.clone());
+        *self
+            .upstream_name
+            .lock()
+            .expect("BUG: upstream name lock poisoned") = Some(upstream_name);
+
+        if let Some((version, src, dst)) = self.proxy_header {
+            Connector::new(version)
+                .write_proxy_header(&mut v1_conn, Some(src), Some(dst))
+                .await
+                .map_err(UpstreamError::ProxyProtocol)?;
+        }
+        let v1_framed_stream =
+            v1::Framed::new(v1_conn, <v1::Framing as ii_wire::Framing>::Codec::default());
+        debug!(
+            "Established translation connection with upstream V1 {}",
+            v1_peer_addr;
+            proxy_info
+        );
+        Ok((v1_framed_stream, v1_peer_addr, credentials))
+    }
+}
+
+struct ProxyConnection<H, S, U> {
     /// Upstream server that we should try to connect to
-    v1_upstream_addr: Address,
+    upstream: U,
     /// See ProxyServer
     connection_handler: H,
     /// Security context for noise handshake
     security_context: Option<Arc<SecurityContext>>,
+    /// See ProxyServer
+    handshake_limiter: Option<HandshakeLimiter>,
+    /// See ProxyServer
+    upstream_retry_policy: RetryPolicy,
+    /// See ProxyServer
+    max_frame_size: Option<usize>,
     /// Builds PROXY protocol acceptor for a specified configuration and clones it into
     /// It is intentionally optional so that the do_handle() method can take it while working with
     /// a mutable reference of Self instance. At the same time it introduces a state into the
     /// connection, where going over `do_handle()` twice is considered a BUG.
-    proxy_protocol_acceptor: Option<proxy::AcceptorFuture<TcpStream>>,
+    proxy_protocol_acceptor: Option<proxy::AcceptorFuture<S>>,
     /// Server will use this version for talking to upstream server (if any)
     proxy_protocol_upstream_version: Option<proxy::ProtocolVersion>,
     metrics: Option<Arc<ProxyMetrics>>,
     client_counter: controller::ClientCounter,
+    /// Registration of this connection among sessions of the server
+    session_entry: Arc<controller::SessionEntry>,
     downstream_peer: DownstreamPeer,
+    /// Local address of the downstream connection
+    local_addr: SocketAddr,
+    /// Cancelled when the server terminates immediately
+    cancel: CancellationToken,
+    /// CPU time consumed by this connection (only if CPU accounting is enabled)
+    cpu_time: Option<cpu_time::CpuTimeCounter>,
+    /// Commands for the session, taken by `do_handle()` once the session starts
+    commands: Option<controller::SessionCommandReceiver>,
+    /// Upstream that the session has connected to
+    upstream_name: Arc<std::sync::Mutex<Option<String>>>,
+    /// Statistics of the session, shared with the session registry
+    stats: SessionStats,
 }
 
-impl<FN> Drop for ProxyConnection<FN> {
+impl<FN, S, U> Drop for ProxyConnection<FN, S, U> {
     fn drop(&mut self) {
         self.client_counter.decrease()
     }
 }
 
-impl<H> ProxyConnection<H>
+impl<H, S, U> ProxyConnection<H, S, U>
 where
-    H: ConnectionHandler,
+    H: ConnectionHandler<S, U::Stream>,
+    S: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug + 'static,
+    U: Upstream,
 {
-    fn new(
-        proxy_server: &ProxyServer<H>,
-        connection: TcpStream,
-        downstream_peer: SocketAddr,
-    ) -> Self {
+    fn new<L>(proxy_server: &ProxyServer<H, L, U>, connection: IncomingConnection<S>) -> Self
+    where
+        L: Listener<Stream = S>,
+    {
+        let downstream_peer = DownstreamPeer::new(connection.peer_addr);
+        let cpu_time = if proxy_server.cpu_accounting {
+            Some(cpu_time::CpuTimeCounter::default())
+        } else {
+            None
+        };
+        let (command_tx, commands) = tokio::sync::mpsc::unbounded_channel();
+        let stats = SessionStats::default().with_cpu_time(cpu_time.clone());
+        let session_entry =
+            proxy_server
+                .controller
+                .session_registry()
+                .register(controller::SessionInfo {
+                    downstream_peer,
+                    local_addr: connection.local_addr,
+                    established: Instant::now(),
+                    cpu_time: cpu_time.clone(),
+                    command_tx,
+                    upstream_name: None,
+                    stats: stats.clone(),
+                });
         Self {
-            v1_upstream_addr: proxy_server.v1_upstream_addr.clone(),
+            upstream: proxy_server.upstream.clone(),
             connection_handler: proxy_server.connection_handler.clone(),
             security_context: proxy_server.security_context.clone(),
+            handshake_limiter: proxy_server.handshake_limiter.clone(),
+            upstream_retry_policy: proxy_server.upstream_retry_policy.clone(),
+            max_frame_size: proxy_server.max_frame_size,
             proxy_protocol_acceptor: Some(
                 proxy_server
                     .proxy_protocol_acceptor_builder
-                    .build(connection),
+                    .build(connection.stream),
             ),
             proxy_protocol_upstream_version: proxy_server.proxy_protocol_upstream_version,
             metrics: proxy_server.metrics.clone(),
             client_counter: proxy_server.controller.counter_for_new_client(),
-            downstream_peer: DownstreamPeer::new(downstream_peer),
+            session_entry: Arc::new(session_entry),
+            downstream_peer,
+            local_addr: connection.local_addr,
+            cancel: proxy_server.shutdown.child_token(),
+            cpu_time,
+            commands: Some(commands),
+            upstream_name: Default::default(),
+            stats,
         }
     }
 
     /// Handle incoming connection:
-    ///  - establish upstream V1 connection
     ///  - check PROXY protocol header (if configured)
+    ///  - establish upstream V1 connection (unless the session connects once its channel is
+    ///    authorized or the upstream is chosen by the worker name)
     ///  - pass PROXY protocol header (if configured)
     ///  - establish noise handshake (if configured)
-    async fn do_handle(&mut self) -> Result<()> {
+    async fn do_handle(&mut self) -> Result<SessionSummary> {
+        let cancel = self.cancel.clone();
         // Handle proxy protocol
         let proxy_protocol_acceptor = self
             .proxy_protocol_acceptor
             .take()
             .expect("BUG: proxy protocol acceptor has already been used");
         let proxy_stream = proxy_protocol_acceptor
+            .cancel(cancel.cancelled())
             .await
+            .map_err(|()| Error::Cancelled)?
             .map_err(DownstreamError::ProxyProtocol)?;
-        let local_addr = proxy_stream
-            .local_addr()
-            .map_err(|e| DownstreamError::ProxyProtocol(ii_wire::proxy::error::Error::from(e)))?;
+        let local_addr = self.local_addr;
         let proxy_info = proxy_stream
             .proxy_info()
             .map_err(DownstreamError::ProxyProtocol)?;
         self.downstream_peer.set_proxy_info(proxy_info);
+        self.session_entry.set_downstream_peer(self.downstream_peer);
 
         debug!(
             "Received connection from: {}, local destination: {}",
             self.downstream_peer.direct_peer,
-            local_addr.to_string();
+            local_addr;
             proxy_info
         );
-        // Connect to upstream V1 server
-        let mut v1_client = Client::new(self.v1_upstream_addr.clone());
-        // TODO Attempt only once to connect -> consider using the backoff for a few rounds before
-        // failing. Also
-        // Use the connection only to build the Framed object with V1 framing and to extract the
-        // peer address
-        let mut v1_conn = v1_client.next().await?;
-        let v1_peer_addr = v1_conn.peer_addr().map_err(UpstreamError::Io)?;
-
-        if let Some(version) = self.proxy_protocol_upstream_version {
-            let (src, dst) = if let (Some(src), Some(dst)) = (
+        // Reserve the handshake slot before anything else is done for the client
+        let handshake_permit = match (self.security_context.as_ref(), &self.handshake_limiter) {
+            (Some(_), Some(handshake_limiter)) => match handshake_limiter.try_acquire() {
+                Some(handshake_permit) => Some(handshake_permit),
+                None => {
+                    if let Some(metrics) = self.metrics.as_ref() {
+                        metrics.account_handshake_rejected();
+                    }
+                    return Err(ii_stratum::error::Error::Noise(format!(
+                        "Too many handshakes in progress (limit: {})",
+                        handshake_limiter.max_in_flight()
+                    ))
+                    .into());
+                }
+            },
+            _ => None,
+        };
+        let proxy_header = match self.proxy_protocol_upstream_version {
+            Some(version) => match (
                 proxy_stream.original_peer_addr(),
                 proxy_stream.original_destination_addr(),
             ) {
-                (Some(src), Some(dst))
-            } else {
-                debug!(
-                    "Passing of proxy protocol is required, but incoming connection does \
+                (Some(src), Some(dst)) => Some((version, src, dst)),
+                _ => {
+                    debug!(
+                        "Passing of proxy protocol is required, but incoming connection does \
                             not contain original addresses, using socket addresses"
-                );
-                (Some(self.downstream_peer.direct_peer), Some(local_addr))
-            };
-            Connector::new(version)
-                .write_proxy_header(&mut v1_conn, src, dst)
-                .await
-                .map_err(UpstreamError::ProxyProtocol)?;
-        }
-        let v1_framed_stream = Connection::<v1::Framing>::new(v1_conn).into_inner();
-        debug!(
-            "Established translation connection with upstream V1 {}",
-            v1_peer_addr;
-            proxy_info
-        );
-        let v2_framed_stream = match self.security_context.as_ref() {
-            Some(security_context) => security_context
-                .build_framed_tcp_from_parts(proxy_stream.into_framed_parts())
-                .await
-                .map_err(|e| ii_stratum::error::Error::Noise(e.to_string()))?,
-            None => Connection::<v2::Framing>::from(proxy_stream).into_inner(),
+                    );
+                    Some((version, self.downstream_peer.direct_peer, local_addr))
+                }
+            },
+            None => None,
         };
+        let connector = UpstreamConnector {
+            upstream: self.upstream.clone(),
+            retry_policy: self.upstream_retry_policy.clone(),
+            proxy_header,
+            metrics: self.metrics.clone(),
+            session_entry: self.session_entry.clone(),
+            upstream_name: self.upstream_name.clone(),
+            downstream_peer: self.downstream_peer,
+            cancel: cancel.clone(),
+        };
+        let routes_by_key = self.upstream.routes_by_key();
+        let v1_upstream = if routes_by_key || self.connection_handler.defers_upstream_connection() {
+            // The worker name selects the upstream only if the upstream routes by it
+            SessionUpstream::Routed(Box::new(move |routing_key| {
+                connector
+                    .connect(Some(routing_key).filter(|_| routes_by_key))
+                    .boxed()
+            }))
+        } else {
+            let (v1_framed_stream, v1_peer_addr, credentials) = connector.connect(None).await?;
+            SessionUpstream::Connected(v1_framed_stream, v1_peer_addr, credentials)
+        };
+        let mut v2_framed_stream = match self.security_context.as_ref() {
+            Some(security_context) => {
+                let (stream, read_buf) = proxy_stream.into_inner_with_buf();
+                security_context
+                    .build_framed(stream, Some(read_buf), handshake_permit)
+                    .cancel(cancel.cancelled())
+                    .await
+                    .map_err(|()| Error::Cancelled)?
+                    .map_err(|e| ii_stratum::error::Error::Noise(e.to_string()))?
+            }
+            None => v2::Framed::from_parts(proxy_stream.into_framed_parts::<_, v2::Frame>()),
+        };
+        if let Some(max_frame_size) = self.max_frame_size {
+            v2_framed_stream
+                .codec_mut()
+                .l2_codec_mut()
+                .set_max_frame_size(max_frame_size);
+        }
 
         // Start processing of both ends
-        // TODO adjust connection handler to return a Result
-        self.connection_handler
+        let commands = self
+            .commands
+            .take()
+            .expect("BUG: session commands have already been taken");
+        Ok(self
+            .connection_handler
             .handle_connection(
                 v2_framed_stream,
                 self.downstream_peer,
-                v1_framed_stream,
-                v1_peer_addr,
+                v1_upstream,
+                cancel,
+                commands,
+                self.stats.clone(),
             )
-            .await
+            .await)
     }
 
     /// Handle connection by delegating it to a method that is able to handle a Result so that we
@@ -463,29 +1032,42 @@ where
         // TODO report full address info here once ProxyConnection has internal information about
         // (possible provide full 'ProxyInfo')
         let proxy_info = self.downstream_peer.proxy_info;
-        match self.do_handle().await {
-            Ok(()) => {
-                if let Some(x) = metrics.as_ref() {
-                    x.tcp_connection_close_ok();
-                }
-                debug!(
-                    "Connection closed by downstream peer: {}",
-                    self.downstream_peer; proxy_info
-                );
+        let cpu_time = self.cpu_time.clone();
+        let result = match cpu_time.as_ref() {
+            Some(cpu_time) => cpu_time.instrument(self.do_handle()).await,
+            None => self.do_handle().await,
+        };
+        // Sessions that fail before reaching the connection handler have no statistics
+        let mut summary = match result {
+            Ok(summary) => summary,
+            Err(err) => SessionSummary::new(
+                self.downstream_peer,
+                timer.elapsed(),
+                &self.stats,
+                CloseReason::Error(err),
+            ),
+        };
+        summary.cpu_time = cpu_time.map(|cpu_time| cpu_time.cpu_time());
+        match summary.close_reason {
+            // Miners that keep reconnecting and failing would flood the log otherwise
+            CloseReason::Error(_) => {
+                let source_ip = summary.downstream_peer.source_ip();
+                info_limited!(source_ip; "Finished {}", summary; proxy_info)
             }
-            Err(err) => {
-                if let Some(x) = metrics.as_ref() {
-                    x.tcp_connection_close_with_error(&err);
-                }
-                debug!(
-                    "Connection error: {} downstream peer: {}",
-                    err, self.downstream_peer;
-                    proxy_info
-                )
+            CloseReason::Downstream | CloseReason::Cancelled => {
+                info!("Finished {}", summary; proxy_info)
             }
-        };
-        if let Some(x) = self.metrics.as_ref() {
+        }
+        if let Some(x) = metrics.as_ref() {
+            x.observe_session_summary(&summary);
             x.tcp_connection_timer_observe(timer);
+            let upstream_name = self
+                .upstream_name
+                .lock()
+                .expect("BUG: upstream name lock poisoned");
+            if let Some(upstream_name) = upstream_name.as_ref() {
+                x.account_upstream_session_finished(upstream_name);
+            }
         }
     }
 }
@@ -497,23 +1079,47 @@ where
 /// (a stream-like interface) or, as a higher-level interface,
 /// the `run()` method turns the `ProxyServer`
 /// into an asynchronous task (which internally calls `next()` in a loop).
-pub struct ProxyServer<H> {
-    server: Option<TcpListener>,
-    listen_socket: SocketAddr,
-    v1_upstream_addr: Address,
+pub struct ProxyServer<H, L = TcpSocketListener, U = TcpUpstream>
+where
+    L: Listener,
+{
+    listener: L,
+    /// Provides a new upstream connection for each session
+    upstream: U,
     controller: controller::Controller,
     /// Closure that generates a handler in the form of a Future that will be passed to the
     connection_handler: H,
     /// Security context for noise handshake
     security_context: Option<Arc<SecurityContext>>,
+    /// Optional cap on the number of noise handshakes in progress
+    handshake_limiter: Option<HandshakeLimiter>,
+    /// How connecting to the upstream server is retried for each session
+    upstream_retry_policy: RetryPolicy,
+    /// Limit of the payload length of frames received from downstream connections
+    max_frame_size: Option<usize>,
     metrics: Option<Arc<ProxyMetrics>>,
     /// Builds PROXY protocol acceptor for a specified configuration
-    proxy_protocol_acceptor_builder: proxy::AcceptorBuilder<TcpStream>,
+    proxy_protocol_acceptor_builder: proxy::AcceptorBuilder<L::Stream>,
     /// Server will use this version for talking to upstream server (when defined)
     proxy_protocol_upstream_version: Option<proxy::ProtocolVersion>,
+    /// Sender part is cloned into every `ServerHandle`
+    command_tx: tokio::sync::mpsc::UnboundedSender<controller::ServerCommand>,
+    command_rx: tokio::sync::mpsc::UnboundedReceiver<controller::ServerCommand>,
+    /// Cancels all sessions on immediate termination, each session uses its child token
+    shutdown: CancellationToken,
+    /// Account CPU time consumed by each session
+    cpu_accounting: bool,
 }
 
-impl<H> ProxyServer<H>
+impl<H> ProxyServer<H, TcpSocketListener, TcpUpstream> {
+    /// Starts building the server, the listener and the upstream default to TCP and can be
+    /// replaced by arbitrary implementations
+    pub fn builder() -> ProxyServerBuilder<H> {
+        ProxyServerBuilder::new()
+    }
+}
+
+impl<H> ProxyServer<H, TcpSocketListener, TcpUpstream>
 where
     H: ConnectionHandler,
 {
@@ -527,50 +1133,237 @@ where
         proxy_protocol_config: ProxyProtocolConfig,
         metrics: Option<Arc<ProxyMetrics>>,
     ) -> Result<ProxyServer<H>> {
-        let listen_socket = listen_addr
-            .to_socket_addrs()
-            .map_err(|e| Error::HostNameError(e.to_string()))?
-            .next()
-            .ok_or_else(|| Error::HostNameError("Failed to resolve listen_addr".into()))?;
-
-        let mut proxy_server = ProxyServer {
-            server: None,
-            listen_socket,
-            v1_upstream_addr,
+        Self::builder()
+            .with_listen_address(listen_addr)
+            .with_upstream_address(v1_upstream_addr)
+            .with_connection_handler(connection_handler)
+            .with_security_context(security_context)
+            .with_proxy_protocol_config(proxy_protocol_config)
+            .with_metrics(metrics)
+            .build()
+            .await
+    }
+}
+
+impl<H, U> ProxyServer<H, TcpSocketListener, U>
+where
+    U: Upstream,
+    H: ConnectionHandler<TcpStream, U::Stream>,
+{
+    /// Same as `listen()`, connections to the upstream are provided by `upstream`
+    pub async fn listen_with_upstream(
+        listen_addr: Address,
+        upstream: U,
+        connection_handler: H,
+        security_context: Option<Arc<SecurityContext>>,
+        proxy_protocol_config: ProxyProtocolConfig,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) -> Result<Self> {
+        ProxyServer::builder()
+            .with_listen_address(listen_addr)
+            .with_upstream(upstream)
+            .with_connection_handler(connection_handler)
+            .with_security_context(security_context)
+            .with_proxy_protocol_config(proxy_protocol_config)
+            .with_metrics(metrics)
+            .build()
+            .await
+    }
+}
+
+impl<H, L> ProxyServer<H, L, TcpUpstream>
+where
+    L: Listener,
+    H: ConnectionHandler<L::Stream>,
+{
+    /// Constructor that builds the `ProxyServer` instance on top of an arbitrary `listener` that
+    /// provides incoming downstream connections
+    pub fn with_listener(
+        listener: L,
+        v1_upstream_addr: Address,
+        connection_handler: H,
+        security_context: Option<Arc<SecurityContext>>,
+        proxy_protocol_config: ProxyProtocolConfig,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) -> Self {
+        Self::with_listener_and_upstream(
+            listener,
+            TcpUpstream::new(v1_upstream_addr),
             connection_handler,
             security_context,
+            proxy_protocol_config,
+            metrics,
+        )
+    }
+}
+
+impl<H, L, U> ProxyServer<H, L, U>
+where
+    L: Listener,
+    U: Upstream,
+    H: ConnectionHandler<L::Stream, U::Stream>,
+{
+    /// Constructor that builds the `ProxyServer` instance on top of an arbitrary `listener` that
+    /// provides incoming downstream connections and an arbitrary `upstream` that provides
+    /// connections to the upstream server
+    pub fn with_listener_and_upstream(
+        listener: L,
+        upstream: U,
+        connection_handler: H,
+        security_context: Option<Arc<SecurityContext>>,
+        proxy_protocol_config: ProxyProtocolConfig,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) -> Self {
+        let (command_tx, command_rx) = tokio::sync::mpsc::unbounded_channel();
+        Self {
+            listener,
+            upstream,
+            connection_handler,
+            security_context,
+            handshake_limiter: None,
+            upstream_retry_policy: Self::default_upstream_retry_policy(),
+            max_frame_size: None,
             metrics,
             proxy_protocol_acceptor_builder: proxy::AcceptorBuilder::new(
                 proxy_protocol_config.downstream_config,
             ),
             proxy_protocol_upstream_version: proxy_protocol_config.upstream_version,
             controller: Default::default(),
-        };
-        proxy_server.bind_new_socket().await?;
-        Ok(proxy_server)
+            command_tx,
+            command_rx,
+            shutdown: CancellationToken::new(),
+            cpu_accounting: false,
+        }
+    }
+
+    /// Limit number of concurrently handled connections
+    pub fn with_connection_limit(
+        mut self,
+        connection_limit: Option<controller::ConnectionLimit>,
+    ) -> Self {
+        self.controller.set_connection_limit(connection_limit);
+        self
+    }
+
+    /// Limit number of concurrently running noise handshakes, connections above the limit are
+    /// closed
+    pub fn with_handshake_limiter(mut self, handshake_limiter: Option<HandshakeLimiter>) -> Self {
+        self.handshake_limiter = handshake_limiter;
+        self
+    }
+
+    /// A few quick attempts cover transient DNS and connection failures while the downstream
+    /// device is still waiting, the deadline prevents sessions from hanging on an unreachable
+    /// upstream
+    fn default_upstream_retry_policy() -> RetryPolicy {
+        RetryPolicy::default()
+            .with_max_attempts(Some(3))
+            .with_deadline(Some(Duration::from_secs(10)))
+    }
+
+    /// Change how connecting to the upstream server is retried
+    pub fn with_upstream_retry_policy(mut self, upstream_retry_policy: RetryPolicy) -> Self {
+        self.upstream_retry_policy = upstream_retry_policy;
+        self
     }
 
-    async fn bind_new_socket(&mut self) -> Result<()> {
+    /// Downstream connections that send a frame with payload longer than `max_frame_size` are
+    /// terminated, by default the limit is given by the frame header only
+    pub fn with_max_frame_size(mut self, max_frame_size: Option<usize>) -> Self {
+        self.max_frame_size = max_frame_size;
+        self
+    }
+
+    /// Account CPU time consumed by each session, the time is reported in session summaries and
+    /// available via `ServerHandle::sessions()`. Sampling the thread CPU clock adds two system
+    /// calls to each poll of a session, hence it is disabled by default.
+    pub fn with_cpu_accounting(mut self, cpu_accounting: bool) -> Self {
+        self.cpu_accounting = cpu_accounting;
+        self
+    }
+
+    async fn rebind_listener(&mut self) -> Result<()> {
         if let Some(metrics) = self.metrics.as_ref() {
             metrics.account_tcp_listener_breakdown();
         }
-        self.server.replace(
-            TcpListener::bind(self.listen_socket)
-                .await
-                .map_err(Error::Io)?,
-        );
-        Ok(())
+        self.listener.rebind().await.map_err(Error::Io)
     }
 
     pub fn termination_notifier(&self) -> Arc<tokio::sync::Notify> {
         self.controller.termination_notifier()
     }
 
+    /// Address the server is listening on, e.g. to find out the port when bound to port 0
+    pub fn local_addr(&self) -> std::io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    /// Handle for controlling the server once it is running
+    pub fn handle(&self) -> controller::ServerHandle {
+        controller::ServerHandle::new(
+            self.command_tx.clone(),
+            self.controller.session_registry().clone(),
+        )
+    }
+
+    /// Applies `command` to the server, returns true if the server should stop accepting new
+    /// connections
+    fn handle_command(&mut self, command: controller::ServerCommand) -> bool {
+        use controller::ServerCommand::*;
+        match command {
+            SetSecurityContext(security_context) => {
+                info!(
+                    "Security context changed, noise enabled for new connections: {}",
+                    security_context.is_some()
+                );
+                self.security_context = security_context;
+            }
+            SetConnectionLimit(connection_limit) => {
+                info!("Connection limit changed to: {:?}", connection_limit);
+                self.controller.set_connection_limit(connection_limit);
+            }
+            SetUpstreamDraining {
+                upstream_name,
+                draining,
+                reply_tx,
+            } => {
+                let known = self.upstream.set_draining(&upstream_name, draining);
+                if known {
+                    info!("Upstream {} draining: {}", upstream_name, draining);
+                    if let Some(metrics) = self.metrics.as_ref() {
+                        metrics.set_upstream_draining(&upstream_name, draining);
+                    }
+                }
+                // The requester may have given up waiting
+                let _ = reply_tx.send(known);
+            }
+            Drain => {
+                info!("Draining requested, no new connections will be accepted");
+                return true;
+            }
+            Quit => {
+                info!("Immediate termination requested");
+                self.terminate_immediately();
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Stop waiting for sessions to finish and cancel them
+    fn terminate_immediately(&mut self) {
+        self.controller.request_immediate_termination();
+        self.shutdown.cancel();
+    }
+
     /// Helper method for accepting incoming connections
-    fn accept(&self, connection: TcpStream, peer: SocketAddr) {
-        trace!("stratum proxy: Handling connection from: {:?}", peer);
+    fn accept(&self, connection: IncomingConnection<L::Stream>) {
+        trace!(
+            "stratum proxy: Handling connection from: {:?}",
+            connection.peer_addr
+        );
         // Fully secured connection has been established
-        let proxy_connection = ProxyConnection::new(self, connection, peer);
+        let proxy_connection = ProxyConnection::new(self, connection);
         if let Some(metrics) = self.metrics.as_ref() {
             metrics.accounted_spawn(proxy_connection.handle());
         } else {
@@ -584,47 +1377,79 @@ where
     /// connection errors via the logging crate.
     pub async fn main_loop(mut self, tripwire: Tripwire) {
         info!(
-            "Stratum proxy service starting @ {} -> {}",
-            self.listen_socket, self.v1_upstream_addr
+            "Stratum proxy service starting @ {:?} -> {}",
+            self.listener.local_addr(),
+            self.upstream
         );
-        let mut inbound_conections = self
-            .server
-            .take()
-            .expect("BUG: Missing wire::Server instance");
 
         let mut latest_connection_accept_failure = None::<Instant>;
 
         loop {
+            let accept_paused = self.controller.connection_limit_reached()
+                == Some(controller::ConnectionLimitAction::Pause);
+            if accept_paused {
+                warn!("Connection limit reached, accepting of new connections is paused");
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics
+                        .account_connection_limit_reached(controller::ConnectionLimitAction::Pause);
+                }
+            }
             // Three situations can happen:
             // 1. Next connection is either yielded
             // 2. Listening is terminated by tripwire (results in immediate termination)
             // 3. Listening is terminated from shutdown api call (results in slow termination)
-            let tcp_accept_result = tokio::select! {
-                tcp_accept_result = inbound_conections.accept() => {
-                    tcp_accept_result
+            let accept_result = tokio::select! {
+                // Resume accepting once some sessions have terminated
+                _ = self.controller.wait_for_free_slot(), if accept_paused => {
+                    info!("Connection limit no longer reached, resuming accepting of connections");
+                    continue
+                },
+                accept_result = self.listener.accept(), if !accept_paused => {
+                    accept_result
                 },
                 _ = tripwire.clone() => {
-                    self.controller.request_immediate_termination();
+                    self.terminate_immediately();
                     break
                 }
                 // Termination has been requested via shutdown api
                 _ = self.controller.wait_for_notification() => {
                     break
                 }
+                Some(command) = self.command_rx.recv() => {
+                    if self.handle_command(command) {
+                        break
+                    }
+                    continue
+                }
             };
-            match tcp_accept_result {
-                Ok((stream, peer)) => {
-                    debug!("Connection accepted from {}", peer);
+            match accept_result {
+                Ok(connection) => {
+                    debug!("Connection accepted from {}", connection.peer_addr);
+                    if self.controller.connection_limit_reached()
+                        == Some(controller::ConnectionLimitAction::Reject)
+                    {
+                        warn_limited!(
+                            connection.peer_addr.ip();
+                            "Connection limit reached, closing connection from {}",
+                            connection.peer_addr
+                        );
+                        if let Some(metrics) = self.metrics.as_ref() {
+                            metrics.account_connection_limit_reached(
+                                controller::ConnectionLimitAction::Reject,
+                            );
+                        }
+                        continue;
+                    }
                     if let Some(metrics) = self.metrics.as_ref() {
                         // TODO eliminate duplicate code for metrics accounting, consider moving the inc_by_error
                         //  to the caller. The problem is that it would not be as transparent due to
                         metrics.account_successful_tcp_open();
                     }
-                    self.accept(stream, peer);
+                    self.accept(connection);
                 }
                 Err(accept_error) => {
                     warn!(
-                        "TcpListener failed to provide functional TcpStream: {}",
+                        "Listener failed to provide functional connection: {}",
                         accept_error
                     );
                     if let Some(metrics) = self.metrics.as_ref() {
@@ -637,41 +1462,41 @@ where
                         // If the latest connection-accept event was less then millisecond ago,
                         // create drop the listener and bind again.
                         if last_fail.elapsed() < Duration::from_millis(1) {
-                            info!("Trying to rebind new TcpListener");
+                            info!("Trying to rebind new listener");
                             // This doesn't affect existing connections
-                            drop(inbound_conections);
+                            self.listener.close();
                             // Wait a little to let system close the socket before trying to create a new one
                             tokio::time::sleep(Duration::from_millis(100)).await;
-                            inbound_conections = loop {
-                                match self.bind_new_socket().await {
+                            loop {
+                                match self.rebind_listener().await {
                                     Ok(()) => {
-                                        info!("TcpListener successfully bound");
-                                        break self.server.take().expect(
-                                            "BUG: Missing TcpStream right after successful binding",
-                                        );
+                                        info!("Listener successfully bound");
+                                        break;
                                     }
                                     Err(e) => {
-                                        warn!("TcpListener cannot be bound: {}", e);
+                                        warn!("Listener cannot be bound: {}", e);
                                         tokio::time::sleep(Duration::from_millis(1000)).await;
                                     }
                                 }
-                            };
+                            }
                         }
                     }
                 }
             }
         }
         // This doesn't affect existing connections
-        drop(inbound_conections);
+        self.listener.close();
         self.controller.wait_for_termination(None).await;
 
         info!("Stratum proxy service terminated");
     }
 }
 
-impl<H> Spawnable for ProxyServer<H>
+impl<H, L, U> Spawnable for ProxyServer<H, L, U>
 where
-    H: ConnectionHandler,
+    L: Listener,
+    U: Upstream,
+    H: ConnectionHandler<L::Stream, U::Stream>,
 {
     fn run(self, tripwire: Tripwire) -> tokio::task::JoinHandle<()> {
         tokio::spawn(self.main_loop(tripwire))
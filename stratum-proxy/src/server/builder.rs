@@ -0,0 +1,359 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Builder of `ProxyServer` for applications that embed the proxy. Only the connection handler,
+//! the listener and the upstream are mandatory, the rest of the parts is optional.
+
+use std::io;
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use futures::prelude::*;
+
+use ii_async_utils::CancellationToken;
+use ii_noise_proxy::SecurityContext;
+use ii_stratum::v2::noise::HandshakeLimiter;
+use ii_wire::Address;
+
+use super::{
+    ConnectionHandler, Listener, ProxyProtocolConfig, ProxyServer, TcpSocketListener, TcpUpstream,
+    Upstream,
+};
+use crate::error::{BuilderError, Error, Result};
+use crate::metrics::ProxyMetrics;
+
+type BindFn<L> = fn(SocketAddr) -> Pin<Box<dyn Future<Output = io::Result<L>> + Send>>;
+
+/// Builds `ProxyServer`, see `ProxyServer::builder()`. Invalid combinations of parts are
+/// reported by `build()` as `BuilderError`.
+pub struct ProxyServerBuilder<H, L = TcpSocketListener, U = TcpUpstream> {
+    connection_handler: Option<H>,
+    listener: Option<L>,
+    /// Address to bind, it is resolved and bound by `build()`
+    listen_address: Option<Address>,
+    /// Binds a listener of type `L`, only available for `TcpSocketListener`
+    bind: Option<BindFn<L>>,
+    upstream: Option<U>,
+    upstream_address: Option<Address>,
+    /// Builds an upstream of type `U` from an address, only available for `TcpUpstream`
+    new_upstream: Option<fn(Address) -> U>,
+    security_context: Option<Arc<SecurityContext>>,
+    handshake_limiter: Option<HandshakeLimiter>,
+    proxy_protocol_config: ProxyProtocolConfig,
+    metrics: Option<Arc<ProxyMetrics>>,
+    cancel: Option<CancellationToken>,
+}
+
+impl<H> ProxyServerBuilder<H> {
+    pub(super) fn new() -> Self {
+        Self {
+            connection_handler: None,
+            listener: None,
+            listen_address: None,
+            bind: None,
+            upstream: None,
+            upstream_address: None,
+            new_upstream: None,
+            security_context: None,
+            handshake_limiter: None,
+            proxy_protocol_config: Default::default(),
+            metrics: None,
+            cancel: None,
+        }
+    }
+}
+
+impl<H, U> ProxyServerBuilder<H, TcpSocketListener, U> {
+    /// Accept downstream connections on a TCP socket bound to `listen_address`
+    pub fn with_listen_address(mut self, listen_address: Address) -> Self {
+        self.listen_address = Some(listen_address);
+        self.bind = Some(|listen_socket| TcpSocketListener::bind(listen_socket).boxed());
+        self
+    }
+}
+
+impl<H, L> ProxyServerBuilder<H, L, TcpUpstream> {
+    /// Connect each session to the V1 server at `upstream_address` via TCP
+    pub fn with_upstream_address(mut self, upstream_address: Address) -> Self {
+        self.upstream_address = Some(upstream_address);
+        self.new_upstream = Some(TcpUpstream::new);
+        self
+    }
+}
+
+impl<H, L, U> ProxyServerBuilder<H, L, U> {
+    pub fn with_connection_handler(mut self, connection_handler: H) -> Self {
+        self.connection_handler = Some(connection_handler);
+        self
+    }
+
+    /// Accept downstream connections from an arbitrary `listener`
+    pub fn with_listener<L2>(self, listener: L2) -> ProxyServerBuilder<H, L2, U> {
+        ProxyServerBuilder {
+            connection_handler: self.connection_handler,
+            listener: Some(listener),
+            listen_address: self.listen_address,
+            bind: None,
+            upstream: self.upstream,
+            upstream_address: self.upstream_address,
+            new_upstream: self.new_upstream,
+            security_context: self.security_context,
+            handshake_limiter: self.handshake_limiter,
+            proxy_protocol_config: self.proxy_protocol_config,
+            metrics: self.metrics,
+            cancel: self.cancel,
+        }
+    }
+
+    /// Connect sessions via an arbitrary `upstream`
+    pub fn with_upstream<U2>(self, upstream: U2) -> ProxyServerBuilder<H, L, U2> {
+        ProxyServerBuilder {
+            connection_handler: self.connection_handler,
+            listener: self.listener,
+            listen_address: self.listen_address,
+            bind: self.bind,
+            upstream: Some(upstream),
+            upstream_address: self.upstream_address,
+            new_upstream: None,
+            security_context: self.security_context,
+            handshake_limiter: self.handshake_limiter,
+            proxy_protocol_config: self.proxy_protocol_config,
+            metrics: self.metrics,
+            cancel: self.cancel,
+        }
+    }
+
+    /// Noise is used for downstream connections only if the security context is present
+    pub fn with_security_context(mut self, security_context: Option<Arc<SecurityContext>>) -> Self {
+        self.security_context = security_context;
+        self
+    }
+
+    /// Limit number of concurrently running noise handshakes, requires a security context
+    pub fn with_handshake_limiter(mut self, handshake_limiter: Option<HandshakeLimiter>) -> Self {
+        self.handshake_limiter = handshake_limiter;
+        self
+    }
+
+    pub fn with_proxy_protocol_config(
+        mut self,
+        proxy_protocol_config: ProxyProtocolConfig,
+    ) -> Self {
+        self.proxy_protocol_config = proxy_protocol_config;
+        self
+    }
+
+    pub fn with_metrics(mut self, metrics: Option<Arc<ProxyMetrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// All sessions of the server are cancelled once `cancel` is cancelled, e.g. when the
+    /// embedding application shuts down
+    pub fn with_cancellation(mut self, cancel: CancellationToken) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+}
+
+impl<H, L, U> ProxyServerBuilder<H, L, U>
+where
+    L: Listener,
+    U: Upstream,
+    H: ConnectionHandler<L::Stream, U::Stream>,
+{
+    /// Checks the combination of parts and binds the listening socket (if a listen address has
+    /// been specified)
+    pub async fn build(self) -> Result<ProxyServer<H, L, U>> {
+        let connection_handler = self
+            .connection_handler
+            .ok_or(BuilderError::Missing("connection handler"))?;
+        let upstream = match (self.upstream, self.upstream_address, self.new_upstream) {
+            (Some(_), Some(_), _) => {
+                return Err(BuilderError::Conflict("upstream", "upstream address").into())
+            }
+            (Some(upstream), None, _) => upstream,
+            (None, Some(upstream_address), Some(new_upstream)) => new_upstream(upstream_address),
+            (None, _, _) => return Err(BuilderError::Missing("upstream").into()),
+        };
+        if self.handshake_limiter.is_some() && self.security_context.is_none() {
+            return Err(BuilderError::Requires("handshake limiter", "security context").into());
+        }
+        let listener = match (self.listener, self.listen_address, self.bind) {
+            (Some(_), Some(_), _) => {
+                return Err(BuilderError::Conflict("listener", "listen address").into())
+            }
+            (Some(listener), None, _) => listener,
+            (None, Some(listen_address), Some(bind)) => {
+                let listen_socket = listen_address
+                    .to_socket_addrs()
+                    .map_err(|e| Error::HostNameError(e.to_string()))?
+                    .next()
+                    .ok_or_else(|| Error::HostNameError("Failed to resolve listen_addr".into()))?;
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.account_tcp_listener_breakdown();
+                }
+                bind(listen_socket).await.map_err(Error::Io)?
+            }
+            (None, _, _) => return Err(BuilderError::Missing("listener").into()),
+        };
+
+        let mut server = ProxyServer::with_listener_and_upstream(
+            listener,
+            upstream,
+            connection_handler,
+            self.security_context,
+            self.proxy_protocol_config,
+            self.metrics,
+        )
+        .with_handshake_limiter(self.handshake_limiter);
+        if let Some(cancel) = self.cancel {
+            server.shutdown = cancel.child_token();
+        }
+        Ok(server)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::server::{listener::ChannelListener, upstream::ChannelUpstream, TranslationHandler};
+    use tokio::io::DuplexStream;
+
+    fn assert_builder_error<T>(result: Result<T>, expected: BuilderError) {
+        match result {
+            Err(Error::Builder(e)) => assert_eq!(e, expected),
+            Err(e) => panic!("Unexpected error: {}", e),
+            Ok(_) => panic!("BUG: invalid parts accepted"),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_invalid_combinations() {
+        let address = Address("127.0.0.1".to_owned(), 0);
+        let handler = TranslationHandler::new(None);
+
+        assert_builder_error(
+            ProxyServer::<TranslationHandler>::builder()
+                .with_listen_address(address.clone())
+                .with_upstream_address(address.clone())
+                .build()
+                .await,
+            BuilderError::Missing("connection handler"),
+        );
+        assert_builder_error(
+            ProxyServer::builder()
+                .with_connection_handler(handler.clone())
+                .with_listen_address(address.clone())
+                .build()
+                .await,
+            BuilderError::Missing("upstream"),
+        );
+        assert_builder_error(
+            ProxyServer::builder()
+                .with_connection_handler(handler.clone())
+                .with_upstream_address(address.clone())
+                .build()
+                .await,
+            BuilderError::Missing("listener"),
+        );
+        let (_connections, listener) = ChannelListener::<DuplexStream>::new(
+            "127.0.0.1:3336".parse().expect("BUG: Invalid address"),
+        );
+        assert_builder_error(
+            ProxyServer::builder()
+                .with_connection_handler(handler.clone())
+                .with_listen_address(address.clone())
+                .with_listener(listener)
+                .with_upstream_address(address.clone())
+                .build()
+                .await,
+            BuilderError::Conflict("listener", "listen address"),
+        );
+        let (upstream, _upstream_rx) =
+            ChannelUpstream::new("127.0.0.1:3333".parse().expect("BUG: Invalid address"));
+        assert_builder_error(
+            ProxyServer::builder()
+                .with_connection_handler(handler.clone())
+                .with_listen_address(address.clone())
+                .with_upstream_address(address.clone())
+                .with_upstream(upstream)
+                .build()
+                .await,
+            BuilderError::Conflict("upstream", "upstream address"),
+        );
+        assert_builder_error(
+            ProxyServer::builder()
+                .with_connection_handler(handler)
+                .with_listen_address(address.clone())
+                .with_upstream_address(address)
+                .with_handshake_limiter(Some(HandshakeLimiter::new(1)))
+                .build()
+                .await,
+            BuilderError::Requires("handshake limiter", "security context"),
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build() {
+        let (_connections, listener) = ChannelListener::<DuplexStream>::new(
+            "127.0.0.1:3336".parse().expect("BUG: Invalid address"),
+        );
+        let (upstream, _upstream_rx) =
+            ChannelUpstream::new("127.0.0.1:3333".parse().expect("BUG: Invalid address"));
+        let cancel = CancellationToken::new();
+        let server = ProxyServer::builder()
+            .with_connection_handler(TranslationHandler::new(None))
+            .with_listener(listener)
+            .with_upstream(upstream)
+            .with_cancellation(cancel.clone())
+            .build()
+            .await
+            .expect("BUG: Cannot build server");
+        assert_eq!(
+            server
+                .listener
+                .local_addr()
+                .expect("BUG: Cannot get listener address"),
+            "127.0.0.1:3336".parse().expect("BUG: Invalid address")
+        );
+        cancel.cancel();
+        assert!(server.shutdown.is_cancelled());
+
+        let server = ProxyServer::builder()
+            .with_connection_handler(TranslationHandler::new(None))
+            .with_listen_address(Address("127.0.0.1".to_owned(), 0))
+            .with_upstream_address(Address("127.0.0.1".to_owned(), 3333))
+            .build()
+            .await
+            .expect("BUG: Cannot build server");
+        assert_ne!(
+            server
+                .listener
+                .local_addr()
+                .expect("BUG: Cannot get listener address")
+                .port(),
+            0
+        );
+    }
+}
@@ -23,6 +23,8 @@
 //! This module implements server controller that controls the way new connections are accepted,
 //! way server is terminated and logging.
 
+use std::collections::HashMap;
+use std::net::SocketAddr;
 use std::sync::{
     atomic::{AtomicUsize, Ordering::Relaxed},
     Arc, Mutex,
@@ -31,14 +33,24 @@ use std::task::{Context, Poll, Waker};
 
 use ii_async_utils::FutureExt;
 use ii_logging::macros::*;
-use ii_logging::FlushGuard;
-use tokio::sync::{mpsc, Notify};
-use tokio::time::Duration;
+use ii_logging::{FlushGuard, Level, LogLevels, LoggingConfig, LOGGER};
+use ii_noise_proxy::SecurityContext;
+use ii_stratum::v2;
+use serde::Deserialize;
+use tokio::sync::{mpsc, oneshot, Notify};
+use tokio::time::{Duration, Instant};
+
+use super::cpu_time::CpuTimeCounter;
+use super::peer_address::DownstreamPeer;
+use super::summary::SessionStats;
+use crate::error::{Error, Result};
 
 #[derive(Default)]
 pub struct ClientCounter {
     client_counter: Arc<AtomicUsize>,
     waker: Arc<Mutex<Option<Waker>>>,
+    /// Notified whenever a client disconnects
+    slot_released: Arc<Notify>,
 }
 
 impl Clone for ClientCounter {
@@ -48,6 +60,7 @@ impl Clone for ClientCounter {
         Self {
             client_counter,
             waker: self.waker.clone(),
+            slot_released: self.slot_released.clone(),
         }
     }
 }
@@ -69,6 +82,11 @@ impl std::future::Future for ClientCounter {
 }
 
 impl ClientCounter {
+    /// Number of currently connected clients
+    pub fn count(&self) -> usize {
+        self.client_counter.load(Relaxed)
+    }
+
     /// Decreases internal client counter and wakes its future if it hits 0
     pub fn decrease(&mut self) {
         assert_ne!(
@@ -76,6 +94,7 @@ impl ClientCounter {
             0,
             "BUG: Client counter underflow"
         );
+        self.slot_released.notify_one();
         // If this is the last client, wake its future
         if self.client_counter.load(Relaxed) == 0 {
             let waker_opt = self
@@ -104,6 +123,26 @@ impl Default for TerminationMethod {
     }
 }
 
+/// Action taken when the number of connected clients reaches the limit
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConnectionLimitAction {
+    /// Stop accepting new connections until some of the existing sessions terminate
+    #[default]
+    Pause,
+    /// Keep accepting new connections but close them immediately
+    Reject,
+}
+
+/// Limits the number of concurrently connected clients to protect the host from file descriptor
+/// and memory exhaustion
+#[derive(Copy, Clone, Debug, Deserialize)]
+pub struct ConnectionLimit {
+    pub max_connections: usize,
+    #[serde(default)]
+    pub action: ConnectionLimitAction,
+}
+
 /// Structure that tracks amount of clients connected to the server.
 ///
 /// If the server is about to be shut down, it allows to wait some time:
@@ -115,9 +154,29 @@ pub struct Controller {
     client_counter: ClientCounter,
     termination_method: TerminationMethod,
     termination_notifier: Arc<Notify>,
+    connection_limit: Option<ConnectionLimit>,
+    session_registry: SessionRegistry,
 }
 
 impl Controller {
+    pub fn set_connection_limit(&mut self, connection_limit: Option<ConnectionLimit>) {
+        self.connection_limit = connection_limit;
+    }
+
+    /// Returns the action to be taken if the connection limit has been reached
+    pub fn connection_limit_reached(&self) -> Option<ConnectionLimitAction> {
+        self.connection_limit
+            .filter(|limit| self.client_counter.count() >= limit.max_connections)
+            .map(|limit| limit.action)
+    }
+
+    /// Completes once the number of connected clients drops below the connection limit
+    pub async fn wait_for_free_slot(&self) {
+        while self.connection_limit_reached().is_some() {
+            self.client_counter.slot_released.notified().await;
+        }
+    }
+
     pub async fn wait_for_termination(self, timeout: Option<Duration>) {
         use TerminationMethod::*;
         match self.termination_method {
@@ -152,6 +211,278 @@ impl Controller {
     pub fn counter_for_new_client(&self) -> ClientCounter {
         self.client_counter.clone()
     }
+
+    pub fn session_registry(&self) -> &SessionRegistry {
+        &self.session_registry
+    }
+}
+
+/// Basic information about a downstream session handled by the server
+#[derive(Clone, Debug)]
+pub struct SessionInfo {
+    pub downstream_peer: DownstreamPeer,
+    /// Local address of the downstream connection
+    pub local_addr: SocketAddr,
+    /// Time stamp when the downstream connection has been accepted
+    pub established: Instant,
+    /// CPU time consumed by the session so far, present only if CPU accounting is enabled
+    pub cpu_time: Option<CpuTimeCounter>,
+    /// Delivers commands to the running session
+    pub command_tx: mpsc::UnboundedSender<SessionCommand>,
+    /// Name of the upstream the session is connected to, unknown until the upstream connection
+    /// is established
+    pub upstream_name: Option<String>,
+    /// Statistics of the session including the device announced by the downstream
+    pub stats: SessionStats,
+}
+
+impl SessionInfo {
+    /// Passes `command` to the session, returns false if the session has already ended
+    pub fn send_command(&self, command: SessionCommand) -> bool {
+        self.command_tx.send(command).is_ok()
+    }
+
+    /// Asks the session to send `reconnect` to its downstream device, returns false if the
+    /// session has already ended
+    pub fn request_reconnect(&self, reconnect: v2::messages::Reconnect) -> bool {
+        let requested = self.send_command(SessionCommand::Reconnect(reconnect));
+        if requested {
+            self.stats.set_reconnect_requested();
+        }
+        requested
+    }
+
+    /// The session can be asked to reconnect: it hasn't been asked yet and its downstream
+    /// device has already set up the connection (the device is announced in `SetupConnection`)
+    fn reconnectable(&self) -> bool {
+        !self.stats.reconnect_requested() && self.stats.device().is_some()
+    }
+}
+
+/// Commands that are executed by an individual running session
+#[derive(Clone, Debug, PartialEq)]
+pub enum SessionCommand {
+    /// Send `Reconnect` to the downstream device, e.g. to migrate it to another proxy instance
+    Reconnect(v2::messages::Reconnect),
+}
+
+pub type SessionCommandReceiver = mpsc::UnboundedReceiver<SessionCommand>;
+
+/// Registry of sessions that are currently being handled by the server
+#[derive(Clone, Default)]
+pub struct SessionRegistry {
+    next_session_id: Arc<AtomicUsize>,
+    sessions: Arc<Mutex<HashMap<usize, SessionInfo>>>,
+}
+
+impl SessionRegistry {
+    /// Registers a new session, the session stays registered as long as the returned entry
+    /// is alive
+    pub fn register(&self, session_info: SessionInfo) -> SessionEntry {
+        let id = self.next_session_id.fetch_add(1, Relaxed);
+        self.sessions
+            .lock()
+            .expect("BUG: Poisoned session registry")
+            .insert(id, session_info);
+        SessionEntry {
+            id,
+            registry: self.clone(),
+        }
+    }
+
+    /// Snapshot of all registered sessions sorted from the oldest one
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        let mut sessions: Vec<_> = self
+            .sessions
+            .lock()
+            .expect("BUG: Poisoned session registry")
+            .values()
+            .cloned()
+            .collect();
+        sessions.sort_by_key(|session| session.established);
+        sessions
+    }
+}
+
+/// Session registration that is removed from the registry when dropped
+pub struct SessionEntry {
+    id: usize,
+    registry: SessionRegistry,
+}
+
+impl SessionEntry {
+    fn update<F>(&self, update: F)
+    where
+        F: FnOnce(&mut SessionInfo),
+    {
+        if let Some(session_info) = self
+            .registry
+            .sessions
+            .lock()
+            .expect("BUG: Poisoned session registry")
+            .get_mut(&self.id)
+        {
+            update(session_info);
+        }
+    }
+
+    /// Updates peer information once more details are known (e.g. from PROXY protocol)
+    pub fn set_downstream_peer(&self, downstream_peer: DownstreamPeer) {
+        self.update(|session_info| session_info.downstream_peer = downstream_peer);
+    }
+
+    /// Records the upstream that the session has connected to
+    pub fn set_upstream_name(&self, upstream_name: String) {
+        self.update(|session_info| session_info.upstream_name = Some(upstream_name));
+    }
+}
+
+impl Drop for SessionEntry {
+    fn drop(&mut self) {
+        self.registry
+            .sessions
+            .lock()
+            .expect("BUG: Poisoned session registry")
+            .remove(&self.id);
+    }
+}
+
+/// Commands that change behavior of a running server
+pub enum ServerCommand {
+    /// Security context to be used for all new downstream connections
+    SetSecurityContext(Option<Arc<SecurityContext>>),
+    SetConnectionLimit(Option<ConnectionLimit>),
+    /// Stop or resume routing of new sessions to an upstream, the server replies whether the
+    /// upstream is known
+    SetUpstreamDraining {
+        upstream_name: String,
+        draining: bool,
+        reply_tx: oneshot::Sender<bool>,
+    },
+    /// Stop accepting new connections and terminate once all sessions are closed
+    Drain,
+    /// Terminate immediately
+    Quit,
+}
+
+/// Handle for controlling a running server, e.g. from a control socket or from an embedding
+/// application
+#[derive(Clone)]
+pub struct ServerHandle {
+    command_tx: mpsc::UnboundedSender<ServerCommand>,
+    session_registry: SessionRegistry,
+}
+
+impl ServerHandle {
+    pub fn new(
+        command_tx: mpsc::UnboundedSender<ServerCommand>,
+        session_registry: SessionRegistry,
+    ) -> Self {
+        Self {
+            command_tx,
+            session_registry,
+        }
+    }
+
+    fn send_command(&self, command: ServerCommand) -> Result<()> {
+        self.command_tx
+            .send(command)
+            .map_err(|_| Error::General("Server is not running".to_string()))
+    }
+
+    pub fn set_security_context(
+        &self,
+        security_context: Option<Arc<SecurityContext>>,
+    ) -> Result<()> {
+        self.send_command(ServerCommand::SetSecurityContext(security_context))
+    }
+
+    pub fn set_connection_limit(&self, connection_limit: Option<ConnectionLimit>) -> Result<()> {
+        self.send_command(ServerCommand::SetConnectionLimit(connection_limit))
+    }
+
+    pub fn drain(&self) -> Result<()> {
+        self.send_command(ServerCommand::Drain)
+    }
+
+    pub fn quit(&self) -> Result<()> {
+        self.send_command(ServerCommand::Quit)
+    }
+
+    pub fn sessions(&self) -> Vec<SessionInfo> {
+        self.session_registry.sessions()
+    }
+
+    /// Asks `percentage` of the connected sessions to send `reconnect` to their downstream
+    /// devices. Sessions are picked from the oldest one, sessions that are still being set up
+    /// or that have already been asked to reconnect are skipped. Returns the number of sessions
+    /// that have been asked.
+    pub fn reconnect(&self, reconnect: v2::messages::Reconnect, percentage: u8) -> usize {
+        let sessions: Vec<_> = self
+            .sessions()
+            .into_iter()
+            .filter(SessionInfo::reconnectable)
+            .collect();
+        let count = (sessions.len() as f64 * f64::from(percentage.min(100)) / 100.0).ceil();
+        sessions
+            .iter()
+            .take(count as usize)
+            .filter(|session| session.request_reconnect(reconnect.clone()))
+            .count()
+    }
+
+    /// Stops routing new sessions to upstream `upstream_name` and asks all its sessions to
+    /// reconnect their downstream devices. The devices reconnect to this proxy (V2 `Reconnect`
+    /// with empty host and zero port) that routes them to the remaining upstreams. Returns the
+    /// number of sessions that have been asked to reconnect.
+    pub async fn drain_upstream(&self, upstream_name: &str) -> Result<usize> {
+        self.set_upstream_draining(upstream_name, true).await?;
+        let reconnect = v2::messages::Reconnect {
+            new_host: Default::default(),
+            new_port: 0,
+        };
+        Ok(self
+            .sessions()
+            .iter()
+            .filter(|session| session.upstream_name.as_deref() == Some(upstream_name))
+            .filter(|session| session.request_reconnect(reconnect.clone()))
+            .count())
+    }
+
+    /// Routes new sessions to upstream `upstream_name` again
+    pub async fn resume_upstream(&self, upstream_name: &str) -> Result<()> {
+        self.set_upstream_draining(upstream_name, false).await
+    }
+
+    async fn set_upstream_draining(&self, upstream_name: &str, draining: bool) -> Result<()> {
+        let (reply_tx, reply_rx) = oneshot::channel();
+        self.send_command(ServerCommand::SetUpstreamDraining {
+            upstream_name: upstream_name.to_string(),
+            draining,
+            reply_tx,
+        })?;
+        match reply_rx.await {
+            Ok(true) => Ok(()),
+            Ok(false) => Err(Error::General(format!(
+                "Unknown upstream: {}",
+                upstream_name
+            ))),
+            Err(_) => Err(Error::General("Server is not running".to_string())),
+        }
+    }
+}
+
+/// Changes of logging of a running proxy
+#[derive(Debug, Clone, PartialEq)]
+pub enum LoggingCommand {
+    /// Replace all filters with `RUST_LOG` style filters
+    SetFilters(String),
+    /// Change the default level, levels of individual modules are kept
+    SetLevel(Level),
+    /// Change the level of a module, `None` makes the module use the default level again
+    SetModuleLevel(String, Option<Level>),
+    /// Switch to debug level or back to the levels that have been used before
+    ToggleDebug,
 }
 
 pub struct LoggingController {
@@ -161,24 +492,47 @@ pub struct LoggingController {
 impl LoggingController {
     pub const ASYNC_LOGGER_DRAIN_CHANNEL_SIZE: usize = 4096;
 
-    pub fn new(filter_receiver: Option<mpsc::Receiver<String>>) -> Self {
-        Self::with_drain_channel_size(filter_receiver, Self::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE)
+    pub fn new(command_receiver: Option<mpsc::Receiver<LoggingCommand>>) -> Self {
+        Self::with_drain_channel_size(command_receiver, Self::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE)
     }
 
     pub fn with_drain_channel_size(
-        filter_receiver: Option<mpsc::Receiver<String>>,
+        command_receiver: Option<mpsc::Receiver<LoggingCommand>>,
         drain_channel_size: usize,
     ) -> Self {
-        let flush_guard = Arc::new(Mutex::new(ii_logging::setup_for_app(drain_channel_size)));
-        if let Some(mut filter_rx) = filter_receiver {
+        Self::with_config(command_receiver, LoggingConfig::for_app(drain_channel_size))
+    }
+
+    /// Sets up global logging with `config`, e.g. with a JSON output
+    pub fn with_config(
+        command_receiver: Option<mpsc::Receiver<LoggingCommand>>,
+        config: LoggingConfig,
+    ) -> Self {
+        let flush_guard = Arc::new(Mutex::new(ii_logging::setup(config)));
+        if let Some(mut command_rx) = command_receiver {
             let flush_guard_clone = flush_guard.clone();
             tokio::spawn(async move {
-                while let Some(filter) = filter_rx.recv().await {
-                    warn!("Changing logging filter to: {}", filter);
+                // Levels to be restored when debug logging is toggled off
+                let mut levels_before_debug = None;
+                while let Some(command) = command_rx.recv().await {
+                    warn!("Changing logging: {:?}", command);
+                    let new_flush_guard = match command {
+                        LoggingCommand::SetFilters(filters) => LOGGER.set_filters(filters),
+                        LoggingCommand::SetLevel(level) => LOGGER.set_level(level),
+                        LoggingCommand::SetModuleLevel(module, level) => {
+                            LOGGER.set_module_level(&module, level)
+                        }
+                        LoggingCommand::ToggleDebug => match levels_before_debug.take() {
+                            Some(levels) => LOGGER.set_levels(levels),
+                            None => {
+                                levels_before_debug = Some(LOGGER.levels());
+                                LOGGER.set_levels(LogLevels::new(Level::Debug))
+                            }
+                        },
+                    };
                     *flush_guard_clone
                         .lock()
-                        .expect("BUG: Poisoned logging guard") =
-                        ii_logging::LOGGER.set_filters(filter);
+                        .expect("BUG: Poisoned logging guard") = new_flush_guard;
                 }
             });
         }
@@ -188,3 +542,89 @@ impl LoggingController {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ii_stratum::test_utils;
+    use std::convert::TryInto;
+
+    #[test]
+    fn test_reconnect_percentage() {
+        let (command_tx, _command_rx) = mpsc::unbounded_channel();
+        let handle = ServerHandle::new(command_tx, SessionRegistry::default());
+        let mut session_rxs = vec![];
+        let start = Instant::now();
+        let _entries: Vec<_> = (0..4)
+            .map(|port| {
+                let (command_tx, command_rx) = mpsc::unbounded_channel();
+                session_rxs.push(command_rx);
+                let peer_addr = SocketAddr::from(([1, 2, 3, 4], port));
+                let stats = SessionStats::default();
+                // The newest session hasn't set up its connection yet
+                if port < 3 {
+                    stats.set_device(test_utils::v2::build_setup_connection().device);
+                }
+                handle.session_registry.register(SessionInfo {
+                    downstream_peer: DownstreamPeer::new(peer_addr),
+                    local_addr: peer_addr,
+                    established: start + Duration::from_secs(port.into()),
+                    cpu_time: None,
+                    command_tx,
+                    upstream_name: None,
+                    stats,
+                })
+            })
+            .collect();
+        let reconnect = v2::messages::Reconnect {
+            new_host: "proxy2".try_into().expect("BUG: invalid host"),
+            new_port: 3336,
+        };
+        let mut received = || -> Vec<bool> {
+            session_rxs
+                .iter_mut()
+                .map(|rx| futures::FutureExt::now_or_never(rx.recv()))
+                .map(|command| command == Some(Some(SessionCommand::Reconnect(reconnect.clone()))))
+                .collect()
+        };
+
+        // Rounded up to a whole session
+        assert_eq!(handle.reconnect(reconnect.clone(), 50), 2);
+        assert_eq!(received(), vec![true, true, false, false]);
+        // Sessions that have already been asked are skipped
+        assert_eq!(handle.reconnect(reconnect.clone(), 100), 1);
+        assert_eq!(received(), vec![false, false, true, false]);
+        assert_eq!(handle.reconnect(reconnect.clone(), 100), 0);
+        assert_eq!(handle.reconnect(reconnect, 0), 0);
+    }
+
+    #[tokio::test]
+    async fn test_connection_limit() {
+        let mut controller = Controller::default();
+        assert_eq!(controller.connection_limit_reached(), None);
+        controller.set_connection_limit(Some(ConnectionLimit {
+            max_connections: 2,
+            action: ConnectionLimitAction::Pause,
+        }));
+
+        let mut first_client = controller.counter_for_new_client();
+        let _second_client = controller.counter_for_new_client();
+        assert_eq!(
+            controller.connection_limit_reached(),
+            Some(ConnectionLimitAction::Pause)
+        );
+        controller
+            .wait_for_free_slot()
+            .timeout(Duration::from_millis(10))
+            .await
+            .expect_err("BUG: free slot reported while limit is reached");
+
+        first_client.decrease();
+        assert_eq!(controller.connection_limit_reached(), None);
+        controller
+            .wait_for_free_slot()
+            .timeout(Duration::from_millis(10))
+            .await
+            .expect("BUG: free slot not reported");
+    }
+}
@@ -0,0 +1,132 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Accounting of CPU time consumed by individual sessions. The CPU clock of the worker thread is
+//! sampled around every poll of the session task and of the tasks that send out frames of the
+//! session, the difference is attributed to the session.
+
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Duration;
+
+use futures::future::Either;
+
+fn clock_time(clock_id: libc::clockid_t) -> Duration {
+    let mut time = libc::timespec {
+        tv_sec: 0,
+        tv_nsec: 0,
+    };
+    // The call can fail only for an invalid clock or pointer
+    let result = unsafe { libc::clock_gettime(clock_id, &mut time) };
+    assert_eq!(result, 0, "BUG: Cannot read CPU clock {}", clock_id);
+    Duration::new(time.tv_sec as u64, time.tv_nsec as u32)
+}
+
+/// CPU time consumed by the calling thread so far
+pub fn thread_cpu_time() -> Duration {
+    clock_time(libc::CLOCK_THREAD_CPUTIME_ID)
+}
+
+/// CPU time consumed by the whole process so far (all threads)
+pub fn process_cpu_time() -> Duration {
+    clock_time(libc::CLOCK_PROCESS_CPUTIME_ID)
+}
+
+/// CPU time attributed to a single session. Clones share the counter so that the time can be
+/// read while the session is still running.
+#[derive(Clone, Debug, Default)]
+pub struct CpuTimeCounter {
+    nanos: Arc<AtomicU64>,
+}
+
+impl CpuTimeCounter {
+    pub fn add(&self, duration: Duration) {
+        self.nanos.fetch_add(duration.as_nanos() as u64, Relaxed);
+    }
+
+    pub fn cpu_time(&self) -> Duration {
+        Duration::from_nanos(self.nanos.load(Relaxed))
+    }
+
+    /// Accounts CPU time spent polling `future` to this counter. Work that `future` hands over
+    /// to other tasks is not accounted, such tasks have to be instrumented separately.
+    pub fn instrument<F: Future>(&self, future: F) -> impl Future<Output = F::Output> {
+        let counter = self.clone();
+        let mut future = Box::pin(future);
+        futures::future::poll_fn(move |cx| {
+            let start = thread_cpu_time();
+            let poll = future.as_mut().poll(cx);
+            counter.add(thread_cpu_time().saturating_sub(start));
+            poll
+        })
+    }
+}
+
+/// Instruments `future` if there is a `counter`, i.e. CPU accounting is enabled
+pub fn instrument<F: Future>(
+    counter: Option<&CpuTimeCounter>,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    match counter {
+        Some(counter) => Either::Left(counter.instrument(future)),
+        None => Either::Right(future),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn burn_cpu(duration: Duration) {
+        let start = thread_cpu_time();
+        while thread_cpu_time() - start < duration {
+            std::hint::spin_loop();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_instrumented_future() {
+        let counter = CpuTimeCounter::default();
+        counter
+            .instrument(async {
+                burn_cpu(Duration::from_millis(20));
+                tokio::time::sleep(Duration::from_millis(1)).await;
+                burn_cpu(Duration::from_millis(20));
+            })
+            .await;
+        let cpu_time = counter.cpu_time();
+        assert!(
+            cpu_time >= Duration::from_millis(40),
+            "BUG: CPU time not accounted: {:?}",
+            cpu_time
+        );
+        assert!(process_cpu_time() >= cpu_time);
+
+        // Idle waiting consumes no CPU time
+        let idle_counter = CpuTimeCounter::default();
+        idle_counter
+            .instrument(tokio::time::sleep(Duration::from_millis(50)))
+            .await;
+        assert!(idle_counter.cpu_time() < Duration::from_millis(20));
+    }
+}
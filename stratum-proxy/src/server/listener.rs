@@ -0,0 +1,163 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Abstraction of the source of incoming downstream connections. The proxy server accepts
+//! connections via the `Listener` trait so that embedders can provide streams other than plain
+//! TCP (TLS, Unix sockets, WebSockets) and tests can inject in-memory streams.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+
+use async_trait::async_trait;
+use futures::channel::mpsc;
+use futures::StreamExt;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Newly accepted downstream connection
+#[derive(Debug)]
+pub struct IncomingConnection<S> {
+    pub stream: S,
+    /// Address of the directly connected peer
+    pub peer_addr: SocketAddr,
+    /// Local address where the connection has been accepted. It is used when the upstream
+    /// connection is to carry PROXY protocol information but the downstream didn't provide any
+    pub local_addr: SocketAddr,
+}
+
+/// Source of downstream connections for `ProxyServer`
+#[async_trait]
+pub trait Listener: Send + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug + 'static;
+
+    /// Waits for the next incoming connection
+    async fn accept(&mut self) -> io::Result<IncomingConnection<Self::Stream>>;
+
+    /// Address the listener accepts connections on (not all listeners have one)
+    fn local_addr(&self) -> io::Result<SocketAddr>;
+
+    /// Stops accepting new connections, existing connections aren't affected
+    fn close(&mut self) {}
+
+    /// Attempts to recover from repeated accept failures, e.g. by binding a new socket. It is
+    /// always called after `close()`
+    async fn rebind(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Default listener that accepts plain TCP connections on a given socket address
+#[derive(Debug)]
+pub struct TcpSocketListener {
+    listen_socket: SocketAddr,
+    listener: Option<TcpListener>,
+}
+
+impl TcpSocketListener {
+    pub async fn bind(listen_socket: SocketAddr) -> io::Result<Self> {
+        let listener = TcpListener::bind(listen_socket).await?;
+        Ok(Self {
+            listen_socket,
+            listener: Some(listener),
+        })
+    }
+}
+
+#[async_trait]
+impl Listener for TcpSocketListener {
+    type Stream = TcpStream;
+
+    async fn accept(&mut self) -> io::Result<IncomingConnection<Self::Stream>> {
+        let listener = self.listener.as_ref().ok_or_else(|| {
+            io::Error::new(io::ErrorKind::NotConnected, "TCP listener has been closed")
+        })?;
+        let (stream, peer_addr) = listener.accept().await?;
+        let local_addr = stream.local_addr()?;
+        Ok(IncomingConnection {
+            stream,
+            peer_addr,
+            local_addr,
+        })
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        match self.listener.as_ref() {
+            Some(listener) => listener.local_addr(),
+            None => Ok(self.listen_socket),
+        }
+    }
+
+    fn close(&mut self) {
+        self.listener = None;
+    }
+
+    async fn rebind(&mut self) -> io::Result<()> {
+        self.listener = Some(TcpListener::bind(self.listen_socket).await?);
+        Ok(())
+    }
+}
+
+/// Listener that yields connections pushed through a channel. It is useful for feeding the
+/// server with in-memory streams (e.g. `tokio::io::duplex`) in tests or with connections
+/// accepted by the embedding application itself.
+#[derive(Debug)]
+pub struct ChannelListener<S> {
+    local_addr: SocketAddr,
+    incoming: mpsc::UnboundedReceiver<IncomingConnection<S>>,
+}
+
+impl<S> ChannelListener<S> {
+    /// Builds the listener along with the sender half that is used for pushing connections
+    /// into the listener. `local_addr` is reported as the local address of the listener.
+    pub fn new(local_addr: SocketAddr) -> (mpsc::UnboundedSender<IncomingConnection<S>>, Self) {
+        let (tx, incoming) = mpsc::unbounded();
+        (
+            tx,
+            Self {
+                local_addr,
+                incoming,
+            },
+        )
+    }
+}
+
+#[async_trait]
+impl<S> Listener for ChannelListener<S>
+where
+    S: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug + 'static,
+{
+    type Stream = S;
+
+    async fn accept(&mut self) -> io::Result<IncomingConnection<Self::Stream>> {
+        match self.incoming.next().await {
+            Some(connection) => Ok(connection),
+            // There is no way to recover from closed channel, keep pending forever so that the
+            // server can only be terminated by regular means
+            None => futures::future::pending().await,
+        }
+    }
+
+    fn local_addr(&self) -> io::Result<SocketAddr> {
+        Ok(self.local_addr)
+    }
+}
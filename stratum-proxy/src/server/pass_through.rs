@@ -0,0 +1,153 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Pass-through of downstream extension frames that the proxy doesn't handle itself to a V2
+//! endpoint
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+use tokio::time::{Duration, Instant};
+
+use ii_logging::macros::*;
+use ii_stratum::v2;
+use ii_wire::Address;
+
+use crate::metrics::ProxyMetrics;
+
+/// Forwards extension frames of each session over a dedicated connection to the V2 endpoint at
+/// `address`, the endpoint can thus tell the sessions apart. Frames that cannot be delivered are
+/// dropped.
+#[derive(Clone, Debug)]
+pub struct ExtensionPassThrough {
+    address: Address,
+}
+
+impl ExtensionPassThrough {
+    /// Frames queued for a single session, further frames are dropped until the queue drains
+    const QUEUE_LEN: usize = 64;
+    /// Delay before connecting again after the first failure, it doubles with each failure
+    const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+    const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+    pub fn new(address: Address) -> Self {
+        Self { address }
+    }
+
+    /// Spawns the forwarder of a new session and returns the extension handler that feeds it.
+    /// The forwarder terminates once the handler is dropped.
+    pub fn start_session(
+        &self,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) -> Arc<dyn v2::extensions::ExtensionHandler> {
+        let (frame_tx, frame_rx) = mpsc::channel(Self::QUEUE_LEN);
+        tokio::spawn(Self::forward(
+            self.address.clone(),
+            frame_rx,
+            metrics.clone(),
+        ));
+        Arc::new(SessionPassThrough { frame_tx, metrics })
+    }
+
+    /// The connection is established with the first frame and again after a failure, but not
+    /// sooner than the backoff since the last failed attempt elapses
+    async fn forward(
+        address: Address,
+        mut frame_rx: mpsc::Receiver<v2::Frame>,
+        metrics: Option<Arc<ProxyMetrics>>,
+    ) {
+        let account_dropped = || {
+            if let Some(metrics) = metrics.as_ref() {
+                metrics.account_extension_frame_dropped();
+            }
+        };
+        let mut conn: Option<v2::Framed<TcpStream>> = None;
+        let mut backoff = Self::INITIAL_BACKOFF;
+        let mut retry_at: Option<Instant> = None;
+        while let Some(frame) = frame_rx.recv().await {
+            if conn.is_none() {
+                if matches!(retry_at, Some(retry_at) if Instant::now() < retry_at) {
+                    account_dropped();
+                    continue;
+                }
+                match address.connect().await {
+                    Ok(stream) => {
+                        conn = Some(v2::Framed::new(stream, Default::default()));
+                        backoff = Self::INITIAL_BACKOFF;
+                        retry_at = None;
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Dropping extension frames, cannot connect to {} (next attempt in \
+                             {:?}): {}",
+                            address, backoff, e
+                        );
+                        retry_at = Some(Instant::now() + backoff);
+                        backoff = (backoff * 2).min(Self::MAX_BACKOFF);
+                        account_dropped();
+                        continue;
+                    }
+                }
+            }
+            let conn_ref = conn.as_mut().expect("BUG: extension connection missing");
+            if let Err(e) = conn_ref.send(frame).await {
+                warn!(
+                    "Dropping extension frame, cannot send it to {}: {}",
+                    address, e
+                );
+                conn = None;
+                account_dropped();
+            }
+        }
+    }
+}
+
+/// Extension handler that queues the frames of a session for its forwarder
+struct SessionPassThrough {
+    frame_tx: mpsc::Sender<v2::Frame>,
+    metrics: Option<Arc<ProxyMetrics>>,
+}
+
+#[async_trait]
+impl v2::extensions::ExtensionHandler for SessionPassThrough {
+    async fn handle_extension_frame(
+        &self,
+        frame: v2::framing::Frame,
+    ) -> ii_stratum::error::Result<()> {
+        match self.frame_tx.try_send(frame) {
+            Ok(()) => Ok(()),
+            // The downstream must not be able to exhaust memory of the proxy
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.account_extension_frame_dropped();
+                }
+                Ok(())
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => {
+                Err("Extension frame forwarder has terminated".into())
+            }
+        }
+    }
+}
@@ -23,7 +23,10 @@
 //! Module contains primitives for deeper peer information tracking
 
 use ii_wire::proxy::ProxyInfo;
-use std::{fmt, net::SocketAddr};
+use std::{
+    fmt,
+    net::{IpAddr, SocketAddr},
+};
 
 /// Downstream peer representation as a direct peer address with optional original peer address
 /// known, for example from PROXY protocol.
@@ -45,6 +48,15 @@ impl DownstreamPeer {
     pub fn set_proxy_info(&mut self, proxy_info: ProxyInfo) {
         self.proxy_info = proxy_info;
     }
+
+    /// Address of the miner itself, i.e. the original source when connected via PROXY protocol
+    pub fn source_addr(&self) -> SocketAddr {
+        self.proxy_info.original_source.unwrap_or(self.direct_peer)
+    }
+
+    pub fn source_ip(&self) -> IpAddr {
+        self.source_addr().ip()
+    }
 }
 
 impl fmt::Display for DownstreamPeer {
@@ -83,4 +95,16 @@ mod tests {
             String::from("5.4.3.2:5432(ProxyInfo[SRC:4.5.6.7:4567, DST:1.2.3.4:1234])")
         );
     }
+
+    #[test]
+    fn downstream_peer_source_ip() {
+        let src = SocketAddr::new(IpAddr::from([4, 5, 6, 7]), 4567);
+        let dst = SocketAddr::new(IpAddr::from([1, 2, 3, 4]), 1234);
+        let mut peer = DownstreamPeer::new(SocketAddr::new(IpAddr::from([5, 4, 3, 2]), 5432));
+        assert_eq!(peer.source_ip(), IpAddr::from([5, 4, 3, 2]));
+        peer.set_proxy_info(
+            ProxyInfo::try_from((Some(src), Some(dst))).expect("BUG: cannot produce proxy info"),
+        );
+        assert_eq!(peer.source_ip(), IpAddr::from([4, 5, 6, 7]));
+    }
 }
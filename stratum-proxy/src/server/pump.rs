@@ -0,0 +1,330 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Sending of frames that the translation produces for either side of a session
+
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+
+use futures::prelude::*;
+use tokio::time::{self, Duration, Instant};
+
+use ii_async_utils::{CancellationToken, WriteCoalescing};
+use ii_logging::macros::*;
+use ii_stratum::error::{Direction, ErrorContext};
+
+use super::DownstreamPeer;
+use crate::error::{DownstreamError, Error, Result, UpstreamError};
+use crate::metrics::ProxyMetrics;
+
+/// Pumps frames from a translation stream into a connection until the session is cancelled.
+/// Frames that are queued at the same time are written at once as specified by the write
+/// coalescing policy. The pump also finishes cleanly once the stream runs out of frames, i.e.
+/// when the translation has closed the queue.
+pub struct FramePump<St: Stream, Si> {
+    frames: St,
+    sink: Si,
+    /// Direction and peer of the connection, all errors are annotated with it
+    context: ErrorContext,
+    /// Downstream peer of the session, used for logging
+    session_peer: DownstreamPeer,
+    cancel: CancellationToken,
+    write_coalescing: WriteCoalescing,
+    /// Each batch of frames has to be written within this period
+    write_timeout: Option<Duration>,
+    metrics: Option<Arc<ProxyMetrics>>,
+    /// Latency of jobs whose frames are recognized by `is_job`
+    job_latency: Option<JobLatency>,
+    is_job: fn(&St::Item) -> bool,
+}
+
+impl<St, Si> FramePump<St, Si>
+where
+    St: Stream + Unpin,
+    St::Item: fmt::Debug,
+    Si: Sink<St::Item> + Unpin,
+    Error: From<Si::Error>,
+{
+    pub fn new(
+        frames: St,
+        sink: Si,
+        context: ErrorContext,
+        session_peer: DownstreamPeer,
+        cancel: CancellationToken,
+    ) -> Self {
+        Self {
+            frames,
+            sink,
+            context,
+            session_peer,
+            cancel,
+            write_coalescing: WriteCoalescing::disabled(),
+            write_timeout: None,
+            metrics: None,
+            job_latency: None,
+            is_job: |_| false,
+        }
+    }
+
+    pub fn with_write_coalescing(mut self, write_coalescing: WriteCoalescing) -> Self {
+        self.write_coalescing = write_coalescing;
+        self
+    }
+
+    /// Fail when the connection doesn't accept a batch of frames within `write_timeout`, the
+    /// period includes waiting for more frames as specified by the write coalescing policy
+    pub fn with_write_timeout(mut self, write_timeout: Option<Duration>) -> Self {
+        self.write_timeout = write_timeout;
+        self
+    }
+
+    /// Account the pump as a running task in `metrics`
+    pub fn with_metrics(mut self, metrics: Option<Arc<ProxyMetrics>>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Complete the latency of jobs queued in `job_latency` once their frames (recognized by
+    /// `is_job`) have been written
+    pub fn with_job_latency(
+        mut self,
+        job_latency: Option<JobLatency>,
+        is_job: fn(&St::Item) -> bool,
+    ) -> Self {
+        self.job_latency = job_latency;
+        self.is_job = is_job;
+        self
+    }
+
+    pub async fn run(self) -> Result<()> {
+        let context = self.context.clone();
+        let result = match self.metrics.clone() {
+            Some(metrics) => metrics.accounted(self.pump()).await,
+            None => self.pump().await,
+        };
+        result.map_err(|e| {
+            debug!("Send error: {} ({})", e, context);
+            e.with_context(context)
+        })
+    }
+
+    async fn pump(mut self) -> Result<()> {
+        let context = self.context.clone();
+        let session_peer = self.session_peer;
+        let direction = self.context.direction;
+        let is_job = self.is_job;
+        // Jobs taken from the stream, they have been written once the batch is complete
+        let pending_jobs = AtomicUsize::new(0);
+        let frames = self
+            .frames
+            .take_until(self.cancel.cancelled())
+            .inspect(|frame| {
+                trace!("TX:{} {}: {:x?}", context, session_peer, frame);
+                if is_job(frame) {
+                    pending_jobs.fetch_add(1, Relaxed);
+                }
+            })
+            .fuse();
+        futures::pin_mut!(frames);
+        while let Some(first) = frames.next().await {
+            let batch = self
+                .write_coalescing
+                .send_batch(&mut self.sink, first, &mut frames);
+            match self.write_timeout {
+                Some(write_timeout) => time::timeout(write_timeout, batch)
+                    .await
+                    .map_err(|e| Self::timeout_error(direction, e))??,
+                None => batch.await?,
+            }
+            if let Some(job_latency) = self.job_latency.as_ref() {
+                job_latency.jobs_written(pending_jobs.swap(0, Relaxed));
+            }
+        }
+        if !self.cancel.is_cancelled() {
+            debug!("No more frames to send ({})", self.context);
+        }
+        Ok(())
+    }
+
+    fn timeout_error(direction: Option<Direction>, e: time::error::Elapsed) -> Error {
+        match direction {
+            Some(Direction::Upstream) => UpstreamError::Timeout(e).into(),
+            Some(Direction::Downstream) => DownstreamError::Timeout(e).into(),
+            None => Error::Timeout(e),
+        }
+    }
+}
+
+/// Measures the time from the reception of an upstream job until the frame of the translated
+/// job has been written to the downstream connection
+#[derive(Clone)]
+pub struct JobLatency {
+    /// Reception times of the jobs whose frames haven't been written yet, oldest first. Jobs
+    /// without the time are not observed, they only keep the order of the frames.
+    pending: Arc<Mutex<VecDeque<Option<Instant>>>>,
+    metrics: Arc<ProxyMetrics>,
+}
+
+impl JobLatency {
+    pub fn new(metrics: Arc<ProxyMetrics>) -> Self {
+        Self {
+            pending: Default::default(),
+            metrics,
+        }
+    }
+
+    /// Registers a job received at `received` whose frame is about to be queued for sending
+    pub fn job_queued(&self, received: Option<Instant>) {
+        self.pending
+            .lock()
+            .expect("BUG: poisoned job latency")
+            .push_back(received);
+    }
+
+    /// Observes the latency of `count` oldest jobs, their frames have been written
+    fn jobs_written(&self, count: usize) {
+        let mut pending = self.pending.lock().expect("BUG: poisoned job latency");
+        let count = count.min(pending.len());
+        for received in pending.drain(..count).flatten() {
+            self.metrics
+                .observe_job_translation_latency(received.elapsed());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::mpsc;
+    use std::net::SocketAddr;
+    use std::pin::Pin;
+    use std::sync::Mutex;
+    use std::task::{Context, Poll};
+
+    /// Sink that collects items, a stalled sink never accepts anything
+    #[derive(Clone, Default)]
+    struct TestSink {
+        items: Arc<Mutex<Vec<u32>>>,
+        stalled: bool,
+    }
+
+    impl Sink<u32> for TestSink {
+        type Error = Error;
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<()>> {
+            if self.stalled {
+                Poll::Pending
+            } else {
+                Poll::Ready(Ok(()))
+            }
+        }
+
+        fn start_send(self: Pin<&mut Self>, item: u32) -> Result<()> {
+            self.items.lock().expect("BUG: poisoned lock").push(item);
+            Ok(())
+        }
+
+        fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.poll_ready(cx)
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+            self.poll_ready(cx)
+        }
+    }
+
+    fn build_pump(
+        sink: TestSink,
+        cancel: CancellationToken,
+    ) -> (mpsc::Sender<u32>, FramePump<mpsc::Receiver<u32>, TestSink>) {
+        let peer_addr: SocketAddr = "10.0.0.1:3333".parse().expect("BUG: invalid address");
+        let (frames_tx, frames_rx) = mpsc::channel(4);
+        let pump = FramePump::new(
+            frames_rx,
+            sink,
+            ErrorContext::default()
+                .with_peer(peer_addr)
+                .with_direction(Direction::Downstream),
+            DownstreamPeer::new(peer_addr),
+            cancel,
+        );
+        (frames_tx, pump)
+    }
+
+    #[tokio::test]
+    async fn test_frames_pumped() {
+        let sink = TestSink::default();
+        let cancel = CancellationToken::new();
+        let (mut frames_tx, pump) = build_pump(sink.clone(), cancel.clone());
+        let pump = tokio::spawn(pump.run());
+        for frame in 0..3 {
+            frames_tx.send(frame).await.expect("BUG: pump dropped");
+        }
+        while sink.items.lock().expect("BUG: poisoned lock").len() < 3 {
+            time::sleep(Duration::from_millis(1)).await;
+        }
+        // Cancellation stops the pump while the queue is still open
+        cancel.cancel();
+        pump.await
+            .expect("BUG: pump panicked")
+            .expect("BUG: pump failed");
+        assert_eq!(
+            *sink.items.lock().expect("BUG: poisoned lock"),
+            vec![0, 1, 2]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_closed_queue_terminates() {
+        let sink = TestSink::default();
+        let (mut frames_tx, pump) = build_pump(sink.clone(), CancellationToken::new());
+        frames_tx.send(1).await.expect("BUG: pump dropped");
+        drop(frames_tx);
+        pump.run()
+            .await
+            .expect("BUG: closed queue reported as failure");
+        assert_eq!(*sink.items.lock().expect("BUG: poisoned lock"), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn test_write_timeout() {
+        let sink = TestSink {
+            stalled: true,
+            ..Default::default()
+        };
+        let (mut frames_tx, pump) = build_pump(sink, CancellationToken::new());
+        frames_tx.send(1).await.expect("BUG: pump dropped");
+        let result = pump
+            .with_write_timeout(Some(Duration::from_millis(10)))
+            .run()
+            .await;
+        match result {
+            Err(Error::Context { source, .. }) => assert!(matches!(
+                *source,
+                Error::Downstream(DownstreamError::Timeout(_))
+            )),
+            result => panic!("BUG: unexpected result of stalled sink: {:?}", result),
+        }
+    }
+}
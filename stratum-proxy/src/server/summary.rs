@@ -0,0 +1,279 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Statistics of downstream sessions that are reported when a session ends
+
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering::Relaxed};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use ii_stratum::v2::noise::ByteCounter;
+use ii_stratum::v2::types::DeviceInfo;
+
+use super::cpu_time::CpuTimeCounter;
+use super::DownstreamPeer;
+use crate::error::{Error, Result};
+
+/// Statistics collected while a session is being handled. Clones share the statistics so that
+/// each part of the session can account its own events.
+#[derive(Clone, Debug, Default)]
+pub struct SessionStats {
+    shares_accepted: Arc<AtomicU64>,
+    shares_rejected: Arc<AtomicU64>,
+    /// Traffic of the downstream connection
+    downstream_bytes: ByteCounter,
+    /// Device announced by the downstream in `SetupConnection`
+    device: Arc<Mutex<Option<DeviceInfo>>>,
+    /// CPU time consumed by the session, present only if CPU accounting is enabled
+    cpu_time: Option<CpuTimeCounter>,
+    /// The session has been asked to reconnect its downstream device
+    reconnect_requested: Arc<AtomicBool>,
+}
+
+impl SessionStats {
+    pub fn with_cpu_time(mut self, cpu_time: Option<CpuTimeCounter>) -> Self {
+        self.cpu_time = cpu_time;
+        self
+    }
+
+    /// Counter that all tasks of the session account their CPU time to
+    pub fn cpu_time(&self) -> Option<CpuTimeCounter> {
+        self.cpu_time.clone()
+    }
+
+    pub fn account_accepted_share(&self) {
+        self.shares_accepted.fetch_add(1, Relaxed);
+    }
+
+    pub fn account_rejected_share(&self) {
+        self.shares_rejected.fetch_add(1, Relaxed);
+    }
+
+    /// Counter to be attached to the codec of the downstream connection
+    pub fn downstream_bytes(&self) -> ByteCounter {
+        self.downstream_bytes.clone()
+    }
+
+    pub fn set_device(&self, device: DeviceInfo) {
+        *self.device.lock().expect("BUG: Poisoned device info") = Some(device);
+    }
+
+    pub fn device(&self) -> Option<DeviceInfo> {
+        self.device
+            .lock()
+            .expect("BUG: Poisoned device info")
+            .clone()
+    }
+
+    pub fn set_reconnect_requested(&self) {
+        self.reconnect_requested.store(true, Relaxed);
+    }
+
+    pub fn reconnect_requested(&self) -> bool {
+        self.reconnect_requested.load(Relaxed)
+    }
+}
+
+/// Displays device information in a form suitable for logs and inventory listings. The fields
+/// are provided by the downstream, they are quoted and escaped so that they cannot forge other
+/// parts of the output.
+pub struct DeviceFingerprint<'a>(pub &'a DeviceInfo);
+
+impl fmt::Display for DeviceFingerprint<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "vendor:{:?} hw_rev:{:?} fw_ver:{:?} dev_id:{:?}",
+            self.0.vendor.as_str(),
+            self.0.hw_rev.as_str(),
+            self.0.fw_ver.as_str(),
+            self.0.dev_id.as_str()
+        )
+    }
+}
+
+/// Reason why a session has ended
+#[derive(Debug)]
+pub enum CloseReason {
+    /// Downstream peer has closed the connection
+    Downstream,
+    /// Session has been cancelled by the server (e.g. on shutdown)
+    Cancelled,
+    /// Session has been terminated by an error (including a drop of the upstream connection)
+    Error(Error),
+}
+
+impl From<Result<()>> for CloseReason {
+    fn from(result: Result<()>) -> Self {
+        match result {
+            Ok(()) | Err(Error::DownstreamClosed { .. }) => Self::Downstream,
+            Err(Error::Cancelled) => Self::Cancelled,
+            // Cancellation observed while receiving is annotated with the direction
+            Err(Error::Context { source, .. }) if matches!(*source, Error::Cancelled) => {
+                Self::Cancelled
+            }
+            Err(e) => Self::Error(e),
+        }
+    }
+}
+
+impl fmt::Display for CloseReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Downstream => write!(f, "closed by downstream"),
+            Self::Cancelled => write!(f, "cancelled"),
+            Self::Error(e) => write!(f, "error: {}", e),
+        }
+    }
+}
+
+/// Summary of a finished session
+#[derive(Debug)]
+pub struct SessionSummary {
+    pub downstream_peer: DownstreamPeer,
+    pub duration: Duration,
+    pub shares_accepted: u64,
+    pub shares_rejected: u64,
+    /// Bytes received from the downstream peer
+    pub bytes_in: u64,
+    /// Bytes sent to the downstream peer
+    pub bytes_out: u64,
+    pub close_reason: CloseReason,
+    /// CPU time consumed by the session, present only if CPU accounting is enabled
+    pub cpu_time: Option<Duration>,
+    /// Device of the session, present only if the downstream has set up the connection
+    pub device: Option<DeviceInfo>,
+}
+
+impl SessionSummary {
+    pub fn new(
+        downstream_peer: DownstreamPeer,
+        duration: Duration,
+        stats: &SessionStats,
+        close_reason: CloseReason,
+    ) -> Self {
+        Self {
+            downstream_peer,
+            duration,
+            shares_accepted: stats.shares_accepted.load(Relaxed),
+            shares_rejected: stats.shares_rejected.load(Relaxed),
+            bytes_in: stats.downstream_bytes.rx_bytes(),
+            bytes_out: stats.downstream_bytes.tx_bytes(),
+            close_reason,
+            cpu_time: None,
+            device: stats.device(),
+        }
+    }
+}
+
+impl fmt::Display for SessionSummary {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "session of {} {}, duration: {:.1}s, shares accepted: {}, rejected: {}, bytes in: {}, \
+             out: {}",
+            self.downstream_peer,
+            self.close_reason,
+            self.duration.as_secs_f64(),
+            self.shares_accepted,
+            self.shares_rejected,
+            self.bytes_in,
+            self.bytes_out
+        )?;
+        if let Some(cpu_time) = self.cpu_time {
+            write!(f, ", cpu: {:.3}s", cpu_time.as_secs_f64())?;
+        }
+        if let Some(device) = self.device.as_ref() {
+            write!(f, ", device: {}", DeviceFingerprint(device))?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryInto;
+    use std::net::{IpAddr, SocketAddr};
+
+    #[test]
+    fn summary_from_stats() {
+        let peer = DownstreamPeer::new(SocketAddr::new(IpAddr::from([1, 2, 3, 4]), 1234));
+        let stats = SessionStats::default();
+        stats.account_accepted_share();
+        stats.account_accepted_share();
+        stats.account_rejected_share();
+
+        let summary = SessionSummary::new(
+            peer,
+            Duration::from_secs(3),
+            &stats,
+            Err(Error::DownstreamClosed {
+                peer: peer.direct_peer,
+            })
+            .into(),
+        );
+        assert_eq!(summary.shares_accepted, 2);
+        assert_eq!(summary.shares_rejected, 1);
+        assert!(matches!(summary.close_reason, CloseReason::Downstream));
+
+        let close_reason: CloseReason = Err(Error::UpstreamClosed {
+            peer: peer.direct_peer,
+        })
+        .into();
+        assert!(matches!(
+            close_reason,
+            CloseReason::Error(Error::UpstreamClosed { .. })
+        ));
+
+        let device = DeviceInfo {
+            vendor: "Braiins".try_into().expect("BUG: Invalid vendor"),
+            hw_rev: "S9".try_into().expect("BUG: Invalid hw_rev"),
+            fw_ver: "Braiins OS 2021-02"
+                .try_into()
+                .expect("BUG: Invalid fw_ver"),
+            dev_id: "xyz".try_into().expect("BUG: Invalid dev_id"),
+        };
+        stats.set_device(device.clone());
+        let summary = SessionSummary::new(peer, Duration::from_secs(3), &stats, Ok(()).into());
+        assert_eq!(summary.device, Some(device));
+        assert!(summary.to_string().ends_with(
+            "device: vendor:\"Braiins\" hw_rev:\"S9\" fw_ver:\"Braiins OS 2021-02\" dev_id:\"xyz\""
+        ));
+        let forged = DeviceInfo {
+            dev_id: "xyz\"\nINFO fake".try_into().expect("BUG: Invalid dev_id"),
+            ..summary.device.expect("BUG: Missing device")
+        };
+        assert!(DeviceFingerprint(&forged)
+            .to_string()
+            .ends_with("dev_id:\"xyz\\\"\\nINFO fake\""));
+
+        let close_reason: CloseReason = Err(Error::Cancelled).into();
+        assert!(matches!(close_reason, CloseReason::Cancelled));
+        assert_eq!(close_reason.to_string(), "cancelled");
+
+        let close_reason: CloseReason =
+            Err(Error::Cancelled.with_context(Default::default())).into();
+        assert!(matches!(close_reason, CloseReason::Cancelled));
+    }
+}
@@ -0,0 +1,533 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Abstraction of the upstream V1 server. Each downstream session opens its own connection via
+//! the `Upstream` trait so that the proxy can be embedded with other transports and tests can
+//! run complete sessions over in-memory streams.
+
+use std::fmt;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering::Relaxed};
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use bitcoin_hashes::{sha256, Hash};
+use futures::channel::mpsc;
+use serde::Deserialize;
+use tokio::io::{AsyncRead, AsyncWrite, DuplexStream};
+use tokio::net::TcpStream;
+
+use ii_wire::{srv::SrvResolver, Address};
+
+/// Established connection to the upstream server
+#[derive(Debug)]
+pub struct UpstreamConnection<S> {
+    pub stream: S,
+    /// Address of the upstream peer
+    pub peer_addr: SocketAddr,
+    /// Name of the upstream that has provided the connection, see `Upstream::set_draining()`
+    pub upstream_name: String,
+    /// Credentials that the session uses instead of those provided by the downstream device
+    pub credentials: Option<UpstreamCredentials>,
+}
+
+/// Fixed credentials of an upstream pool account, e.g. so that farm operators don't have to
+/// configure the account on individual devices
+#[derive(Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UpstreamCredentials {
+    pub user: String,
+    /// Password of the downstream device (or the configured default) is used if not specified
+    pub password: Option<String>,
+    /// The user provided by the downstream device is appended to `user` as a worker name
+    /// (`user.device_user`) instead of being replaced
+    #[serde(default)]
+    pub append_downstream_user: bool,
+}
+
+/// The password is redacted so that it doesn't leak into logs (e.g. the configuration dump)
+impl fmt::Debug for UpstreamCredentials {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("UpstreamCredentials")
+            .field("user", &self.user)
+            .field("password", &self.password.as_ref().map(|_| ".."))
+            .field("append_downstream_user", &self.append_downstream_user)
+            .finish()
+    }
+}
+
+impl UpstreamCredentials {
+    /// User that is authorized upstream on behalf of `downstream_user`
+    pub fn upstream_user(&self, downstream_user: &str) -> String {
+        if self.append_downstream_user && !downstream_user.is_empty() {
+            format!("{}.{}", self.user, downstream_user)
+        } else {
+            self.user.clone()
+        }
+    }
+}
+
+/// Provides connections to the upstream server for `ProxyServer`
+#[async_trait]
+pub trait Upstream: Clone + Send + Sync + fmt::Display + 'static {
+    type Stream: AsyncRead + AsyncWrite + Unpin + Send + fmt::Debug + 'static;
+
+    /// Opens a new connection, it is called once for each connection attempt of each session
+    async fn connect(&self) -> io::Result<UpstreamConnection<Self::Stream>>;
+
+    /// Opens a new connection for a session identified by `routing_key` (the worker name). It
+    /// is used instead of `connect()` only if `routes_by_key()` is true.
+    async fn connect_routed(
+        &self,
+        _routing_key: &str,
+    ) -> io::Result<UpstreamConnection<Self::Stream>> {
+        self.connect().await
+    }
+
+    /// Sessions are connected via `connect_routed()` once the worker name is known, i.e. the
+    /// upstream connection is postponed until the downstream device opens a channel
+    fn routes_by_key(&self) -> bool {
+        false
+    }
+
+    /// Stops routing new sessions to the upstream called `name` (or resumes the routing when
+    /// `draining` is false). Returns false if there is no such upstream that could be drained.
+    fn set_draining(&self, _name: &str, _draining: bool) -> bool {
+        false
+    }
+}
+
+/// Default upstream that connects via TCP, the host name is resolved on each attempt. An SRV
+/// record name is resolved by `srv_resolver` (the answer is cached for its TTL) and its targets
+/// are tried in the order given by the record.
+#[derive(Clone, Debug)]
+pub struct TcpUpstream {
+    address: Address,
+    credentials: Option<UpstreamCredentials>,
+    srv_resolver: Option<SrvResolver>,
+}
+
+impl TcpUpstream {
+    pub fn new(address: Address) -> Self {
+        Self {
+            address,
+            credentials: None,
+            srv_resolver: None,
+        }
+    }
+
+    pub fn with_credentials(mut self, credentials: Option<UpstreamCredentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Resolver of the address if it is an SRV record name, connection attempts fail without it
+    pub fn with_srv_resolver(mut self, srv_resolver: SrvResolver) -> Self {
+        self.srv_resolver = Some(srv_resolver);
+        self
+    }
+
+    async fn connect_stream(&self) -> io::Result<TcpStream> {
+        let srv_resolver = match self.srv_resolver.as_ref() {
+            Some(srv_resolver) if self.address.is_srv() => srv_resolver,
+            _ => return self.address.connect().await,
+        };
+        let mut last_error = None;
+        for target in srv_resolver.resolve(&self.address.0).await? {
+            match target.connect().await {
+                Ok(stream) => return Ok(stream),
+                Err(e) => last_error = Some(e),
+            }
+        }
+        Err(last_error.expect("BUG: SRV record without targets"))
+    }
+}
+
+impl fmt::Display for TcpUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.address)
+    }
+}
+
+#[async_trait]
+impl Upstream for TcpUpstream {
+    type Stream = TcpStream;
+
+    async fn connect(&self) -> io::Result<UpstreamConnection<Self::Stream>> {
+        let stream = self.connect_stream().await?;
+        let peer_addr = stream.peer_addr()?;
+        Ok(UpstreamConnection {
+            stream,
+            peer_addr,
+            upstream_name: self.to_string(),
+            credentials: self.credentials.clone(),
+        })
+    }
+}
+
+/// Upstream that builds an in-memory connection for each connection attempt and passes the
+/// server side of it through a channel. The receiver of the channel plays the role of the
+/// upstream server, dropping the receiver makes all further attempts fail as refused.
+#[derive(Clone, Debug)]
+pub struct ChannelUpstream {
+    peer_addr: SocketAddr,
+    connections: mpsc::UnboundedSender<DuplexStream>,
+    credentials: Option<UpstreamCredentials>,
+}
+
+impl ChannelUpstream {
+    /// Size of the in-memory buffer of each direction of the connection
+    const MAX_BUF_SIZE: usize = 64 * 1024;
+
+    /// Builds the upstream along with the receiver of server sides of the connections.
+    /// `peer_addr` is reported as the address of the upstream peer.
+    pub fn new(peer_addr: SocketAddr) -> (Self, mpsc::UnboundedReceiver<DuplexStream>) {
+        let (connections, rx) = mpsc::unbounded();
+        (
+            Self {
+                peer_addr,
+                connections,
+                credentials: None,
+            },
+            rx,
+        )
+    }
+
+    pub fn with_credentials(mut self, credentials: Option<UpstreamCredentials>) -> Self {
+        self.credentials = credentials;
+        self
+    }
+}
+
+impl fmt::Display for ChannelUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "in-memory {}", self.peer_addr)
+    }
+}
+
+#[async_trait]
+impl Upstream for ChannelUpstream {
+    type Stream = DuplexStream;
+
+    async fn connect(&self) -> io::Result<UpstreamConnection<Self::Stream>> {
+        let (stream, server_stream) = tokio::io::duplex(Self::MAX_BUF_SIZE);
+        self.connections
+            .unbounded_send(server_stream)
+            .map_err(|_| {
+                io::Error::new(
+                    io::ErrorKind::ConnectionRefused,
+                    "In-memory upstream has been closed",
+                )
+            })?;
+        Ok(UpstreamConnection {
+            stream,
+            peer_addr: self.peer_addr,
+            upstream_name: self.to_string(),
+            credentials: self.credentials.clone(),
+        })
+    }
+}
+
+/// How `UpstreamSet` chooses the upstream for a new session
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpstreamRouting {
+    /// Sessions are distributed evenly regardless of the worker
+    RoundRobin,
+    /// Sessions of a worker always end up on the same upstream (as long as it isn't draining),
+    /// the upstream is chosen by hashing the worker name
+    Sticky,
+}
+
+struct UpstreamSetMember<U> {
+    upstream: U,
+    name: String,
+    draining: AtomicBool,
+}
+
+/// Upstream that distributes new sessions among multiple upstreams as specified by
+/// `UpstreamRouting`. Each upstream can be drained (e.g. for a maintenance of the pool), no new
+/// sessions are routed to a draining upstream. Upstreams are identified by their names
+/// (`Display`).
+#[derive(Clone)]
+pub struct UpstreamSet<U> {
+    members: Arc<Vec<UpstreamSetMember<U>>>,
+    next_member: Arc<AtomicUsize>,
+    routing: UpstreamRouting,
+}
+
+impl<U: Upstream> UpstreamSet<U> {
+    pub fn new(upstreams: Vec<U>) -> Self {
+        assert!(!upstreams.is_empty(), "BUG: Empty set of upstreams");
+        let members = upstreams
+            .into_iter()
+            .map(|upstream| UpstreamSetMember {
+                name: upstream.to_string(),
+                upstream,
+                draining: AtomicBool::new(false),
+            })
+            .collect();
+        Self {
+            members: Arc::new(members),
+            next_member: Arc::new(AtomicUsize::new(0)),
+            routing: UpstreamRouting::RoundRobin,
+        }
+    }
+
+    pub fn with_routing(mut self, routing: UpstreamRouting) -> Self {
+        self.routing = routing;
+        self
+    }
+
+    fn all_draining_error() -> io::Error {
+        io::Error::new(
+            io::ErrorKind::ConnectionRefused,
+            "All upstreams are draining",
+        )
+    }
+
+    /// Rendezvous hashing: each worker ranks the upstreams by a hash of the worker name and the
+    /// upstream name and picks the best ranked upstream that isn't draining. Adding, removing or
+    /// draining an upstream moves only the workers of that upstream.
+    fn sticky_member(&self, routing_key: &str) -> Option<&UpstreamSetMember<U>> {
+        self.members
+            .iter()
+            .filter(|member| !member.draining.load(Relaxed))
+            .max_by_key(|member| {
+                let mut input = Vec::with_capacity(routing_key.len() + member.name.len() + 1);
+                input.extend_from_slice(routing_key.as_bytes());
+                input.push(0);
+                input.extend_from_slice(member.name.as_bytes());
+                sha256::Hash::hash(&input).into_inner()
+            })
+    }
+
+    /// Names of all upstreams along with their draining status
+    pub fn upstreams(&self) -> Vec<(String, bool)> {
+        self.members
+            .iter()
+            .map(|member| (member.name.clone(), member.draining.load(Relaxed)))
+            .collect()
+    }
+}
+
+impl<U> fmt::Display for UpstreamSet<U> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = self
+            .members
+            .iter()
+            .map(|member| member.name.as_str())
+            .collect();
+        write!(f, "{}", names.join(", "))
+    }
+}
+
+#[async_trait]
+impl<U: Upstream> Upstream for UpstreamSet<U> {
+    type Stream = U::Stream;
+
+    async fn connect(&self) -> io::Result<UpstreamConnection<Self::Stream>> {
+        let first = self.next_member.fetch_add(1, Relaxed);
+        let member = (0..self.members.len())
+            .map(|offset| &self.members[(first + offset) % self.members.len()])
+            .find(|member| !member.draining.load(Relaxed))
+            .ok_or_else(Self::all_draining_error)?;
+        member.upstream.connect().await
+    }
+
+    async fn connect_routed(
+        &self,
+        routing_key: &str,
+    ) -> io::Result<UpstreamConnection<Self::Stream>> {
+        // Sessions may be connected late for other reasons than routing (e.g. authentication)
+        if self.routing != UpstreamRouting::Sticky {
+            return self.connect().await;
+        }
+        let member = self
+            .sticky_member(routing_key)
+            .ok_or_else(Self::all_draining_error)?;
+        member.upstream.connect().await
+    }
+
+    fn routes_by_key(&self) -> bool {
+        self.routing == UpstreamRouting::Sticky
+    }
+
+    fn set_draining(&self, name: &str, draining: bool) -> bool {
+        match self.members.iter().find(|member| member.name == name) {
+            Some(member) => {
+                member.draining.store(draining, Relaxed);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_credentials_debug_redacts_password() {
+        let credentials = UpstreamCredentials {
+            user: "farm".to_string(),
+            password: Some("secret".to_string()),
+            append_downstream_user: false,
+        };
+        let debug = format!("{:?}", credentials);
+        assert!(!debug.contains("secret"), "BUG: Password leaked: {}", debug);
+        assert!(debug.contains("farm"));
+    }
+
+    #[tokio::test]
+    async fn test_upstream_set_skips_draining() {
+        let (first, _first_rx) =
+            ChannelUpstream::new("10.0.0.1:3333".parse().expect("BUG: Invalid address"));
+        let (second, _second_rx) =
+            ChannelUpstream::new("10.0.0.2:3333".parse().expect("BUG: Invalid address"));
+        let first_name = first.to_string();
+        let upstreams = UpstreamSet::new(vec![first, second]);
+
+        let mut names = vec![];
+        for _ in 0..2 {
+            names.push(
+                upstreams
+                    .connect()
+                    .await
+                    .expect("BUG: Cannot connect upstream")
+                    .upstream_name,
+            );
+        }
+        assert_eq!(
+            names,
+            vec![first_name.clone(), upstreams.members[1].name.clone()]
+        );
+
+        assert!(upstreams.set_draining(&first_name, true));
+        assert!(!upstreams.set_draining("10.0.0.3:3333", true));
+        for _ in 0..2 {
+            assert_ne!(
+                upstreams
+                    .connect()
+                    .await
+                    .expect("BUG: Cannot connect upstream")
+                    .upstream_name,
+                first_name
+            );
+        }
+        assert!(upstreams.set_draining(&upstreams.members[1].name, true));
+        assert_eq!(
+            upstreams.connect().await.unwrap_err().kind(),
+            io::ErrorKind::ConnectionRefused
+        );
+        assert_eq!(
+            upstreams
+                .upstreams()
+                .iter()
+                .filter(|(_, draining)| *draining)
+                .count(),
+            2
+        );
+    }
+
+    #[tokio::test]
+    async fn test_upstream_set_routed_round_robin() {
+        let (first, _first_rx) =
+            ChannelUpstream::new("10.0.0.1:3333".parse().expect("BUG: Invalid address"));
+        let (second, _second_rx) =
+            ChannelUpstream::new("10.0.0.2:3333".parse().expect("BUG: Invalid address"));
+        let upstreams = UpstreamSet::new(vec![first, second]);
+        assert!(!upstreams.routes_by_key());
+
+        // The same worker is still distributed round robin
+        let first_name = upstreams
+            .connect_routed("user.worker")
+            .await
+            .expect("BUG: Cannot connect upstream")
+            .upstream_name;
+        let second_name = upstreams
+            .connect_routed("user.worker")
+            .await
+            .expect("BUG: Cannot connect upstream")
+            .upstream_name;
+        assert_ne!(first_name, second_name);
+    }
+
+    #[tokio::test]
+    async fn test_upstream_set_sticky_routing() {
+        let (first, _first_rx) =
+            ChannelUpstream::new("10.0.0.1:3333".parse().expect("BUG: Invalid address"));
+        let (second, _second_rx) =
+            ChannelUpstream::new("10.0.0.2:3333".parse().expect("BUG: Invalid address"));
+        let (third, _third_rx) =
+            ChannelUpstream::new("10.0.0.3:3333".parse().expect("BUG: Invalid address"));
+        let upstreams =
+            UpstreamSet::new(vec![first, second, third]).with_routing(UpstreamRouting::Sticky);
+        assert!(upstreams.routes_by_key());
+
+        let workers: Vec<_> = (0..30).map(|i| format!("user.worker{}", i)).collect();
+        let mut routes = vec![];
+        for worker in workers.iter() {
+            let name = upstreams
+                .connect_routed(worker)
+                .await
+                .expect("BUG: Cannot connect upstream")
+                .upstream_name;
+            // Reconnecting worker lands on the same upstream
+            assert_eq!(
+                upstreams
+                    .connect_routed(worker)
+                    .await
+                    .expect("BUG: Cannot connect upstream")
+                    .upstream_name,
+                name
+            );
+            routes.push(name);
+        }
+        // Workers are spread among all upstreams
+        for member in upstreams.members.iter() {
+            assert!(
+                routes.contains(&member.name),
+                "No worker on {}",
+                member.name
+            );
+        }
+
+        // Only workers of the draining upstream are moved
+        let drained = upstreams.members[0].name.clone();
+        assert!(upstreams.set_draining(&drained, true));
+        for (worker, route) in workers.iter().zip(routes.iter()) {
+            let name = upstreams
+                .connect_routed(worker)
+                .await
+                .expect("BUG: Cannot connect upstream")
+                .upstream_name;
+            if *route == drained {
+                assert_ne!(name, drained);
+            } else {
+                assert_eq!(name, *route);
+            }
+        }
+    }
+}
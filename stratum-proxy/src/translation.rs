@@ -20,42 +20,53 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-use std::collections::{HashMap, VecDeque};
+use std::collections::VecDeque;
 use std::convert::From;
 use std::convert::TryFrom;
 use std::convert::TryInto;
 use std::fmt;
 use std::mem::size_of;
+use std::net::SocketAddr;
 use std::str::FromStr;
 
 use bytes::BytesMut;
 use futures::channel::mpsc;
 use primitive_types::U256;
 
-use bitcoin_hashes::{sha256d, Hash, HashEngine};
+use bitcoin_hashes::{sha256d, Hash};
 use serde_json::Value;
 
 use ii_logging::macros::*;
 use ii_stratum::v1::{self, MessageId};
 use ii_stratum::v2::{
     self,
+    id_allocator::IdAllocator,
     types::{Bytes0_32, Str0_255, Uint256Bytes},
 };
 use ii_unvariant::handler;
 use ii_wire::proxy::ProxyInfo;
 
-use crate::error::{DownstreamError, Error, Result, UpstreamError, V2ProtocolError};
+use crate::accounting::ShareAccounting;
+use crate::auth::{AuthDecision, AuthRequest, Authenticator};
+use crate::error::{
+    DownstreamError, Error, InvalidUpstreamMessage, Result, UpstreamError, V2ProtocolError,
+};
+use crate::events::{Event, EventExporter};
 use crate::metrics::ProxyMetrics;
+use crate::server::{pump::JobLatency, DeviceFingerprint, SessionStats, UpstreamCredentials};
 use crate::util;
 use std::sync::Arc;
 use tokio::time::{Duration, Instant};
 
-mod stratum {
-    pub use ii_stratum::error::{Error, Result};
-}
-
+pub mod adapter;
+mod job_map;
+mod reject;
+pub mod session;
 #[cfg(test)]
 mod test;
+mod validation;
+
+pub use reject::RejectCode;
 
 /// Sequential ID to pair up messages, requests etc.
 #[derive(Default, Debug)]
@@ -84,6 +95,36 @@ pub struct V2ToV1TranslationOptions {
     pub propagate_reconnect_downstream: bool,
     // cannot use String here because of Copy trait requirement
     pub password: arrayvec::ArrayString<[u8; Self::MAX_V1_PASSWORD_SIZE]>,
+    /// Operational channel that submits no shares for this period is closed
+    pub idle_channel_timeout: Option<Duration>,
+    /// Upstream has to respond to each V1 request within this period
+    pub v1_request_timeout: Option<Duration>,
+    /// Maximum number of `mining.submit` requests waiting for a response from the upstream,
+    /// further shares are held back until some of the outstanding submits is answered
+    pub max_submits_in_flight: Option<usize>,
+    /// Share is rejected when the upstream doesn't answer its `mining.submit` within this period.
+    /// Unlike `v1_request_timeout`, the session is kept.
+    pub v1_submit_timeout: Option<Duration>,
+    /// Strictness of parsing of messages received from the upstream
+    pub v1_parse_mode: v1::rpc::ParseMode,
+    /// Session is terminated when the upstream sends more `mining.notify` messages within a
+    /// minute, which protects devices from being flooded with jobs
+    pub v1_max_notifies_per_minute: Option<u32>,
+    /// Maximum number of jobs that shares can be submitted for, the oldest jobs are forgotten
+    /// first. At least the most recent job is always tracked.
+    pub max_tracked_jobs: Option<usize>,
+    /// Jobs invalidated by a new previous block hash still accept shares for this period
+    pub stale_job_grace_period: Option<Duration>,
+    /// Shares are rejected without being submitted upstream when their `ntime` is rolled beyond
+    /// `min_ntime` of the job by more than this period
+    pub max_ntime_roll: Option<Duration>,
+    /// Difficulty set by the upstream is raised to at least this value before it is passed
+    /// downstream. The raised difficulty is suggested to the upstream.
+    pub min_difficulty: Option<u32>,
+    /// Difficulty set by the upstream is lowered to at most this value before it is passed
+    /// downstream. The lowered difficulty is suggested to the upstream and shares that don't meet
+    /// the difficulty of the upstream are acknowledged by the proxy without being submitted.
+    pub max_difficulty: Option<u32>,
 }
 
 impl V2ToV1TranslationOptions {
@@ -100,6 +141,17 @@ impl V2ToV1TranslationOptions {
             try_enable_xnsub,
             propagate_reconnect_downstream,
             password,
+            idle_channel_timeout: None,
+            v1_request_timeout: None,
+            max_submits_in_flight: None,
+            v1_submit_timeout: None,
+            v1_parse_mode: Default::default(),
+            v1_max_notifies_per_minute: None,
+            max_tracked_jobs: None,
+            stale_job_grace_period: None,
+            max_ntime_roll: None,
+            min_difficulty: None,
+            max_difficulty: None,
         }
     }
 }
@@ -110,6 +162,17 @@ impl Default for V2ToV1TranslationOptions {
             try_enable_xnsub: false,
             propagate_reconnect_downstream: false,
             password: arrayvec::ArrayString::new(),
+            idle_channel_timeout: None,
+            v1_request_timeout: None,
+            max_submits_in_flight: None,
+            v1_submit_timeout: None,
+            v1_parse_mode: Default::default(),
+            v1_max_notifies_per_minute: None,
+            max_tracked_jobs: None,
+            stale_job_grace_period: None,
+            max_ntime_roll: None,
+            min_difficulty: None,
+            max_difficulty: None,
         }
     }
 }
@@ -143,31 +206,21 @@ type V1StratumErrorHandler =
 struct V1CompoundHandler {
     v1_stratum_result_handler: V1StratumResultHandler,
     v1_stratum_error_handler: V1StratumErrorHandler,
-    /// Request method associated with these handlers is used for code instrumentation
-    request_method: v1::rpc::Method,
-    /// Time stamp when the request has been submitted
-    timestamp_created: Instant,
 }
 impl V1CompoundHandler {
     fn new(
         v1_stratum_result_handler: V1StratumResultHandler,
         v1_stratum_error_handler: V1StratumErrorHandler,
-        request_method: v1::rpc::Method,
     ) -> Self {
         Self {
             v1_stratum_result_handler,
             v1_stratum_error_handler,
-            request_method,
-            timestamp_created: Instant::now(),
         }
     }
-    fn elapsed(&self) -> Duration {
-        self.timestamp_created.elapsed()
-    }
 }
 
-/// Custom mapping of V1 request id onto result/error handlers
-type V1ReqMap = HashMap<u32, V1CompoundHandler>;
+/// Pairs V1 responses with result/error handlers of the original requests
+type V1RequestTracker = v1::correlation::RequestTracker<V1CompoundHandler>;
 
 /// Helper template stored in V2->V1 job map
 #[derive(Clone, PartialEq, Debug)]
@@ -175,6 +228,12 @@ struct V1SubmitTemplate {
     job_id: v1::messages::JobId,
     time: u32,
     version: u32,
+    /// Fields of the block header that allow checking shares locally
+    prev_hash: [u8; 32],
+    merkle_root: [u8; 32],
+    bits: u32,
+    /// Target set by the upstream when the job has been received
+    upstream_target: Option<U256>,
 }
 
 enum V1ResultOrError<'a> {
@@ -183,7 +242,7 @@ enum V1ResultOrError<'a> {
 }
 
 /// Maps V2 job ID to V1 job ID so that we can submit mining results upstream to V1 server
-type JobMap = HashMap<u32, V1SubmitTemplate>;
+type JobMap = job_map::JobMap<V1SubmitTemplate>;
 
 //type V2ReqMap = HashMap<u32, FnMut(&mut V2ToV1Translation, &ii_stratum::Message<Protocol>, &v1::rpc::StratumResult)>;
 
@@ -192,17 +251,33 @@ enum SeqNum {
     V2(u32),
 }
 
-/// Describes 2 variants of submitted shares
+/// Describes variants of submitted shares. Responses are sent downstream in the order of the
+/// submitted shares regardless of the order in which the upstream answers.
 enum SubmitShare {
     /// Sequence number mapping between Stratum V1 and V2 SubmitShares/mining.submit resp.
-    V1ToV2Mapping(u32, u32),
+    /// along with the downstream target that the share has been submitted for
+    V1ToV2Mapping(u32, u32, Option<U256>),
+    /// Share (with its V2 sequence number and downstream target) that waits for a free slot in
+    /// the window of submits in flight
+    Deferred(u32, v1::messages::Submit, Option<U256>),
     /// Submit share error which proxy generates and can be faster than submitted shares to
     /// remote server
     SubmitSharesError(v2::messages::SubmitSharesError),
+    /// Upstream has accepted the share, the response waits for responses to earlier shares
+    SubmitSharesSuccess(v2::messages::SubmitSharesSuccess),
 }
 
 type SubmitShareQueue = VecDeque<SubmitShare>;
 
+/// Identifies the translation channel that frames are submitted into
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum ChannelDirection {
+    /// Frames to be sent to the V1 upstream server
+    Upstream,
+    /// Frames to be sent to the V2 downstream device
+    Downstream,
+}
+
 /// Object capable of translating stratm V2 header-only mining protocol that uses standard mining
 /// channels into stratum V1 including extranonce 1 subscription
 pub struct V2ToV1Translation {
@@ -211,15 +286,22 @@ pub struct V2ToV1Translation {
 
     /// Channel for sending out V1 responses
     v1_tx: mpsc::Sender<v1::Frame>,
-    /// Unique request ID generator
-    v1_req_id: SeqId,
-    /// Mapping for pairing of incoming V1 message with original requests
-    v1_req_map: V1ReqMap,
+    /// Outstanding V1 requests waiting for a response
+    v1_requests: V1RequestTracker,
 
     v1_extra_nonce1: Option<v1::ExtraNonce1>,
     v1_extra_nonce2_size: usize,
     v1_authorized: bool,
     v1_xnsub_enabled: bool,
+    /// Extensions enabled by the upstream via `mining.configure`
+    v1_negotiated: v1::messages::NegotiatedFeatures,
+    /// `mining.configure` is postponed until the channel is opened, see `defer_v1_configure()`
+    v1_configure_deferred: bool,
+    /// Session IDs of previous subscriptions for resuming the V1 session
+    v1_sessions: Option<session::V1SessionStore>,
+    /// Key of the downstream device in `v1_sessions`, set while the session is claimed by this
+    /// translation
+    v1_session_key: Option<String>,
 
     /// Whether to force future jobs: might be handy for v1 pools which don't accept solutions with
     /// `ntime` less than specified on jobs they are solving (but greater than ntime on prevhash).
@@ -228,6 +310,8 @@ pub struct V2ToV1Translation {
     /// Latest mining.notify payload that arrived before V1 authorize has completed.
     /// This allows immediate completion of channel open on V2.
     v1_deferred_notify: Option<v1::messages::Notify>,
+    /// Present if the rate of `mining.notify` messages is limited
+    v1_notify_rate_limiter: Option<validation::NotifyRateLimiter>,
 
     /// Channel for sending out V2 responses
     v2_tx: mpsc::Sender<v2::Frame>,
@@ -235,56 +319,88 @@ pub struct V2ToV1Translation {
     v2_req_id: SeqId,
     /// All connection details
     v2_conn_details: Option<v2::messages::SetupConnection>,
+    /// Protocol version negotiated in `SetupConnection`
+    v2_protocol_version: Option<u16>,
+    /// Allocates ids of channels opened on the V2 connection
+    v2_channel_ids: IdAllocator,
+    /// Id of the (single) channel of the V2 connection
+    v2_channel_id: u32,
     /// Additional information about the pending channel being open
     v2_channel_details: Option<v2::messages::OpenStandardMiningChannel>,
     /// Target difficulty derived from mining.set_difficulty message
     /// The channel opening is not complete until the target is determined
     v2_target: Option<U256>,
+    /// Target set by the upstream, it differs from `v2_target` when the difficulty is clamped
+    v1_target: Option<U256>,
+    /// Difficulty most recently suggested upstream via `mining.suggest_difficulty`
+    v1_suggested_difficulty: Option<u32>,
     /// Unique job ID generator
     v2_job_id: SeqId,
     /// Translates V2 job ID to V1 job ID
     v2_to_v1_job_map: JobMap,
+    /// Previous block hash of the most recent job, a change invalidates all registered jobs
+    v2_prev_hash: Option<[u8; 32]>,
     /// Queue of submitted shares waiting for response processing
     v2_submit_share_queue: SubmitShareQueue,
+    /// Number of `SubmitShare::V1ToV2Mapping` items in the submit share queue, i.e.
+    /// `mining.submit` requests waiting for a response from the upstream
+    v1_submits_in_flight: usize,
+    /// Number of `SubmitShare::Deferred` items in the submit share queue
+    v2_deferred_shares: usize,
+    /// IDs of requests that have timed out without terminating the session (`mining.submit`,
+    /// `mining.suggest_difficulty`), their late responses are ignored
+    v1_expired_requests: VecDeque<u32>,
     /// Options for translation
     options: V2ToV1TranslationOptions,
     v1_password: String,
+    /// Credentials of the upstream account that replace those of the downstream device
+    v1_credentials: Option<UpstreamCredentials>,
     metrics: Option<Arc<ProxyMetrics>>,
+    /// Jobs are accounted here once queued, their latency is complete when written downstream
+    job_latency: Option<JobLatency>,
     pub last_submit: Option<Instant>,
+    /// Time stamp when the channel has become operational
+    channel_operational_since: Option<Instant>,
+    /// Upstream V1 server address used for labeling latency metrics
+    v1_upstream_addr: Option<SocketAddr>,
+    /// Optional statistics of the session that is being translated
+    session_stats: Option<SessionStats>,
+    /// Per-worker share accounting shared by all sessions
+    share_accounting: Option<ShareAccounting>,
+    /// Authorizes channels along with the source address of the downstream device
+    authenticator: Option<(Arc<dyn Authenticator>, SocketAddr)>,
+    /// Exports share events along with the source address of the downstream device
+    event_exporter: Option<(EventExporter, SocketAddr)>,
     proxy_info: ProxyInfo,
 }
 
 impl V2ToV1Translation {
-    const PROTOCOL_VERSION: usize = 0;
+    /// Range of V2 protocol versions supported by the translation
+    const MIN_PROTOCOL_VERSION: u16 = 2;
+    const MAX_PROTOCOL_VERSION: u16 = 2;
     /// No support for the extended protocol yet, therefore, no extranonce advertised
     #[allow(dead_code)]
     const MAX_EXTRANONCE_SIZE: usize = 0;
-    /// Currently, no support for multiple channels in the proxy
-    const CHANNEL_ID: u32 = 0;
     /// Default group channel
     const DEFAULT_GROUP_CHANNEL_ID: u32 = 0;
 
-    /// U256 in little endian
-    /// TODO: consolidate into common part/generalize
-    /// TODO: DIFF1 const target is broken, the last U64 word gets actually initialized to 0xffffffff, not sure why
-    const DIFF1_TARGET: U256 = U256([0, 0, 0, 0xffff0000u64]);
+    /// Number of timed out requests remembered for ignoring their late responses
+    const MAX_EXPIRED_REQUESTS: usize = 64;
 
-    pub fn target_to_diff(target: U256) -> U256 {
-        if target == U256::from(0) {
-            U256::MAX
-        } else {
-            Self::DIFF1_TARGET / target
-        }
-    }
+    /// Maximum number of shares held back while the window of submits in flight is full,
+    /// further shares are rejected
+    const MAX_DEFERRED_SHARES: usize = 256;
 
-    pub fn diff_to_target<T: Into<U256>>(diff: T) -> U256 {
-        let diff = diff.into();
-        if diff == U256::from(0) {
-            U256::MAX
-        } else {
-            Self::DIFF1_TARGET / diff
-        }
-    }
+    /// Period that `V2ToV1TranslationOptions::v1_max_notifies_per_minute` applies to
+    const NOTIFY_RATE_PERIOD: Duration = Duration::from_secs(60);
+
+    /// Default of `V2ToV1TranslationOptions::max_tracked_jobs`
+    const DEFAULT_MAX_TRACKED_JOBS: usize = 256;
+    /// Default of `V2ToV1TranslationOptions::stale_job_grace_period`
+    const DEFAULT_STALE_JOB_GRACE_PERIOD: Duration = Duration::from_secs(10);
+    /// Default of `V2ToV1TranslationOptions::max_ntime_roll`, block timestamp must not be more
+    /// than 2 hours in the future
+    const DEFAULT_MAX_NTIME_ROLL: Duration = Duration::from_secs(7200);
 
     pub fn new(
         v1_tx: mpsc::Sender<v1::Frame>,
@@ -294,33 +410,255 @@ impl V2ToV1Translation {
         proxy_info: ProxyInfo,
     ) -> Self {
         let v1_password = options.password.to_string();
+        // Currently, no support for multiple channels in the proxy
+        let mut v2_channel_ids = IdAllocator::new();
+        let v2_channel_id = v2_channel_ids
+            .allocate()
+            .expect("BUG: cannot allocate channel id");
         Self {
             v2_conn_details: None,
+            v2_protocol_version: None,
+            v2_channel_ids,
+            v2_channel_id,
             v2_channel_details: None,
             v2_target: None,
+            v1_target: None,
+            v1_suggested_difficulty: None,
             state: V2ToV1TranslationState::Init,
             v1_tx,
-            v1_req_id: SeqId::new(),
-            v1_req_map: V1ReqMap::default(),
+            v1_requests: Self::build_v1_request_tracker(&options),
             v1_extra_nonce1: None,
             v1_extra_nonce2_size: 0,
             v1_authorized: false,
             v1_force_future_jobs: true,
             v1_xnsub_enabled: false,
+            v1_negotiated: Default::default(),
+            v1_configure_deferred: false,
+            v1_sessions: None,
+            v1_session_key: None,
             v1_deferred_notify: None,
+            v1_notify_rate_limiter: options
+                .v1_max_notifies_per_minute
+                .map(|limit| validation::NotifyRateLimiter::new(limit, Self::NOTIFY_RATE_PERIOD)),
             v2_tx,
             v2_req_id: SeqId::new(),
             v2_job_id: SeqId::new(),
-            v2_to_v1_job_map: JobMap::default(),
+            v2_to_v1_job_map: JobMap::new(
+                options
+                    .max_tracked_jobs
+                    .unwrap_or(Self::DEFAULT_MAX_TRACKED_JOBS)
+                    .max(1),
+                options
+                    .stale_job_grace_period
+                    .unwrap_or(Self::DEFAULT_STALE_JOB_GRACE_PERIOD),
+            ),
+            v2_prev_hash: None,
             v2_submit_share_queue: SubmitShareQueue::default(),
+            v1_submits_in_flight: 0,
+            v2_deferred_shares: 0,
+            v1_expired_requests: VecDeque::new(),
             options,
             v1_password,
+            v1_credentials: None,
             metrics,
+            job_latency: None,
             last_submit: None,
+            channel_operational_since: None,
+            v1_upstream_addr: None,
+            session_stats: None,
+            share_accounting: None,
+            authenticator: None,
+            event_exporter: None,
             proxy_info,
         }
     }
 
+    /// Resume upstream sessions of returning devices with session IDs kept in `v1_sessions`
+    pub fn set_v1_session_store(&mut self, v1_sessions: session::V1SessionStore) {
+        self.v1_sessions = Some(v1_sessions);
+    }
+
+    /// Makes the session of the downstream device available to its next connection
+    fn release_v1_session(&mut self) {
+        if let (Some(sessions), Some(key)) = (self.v1_sessions.as_ref(), self.v1_session_key.take())
+        {
+            sessions.release(&key);
+        }
+    }
+
+    /// Account accepted and rejected shares in `session_stats`
+    pub fn set_session_stats(&mut self, session_stats: SessionStats) {
+        self.session_stats = Some(session_stats);
+    }
+
+    /// Account accepted and rejected shares of the mining channel's user in `share_accounting`
+    pub fn set_share_accounting(&mut self, share_accounting: ShareAccounting) {
+        self.share_accounting = Some(share_accounting);
+    }
+
+    /// Each channel has to be authorized by `authenticator` before anything is sent upstream,
+    /// `source_addr` is the address of the downstream device
+    pub fn set_authenticator(
+        &mut self,
+        authenticator: Arc<dyn Authenticator>,
+        source_addr: SocketAddr,
+    ) {
+        self.authenticator = Some((authenticator, source_addr));
+    }
+
+    /// Export accepted and rejected shares, `source_addr` is the address of the downstream device
+    pub fn set_event_exporter(&mut self, event_exporter: EventExporter, source_addr: SocketAddr) {
+        self.event_exporter = Some((event_exporter, source_addr));
+    }
+
+    pub fn set_v1_upstream_addr(&mut self, v1_upstream_addr: SocketAddr) {
+        self.v1_upstream_addr = Some(v1_upstream_addr);
+    }
+
+    /// Measure the latency of translated jobs until the frame pump writes them downstream
+    pub fn set_job_latency(&mut self, job_latency: JobLatency) {
+        self.job_latency = Some(job_latency);
+    }
+
+    /// Authorize the channel upstream with `v1_credentials` instead of the user and password
+    /// provided by the downstream device
+    pub fn set_v1_credentials(&mut self, v1_credentials: Option<UpstreamCredentials>) {
+        self.v1_credentials = v1_credentials;
+    }
+
+    /// Complete SetupConnection without talking to the upstream and send `mining.configure`
+    /// together with the rest of the channel setup once the upstream connection has been
+    /// established, see `resume_v1_configure()`. This allows choosing the upstream server based
+    /// on the worker name, see `v1_routing_key()`.
+    pub fn defer_v1_configure(&mut self) {
+        self.v1_configure_deferred = true;
+    }
+
+    /// Sends the deferred `mining.configure` and opens the pending channel upstream
+    pub fn resume_v1_configure(&mut self) -> Result<()> {
+        if !self.v1_configure_deferred || self.v1_routing_key().is_none() {
+            return Ok(());
+        }
+        self.v1_configure_deferred = false;
+        let configure = self.build_v1_configure();
+        self.submit_v1_request_message(
+            configure,
+            Self::handle_configure_result,
+            Self::handle_configure_error,
+        )
+        .map_err(V2ProtocolError::open_mining_channel)?;
+        self.open_v1_channel()
+    }
+
+    /// User that authorizes the channel and submits its shares upstream
+    fn v1_user(&self, downstream_user: &str) -> String {
+        match self.v1_credentials.as_ref() {
+            Some(credentials) => credentials.upstream_user(downstream_user),
+            None => downstream_user.to_string(),
+        }
+    }
+
+    /// Subscribes and authorizes the pending channel upstream
+    fn open_v1_channel(&mut self) -> Result<()> {
+        self.release_v1_session();
+        let (conn_details, channel_details) = match (
+            self.v2_conn_details.as_ref(),
+            self.v2_channel_details.as_ref(),
+        ) {
+            (Some(conn_details), Some(channel_details)) => (conn_details, channel_details),
+            _ => panic!("BUG: missing details of the channel being opened"),
+        };
+        let hostname: String = conn_details
+            .endpoint_host
+            .clone()
+            .try_into()
+            .expect("BUG: Cannot convert to string from connection details");
+
+        let hostname_port = format!("{}:{}", hostname, conn_details.endpoint_port);
+        let downstream_user = channel_details.user.to_string();
+        let max_target = channel_details.max_target;
+        // Previous session of the device is resumed if the upstream supports it. The session must
+        // have been assigned by the same upstream.
+        let mut session_id = None;
+        if let (Some(sessions), Some(upstream)) = (self.v1_sessions.as_ref(), self.v1_upstream_addr)
+        {
+            let key = session::V1SessionStore::key(
+                upstream,
+                &downstream_user,
+                &conn_details.device.dev_id.to_string(),
+            );
+            if let Some(key) = key {
+                if let session::Claim::Claimed(previous) = sessions.claim(&key) {
+                    session_id = previous;
+                    self.v1_session_key = Some(key);
+                }
+            }
+        }
+        let subscribe = v1::messages::Subscribe {
+            agent_signature: Self::v1_user_agent(&conn_details.device),
+            extra_nonce1: session_id,
+            url: Some(hostname_port),
+            port: None,
+        };
+
+        self.submit_v1_request_message(
+            subscribe,
+            Self::handle_subscribe_result,
+            Self::handle_authorize_or_subscribe_error,
+        )?;
+
+        if self.options.try_enable_xnsub && !self.v1_xnsub_enabled {
+            let extranonce_subscribe = v1::messages::ExtranonceSubscribe;
+            self.submit_v1_request_message(
+                extranonce_subscribe,
+                Self::handle_extranonce_subscribe_result,
+                Self::handle_extranonce_subscribe_error,
+            )
+            .map_err(V2ProtocolError::open_mining_channel)?;
+        }
+
+        let password = match self.v1_credentials.as_ref() {
+            Some(UpstreamCredentials {
+                password: Some(password),
+                ..
+            }) => password.clone(),
+            _ => self.v1_password.clone(),
+        };
+        let authorize = v1::messages::Authorize {
+            name: self.v1_user(&downstream_user),
+            password,
+        };
+        self.submit_v1_request_message(
+            authorize,
+            Self::handle_authorize_result,
+            Self::handle_authorize_or_subscribe_error,
+        )
+        .map_err(V2ProtocolError::open_mining_channel)?;
+        self.suggest_downstream_difficulty(&max_target)
+            .map_err(V2ProtocolError::open_mining_channel)?;
+        Ok(())
+    }
+
+    /// Worker name of the channel that is being opened. Nothing is sent upstream before the key
+    /// is known when `mining.configure` is deferred.
+    pub fn v1_routing_key(&self) -> Option<String> {
+        match self.state {
+            V2ToV1TranslationState::OpenStandardMiningChannelPending => self
+                .v2_channel_details
+                .as_ref()
+                .map(|channel_details| channel_details.user.to_string()),
+            _ => None,
+        }
+    }
+
+    fn build_v1_request_tracker(options: &V2ToV1TranslationOptions) -> V1RequestTracker {
+        let tracker = V1RequestTracker::new().with_timeout(options.v1_request_timeout);
+        match options.v1_submit_timeout {
+            Some(timeout) => tracker.with_method_timeout(v1::rpc::Method::Submit, timeout),
+            None => tracker,
+        }
+    }
+
     fn submit_v1_request_message<M>(
         &mut self,
         message: M,
@@ -334,7 +672,9 @@ impl V2ToV1Translation {
         let (req_id, request_rpc) =
             self.v1_method_into_message(message, result_handler, error_handler);
 
-        util::submit_message(&mut self.v1_tx, request_rpc).map_err(|e| {
+        let result = util::submit_message(&mut self.v1_tx, request_rpc);
+        self.account_submit_result(ChannelDirection::Upstream, &result);
+        result.map_err(|e| {
             debug!("Cannot submit request upstream: {:?}", e);
             UpstreamError::from(e)
         })?;
@@ -347,14 +687,16 @@ impl V2ToV1Translation {
         stratum_result: Option<v1::rpc::StratumResult>,
     ) -> Result<()> {
         use v1::rpc::{Response, Rpc};
-        util::submit_message(
+        let result = util::submit_message(
             &mut self.v1_tx,
             Rpc::Response(Response {
                 id: message_id.unwrap_or_default(),
                 stratum_result,
                 stratum_error: None,
             }),
-        )?;
+        );
+        self.account_submit_result(ChannelDirection::Upstream, &result);
+        result?;
         Ok(())
     }
 
@@ -363,13 +705,31 @@ impl V2ToV1Translation {
         M: TryInto<v2::Frame> + fmt::Debug + Clone,
         <M as TryInto<v2::Frame>>::Error: fmt::Debug,
     {
-        util::submit_message(&mut self.v2_tx, message).map_err(|e| {
+        let result = util::submit_message(&mut self.v2_tx, message);
+        self.account_submit_result(ChannelDirection::Downstream, &result);
+        result.map_err(|e| {
             debug!("Cannot submit message downstream: {}", e);
             DownstreamError::from(e)
         })?;
         Ok(())
     }
 
+    /// Counts submissions that failed because the translation channel was full, i.e. the
+    /// send task hasn't been able to keep up with the translation
+    fn account_submit_result<F>(
+        &self,
+        direction: ChannelDirection,
+        result: &std::result::Result<(), mpsc::TrySendError<F>>,
+    ) {
+        if let Err(e) = result {
+            if e.is_full() {
+                if let Some(metrics) = self.metrics.as_ref() {
+                    metrics.account_translation_channel_full(direction);
+                }
+            }
+        }
+    }
+
     /// Builds a V1 request from V1 method and assigns a unique identifier to it
     fn v1_method_into_message<M>(
         &mut self,
@@ -385,21 +745,11 @@ impl V2ToV1Translation {
             .try_into()
             .expect("BUG: Cannot convert V1 method into a message");
 
-        // TODO: decorate the request with a new unique ID -> this is the request ID
-        let id = self.v1_req_id.next_id();
+        let id = self.v1_requests.register(
+            payload.method,
+            V1CompoundHandler::new(result_handler, error_handler),
+        );
         trace!("Registering v1, request ID: {} method: {:?}", id, payload);
-        if self
-            .v1_req_map
-            .insert(
-                id,
-                V1CompoundHandler::new(result_handler, error_handler, payload.method),
-            )
-            .is_some()
-        {
-            error!("BUG: V1 id {} already exists...", id);
-            // TODO add graceful handling of this bug (shutdown?)
-            panic!("V1 id already exists");
-        }
 
         (
             id,
@@ -421,19 +771,21 @@ impl V2ToV1Translation {
         // when V1 authorization has already taken place, report channel opening success
         if let Some(v2_channel_details) = self.v2_channel_details.as_ref() {
             self.state = V2ToV1TranslationState::Operational;
+            self.channel_operational_since = Some(Instant::now());
             debug!("Switching mining channel to operational mode"; self.proxy_info);
             let msg = v2::messages::OpenStandardMiningChannelSuccess {
                 req_id: v2_channel_details.req_id,
-                channel_id: Self::CHANNEL_ID,
+                channel_id: self.v2_channel_id,
                 target: init_target,
                 extranonce_prefix: Bytes0_32::new(),
                 group_channel_id: Self::DEFAULT_GROUP_CHANNEL_ID,
             };
             self.submit_v2_message(msg)?;
 
-            // If mining.notify is pending, process it now as part of open channel finalization
+            // If mining.notify is pending, process it now as part of open channel finalization.
+            // Its latency is not observed as it includes waiting for the channel.
             if let Some(notify_payload) = self.v1_deferred_notify.take() {
-                self.perform_notify(&notify_payload)?;
+                self.perform_notify(&notify_payload, None)?;
             }
             Ok(())
         } else {
@@ -456,7 +808,7 @@ impl V2ToV1Translation {
         ));
 
         let msg = v2::messages::SetTarget {
-            channel_id: Self::CHANNEL_ID,
+            channel_id: self.v2_channel_id,
             max_target,
         };
 
@@ -516,35 +868,36 @@ impl V2ToV1Translation {
             self.proxy_info
         );
 
-        // TODO review the use of serde_json here, it may be possible to eliminate this dependency
-        // Extract version mask and verify it matches the maximum possible value
-        let proposed_version_mask: v1::messages::VersionMask =
-            serde_json::from_value(payload.0["version-rolling.mask"].clone())?;
+        let configure_result = v1::messages::ConfigureResult::try_from(payload)?;
+        self.v1_negotiated = v1::messages::NegotiatedFeatures::try_from(&configure_result)?;
+        trace!("Evaluating negotiated features: {:x?}", self.v1_negotiated; self.proxy_info);
 
-        trace!(
-            "Evaluating: version-rolling state == {:?} && mask=={:x?}",
-            payload.0["version-rolling"].as_bool(),
-            proposed_version_mask;
-            self.proxy_info
-        );
-        if payload.0["version-rolling"].as_bool() == Some(true)
-            && (proposed_version_mask.0).0 == ii_stratum::BIP320_N_VERSION_MASK
-        {
+        // Extranonce subscription granted via configure doesn't need `mining.extranonce.subscribe`
+        if self.v1_negotiated.subscribe_extranonce {
+            info!("Support for #xnsub enabled via mining.configure"; self.proxy_info);
+            self.v1_xnsub_enabled = true;
+        }
+        let version_mask_negotiated =
+            self.v1_negotiated.version_rolling_mask == Some(ii_stratum::BIP320_N_VERSION_MASK);
+        // Connection setup has been completed before configuring the upstream
+        if self.state != V2ToV1TranslationState::V1Configure {
+            return self.check_deferred_configure(version_mask_negotiated);
+        }
+        // Verify the version mask matches the maximum possible value
+        if version_mask_negotiated {
             self.state = V2ToV1TranslationState::ConnectionSetup;
 
-            self.submit_v2_message(v2::messages::SetupConnectionSuccess {
-                used_version: Self::PROTOCOL_VERSION as u16,
-                flags: 0,
-            })
+            self.submit_v2_message(self.build_setup_connection_success())
         } else {
             // TODO consolidate into abort_connection() + communicate shutdown of this
             // connection similarly everywhere in the code
-            self.submit_v2_message(v2::messages::SetupConnectionError {
-                flags: 0, // TODO handle flags
-                code: "Cannot negotiate upstream V1 version mask"
-                    .try_into()
+            self.submit_v2_message(
+                v2::messages::SetupConnectionError::builder()
+                    .flags(0) // TODO handle flags
+                    .code("Cannot negotiate upstream V1 version mask")
+                    .build()
                     .expect("BUG: incorrect error message"),
-            })
+            )
         }
     }
 
@@ -560,14 +913,92 @@ impl V2ToV1Translation {
             payload;
             self.proxy_info
         );
+        if self.state != V2ToV1TranslationState::V1Configure {
+            return self.check_deferred_configure(false);
+        }
         // TODO consolidate into abort_connection() + communicate shutdown of this
         // connection similarly everywhere in the code
-        self.submit_v2_message(v2::messages::SetupConnectionError {
-            flags: 0, // TODO handle flags
-            code: "Cannot negotiate upstream V1 version mask"
-                .try_into()
+        self.submit_v2_message(
+            v2::messages::SetupConnectionError::builder()
+                .flags(0) // TODO handle flags
+                .code("Cannot negotiate upstream V1 version mask")
+                .build()
                 .expect("BUG: incorrect error message"),
-        })
+        )
+    }
+
+    /// The downstream device has already been told that the connection is set up when
+    /// `mining.configure` is deferred, therefore, the channel is aborted and the session
+    /// terminated if the upstream doesn't support version rolling
+    fn check_deferred_configure(&mut self, version_mask_negotiated: bool) -> Result<()> {
+        if version_mask_negotiated {
+            return Ok(());
+        }
+        self.abort_open_channel("Cannot negotiate upstream V1 version mask");
+        Err(Error::General(
+            "Cannot negotiate upstream V1 version mask".to_string(),
+        ))
+    }
+
+    /// Asks the authenticator (if any) whether the channel may be opened, a denied channel is
+    /// reported downstream right away
+    async fn authorize_channel(
+        &mut self,
+        msg: &v2::messages::OpenStandardMiningChannel,
+    ) -> Result<bool> {
+        let (authenticator, source_addr) = match self.authenticator.as_ref() {
+            Some((authenticator, source_addr)) => (authenticator.clone(), *source_addr),
+            None => return Ok(true),
+        };
+        let device = match self.v2_conn_details.as_ref() {
+            Some(conn_details) => conn_details.device.clone(),
+            // Out of sequence channel, there is nothing to authorize
+            None => return Ok(true),
+        };
+        let request = AuthRequest {
+            user: msg.user.to_string(),
+            device,
+            source_addr,
+        };
+        let reason = match authenticator.authenticate(&request).await {
+            Ok(AuthDecision::Allow) => return Ok(true),
+            Ok(AuthDecision::Deny(reason)) => reason,
+            Err(e) => format!("authentication failed: {}", e),
+        };
+        info!(
+            "Channel of user {} from {} denied: {}",
+            request.user,
+            source_addr,
+            reason;
+            self.proxy_info
+        );
+        self.submit_v2_message(
+            v2::messages::OpenMiningChannelError::builder()
+                .req_id(msg.req_id)
+                .code(v2::error_codes::OpenMiningChannelErrorCode::UnknownUser)
+                .build()
+                .expect("BUG: incorrect error message"),
+        )
+        .map_err(V2ProtocolError::open_mining_channel)?;
+        Ok(false)
+    }
+
+    /// Builds `mining.configure` requesting version rolling (and extranonce subscription if
+    /// enabled)
+    fn build_v1_configure(&self) -> v1::messages::Configure {
+        let mut configure = v1::messages::Configure::new();
+        configure
+            .add_feature(v1::messages::VersionRolling::new(
+                ii_stratum::BIP320_N_VERSION_MASK,
+                ii_stratum::BIP320_N_VERSION_MAX_BITS,
+            ))
+            .expect("BUG: addfeature failed"); // FIXME: how to handle errors from configure.add_feature() ?
+        if self.options.try_enable_xnsub {
+            configure
+                .add_feature(v1::messages::SubscribeExtranonce)
+                .expect("BUG: addfeature failed");
+        }
+        configure
     }
 
     fn handle_extranonce_subscribe_result(
@@ -621,6 +1052,11 @@ impl V2ToV1Translation {
 
         self.v1_extra_nonce1 = Some(subscribe_result.extra_nonce_1().clone());
         self.v1_extra_nonce2_size = subscribe_result.extra_nonce_2_size();
+        if let (Some(sessions), Some(key)) =
+            (self.v1_sessions.as_ref(), self.v1_session_key.as_ref())
+        {
+            sessions.set_session_id(key, subscribe_result.session_id());
+        }
 
         // In order to finalize the opening procedure we need 3 items: authorization,
         // subscription and difficulty
@@ -689,6 +1125,12 @@ impl V2ToV1Translation {
             payload;
             self.proxy_info
         );
+        // The upstream may refuse to resume the session, next subscribe starts a new one
+        if let (Some(sessions), Some(key)) =
+            (self.v1_sessions.as_ref(), self.v1_session_key.as_ref())
+        {
+            sessions.set_session_id(key, None);
+        }
         // Only the first of authorize or subscribe error issues the OpenMiningChannelError message
         if self.state != V2ToV1TranslationState::V1SubscribeOrAuthorizeFail {
             trace!(
@@ -742,18 +1184,14 @@ impl V2ToV1Translation {
 
                 if bool_result.0 {
                     debug!("Share accepted: SESSION {}", self.session_details(); self.proxy_info);
-                    if let Some(metrics) = self.metrics.as_ref() {
-                        metrics.account_accepted_share(self.v2_target);
-                    }
-                    // TODO what if v2_target > 2**64 - 1?
-                    self.accept_shares(
-                        id,
-                        self.v2_target.expect("BUG: difficulty missing").low_u64(),
-                    )
+                    self.accept_shares(id)
                 } else {
                     info!("Share rejected for {}", v2_channel_details.user.to_string(); self.proxy_info);
+                    if let Some(metrics) = self.metrics.as_ref() {
+                        metrics.account_upstream_rejected_share(None);
+                    }
                     self.reject_shares(
-                        Self::CHANNEL_ID,
+                        self.v2_channel_id,
                         SeqNum::V1(*id),
                         format!("ShareRjct:{:?}", payload),
                     )
@@ -776,11 +1214,16 @@ impl V2ToV1Translation {
             payload;
             self.proxy_info
         );
-        self.reject_shares(
-            Self::CHANNEL_ID,
-            SeqNum::V1(*id),
-            format!("ShareRjct:{:?}", payload),
-        )
+        // Reasons that aren't recognized are passed downstream as they are
+        let code = reject::classify(payload);
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.account_upstream_rejected_share(code);
+        }
+        let err_msg = match code {
+            Some(code) => code.to_string(),
+            None => format!("ShareRjct:{:?}", payload),
+        };
+        self.reject_shares(self.v2_channel_id, SeqNum::V1(*id), err_msg)
     }
 
     /// Iterates the merkle branches and calculates block merkle root using the extra nonce 1.
@@ -788,44 +1231,28 @@ impl V2ToV1Translation {
     /// TODO review, whether a Result has to be returned as missing enonce1 would be considered a bug
     fn calculate_merkle_root(
         &mut self,
-        payload: &v1::messages::Notify,
+        job: &v1::messages::MiningJob,
     ) -> crate::error::Result<sha256d::Hash> {
-        // TODO get rid of extra nonce 1 cloning
-        if let Some(v1_extra_nonce1) = self.v1_extra_nonce1.clone() {
-            // Build coin base transaction,
-            let mut coin_base: BytesMut = BytesMut::with_capacity(
-                payload.coin_base_1().len()
-                    + (v1_extra_nonce1.0).len()
-                    + self.v1_extra_nonce2_size
-                    + payload.coin_base_2().len(),
-            );
-            coin_base.extend_from_slice(payload.coin_base_1());
-            coin_base.extend_from_slice(v1_extra_nonce1.0.as_ref());
-            coin_base.extend_from_slice(
-                Self::channel_to_extra_nonce2_bytes(Self::CHANNEL_ID, self.v1_extra_nonce2_size)
+        match self.v1_extra_nonce1.as_ref() {
+            Some(v1_extra_nonce1) => {
+                let merkle_root = job.merkle_root(
+                    v1_extra_nonce1.0.as_ref(),
+                    Self::channel_to_extra_nonce2_bytes(
+                        self.v2_channel_id,
+                        self.v1_extra_nonce2_size,
+                    )
                     .as_ref(),
-            );
-            coin_base.extend_from_slice(payload.coin_base_2());
-
-            let mut engine = sha256d::Hash::engine();
-            engine.input(&coin_base);
-
-            let cb_tx_hash = sha256d::Hash::from_engine(engine);
-            trace!("Coinbase TX hash: {:x?} {:x?}", cb_tx_hash, coin_base);
-
-            let merkle_root = payload
-                .merkle_branch()
-                .fold_branch_into_merkle_root(cb_tx_hash);
-            trace!("Merkle root calculated: {:x?}", merkle_root);
-            Ok(merkle_root)
-        } else {
-            Err(Error::General(
+                );
+                trace!("Merkle root calculated: {:x?}", merkle_root);
+                Ok(merkle_root)
+            }
+            None => Err(Error::General(
                 "Extra nonce 1 missing, cannot calculate merkle root".into(),
-            ))
+            )),
         }
     }
 
-    /// Builds SetNewPrevHash for the specified v1 Notify `payload`
+    /// Builds SetNewPrevHash for the specified v1 mining `job`
     ///
     /// The SetNewPrevHash has to reference the future job that the V2 downstream has
     /// previously received from us.
@@ -841,20 +1268,15 @@ impl V2ToV1Translation {
     fn build_set_new_prev_hash(
         &self,
         job_id: u32,
-        payload: &v1::messages::Notify,
-    ) -> crate::error::Result<v2::messages::SetNewPrevHash> {
-        // TODO review how this can be prevented from failing. If this fails, it should result in
-        // panic as it marks a software bug
-        let prev_hash = sha256d::Hash::from_slice(payload.prev_hash())?;
-        let prev_hash = Uint256Bytes(prev_hash.into_inner());
-
-        Ok(v2::messages::SetNewPrevHash {
-            channel_id: Self::CHANNEL_ID,
-            prev_hash,
-            min_ntime: payload.time(),
-            nbits: payload.bits(),
+        job: &v1::messages::MiningJob,
+    ) -> v2::messages::SetNewPrevHash {
+        v2::messages::SetNewPrevHash {
+            channel_id: self.v2_channel_id,
+            prev_hash: Uint256Bytes(job.prev_hash),
+            min_ntime: job.time,
+            nbits: job.bits,
             job_id,
-        })
+        }
     }
 
     /// Converts specified `channel_id` into extra nonce 2 with a specified
@@ -881,58 +1303,365 @@ impl V2ToV1Translation {
         extra_nonce2
     }
 
-    /// Scan the submit share queue if the front item is a `SubmitSharesError`, send out all
-    /// shares error messages
+    /// Scan the submit share queue and send out all share responses from its front that are
+    /// ready, i.e. stop at the first share that still waits for the upstream
     fn submit_queued_share_responses(&mut self) -> Result<()> {
         loop {
             match self.v2_submit_share_queue.front() {
-                Some(SubmitShare::SubmitSharesError(_)) => {}
+                Some(SubmitShare::SubmitSharesError(_))
+                | Some(SubmitShare::SubmitSharesSuccess(_)) => {}
                 _ => return Ok(()),
             }
             match self.v2_submit_share_queue.pop_front() {
                 Some(SubmitShare::SubmitSharesError(submit_shares_error_msg)) => {
                     self.submit_v2_message(submit_shares_error_msg)?;
                 }
+                Some(SubmitShare::SubmitSharesSuccess(submit_shares_success_msg)) => {
+                    self.submit_v2_message(submit_shares_success_msg)?;
+                }
                 _ => panic!("BUG: unexpected submit share item"),
             }
         }
     }
 
-    fn submit_share_response<T>(&mut self, msg: T) -> Result<()>
-    where
-        T: TryInto<v2::Frame> + fmt::Debug + Clone,
-        <T as TryInto<v2::Frame>>::Error: fmt::Debug,
-    {
-        self.submit_v2_message(msg)?;
+    fn submit_window_full(&self) -> bool {
+        matches!(self.options.max_submits_in_flight,
+            Some(max_submits) if self.v1_submits_in_flight >= max_submits)
+    }
+
+    /// Sends the share upstream unless the window of submits in flight is full. In that case,
+    /// the share is deferred until some of the outstanding submits is answered or rejected when
+    /// too many shares are deferred already.
+    fn submit_share_upstream(&mut self, seq_num: u32, submit: v1::messages::Submit) -> Result<()> {
+        let share = if self.submit_window_full() {
+            if self.v2_deferred_shares >= Self::MAX_DEFERRED_SHARES {
+                return self.reject_shares(
+                    self.v2_channel_id,
+                    SeqNum::V2(seq_num),
+                    "too-many-deferred-shares".to_string(),
+                );
+            }
+            self.v2_deferred_shares += 1;
+            SubmitShare::Deferred(seq_num, submit, self.v2_target)
+        } else {
+            let v1_seq_num = self.submit_v1_request_message(
+                submit,
+                Self::handle_submit_result,
+                Self::handle_submit_error,
+            )?;
+            self.v1_submits_in_flight += 1;
+            SubmitShare::V1ToV2Mapping(v1_seq_num, seq_num, self.v2_target)
+        };
+        self.v2_submit_share_queue.push_back(share);
+        Ok(())
+    }
+
+    /// Sends deferred shares upstream as long as the window of submits in flight allows it.
+    /// Shares that cannot be sent are rejected.
+    fn submit_deferred_shares(&mut self) -> Result<()> {
+        let mut position = 0;
+        while position < self.v2_submit_share_queue.len()
+            && self.v2_deferred_shares > 0
+            && !self.submit_window_full()
+        {
+            if let SubmitShare::Deferred(seq_num, submit, target) =
+                &self.v2_submit_share_queue[position]
+            {
+                let (seq_num, submit, target) = (*seq_num, submit.clone(), *target);
+                self.v2_deferred_shares -= 1;
+                let share = match self.submit_v1_request_message(
+                    submit,
+                    Self::handle_submit_result,
+                    Self::handle_submit_error,
+                ) {
+                    Ok(v1_seq_num) => {
+                        self.v1_submits_in_flight += 1;
+                        SubmitShare::V1ToV2Mapping(v1_seq_num, seq_num, target)
+                    }
+                    Err(e) => SubmitShare::SubmitSharesError(self.build_shares_error(
+                        self.v2_channel_id,
+                        seq_num,
+                        e.to_string(),
+                        target,
+                    )),
+                };
+                self.v2_submit_share_queue[position] = share;
+            }
+            position += 1;
+        }
         self.submit_queued_share_responses()
     }
 
-    /// Helper that converts id of V1 mining.submit message to V2 sequence
-    /// number of a submit shares message
-    fn get_v2_submit_shares_seq_num(&mut self, id: &v1::MessageId) -> Result<u32> {
+    /// Helper that finds the submitted share that corresponds to id of V1 mining.submit message.
+    /// Returns its position in the queue, the V2 sequence number of a submit shares message and
+    /// the downstream target of the share
+    fn find_submitted_share(&self, id: &v1::MessageId) -> Result<(usize, u32, Option<U256>)> {
         let id = id.ok_or(Error::General(
             "Missing V1 message id for 'mining.submit' response".to_string(),
         ))?;
-        match self.v2_submit_share_queue.pop_front() {
-            Some(SubmitShare::V1ToV2Mapping(v1_seq_num, v2_seq_num)) if id == v1_seq_num => {
-                Ok(v2_seq_num)
+        self.v2_submit_share_queue
+            .iter()
+            .enumerate()
+            .find_map(|(position, share)| match share {
+                SubmitShare::V1ToV2Mapping(v1_seq_num, v2_seq_num, target) if id == *v1_seq_num => {
+                    Some((position, *v2_seq_num, *target))
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                Error::General(format!(
+                    "Unexpected V1 message id ({}) in 'mining.submit' response",
+                    id
+                ))
+            })
+    }
+
+    /// Resolves the share submitted as V1 request `id` with a response built by `build_response`
+    /// (that receives the V2 sequence number and the downstream target of the share). Responses
+    /// that are ready are sent downstream and the slot in the window of submits in flight is
+    /// reused for deferred shares.
+    fn resolve_submitted_share<F>(&mut self, id: &v1::MessageId, build_response: F) -> Result<()>
+    where
+        F: FnOnce(&mut Self, u32, Option<U256>) -> SubmitShare,
+    {
+        let (position, seq_num, target) = self.find_submitted_share(id)?;
+        self.v1_submits_in_flight -= 1;
+        self.v2_submit_share_queue[position] = build_response(self, seq_num, target);
+        self.submit_queued_share_responses()?;
+        self.submit_deferred_shares()
+    }
+
+    /// Accounts the share submitted as V1 request `id` as accepted and builds the success reply
+    /// to the client
+    fn accept_shares(&mut self, id: &v1::MessageId) -> Result<()> {
+        self.resolve_submitted_share(id, |translation, seq_num, target| {
+            if let Some(metrics) = translation.metrics.as_ref() {
+                metrics.account_accepted_share(target);
             }
-            _ => Err(Error::General(format!(
-                "Unexpected V1 message id ({}) in 'mining.submit' response",
-                id
-            ))),
+            if let Some(session_stats) = translation.session_stats.as_ref() {
+                session_stats.account_accepted_share();
+            }
+            translation.account_worker_share(true, target);
+            translation.export_share_event(None, target);
+            // TODO what if target > 2**64 - 1?
+            let new_shares = target.expect("BUG: difficulty missing").low_u64();
+            SubmitShare::SubmitSharesSuccess(v2::messages::SubmitSharesSuccess {
+                channel_id: translation.v2_channel_id,
+                last_seq_num: seq_num,
+                new_submits_accepted_count: 1,
+                new_shares_sum: new_shares as u32,
+            })
+        })
+    }
+
+    /// Accounts a share of `target` to the user of the mining channel
+    fn account_worker_share(&self, accepted: bool, target: Option<U256>) {
+        if let (Some(share_accounting), Some(channel_details)) = (
+            self.share_accounting.as_ref(),
+            self.v2_channel_details.as_ref(),
+        ) {
+            let difficulty = Self::share_difficulty(target);
+            let worker = channel_details.user.to_string();
+            if accepted {
+                share_accounting.account_accepted_share(&worker, difficulty);
+            } else {
+                share_accounting.account_rejected_share(&worker, difficulty);
+            }
+        }
+    }
+
+    /// Exports a share of `target`, the share has been rejected if `rejection_reason` is present
+    fn export_share_event(&self, rejection_reason: Option<&str>, target: Option<U256>) {
+        if let (Some((event_exporter, source_addr)), Some(channel_details)) = (
+            self.event_exporter.as_ref(),
+            self.v2_channel_details.as_ref(),
+        ) {
+            let source_addr = *source_addr;
+            let user = channel_details.user.to_string();
+            let difficulty = Self::share_difficulty(target);
+            event_exporter.export(match rejection_reason {
+                None => Event::ShareAccepted {
+                    source_addr,
+                    user,
+                    difficulty,
+                },
+                Some(reason) => Event::ShareRejected {
+                    source_addr,
+                    user,
+                    difficulty,
+                    reason: reason.to_string(),
+                },
+            });
+        }
+    }
+
+    /// Picks the highest protocol version supported by both the downstream and the translation
+    fn negotiate_protocol_version(min_version: u16, max_version: u16) -> Option<u16> {
+        let version = max_version.min(Self::MAX_PROTOCOL_VERSION);
+        if version >= min_version.max(Self::MIN_PROTOCOL_VERSION) {
+            Some(version)
+        } else {
+            None
+        }
+    }
+
+    /// Success of `SetupConnection` with the negotiated protocol version
+    fn build_setup_connection_success(&self) -> v2::messages::SetupConnectionSuccess {
+        v2::messages::SetupConnectionSuccess {
+            used_version: self
+                .v2_protocol_version
+                .expect("BUG: protocol version not negotiated"),
+            flags: 0,
         }
     }
 
-    fn accept_shares(&mut self, id: &v1::MessageId, new_shares: u64) -> Result<()> {
-        let success_msg = v2::messages::SubmitSharesSuccess {
-            channel_id: Self::CHANNEL_ID,
-            last_seq_num: self.get_v2_submit_shares_seq_num(id)?,
-            new_submits_accepted_count: 1,
-            new_shares_sum: new_shares as u32,
+    /// User agent of the upstream session that identifies the device, e.g.
+    /// `Braiins OS 2019-06-05 (Braiins; S9; xyz)`, empty fields are left out
+    fn v1_user_agent(device: &v2::types::DeviceInfo) -> Option<String> {
+        let details: Vec<&str> = [&device.vendor, &device.hw_rev, &device.dev_id]
+            .iter()
+            .map(|field| field.as_str())
+            .filter(|field| !field.is_empty())
+            .collect();
+        let fw_ver = device.fw_ver.as_str();
+        match (fw_ver.is_empty(), details.is_empty()) {
+            (true, true) => None,
+            (false, true) => Some(fw_ver.to_string()),
+            (true, false) => Some(format!("({})", details.join("; "))),
+            (false, false) => Some(format!("{} ({})", fw_ver, details.join("; "))),
+        }
+    }
+
+    /// Submitted `ntime` must not precede `min_ntime` of the job nor exceed the rolling window
+    fn is_ntime_valid(&self, ntime: u32, min_ntime: u32) -> bool {
+        let max_ntime_roll = self
+            .options
+            .max_ntime_roll
+            .unwrap_or(Self::DEFAULT_MAX_NTIME_ROLL)
+            .as_secs();
+        ntime >= min_ntime && u64::from(ntime - min_ntime) <= max_ntime_roll
+    }
+
+    /// Limits the difficulty assigned downstream to the configured range
+    fn clamp_difficulty(&self, diff: u32) -> u32 {
+        let clamped = match (self.options.min_difficulty, self.options.max_difficulty) {
+            (Some(min), _) if diff < min => min,
+            (_, Some(max)) if diff > max => max,
+            _ => diff,
         };
+        if clamped != diff {
+            debug!(
+                "Upstream difficulty {} clamped to {}",
+                diff,
+                clamped;
+                self.proxy_info
+            );
+        }
+        clamped
+    }
 
-        self.submit_share_response(success_msg)
+    /// Sends `mining.suggest_difficulty` upstream unless the same difficulty has already been
+    /// suggested. Pools that don't support the method may not answer at all, therefore, the
+    /// request doesn't terminate the session when it times out.
+    fn suggest_v1_difficulty(&mut self, diff: u32) -> Result<()> {
+        if self.v1_suggested_difficulty == Some(diff) {
+            return Ok(());
+        }
+        debug!("Suggesting difficulty {} upstream", diff; self.proxy_info);
+        self.v1_suggested_difficulty = Some(diff);
+        self.submit_v1_request_message(
+            v1::messages::SuggestDifficulty::from(diff as f32),
+            Self::handle_suggest_difficulty_result,
+            Self::handle_suggest_difficulty_error,
+        )
+        .map(|_| ())
+    }
+
+    /// Forwards the difficulty corresponding to `max_target` requested by the downstream device
+    /// (limited to the configured range) upstream. A maximum target at or above the difficulty 1
+    /// target carries no hint.
+    fn suggest_downstream_difficulty(&mut self, max_target: &Uint256Bytes) -> Result<()> {
+        let diff = max_target.difficulty().round().min(u32::MAX as f64) as u32;
+        if diff <= 1 {
+            return Ok(());
+        }
+        let diff = self.clamp_difficulty(diff);
+        self.suggest_v1_difficulty(diff)
+    }
+
+    #[allow(clippy::unnecessary_wraps)]
+    fn handle_suggest_difficulty_result(
+        &mut self,
+        _id: &v1::MessageId,
+        payload: &v1::rpc::StratumResult,
+    ) -> Result<()> {
+        trace!("Difficulty suggestion answered: {:?}", payload; self.proxy_info);
+        Ok(())
+    }
+
+    #[allow(clippy::unnecessary_wraps)]
+    fn handle_suggest_difficulty_error(
+        &mut self,
+        _id: &v1::MessageId,
+        payload: &v1::rpc::StratumError,
+    ) -> Result<()> {
+        debug!("Upstream refused difficulty suggestion: {:?}", payload; self.proxy_info);
+        Ok(())
+    }
+
+    /// Checks shares locally when the downstream target has been clamped below the difficulty
+    /// of the upstream (see `max_difficulty`). Shares that meet only the downstream target are
+    /// acknowledged without being submitted so that the upstream isn't flooded with shares it
+    /// would reject. Returns true if the share has been answered.
+    fn handle_share_below_upstream_target(
+        &mut self,
+        msg: &v2::messages::SubmitSharesStandard,
+        template: &V1SubmitTemplate,
+    ) -> Result<bool> {
+        let (downstream_target, upstream_target) = match (self.v2_target, template.upstream_target)
+        {
+            (Some(downstream), Some(upstream)) if downstream > upstream => (downstream, upstream),
+            _ => return Ok(false),
+        };
+        let header = v2::job::block_header(
+            msg.version,
+            &Uint256Bytes(template.prev_hash),
+            &Uint256Bytes(template.merkle_root),
+            msg.ntime,
+            template.bits,
+            msg.nonce,
+        );
+        let hash = U256::from_little_endian(&header.hash().into_inner());
+        if hash <= upstream_target {
+            return Ok(false);
+        }
+        if hash > downstream_target {
+            self.reject_shares(
+                msg.channel_id,
+                SeqNum::V2(msg.seq_num),
+                v2::error_codes::SubmitSharesErrorCode::DifficultyTooLow.to_string(),
+            )?;
+        } else {
+            trace!("Share below upstream target acknowledged locally"; self.proxy_info);
+            self.v2_submit_share_queue
+                .push_back(SubmitShare::SubmitSharesSuccess(
+                    v2::messages::SubmitSharesSuccess {
+                        channel_id: self.v2_channel_id,
+                        last_seq_num: msg.seq_num,
+                        new_submits_accepted_count: 1,
+                        new_shares_sum: downstream_target.low_u32(),
+                    },
+                ));
+            self.submit_queued_share_responses()?;
+        }
+        Ok(true)
+    }
+
+    /// Difficulty of `target` rounded to an integer, saturated at `u128::MAX`
+    fn share_difficulty(target: Option<U256>) -> u128 {
+        target.map_or(0, |target| {
+            Uint256Bytes::from(target).difficulty().round() as u128
+        })
     }
 
     /// Generates log trace entry and reject shares error reply to the client
@@ -946,15 +1675,45 @@ impl V2ToV1Translation {
         seq_num_variant: SeqNum,
         err_msg: String,
     ) -> Result<()> {
+        match seq_num_variant {
+            SeqNum::V1(id) => self.resolve_submitted_share(&id, |translation, seq_num, target| {
+                SubmitShare::SubmitSharesError(
+                    translation.build_shares_error(channel_id, seq_num, err_msg, target),
+                )
+            }),
+            SeqNum::V2(seq_num) => {
+                // Shares rejected by the proxy itself are of the current target
+                let submit_shares_error_msg =
+                    self.build_shares_error(channel_id, seq_num, err_msg, self.v2_target);
+                if self.v2_submit_share_queue.is_empty() {
+                    self.submit_v2_message(submit_shares_error_msg)
+                } else {
+                    self.v2_submit_share_queue
+                        .push_back(SubmitShare::SubmitSharesError(submit_shares_error_msg));
+                    Ok(())
+                }
+            }
+        }
+    }
+
+    /// Accounts the rejected share of `target` and builds the error reply to the client
+    fn build_shares_error(
+        &self,
+        channel_id: u32,
+        seq_num: u32,
+        err_msg: String,
+        target: Option<U256>,
+    ) -> v2::messages::SubmitSharesError {
         trace!("{}", err_msg; self.proxy_info);
-        let (seq_num, submit) = match seq_num_variant {
-            SeqNum::V1(id) => (self.get_v2_submit_shares_seq_num(&id)?, true),
-            SeqNum::V2(value) => (value, self.v2_submit_share_queue.is_empty()),
-        };
         if let Some(metrics) = self.metrics.as_ref() {
-            metrics.account_rejected_share(self.v2_target);
+            metrics.account_rejected_share(target);
+        }
+        if let Some(session_stats) = self.session_stats.as_ref() {
+            session_stats.account_rejected_share();
         }
-        let submit_shares_error_msg = v2::messages::SubmitSharesError {
+        self.account_worker_share(false, target);
+        self.export_share_event(Some(&err_msg), target);
+        v2::messages::SubmitSharesError {
             channel_id,
             seq_num,
             code: err_msg[..err_msg.len().min(32)]
@@ -965,61 +1724,75 @@ impl V2ToV1Translation {
                         err_msg
                     )
                 }),
-        };
+        }
+    }
 
-        if submit {
-            self.submit_share_response(submit_shares_error_msg)
-        } else {
-            self.v2_submit_share_queue
-                .push_back(SubmitShare::SubmitSharesError(submit_shares_error_msg));
-            Ok(())
+    /// Accounts a message that has failed the upstream sanity checks and converts the failure
+    /// into an error that terminates the session
+    fn reject_upstream_message(&self, e: InvalidUpstreamMessage) -> Error {
+        warn!("Rejecting upstream message: {}", e; self.proxy_info);
+        if let Some(metrics) = self.metrics.as_ref() {
+            metrics.account_invalid_upstream_message(&e);
         }
+        e.into()
     }
 
-    fn perform_notify(&mut self, payload: &v1::messages::Notify) -> Result<()> {
-        let merkle_root = self.calculate_merkle_root(payload)?;
+    /// Translates `payload` into a V2 job, its latency is measured from `received` (if present)
+    fn perform_notify(
+        &mut self,
+        payload: &v1::messages::Notify,
+        received: Option<Instant>,
+    ) -> Result<()> {
+        // Reject malformed jobs before touching any translation state
+        let job = v1::messages::MiningJob::try_from(payload)?;
+        let merkle_root = self.calculate_merkle_root(&job)?;
 
         let v2_job = v2::messages::NewMiningJob {
-            channel_id: Self::CHANNEL_ID,
+            channel_id: self.v2_channel_id,
             job_id: self.v2_job_id.next_id(),
             future_job: self.v2_to_v1_job_map.is_empty()
-                || payload.clean_jobs()
+                || job.clean_jobs
                 || self.v1_force_future_jobs,
             merkle_root: Uint256Bytes(merkle_root.into_inner()),
-            version: payload.version(),
+            version: job.version,
         };
 
         // Make sure we generate new prev hash. Empty JobMap means this is the first mining.notify
         // message and we also have to issue NewPrevHash. In addition to that, we also check the
         // clean jobs flag that indicates a must for new prev hash, too.
         let maybe_set_new_prev_hash = if v2_job.future_job {
-            // Clean the job map only if V1 indicates new prev hash.
-            if payload.clean_jobs() {
-                self.v2_to_v1_job_map.clear();
-            }
-            // Any error means immediate termination
-            // TODO write a unit test for such scenario, too
-            Some(self.build_set_new_prev_hash(v2_job.job_id, payload)?)
+            Some(self.build_set_new_prev_hash(v2_job.job_id, &job))
         } else {
             None
         };
+        // Jobs of the previous block are forgotten once their grace period elapses
+        let now = Instant::now();
+        if job.clean_jobs || matches!(self.v2_prev_hash, Some(hash) if hash != job.prev_hash) {
+            self.v2_to_v1_job_map.invalidate_all(now);
+        }
+        self.v2_prev_hash = Some(job.prev_hash);
         trace!(
-            "Registering V2 job ID {:x?} -> V1 job ID {:x?}",
+            "Registering V2 job ID {:x?} -> V1 job ID {:x?} ({} jobs registered)",
             v2_job.job_id,
-            payload.job_id();
+            job.job_id,
+            self.v2_to_v1_job_map.len();
             self.proxy_info
         );
-        // TODO extract this duplicate code, turn the map into a new type with this
-        // custom policy (attempt to insert with the same key is a bug)
+        // Attempt to insert with the same key is a bug
         if self
             .v2_to_v1_job_map
             .insert(
                 v2_job.job_id,
                 V1SubmitTemplate {
-                    job_id: v1::messages::JobId::from_str(payload.job_id())?,
-                    time: payload.time(),
-                    version: payload.version(),
+                    job_id: v1::messages::JobId::from_str(&job.job_id)?,
+                    time: job.time,
+                    version: job.version,
+                    prev_hash: job.prev_hash,
+                    merkle_root: merkle_root.into_inner(),
+                    bits: job.bits,
+                    upstream_target: self.v1_target,
                 },
+                now,
             )
             .is_some()
         {
@@ -1028,6 +1801,10 @@ impl V2ToV1Translation {
             panic!("V2 id already exists");
         }
 
+        // The job has to be registered before its frame can be written
+        if let Some(job_latency) = self.job_latency.as_ref() {
+            job_latency.job_queued(received);
+        }
         self.submit_v2_message(v2_job)?;
 
         if let Some(set_new_prev_hash) = maybe_set_new_prev_hash {
@@ -1044,37 +1821,43 @@ impl V2ToV1Translation {
         id: &'a v1::MessageId,
         payload: V1ResultOrError<'a>,
     ) -> Result<()> {
-        // Each response message should have an ID for pairing
-        id.ok_or(Error::from(stratum::Error::from(v1::error::Error::Rpc(
-            "Missing ID in ii_stratum result".to_string(),
-        ))))
-        // find the ID in the request map
-        .and_then(|id| {
-            self.v1_req_map.remove(&id).ok_or_else(|| {
-                Error::from(stratum::Error::from(v1::error::Error::Rpc(format!(
-                    "Received invalid ID {}",
-                    id
-                ))))
-            })
-        })
+        // Responses to submits that have already been rejected due to a timeout are dropped
+        if let Some(position) = id.and_then(|id| {
+            self.v1_expired_requests
+                .iter()
+                .position(|expired_id| *expired_id == id)
+        }) {
+            self.v1_expired_requests.remove(position);
+            debug!("Ignoring late response to timed out mining.submit, ID: {:?}", id; self.proxy_info);
+            return Ok(());
+        }
+        // Pair the response with the original request (this fails when the ID is missing or
+        // unknown)
+        let request = self.v1_requests.complete(*id)?;
+        let request_method = request.method;
+        let req_duration = request.elapsed;
+        let handler = request.context;
         // run the result through the result handler
-        .and_then(|handler| {
-            let req_duration = handler.elapsed();
-            match payload {
-                V1ResultOrError::Result(r) => {
-                    if let Some(m) = self.metrics.as_ref() {
-                        m.observe_v1_request_success(handler.request_method, req_duration);
-                    };
-                    (handler.v1_stratum_result_handler)(self, id, r)
-                }
-                V1ResultOrError::Error(e) => {
-                    if let Some(m) = self.metrics.as_ref() {
-                        m.observe_v1_request_error(handler.request_method, req_duration);
-                    };
-                    (handler.v1_stratum_error_handler)(self, id, e)
-                }
+        match payload {
+            V1ResultOrError::Result(r) => {
+                if let Some(m) = self.metrics.as_ref() {
+                    m.observe_v1_request_success(request_method, req_duration);
+                    if request_method == v1::rpc::Method::Submit {
+                        m.observe_submit_latency(self.v1_upstream_addr, req_duration, true);
+                    }
+                };
+                (handler.v1_stratum_result_handler)(self, id, r)
             }
-        })
+            V1ResultOrError::Error(e) => {
+                if let Some(m) = self.metrics.as_ref() {
+                    m.observe_v1_request_error(request_method, req_duration);
+                    if request_method == v1::rpc::Method::Submit {
+                        m.observe_submit_latency(self.v1_upstream_addr, req_duration, false);
+                    }
+                };
+                (handler.v1_stratum_error_handler)(self, id, e)
+            }
+        }
     }
 
     /// Parse the stratum V1 reconnect message into new host/port pair, where host is
@@ -1140,6 +1923,107 @@ impl V2ToV1Translation {
             user, v2_connection_details, last_submit,
         )
     }
+
+    /// Asks the downstream device to connect elsewhere (e.g. to another proxy instance). The
+    /// request is ignored when the downstream hasn't set up the connection yet.
+    pub fn reconnect_downstream(&mut self, reconnect: v2::messages::Reconnect) -> Result<()> {
+        match self.state {
+            V2ToV1TranslationState::Init | V2ToV1TranslationState::V1Configure => {
+                debug!("Connection not set up yet, ignoring {:?}", reconnect; self.proxy_info);
+                Ok(())
+            }
+            _ => {
+                info!("Reconnecting downstream: {:?}", reconnect; self.proxy_info);
+                self.submit_v2_message(reconnect)
+            }
+        }
+    }
+
+    /// Strictness of parsing of V1 frames received for this translation
+    pub fn v1_parse_mode(&self) -> v1::rpc::ParseMode {
+        self.options.v1_parse_mode
+    }
+
+    /// Rejects shares whose `mining.submit` hasn't been answered within the submit timeout.
+    /// Fails when upstream hasn't answered any other V1 request within the configured timeout.
+    pub fn check_v1_request_timeouts(&mut self) -> Result<()> {
+        let now = std::time::Instant::now();
+        // Unanswered difficulty suggestions are forgotten
+        for id in self
+            .v1_requests
+            .take_expired(v1::rpc::Method::SuggestDifficulty, now)
+        {
+            if self.v1_expired_requests.len() >= Self::MAX_EXPIRED_REQUESTS {
+                self.v1_expired_requests.pop_front();
+            }
+            self.v1_expired_requests.push_back(id);
+        }
+        if self.options.v1_submit_timeout.is_some() {
+            for id in self.v1_requests.take_expired(v1::rpc::Method::Submit, now) {
+                if self.v1_expired_requests.len() >= Self::MAX_EXPIRED_REQUESTS {
+                    self.v1_expired_requests.pop_front();
+                }
+                self.v1_expired_requests.push_back(id);
+                self.reject_shares(
+                    self.v2_channel_id,
+                    SeqNum::V1(Some(id)),
+                    "upstream-timeout".to_string(),
+                )?;
+            }
+        }
+        self.v1_requests
+            .check_timeouts(now)
+            .map_err(|e| UpstreamError::Stratum(e).into())
+    }
+
+    /// Closes the channel with `CloseChannel` if it has been operational for the configured
+    /// idle timeout without submitting any shares. The returned error indicates that the
+    /// session should be terminated.
+    pub fn check_idle_channel(&mut self) -> Result<()> {
+        let idle_channel_timeout = match self.options.idle_channel_timeout {
+            Some(timeout) if self.state == V2ToV1TranslationState::Operational => timeout,
+            _ => return Ok(()),
+        };
+        // Idle period starts with the last submit or the channel opening if nothing has been
+        // submitted yet
+        let idle_since = match self.last_submit.or(self.channel_operational_since) {
+            Some(idle_since) => idle_since,
+            None => return Ok(()),
+        };
+        let idle_duration = idle_since.elapsed();
+        if idle_duration < idle_channel_timeout {
+            return Ok(());
+        }
+
+        info!(
+            "Closing channel with no submitted shares for {:.1}secs",
+            idle_duration.as_secs_f64();
+            self.proxy_info
+        );
+        let msg = v2::messages::CloseChannel {
+            channel_id: self.v2_channel_id,
+            reason_code: "idle-channel"
+                .try_into()
+                .expect("BUG: incorrect reason code"),
+        };
+        // Failure to deliver the message doesn't matter as the session is being closed anyway
+        if let Err(e) = self.submit_v2_message(msg) {
+            debug!("Cannot send CloseChannel: {}", e; self.proxy_info);
+        }
+        Err(Error::GeneralWithMetricsLabel(
+            format!(
+                "Channel idle for {:.1}secs, no shares submitted",
+                idle_duration.as_secs_f64()
+            ),
+            "idle_channel",
+        ))
+    }
+}
+
+impl Drop for V2ToV1Translation {
+    fn drop(&mut self) {
+        self.release_v1_session();
+    }
 }
 
 #[handler(async try v1::rpc::Rpc suffix _v1)]
@@ -1188,8 +2072,24 @@ impl V2ToV1Translation {
             msg;
             self.proxy_info
         );
-        let diff = msg.value() as u32;
-        self.v2_target = Some(Self::diff_to_target(diff));
+        validation::validate_difficulty(&msg).map_err(|e| self.reject_upstream_message(e))?;
+        let upstream_diff = msg.value() as u32;
+        let diff = self.clamp_difficulty(upstream_diff);
+        self.v1_target = Some(
+            Uint256Bytes::from_difficulty(upstream_diff.into())
+                .map_err(ii_stratum::error::Error::from)?
+                .into(),
+        );
+        self.v2_target = Some(
+            Uint256Bytes::from_difficulty(diff.into())
+                .map_err(ii_stratum::error::Error::from)?
+                .into(),
+        );
+        // Ask the upstream to move into the configured range so that shares are neither
+        // credited at a lower difficulty nor dropped by the proxy
+        if diff != upstream_diff && self.v1_authorized {
+            self.suggest_v1_difficulty(diff)?;
+        }
         if self.v1_authorized && self.v1_extra_nonce1.is_some() {
             // Initial set difficulty finalizes open channel if all preconditions are met
             if self.state == V2ToV1TranslationState::OpenStandardMiningChannelPending {
@@ -1222,6 +2122,12 @@ impl V2ToV1Translation {
             self.proxy_info
         );
 
+        if !self.v1_xnsub_enabled {
+            warn!(
+                "Upstream changes extranonce without #xnsub being enabled";
+                self.proxy_info
+            );
+        }
         // Update extranonces.
         // Changes are reflected after new mining job as per:
         //   https://en.bitcoin.it/wiki/Stratum_mining_protocol#mining.set_extranonce
@@ -1241,6 +2147,13 @@ impl V2ToV1Translation {
             msg;
             self.proxy_info
         );
+        validation::validate_notify(&msg).map_err(|e| self.reject_upstream_message(e))?;
+        let received = Instant::now();
+        let rate_check = self
+            .v1_notify_rate_limiter
+            .as_mut()
+            .map_or(Ok(()), |rate_limiter| rate_limiter.check(received));
+        rate_check.map_err(|e| self.reject_upstream_message(e))?;
 
         // We won't process the job as long as the channel is not operational
         if self.state != V2ToV1TranslationState::Operational {
@@ -1250,7 +2163,7 @@ impl V2ToV1Translation {
             );
             return Ok(());
         }
-        self.perform_notify(&msg).map_err(|e| {
+        self.perform_notify(&msg, Some(received)).map_err(|e| {
             Error::General(format!(
                 "visit_notify: Sending new mining job failed error={:?} id={:?} state={:?} \
                  payload:{:?}",
@@ -1301,7 +2214,7 @@ impl V2ToV1Translation {
     }
 
     async fn handle_ping(&mut self, payload: (MessageId, v1::messages::Ping)) -> Result<()> {
-        let msg = v1::messages::Pong("pong".into());
+        let msg = v1::messages::Pong::new();
         debug!("Received {:?} message, sending {:?} response", payload, msg; self.proxy_info);
         let stratum_result = v1::rpc::ResponsePayload::try_from(msg)
             .expect("BUG: Pong response to ping couldn't be serialized")
@@ -1337,26 +2250,47 @@ impl V2ToV1Translation {
         if self.state != V2ToV1TranslationState::Init {
             trace!("Cannot setup connection again, received: {:?}", msg; self.proxy_info);
 
-            let err_msg = v2::messages::SetupConnectionError {
-                code: "Connection can be setup only once"
-                    .try_into()
-                    .expect("BUG: incorrect error message"),
-                flags: msg.flags, // TODO Flags indicating features causing an error
-            };
+            let err_msg = v2::messages::SetupConnectionError::builder()
+                .code("Connection can be setup only once")
+                .flags(msg.flags) // TODO Flags indicating features causing an error
+                .build()
+                .expect("BUG: incorrect error message");
 
             self.submit_v2_message(err_msg)
                 .map_err(V2ProtocolError::setup_connection)?;
         }
 
+        // The connection stays unset so that the device may try again with other versions
+        self.v2_protocol_version =
+            Self::negotiate_protocol_version(msg.min_version, msg.max_version);
+        if self.v2_protocol_version.is_none() {
+            info!(
+                "No common protocol version with device supporting versions {}-{}",
+                msg.min_version,
+                msg.max_version;
+                self.proxy_info
+            );
+            let err_msg = v2::messages::SetupConnectionError::builder()
+                .code(v2::error_codes::SetupConnectionErrorCode::ProtocolVersionMismatch)
+                .flags(0)
+                .build()
+                .expect("BUG: incorrect error message");
+            self.submit_v2_message(err_msg)
+                .map_err(V2ProtocolError::setup_connection)?;
+            return Ok(());
+        }
+        info!("Device connected: {}", DeviceFingerprint(&msg.device); self.proxy_info);
+        if let Some(session_stats) = self.session_stats.as_ref() {
+            session_stats.set_device(msg.device.clone());
+        }
         self.v2_conn_details = Some(msg);
-        let mut configure = v1::messages::Configure::new();
-        configure
-            .add_feature(v1::messages::VersionRolling::new(
-                ii_stratum::BIP320_N_VERSION_MASK,
-                ii_stratum::BIP320_N_VERSION_MAX_BITS,
-            ))
-            .expect("BUG: addfeature failed"); // FIXME: how to handle errors from configure.add_feature() ?
-
+        if self.v1_configure_deferred {
+            self.state = V2ToV1TranslationState::ConnectionSetup;
+            self.submit_v2_message(self.build_setup_connection_success())
+                .map_err(V2ProtocolError::setup_connection)?;
+            return Ok(());
+        }
+        let configure = self.build_v1_configure();
         self.submit_v1_request_message(
             configure,
             Self::handle_configure_result,
@@ -1393,61 +2327,27 @@ impl V2ToV1Translation {
                 msg;
                 self.proxy_info
             );
-            let err_msg = v2::messages::OpenMiningChannelError {
-                req_id: msg.req_id,
-                code: "out-of-sequence-message"
-                    .try_into()
-                    .expect("BUG: incorrect error message"),
-            };
+            let err_msg = v2::messages::OpenMiningChannelError::builder()
+                .req_id(msg.req_id)
+                .code("out-of-sequence-message")
+                .build()
+                .expect("BUG: incorrect error message");
 
             self.submit_v2_message(err_msg)
                 .map_err(V2ProtocolError::open_mining_channel)?
         }
+        if !self.authorize_channel(&msg).await? {
+            return Ok(());
+        }
         // Connection details are present by now
-        if let Some(conn_details) = self.v2_conn_details.as_ref() {
-            self.v2_channel_details = Some(msg.clone());
+        if self.v2_conn_details.is_some() {
+            self.v2_channel_details = Some(msg);
             self.state = V2ToV1TranslationState::OpenStandardMiningChannelPending;
-
-            let hostname: String = conn_details
-                .endpoint_host
-                .clone()
-                .try_into()
-                .expect("BUG: Cannot convert to string from connection details");
-
-            let hostname_port = format!("{}:{}", hostname, conn_details.endpoint_port);
-            let subscribe = v1::messages::Subscribe {
-                agent_signature: Some(conn_details.device.fw_ver.to_string()),
-                extra_nonce1: None,
-                url: Some(hostname_port),
-                port: None,
-            };
-
-            self.submit_v1_request_message(
-                subscribe,
-                Self::handle_subscribe_result,
-                Self::handle_authorize_or_subscribe_error,
-            )?;
-
-            if self.options.try_enable_xnsub {
-                let extranonce_subscribe = v1::messages::ExtranonceSubscribe;
-                self.submit_v1_request_message(
-                    extranonce_subscribe,
-                    Self::handle_extranonce_subscribe_result,
-                    Self::handle_extranonce_subscribe_error,
-                )
-                .map_err(V2ProtocolError::open_mining_channel)?;
+            // The upstream (and its credentials) is chosen by the worker name first
+            if self.v1_configure_deferred {
+                return Ok(());
             }
-
-            let authorize = v1::messages::Authorize {
-                name: msg.user.to_string(),
-                password: self.v1_password.clone(),
-            };
-            self.submit_v1_request_message(
-                authorize,
-                Self::handle_authorize_result,
-                Self::handle_authorize_or_subscribe_error,
-            )
-            .map_err(V2ProtocolError::open_mining_channel)?;
+            self.open_v1_channel()?;
         }
         Ok(())
     }
@@ -1471,11 +2371,11 @@ impl V2ToV1Translation {
         );
         self.last_submit = Some(Instant::now());
         // Report invalid channel ID
-        if msg.channel_id != Self::CHANNEL_ID {
+        if !self.v2_channel_ids.is_allocated(msg.channel_id) {
             let _ = self.reject_shares(
                 msg.channel_id,
                 SeqNum::V2(msg.seq_num),
-                format!("Unrecognized channel ID {}", msg.channel_id),
+                v2::error_codes::SubmitSharesErrorCode::InvalidChannelId.to_string(),
             );
             return Err(Error::Stratum(ii_stratum::error::Error::General(format!(
                 "Unrecognized channel ID {}",
@@ -1505,31 +2405,40 @@ impl V2ToV1Translation {
                 ))
             })
             .map(|tmpl| tmpl.clone());
-        // TODO validate the job (recalculate the hash and compare the target)
-        // Submit upstream V1 job based on the found job ID in the map
-        let submit_result = v1_submit_template
-            .and_then(|v1_submit_template| {
-                let submit = v1::messages::Submit::new(
-                    v2_channel_details.user.to_string(),
-                    v1_submit_template.job_id,
-                    Self::channel_to_extra_nonce2_bytes(Self::CHANNEL_ID, v1_extra_nonce2_size)
-                        .as_ref(),
+        // Don't bother the upstream with shares of broken firmware
+        if let Ok(v1_submit_template) = &v1_submit_template {
+            if !self.is_ntime_valid(msg.ntime, v1_submit_template.time) {
+                debug!(
+                    "Share ntime {} out of range of job min_ntime {}",
                     msg.ntime,
-                    msg.nonce,
-                    // ensure the version bits in the template follow BIP320
-                    msg.version & ii_stratum::BIP320_N_VERSION_MASK,
+                    v1_submit_template.time;
+                    self.proxy_info
                 );
-                // Convert the method into a message + provide handling methods
-                self.submit_v1_request_message(
-                    submit,
-                    Self::handle_submit_result,
-                    Self::handle_submit_error,
-                )
-            })
-            .map(|v1_seq_num| {
-                self.v2_submit_share_queue
-                    .push_back(SubmitShare::V1ToV2Mapping(v1_seq_num, msg.seq_num));
-            });
+                return self.reject_shares(
+                    msg.channel_id,
+                    SeqNum::V2(msg.seq_num),
+                    RejectCode::InvalidNtime.to_string(),
+                );
+            }
+            if self.handle_share_below_upstream_target(&msg, v1_submit_template)? {
+                return Ok(());
+            }
+        }
+        // TODO validate the job (recalculate the hash and compare the target)
+        // Submit upstream V1 job based on the found job ID in the map
+        let submit_result = v1_submit_template.and_then(|v1_submit_template| {
+            let submit = v1::messages::Submit::new(
+                self.v1_user(&v2_channel_details.user.to_string()),
+                v1_submit_template.job_id,
+                Self::channel_to_extra_nonce2_bytes(self.v2_channel_id, v1_extra_nonce2_size)
+                    .as_ref(),
+                msg.ntime,
+                msg.nonce,
+                // ensure the version bits in the template follow BIP320
+                msg.version & ii_stratum::BIP320_N_VERSION_MASK,
+            );
+            self.submit_share_upstream(msg.seq_num, submit)
+        });
         if let Err(e) = submit_result {
             self.reject_shares(msg.channel_id, SeqNum::V2(msg.seq_num), format!("{}", e))
                 .ok(); // TODO: Should the error be propagated?
@@ -1537,6 +2446,47 @@ impl V2ToV1Translation {
         Ok(())
     }
 
+    /// Maximum target of the channel is forwarded upstream as a difficulty suggestion
+    async fn handle_update_channel(&mut self, msg: v2::messages::UpdateChannel) -> Result<()> {
+        trace!(
+            "handle_update_channel() state={:?} payload:{:?}",
+            self.state,
+            msg;
+            self.proxy_info
+        );
+        if !self.v2_channel_ids.is_allocated(msg.channel_id) {
+            let err_msg = v2::messages::UpdateChannelError::builder()
+                .channel_id(msg.channel_id)
+                .error_code(v2::error_codes::UpdateChannelErrorCode::InvalidChannelId.to_string())
+                .build()
+                .expect("BUG: incorrect error message");
+            return self.submit_v2_message(err_msg);
+        }
+        if self.v1_authorized {
+            self.suggest_downstream_difficulty(&msg.maximum_target)?;
+        }
+        Ok(())
+    }
+
+    /// Only standard channels are opened by the translation, therefore, extended shares are
+    /// always rejected
+    async fn handle_submit_shares_extended(
+        &mut self,
+        msg: v2::messages::SubmitSharesExtended,
+    ) -> Result<()> {
+        trace!(
+            "handle_submit_shares_extended() state={:?} payload:{:02x?}",
+            self.state,
+            msg;
+            self.proxy_info
+        );
+        self.reject_shares(
+            msg.channel_id,
+            SeqNum::V2(msg.seq_num),
+            "extended-channel-unsupported".to_string(),
+        )
+    }
+
     #[handle(_)]
     async fn handle_unknown_v2(&mut self, parsed_frame: Result<v2::framing::Frame>) -> Result<()> {
         // Broken v2 frame should never occur, since stratum v2 is well defined
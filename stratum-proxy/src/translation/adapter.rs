@@ -0,0 +1,288 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Sink and stream adapters of the translation. Frames received from either side of a session
+//! are fed into the sinks and the translated frames are read from the streams, so the
+//! translation can be plugged into `forward()` pipelines of both connections: `v2_sink` with
+//! `v1_stream` translate downstream frames for the upstream server and `v1_sink` with
+//! `v2_stream` translate the upstream frames back for the downstream peer.
+//!
+//! The adapters are a facade only, the translation itself still emits its frames into bounded
+//! mpsc queues that back the streams and both sinks share the translation via a mutex.
+//! Only the V2 downstream to V1 upstream translation exists, there is no V1 to V2 counterpart.
+
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use futures::channel::mpsc;
+use futures::lock::Mutex;
+use futures::prelude::*;
+use futures::ready;
+
+use ii_logging::macros::*;
+use ii_stratum::v1;
+use ii_stratum::v2;
+use ii_wire::proxy::ProxyInfo;
+
+use super::{V2ToV1Translation, V2ToV1TranslationOptions};
+use crate::error::{Error, Result};
+use crate::metrics::ProxyMetrics;
+
+/// Translation shared by the sinks of a split adapter and anyone else who needs to access it
+/// between frames (e.g. to execute session commands)
+pub type SharedTranslation = Arc<Mutex<V2ToV1Translation>>;
+
+/// Translated frames to be sent to one side of the session
+/// TODO: emit frames directly from the translation once its handlers stop submitting them via
+///  `util::submit_message()`, this would remove the intermediate queues and the shared mutex
+pub type TranslationStream<F> = mpsc::Receiver<F>;
+
+type Handling = Pin<Box<dyn Future<Output = Result<()>> + Send>>;
+
+/// Translation along with the queues of the frames it produces. The translation is configured
+/// via `translation_mut()` and then split into its sinks and streams.
+pub struct TranslationAdapter {
+    translation: V2ToV1Translation,
+    v1_rx: mpsc::Receiver<v1::Frame>,
+    v2_rx: mpsc::Receiver<v2::Frame>,
+    extensions: v2::extensions::ExtensionRegistry,
+}
+
+impl TranslationAdapter {
+    /// Maximum number of translated frames queued for either side
+    pub const MAX_QUEUE_SIZE: usize = 10;
+
+    pub fn new(
+        options: V2ToV1TranslationOptions,
+        metrics: Option<Arc<ProxyMetrics>>,
+        proxy_info: ProxyInfo,
+    ) -> Self {
+        let (v1_tx, v1_rx) = mpsc::channel(Self::MAX_QUEUE_SIZE);
+        let (v2_tx, v2_rx) = mpsc::channel(Self::MAX_QUEUE_SIZE);
+        Self {
+            translation: V2ToV1Translation::new(v1_tx, v2_tx, options, metrics, proxy_info),
+            v1_rx,
+            v2_rx,
+            extensions: Default::default(),
+        }
+    }
+
+    /// Handlers of non-base extension frames received from downstream. Frames of extensions
+    /// without a handler are dropped.
+    pub fn with_extensions(mut self, extensions: v2::extensions::ExtensionRegistry) -> Self {
+        self.extensions = extensions;
+        self
+    }
+
+    pub fn translation_mut(&mut self) -> &mut V2ToV1Translation {
+        &mut self.translation
+    }
+
+    pub fn split(self) -> TranslationPorts {
+        let translation = Arc::new(Mutex::new(self.translation));
+        let extensions = self.extensions;
+        TranslationPorts {
+            v2_sink: TranslationSink::new({
+                let translation = translation.clone();
+                move |frame| {
+                    let translation = translation.clone();
+                    let extensions = extensions.clone();
+                    async move {
+                        let mut translation = translation.lock().await;
+                        handle_v2_frame(&mut translation, &extensions, frame).await
+                    }
+                    .boxed()
+                }
+            }),
+            v1_sink: TranslationSink::new({
+                let translation = translation.clone();
+                move |frame| {
+                    let translation = translation.clone();
+                    async move {
+                        let mut translation = translation.lock().await;
+                        handle_v1_frame(&mut translation, frame).await
+                    }
+                    .boxed()
+                }
+            }),
+            v1_stream: self.v1_rx,
+            v2_stream: self.v2_rx,
+            translation,
+        }
+    }
+}
+
+/// Sinks and streams of a split translation adapter
+pub struct TranslationPorts {
+    pub translation: SharedTranslation,
+    /// Frames received from the downstream peer
+    pub v2_sink: TranslationSink<v2::Frame>,
+    /// Translated frames to be sent upstream
+    pub v1_stream: TranslationStream<v1::Frame>,
+    /// Frames received from the upstream server
+    pub v1_sink: TranslationSink<v1::Frame>,
+    /// Translated frames to be sent downstream
+    pub v2_stream: TranslationStream<v2::Frame>,
+}
+
+/// Sink that passes each frame to the translation. The frame is handled completely before the
+/// sink is ready for the next one, handling errors are reported by the subsequent `poll_ready()`
+/// or `poll_flush()`.
+pub struct TranslationSink<F> {
+    handler: Box<dyn FnMut(F) -> Handling + Send>,
+    handling: Option<Handling>,
+    _frame: PhantomData<fn(F)>,
+}
+
+impl<F> TranslationSink<F> {
+    fn new<H>(handler: H) -> Self
+    where
+        H: FnMut(F) -> Handling + Send + 'static,
+    {
+        Self {
+            handler: Box::new(handler),
+            handling: None,
+            _frame: PhantomData,
+        }
+    }
+
+    fn poll_handling(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if let Some(handling) = self.handling.as_mut() {
+            let result = ready!(handling.as_mut().poll(cx));
+            self.handling = None;
+            result?;
+        }
+        Poll::Ready(Ok(()))
+    }
+}
+
+impl<F> Sink<F> for TranslationSink<F> {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_handling(cx)
+    }
+
+    fn start_send(self: Pin<&mut Self>, frame: F) -> Result<()> {
+        let this = self.get_mut();
+        assert!(
+            this.handling.is_none(),
+            "BUG: frame sent before the sink is ready"
+        );
+        this.handling = Some((this.handler)(frame));
+        Ok(())
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_handling(cx)
+    }
+
+    fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.get_mut().poll_handling(cx)
+    }
+}
+
+/// The frame may carry a JSON-RPC batch, each RPC is handled separately. Malformed elements of a
+/// batch are skipped, only a frame that cannot be parsed at all terminates the session.
+async fn handle_v1_frame(translation: &mut V2ToV1Translation, frame: v1::Frame) -> Result<()> {
+    let parse_mode = translation.v1_parse_mode();
+    for deserialized in v1::rpc::Rpc::from_frame_batch(frame, parse_mode)? {
+        match deserialized {
+            Ok(rpc) => translation.handle_v1(rpc).await?,
+            Err(e) => warn!("Skipping invalid message in V1 batch: {}", e),
+        }
+    }
+    Ok(())
+}
+
+async fn handle_v2_frame(
+    translation: &mut V2ToV1Translation,
+    extensions: &v2::extensions::ExtensionRegistry,
+    frame: v2::Frame,
+) -> Result<()> {
+    match frame.header.extension_type {
+        v2::extensions::BASE => {
+            translation.handle_v2(frame).await?;
+        }
+        extension_type if extensions.handles(extension_type) => {
+            extensions.handle_frame(frame).await?;
+        }
+        // Report any other extension down the line
+        _ => {
+            warn!("Unsupported extension frame: {:x?} ", frame);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::{TryFrom, TryInto};
+
+    use ii_stratum::test_utils;
+    use ii_unvariant::Id;
+
+    #[tokio::test]
+    async fn test_forward_pipelines() {
+        let TranslationPorts {
+            v2_sink,
+            mut v1_stream,
+            v1_sink,
+            mut v2_stream,
+            ..
+        } = TranslationAdapter::new(Default::default(), None, Default::default()).split();
+
+        let setup_connection: v2::Frame = test_utils::v2::build_setup_connection()
+            .try_into()
+            .expect("BUG: cannot build frame");
+        stream::iter(vec![Ok(setup_connection)])
+            .forward(v2_sink)
+            .await
+            .expect("BUG: forwarding downstream frames failed");
+        let configure = v1::rpc::Rpc::try_from(v1_stream.next().await.expect("BUG: no V1 frame"))
+            .expect("BUG: cannot parse V1 frame");
+        assert!(matches!(
+            configure,
+            v1::rpc::Rpc::Request(request) if request.payload.method == v1::rpc::Method::Configure
+        ));
+
+        // Malformed element of a batch doesn't prevent handling of the remaining ones
+        let batch = format!(
+            "[{}, {}]",
+            test_utils::v1::MINING_BROKEN_REQ_JSON,
+            test_utils::v1::MINING_CONFIGURE_OK_RESP_JSON
+        );
+        let batch = v1::Frame::from_serialized_payload(bytes::BytesMut::from(batch.as_str()));
+        stream::iter(vec![Ok(batch)])
+            .forward(v1_sink)
+            .await
+            .expect("BUG: forwarding upstream frames failed");
+        let frame = v2_stream.next().await.expect("BUG: no V2 frame");
+        assert_eq!(
+            frame.header.msg_type,
+            <v2::messages::SetupConnectionSuccess as Id<v2::framing::MsgType>>::ID
+        );
+    }
+}
@@ -0,0 +1,185 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Registry of V1 jobs announced downstream under V2 job IDs
+//!
+//! Jobs invalidated by a new previous block hash are kept for a grace period so that shares
+//! that were already on their way can still be submitted upstream. The total number of jobs is
+//! capped so that a session whose upstream never cleans jobs doesn't grow the registry without
+//! bound.
+
+use std::collections::{HashMap, VecDeque};
+use std::time::Duration;
+use tokio::time::Instant;
+
+#[derive(Debug)]
+struct Entry<T> {
+    job: T,
+    /// Time when the job has been invalidated by a new previous block hash
+    invalidated_at: Option<Instant>,
+}
+
+/// Maps V2 job IDs to jobs. Jobs are evicted in the order of their registration.
+#[derive(Debug)]
+pub struct JobMap<T> {
+    jobs: HashMap<u32, Entry<T>>,
+    /// V2 job IDs in the order of registration, invalidated jobs always form a prefix
+    order: VecDeque<u32>,
+    capacity: usize,
+    grace_period: Duration,
+}
+
+impl<T> JobMap<T> {
+    pub fn new(capacity: usize, grace_period: Duration) -> Self {
+        assert!(capacity > 0, "BUG: job map capacity must be non-zero");
+        Self {
+            jobs: HashMap::new(),
+            order: VecDeque::new(),
+            capacity,
+            grace_period,
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.jobs.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.jobs.len()
+    }
+
+    pub fn get(&self, job_id: &u32) -> Option<&T> {
+        self.jobs.get(job_id).map(|entry| &entry.job)
+    }
+
+    /// Registers a new job, evicting invalidated jobs whose grace period has elapsed and the
+    /// oldest jobs beyond capacity. Returns the job previously registered under `job_id`.
+    pub fn insert(&mut self, job_id: u32, job: T, now: Instant) -> Option<T> {
+        self.evict_expired(now);
+        let previous = self.jobs.insert(
+            job_id,
+            Entry {
+                job,
+                invalidated_at: None,
+            },
+        );
+        match previous {
+            Some(previous) => Some(previous.job),
+            None => {
+                self.order.push_back(job_id);
+                while self.jobs.len() > self.capacity {
+                    self.evict_oldest();
+                }
+                None
+            }
+        }
+    }
+
+    /// Invalidates all registered jobs, they are evicted once the grace period elapses
+    pub fn invalidate_all(&mut self, now: Instant) {
+        for entry in self.jobs.values_mut() {
+            entry.invalidated_at.get_or_insert(now);
+        }
+    }
+
+    fn evict_expired(&mut self, now: Instant) {
+        while let Some(job_id) = self.order.front() {
+            match self.jobs[job_id].invalidated_at {
+                Some(invalidated_at) if now.duration_since(invalidated_at) >= self.grace_period => {
+                    self.evict_oldest()
+                }
+                _ => break,
+            }
+        }
+    }
+
+    fn evict_oldest(&mut self) {
+        if let Some(job_id) = self.order.pop_front() {
+            self.jobs.remove(&job_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const GRACE_PERIOD: Duration = Duration::from_secs(5);
+
+    #[test]
+    fn invalidated_jobs_evicted_after_grace_period() {
+        let start = Instant::now();
+        let mut map = JobMap::new(10, GRACE_PERIOD);
+        assert!(map.is_empty());
+        map.insert(0, "a", start);
+        map.insert(1, "b", start);
+
+        map.invalidate_all(start);
+        map.insert(2, "c", start + Duration::from_secs(1));
+        // Shares for the previous block are still accepted within the grace period
+        assert_eq!(map.get(&0), Some(&"a"));
+        assert_eq!(map.len(), 3);
+
+        map.insert(3, "d", start + GRACE_PERIOD);
+        assert_eq!(map.get(&0), None);
+        assert_eq!(map.get(&1), None);
+        assert_eq!(map.get(&2), Some(&"c"));
+        assert_eq!(map.get(&3), Some(&"d"));
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn repeated_invalidation_keeps_first_time() {
+        let start = Instant::now();
+        let mut map = JobMap::new(10, GRACE_PERIOD);
+        map.insert(0, "a", start);
+        map.invalidate_all(start);
+        map.insert(1, "b", start);
+        map.invalidate_all(start + Duration::from_secs(3));
+
+        map.insert(2, "c", start + GRACE_PERIOD);
+        assert_eq!(map.get(&0), None);
+        assert_eq!(map.get(&1), Some(&"b"));
+    }
+
+    #[test]
+    fn capacity_evicts_oldest_jobs() {
+        let now = Instant::now();
+        let mut map = JobMap::new(2, GRACE_PERIOD);
+        map.insert(0, "a", now);
+        map.insert(1, "b", now);
+        map.insert(2, "c", now);
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&0), None);
+        assert_eq!(map.get(&1), Some(&"b"));
+        assert_eq!(map.get(&2), Some(&"c"));
+    }
+
+    #[test]
+    fn duplicate_job_id_returns_previous_job() {
+        let now = Instant::now();
+        let mut map = JobMap::new(2, GRACE_PERIOD);
+        assert_eq!(map.insert(0, "a", now), None);
+        assert_eq!(map.insert(0, "b", now), Some("a"));
+        assert_eq!(map.len(), 1);
+    }
+}
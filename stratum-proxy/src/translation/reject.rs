@@ -0,0 +1,153 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Classification of reasons that the upstream gives for rejecting a share
+
+use std::fmt;
+
+use ii_stratum::v1;
+use ii_stratum::v2::error_codes::SubmitSharesErrorCode;
+
+/// Code of `SubmitSharesError` sent downstream. Codes that the specification doesn't define are
+/// specific to this proxy.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum RejectCode {
+    Standard(SubmitSharesErrorCode),
+    /// `ntime` is out of range of the job
+    InvalidNtime,
+    /// Share has already been submitted
+    DuplicateShare,
+    /// Worker isn't authorized to submit shares
+    UnauthorizedWorker,
+}
+
+impl RejectCode {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Standard(code) => code.as_str(),
+            Self::InvalidNtime => "invalid-ntime",
+            Self::DuplicateShare => "duplicate-share",
+            Self::UnauthorizedWorker => "unauthorized-worker",
+        }
+    }
+}
+
+impl From<SubmitSharesErrorCode> for RejectCode {
+    fn from(code: SubmitSharesErrorCode) -> Self {
+        Self::Standard(code)
+    }
+}
+
+impl fmt::Display for RejectCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+/// Error codes that most V1 pools use for rejected shares
+const V1_JOB_NOT_FOUND: i32 = 21;
+const V1_DUPLICATE_SHARE: i32 = 22;
+const V1_LOW_DIFFICULTY: i32 = 23;
+const V1_UNAUTHORIZED_WORKER: i32 = 24;
+
+/// Fragments of error messages of pools that don't use the standard error codes
+const MESSAGE_PATTERNS: [(&str, RejectCode); 6] = [
+    (
+        "job not found",
+        RejectCode::Standard(SubmitSharesErrorCode::InvalidJobId),
+    ),
+    (
+        "stale",
+        RejectCode::Standard(SubmitSharesErrorCode::StaleShare),
+    ),
+    (
+        "low difficulty",
+        RejectCode::Standard(SubmitSharesErrorCode::DifficultyTooLow),
+    ),
+    (
+        "above target",
+        RejectCode::Standard(SubmitSharesErrorCode::DifficultyTooLow),
+    ),
+    ("duplicate", RejectCode::DuplicateShare),
+    ("unauthorized", RejectCode::UnauthorizedWorker),
+];
+
+/// Maps the V1 reject reason to V2 error code, `None` means the reason is unknown
+pub fn classify(error: &v1::rpc::StratumError) -> Option<RejectCode> {
+    let v1::rpc::StratumError(code, message, _) = error;
+    match *code {
+        V1_JOB_NOT_FOUND => Some(SubmitSharesErrorCode::InvalidJobId.into()),
+        V1_DUPLICATE_SHARE => Some(RejectCode::DuplicateShare),
+        V1_LOW_DIFFICULTY => Some(SubmitSharesErrorCode::DifficultyTooLow.into()),
+        V1_UNAUTHORIZED_WORKER => Some(RejectCode::UnauthorizedWorker),
+        _ => {
+            let message = message.to_lowercase();
+            MESSAGE_PATTERNS
+                .iter()
+                .find(|(pattern, _)| message.contains(pattern))
+                .map(|(_, code)| *code)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn error(code: i32, message: &str) -> v1::rpc::StratumError {
+        v1::rpc::StratumError(code, message.to_string(), None)
+    }
+
+    #[test]
+    fn classify_by_code() {
+        assert_eq!(
+            classify(&error(21, "Job not found (=stale)")),
+            Some(SubmitSharesErrorCode::InvalidJobId.into())
+        );
+        assert_eq!(classify(&error(22, "")), Some(RejectCode::DuplicateShare));
+        assert_eq!(
+            classify(&error(23, "")),
+            Some(SubmitSharesErrorCode::DifficultyTooLow.into())
+        );
+        assert_eq!(
+            classify(&error(24, "")),
+            Some(RejectCode::UnauthorizedWorker)
+        );
+    }
+
+    #[test]
+    fn classify_by_message() {
+        assert_eq!(
+            classify(&error(0, "Low difficulty share")),
+            Some(SubmitSharesErrorCode::DifficultyTooLow.into())
+        );
+        assert_eq!(
+            classify(&error(-1, "Duplicate share")),
+            Some(RejectCode::DuplicateShare)
+        );
+        assert_eq!(
+            classify(&error(20, "stale")),
+            Some(SubmitSharesErrorCode::StaleShare.into())
+        );
+        assert_eq!(classify(&error(20, "Other/Unknown")), None);
+    }
+}
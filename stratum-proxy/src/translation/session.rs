@@ -0,0 +1,255 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! V1 sessions that can be resumed when the same downstream device connects again
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use ii_stratum::v1;
+
+/// Default maximum number of devices tracked by the store
+pub const DEFAULT_CAPACITY: usize = 10_000;
+/// Default period for which a session of a disconnected device can be resumed
+pub const DEFAULT_SESSION_TTL: Duration = Duration::from_secs(60 * 60);
+
+/// Session of a single device
+#[derive(Debug)]
+struct Entry {
+    /// Session ID assigned by the upstream, if any
+    session_id: Option<v1::ExtraNonce1>,
+    /// The session belongs to a live connection and must not be handed out to anyone else
+    attached: bool,
+    /// Time of the last change, expiration of detached sessions is counted from it
+    updated: Instant,
+}
+
+/// Outcome of `V1SessionStore::claim()`
+#[derive(Clone, PartialEq, Debug)]
+pub enum Claim {
+    /// The device key belongs to the caller until it is released, the previous session (if
+    /// any) can be resumed
+    Claimed(Option<v1::ExtraNonce1>),
+    /// The key is used by another live connection (e.g. two devices with the same ID) or the
+    /// store is full, the caller has to start a new session and must not store it
+    Unavailable,
+}
+
+/// Session IDs assigned by the upstream to previous subscriptions. The store is shared by all
+/// translation sessions of the proxy and keyed by the upstream and the downstream device. Both keys and the number
+/// of entries are controlled by downstream devices, therefore, the store is capped and sessions
+/// of disconnected devices expire.
+#[derive(Clone, Debug)]
+pub struct V1SessionStore {
+    sessions: Arc<Mutex<HashMap<String, Entry>>>,
+    capacity: usize,
+    ttl: Duration,
+}
+
+impl V1SessionStore {
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            sessions: Default::default(),
+            capacity,
+            ttl,
+        }
+    }
+
+    /// Builds the key of a device that `user` mines with at `upstream`. Session IDs are only
+    /// meaningful to the upstream that has assigned them. Devices without an ID cannot be told
+    /// apart and their sessions are not resumed.
+    pub fn key(upstream: SocketAddr, user: &str, device_id: &str) -> Option<String> {
+        if device_id.is_empty() {
+            None
+        } else {
+            Some(format!("{}/{}/{}", upstream, user, device_id))
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, Entry>> {
+        self.sessions
+            .lock()
+            .expect("BUG: Poisoned V1 session store")
+    }
+
+    fn is_expired(&self, entry: &Entry, now: Instant) -> bool {
+        !entry.attached && now.saturating_duration_since(entry.updated) >= self.ttl
+    }
+
+    /// Attaches the session of device `key` to the caller's connection
+    pub fn claim(&self, key: &str) -> Claim {
+        let now = Instant::now();
+        let mut sessions = self.lock();
+        if let Some(entry) = sessions.get_mut(key) {
+            if entry.attached {
+                return Claim::Unavailable;
+            }
+            let session_id = if self.is_expired(entry, now) {
+                None
+            } else {
+                entry.session_id.take()
+            };
+            entry.attached = true;
+            entry.updated = now;
+            return Claim::Claimed(session_id);
+        }
+
+        if sessions.len() >= self.capacity {
+            sessions.retain(|_, entry| !self.is_expired(entry, now));
+        }
+        if sessions.len() >= self.capacity {
+            // Make room by forgetting the least recently detached session
+            let oldest = sessions
+                .iter()
+                .filter(|(_, entry)| !entry.attached)
+                .min_by_key(|(_, entry)| entry.updated)
+                .map(|(key, _)| key.clone());
+            match oldest {
+                Some(oldest) => sessions.remove(&oldest),
+                None => return Claim::Unavailable,
+            };
+        }
+        sessions.insert(
+            key.to_string(),
+            Entry {
+                session_id: None,
+                attached: true,
+                updated: now,
+            },
+        );
+        Claim::Claimed(None)
+    }
+
+    /// Records session ID assigned to the claimed device `key` by the upstream
+    pub fn set_session_id(&self, key: &str, session_id: Option<v1::ExtraNonce1>) {
+        if let Some(entry) = self.lock().get_mut(key) {
+            entry.session_id = session_id;
+            entry.updated = Instant::now();
+        }
+    }
+
+    /// Detaches the session of device `key` from the connection that has claimed it, the
+    /// session can be resumed until it expires
+    pub fn release(&self, key: &str) {
+        let mut sessions = self.lock();
+        let resumable = match sessions.get_mut(key) {
+            Some(entry) => {
+                entry.attached = false;
+                entry.updated = Instant::now();
+                entry.session_id.is_some()
+            }
+            None => return,
+        };
+        if !resumable {
+            sessions.remove(key);
+        }
+    }
+
+    /// Returns the session ID of device `key` that hasn't been claimed by a live connection
+    pub fn get(&self, key: &str) -> Option<v1::ExtraNonce1> {
+        let sessions = self.lock();
+        sessions
+            .get(key)
+            .filter(|entry| !entry.attached && !self.is_expired(entry, Instant::now()))
+            .and_then(|entry| entry.session_id.clone())
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+}
+
+impl Default for V1SessionStore {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, DEFAULT_SESSION_TTL)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::convert::TryFrom;
+
+    fn session_id(hex: &str) -> v1::ExtraNonce1 {
+        v1::ExtraNonce1(v1::HexBytes::try_from(hex).expect("BUG: Cannot parse session ID"))
+    }
+
+    #[test]
+    fn test_claim_and_release() {
+        let store = V1SessionStore::default();
+        let upstream = "10.0.0.1:3333".parse().expect("BUG: Invalid address");
+        assert_eq!(V1SessionStore::key(upstream, "user", ""), None);
+        let key = V1SessionStore::key(upstream, "user", "dev").expect("BUG: No key");
+        assert_ne!(
+            V1SessionStore::key(
+                "10.0.0.2:3333".parse().expect("BUG: Invalid address"),
+                "user",
+                "dev"
+            ),
+            Some(key.clone())
+        );
+
+        assert_eq!(store.claim(&key), Claim::Claimed(None));
+        store.set_session_id(&key, Some(session_id("ae6812eb")));
+        // Second device with the same ID must not get the session of a live connection
+        assert_eq!(store.claim(&key), Claim::Unavailable);
+        assert_eq!(store.get(&key), None);
+
+        store.release(&key);
+        assert_eq!(store.get(&key), Some(session_id("ae6812eb")));
+        assert_eq!(
+            store.claim(&key),
+            Claim::Claimed(Some(session_id("ae6812eb")))
+        );
+        // Sessions that the upstream hasn't assigned an ID to are forgotten
+        store.release(&key);
+        assert!(store.is_empty());
+    }
+
+    #[test]
+    fn test_capacity_and_expiry() {
+        let store = V1SessionStore::new(2, Duration::from_secs(3600));
+        for dev in ["a", "b"].iter() {
+            assert_eq!(store.claim(dev), Claim::Claimed(None));
+        }
+        // All sessions are attached to live connections
+        assert_eq!(store.claim("c"), Claim::Unavailable);
+
+        store.set_session_id("a", Some(session_id("aa")));
+        store.release("a");
+        assert_eq!(store.claim("c"), Claim::Claimed(None));
+        assert_eq!(store.len(), 2);
+        assert_eq!(store.get("a"), None);
+
+        let store = V1SessionStore::new(2, Duration::from_secs(0));
+        assert_eq!(store.claim("a"), Claim::Claimed(None));
+        store.set_session_id("a", Some(session_id("aa")));
+        store.release("a");
+        assert_eq!(store.claim("a"), Claim::Claimed(None));
+    }
+}
@@ -41,8 +41,14 @@ struct TranslationTester {
 
 impl TranslationTester {
     pub fn new(options: V2ToV1TranslationOptions) -> Self {
-        let (v1_sender, v1_receiver) = mpsc::channel(1);
-        let (v2_sender, v2_receiver) = mpsc::channel(1);
+        Self::with_channel_size(options, 1)
+    }
+
+    /// Builds the tester whose channels can buffer `channel_size` messages, e.g. when the
+    /// translation emits multiple messages at once
+    pub fn with_channel_size(options: V2ToV1TranslationOptions, channel_size: usize) -> Self {
+        let (v1_sender, v1_receiver) = mpsc::channel(channel_size);
+        let (v2_sender, v2_receiver) = mpsc::channel(channel_size);
         let translation =
             V2ToV1Translation::new(v1_sender, v2_sender, options, None, Default::default());
 
@@ -163,11 +169,23 @@ async fn test_initial_sequence_translate(tester: &mut TranslationTester) {
     let id = 1.into();
     tester
         .check_next_v1(id, |msg: v1::messages::Subscribe| {
+            // User agent identifies the device announced in SetupConnection
+            let subscribe = v1::messages::Subscribe {
+                agent_signature: Some(format!(
+                    "{} (Braiins; 1; xyz)",
+                    test_utils::common::MINER_SW_SIGNATURE
+                )),
+                ..test_utils::v1::build_subscribe()
+            };
             test_utils::v1::message_request_check(
                 id,
                 &msg,
-                test_utils::v1::build_subscribe(),
-                test_utils::v1::MINING_SUBSCRIBE_REQ_JSON,
+                subscribe,
+                concat!(
+                    r#"{"id":1,"method":"mining.subscribe","#,
+                    r#""params":["Braiins OS 2019-06-05 (Braiins; 1; xyz)",null,"#,
+                    r#""stratum.slushpool.com:3333",null]}"#
+                ),
             );
         })
         .await;
@@ -224,6 +242,10 @@ async fn test_initial_sequence_translate(tester: &mut TranslationTester) {
             .expect("BUG: cannot build JobId"),
         time: test_utils::common::MINING_WORK_NTIME,
         version: test_utils::common::MINING_WORK_VERSION,
+        prev_hash: test_utils::v2::build_set_new_prev_hash().prev_hash.0,
+        merkle_root: test_utils::v2::build_new_mining_job().merkle_root.0,
+        bits: test_utils::v2::build_set_new_prev_hash().nbits,
+        upstream_target: tester.translation.v2_target,
     };
 
     let registered_submit_template = tester
@@ -275,11 +297,440 @@ async fn test_setup_connection_translate() {
 }
 
 #[tokio::test]
-async fn test_shares_sequence_number_translate() {
+async fn test_protocol_version_mismatch() {
+    let mut tester = TranslationTester::default();
+
+    tester
+        .send_v2(v2::messages::SetupConnection {
+            min_version: 3,
+            max_version: 4,
+            ..test_utils::v2::build_setup_connection()
+        })
+        .await;
+    tester
+        .check_next_v2(|msg: v2::messages::SetupConnectionError| {
+            assert_eq!(msg.code.as_str(), "protocol-version-mismatch");
+        })
+        .await;
+    // Nothing is configured upstream
+    assert!(tester.v1_receiver.try_next().is_err());
+    assert_eq!(tester.translation.v2_protocol_version, None);
+}
+
+#[test]
+fn test_negotiate_protocol_version() {
+    assert_eq!(V2ToV1Translation::negotiate_protocol_version(2, 2), Some(2));
+    assert_eq!(V2ToV1Translation::negotiate_protocol_version(1, 5), Some(2));
+    assert_eq!(V2ToV1Translation::negotiate_protocol_version(0, 1), None);
+    assert_eq!(V2ToV1Translation::negotiate_protocol_version(3, 5), None);
+    // Inverted range of the downstream
+    assert_eq!(V2ToV1Translation::negotiate_protocol_version(3, 2), None);
+}
+
+#[tokio::test]
+async fn test_idle_channel_close() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        idle_channel_timeout: Some(Duration::from_secs(0)),
+        ..Default::default()
+    });
+
+    // Channel that is not operational yet cannot be idle
+    tester
+        .translation
+        .check_idle_channel()
+        .expect("BUG: Channel considered idle before being opened");
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    // Channel has just submitted shares
+    tester.translation.options.idle_channel_timeout = Some(Duration::from_secs(3600));
+    tester.send_v2(test_utils::v2::build_submit_shares()).await;
+    tester.v1_receiver.next().await;
+    tester
+        .translation
+        .check_idle_channel()
+        .expect("BUG: Active channel considered idle");
+
+    tester.translation.options.idle_channel_timeout = Some(Duration::from_secs(0));
+    match tester.translation.check_idle_channel() {
+        Err(Error::GeneralWithMetricsLabel(_, label)) => assert_eq!(label, "idle_channel"),
+        result => panic!("BUG: Unexpected idle channel check result: {:?}", result),
+    }
+    let channel_id = tester.translation.v2_channel_id;
+    tester
+        .check_next_v2(|msg: v2::messages::CloseChannel| {
+            assert_eq!(msg.channel_id, channel_id);
+            assert_eq!(msg.reason_code.to_string(), "idle-channel");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_v1_request_timeout() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        v1_request_timeout: Some(Duration::from_secs(0)),
+        ..Default::default()
+    });
+    tester
+        .translation
+        .check_v1_request_timeouts()
+        .expect("BUG: Timeout without any request");
+
+    // Setup connection results in mining.configure that is never answered
+    tester
+        .send_v2(test_utils::v2::build_setup_connection())
+        .await;
+    tester
+        .check_next_v1(0.into(), |_msg: v1::messages::Configure| {})
+        .await;
+    match tester.translation.check_v1_request_timeouts() {
+        Err(Error::Upstream(UpstreamError::Stratum(ii_stratum::error::Error::V1(
+            v1::error::Error::RequestTimeout { id, method, .. },
+        )))) => {
+            assert_eq!(id, 0);
+            assert_eq!(method, v1::rpc::Method::Configure);
+        }
+        result => panic!("BUG: Unexpected timeout check result: {:?}", result),
+    }
+    // Late response is no longer paired with the request
+    assert!(tester
+        .translation
+        .handle_v1(test_utils::v1::build_configure_ok_response_message())
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_invalid_difficulty_rejected() {
+    let mut tester = TranslationTester::default();
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let v2_target = tester.translation.v2_target;
+    match tester
+        .translation
+        .handle_v1(test_utils::v1::build_request_message(
+            None,
+            v1::messages::SetDifficulty::from(0.5f32),
+        ))
+        .await
+    {
+        Err(Error::Upstream(UpstreamError::InvalidMessage(
+            InvalidUpstreamMessage::Difficulty(difficulty),
+        ))) => assert_eq!(difficulty, 0.5),
+        result => panic!("BUG: Unexpected result of invalid difficulty: {:?}", result),
+    }
+    assert_eq!(tester.translation.v2_target, v2_target);
+}
+
+#[tokio::test]
+async fn test_difficulty_clamped() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        min_difficulty: Some(2),
+        max_difficulty: Some(1024),
+        ..Default::default()
+    });
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    for (upstream_difficulty, difficulty) in
+        [(1f32, 2u32), (65536f32, 1024u32), (8f32, 8u32)].iter()
+    {
+        tester
+            .send_v1(test_utils::v1::build_request_message(
+                None,
+                v1::messages::SetDifficulty::from(*upstream_difficulty),
+            ))
+            .await;
+        tester
+            .check_next_v2(|msg: v2::messages::SetTarget| {
+                assert_eq!(
+                    msg.max_target,
+                    Uint256Bytes::from_difficulty(f64::from(*difficulty))
+                        .expect("BUG: Invalid difficulty")
+                );
+            })
+            .await;
+    }
+
+    // Clamped difficulties are suggested to the upstream
+    for (id, difficulty) in [(3u32, 2f32), (4, 1024f32)].iter() {
+        tester
+            .check_next_v1((*id).into(), |msg: v1::messages::SuggestDifficulty| {
+                assert_eq!(msg.value(), *difficulty);
+            })
+            .await;
+    }
+    assert!(tester.v1_receiver.try_next().is_err());
+}
+
+/// Maximum target requested when opening the channel is suggested upstream as difficulty right
+/// after authorization
+#[tokio::test]
+async fn test_open_channel_difficulty_suggested() {
+    let mut tester = TranslationTester::with_channel_size(Default::default(), 4);
+
+    tester
+        .send_v2(test_utils::v2::build_setup_connection())
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_configure_ok_response_message())
+        .await;
+    tester
+        .send_v2(v2::messages::OpenStandardMiningChannel {
+            max_target: Uint256Bytes::from_difficulty(256.0).expect("BUG: Invalid difficulty"),
+            ..test_utils::v2::build_open_channel()
+        })
+        .await;
+
+    tester
+        .check_next_v1(0.into(), |_: v1::messages::Configure| ())
+        .await;
+    tester
+        .check_next_v1(1.into(), |_: v1::messages::Subscribe| ())
+        .await;
+    tester
+        .check_next_v1(2.into(), |_: v1::messages::Authorize| ())
+        .await;
+    tester
+        .check_next_v1(3.into(), |msg: v1::messages::SuggestDifficulty| {
+            assert_eq!(msg.value(), 256f32);
+        })
+        .await;
+}
+
+/// Maximum target of `UpdateChannel` is suggested upstream within the configured difficulty
+/// range
+#[tokio::test]
+async fn test_update_channel_difficulty_suggested() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        max_difficulty: Some(1024),
+        ..Default::default()
+    });
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let channel_id = tester.translation.v2_channel_id;
+    for (id, max_difficulty, difficulty) in [(3u32, 512u32, 512f32), (4, 4096, 1024f32)].iter() {
+        tester
+            .send_v2(v2::messages::UpdateChannel {
+                channel_id,
+                nominal_hash_rate: 1e9,
+                maximum_target: Uint256Bytes::from_difficulty(f64::from(*max_difficulty))
+                    .expect("BUG: Invalid difficulty"),
+            })
+            .await;
+        tester
+            .check_next_v1((*id).into(), |msg: v1::messages::SuggestDifficulty| {
+                assert_eq!(msg.value(), *difficulty);
+            })
+            .await;
+    }
+
+    tester
+        .send_v2(v2::messages::UpdateChannel {
+            channel_id: channel_id + 1,
+            nominal_hash_rate: 1e9,
+            maximum_target: Uint256Bytes::from_difficulty(512.0).expect("BUG: Invalid difficulty"),
+        })
+        .await;
+    tester
+        .check_next_v2(|msg: v2::messages::UpdateChannelError| {
+            assert_eq!(msg.channel_id, channel_id + 1);
+            assert_eq!(msg.error_code.to_string(), "invalid-channel-id");
+        })
+        .await;
+    assert!(tester.v1_receiver.try_next().is_err());
+}
+
+/// Shares of a downstream difficulty clamped below the upstream difficulty are checked locally
+/// and only those meeting the upstream target are submitted
+#[tokio::test]
+async fn test_share_below_upstream_target() {
+    let mut tester = TranslationTester::default();
+
+    test_initial_sequence_translate(&mut tester).await;
+    // As if `max_difficulty` clamped the upstream difficulty
+    tester.translation.v2_target = Some(
+        Uint256Bytes::from_difficulty(1.0)
+            .expect("BUG: Invalid difficulty")
+            .into(),
+    );
+
+    // The share doesn't meet difficulty 1 (or any practical difficulty)
+    let mut shares = test_utils::v2::build_submit_shares();
+    shares.nonce = shares.nonce.wrapping_add(1);
+    tester.send_v2(shares.clone()).await;
+    tester
+        .check_next_v2(|msg: v2::messages::SubmitSharesError| {
+            assert_eq!(msg.seq_num, shares.seq_num);
+            assert_eq!(msg.code.to_string(), "difficulty-too-low");
+        })
+        .await;
+
+    // Any hash meets the downstream target now, but not the upstream one
+    tester.translation.v2_target = Some(U256::MAX);
+    shares.seq_num += 1;
+    tester.send_v2(shares.clone()).await;
+    tester
+        .check_next_v2(|msg: v2::messages::SubmitSharesSuccess| {
+            assert_eq!(msg.last_seq_num, shares.seq_num);
+        })
+        .await;
+    // Nothing has been submitted upstream
+    assert!(tester.v1_receiver.try_next().is_err());
+
+    // Shares meeting the upstream target of a new job are submitted
+    tester.translation.v1_target = Some(U256::MAX - 1);
+    tester
+        .translation
+        .handle_v1(test_utils::v1::build_mining_notify_request_message())
+        .await
+        .expect("BUG: Cannot translate mining job");
+    shares.job_id = 1;
+    shares.seq_num += 1;
+    tester.send_v2(shares).await;
+    tester
+        .check_next_v1(3.into(), |_msg: v1::messages::Submit| {})
+        .await;
+}
+
+#[tokio::test]
+async fn test_notify_storm_rejected() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        v1_max_notifies_per_minute: Some(1),
+        ..Default::default()
+    });
+
+    // Initial sequence includes the only notification allowed within a minute
+    test_initial_sequence_translate(&mut tester).await;
+
+    match tester
+        .translation
+        .handle_v1(test_utils::v1::build_mining_notify_request_message())
+        .await
+    {
+        Err(Error::Upstream(UpstreamError::InvalidMessage(
+            InvalidUpstreamMessage::NotifyStorm { limit, .. },
+        ))) => assert_eq!(limit, 1),
+        result => panic!("BUG: Unexpected result of notify storm: {:?}", result),
+    }
+    // The job is not passed downstream
+    assert!(tester.v2_receiver.try_next().is_err());
+}
+
+#[tokio::test]
+async fn test_job_map_capacity() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        max_tracked_jobs: Some(1),
+        ..Default::default()
+    });
+
+    test_initial_sequence_translate(&mut tester).await;
+    tester
+        .translation
+        .handle_v1(test_utils::v1::build_mining_notify_request_message())
+        .await
+        .expect("BUG: Cannot translate mining job");
+
+    // Only the most recent job is kept
+    let job_map = &tester.translation.v2_to_v1_job_map;
+    assert_eq!(job_map.len(), 1);
+    assert!(job_map.get(&0).is_none());
+    assert!(job_map.get(&1).is_some());
+}
+
+/// Shares for jobs of the previous block that arrive shortly after the block change are still
+/// submitted upstream
+#[tokio::test]
+async fn test_stale_job_grace_period() {
     let mut tester = TranslationTester::default();
 
     test_initial_sequence_translate(&mut tester).await;
 
+    let mut notify_v1 = test_utils::v1::build_mining_notify();
+    notify_v1.clean_jobs = true;
+    tester
+        .send_v1(test_utils::v1::build_request_message(None, notify_v1))
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::NewMiningJob| {})
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::SetNewPrevHash| {})
+        .await;
+
+    let shares_v2 = test_utils::v2::build_submit_shares();
+    assert_eq!(shares_v2.job_id, 0);
+    tester.send_v2(shares_v2).await;
+    tester
+        .check_next_v1(3.into(), |msg: v1::messages::Submit| {
+            assert_eq!(test_utils::v1::build_mining_submit(), msg);
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_ntime_out_of_range_rejected() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        max_ntime_roll: Some(Duration::from_secs(600)),
+        ..Default::default()
+    });
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let mut shares = test_utils::v2::build_submit_shares();
+    let min_ntime = test_utils::common::MINING_WORK_NTIME;
+    for (seq_num, ntime) in [min_ntime - 1, min_ntime + 601].iter().enumerate() {
+        shares.seq_num = seq_num as u32;
+        shares.ntime = *ntime;
+        tester.send_v2(shares.clone()).await;
+        tester
+            .check_next_v2(|msg: v2::messages::SubmitSharesError| {
+                assert_eq!(msg.seq_num, shares.seq_num);
+                assert_eq!(msg.code.to_string(), "invalid-ntime");
+            })
+            .await;
+    }
+    // Nothing has been submitted upstream
+    assert!(tester.v1_receiver.try_next().is_err());
+
+    // The upper bound of the window is still valid
+    shares.seq_num = 2;
+    shares.ntime = min_ntime + 600;
+    tester.send_v2(shares).await;
+    tester
+        .check_next_v1(3.into(), |_msg: v1::messages::Submit| {})
+        .await;
+}
+
+#[tokio::test]
+async fn test_submit_shares_extended_rejected() {
+    let mut tester = TranslationTester::default();
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let shares = test_utils::v2::build_submit_shares_extended();
+    tester.send_v2(shares.clone()).await;
+    tester
+        .check_next_v2(|msg: v2::messages::SubmitSharesError| {
+            assert_eq!(msg.channel_id, shares.channel_id);
+            assert_eq!(msg.seq_num, shares.seq_num);
+            assert_eq!(msg.code.to_string(), "extended-channel-unsupported");
+        })
+        .await;
+}
+
+#[tokio::test]
+async fn test_shares_sequence_number_translate() {
+    // Jobs are forgotten as soon as the upstream cleans them
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        stale_job_grace_period: Some(Duration::from_secs(0)),
+        ..Default::default()
+    });
+
+    test_initial_sequence_translate(&mut tester).await;
+
     let mut notify_v1 = test_utils::v1::build_mining_notify();
     let mut new_mining_job_v2 = test_utils::v2::build_new_mining_job();
     let mut new_prev_hash_v2 = test_utils::v2::build_set_new_prev_hash();
@@ -357,8 +808,7 @@ async fn test_shares_sequence_number_translate() {
         .await;
 
     shares_error_v2.seq_num = 1;
-    shares_error_v2.code =
-        v2::types::Str0_32::from_string(format!("ShareRjct:StratumError(0, \"{}", ERR_STALE));
+    shares_error_v2.code = v2::error_codes::SubmitSharesErrorCode::StaleShare.into();
 
     tester
         .check_next_v2(|msg: v2::messages::SubmitSharesError| {
@@ -387,6 +837,286 @@ async fn test_shares_sequence_number_translate() {
         .await;
 }
 
+/// Verifies that shares beyond the window of submits in flight are held back and that responses
+/// are sent downstream in the order of the shares even when the upstream answers out of order
+#[tokio::test]
+async fn test_submit_window() {
+    let mut tester = TranslationTester::with_channel_size(
+        V2ToV1TranslationOptions {
+            max_submits_in_flight: Some(2),
+            ..Default::default()
+        },
+        4,
+    );
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let mut shares_v2 = test_utils::v2::build_submit_shares();
+    let mut shares_success_v2 = test_utils::v2::build_submit_shares_success();
+    for seq_num in 0..3 {
+        shares_v2.seq_num = seq_num;
+        tester.send_v2(shares_v2.clone()).await;
+    }
+    for id in 3..5 {
+        tester
+            .check_next_v1(id.into(), |_msg: v1::messages::Submit| {})
+            .await;
+    }
+    assert!(
+        tester.v1_receiver.try_next().is_err(),
+        "BUG: share submitted beyond the window"
+    );
+
+    // Answer to the second share frees the window for the third share, however, its response
+    // waits for the first share
+    tester
+        .send_v1(test_utils::v1::build_ok_response_message(4))
+        .await;
+    tester
+        .check_next_v1(5.into(), |_msg: v1::messages::Submit| {})
+        .await;
+    assert!(
+        tester.v2_receiver.try_next().is_err(),
+        "BUG: share response sent out of order"
+    );
+
+    tester
+        .send_v1(test_utils::v1::build_ok_response_message(3))
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_ok_response_message(5))
+        .await;
+    for seq_num in 0..3 {
+        shares_success_v2.last_seq_num = seq_num;
+        tester
+            .check_next_v2(|msg: v2::messages::SubmitSharesSuccess| {
+                assert_eq!(shares_success_v2, msg);
+            })
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn test_deferred_shares_limit() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        max_submits_in_flight: Some(1),
+        ..Default::default()
+    });
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let mut shares_v2 = test_utils::v2::build_submit_shares();
+    let share_count = V2ToV1Translation::MAX_DEFERRED_SHARES as u32 + 2;
+    for seq_num in 0..share_count {
+        shares_v2.seq_num = seq_num;
+        tester.send_v2(shares_v2.clone()).await;
+    }
+    assert_eq!(tester.translation.v1_submits_in_flight, 1);
+    assert_eq!(
+        tester.translation.v2_deferred_shares,
+        V2ToV1Translation::MAX_DEFERRED_SHARES
+    );
+    // Share beyond the limit is rejected, the response waits for the preceding shares
+    match tester.translation.v2_submit_share_queue.back() {
+        Some(SubmitShare::SubmitSharesError(error)) => {
+            assert_eq!(error.seq_num, share_count - 1);
+            assert_eq!(error.code.as_str(), "too-many-deferred-shares");
+        }
+        _ => panic!("BUG: share beyond the limit not rejected"),
+    }
+}
+
+/// Storage that collects all share counts flushed into it
+#[derive(Default)]
+struct TestShareStorage(std::sync::Mutex<Vec<crate::accounting::WorkerShareMap>>);
+
+impl crate::accounting::ShareStorage for TestShareStorage {
+    fn store(
+        &self,
+        _timestamp: std::time::SystemTime,
+        shares: &crate::accounting::WorkerShareMap,
+    ) -> std::io::Result<()> {
+        self.0.lock().unwrap().push(shares.clone());
+        Ok(())
+    }
+}
+
+/// Verifies that shares are accounted with the difficulty they have been submitted for even if
+/// the difficulty changes before the upstream answers
+#[tokio::test]
+async fn test_share_accounting_difficulty() {
+    let mut tester = TranslationTester::default();
+    let share_accounting = ShareAccounting::default();
+    tester
+        .translation
+        .set_share_accounting(share_accounting.clone());
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let shares_v2 = test_utils::v2::build_submit_shares();
+    tester.send_v2(shares_v2).await;
+    tester
+        .check_next_v1(3.into(), |_msg: v1::messages::Submit| {})
+        .await;
+    tester.translation.v2_target = Some(
+        Uint256Bytes::from_difficulty(1024.0)
+            .expect("BUG: Invalid difficulty")
+            .into(),
+    );
+    tester
+        .send_v1(test_utils::v1::build_ok_response_message(3))
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::SubmitSharesSuccess| {})
+        .await;
+
+    let storage = TestShareStorage::default();
+    share_accounting.flush(&storage).expect("BUG: Flush failed");
+    let stored = storage.0.lock().unwrap();
+    let worker_shares = stored[0].values().next().expect("BUG: No shares accounted");
+    assert_eq!(worker_shares.accepted, 1);
+    assert_eq!(worker_shares.accepted_difficulty, 4);
+}
+
+/// Verifies that a share is rejected when its submission times out while the session goes on
+#[tokio::test]
+async fn test_submit_timeout() {
+    let mut tester = TranslationTester::new(V2ToV1TranslationOptions {
+        v1_submit_timeout: Some(Duration::from_secs(0)),
+        ..Default::default()
+    });
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let mut shares_v2 = test_utils::v2::build_submit_shares();
+    tester.send_v2(shares_v2.clone()).await;
+    tester
+        .check_next_v1(3.into(), |_msg: v1::messages::Submit| {})
+        .await;
+    tester
+        .translation
+        .check_v1_request_timeouts()
+        .expect("BUG: Submit timeout terminates the session");
+    tester
+        .check_next_v2(|msg: v2::messages::SubmitSharesError| {
+            assert_eq!(msg.seq_num, shares_v2.seq_num);
+            assert_eq!(msg.code.to_string(), "upstream-timeout");
+        })
+        .await;
+
+    // Late response is ignored
+    tester
+        .send_v1(test_utils::v1::build_ok_response_message(3))
+        .await;
+    assert!(
+        tester.v2_receiver.try_next().is_err(),
+        "BUG: response sent for a timed out share"
+    );
+
+    shares_v2.seq_num = 1;
+    tester.send_v2(shares_v2.clone()).await;
+    tester
+        .check_next_v1(4.into(), |_msg: v1::messages::Submit| {})
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_ok_response_message(4))
+        .await;
+    tester
+        .check_next_v2(|msg: v2::messages::SubmitSharesSuccess| {
+            assert_eq!(msg.last_seq_num, 1);
+        })
+        .await;
+}
+
+/// Verifies that `mining.extranonce.subscribe` is sent when enabled and that the upstream answer
+/// is recorded
+#[tokio::test]
+async fn test_extranonce_subscribe_translate() {
+    // Subscribe, extranonce subscribe and authorize are sent at once
+    let mut tester = TranslationTester::with_channel_size(
+        V2ToV1TranslationOptions {
+            try_enable_xnsub: true,
+            ..Default::default()
+        },
+        3,
+    );
+
+    tester
+        .send_v2(test_utils::v2::build_setup_connection())
+        .await;
+    tester
+        .check_next_v1(0.into(), |_msg: v1::messages::Configure| {})
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_configure_ok_response_message())
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::SetupConnectionSuccess| {})
+        .await;
+
+    tester.send_v2(test_utils::v2::build_open_channel()).await;
+    tester
+        .check_next_v1(1.into(), |_msg: v1::messages::Subscribe| {})
+        .await;
+    let id = 2.into();
+    tester
+        .check_next_v1(id, |msg: v1::messages::ExtranonceSubscribe| {
+            test_utils::v1::message_request_check(
+                id,
+                &msg,
+                test_utils::v1::build_extranonce_subscribe(),
+                test_utils::v1::MINING_EXTRANONCE_SUBSCRIBE_JSON,
+            );
+        })
+        .await;
+    tester
+        .check_next_v1(3.into(), |_msg: v1::messages::Authorize| {})
+        .await;
+
+    assert!(!tester.translation.v1_xnsub_enabled);
+    tester
+        .send_v1(test_utils::v1::build_ok_response_message(2))
+        .await;
+    assert!(tester.translation.v1_xnsub_enabled);
+}
+
+/// Verifies that new extranonce is used for jobs that arrive after `mining.set_extranonce`
+#[tokio::test]
+async fn test_set_extranonce_translate() {
+    let mut tester = TranslationTester::default();
+
+    test_initial_sequence_translate(&mut tester).await;
+
+    let set_extranonce = test_utils::v1::build_set_extranonce();
+    tester
+        .send_v1(test_utils::v1::build_set_extranonce_request_message())
+        .await;
+    assert_eq!(
+        tester.translation.v1_extra_nonce1,
+        Some(set_extranonce.extra_nonce1.clone())
+    );
+    assert_eq!(
+        tester.translation.v1_extra_nonce2_size,
+        set_extranonce.extra_nonce2_size
+    );
+
+    let mut notify_v1 = test_utils::v1::build_mining_notify();
+    notify_v1.clean_jobs = true;
+    tester
+        .send_v1(test_utils::v1::build_request_message(None, notify_v1))
+        .await;
+    let original_mining_job = test_utils::v2::build_new_mining_job();
+    tester
+        .check_next_v2(|msg: v2::messages::NewMiningJob| {
+            assert_eq!(msg.job_id, 1);
+            assert_ne!(
+                msg.merkle_root, original_mining_job.merkle_root,
+                "BUG: Job doesn't reflect the new extranonce"
+            );
+        })
+        .await;
+}
+
 #[test]
 fn test_diff_1_bitcoin_target() {
     // Difficulty 1 target in big-endian format
@@ -399,10 +1129,10 @@ fn test_diff_1_bitcoin_target() {
 
     assert_eq!(
         expected_difficulty_1_target_uint256,
-        V2ToV1Translation::DIFF1_TARGET,
+        v2::types::DIFFICULTY_1_TARGET,
         "Bitcoin difficulty 1 targets don't match exp: {:x?}, actual:{:x?}",
         expected_difficulty_1_target_uint256,
-        V2ToV1Translation::DIFF1_TARGET
+        v2::types::DIFFICULTY_1_TARGET
     );
 }
 
@@ -555,3 +1285,251 @@ fn test_client_reconnect_parsing_with_invalid_arguments() {
         panic!("invalid host name data type not detected")
     }
 }
+
+/// Opens a channel of a translation connected to `upstream` that resumes sessions from
+/// `sessions`, returns the session ID sent upstream in `mining.subscribe`
+async fn open_channel_with_session_store(
+    sessions: &session::V1SessionStore,
+    upstream: SocketAddr,
+) -> (TranslationTester, Option<v1::ExtraNonce1>) {
+    let mut tester = TranslationTester::with_channel_size(Default::default(), 2);
+    tester.translation.set_v1_session_store(sessions.clone());
+    tester.translation.set_v1_upstream_addr(upstream);
+
+    tester
+        .send_v2(test_utils::v2::build_setup_connection())
+        .await;
+    tester
+        .check_next_v1(0.into(), |_msg: v1::messages::Configure| {})
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_configure_ok_response_message())
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::SetupConnectionSuccess| {})
+        .await;
+
+    tester.send_v2(test_utils::v2::build_open_channel()).await;
+    let mut session_id = None;
+    tester
+        .check_next_v1(1.into(), |msg: v1::messages::Subscribe| {
+            session_id = msg.extra_nonce1().cloned();
+        })
+        .await;
+    tester
+        .check_next_v1(2.into(), |_msg: v1::messages::Authorize| {})
+        .await;
+    (tester, session_id)
+}
+
+/// Verifies that the session ID of a previous subscription is passed upstream and that the
+/// session ID assigned by the upstream is remembered for the next connection of the device
+#[tokio::test]
+async fn test_v1_session_resumption() {
+    let sessions = session::V1SessionStore::default();
+    let upstream = "10.0.0.1:3333".parse().expect("BUG: Invalid address");
+    let key = session::V1SessionStore::key(
+        upstream,
+        &test_utils::v2::build_open_channel().user.to_string(),
+        &test_utils::v2::build_setup_connection()
+            .device
+            .dev_id
+            .to_string(),
+    )
+    .expect("BUG: Device without ID");
+    let previous_session_id =
+        v1::ExtraNonce1(v1::HexBytes::try_from("ae6812eb").expect("BUG: Cannot parse session ID"));
+    assert_eq!(sessions.claim(&key), session::Claim::Claimed(None));
+    sessions.set_session_id(&key, Some(previous_session_id.clone()));
+    sessions.release(&key);
+
+    let (mut tester, session_id) = open_channel_with_session_store(&sessions, upstream).await;
+    assert_eq!(session_id, Some(previous_session_id));
+
+    // Upstream starts a new session
+    let subscriptions = serde_json::json!([["mining.notify", "b4b6693b"]]);
+    let ok_result = test_utils::v1::build_subscribe_ok_result();
+    let subscribe_result = v1::messages::SubscribeResult(
+        serde_json::from_value(subscriptions).expect("BUG: Invalid subscriptions"),
+        ok_result.extra_nonce_1().clone(),
+        ok_result.extra_nonce_2_size(),
+    );
+    tester
+        .send_v1(
+            v1::server::result_response(1, subscribe_result)
+                .expect("BUG: Cannot build subscribe response"),
+        )
+        .await;
+    // The session stays attached to the live connection
+    assert_eq!(sessions.get(&key), None);
+    assert_eq!(sessions.claim(&key), session::Claim::Unavailable);
+
+    drop(tester);
+    assert_eq!(
+        sessions.get(&key),
+        Some(v1::ExtraNonce1(
+            v1::HexBytes::try_from("b4b6693b").expect("BUG: Cannot parse session ID")
+        ))
+    );
+}
+
+/// Verifies that a session assigned by one upstream is never resumed with another one
+#[tokio::test]
+async fn test_v1_session_resumption_switching_upstream() {
+    let sessions = session::V1SessionStore::default();
+    let upstream_a = "10.0.0.1:3333".parse().expect("BUG: Invalid address");
+    let upstream_b = "10.0.0.2:3333".parse().expect("BUG: Invalid address");
+    let key_a = session::V1SessionStore::key(
+        upstream_a,
+        &test_utils::v2::build_open_channel().user.to_string(),
+        &test_utils::v2::build_setup_connection()
+            .device
+            .dev_id
+            .to_string(),
+    )
+    .expect("BUG: Device without ID");
+    let session_id_a =
+        v1::ExtraNonce1(v1::HexBytes::try_from("ae6812eb").expect("BUG: Cannot parse session ID"));
+    assert_eq!(sessions.claim(&key_a), session::Claim::Claimed(None));
+    sessions.set_session_id(&key_a, Some(session_id_a.clone()));
+    sessions.release(&key_a);
+
+    // The device fails over to another upstream that has to start a new session
+    let (tester, session_id) = open_channel_with_session_store(&sessions, upstream_b).await;
+    assert_eq!(session_id, None);
+    drop(tester);
+
+    // Session of the first upstream is still available when the device comes back
+    let (_tester, session_id) = open_channel_with_session_store(&sessions, upstream_a).await;
+    assert_eq!(session_id, Some(session_id_a));
+}
+
+/// Verifies that a deferred channel is opened upstream only after the upstream has been chosen
+/// and that its credentials replace those of the device
+#[tokio::test]
+async fn test_upstream_credentials() {
+    let mut tester = TranslationTester::with_channel_size(Default::default(), 3);
+    tester.translation.defer_v1_configure();
+    let downstream_user = test_utils::v2::build_open_channel().user.to_string();
+
+    tester
+        .send_v2(test_utils::v2::build_setup_connection())
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::SetupConnectionSuccess| {})
+        .await;
+    tester.send_v2(test_utils::v2::build_open_channel()).await;
+    assert!(tester.v1_receiver.try_next().is_err());
+    assert_eq!(
+        tester.translation.v1_routing_key(),
+        Some(downstream_user.clone())
+    );
+
+    tester
+        .translation
+        .set_v1_credentials(Some(UpstreamCredentials {
+            user: "farm".to_string(),
+            password: Some("secret".to_string()),
+            append_downstream_user: true,
+        }));
+    tester
+        .translation
+        .resume_v1_configure()
+        .expect("BUG: Cannot resume upstream configuration");
+    let upstream_user = format!("farm.{}", downstream_user);
+    tester
+        .check_next_v1(0.into(), |_msg: v1::messages::Configure| {})
+        .await;
+    tester
+        .check_next_v1(1.into(), |_msg: v1::messages::Subscribe| {})
+        .await;
+    tester
+        .check_next_v1(2.into(), |msg: v1::messages::Authorize| {
+            assert_eq!(msg.name, upstream_user);
+            assert_eq!(msg.password, "secret");
+        })
+        .await;
+
+    tester
+        .send_v1(test_utils::v1::build_configure_ok_response_message())
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_subscribe_ok_response_message())
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_authorize_ok_response_message())
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_set_difficulty_request_message())
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::OpenStandardMiningChannelSuccess| {})
+        .await;
+    tester
+        .send_v1(test_utils::v1::build_mining_notify_request_message())
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::NewMiningJob| {})
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::SetNewPrevHash| {})
+        .await;
+
+    // Shares are submitted on behalf of the upstream account too
+    tester.send_v2(test_utils::v2::build_submit_shares()).await;
+    tester
+        .check_next_v1(3.into(), |msg: v1::messages::Submit| {
+            assert_eq!(msg.user_name(), &upstream_user);
+        })
+        .await;
+}
+
+/// Verifies that extranonce subscription granted via `mining.configure` replaces
+/// `mining.extranonce.subscribe`
+#[tokio::test]
+async fn test_configure_subscribe_extranonce_translate() {
+    let mut tester = TranslationTester::with_channel_size(
+        V2ToV1TranslationOptions {
+            try_enable_xnsub: true,
+            ..Default::default()
+        },
+        2,
+    );
+
+    tester
+        .send_v2(test_utils::v2::build_setup_connection())
+        .await;
+    let configure = tester
+        .check_next_v1(0.into(), |msg: v1::messages::Configure| msg)
+        .await;
+    assert!(configure.subscribe_extranonce());
+
+    let negotiated = v1::messages::NegotiatedFeatures {
+        version_rolling_mask: Some(ii_stratum::BIP320_N_VERSION_MASK),
+        minimum_difficulty: false,
+        subscribe_extranonce: true,
+    };
+    tester
+        .send_v1(
+            v1::server::result_response(
+                0,
+                v1::messages::ConfigureResult::negotiated(&configure, &negotiated),
+            )
+            .expect("BUG: Cannot build configure response"),
+        )
+        .await;
+    tester
+        .check_next_v2(|_msg: v2::messages::SetupConnectionSuccess| {})
+        .await;
+    assert_eq!(tester.translation.v1_negotiated, negotiated);
+    assert!(tester.translation.v1_xnsub_enabled);
+
+    // No mining.extranonce.subscribe between subscribe and authorize
+    tester.send_v2(test_utils::v2::build_open_channel()).await;
+    tester
+        .check_next_v1(1.into(), |_msg: v1::messages::Subscribe| {})
+        .await;
+    tester
+        .check_next_v1(2.into(), |_msg: v1::messages::Authorize| {})
+        .await;
+}
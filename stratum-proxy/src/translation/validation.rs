@@ -0,0 +1,138 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Sanity checks of messages received from the upstream that are applied before anything is
+//! translated and passed downstream
+
+use std::time::Duration;
+use tokio::time::Instant;
+
+use ii_stratum::v1;
+
+use crate::error::InvalidUpstreamMessage;
+
+/// Length of the previous block hash
+const PREV_HASH_LEN: usize = 32;
+
+/// Difficulty has to be a finite number of at least 1. Fractional difficulties are not supported
+/// as the translation works with integer difficulties only, converting them would yield a zero
+/// difficulty and an unbounded target.
+pub fn validate_difficulty(
+    msg: &v1::messages::SetDifficulty,
+) -> Result<(), InvalidUpstreamMessage> {
+    let difficulty = msg.value();
+    if difficulty.is_finite() && difficulty >= 1.0 {
+        Ok(())
+    } else {
+        Err(InvalidUpstreamMessage::Difficulty(difficulty))
+    }
+}
+
+pub fn validate_notify(msg: &v1::messages::Notify) -> Result<(), InvalidUpstreamMessage> {
+    let prev_hash_len = msg.prev_hash().len();
+    if prev_hash_len == PREV_HASH_LEN {
+        Ok(())
+    } else {
+        Err(InvalidUpstreamMessage::PrevHashLength(prev_hash_len))
+    }
+}
+
+/// Limits the number of `mining.notify` messages received within each period
+#[derive(Debug)]
+pub struct NotifyRateLimiter {
+    limit: u32,
+    period: Duration,
+    period_start: Instant,
+    count: u32,
+}
+
+impl NotifyRateLimiter {
+    pub fn new(limit: u32, period: Duration) -> Self {
+        Self {
+            limit,
+            period,
+            period_start: Instant::now(),
+            count: 0,
+        }
+    }
+
+    /// Accounts a notification received at `now`, fails once the limit of the current period is
+    /// exceeded
+    pub fn check(&mut self, now: Instant) -> Result<(), InvalidUpstreamMessage> {
+        if now.duration_since(self.period_start) >= self.period {
+            self.period_start = now;
+            self.count = 0;
+        }
+        self.count += 1;
+        if self.count > self.limit {
+            Err(InvalidUpstreamMessage::NotifyStorm {
+                limit: self.limit,
+                period: self.period,
+            })
+        } else {
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ii_stratum::test_utils;
+
+    #[test]
+    fn test_validate_difficulty() {
+        for difficulty in [1.0, 1.5, 65536.0].iter() {
+            assert!(validate_difficulty(&(*difficulty).into()).is_ok());
+        }
+        for difficulty in [0.0, 0.5, 0.999, -1.0, f32::NAN, f32::INFINITY].iter() {
+            assert!(validate_difficulty(&(*difficulty).into()).is_err());
+        }
+    }
+
+    #[test]
+    fn test_validate_notify() {
+        let mut notify = test_utils::v1::build_mining_notify();
+        assert!(validate_notify(&notify).is_ok());
+
+        notify.prev_hash = v1::PrevHash::from("0011".to_string());
+        assert_eq!(
+            validate_notify(&notify),
+            Err(InvalidUpstreamMessage::PrevHashLength(0))
+        );
+    }
+
+    #[test]
+    fn test_notify_rate_limiter() {
+        let period = Duration::from_secs(60);
+        let mut limiter = NotifyRateLimiter::new(2, period);
+        let start = Instant::now();
+        assert!(limiter.check(start).is_ok());
+        assert!(limiter.check(start).is_ok());
+        assert_eq!(
+            limiter.check(start + Duration::from_secs(59)),
+            Err(InvalidUpstreamMessage::NotifyStorm { limit: 2, period })
+        );
+        // Limit applies to each period separately
+        assert!(limiter.check(start + period).is_ok());
+    }
+}
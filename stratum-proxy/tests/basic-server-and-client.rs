@@ -53,6 +53,8 @@ const PORT_V1_WITH_PROXY: u16 = 9092;
 static PORT_V2: u16 = 9002;
 static PORT_V2_FULL: u16 = 9003;
 static PORT_V2_WITH_PROXY: u16 = 9004;
+const PORT_V1_IN_MEMORY: u16 = 9093;
+const PORT_V1_EXTENSIONS: u16 = 9094;
 
 /// Generic stratum V1 tester that is able to send and receive a V1 frame and can be used for
 /// verifying client and server protocol flows
@@ -378,3 +380,180 @@ async fn test_v2server_full_with_proxy_protocol() {
     // Signal the server to shut down
     halt_handle.halt();
 }
+
+/// Verify that the proxy server can be fed with in-memory downstream connections
+#[tokio::test]
+async fn test_v2server_full_in_memory_listener() {
+    let addr_v1 = Address(ADDR.into(), PORT_V1_IN_MEMORY);
+    // dummy pool server
+    tokio::spawn(v1server_task(addr_v1.clone(), None));
+
+    let local_addr: SocketAddr = "127.0.0.1:3336".parse().expect("BUG: invalid address");
+    let (connection_tx, listener) = server::listener::ChannelListener::new(local_addr);
+    let v2server = server::ProxyServer::with_listener(
+        listener,
+        addr_v1,
+        server::TranslationHandler::new(None),
+        None,
+        server::ProxyProtocolConfig::default(),
+        None,
+    );
+    let halt_handle = HaltHandle::arc();
+    halt_handle.spawn_object(v2server);
+    halt_handle.ready();
+
+    let (client_stream, server_stream) = tokio::io::duplex(4096);
+    connection_tx
+        .unbounded_send(server::IncomingConnection {
+            stream: server_stream,
+            peer_addr: "127.0.0.2:1234".parse().expect("BUG: invalid address"),
+            local_addr,
+        })
+        .expect("BUG: cannot pass connection to the listener");
+
+    let mut conn = tokio_util::codec::Framed::new(
+        client_stream,
+        <v2::Framing as ii_wire::Framing>::Codec::default(),
+    );
+    conn.send(
+        test_utils::v2::build_setup_connection()
+            .try_into()
+            .expect("BUG: Cannot convert to frame"),
+    )
+    .await
+    .expect("BUG: Could not send message");
+    let response = conn
+        .next()
+        .await
+        .expect("BUG: should get response message")
+        .expect("BUG: failed to get response");
+    test_utils::v2::TestIdentityHandler
+        .handle_v2(response)
+        .await;
+
+    // Signal the server to shut down
+    halt_handle.halt();
+}
+
+/// Verify that frames of extensions unknown to the proxy are passed on when configured to do so
+#[tokio::test]
+async fn test_v2server_unknown_extension_pass_through() {
+    let addr_v1 = Address(ADDR.into(), PORT_V1_EXTENSIONS);
+    // dummy pool server
+    tokio::spawn(v1server_task(addr_v1.clone(), None));
+
+    let endpoint = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: cannot bind extension endpoint");
+    let endpoint_addr = endpoint
+        .local_addr()
+        .expect("BUG: cannot get endpoint address");
+    let pass_through = server::ExtensionPassThrough::new(Address(
+        endpoint_addr.ip().to_string(),
+        endpoint_addr.port(),
+    ));
+    let local_addr: SocketAddr = "127.0.0.1:3337".parse().expect("BUG: invalid address");
+    let (connection_tx, listener) = server::listener::ChannelListener::new(local_addr);
+    let v2server = server::ProxyServer::with_listener(
+        listener,
+        addr_v1,
+        server::TranslationHandler::new(None).with_unknown_extensions_passed_to(pass_through),
+        None,
+        server::ProxyProtocolConfig::default(),
+        None,
+    );
+    let halt_handle = HaltHandle::arc();
+    halt_handle.spawn_object(v2server);
+    halt_handle.ready();
+
+    let (client_stream, server_stream) = tokio::io::duplex(4096);
+    connection_tx
+        .unbounded_send(server::IncomingConnection {
+            stream: server_stream,
+            peer_addr: "127.0.0.2:1234".parse().expect("BUG: invalid address"),
+            local_addr,
+        })
+        .expect("BUG: cannot pass connection to the listener");
+
+    let mut conn = tokio_util::codec::Framed::new(
+        client_stream,
+        <v2::Framing as ii_wire::Framing>::Codec::default(),
+    );
+    let telemetry_msg = v2::telemetry::messages::OpenTelemetryChannel {
+        req_id: 1,
+        dev_id: v2::types::Str0_255::try_from("device").expect("BUG: cannot build string"),
+    };
+    conn.send(
+        telemetry_msg
+            .try_into()
+            .expect("BUG: Cannot convert to frame"),
+    )
+    .await
+    .expect("BUG: Could not send message");
+
+    let (stream, _) = endpoint
+        .accept()
+        .await
+        .expect("BUG: extension frame has not been passed through");
+    let mut endpoint_conn =
+        tokio_util::codec::Framed::new(stream, <v2::Framing as ii_wire::Framing>::Codec::default());
+    let frame = endpoint_conn
+        .next()
+        .await
+        .expect("BUG: connection closed")
+        .expect("BUG: cannot receive frame");
+    assert_eq!(frame.header.extension_type, v2::extensions::TELEMETRY);
+
+    // Signal the server to shut down
+    halt_handle.halt();
+}
+
+/// Verify that passed through extension frames are forwarded to the configured endpoint
+#[tokio::test]
+async fn test_extension_frames_forwarded() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0")
+        .await
+        .expect("BUG: cannot bind extension endpoint");
+    let endpoint_addr = listener
+        .local_addr()
+        .expect("BUG: cannot get endpoint address");
+
+    let pass_through = server::ExtensionPassThrough::new(Address(
+        endpoint_addr.ip().to_string(),
+        endpoint_addr.port(),
+    ));
+    // Each session is forwarded over its own connection
+    for dev_id in ["device-1", "device-2"].iter() {
+        let session = pass_through.start_session(None);
+        let telemetry_msg = v2::telemetry::messages::OpenTelemetryChannel {
+            req_id: 1,
+            dev_id: v2::types::Str0_255::try_from(*dev_id).expect("BUG: cannot build string"),
+        };
+        session
+            .handle_extension_frame(
+                telemetry_msg
+                    .try_into()
+                    .expect("BUG: Cannot convert to frame"),
+            )
+            .await
+            .expect("BUG: forwarder has terminated");
+
+        let (stream, _) = listener
+            .accept()
+            .await
+            .expect("BUG: extension frames not forwarded");
+        let mut conn = tokio_util::codec::Framed::new(
+            stream,
+            <v2::Framing as ii_wire::Framing>::Codec::default(),
+        );
+        let frame = conn
+            .next()
+            .await
+            .expect("BUG: connection closed")
+            .expect("BUG: cannot receive frame");
+        assert_eq!(frame.header.extension_type, v2::extensions::TELEMETRY);
+        let msg = v2::telemetry::messages::OpenTelemetryChannel::try_from(frame)
+            .expect("BUG: cannot parse forwarded frame");
+        assert_eq!(msg.dev_id.to_string(), *dev_id);
+    }
+}
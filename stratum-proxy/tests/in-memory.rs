@@ -0,0 +1,748 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Complete miner <-> proxy <-> pool conversations over in-memory streams. No sockets are
+//! involved, so the tests don't depend on free ports and can run in parallel. Timeouts are
+//! shortened so that the timeout scenarios complete quickly.
+
+use async_trait::async_trait;
+use futures::prelude::*;
+use std::convert::{TryFrom, TryInto};
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering::Relaxed};
+use std::sync::Arc;
+use tokio::io::DuplexStream;
+use tokio::time::{Duration, Instant};
+
+use ii_async_utils::{CancellationToken, HaltHandle};
+use ii_noise_proxy::SecurityContext;
+use ii_stratum::error::Direction;
+use ii_stratum::error::ErrorKind;
+use ii_stratum::test_utils;
+use ii_stratum::v1;
+use ii_stratum::v2;
+use ii_stratum::v2::noise::{
+    auth::EncodedEd25519PublicKey, negotiation::EncryptionAlgorithm, Initiator,
+};
+use ii_stratum_proxy::auth::{AuthDecision, AuthRequest, Authenticator};
+use ii_stratum_proxy::events::{Event, EventExporter, EventRecord, EventSink};
+use ii_stratum_proxy::server::{
+    self, controller::ServerHandle, listener::ChannelListener, upstream::ChannelUpstream,
+    CloseReason, ConnectionHandler, DownstreamPeer, IncomingConnection, SessionTimeouts,
+    SessionUpstream, Upstream, UpstreamConnection, UpstreamRouting, UpstreamSet,
+};
+use ii_stratum_proxy::translation::V2ToV1TranslationOptions;
+use ii_stratum_sim::{
+    MinerConfig, PoolScript, PoolStats, ScriptStep, SimMiner, SimPool, SubmitPolicy,
+};
+
+mod utils;
+use utils::{open_channel, receive, send, wait_for_close, V2Conn};
+
+const CERTIFICATE: &str = r#"{
+  "signed_part_header": {
+    "version": 0,
+    "valid_from": 1613145976,
+    "not_valid_after": 2477145976
+  },
+  "public_key": {
+    "noise_public_key": "2Nki8zRNjrYLdcGbRLFrTbwLsDfKSiDMsiK3UWGTJNJpaPjAZW"
+  },
+  "authority_public_key": {
+    "ed25519_public_key": "2eMjqMKXXFjhY1eAdvnmhk3xuWYdPpawYSWXXabPxVmCdeuWx"
+  },
+  "signature": {
+    "ed25519_signature": "AdrgZxKNM3wCQmv5q3aTn8T96DV6egAYYFQRgcxuQjfiKvraR2xp3pNLRuDTvwQApYZc6YXnwbxXzUdHbGxaxSMq4g67c"
+  }
+}"#;
+const SECRET_KEY: &str = r#"{
+  "noise_secret_key": "2owBcKCGg7k46rTUYEwNEKJsnT2TqYDtFsMAuicrsLXhi3VwK4"
+}"#;
+const AUTHORITY_PUBLIC_KEY: &str = "2eMjqMKXXFjhY1eAdvnmhk3xuWYdPpawYSWXXabPxVmCdeuWx";
+
+fn addr(addr: &str) -> SocketAddr {
+    addr.parse().expect("BUG: invalid address")
+}
+
+/// Serves every connection of `upstream_rx` by a simulated pool running `script`
+fn start_pool<S>(script: PoolScript, mut upstream_rx: S) -> PoolStats
+where
+    S: Stream<Item = DuplexStream> + Unpin + Send + 'static,
+{
+    let pool = SimPool::new(script);
+    let stats = pool.stats();
+    tokio::spawn(async move {
+        while let Some(stream) = upstream_rx.next().await {
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.serve(stream).await });
+        }
+    });
+    stats
+}
+
+/// Proxy server that accepts in-memory connections and connects to `upstream`
+struct Proxy {
+    connection_tx: futures::channel::mpsc::UnboundedSender<IncomingConnection<DuplexStream>>,
+    server_handle: ServerHandle,
+    halt_handle: Arc<HaltHandle>,
+}
+
+type InMemoryServer<U> =
+    server::ProxyServer<server::TranslationHandler, ChannelListener<DuplexStream>, U>;
+
+impl Proxy {
+    fn start<U>(
+        upstream: U,
+        handler: server::TranslationHandler,
+        security_context: Option<Arc<SecurityContext>>,
+    ) -> Self
+    where
+        U: Upstream<Stream = DuplexStream>,
+    {
+        Self::start_with(upstream, handler, security_context, |v2server| v2server)
+    }
+
+    /// Starts the proxy after `configure` has adjusted the server
+    fn start_with<U, F>(
+        upstream: U,
+        handler: server::TranslationHandler,
+        security_context: Option<Arc<SecurityContext>>,
+        configure: F,
+    ) -> Self
+    where
+        U: Upstream<Stream = DuplexStream>,
+        F: FnOnce(InMemoryServer<U>) -> InMemoryServer<U>,
+    {
+        let (connection_tx, listener) = ChannelListener::new(addr("127.0.0.1:3336"));
+        let v2server = configure(server::ProxyServer::with_listener_and_upstream(
+            listener,
+            upstream,
+            handler,
+            security_context,
+            server::ProxyProtocolConfig::default(),
+            None,
+        ));
+        let server_handle = v2server.handle();
+        let halt_handle = HaltHandle::arc();
+        halt_handle.spawn_object(v2server);
+        halt_handle.ready();
+        Self {
+            connection_tx,
+            server_handle,
+            halt_handle,
+        }
+    }
+
+    /// Opens a new downstream connection to the proxy
+    fn connect(&self) -> DuplexStream {
+        let (client_stream, server_stream) = tokio::io::duplex(64 * 1024);
+        self.connection_tx
+            .unbounded_send(IncomingConnection {
+                stream: server_stream,
+                peer_addr: addr("127.0.0.2:1234"),
+                local_addr: addr("127.0.0.1:3336"),
+            })
+            .expect("BUG: cannot pass connection to the listener");
+        client_stream
+    }
+
+    fn connect_framed(&self) -> V2Conn {
+        v2::Framed::new(self.connect(), Default::default())
+    }
+
+    fn halt(self) {
+        self.halt_handle.halt();
+    }
+}
+
+/// Upstream that refuses a given number of connection attempts before it starts to connect
+#[derive(Clone)]
+struct FlakyUpstream {
+    inner: ChannelUpstream,
+    refusals: u32,
+    attempts: Arc<AtomicU32>,
+}
+
+impl std::fmt::Display for FlakyUpstream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "flaky {}", self.inner)
+    }
+}
+
+#[async_trait]
+impl Upstream for FlakyUpstream {
+    type Stream = DuplexStream;
+
+    async fn connect(&self) -> io::Result<UpstreamConnection<Self::Stream>> {
+        if self.attempts.fetch_add(1, Relaxed) < self.refusals {
+            return Err(io::ErrorKind::ConnectionRefused.into());
+        }
+        self.inner.connect().await
+    }
+}
+
+#[tokio::test]
+async fn test_shares_translated() {
+    const SHARES: u64 = 8;
+
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    let pool_stats = start_pool(
+        PoolScript::default().with_submit_policy(SubmitPolicy::RejectEvery(4)),
+        upstream_rx,
+    );
+    let proxy = Proxy::start(upstream, server::TranslationHandler::new(None), None);
+
+    let mut config = MinerConfig::new(addr("127.0.0.1:3336"));
+    config.shares_per_session = Some(SHARES);
+    let miner = SimMiner::new(config);
+    miner
+        .run_session(0, proxy.connect_framed())
+        .await
+        .expect("BUG: Simulated miner session failed");
+
+    let stats = miner.stats();
+    assert_eq!(stats.channels_opened(), 1);
+    assert_eq!(stats.shares_submitted(), SHARES);
+    assert_eq!(stats.shares_unanswered(), 0);
+    assert_eq!(stats.shares_rejected(), SHARES / 4);
+    assert_eq!(
+        stats.shares_accepted(),
+        u64::from(pool_stats.accepted_submits())
+    );
+    assert_eq!(pool_stats.connections(), 1);
+    proxy.halt();
+}
+
+#[tokio::test]
+async fn test_shares_translated_over_noise() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(PoolScript::default(), upstream_rx);
+    let security_context =
+        SecurityContext::read_from_strings(CERTIFICATE.to_string(), SECRET_KEY.to_string())
+            .expect("BUG: Failed to build security context");
+    let proxy = Proxy::start(
+        upstream,
+        server::TranslationHandler::new(None),
+        Some(Arc::new(security_context)),
+    );
+
+    let authority_public_key = EncodedEd25519PublicKey::try_from(AUTHORITY_PUBLIC_KEY.to_string())
+        .expect("BUG: Failed to parse authority public key")
+        .into_inner();
+    let conn = Initiator::new(authority_public_key, vec![EncryptionAlgorithm::AESGCM])
+        .connect(proxy.connect())
+        .await
+        .expect("BUG: Noise handshake failed");
+
+    let mut config = MinerConfig::new(addr("127.0.0.1:3336"));
+    config.shares_per_session = Some(2);
+    let miner = SimMiner::new(config);
+    miner
+        .run_session(0, conn)
+        .await
+        .expect("BUG: Simulated miner session failed");
+    assert_eq!(miner.stats().shares_accepted(), 2);
+    proxy.halt();
+}
+
+/// A miner reconnecting after the pool has dropped its session gets a new upstream connection
+#[tokio::test]
+async fn test_reconnect_after_pool_disconnect() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    let script = PoolScript::default().with_steps(vec![
+        ScriptStep::SetDifficulty(test_utils::v1::build_set_difficulty().value()),
+        ScriptStep::Notify(test_utils::v1::build_mining_notify()),
+        ScriptStep::Sleep(Duration::from_millis(50)),
+        ScriptStep::Disconnect,
+    ]);
+    let pool_stats = start_pool(script, upstream_rx);
+    let proxy = Proxy::start(upstream, server::TranslationHandler::new(None), None);
+
+    for connection in 1..=2 {
+        let mut conn = proxy.connect_framed();
+        open_channel(&mut conn).await;
+        wait_for_close(&mut conn).await;
+        assert_eq!(pool_stats.connections(), connection);
+        assert_eq!(pool_stats.authorized(), connection);
+    }
+    proxy.halt();
+}
+
+/// Refused upstream connection attempts are retried within the same session
+#[tokio::test]
+async fn test_upstream_connect_retried() {
+    let (inner, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    let pool_stats = start_pool(PoolScript::default(), upstream_rx);
+    let upstream = FlakyUpstream {
+        inner,
+        refusals: 2,
+        attempts: Default::default(),
+    };
+    let attempts = upstream.attempts.clone();
+    let proxy = Proxy::start(upstream, server::TranslationHandler::new(None), None);
+
+    let mut conn = proxy.connect_framed();
+    open_channel(&mut conn).await;
+    assert_eq!(attempts.load(Relaxed), 3);
+    assert_eq!(pool_stats.connections(), 1);
+    proxy.halt();
+}
+
+/// Downstream connection is closed when the upstream cannot be reached
+#[tokio::test]
+async fn test_upstream_unreachable() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    drop(upstream_rx);
+    let proxy = Proxy::start(upstream, server::TranslationHandler::new(None), None);
+
+    let mut conn = proxy.connect_framed();
+    send(&mut conn, test_utils::v2::build_setup_connection()).await;
+    assert!(conn.next().await.is_none());
+    proxy.halt();
+}
+
+/// Downstream connection is closed when it sends a frame exceeding the configured size
+#[tokio::test]
+async fn test_frame_too_large() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(PoolScript::default(), upstream_rx);
+    let proxy = Proxy::start_with(
+        upstream,
+        server::TranslationHandler::new(None),
+        None,
+        |v2server| v2server.with_max_frame_size(Some(16)),
+    );
+
+    let mut conn = proxy.connect_framed();
+    send(&mut conn, test_utils::v2::build_setup_connection()).await;
+    wait_for_close(&mut conn).await;
+    proxy.halt();
+}
+
+/// Session whose pool stops sending jobs is terminated after the upstream timeout
+#[tokio::test]
+async fn test_upstream_timeout() {
+    let timeouts = SessionTimeouts {
+        upstream: Duration::from_millis(300),
+        downstream: Duration::from_secs(3600),
+        ..Default::default()
+    };
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(PoolScript::default(), upstream_rx);
+    let proxy = Proxy::start(
+        upstream,
+        server::TranslationHandler::new(None).with_timeouts(timeouts),
+        None,
+    );
+
+    let mut conn = proxy.connect_framed();
+    open_channel(&mut conn).await;
+    let silent_since = Instant::now();
+    wait_for_close(&mut conn).await;
+    // The pool has sent its last job shortly before the channel has been opened
+    assert!(silent_since.elapsed() >= timeouts.upstream / 2);
+    assert!(silent_since.elapsed() < timeouts.downstream);
+    proxy.halt();
+}
+
+/// Share that the pool never answers is rejected once the submit timeout elapses even though
+/// neither side sends anything in the meantime
+#[tokio::test]
+async fn test_submit_timeout() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(
+        PoolScript::default().with_submit_policy(SubmitPolicy::IgnoreAll),
+        upstream_rx,
+    );
+    let proxy = Proxy::start(
+        upstream,
+        server::TranslationHandler::new(None).with_options(V2ToV1TranslationOptions {
+            v1_submit_timeout: Some(Duration::from_millis(300)),
+            ..Default::default()
+        }),
+        None,
+    );
+
+    let mut conn = proxy.connect_framed();
+    open_channel(&mut conn).await;
+    send(&mut conn, test_utils::v2::build_submit_shares()).await;
+    let error = receive::<v2::messages::SubmitSharesError>(&mut conn).await;
+    assert_eq!(error.code.as_str(), "upstream-timeout");
+    proxy.halt();
+}
+
+/// The connection handler can be driven directly without any server, the summary reports the
+/// silent downstream
+#[tokio::test]
+async fn test_downstream_timeout() {
+    let timeouts = SessionTimeouts {
+        upstream: Duration::from_secs(3600),
+        downstream: Duration::from_millis(300),
+        ..Default::default()
+    };
+    let pool = SimPool::new(PoolScript::default());
+    let (v1_stream, pool_stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { pool.serve(pool_stream).await });
+    let (v2_stream, miner_stream) = tokio::io::duplex(64 * 1024);
+    let mut miner_conn = v2::Framed::new(miner_stream, Default::default());
+
+    let mut handler = server::TranslationHandler::new(None).with_timeouts(timeouts);
+    let session = ConnectionHandler::<DuplexStream, DuplexStream>::handle_connection(
+        &mut handler,
+        v2::Framed::new(v2_stream, Default::default()),
+        DownstreamPeer::new(addr("127.0.0.2:1234")),
+        SessionUpstream::Connected(
+            v1::Framed::new(v1_stream, Default::default()),
+            addr("10.0.0.1:3333"),
+            None,
+        ),
+        CancellationToken::new(),
+        tokio::sync::mpsc::unbounded_channel().1,
+        Default::default(),
+    );
+    let session = tokio::spawn(session);
+
+    open_channel(&mut miner_conn).await;
+    let summary = session.await.expect("BUG: Session panicked");
+    assert!(summary.duration >= timeouts.downstream);
+    assert!(summary.duration < timeouts.upstream);
+    match summary.close_reason {
+        CloseReason::Error(e) => {
+            assert_eq!(e.kind(), ErrorKind::Timeout);
+            assert_eq!(
+                e.context().and_then(|context| context.direction),
+                Some(Direction::Downstream)
+            );
+        }
+        reason => panic!("BUG: Unexpected close reason: {}", reason),
+    }
+}
+
+/// Cancelling the session terminates both connections
+#[tokio::test]
+async fn test_session_cancelled() {
+    let pool = SimPool::new(PoolScript::default());
+    let (v1_stream, pool_stream) = tokio::io::duplex(64 * 1024);
+    tokio::spawn(async move { pool.serve(pool_stream).await });
+    let (v2_stream, miner_stream) = tokio::io::duplex(64 * 1024);
+    let mut miner_conn = v2::Framed::new(miner_stream, Default::default());
+
+    let cancel = CancellationToken::new();
+    let mut handler = server::TranslationHandler::new(None);
+    let session = tokio::spawn(handler.handle_connection(
+        v2::Framed::new(v2_stream, Default::default()),
+        DownstreamPeer::new(addr("127.0.0.2:1234")),
+        SessionUpstream::Connected(
+            v1::Framed::<DuplexStream>::new(v1_stream, Default::default()),
+            addr("10.0.0.1:3333"),
+            None,
+        ),
+        cancel.clone(),
+        tokio::sync::mpsc::unbounded_channel().1,
+        Default::default(),
+    ));
+
+    open_channel(&mut miner_conn).await;
+    cancel.cancel();
+    let summary = session.await.expect("BUG: Session panicked");
+    assert!(matches!(summary.close_reason, CloseReason::Cancelled));
+    wait_for_close(&mut miner_conn).await;
+}
+
+/// CPU time of a running session is available via the server handle
+#[tokio::test]
+async fn test_session_cpu_accounted() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(PoolScript::default(), upstream_rx);
+    let proxy = Proxy::start_with(
+        upstream,
+        server::TranslationHandler::new(None),
+        None,
+        |v2server| v2server.with_cpu_accounting(true),
+    );
+
+    let mut conn = proxy.connect_framed();
+    open_channel(&mut conn).await;
+    let sessions = proxy.server_handle.sessions();
+    assert_eq!(sessions.len(), 1);
+    let cpu_time = sessions[0]
+        .cpu_time
+        .as_ref()
+        .expect("BUG: CPU time not accounted")
+        .cpu_time();
+    assert!(
+        cpu_time > Duration::from_secs(0),
+        "BUG: No CPU time accounted"
+    );
+    proxy.halt();
+}
+
+/// Device announced in SetupConnection is listed among the running sessions
+#[tokio::test]
+async fn test_session_device_listed() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(PoolScript::default(), upstream_rx);
+    let proxy = Proxy::start(upstream, server::TranslationHandler::new(None), None);
+
+    let mut conn = proxy.connect_framed();
+    open_channel(&mut conn).await;
+    let sessions = proxy.server_handle.sessions();
+    assert_eq!(sessions.len(), 1);
+    assert_eq!(
+        sessions[0].stats.device(),
+        Some(test_utils::v2::build_setup_connection().device)
+    );
+    proxy.halt();
+}
+
+/// Reconnect requested via the server handle is delivered to the downstream device
+#[tokio::test]
+async fn test_reconnect_requested() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(PoolScript::default(), upstream_rx);
+    let proxy = Proxy::start(upstream, server::TranslationHandler::new(None), None);
+
+    let mut conn = proxy.connect_framed();
+    open_channel(&mut conn).await;
+    let reconnect = v2::messages::Reconnect {
+        new_host: "proxy2.example.com".try_into().expect("BUG: invalid host"),
+        new_port: 3336,
+    };
+    assert_eq!(proxy.server_handle.reconnect(reconnect.clone(), 100), 1);
+    assert_eq!(
+        receive::<v2::messages::Reconnect>(&mut conn).await,
+        reconnect
+    );
+    proxy.halt();
+}
+
+/// Sessions of a drained upstream are asked to reconnect and new sessions avoid the upstream
+#[tokio::test]
+async fn test_upstream_drained() {
+    let (first, first_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    let (second, second_rx) = ChannelUpstream::new(addr("10.0.0.2:3333"));
+    let first_stats = start_pool(PoolScript::default(), first_rx);
+    let second_stats = start_pool(PoolScript::default(), second_rx);
+    let first_name = first.to_string();
+    let proxy = Proxy::start(
+        UpstreamSet::new(vec![first, second]),
+        server::TranslationHandler::new(None),
+        None,
+    );
+
+    let mut conn = proxy.connect_framed();
+    open_channel(&mut conn).await;
+    let sessions = proxy.server_handle.sessions();
+    assert_eq!(sessions[0].upstream_name.as_ref(), Some(&first_name));
+
+    assert_eq!(
+        proxy
+            .server_handle
+            .drain_upstream(&first_name)
+            .await
+            .expect("BUG: Cannot drain upstream"),
+        1
+    );
+    let reconnect = receive::<v2::messages::Reconnect>(&mut conn).await;
+    assert_eq!(reconnect.new_host.to_string(), "");
+    assert_eq!(reconnect.new_port, 0);
+    assert!(proxy
+        .server_handle
+        .drain_upstream("10.0.0.3:3333")
+        .await
+        .is_err());
+
+    // Both reconnecting and new devices are routed to the remaining upstream
+    for _ in 0..2 {
+        let mut conn = proxy.connect_framed();
+        open_channel(&mut conn).await;
+    }
+    assert_eq!(first_stats.connections(), 1);
+    assert_eq!(second_stats.connections(), 2);
+
+    proxy
+        .server_handle
+        .resume_upstream(&first_name)
+        .await
+        .expect("BUG: Cannot resume upstream");
+    // Round robin routes one of the next two sessions to the resumed upstream
+    for _ in 0..2 {
+        let mut conn = proxy.connect_framed();
+        open_channel(&mut conn).await;
+    }
+    assert_eq!(first_stats.connections(), 2);
+    proxy.halt();
+}
+
+#[tokio::test]
+async fn test_sticky_routing() {
+    let (first, first_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    let (second, second_rx) = ChannelUpstream::new(addr("10.0.0.2:3333"));
+    let first_stats = start_pool(PoolScript::default(), first_rx);
+    let second_stats = start_pool(PoolScript::default(), second_rx);
+    let proxy = Proxy::start(
+        UpstreamSet::new(vec![first, second]).with_routing(UpstreamRouting::Sticky),
+        server::TranslationHandler::new(None),
+        None,
+    );
+
+    // Nothing is sent upstream until the worker is known
+    let mut conn = proxy.connect_framed();
+    send(&mut conn, test_utils::v2::build_setup_connection()).await;
+    receive::<v2::messages::SetupConnectionSuccess>(&mut conn).await;
+    assert_eq!(first_stats.connections() + second_stats.connections(), 0);
+    send(&mut conn, test_utils::v2::build_open_channel()).await;
+    receive::<v2::messages::OpenStandardMiningChannelSuccess>(&mut conn).await;
+    let upstream_name = proxy.server_handle.sessions()[0].upstream_name.clone();
+    assert!(upstream_name.is_some());
+    drop(conn);
+
+    // The worker lands on the same upstream each time it reconnects
+    for _ in 0..3 {
+        let mut conn = proxy.connect_framed();
+        open_channel(&mut conn).await;
+        let sessions = proxy.server_handle.sessions();
+        assert!(sessions
+            .iter()
+            .all(|session| session.upstream_name == upstream_name));
+    }
+    let mut connections = vec![first_stats.connections(), second_stats.connections()];
+    connections.sort_unstable();
+    assert_eq!(connections, vec![0, 4]);
+    proxy.halt();
+}
+
+/// Allows channels only once it is switched on
+#[derive(Default)]
+struct SwitchedAuthenticator {
+    allowed: AtomicBool,
+}
+
+#[async_trait]
+impl Authenticator for SwitchedAuthenticator {
+    async fn authenticate(
+        &self,
+        request: &AuthRequest,
+    ) -> ii_stratum_proxy::error::Result<AuthDecision> {
+        assert_eq!(request.source_addr.ip(), addr("127.0.0.2:0").ip());
+        if self.allowed.load(Relaxed) {
+            Ok(AuthDecision::Allow)
+        } else {
+            Ok(AuthDecision::Deny("switched off".to_string()))
+        }
+    }
+}
+
+#[tokio::test]
+async fn test_channel_authentication() {
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    let pool_stats = start_pool(PoolScript::default(), upstream_rx);
+    let authenticator = Arc::new(SwitchedAuthenticator::default());
+    let proxy = Proxy::start(
+        upstream,
+        server::TranslationHandler::new(None).with_authenticator(authenticator.clone()),
+        None,
+    );
+
+    let mut conn = proxy.connect_framed();
+    send(&mut conn, test_utils::v2::build_setup_connection()).await;
+    receive::<v2::messages::SetupConnectionSuccess>(&mut conn).await;
+    send(&mut conn, test_utils::v2::build_open_channel()).await;
+    let error = receive::<v2::messages::OpenMiningChannelError>(&mut conn).await;
+    assert_eq!(error.code.to_string(), "unknown-user");
+    // Denied channel never reaches the pool
+    assert_eq!(pool_stats.connections(), 0);
+
+    authenticator.allowed.store(true, Relaxed);
+    send(&mut conn, test_utils::v2::build_open_channel()).await;
+    receive::<v2::messages::OpenStandardMiningChannelSuccess>(&mut conn).await;
+    assert_eq!(pool_stats.connections(), 1);
+    proxy.halt();
+}
+
+/// Passes exported events to the test
+struct ChannelSink {
+    event_tx: futures::channel::mpsc::UnboundedSender<Event>,
+}
+
+#[async_trait]
+impl EventSink for ChannelSink {
+    async fn deliver(&mut self, events: &[EventRecord]) -> ii_stratum_proxy::error::Result<()> {
+        for record in events {
+            self.event_tx
+                .unbounded_send(record.event.clone())
+                .expect("BUG: event receiver dropped");
+        }
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn test_events_exported() {
+    const SHARES: u64 = 4;
+
+    let (upstream, upstream_rx) = ChannelUpstream::new(addr("10.0.0.1:3333"));
+    start_pool(
+        PoolScript::default().with_submit_policy(SubmitPolicy::RejectEvery(2)),
+        upstream_rx,
+    );
+    let (event_tx, event_rx) = futures::channel::mpsc::unbounded();
+    let (event_exporter, export_task) = EventExporter::new(Box::new(ChannelSink { event_tx }), 16);
+    tokio::spawn(export_task);
+    let proxy = Proxy::start(
+        upstream,
+        server::TranslationHandler::new(None).with_event_exporter(event_exporter),
+        None,
+    );
+
+    let mut config = MinerConfig::new(addr("127.0.0.1:3336"));
+    config.shares_per_session = Some(SHARES);
+    SimMiner::new(config)
+        .run_session(0, proxy.connect_framed())
+        .await
+        .expect("BUG: Simulated miner session failed");
+
+    let events: Vec<Event> = tokio::time::timeout(
+        Duration::from_secs(5),
+        event_rx
+            .take_while(|event| future::ready(!matches!(event, Event::ConnectionClosed { .. })))
+            .collect(),
+    )
+    .await
+    .expect("BUG: connection close not exported");
+    let source_addr = addr("127.0.0.2:1234");
+    assert_eq!(events[0], Event::ConnectionOpened { source_addr });
+    let count = |accepted: bool| {
+        events
+            .iter()
+            .filter(|event| match event {
+                Event::ShareAccepted { .. } => accepted,
+                Event::ShareRejected { .. } => !accepted,
+                _ => false,
+            })
+            .count() as u64
+    };
+    assert_eq!(count(true), SHARES / 2);
+    assert_eq!(count(false), SHARES / 2);
+    proxy.halt();
+}
@@ -0,0 +1,172 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Translation of complete V2 sessions against a simulated V1 pool
+
+use futures::prelude::*;
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use ii_async_utils::HaltHandle;
+use ii_stratum::test_utils;
+use ii_stratum::v2;
+use ii_stratum_proxy::server;
+use ii_stratum_sim::{
+    MinerConfig, PoolScript, PoolStats, ScriptStep, SimMiner, SimPool, SubmitPolicy,
+};
+use ii_wire::Address;
+
+mod utils;
+use utils::{open_channel, V2Conn};
+
+/// The load test listens on an ephemeral port unless a port is given by this variable
+const LOAD_TEST_PORT_VAR: &str = "STRATUM_PROXY_LOAD_TEST_PORT";
+
+/// Starts the simulated pool and a proxy translating for it, returns a V2 connection to the
+/// proxy, statistics of the pool and a handle that stops the proxy
+async fn start_session(script: PoolScript) -> (V2Conn, PoolStats, std::sync::Arc<HaltHandle>) {
+    let pool = SimPool::new(script);
+    let stats = pool.stats();
+    let (pool_addr, serve) = pool
+        .bind("127.0.0.1:0".parse().expect("BUG: invalid address"))
+        .await
+        .expect("BUG: Cannot bind the simulated pool");
+    tokio::spawn(serve);
+
+    let local_addr: SocketAddr = "127.0.0.1:3336".parse().expect("BUG: invalid address");
+    let (connection_tx, listener) = server::listener::ChannelListener::new(local_addr);
+    let v2server = server::ProxyServer::with_listener(
+        listener,
+        Address(pool_addr.ip().to_string(), pool_addr.port()),
+        server::TranslationHandler::new(None),
+        None,
+        server::ProxyProtocolConfig::default(),
+        None,
+    );
+    let halt_handle = HaltHandle::arc();
+    halt_handle.spawn_object(v2server);
+    halt_handle.ready();
+
+    let (client_stream, server_stream) = tokio::io::duplex(4096);
+    connection_tx
+        .unbounded_send(server::IncomingConnection {
+            stream: server_stream,
+            peer_addr: "127.0.0.2:1234".parse().expect("BUG: invalid address"),
+            local_addr,
+        })
+        .expect("BUG: cannot pass connection to the listener");
+    let conn = tokio_util::codec::Framed::new(
+        client_stream,
+        <v2::Framing as ii_wire::Framing>::Codec::default(),
+    );
+    (conn, stats, halt_handle)
+}
+
+#[tokio::test]
+async fn test_open_channel() {
+    let (mut conn, pool_stats, halt_handle) = start_session(PoolScript::default()).await;
+    open_channel(&mut conn).await;
+    assert_eq!(pool_stats.connections(), 1);
+    assert_eq!(pool_stats.authorized(), 1);
+    halt_handle.halt();
+}
+
+#[tokio::test]
+async fn test_pool_disconnect_terminates_session() {
+    let script = PoolScript::default().with_steps(vec![
+        ScriptStep::SetDifficulty(test_utils::v1::build_set_difficulty().value()),
+        ScriptStep::Notify(test_utils::v1::build_mining_notify()),
+        ScriptStep::Sleep(Duration::from_millis(100)),
+        ScriptStep::Disconnect,
+    ]);
+    let (mut conn, _pool_stats, halt_handle) = start_session(script).await;
+    open_channel(&mut conn).await;
+    let closed = tokio::time::timeout(Duration::from_secs(5), conn.next())
+        .await
+        .expect("BUG: Session hasn't been terminated");
+    assert!(closed.is_none(), "BUG: Unexpected frame {:?}", closed);
+    halt_handle.halt();
+}
+
+/// Many concurrent miners: every share has to be answered and the results have to match the
+/// decisions of the pool
+#[tokio::test]
+async fn test_concurrent_miners() {
+    const SESSIONS: u64 = 50;
+    const SHARES_PER_SESSION: u64 = 8;
+
+    let pool = SimPool::new(PoolScript::default().with_submit_policy(SubmitPolicy::RejectEvery(4)));
+    let pool_stats = pool.stats();
+    let (pool_addr, serve) = pool
+        .bind("127.0.0.1:0".parse().expect("BUG: invalid address"))
+        .await
+        .expect("BUG: Cannot bind the simulated pool");
+    tokio::spawn(serve);
+
+    let port = std::env::var(LOAD_TEST_PORT_VAR).map_or(0, |port| {
+        port.parse()
+            .unwrap_or_else(|_| panic!("BUG: Invalid {}: {}", LOAD_TEST_PORT_VAR, port))
+    });
+    let proxy_addr = Address("127.0.0.1".into(), port);
+    let v2server = server::ProxyServer::listen(
+        proxy_addr,
+        Address(pool_addr.ip().to_string(), pool_addr.port()),
+        server::TranslationHandler::new(None),
+        None,
+        server::ProxyProtocolConfig::default(),
+        None,
+    )
+    .await
+    .expect("BUG: Could not bind v2server");
+    let proxy_addr = v2server
+        .local_addr()
+        .expect("BUG: Cannot get address of v2server");
+    let halt_handle = HaltHandle::arc();
+    halt_handle.spawn_object(v2server);
+    halt_handle.ready();
+
+    let mut config = MinerConfig::new(proxy_addr);
+    config.sessions = SESSIONS as usize;
+    config.shares_per_session = Some(SHARES_PER_SESSION);
+    // Difficulty 4 of the pool results in a share every ~2ms
+    config.hashrate = 1e13;
+    let miner = SimMiner::new(config);
+    tokio::time::timeout(Duration::from_secs(30), miner.run())
+        .await
+        .expect("BUG: Simulated miners haven't finished");
+
+    let stats = miner.stats();
+    assert_eq!(stats.sessions_failed(), 0);
+    assert_eq!(stats.channels_opened(), SESSIONS);
+    assert_eq!(stats.shares_submitted(), SESSIONS * SHARES_PER_SESSION);
+    assert_eq!(stats.shares_unanswered(), 0);
+    assert_eq!(
+        stats.shares_accepted(),
+        u64::from(pool_stats.accepted_submits())
+    );
+    assert_eq!(
+        stats.shares_rejected(),
+        u64::from(pool_stats.rejected_submits())
+    );
+    assert_eq!(stats.shares_rejected(), SESSIONS * SHARES_PER_SESSION / 4);
+    halt_handle.halt();
+}
@@ -20,10 +20,25 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
+// Not every test crate uses all of the helpers
+#![allow(dead_code)]
+
 use futures::prelude::*;
+use std::convert::{TryFrom, TryInto};
 use std::time::Duration;
+use tokio::io::DuplexStream;
 use tokio::time;
 
+use ii_stratum::test_utils;
+use ii_stratum::v2;
+
+/// Limit for receiving a single message or the close of a connection so that a stuck proxy fails
+/// the test instead of hanging it
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// V2 connection to an in-memory proxy
+pub type V2Conn = v2::Framed<DuplexStream>;
+
 /// Run an async function/lambda repeatedly with backoff until it
 /// returns Ok(...) or until the number of inerations is reached.
 ///
@@ -57,3 +72,47 @@ pub async fn backoff<E, T, FT: Future<Output = Result<T, E>>, F: Fn() -> FT>(
 
     res
 }
+
+pub async fn send<M>(conn: &mut V2Conn, message: M)
+where
+    M: TryInto<v2::Frame>,
+    <M as TryInto<v2::Frame>>::Error: std::fmt::Debug,
+{
+    conn.send(message.try_into().expect("BUG: Cannot convert to frame"))
+        .await
+        .expect("BUG: Could not send message");
+}
+
+pub async fn receive<M>(conn: &mut V2Conn) -> M
+where
+    M: TryFrom<v2::Frame>,
+    <M as TryFrom<v2::Frame>>::Error: std::fmt::Debug,
+{
+    let frame = time::timeout(RECEIVE_TIMEOUT, conn.next())
+        .await
+        .expect("BUG: Timeout waiting for a message")
+        .expect("BUG: Connection closed")
+        .expect("BUG: Failed to receive frame");
+    M::try_from(frame).expect("BUG: Unexpected message")
+}
+
+/// Opens a mining channel via the proxy and expects the first job of the pool
+pub async fn open_channel(conn: &mut V2Conn) {
+    send(conn, test_utils::v2::build_setup_connection()).await;
+    receive::<v2::messages::SetupConnectionSuccess>(conn).await;
+    send(conn, test_utils::v2::build_open_channel()).await;
+    receive::<v2::messages::OpenStandardMiningChannelSuccess>(conn).await;
+    receive::<v2::messages::NewMiningJob>(conn).await;
+    receive::<v2::messages::SetNewPrevHash>(conn).await;
+}
+
+/// Waits until the proxy closes `conn`, any frames received in the meantime are ignored
+pub async fn wait_for_close(conn: &mut V2Conn) {
+    time::timeout(RECEIVE_TIMEOUT, async {
+        while let Some(frame) = conn.next().await {
+            frame.expect("BUG: Failed to receive frame");
+        }
+    })
+    .await
+    .expect("BUG: Timeout waiting for the connection to close");
+}
@@ -0,0 +1,136 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Load generator that simulates V2 miners connected to a V2 endpoint (e.g. the stratum proxy)
+
+use anyhow::{anyhow, Result};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::time::Duration;
+use structopt::StructOpt;
+
+use ii_logging::macros::*;
+use ii_stratum::v2::noise::auth::EncodedEd25519PublicKey;
+use ii_stratum_sim::{MinerConfig, MinerStats, SimMiner};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "stratum-sim-miner", about = "Simulated Stratum V2 miners")]
+struct Args {
+    #[structopt(
+        long,
+        default_value = "127.0.0.1:3336",
+        help("V2 endpoint to connect to")
+    )]
+    endpoint: SocketAddr,
+    #[structopt(
+        long,
+        help(
+            "Base58 encoded authority public key of the endpoint, insecure V2 is used if not set"
+        )
+    )]
+    authority_public_key: Option<String>,
+    #[structopt(long, default_value = "1", help("Number of concurrent sessions"))]
+    sessions: usize,
+    #[structopt(
+        long,
+        default_value = "1",
+        help("Milliseconds between opening consecutive sessions")
+    )]
+    connect_interval_ms: u64,
+    #[structopt(long, default_value = "100e12", help("Hashrate of each session [h/s]"))]
+    hashrate: f64,
+    #[structopt(long, default_value = "sim", help("User name of the mining channels"))]
+    user: String,
+    #[structopt(long, help("Close each session after this number of shares"))]
+    shares_per_session: Option<u64>,
+    #[structopt(long, default_value = "10", help("Seconds between statistics reports"))]
+    report_interval: u64,
+}
+
+impl Args {
+    /// Rejects values that cannot be simulated
+    fn validate(&self) -> Result<()> {
+        if !(self.hashrate.is_finite() && self.hashrate > 0.0) {
+            return Err(anyhow!(
+                "Hashrate must be a positive number, got: {}",
+                self.hashrate
+            ));
+        }
+        if self.report_interval == 0 {
+            return Err(anyhow!("Report interval must be at least 1 second"));
+        }
+        Ok(())
+    }
+}
+
+fn report(stats: &MinerStats) {
+    info!(
+        "Sessions: {} (failed: {}), channels: {}, jobs: {}, shares submitted: {}, accepted: {}, \
+         rejected: {}, unanswered: {}",
+        stats.sessions_connected(),
+        stats.sessions_failed(),
+        stats.channels_opened(),
+        stats.jobs_received(),
+        stats.shares_submitted(),
+        stats.shares_accepted(),
+        stats.shares_rejected(),
+        stats.shares_unanswered()
+    );
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let _guard =
+        ii_logging::setup_for_app(ii_logging::LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE);
+    let args = Args::from_args();
+    args.validate()?;
+
+    let mut config = MinerConfig::new(args.endpoint);
+    config.authority_public_key = args
+        .authority_public_key
+        .map(|key| {
+            EncodedEd25519PublicKey::try_from(key)
+                .map(EncodedEd25519PublicKey::into_inner)
+                .map_err(|e| anyhow!("Invalid authority public key: {}", e))
+        })
+        .transpose()?;
+    config.sessions = args.sessions;
+    config.connect_interval = Duration::from_millis(args.connect_interval_ms);
+    config.hashrate = args.hashrate;
+    config.user = args.user;
+    config.shares_per_session = args.shares_per_session;
+
+    let miner = SimMiner::new(config);
+    let stats = miner.stats();
+    let mut report_interval = tokio::time::interval(Duration::from_secs(args.report_interval));
+    let run = miner.run();
+    tokio::pin!(run);
+    loop {
+        tokio::select! {
+            _ = &mut run => break,
+            _ = report_interval.tick() => report(&stats),
+            _ = tokio::signal::ctrl_c() => break,
+        }
+    }
+    report(&stats);
+    Ok(())
+}
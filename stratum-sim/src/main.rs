@@ -0,0 +1,119 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Simulated V1 pool for manual testing of the stratum proxy
+
+use anyhow::{Context, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use structopt::StructOpt;
+
+use ii_logging::macros::*;
+use ii_stratum::test_utils;
+use ii_stratum_sim::{PoolScript, ScriptStep, SimPool, SubmitPolicy};
+
+#[derive(Debug, StructOpt)]
+#[structopt(name = "stratum-sim", about = "Simulated Stratum V1 pool")]
+struct Args {
+    #[structopt(long, default_value = "127.0.0.1:3333", help("Address to listen on"))]
+    listen: SocketAddr,
+    #[structopt(
+        long,
+        default_value = "4",
+        help("Difficulty sent to clients once they authorize")
+    )]
+    difficulty: f32,
+    #[structopt(long, default_value = "30", help("Seconds between mining jobs"))]
+    job_interval: u64,
+    #[structopt(
+        long,
+        default_value = "100",
+        help("Number of mining jobs sent to each client")
+    )]
+    jobs: usize,
+    #[structopt(long, help("Reject every n-th submitted share"))]
+    reject_every: Option<u32>,
+    #[structopt(
+        long,
+        help("Reject all submitted shares"),
+        conflicts_with = "reject-every"
+    )]
+    reject_all: bool,
+    #[structopt(long, help("Disconnect clients after this number of submitted shares"))]
+    disconnect_after_submits: Option<u32>,
+    #[structopt(long, help("Disconnect clients once all jobs have been sent"))]
+    disconnect_after_jobs: bool,
+}
+
+impl Args {
+    fn script(&self) -> PoolScript {
+        let mut steps = vec![ScriptStep::SetDifficulty(self.difficulty)];
+        for i in 0..self.jobs {
+            if i > 0 {
+                steps.push(ScriptStep::Sleep(Duration::from_secs(self.job_interval)));
+            }
+            steps.push(ScriptStep::Notify(test_utils::v1::build_mining_notify()));
+        }
+        if self.disconnect_after_jobs {
+            steps.push(ScriptStep::Disconnect);
+        }
+        let submit_policy = if self.reject_all {
+            SubmitPolicy::RejectAll
+        } else if let Some(n) = self.reject_every {
+            SubmitPolicy::RejectEvery(n)
+        } else {
+            SubmitPolicy::AcceptAll
+        };
+        PoolScript::default()
+            .with_steps(steps)
+            .with_submit_policy(submit_policy)
+            .with_disconnect_after_submits(self.disconnect_after_submits)
+    }
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let _guard =
+        ii_logging::setup_for_app(ii_logging::LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE);
+    let args = Args::from_args();
+
+    let pool = SimPool::new(args.script());
+    let stats = pool.stats();
+    let (addr, serve) = pool
+        .bind(args.listen)
+        .await
+        .context("Cannot bind the simulated pool")?;
+    info!("Simulated pool listening on {}", addr);
+
+    tokio::select! {
+        _ = serve => (),
+        _ = tokio::signal::ctrl_c() => (),
+    }
+    info!(
+        "Connections: {}, authorized: {}, accepted submits: {}, rejected submits: {}",
+        stats.connections(),
+        stats.authorized(),
+        stats.accepted_submits(),
+        stats.rejected_submits()
+    );
+    Ok(())
+}
@@ -0,0 +1,433 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! V2 miner simulator that opens many sessions against a V2 endpoint (e.g. the translation proxy)
+//! and submits shares at the rate that corresponds to the channel target and simulated hashrate
+
+use futures::prelude::*;
+use std::convert::{TryFrom, TryInto};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpStream;
+use tokio::time::{self, Instant};
+
+use ii_logging::macros::*;
+use ii_stratum::error::{Error, Result};
+use ii_stratum::test_utils;
+use ii_stratum::v2::{
+    self,
+    framing::{Frame, MsgType},
+    messages::{
+        NewMiningJob, OpenMiningChannelError, OpenStandardMiningChannel,
+        OpenStandardMiningChannelSuccess, SetNewPrevHash, SetTarget, SetupConnectionError,
+        SetupConnectionSuccess, SubmitSharesError, SubmitSharesStandard, SubmitSharesSuccess,
+    },
+    noise::{negotiation::EncryptionAlgorithm, AuthorityPublicKey, Initiator},
+    types::{Str0_255, Uint256Bytes},
+};
+use ii_unvariant::Id;
+
+/// Parameters of the simulation
+#[derive(Clone, Debug)]
+pub struct MinerConfig {
+    /// V2 endpoint to connect to
+    pub endpoint: SocketAddr,
+    /// Run the noise handshake with the endpoint authenticated by this key, plain V2 framing is
+    /// used when not specified
+    pub authority_public_key: Option<AuthorityPublicKey>,
+    /// Number of concurrent sessions
+    pub sessions: usize,
+    /// Delay between opening consecutive sessions to spread the connection ramp-up
+    pub connect_interval: Duration,
+    /// Simulated hashrate of each session [h/s], it has to be positive
+    pub hashrate: f64,
+    /// User name of the mining channels, each session appends its index
+    pub user: String,
+    /// Close the session once this number of shares has been submitted and answered. Sessions
+    /// run until the endpoint closes them if not specified.
+    pub shares_per_session: Option<u64>,
+}
+
+impl MinerConfig {
+    pub fn new(endpoint: SocketAddr) -> Self {
+        Self {
+            endpoint,
+            authority_public_key: None,
+            sessions: 1,
+            connect_interval: Duration::from_millis(1),
+            hashrate: 100e12,
+            user: "sim".to_string(),
+            shares_per_session: None,
+        }
+    }
+}
+
+/// Counters of all sessions of a simulation. Clones share the counters.
+#[derive(Clone, Debug, Default)]
+pub struct MinerStats {
+    sessions_connected: Arc<AtomicU64>,
+    sessions_failed: Arc<AtomicU64>,
+    channels_opened: Arc<AtomicU64>,
+    jobs_received: Arc<AtomicU64>,
+    shares_submitted: Arc<AtomicU64>,
+    shares_accepted: Arc<AtomicU64>,
+    shares_rejected: Arc<AtomicU64>,
+}
+
+impl MinerStats {
+    pub fn sessions_connected(&self) -> u64 {
+        self.sessions_connected.load(Relaxed)
+    }
+
+    pub fn sessions_failed(&self) -> u64 {
+        self.sessions_failed.load(Relaxed)
+    }
+
+    pub fn channels_opened(&self) -> u64 {
+        self.channels_opened.load(Relaxed)
+    }
+
+    pub fn jobs_received(&self) -> u64 {
+        self.jobs_received.load(Relaxed)
+    }
+
+    pub fn shares_submitted(&self) -> u64 {
+        self.shares_submitted.load(Relaxed)
+    }
+
+    pub fn shares_accepted(&self) -> u64 {
+        self.shares_accepted.load(Relaxed)
+    }
+
+    pub fn shares_rejected(&self) -> u64 {
+        self.shares_rejected.load(Relaxed)
+    }
+
+    /// Shares that have been submitted but the endpoint hasn't responded to them (yet)
+    pub fn shares_unanswered(&self) -> u64 {
+        self.shares_submitted()
+            .saturating_sub(self.shares_accepted() + self.shares_rejected())
+    }
+}
+
+/// Expected time between two shares of a device with `hashrate` mining on `target`
+pub fn share_interval(target: &Uint256Bytes, hashrate: f64) -> Duration {
+    // The target is little endian
+    let target = target
+        .0
+        .iter()
+        .rev()
+        .fold(0f64, |value, byte| value * 256.0 + f64::from(*byte));
+    let expected_hashes = 2f64.powi(256) / (target + 1.0);
+    Duration::from_secs_f64(expected_hashes / hashrate)
+}
+
+fn is<M: Id<MsgType>>(frame: &Frame) -> bool {
+    frame.header.msg_type == M::ID
+}
+
+/// Simulated V2 miner
+#[derive(Clone, Debug)]
+pub struct SimMiner {
+    config: Arc<MinerConfig>,
+    stats: MinerStats,
+}
+
+impl SimMiner {
+    pub fn new(config: MinerConfig) -> Self {
+        Self {
+            config: Arc::new(config),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn stats(&self) -> MinerStats {
+        self.stats.clone()
+    }
+
+    /// Runs all sessions of the simulation and completes once all of them have terminated
+    pub async fn run(&self) {
+        let mut sessions = Vec::with_capacity(self.config.sessions);
+        for index in 0..self.config.sessions {
+            if index > 0 {
+                time::sleep(self.config.connect_interval).await;
+            }
+            let miner = self.clone();
+            sessions.push(tokio::spawn(async move {
+                if let Err(e) = miner.connect_and_run_session(index).await {
+                    miner.stats.sessions_failed.fetch_add(1, Relaxed);
+                    info!("Simulated miner: session {} failed: {}", index, e);
+                }
+            }));
+        }
+        for session in sessions {
+            session
+                .await
+                .expect("BUG: Simulated miner session panicked");
+        }
+    }
+
+    async fn connect_and_run_session(&self, index: usize) -> Result<()> {
+        let stream = TcpStream::connect(self.config.endpoint).await?;
+        let conn = match self.config.authority_public_key {
+            Some(key) => {
+                Initiator::new(key, vec![EncryptionAlgorithm::AESGCM])
+                    .connect(stream)
+                    .await?
+            }
+            None => v2::Framed::new(stream, Default::default()),
+        };
+        self.run_session(index, conn).await
+    }
+
+    /// Runs a single session with index `index` over an established connection
+    pub async fn run_session<T>(&self, index: usize, conn: v2::Framed<T>) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.stats.sessions_connected.fetch_add(1, Relaxed);
+        MinerSession {
+            conn,
+            config: &self.config,
+            stats: &self.stats,
+            user: format!("{}.{}", self.config.user, index),
+            channel_id: None,
+            target: None,
+            job: None,
+            future_job: None,
+            seq_num: 0,
+            answered: 0,
+        }
+        .run()
+        .await
+    }
+}
+
+/// Job that the session mines on
+#[derive(Copy, Clone, Debug)]
+struct Job {
+    id: u32,
+    version: u32,
+    ntime: u32,
+}
+
+struct MinerSession<'a, T> {
+    conn: v2::Framed<T>,
+    config: &'a MinerConfig,
+    stats: &'a MinerStats,
+    user: String,
+    channel_id: Option<u32>,
+    target: Option<Uint256Bytes>,
+    /// Job with the previous block hash known
+    job: Option<Job>,
+    /// Job waiting for `SetNewPrevHash`
+    future_job: Option<NewMiningJob>,
+    /// Sequence number of the next share
+    seq_num: u32,
+    /// Number of shares accepted or rejected by the endpoint
+    answered: u64,
+}
+
+impl<'a, T> MinerSession<'a, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    async fn run(&mut self) -> Result<()> {
+        self.send(test_utils::v2::build_setup_connection()).await?;
+        let frame = self.receive().await?;
+        if is::<SetupConnectionError>(&frame) {
+            return Err(Error::General(format!(
+                "Setup connection failed: {:?}",
+                SetupConnectionError::try_from(frame)?
+            )));
+        }
+        SetupConnectionSuccess::try_from(frame)?;
+
+        let open_channel = OpenStandardMiningChannel {
+            req_id: 0,
+            user: Str0_255::try_from(self.user.as_str())?,
+            nominal_hashrate: self.config.hashrate as f32,
+            max_target: Uint256Bytes([0xff; 32]),
+        };
+        self.send(open_channel).await?;
+
+        let mut next_share: Option<Instant> = None;
+        loop {
+            if self.all_shares_answered() {
+                return Ok(());
+            }
+            if next_share.is_none() && self.submits_remaining() {
+                next_share = self
+                    .share_interval()
+                    .map(|interval| Instant::now() + interval);
+            }
+            let frame = match next_share {
+                Some(deadline) => tokio::select! {
+                    frame = self.receive() => Some(frame?),
+                    _ = time::sleep_until(deadline) => None,
+                },
+                None => Some(self.receive().await?),
+            };
+            match frame {
+                Some(frame) => self.handle_frame(frame)?,
+                None => {
+                    next_share = None;
+                    self.submit_share().await?;
+                }
+            }
+        }
+    }
+
+    fn handle_frame(&mut self, frame: Frame) -> Result<()> {
+        if is::<OpenStandardMiningChannelSuccess>(&frame) {
+            let success = OpenStandardMiningChannelSuccess::try_from(frame)?;
+            self.channel_id = Some(success.channel_id);
+            self.target = Some(success.target);
+            self.stats.channels_opened.fetch_add(1, Relaxed);
+        } else if is::<OpenMiningChannelError>(&frame) {
+            return Err(Error::General(format!(
+                "Open channel failed: {:?}",
+                OpenMiningChannelError::try_from(frame)?
+            )));
+        } else if is::<NewMiningJob>(&frame) {
+            let job = NewMiningJob::try_from(frame)?;
+            if job.future_job {
+                self.future_job = Some(job);
+            } else {
+                let ntime = self.job.map_or(0, |job| job.ntime);
+                self.set_job(&job, ntime);
+            }
+        } else if is::<SetNewPrevHash>(&frame) {
+            let prev_hash = SetNewPrevHash::try_from(frame)?;
+            match self.future_job.take() {
+                Some(job) if job.job_id == prev_hash.job_id => {
+                    self.set_job(&job, prev_hash.min_ntime)
+                }
+                _ => {
+                    return Err(Error::General(format!(
+                        "SetNewPrevHash refers to unknown job {}",
+                        prev_hash.job_id
+                    )))
+                }
+            }
+        } else if is::<SetTarget>(&frame) {
+            self.target = Some(SetTarget::try_from(frame)?.max_target);
+        } else if is::<SubmitSharesSuccess>(&frame) {
+            let success = SubmitSharesSuccess::try_from(frame)?;
+            let accepted = u64::from(success.new_submits_accepted_count);
+            self.answered += accepted;
+            self.stats.shares_accepted.fetch_add(accepted, Relaxed);
+        } else if is::<SubmitSharesError>(&frame) {
+            let error = SubmitSharesError::try_from(frame)?;
+            trace!("Simulated miner: share rejected: {:?}", error);
+            self.answered += 1;
+            self.stats.shares_rejected.fetch_add(1, Relaxed);
+        } else {
+            trace!("Simulated miner: ignoring frame: {:?}", frame);
+        }
+        Ok(())
+    }
+
+    fn set_job(&mut self, job: &NewMiningJob, ntime: u32) {
+        self.job = Some(Job {
+            id: job.job_id,
+            version: job.version,
+            ntime,
+        });
+        self.stats.jobs_received.fetch_add(1, Relaxed);
+    }
+
+    /// Time to the next share, available once the channel is ready for mining
+    fn share_interval(&self) -> Option<Duration> {
+        match (&self.target, &self.job) {
+            (Some(target), Some(_)) => Some(share_interval(target, self.config.hashrate)),
+            _ => None,
+        }
+    }
+
+    fn submits_remaining(&self) -> bool {
+        self.config
+            .shares_per_session
+            .is_none_or(|limit| u64::from(self.seq_num) < limit)
+    }
+
+    fn all_shares_answered(&self) -> bool {
+        !self.submits_remaining() && self.answered >= u64::from(self.seq_num)
+    }
+
+    async fn submit_share(&mut self) -> Result<()> {
+        let (channel_id, job) = match (self.channel_id, self.job) {
+            (Some(channel_id), Some(job)) => (channel_id, job),
+            _ => return Ok(()),
+        };
+        let share = SubmitSharesStandard {
+            channel_id,
+            seq_num: self.seq_num,
+            job_id: job.id,
+            // The endpoint is not expected to validate the proof of work
+            nonce: self.seq_num,
+            ntime: job.ntime,
+            version: job.version,
+        };
+        self.seq_num += 1;
+        self.stats.shares_submitted.fetch_add(1, Relaxed);
+        self.send(share).await
+    }
+
+    async fn send<M>(&mut self, message: M) -> Result<()>
+    where
+        M: TryInto<Frame, Error = Error>,
+    {
+        self.conn.send(message.try_into()?).await
+    }
+
+    async fn receive(&mut self) -> Result<Frame> {
+        self.conn
+            .next()
+            .await
+            .unwrap_or_else(|| Err(Error::General("Connection closed by endpoint".to_string())))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_share_interval() {
+        // Difficulty 4 target of the sample channel
+        let target = test_utils::v2::build_open_channel_success().target;
+        let interval = share_interval(&target, 4.0 * 2f64.powi(32));
+        assert!(
+            (interval.as_secs_f64() - 1.0).abs() < 0.01,
+            "Unexpected interval {:?}",
+            interval
+        );
+        assert_eq!(
+            share_interval(&Uint256Bytes([0xff; 32]), 1.0),
+            Duration::from_secs(1)
+        );
+    }
+}
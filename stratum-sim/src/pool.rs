@@ -0,0 +1,499 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! V1 pool that serves each connection according to a `PoolScript`
+
+use futures::prelude::*;
+use std::convert::{TryFrom, TryInto};
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU32, Ordering::Relaxed};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::TcpListener;
+use tokio::time::{self, Instant};
+
+use ii_logging::macros::*;
+use ii_stratum::error::Result;
+use ii_stratum::test_utils;
+use ii_stratum::v1::{
+    self,
+    messages::{BooleanResult, Notify, SetDifficulty, Submit, SubscribeResult},
+    rpc::{Method, Request, Response, Rpc, StratumError, StratumResult},
+    ExtraNonce1,
+};
+
+/// Single step of the script that the pool plays to each client once it has authorized
+#[derive(Clone, Debug)]
+pub enum ScriptStep {
+    SetDifficulty(f32),
+    Notify(Notify),
+    /// Pause the script, requests of the client are still served
+    Sleep(Duration),
+    /// Close the connection
+    Disconnect,
+}
+
+/// Decides which submitted shares are accepted
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SubmitPolicy {
+    AcceptAll,
+    RejectAll,
+    /// Reject every n-th submit of a connection
+    RejectEvery(u32),
+    /// Never answer any submit
+    IgnoreAll,
+}
+
+impl SubmitPolicy {
+    /// Whether submit with sequence number `submit_count` (starting at 1) is accepted
+    fn accepts(&self, submit_count: u32) -> bool {
+        match self {
+            Self::AcceptAll => true,
+            Self::RejectAll => false,
+            Self::RejectEvery(n) => !submit_count.is_multiple_of(*n),
+            Self::IgnoreAll => false,
+        }
+    }
+}
+
+/// Behavior of the pool that is the same for every connection
+#[derive(Clone, Debug)]
+pub struct PoolScript {
+    pub extranonce1: ExtraNonce1,
+    pub extranonce2_size: usize,
+    /// Played after the client has authorized
+    pub steps: Vec<ScriptStep>,
+    pub submit_policy: SubmitPolicy,
+    /// Close the connection after responding to this number of submits
+    pub disconnect_after_submits: Option<u32>,
+}
+
+impl Default for PoolScript {
+    /// Sends the sample difficulty and job of `ii_stratum::test_utils` and accepts all shares
+    fn default() -> Self {
+        let subscribe_result = test_utils::v1::build_subscribe_ok_result();
+        Self {
+            extranonce1: subscribe_result.1,
+            extranonce2_size: subscribe_result.2,
+            steps: vec![
+                ScriptStep::SetDifficulty(test_utils::v1::build_set_difficulty().value()),
+                ScriptStep::Notify(test_utils::v1::build_mining_notify()),
+            ],
+            submit_policy: SubmitPolicy::AcceptAll,
+            disconnect_after_submits: None,
+        }
+    }
+}
+
+impl PoolScript {
+    pub fn with_steps(mut self, steps: Vec<ScriptStep>) -> Self {
+        self.steps = steps;
+        self
+    }
+
+    pub fn with_submit_policy(mut self, submit_policy: SubmitPolicy) -> Self {
+        self.submit_policy = submit_policy;
+        self
+    }
+
+    pub fn with_disconnect_after_submits(mut self, submits: Option<u32>) -> Self {
+        self.disconnect_after_submits = submits;
+        self
+    }
+}
+
+/// Counters of all connections served by a pool. Clones share the counters.
+#[derive(Clone, Debug, Default)]
+pub struct PoolStats {
+    connections: Arc<AtomicU32>,
+    authorized: Arc<AtomicU32>,
+    accepted_submits: Arc<AtomicU32>,
+    rejected_submits: Arc<AtomicU32>,
+}
+
+impl PoolStats {
+    pub fn connections(&self) -> u32 {
+        self.connections.load(Relaxed)
+    }
+
+    pub fn authorized(&self) -> u32 {
+        self.authorized.load(Relaxed)
+    }
+
+    pub fn accepted_submits(&self) -> u32 {
+        self.accepted_submits.load(Relaxed)
+    }
+
+    pub fn rejected_submits(&self) -> u32 {
+        self.rejected_submits.load(Relaxed)
+    }
+}
+
+/// Simulated V1 pool
+#[derive(Clone, Debug, Default)]
+pub struct SimPool {
+    script: Arc<PoolScript>,
+    stats: PoolStats,
+}
+
+impl SimPool {
+    /// Stratum error code of rejected shares
+    pub const REJECT_CODE: i32 = 23;
+
+    pub fn new(script: PoolScript) -> Self {
+        Self {
+            script: Arc::new(script),
+            stats: Default::default(),
+        }
+    }
+
+    pub fn stats(&self) -> PoolStats {
+        self.stats.clone()
+    }
+
+    /// Binds a listener for the pool and returns its address (useful with port 0) together with
+    /// a future that serves all incoming connections
+    pub async fn bind(
+        self,
+        addr: SocketAddr,
+    ) -> std::io::Result<(SocketAddr, impl Future<Output = ()>)> {
+        let listener = TcpListener::bind(addr).await?;
+        let local_addr = listener.local_addr()?;
+        Ok((local_addr, self.run(listener)))
+    }
+
+    /// Serves each incoming connection in a separate task
+    pub async fn run(self, listener: TcpListener) {
+        loop {
+            match listener.accept().await {
+                Ok((stream, peer)) => {
+                    let pool = self.clone();
+                    tokio::spawn(async move {
+                        if let Err(e) = pool.serve(stream).await {
+                            info!("Simulated pool: connection {} failed: {}", peer, e);
+                        }
+                    });
+                }
+                Err(e) => {
+                    warn!("Simulated pool: cannot accept connection: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Serves a single client connected via `stream` until the client disconnects or the script
+    /// disconnects it
+    pub async fn serve<T>(&self, stream: T) -> Result<()>
+    where
+        T: AsyncRead + AsyncWrite + Unpin,
+    {
+        self.stats.connections.fetch_add(1, Relaxed);
+        let mut session = PoolSession {
+            conn: v1::Framed::new(stream, Default::default()),
+            script: &self.script,
+            stats: &self.stats,
+            authorized: false,
+            next_step: 0,
+            submits: 0,
+        };
+        session.run().await
+    }
+}
+
+/// State of a single client connection
+struct PoolSession<'a, T> {
+    conn: v1::Framed<T>,
+    script: &'a PoolScript,
+    stats: &'a PoolStats,
+    authorized: bool,
+    /// Index of the next script step to be played
+    next_step: usize,
+    submits: u32,
+}
+
+/// What to do with the connection after processing a step or request
+enum Flow {
+    Continue,
+    Disconnect,
+}
+
+impl<'a, T> PoolSession<'a, T>
+where
+    T: AsyncRead + AsyncWrite + Unpin,
+{
+    async fn run(&mut self) -> Result<()> {
+        let mut paused_until: Option<Instant> = None;
+        loop {
+            if let Some(deadline) = paused_until {
+                if Instant::now() >= deadline {
+                    paused_until = None;
+                }
+            }
+            let script_pending = self.authorized && self.next_step < self.script.steps.len();
+            if script_pending && paused_until.is_none() {
+                let step = self.script.steps[self.next_step].clone();
+                self.next_step += 1;
+                match step {
+                    ScriptStep::SetDifficulty(difficulty) => {
+                        self.send_request(None, SetDifficulty::from(difficulty))
+                            .await?
+                    }
+                    ScriptStep::Notify(notify) => self.send_request(None, notify).await?,
+                    ScriptStep::Sleep(duration) => paused_until = Some(Instant::now() + duration),
+                    ScriptStep::Disconnect => return Ok(()),
+                }
+                continue;
+            }
+
+            let frame = match paused_until {
+                Some(deadline) => tokio::select! {
+                    frame = self.conn.next() => frame,
+                    _ = time::sleep_until(deadline) => continue,
+                },
+                None => self.conn.next().await,
+            };
+            let rpc = match frame {
+                Some(frame) => Rpc::try_from(frame?)?,
+                // Client has disconnected
+                None => return Ok(()),
+            };
+            match rpc {
+                Rpc::Request(request) => {
+                    if let Flow::Disconnect = self.handle_request(request).await? {
+                        return Ok(());
+                    }
+                }
+                Rpc::Response(response) => {
+                    trace!("Simulated pool: ignoring response: {:?}", response);
+                }
+            }
+        }
+    }
+
+    async fn handle_request(&mut self, request: Request) -> Result<Flow> {
+        let id = request.id.unwrap_or_default();
+        match request.payload.method {
+            Method::Configure => {
+                self.send_result(id, test_utils::v1::build_configure_ok_result())
+                    .await?;
+            }
+            Method::Subscribe => {
+                let result = SubscribeResult(
+                    vec![],
+                    self.script.extranonce1.clone(),
+                    self.script.extranonce2_size,
+                );
+                self.send_result(id, result).await?;
+            }
+            Method::ExtranonceSubscribe => self.send_result(id, BooleanResult(true)).await?,
+            Method::Authorize => {
+                self.send_result(id, BooleanResult(true)).await?;
+                if !self.authorized {
+                    self.authorized = true;
+                    self.stats.authorized.fetch_add(1, Relaxed);
+                }
+            }
+            Method::Submit => {
+                let submit = Submit::try_from(request)?;
+                trace!("Simulated pool: submit: {:?}", submit);
+                self.submits += 1;
+                if self.script.submit_policy == SubmitPolicy::IgnoreAll {
+                    debug!("Simulated pool: ignoring submit");
+                } else if self.script.submit_policy.accepts(self.submits) {
+                    self.stats.accepted_submits.fetch_add(1, Relaxed);
+                    self.send_result(id, BooleanResult(true)).await?;
+                } else {
+                    self.stats.rejected_submits.fetch_add(1, Relaxed);
+                    self.send_error(id, SimPool::REJECT_CODE, "Rejected by simulated pool")
+                        .await?;
+                }
+                if Some(self.submits) == self.script.disconnect_after_submits {
+                    return Ok(Flow::Disconnect);
+                }
+            }
+            method => {
+                debug!("Simulated pool: unsupported method {:?}", method);
+                self.send_error(id, 20, "Other/Unknown").await?;
+            }
+        }
+        Ok(Flow::Continue)
+    }
+
+    async fn send_request<M>(&mut self, id: v1::MessageId, message: M) -> Result<()>
+    where
+        M: TryInto<v1::rpc::RequestPayload, Error = ii_stratum::error::Error>,
+    {
+        let rpc = Rpc::from(Request {
+            id,
+            payload: message.try_into()?,
+        });
+        self.send(rpc).await
+    }
+
+    async fn send_result<R: serde::Serialize>(&mut self, id: u32, result: R) -> Result<()> {
+        self.send(Rpc::from(Response {
+            id,
+            stratum_result: Some(StratumResult::new(result)?),
+            stratum_error: None,
+        }))
+        .await
+    }
+
+    async fn send_error(&mut self, id: u32, code: i32, message: &str) -> Result<()> {
+        self.send(Rpc::from(Response {
+            id,
+            stratum_result: None,
+            stratum_error: Some(StratumError(code, message.to_string(), None)),
+        }))
+        .await
+    }
+
+    async fn send(&mut self, rpc: Rpc) -> Result<()> {
+        let frame: v1::Frame = rpc.try_into()?;
+        self.conn.send(frame).await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Client side of a connection to the simulated pool
+    struct TestClient {
+        conn: v1::Framed<tokio::io::DuplexStream>,
+    }
+
+    impl TestClient {
+        fn connect(pool: &SimPool) -> Self {
+            let (client, server) = tokio::io::duplex(4096);
+            let pool = pool.clone();
+            tokio::spawn(async move { pool.serve(server).await });
+            Self {
+                conn: v1::Framed::new(client, Default::default()),
+            }
+        }
+
+        async fn send(&mut self, rpc: Rpc) {
+            let frame: v1::Frame = rpc.try_into().expect("BUG: Cannot build frame");
+            self.conn.send(frame).await.expect("BUG: Cannot send frame");
+        }
+
+        async fn receive(&mut self) -> Option<Rpc> {
+            self.conn
+                .next()
+                .await
+                .map(|frame| Rpc::try_from(frame.expect("BUG: Invalid frame")).expect("BUG: Rpc"))
+        }
+
+        async fn receive_response(&mut self) -> Response {
+            match self.receive().await {
+                Some(Rpc::Response(response)) => response,
+                rpc => panic!("BUG: Expected response, got {:?}", rpc),
+            }
+        }
+
+        async fn receive_request(&mut self) -> Request {
+            match self.receive().await {
+                Some(Rpc::Request(request)) => request,
+                rpc => panic!("BUG: Expected request, got {:?}", rpc),
+            }
+        }
+
+        async fn submit(&mut self, id: u32) -> Response {
+            self.send(test_utils::v1::build_request_message(
+                Some(id),
+                test_utils::v1::build_mining_submit(),
+            ))
+            .await;
+            self.receive_response().await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_script_and_submit_policy() {
+        let pool = SimPool::new(
+            PoolScript::default()
+                .with_submit_policy(SubmitPolicy::RejectEvery(2))
+                .with_disconnect_after_submits(Some(3)),
+        );
+        let mut client = TestClient::connect(&pool);
+
+        client
+            .send(test_utils::v1::build_subscribe_request_frame())
+            .await;
+        let response = client.receive_response().await;
+        assert_eq!(
+            SubscribeResult::try_from(response).expect("BUG: Invalid subscribe result"),
+            test_utils::v1::build_subscribe_ok_result()
+        );
+
+        client
+            .send(test_utils::v1::build_authorize_request_message())
+            .await;
+        assert!(client.receive_response().await.stratum_result.is_some());
+        assert_eq!(
+            client.receive_request().await.payload.method,
+            Method::SetDifficulty
+        );
+        let notify = Notify::try_from(client.receive_request().await).expect("BUG: Not notify");
+        assert_eq!(notify.job_id(), test_utils::v1::MINING_NOTIFY_JOB_ID);
+
+        assert!(client.submit(3).await.stratum_error.is_none());
+        let rejected = client.submit(4).await;
+        assert_eq!(
+            rejected.stratum_error.map(|e| e.0),
+            Some(SimPool::REJECT_CODE)
+        );
+        assert!(client.submit(5).await.stratum_error.is_none());
+        // Disconnected after the third submit
+        assert!(client.receive().await.is_none());
+
+        let stats = pool.stats();
+        assert_eq!(stats.connections(), 1);
+        assert_eq!(stats.authorized(), 1);
+        assert_eq!(stats.accepted_submits(), 2);
+        assert_eq!(stats.rejected_submits(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_sleep_and_disconnect_steps() {
+        let pool = SimPool::new(PoolScript::default().with_steps(vec![
+            ScriptStep::Sleep(Duration::from_millis(200)),
+            ScriptStep::Disconnect,
+        ]));
+        let mut client = TestClient::connect(&pool);
+        client
+            .send(test_utils::v1::build_authorize_request_message())
+            .await;
+        client.receive_response().await;
+
+        // Requests are served while the script is paused
+        let paused = Instant::now();
+        client
+            .send(test_utils::v1::build_subscribe_request_frame())
+            .await;
+        client.receive_response().await;
+        assert!(paused.elapsed() < Duration::from_millis(200));
+        assert!(client.receive().await.is_none());
+    }
+}
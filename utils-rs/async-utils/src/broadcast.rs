@@ -0,0 +1,248 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Bounded broadcast channel where the latest values win: the channel retains at most `capacity`
+//! most recent values and receivers that fall behind skip the older ones instead of processing
+//! stale values (e.g. mining jobs that have been superseded). Values are shared by all receivers
+//! via `Arc` so that fan-out to many receivers doesn't clone them.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+use crate::tokio;
+use tokio::sync::Notify;
+
+struct State<T> {
+    /// Retained values along with their sequence numbers, the newest one at the back
+    values: VecDeque<(u64, Arc<T>)>,
+    /// Sequence number of the next sent value
+    next_seq: u64,
+    /// Sender has been dropped
+    closed: bool,
+}
+
+struct Shared<T> {
+    capacity: usize,
+    state: Mutex<State<T>>,
+    notify: Notify,
+}
+
+impl<T> Shared<T> {
+    fn lock(&self) -> std::sync::MutexGuard<'_, State<T>> {
+        self.state.lock().expect("BUG: Poisoned broadcast channel")
+    }
+}
+
+/// Creates a channel that retains at most `capacity` values for receivers that haven't received
+/// them yet
+pub fn channel<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    assert!(
+        capacity > 0,
+        "BUG: Broadcast channel capacity must be positive"
+    );
+    let shared = Arc::new(Shared {
+        capacity,
+        state: Mutex::new(State {
+            values: VecDeque::with_capacity(capacity),
+            next_seq: 0,
+            closed: false,
+        }),
+        notify: Notify::new(),
+    });
+    let receiver = Receiver {
+        shared: shared.clone(),
+        next_seq: 0,
+        skipped: 0,
+    };
+    (Sender { shared }, receiver)
+}
+
+/// Sending half of the channel, receivers observe the channel closing when it is dropped
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Broadcast `value`, the oldest retained value is dropped when the channel is full
+    pub fn send(&self, value: T) {
+        self.push(value, false);
+    }
+
+    /// Broadcast `value` that invalidates all previously sent values, receivers that haven't
+    /// received them yet skip them
+    pub fn send_invalidating(&self, value: T) {
+        self.push(value, true);
+    }
+
+    fn push(&self, value: T, invalidate: bool) {
+        {
+            let mut state = self.shared.lock();
+            if invalidate {
+                state.values.clear();
+            } else if state.values.len() == self.shared.capacity {
+                state.values.pop_front();
+            }
+            let seq = state.next_seq;
+            state.values.push_back((seq, Arc::new(value)));
+            state.next_seq += 1;
+        }
+        self.shared.notify.notify_waiters();
+    }
+
+    /// Creates a receiver that gets only values sent from now on
+    pub fn subscribe(&self) -> Receiver<T> {
+        Receiver {
+            shared: self.shared.clone(),
+            next_seq: self.shared.lock().next_seq,
+            skipped: 0,
+        }
+    }
+
+    /// Number of receivers that are alive
+    pub fn receiver_count(&self) -> usize {
+        Arc::strong_count(&self.shared) - 1
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        self.shared.lock().closed = true;
+        self.shared.notify.notify_waiters();
+    }
+}
+
+/// Receiving half of the channel. A cloned receiver continues from the same position.
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    /// Sequence number of the next value that this receiver is interested in
+    next_seq: u64,
+    skipped: u64,
+}
+
+impl<T> Clone for Receiver<T> {
+    fn clone(&self) -> Self {
+        Self {
+            shared: self.shared.clone(),
+            next_seq: self.next_seq,
+            skipped: 0,
+        }
+    }
+}
+
+impl<T> Receiver<T> {
+    /// Returns the oldest retained value that hasn't been received yet or `None` if there is no
+    /// such value
+    pub fn try_recv(&mut self) -> Option<Arc<T>> {
+        let state = self.shared.lock();
+        let (seq, value) = state.values.iter().find(|(seq, _)| *seq >= self.next_seq)?;
+        self.skipped += seq - self.next_seq;
+        self.next_seq = seq + 1;
+        Some(value.clone())
+    }
+
+    /// Waits for a value that hasn't been received yet, resolves to `None` once the sender is
+    /// dropped and all retained values have been received
+    pub async fn recv(&mut self) -> Option<Arc<T>> {
+        let shared = self.shared.clone();
+        loop {
+            // Register for notification before checking the state so that no value is missed
+            let notified = shared.notify.notified();
+            if let Some(value) = self.try_recv() {
+                return Some(value);
+            }
+            if self.shared.lock().closed {
+                return None;
+            }
+            notified.await;
+        }
+    }
+
+    /// Number of values that this receiver has skipped because they have been dropped from the
+    /// channel or invalidated before it received them
+    pub fn skipped(&self) -> u64 {
+        self.skipped
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FutureExt;
+    use tokio::time::Duration;
+
+    #[tokio::test]
+    async fn latest_values_win() {
+        let (tx, mut rx) = channel(2);
+        let mut slow_rx = rx.clone();
+
+        tx.send(1);
+        assert_eq!(rx.recv().await.as_deref(), Some(&1));
+        tx.send(2);
+        tx.send(3);
+        assert_eq!(rx.recv().await.as_deref(), Some(&2));
+        assert_eq!(rx.recv().await.as_deref(), Some(&3));
+        assert_eq!(rx.skipped(), 0);
+
+        // Value 1 has been dropped when the channel was full
+        assert_eq!(slow_rx.recv().await.as_deref(), Some(&2));
+        assert_eq!(slow_rx.skipped(), 1);
+
+        tx.send_invalidating(4);
+        assert_eq!(slow_rx.recv().await.as_deref(), Some(&4));
+        assert_eq!(slow_rx.skipped(), 2);
+        assert_eq!(rx.recv().await.as_deref(), Some(&4));
+        assert_eq!(rx.try_recv(), None);
+    }
+
+    #[tokio::test]
+    async fn values_are_shared() {
+        let (tx, mut rx) = channel(1);
+        let mut other_rx = tx.subscribe();
+        assert_eq!(tx.receiver_count(), 2);
+
+        tx.send(String::from("job"));
+        let value = rx.recv().await.expect("BUG: No value received");
+        let other_value = other_rx.recv().await.expect("BUG: No value received");
+        assert!(Arc::ptr_eq(&value, &other_value));
+    }
+
+    #[tokio::test]
+    async fn subscribe_and_close() {
+        let (tx, mut rx) = channel(4);
+        tx.send(1);
+        let mut late_rx = tx.subscribe();
+
+        let waiter = tokio::spawn(async move { late_rx.recv().await });
+        tx.send(2);
+        let value = waiter
+            .timeout(Duration::from_secs(1))
+            .await
+            .expect("BUG: Value not received")
+            .expect("BUG: Receiver task failed");
+        assert_eq!(value.as_deref(), Some(&2));
+
+        drop(tx);
+        assert_eq!(rx.recv().await.as_deref(), Some(&1));
+        assert_eq!(rx.recv().await.as_deref(), Some(&2));
+        assert_eq!(rx.recv().await, None);
+    }
+}
@@ -0,0 +1,260 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Cooperative cancellation: a `CancellationToken` is cancelled once and all tasks that hold
+//! a clone of it (or of any of its child tokens) can observe it.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::task::{Context, Poll};
+
+use pin_project_lite::pin_project;
+
+use crate::tokio;
+use tokio::sync::Notify;
+use tokio::time;
+
+#[derive(Debug, Default)]
+struct Inner {
+    cancelled: AtomicBool,
+    notify: Notify,
+    /// Tokens derived by `child_token()` that are still alive
+    children: Mutex<Vec<Weak<Inner>>>,
+}
+
+impl Inner {
+    fn cancel(&self) {
+        if self.cancelled.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        self.notify.notify_waiters();
+        let children = mem::take(
+            &mut *self
+                .children
+                .lock()
+                .expect("BUG: Poisoned cancellation token"),
+        );
+        for child in children.iter().filter_map(Weak::upgrade) {
+            child.cancel();
+        }
+    }
+}
+
+/// Token that signals cancellation to all its clones. Tokens form a hierarchy: cancelling
+/// a token (e.g. of the whole server) cancels all its child tokens (e.g. of individual sessions)
+/// but not vice versa.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    inner: Arc<Inner>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a token that is cancelled along with this token or on its own
+    pub fn child_token(&self) -> Self {
+        let child = Self::new();
+        let mut children = self
+            .inner
+            .children
+            .lock()
+            .expect("BUG: Poisoned cancellation token");
+        // Checked while holding the lock so that a concurrent `cancel()` cannot miss the child
+        if self.is_cancelled() {
+            child.cancel();
+        } else {
+            // Tokens of finished sessions are collected here so that a long-lived parent
+            // doesn't accumulate them
+            children.retain(|child| child.strong_count() > 0);
+            children.push(Arc::downgrade(&child.inner));
+        }
+        child
+    }
+
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    /// Resolves once the token is cancelled
+    pub async fn cancelled(&self) {
+        loop {
+            // Register for notification before checking the flag so that `cancel()` cannot
+            // slip in between
+            let notified = self.inner.notify.notified();
+            if self.is_cancelled() {
+                return;
+            }
+            notified.await;
+        }
+    }
+
+    /// Same as `cancelled()` but the future owns the token
+    pub async fn cancelled_owned(self) {
+        self.cancelled().await
+    }
+}
+
+/// Error of `FutureExt::timeout_or_cancel()`
+#[derive(Debug)]
+pub enum TimeoutOrCancelError {
+    /// The future didn't complete in time
+    Elapsed(time::error::Elapsed),
+    /// The token has been cancelled before the future completed
+    Cancelled,
+}
+
+impl fmt::Display for TimeoutOrCancelError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Elapsed(e) => write!(f, "{}", e),
+            Self::Cancelled => write!(f, "operation cancelled"),
+        }
+    }
+}
+
+impl StdError for TimeoutOrCancelError {}
+
+pin_project! {
+    /// Future returned by `FutureExt::timeout_or_cancel()`
+    pub struct TimeoutOrCancel<F> {
+        #[pin]
+        timeout: time::Timeout<F>,
+        cancelled: Pin<Box<dyn Future<Output = ()> + Send>>,
+    }
+}
+
+impl<F: Future> TimeoutOrCancel<F> {
+    pub(crate) fn new(future: F, timeout: time::Duration, token: &CancellationToken) -> Self {
+        Self {
+            timeout: time::timeout(timeout, future),
+            cancelled: Box::pin(token.clone().cancelled_owned()),
+        }
+    }
+}
+
+impl<F: Future> Future for TimeoutOrCancel<F> {
+    type Output = Result<F::Output, TimeoutOrCancelError>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+
+        if this.cancelled.as_mut().poll(cx).is_ready() {
+            return Poll::Ready(Err(TimeoutOrCancelError::Cancelled));
+        }
+        this.timeout
+            .poll(cx)
+            .map(|result| result.map_err(TimeoutOrCancelError::Elapsed))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FutureExt;
+    use futures::prelude::*;
+    use tokio::time::Duration;
+
+    #[tokio::test]
+    async fn cancel() {
+        let token = CancellationToken::new();
+        let clone = token.clone();
+        let waiter = tokio::spawn(async move { clone.cancelled().await });
+
+        assert!(!token.is_cancelled());
+        token.cancel();
+        assert!(token.is_cancelled());
+        waiter
+            .timeout(Duration::from_secs(1))
+            .await
+            .expect("BUG: Cancellation not observed")
+            .expect("BUG: Waiter failed");
+        // Resolves immediately once cancelled
+        token.cancelled().await;
+    }
+
+    #[tokio::test]
+    async fn child_token() {
+        let parent = CancellationToken::new();
+        let child = parent.child_token();
+        let grandchild = child.child_token();
+        let other_child = parent.child_token();
+
+        child.cancel();
+        assert!(grandchild.is_cancelled());
+        assert!(!parent.is_cancelled());
+        assert!(!other_child.is_cancelled());
+
+        parent.cancel();
+        assert!(other_child.is_cancelled());
+        assert!(parent.child_token().is_cancelled());
+    }
+
+    #[test]
+    fn dropped_children_are_released() {
+        let parent = CancellationToken::new();
+        for _ in 0..10 {
+            drop(parent.child_token());
+        }
+        let _child = parent.child_token();
+        assert_eq!(
+            parent
+                .inner
+                .children
+                .lock()
+                .expect("BUG: Poisoned cancellation token")
+                .len(),
+            1
+        );
+    }
+
+    #[tokio::test]
+    async fn timeout_or_cancel() {
+        let token = CancellationToken::new();
+        let result = future::ready(1)
+            .timeout_or_cancel(Duration::from_secs(1), &token)
+            .await;
+        assert_eq!(result.expect("BUG: Future not completed"), 1);
+
+        let result = future::pending::<()>()
+            .timeout_or_cancel(Duration::from_millis(10), &token)
+            .await;
+        assert!(matches!(result, Err(TimeoutOrCancelError::Elapsed(_))));
+
+        let pending = future::pending::<()>().timeout_or_cancel(Duration::from_secs(10), &token);
+        token.cancel();
+        assert!(matches!(
+            pending.await,
+            Err(TimeoutOrCancelError::Cancelled)
+        ));
+    }
+}
@@ -0,0 +1,213 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Coalescing of writes into a sink. Flushing a framed sink after each item results in a write
+//! system call per frame. Feeding a batch of items first and flushing once lets the sink write
+//! the whole batch at once (framed sinks use vectored writes when the underlying I/O supports
+//! them).
+
+use futures::prelude::*;
+use futures::stream::FusedStream;
+
+use crate::tokio;
+use tokio::time::{self, Duration, Instant};
+
+/// How items sent into a sink are batched before the sink is flushed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct WriteCoalescing {
+    /// The sink is flushed at the latest once this many items have been fed into it
+    pub max_items: usize,
+    /// How long to wait for more items before the sink is flushed. Items that are available
+    /// immediately are always batched, no waiting takes place when `None`.
+    pub max_delay: Option<Duration>,
+}
+
+impl Default for WriteCoalescing {
+    fn default() -> Self {
+        Self {
+            max_items: 32,
+            max_delay: None,
+        }
+    }
+}
+
+impl WriteCoalescing {
+    /// Each item is flushed on its own
+    pub fn disabled() -> Self {
+        Self {
+            max_items: 1,
+            max_delay: None,
+        }
+    }
+
+    pub fn with_max_items(mut self, max_items: usize) -> Self {
+        self.max_items = max_items.max(1);
+        self
+    }
+
+    pub fn with_max_delay(mut self, max_delay: Option<Duration>) -> Self {
+        self.max_delay = max_delay;
+        self
+    }
+
+    /// Sends all items of `items` into `sink`, the sink is flushed after each batch of items
+    pub async fn forward<St, Si, T>(&self, sink: &mut Si, items: St) -> Result<(), Si::Error>
+    where
+        St: Stream<Item = T>,
+        Si: Sink<T> + Unpin,
+    {
+        let items = items.fuse();
+        futures::pin_mut!(items);
+        while let Some(first) = items.next().await {
+            self.send_batch(sink, first, &mut items).await?;
+        }
+        Ok(())
+    }
+
+    /// Feeds `first` followed by items that `items` provides within the limits of this policy
+    /// into `sink` and flushes the sink. Callers that need to supervise each write (e.g. with a
+    /// timeout) can drive the batches themselves instead of using `forward()`.
+    pub async fn send_batch<St, Si, T>(
+        &self,
+        sink: &mut Si,
+        first: T,
+        items: &mut St,
+    ) -> Result<(), Si::Error>
+    where
+        St: Stream<Item = T> + FusedStream + Unpin,
+        Si: Sink<T> + Unpin,
+    {
+        let deadline = self.max_delay.map(|max_delay| Instant::now() + max_delay);
+        sink.feed(first).await?;
+        let mut count = 1;
+        while count < self.max_items && !items.is_terminated() {
+            let item = match items.next().now_or_never() {
+                Some(item) => item,
+                None => match deadline {
+                    Some(deadline) => time::timeout_at(deadline, items.next())
+                        .await
+                        .unwrap_or(None),
+                    None => None,
+                },
+            };
+            match item {
+                Some(item) => sink.feed(item).await?,
+                None => break,
+            }
+            count += 1;
+        }
+        sink.flush().await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::channel::mpsc;
+    use std::pin::Pin;
+    use std::task::{Context, Poll};
+
+    /// Sink that records the number of items in each flushed batch
+    #[derive(Default)]
+    struct BatchSink {
+        pending: usize,
+        batches: Vec<usize>,
+    }
+
+    impl Sink<u32> for BatchSink {
+        type Error = ();
+
+        fn poll_ready(self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn start_send(mut self: Pin<&mut Self>, _item: u32) -> Result<(), ()> {
+            self.pending += 1;
+            Ok(())
+        }
+
+        fn poll_flush(mut self: Pin<&mut Self>, _cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            if self.pending > 0 {
+                let pending = self.pending;
+                self.batches.push(pending);
+                self.pending = 0;
+            }
+            Poll::Ready(Ok(()))
+        }
+
+        fn poll_close(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<(), ()>> {
+            self.poll_flush(cx)
+        }
+    }
+
+    async fn send_all(
+        coalescing: WriteCoalescing,
+        items: mpsc::UnboundedReceiver<u32>,
+    ) -> Vec<usize> {
+        let mut sink = BatchSink::default();
+        coalescing
+            .forward(&mut sink, items)
+            .await
+            .expect("BUG: Send failed");
+        sink.batches
+    }
+
+    #[tokio::test]
+    async fn test_available_items_batched() {
+        let (items_tx, items_rx) = mpsc::unbounded();
+        for i in 0..10 {
+            items_tx.unbounded_send(i).expect("BUG: Cannot send item");
+        }
+        drop(items_tx);
+        let batches = send_all(WriteCoalescing::default().with_max_items(4), items_rx).await;
+        assert_eq!(batches, vec![4, 4, 2]);
+    }
+
+    #[tokio::test]
+    async fn test_disabled() {
+        let (items_tx, items_rx) = mpsc::unbounded();
+        for i in 0..3 {
+            items_tx.unbounded_send(i).expect("BUG: Cannot send item");
+        }
+        drop(items_tx);
+        let batches = send_all(WriteCoalescing::disabled(), items_rx).await;
+        assert_eq!(batches, vec![1, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn test_delayed_items_batched() {
+        let (items_tx, items_rx) = mpsc::unbounded();
+        let sender = tokio::spawn(async move {
+            items_tx.unbounded_send(0).expect("BUG: Cannot send item");
+            time::sleep(Duration::from_millis(5)).await;
+            items_tx.unbounded_send(1).expect("BUG: Cannot send item");
+            // Arrives after the batch has been flushed
+            time::sleep(Duration::from_millis(200)).await;
+            items_tx.unbounded_send(2).expect("BUG: Cannot send item");
+        });
+        let coalescing =
+            WriteCoalescing::default().with_max_delay(Some(Duration::from_millis(100)));
+        let batches = send_all(coalescing, items_rx).await;
+        sender.await.expect("BUG: Sender failed");
+        assert_eq!(batches, vec![2, 1]);
+    }
+}
@@ -49,6 +49,29 @@ pub use halthandle02::*;
 mod maybe_future;
 pub use maybe_future::MaybeFuture;
 
+#[cfg(feature = "tokio12")]
+pub mod broadcast;
+
+#[cfg(feature = "tokio12")]
+mod cancellation;
+#[cfg(feature = "tokio12")]
+pub use cancellation::{CancellationToken, TimeoutOrCancel, TimeoutOrCancelError};
+
+#[cfg(feature = "tokio12")]
+mod coalesce;
+#[cfg(feature = "tokio12")]
+pub use coalesce::WriteCoalescing;
+
+#[cfg(feature = "tokio12")]
+mod retry;
+#[cfg(feature = "tokio12")]
+pub use retry::{retry_with_backoff, Attempt, GiveUpReason, Retried, RetryError, RetryPolicy};
+
+#[cfg(feature = "tokio12")]
+mod supervisor;
+#[cfg(feature = "tokio12")]
+pub use supervisor::{FailureCause, RestartPolicy, Supervisor, TaskFailure};
+
 use std::panic::{self, PanicInfo};
 use std::pin::Pin;
 use std::process;
@@ -131,8 +154,7 @@ where
     }
 }
 
-/// An extension trait for `Future` goodies,
-/// currently this only entails the `timeout()` function.
+/// An extension trait for `Future` goodies: timeouts and cancellation.
 pub trait FutureExt: Future + Sized {
     /// Require a `Future` to complete before the specified duration has elapsed.
     ///
@@ -160,6 +182,17 @@ pub trait FutureExt: Future + Sized {
     {
         Cancelable::new(self, cancel_ft)
     }
+
+    /// Require a `Future` to complete before the specified duration has elapsed and before
+    /// `token` is cancelled, whichever comes first.
+    #[cfg(feature = "tokio12")]
+    fn timeout_or_cancel(
+        self,
+        timeout: Duration,
+        token: &CancellationToken,
+    ) -> TimeoutOrCancel<Self> {
+        TimeoutOrCancel::new(self, timeout, token)
+    }
 }
 
 impl<F: Future> FutureExt for F {}
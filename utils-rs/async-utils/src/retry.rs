@@ -0,0 +1,323 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Retrying of fallible asynchronous operations (connection establishment, DNS resolution...)
+//! with exponential backoff, jitter, limited number of attempts and an overall deadline.
+
+use std::error::Error as StdError;
+use std::fmt;
+use std::future::Future;
+
+use rand::Rng;
+
+use crate::tokio;
+use tokio::time::{self, Duration, Instant};
+
+/// When and how many times an operation is attempted
+#[derive(Clone, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Delay before the second attempt
+    pub initial_backoff: Duration,
+    /// The delay is multiplied by `factor` after each failed attempt
+    pub factor: u32,
+    /// Upper bound of the delay between attempts
+    pub max_backoff: Duration,
+    /// Fraction in the range `0.0..=1.0` by which each delay is randomly shortened so that
+    /// clients that failed at the same time don't retry in lockstep
+    pub jitter: f64,
+    /// Maximum number of attempts, unlimited when `None`
+    pub max_attempts: Option<u32>,
+    /// Give up once this much time has passed since the first attempt, an attempt in progress is
+    /// interrupted
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            initial_backoff: Duration::from_millis(100),
+            factor: 2,
+            max_backoff: Duration::from_secs(5),
+            jitter: 0.2,
+            max_attempts: Some(5),
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_max_attempts(mut self, max_attempts: Option<u32>) -> Self {
+        self.max_attempts = max_attempts;
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Option<Duration>) -> Self {
+        self.deadline = deadline;
+        self
+    }
+
+    pub fn with_jitter(mut self, jitter: f64) -> Self {
+        self.jitter = jitter.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Delay after failed attempt number `attempt` (starting from 1)
+    fn backoff(&self, attempt: u32) -> Duration {
+        let factor = self
+            .factor
+            .checked_pow(attempt.saturating_sub(1))
+            .unwrap_or(u32::MAX);
+        let delay = self
+            .initial_backoff
+            .checked_mul(factor)
+            .map_or(self.max_backoff, |delay| delay.min(self.max_backoff));
+        if self.jitter > 0.0 {
+            let shortening = rand::thread_rng().gen_range(0.0..=self.jitter.min(1.0));
+            delay.mul_f64(1.0 - shortening)
+        } else {
+            delay
+        }
+    }
+}
+
+/// Record of a failed attempt
+#[derive(Debug)]
+pub struct Attempt<E> {
+    /// Attempts are numbered from 1
+    pub number: u32,
+    /// Time since the first attempt when this attempt started
+    pub started_after: Duration,
+    pub duration: Duration,
+    /// Error of the operation or `None` if the attempt has been interrupted by the deadline
+    pub error: Option<E>,
+}
+
+/// Successful result of a retried operation along with the attempts that have failed before
+#[derive(Debug)]
+pub struct Retried<T, E> {
+    pub value: T,
+    pub failed_attempts: Vec<Attempt<E>>,
+}
+
+/// Why the operation hasn't been attempted again
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GiveUpReason {
+    MaxAttempts,
+    Deadline,
+}
+
+/// All attempts of a retried operation have failed
+#[derive(Debug)]
+pub struct RetryError<E> {
+    pub reason: GiveUpReason,
+    pub attempts: Vec<Attempt<E>>,
+}
+
+impl<E> RetryError<E> {
+    /// The most recent error returned by the operation
+    pub fn last_error(&self) -> Option<&E> {
+        self.attempts
+            .iter()
+            .rev()
+            .find_map(|attempt| attempt.error.as_ref())
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for RetryError<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let reason = match self.reason {
+            GiveUpReason::MaxAttempts => "maximum number of attempts reached",
+            GiveUpReason::Deadline => "deadline exceeded",
+        };
+        write!(
+            f,
+            "Gave up after {} attempts ({})",
+            self.attempts.len(),
+            reason
+        )?;
+        if let Some(error) = self.last_error() {
+            write!(f, ", last error: {}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: StdError + 'static> StdError for RetryError<E> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.last_error().map(|e| e as &(dyn StdError + 'static))
+    }
+}
+
+/// Attempts `op` until it succeeds or `policy` doesn't allow any further attempts. `op` is
+/// passed the number of the attempt (starting from 1).
+pub async fn retry_with_backoff<T, E, FN, FT>(
+    policy: &RetryPolicy,
+    mut op: FN,
+) -> Result<Retried<T, E>, RetryError<E>>
+where
+    FN: FnMut(u32) -> FT,
+    FT: Future<Output = Result<T, E>>,
+{
+    let start = Instant::now();
+    let deadline = policy.deadline.map(|deadline| start + deadline);
+    let mut attempts = Vec::new();
+
+    for number in 1.. {
+        let started = Instant::now();
+        let result = match deadline {
+            Some(deadline) => time::timeout_at(deadline, op(number)).await.ok(),
+            None => Some(op(number).await),
+        };
+        let error = match result {
+            Some(Ok(value)) => {
+                return Ok(Retried {
+                    value,
+                    failed_attempts: attempts,
+                })
+            }
+            Some(Err(e)) => Some(e),
+            None => None,
+        };
+        let interrupted = error.is_none();
+        attempts.push(Attempt {
+            number,
+            started_after: started - start,
+            duration: started.elapsed(),
+            error,
+        });
+
+        if interrupted {
+            break;
+        }
+        if policy.max_attempts.is_some_and(|max| number >= max) {
+            return Err(RetryError {
+                reason: GiveUpReason::MaxAttempts,
+                attempts,
+            });
+        }
+        let next_attempt = Instant::now() + policy.backoff(number);
+        if deadline.is_some_and(|deadline| next_attempt >= deadline) {
+            break;
+        }
+        time::sleep_until(next_attempt).await;
+    }
+    Err(RetryError {
+        reason: GiveUpReason::Deadline,
+        attempts,
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use futures::prelude::*;
+
+    fn policy() -> RetryPolicy {
+        RetryPolicy {
+            initial_backoff: Duration::from_millis(1),
+            factor: 2,
+            max_backoff: Duration::from_millis(4),
+            jitter: 0.0,
+            max_attempts: Some(3),
+            deadline: None,
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = policy();
+        let delays: Vec<_> = (1..=5).map(|attempt| policy.backoff(attempt)).collect();
+        assert_eq!(
+            delays,
+            [1, 2, 4, 4, 4]
+                .iter()
+                .map(|ms| Duration::from_millis(*ms))
+                .collect::<Vec<_>>()
+        );
+        assert_eq!(policy.backoff(100), Duration::from_millis(4));
+
+        let policy = policy.with_jitter(0.5);
+        for _ in 0..100 {
+            let delay = policy.backoff(3);
+            assert!(delay >= Duration::from_millis(2) && delay <= Duration::from_millis(4));
+        }
+    }
+
+    #[tokio::test]
+    async fn success_after_failures() {
+        let retried = retry_with_backoff(&policy(), |attempt| async move {
+            if attempt < 3 {
+                Err(attempt)
+            } else {
+                Ok("connected")
+            }
+        })
+        .await
+        .expect("BUG: Operation should succeed");
+
+        assert_eq!(retried.value, "connected");
+        let errors: Vec<_> = retried
+            .failed_attempts
+            .iter()
+            .map(|attempt| (attempt.number, attempt.error))
+            .collect();
+        assert_eq!(errors, vec![(1, Some(1)), (2, Some(2))]);
+        assert!(retried.failed_attempts[1].started_after >= Duration::from_millis(1));
+    }
+
+    #[tokio::test]
+    async fn max_attempts() {
+        let error = retry_with_backoff(&policy(), future::err::<(), _>)
+            .await
+            .expect_err("BUG: Operation should fail");
+
+        assert_eq!(error.reason, GiveUpReason::MaxAttempts);
+        assert_eq!(error.attempts.len(), 3);
+        assert_eq!(error.last_error(), Some(&3));
+        assert_eq!(
+            error.to_string(),
+            "Gave up after 3 attempts (maximum number of attempts reached), last error: 3"
+        );
+    }
+
+    #[tokio::test]
+    async fn deadline() {
+        // Attempt in progress is interrupted by the deadline
+        let policy = policy()
+            .with_max_attempts(None)
+            .with_deadline(Some(Duration::from_millis(20)));
+        let error = retry_with_backoff(&policy, |_| future::pending::<Result<(), ()>>())
+            .await
+            .expect_err("BUG: Operation should fail");
+        assert_eq!(error.reason, GiveUpReason::Deadline);
+        assert_eq!(error.attempts.len(), 1);
+        assert!(error.attempts[0].error.is_none());
+
+        // No attempt is made when the backoff would exceed the deadline
+        let error = retry_with_backoff(&policy, future::err::<(), _>)
+            .await
+            .expect_err("BUG: Operation should fail");
+        assert_eq!(error.reason, GiveUpReason::Deadline);
+        assert!(error.attempts.len() > 1);
+        assert!(error.attempts.iter().all(|attempt| attempt.error.is_some()));
+    }
+}
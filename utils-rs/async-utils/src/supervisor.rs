@@ -0,0 +1,335 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Supervision of spawned tasks: failures (errors or panics) of the tasks are observed, the tasks
+//! are restarted according to their `RestartPolicy` and failures that cannot be recovered from are
+//! reported to the owner of the `Supervisor`.
+
+use std::any::Any;
+use std::error::Error as StdError;
+use std::fmt;
+use std::panic::AssertUnwindSafe;
+
+use futures::prelude::*;
+
+use crate::tokio;
+use tokio::sync::mpsc;
+use tokio::task::JoinHandle;
+use tokio::time::{self, Duration};
+
+/// What to do when a supervised task fails
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RestartPolicy {
+    /// Any failure of the task is fatal
+    Never,
+    /// Restart the failed task at most `max_restarts` times. The task is restarted after
+    /// `backoff` that doubles with each restart up to `max_backoff`.
+    OnFailure {
+        max_restarts: usize,
+        backoff: Duration,
+        max_backoff: Duration,
+    },
+}
+
+impl RestartPolicy {
+    /// Delay before restart number `restart` (starting from 0) or `None` when the task is not to
+    /// be restarted anymore
+    fn backoff(&self, restart: usize) -> Option<Duration> {
+        match self {
+            Self::Never => None,
+            Self::OnFailure {
+                max_restarts,
+                backoff,
+                max_backoff,
+            } => {
+                if restart >= *max_restarts {
+                    return None;
+                }
+                let factor = 1u32.checked_shl(restart as u32).unwrap_or(u32::MAX);
+                Some(
+                    backoff
+                        .checked_mul(factor)
+                        .map_or(*max_backoff, |backoff| backoff.min(*max_backoff)),
+                )
+            }
+        }
+    }
+}
+
+/// Cause of a task failure
+#[derive(Debug)]
+pub enum FailureCause<E> {
+    /// The task resolved to an error
+    Error(E),
+    /// The task panicked, the panic message is provided if available
+    Panic(String),
+}
+
+impl<E> FailureCause<E> {
+    fn from_panic(payload: Box<dyn Any + Send>) -> Self {
+        let message = match payload.downcast::<String>() {
+            Ok(message) => *message,
+            Err(payload) => payload.downcast_ref::<&str>().map_or_else(
+                || "unknown panic".to_string(),
+                |message| message.to_string(),
+            ),
+        };
+        Self::Panic(message)
+    }
+}
+
+/// Fatal failure of a supervised task, i.e. the task failed and its restart policy doesn't allow
+/// restarting it anymore
+#[derive(Debug)]
+pub struct TaskFailure<E> {
+    /// Name of the task as passed to `Supervisor::spawn()`
+    pub name: String,
+    /// How many times the task has been restarted before the final failure
+    pub restarts: usize,
+    pub cause: FailureCause<E>,
+}
+
+impl<E: fmt::Display> fmt::Display for TaskFailure<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Task '{}' ", self.name)?;
+        match &self.cause {
+            FailureCause::Error(e) => write!(f, "failed: {}", e)?,
+            FailureCause::Panic(message) => write!(f, "panicked: {}", message)?,
+        }
+        if self.restarts > 0 {
+            write!(f, " (after {} restarts)", self.restarts)?;
+        }
+        Ok(())
+    }
+}
+
+impl<E: StdError + 'static> StdError for TaskFailure<E> {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        match &self.cause {
+            FailureCause::Error(e) => Some(e),
+            FailureCause::Panic(_) => None,
+        }
+    }
+}
+
+/// Spawns tasks and observes their termination. Tasks that fail are restarted according to their
+/// `RestartPolicy`, fatal failures are provided by `next_failure()`.
+///
+/// Dropping the supervisor doesn't stop the tasks, they run to completion unobserved (e.g. send
+/// tasks flush pending frames). Use `abort()` to stop them.
+#[derive(Debug)]
+pub struct Supervisor<E> {
+    tasks: Vec<JoinHandle<()>>,
+    failures_tx: mpsc::UnboundedSender<TaskFailure<E>>,
+    failures_rx: mpsc::UnboundedReceiver<TaskFailure<E>>,
+}
+
+impl<E> Default for Supervisor<E> {
+    fn default() -> Self {
+        let (failures_tx, failures_rx) = mpsc::unbounded_channel();
+        Self {
+            tasks: Vec::new(),
+            failures_tx,
+            failures_rx,
+        }
+    }
+}
+
+impl<E: Send + 'static> Supervisor<E> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Spawn a task named `name` that is restarted according to `policy`. `factory` builds
+    /// the future of the task for the initial run and for each restart.
+    pub fn spawn<FN, FT>(&mut self, name: &str, policy: RestartPolicy, mut factory: FN)
+    where
+        FN: FnMut() -> FT + Send + 'static,
+        FT: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        let name = name.to_string();
+        let failures_tx = self.failures_tx.clone();
+        let task = tokio::spawn(async move {
+            let mut restarts = 0;
+            loop {
+                let cause = match AssertUnwindSafe(factory()).catch_unwind().await {
+                    Ok(Ok(())) => return,
+                    Ok(Err(e)) => FailureCause::Error(e),
+                    Err(payload) => FailureCause::from_panic(payload),
+                };
+                match policy.backoff(restarts) {
+                    Some(backoff) => {
+                        time::sleep(backoff).await;
+                        restarts += 1;
+                    }
+                    None => {
+                        // Nobody may be interested in failures anymore
+                        let _ = failures_tx.send(TaskFailure {
+                            name,
+                            restarts,
+                            cause,
+                        });
+                        return;
+                    }
+                }
+            }
+        });
+        self.tasks.push(task);
+    }
+
+    /// Spawn a task that cannot be restarted (e.g. it takes ownership of a channel), any failure
+    /// of the task is fatal
+    pub fn spawn_once<FT>(&mut self, name: &str, future: FT)
+    where
+        FT: Future<Output = Result<(), E>> + Send + 'static,
+    {
+        let mut future = Some(future);
+        self.spawn(name, RestartPolicy::Never, move || {
+            future
+                .take()
+                .expect("BUG: Task without restart policy restarted")
+        });
+    }
+
+    /// Resolves to the next fatal failure of a supervised task. The future never resolves when
+    /// all tasks complete successfully.
+    pub async fn next_failure(&mut self) -> TaskFailure<E> {
+        self.failures_rx
+            .recv()
+            .await
+            .expect("BUG: Supervisor failure channel closed")
+    }
+
+    /// Stop all supervised tasks
+    pub fn abort(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::FutureExt as _;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    fn restart_policy(max_restarts: usize) -> RestartPolicy {
+        RestartPolicy::OnFailure {
+            max_restarts,
+            backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(4),
+        }
+    }
+
+    #[test]
+    fn backoff() {
+        let policy = restart_policy(5);
+        let backoffs: Vec<_> = (0..6).map(|restart| policy.backoff(restart)).collect();
+        assert_eq!(
+            backoffs,
+            vec![
+                Some(Duration::from_millis(1)),
+                Some(Duration::from_millis(2)),
+                Some(Duration::from_millis(4)),
+                Some(Duration::from_millis(4)),
+                Some(Duration::from_millis(4)),
+                None
+            ]
+        );
+        assert_eq!(policy.backoff(100), None);
+        assert_eq!(
+            restart_policy(200).backoff(100),
+            Some(Duration::from_millis(4))
+        );
+        assert_eq!(RestartPolicy::Never.backoff(0), None);
+    }
+
+    #[tokio::test]
+    async fn restart_until_fatal_failure() {
+        let runs = Arc::new(AtomicUsize::new(0));
+        let mut supervisor = Supervisor::new();
+        let task_runs = runs.clone();
+        supervisor.spawn("failing", restart_policy(2), move || {
+            let run = task_runs.fetch_add(1, Ordering::SeqCst);
+            async move { Err(run) }
+        });
+
+        let failure = supervisor.next_failure().await;
+        assert_eq!(failure.name, "failing");
+        assert_eq!(failure.restarts, 2);
+        assert!(matches!(failure.cause, FailureCause::Error(2)));
+        assert_eq!(runs.load(Ordering::SeqCst), 3);
+        assert_eq!(
+            failure.to_string(),
+            "Task 'failing' failed: 2 (after 2 restarts)"
+        );
+    }
+
+    #[tokio::test]
+    async fn recovered_and_successful_tasks() {
+        let mut supervisor = Supervisor::<()>::new();
+        let runs = Arc::new(AtomicUsize::new(0));
+        let task_runs = runs.clone();
+        supervisor.spawn("recovering", restart_policy(1), move || {
+            let run = task_runs.fetch_add(1, Ordering::SeqCst);
+            async move {
+                if run == 0 {
+                    panic!("first run fails");
+                }
+                Ok(())
+            }
+        });
+        supervisor.spawn_once("successful", future::ok(()));
+
+        supervisor
+            .next_failure()
+            .timeout(Duration::from_millis(100))
+            .await
+            .expect_err("BUG: No failure expected");
+        assert_eq!(runs.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn panic_is_fatal_failure() {
+        let mut supervisor = Supervisor::<String>::new();
+        supervisor.spawn_once("panicking", async { panic!("oops") });
+
+        let failure = supervisor.next_failure().await;
+        assert!(matches!(failure.cause, FailureCause::Panic(ref message) if message == "oops"));
+        assert_eq!(failure.to_string(), "Task 'panicking' panicked: oops");
+    }
+
+    #[tokio::test]
+    async fn abort() {
+        let mut supervisor = Supervisor::<()>::new();
+        let (tx, rx) = futures::channel::oneshot::channel::<()>();
+        supervisor.spawn_once("pending", async move {
+            let _tx = tx;
+            future::pending().await
+        });
+        supervisor.abort();
+        // The sender is dropped along with the aborted task
+        rx.await.expect_err("BUG: Task not aborted");
+    }
+}
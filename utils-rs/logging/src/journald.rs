@@ -0,0 +1,150 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Drain that sends records to journald using its native protocol
+
+use std::io;
+use std::os::unix::net::UnixDatagram;
+use std::path::Path;
+
+use slog::{Drain, OwnedKVList, Record};
+
+use crate::system::{kv_pairs, program_name, severity};
+
+/// Drain that sends each record as a single datagram to the journal socket. Key-value pairs are
+/// sent as journal fields with upper-cased names.
+///
+/// Records that don't fit into a single datagram are dropped by the journal, passing them via
+/// a memory file descriptor is not supported.
+pub struct JournaldDrain {
+    socket: UnixDatagram,
+    identifier: String,
+}
+
+impl JournaldDrain {
+    pub const SOCKET_PATH: &'static str = "/run/systemd/journal/socket";
+
+    pub fn connect() -> io::Result<Self> {
+        Self::connect_to(Path::new(Self::SOCKET_PATH))
+    }
+
+    fn connect_to(path: &Path) -> io::Result<Self> {
+        let socket = UnixDatagram::unbound()?;
+        socket.connect(path)?;
+        Ok(Self {
+            socket,
+            identifier: program_name(),
+        })
+    }
+
+    /// Converts a key to a valid journal field name: only upper case letters, digits and
+    /// underscores, not starting with an underscore (such fields are trusted fields set by
+    /// journald itself)
+    fn field_name(key: &str) -> String {
+        let name: String = key
+            .chars()
+            .map(|c| match c {
+                'a'..='z' | 'A'..='Z' | '0'..='9' => c.to_ascii_uppercase(),
+                _ => '_',
+            })
+            .collect();
+        name.trim_start_matches('_').to_string()
+    }
+
+    /// Appends a field to the datagram, values spanning multiple lines have to be serialized
+    /// with explicit length
+    fn append_field(datagram: &mut Vec<u8>, name: &str, value: &str) {
+        datagram.extend_from_slice(name.as_bytes());
+        if value.contains('\n') {
+            datagram.push(b'\n');
+            datagram.extend_from_slice(&(value.len() as u64).to_le_bytes());
+        } else {
+            datagram.push(b'=');
+        }
+        datagram.extend_from_slice(value.as_bytes());
+        datagram.push(b'\n');
+    }
+
+    fn format(&self, record: &Record, values: &OwnedKVList) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        Self::append_field(&mut datagram, "MESSAGE", &record.msg().to_string());
+        Self::append_field(
+            &mut datagram,
+            "PRIORITY",
+            &severity(record.level()).to_string(),
+        );
+        Self::append_field(&mut datagram, "SYSLOG_IDENTIFIER", &self.identifier);
+        Self::append_field(&mut datagram, "CODE_FILE", record.file());
+        Self::append_field(&mut datagram, "CODE_LINE", &record.line().to_string());
+        Self::append_field(&mut datagram, "CODE_MODULE", record.module());
+        for (key, value) in kv_pairs(record, values) {
+            let name = Self::field_name(&key);
+            if !name.is_empty() {
+                Self::append_field(&mut datagram, &name, &value);
+            }
+        }
+        datagram
+    }
+}
+
+impl Drain for JournaldDrain {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        self.socket.send(&self.format(record, values)).map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::{o, warn, Logger};
+
+    #[test]
+    fn test_field_name() {
+        assert_eq!(JournaldDrain::field_name("peer_addr"), "PEER_ADDR");
+        assert_eq!(JournaldDrain::field_name("_private-key"), "PRIVATE_KEY");
+        assert_eq!(JournaldDrain::field_name("_"), "");
+    }
+
+    #[test]
+    fn test_journald() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("journal.sock");
+        let server = UnixDatagram::bind(&path).expect("BUG: Cannot bind server");
+        let drain = JournaldDrain::connect_to(&path).expect("BUG: Cannot connect");
+        let logger = Logger::root(drain.ignore_res(), o!("conn" => 7));
+
+        warn!(logger, "Multi\nline"; "peer" => "127.0.0.1:3336");
+
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).expect("BUG: Cannot receive");
+        let mut expected = b"MESSAGE\n".to_vec();
+        expected.extend_from_slice(&10u64.to_le_bytes());
+        expected.extend_from_slice(b"Multi\nline\nPRIORITY=4\n");
+        assert!(buf[..len].starts_with(&expected));
+        let text = String::from_utf8_lossy(&buf[expected.len()..len]);
+        assert!(text.contains(&format!("CODE_MODULE={}\n", module_path!())));
+        assert!(text.ends_with("PEER=127.0.0.1:3336\nCONN=7\n"), "{}", text);
+    }
+}
@@ -50,9 +50,11 @@
 //! there's no way to have common setup/teardown for tests, and so
 //! it's best that the default is test-friendly.
 
+use std::collections::BTreeMap;
 use std::env;
 use std::fmt;
-use std::fs::OpenOptions;
+use std::fs::{File, OpenOptions};
+use std::io;
 use std::mem;
 use std::ops::Deref;
 use std::path::{Path, PathBuf};
@@ -62,10 +64,25 @@ use std::sync::{
 };
 
 use lazy_static::lazy_static;
-use slog::{o, Discard, Drain, FilterLevel, Logger};
+use slog::{o, Discard, Drain, FilterLevel, FnValue, Logger, Record};
 use slog_async::{Async, AsyncGuard};
 use slog_envlogger::EnvLogger;
 
+mod rate_limit;
+pub use rate_limit::RateLimiter;
+mod rotation;
+pub use rotation::{FileRotation, RotatingFile};
+#[cfg(feature = "journald")]
+mod journald;
+#[cfg(feature = "journald")]
+pub use journald::JournaldDrain;
+#[cfg(feature = "syslog")]
+mod syslog;
+#[cfg(feature = "syslog")]
+pub use syslog::{Facility, SyslogAddress, SyslogConfig, SyslogDrain};
+#[cfg(any(feature = "syslog", feature = "journald"))]
+mod system;
+
 // Re-export slog things for easy access to slog by dependers
 // and also because these are used by macros
 pub use slog;
@@ -80,8 +97,20 @@ pub enum LoggingTarget {
     Stdout,
     /// Log to standard output as JSON
     StdoutJson,
+    /// Log to standard error as JSON
+    StderrJson,
     /// Log to a file
     File(PathBuf),
+    /// Log to a file as JSON
+    FileJson(PathBuf),
+    /// Log to a file that is rotated according to the specified policy
+    RotatingFile(PathBuf, FileRotation),
+    /// Send records to a syslog daemon
+    #[cfg(feature = "syslog")]
+    Syslog(SyslogConfig),
+    /// Send records to the systemd journal
+    #[cfg(feature = "journald")]
+    Journald,
     /// Don't log anything anywhere
     None,
 }
@@ -146,6 +175,69 @@ impl Default for LoggingConfig {
     }
 }
 
+/// Logging levels that can be changed at runtime: the default level and levels of individual
+/// modules (module paths are matched as prefixes, e.g. `ii_stratum::v2`)
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LogLevels {
+    pub default: Level,
+    pub modules: BTreeMap<String, Level>,
+}
+
+impl LogLevels {
+    pub fn new(default: Level) -> Self {
+        Self {
+            default,
+            modules: BTreeMap::new(),
+        }
+    }
+
+    /// Levels described by `filters` in the `RUST_LOG` syntax, `default` applies unless the
+    /// filters specify the default level. Directives that cannot be represented by levels (the
+    /// `off` level and regular expression filters) are ignored.
+    pub fn from_filters(filters: &str, default: Level) -> Self {
+        let mut levels = Self::new(default);
+        let directives = filters.split('/').next().unwrap_or_default();
+        for directive in directives.split(',').map(str::trim) {
+            let mut parts = directive.splitn(2, '=');
+            match (parts.next(), parts.next().map(str::trim)) {
+                (Some(""), None) => {}
+                (Some(part), None) => match part.parse() {
+                    Ok(level) => levels.default = level,
+                    // Module without level enables all its messages
+                    Err(_) => {
+                        levels.modules.insert(part.to_string(), Level::Trace);
+                    }
+                },
+                (Some(module), Some("")) => {
+                    levels.modules.insert(module.to_string(), Level::Trace);
+                }
+                (Some(module), Some(level)) => {
+                    if let Ok(level) = level.parse() {
+                        levels.modules.insert(module.to_string(), level);
+                    }
+                }
+                (None, _) => {}
+            }
+        }
+        levels
+    }
+
+    /// Filters in the `RUST_LOG` syntax
+    pub fn to_filters(&self) -> String {
+        let mut filters = self.default.as_str().to_lowercase();
+        for (module, level) in self.modules.iter() {
+            filters.push_str(&format!(",{}={}", module, level.as_str().to_lowercase()));
+        }
+        filters
+    }
+}
+
+impl fmt::Display for LogLevels {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_filters())
+    }
+}
+
 /// Lock logger configuration with mutual exclusion
 #[inline(always)]
 fn lock_logger_config() -> MutexGuard<'static, Option<LoggingConfig>> {
@@ -246,6 +338,16 @@ fn build_envlogger<D: Drain>(drain: D, default_level: Level) -> EnvLogger<D> {
     build_envlogger_from_filters(drain, filters)
 }
 
+/// Levels of the logger set up by `build_envlogger()`
+fn initial_levels(default_level: Level) -> LogLevels {
+    match env::var("RUST_LOG") {
+        Ok(ref rust_log) if !rust_log.is_empty() => {
+            LogLevels::from_filters(rust_log, default_level)
+        }
+        _ => LogLevels::new(default_level),
+    }
+}
+
 /// Create terminal drain for logger, logging to either stderr or stdout
 fn get_terminal_drain(stderr: bool) -> impl Drain<Ok = (), Err = impl fmt::Debug> {
     let builder = slog_term::TermDecorator::new();
@@ -258,15 +360,22 @@ fn get_terminal_drain(stderr: bool) -> impl Drain<Ok = (), Err = impl fmt::Debug
     slog_term::FullFormat::new(terminal_decorator).build()
 }
 
-fn get_json_drain() -> impl Drain<Ok = (), Err = impl fmt::Debug> {
-    slog_json::Json::new(std::io::stdout())
+/// Create JSON drain for logger, each record is a single line JSON object with timestamp
+/// (`ts`), level (`level`), target module (`target`), message (`msg`) and all key-value pairs
+/// of the record and the logger
+fn get_json_drain<W>(writer: W) -> impl Drain<Ok = (), Err = impl fmt::Debug>
+where
+    W: io::Write + Send + 'static,
+{
+    slog_json::Json::new(writer)
         .add_default_keys()
+        .add_key_value(o!("target" => FnValue(|record: &Record| record.module())))
         .build()
 }
 
-/// Create file drain for logger
-fn get_file_drain(path: &Path) -> impl Drain<Ok = (), Err = impl fmt::Debug> {
-    let file = OpenOptions::new()
+/// Open file for logging in append mode
+fn open_log_file(path: &Path) -> File {
+    OpenOptions::new()
         .create(true)
         .write(true)
         .append(true)
@@ -278,9 +387,54 @@ fn get_file_drain(path: &Path) -> impl Drain<Ok = (), Err = impl fmt::Debug> {
                 path.display(),
                 e
             )
-        });
+        })
+}
+
+/// Create drain for logger that writes to a rotated file
+fn get_rotating_file_drain(
+    path: &Path,
+    rotation: &FileRotation,
+) -> impl Drain<Ok = (), Err = impl fmt::Debug> {
+    let file = RotatingFile::open(path, rotation.clone()).unwrap_or_else(|e| {
+        panic!(
+            "Logging setup error: Could not open file `{}` for logging: {}",
+            path.display(),
+            e
+        )
+    });
+    // Failure to write or rotate the log file must not bring down the logger, it is reported to
+    // standard error and the record is dropped
+    slog_term::FullFormat::new(slog_term::PlainDecorator::new(file))
+        .build()
+        .map_err(|e| eprintln!("Logging error: Cannot write to log file: {}", e))
+        .ignore_res()
+}
+
+/// Create drain for logger that sends records to syslog
+#[cfg(feature = "syslog")]
+fn get_syslog_drain(config: &SyslogConfig) -> impl Drain<Ok = (), Err = impl fmt::Debug> {
+    SyslogDrain::connect(config)
+        .unwrap_or_else(|e| {
+            panic!(
+                "Logging setup error: Could not connect to syslog at {:?}: {}",
+                config.address, e
+            )
+        })
+        // Records are dropped rather than failing the whole logger when the daemon goes away
+        .ignore_res()
+}
 
-    let file_decorator = slog_term::PlainDecorator::new(file);
+/// Create drain for logger that sends records to journald
+#[cfg(feature = "journald")]
+fn get_journald_drain() -> impl Drain<Ok = (), Err = impl fmt::Debug> {
+    JournaldDrain::connect()
+        .unwrap_or_else(|e| panic!("Logging setup error: Could not connect to journald: {}", e))
+        .ignore_res()
+}
+
+/// Create file drain for logger
+fn get_file_drain(path: &Path) -> impl Drain<Ok = (), Err = impl fmt::Debug> {
+    let file_decorator = slog_term::PlainDecorator::new(open_log_file(path));
     slog_term::FullFormat::new(file_decorator).build()
 }
 
@@ -305,6 +459,8 @@ pub struct GuardedLogger {
     guard: Mutex<FlushGuard>,
     /// Existing configuration that can be replaced
     current_config: LoggingConfig,
+    /// Levels most recently set by `set_level()` et al.
+    levels: Mutex<LogLevels>,
 }
 
 impl GuardedLogger {
@@ -342,6 +498,7 @@ impl GuardedLogger {
 
     pub fn set_config(&mut self, config: LoggingConfig) -> FlushGuard {
         self.current_config = config;
+        *self.lock_levels() = LogLevels::new(self.current_config.level);
         self.switch_target(None)
     }
 
@@ -353,7 +510,8 @@ impl GuardedLogger {
             filter_level => {
                 self.current_config.level = Level::from_usize(filter_level.as_usize()).expect(
                     "BUG: Internal error: Could not convert slog::FilterLevel to slog::Level",
-                )
+                );
+                *self.lock_levels() = LogLevels::new(self.current_config.level);
             }
         };
         self.switch_target(None)
@@ -361,6 +519,48 @@ impl GuardedLogger {
 
     /// Reconfigure logger with specified filters
     pub fn set_filters(&self, filters: String) -> FlushGuard {
+        let mut levels = self.lock_levels();
+        *levels = LogLevels::from_filters(&filters, self.current_config.level);
+        self.switch_target(Some(filters))
+    }
+
+    #[inline]
+    fn lock_levels(&self) -> MutexGuard<'_, LogLevels> {
+        self.levels.lock().expect("BUG: Poisoned log levels")
+    }
+
+    /// Levels currently in effect as set by the configuration, `RUST_LOG` or at runtime
+    pub fn levels(&self) -> LogLevels {
+        self.lock_levels().clone()
+    }
+
+    /// Reconfigure logger with specified `levels`, all filters are replaced
+    pub fn set_levels(&self, levels: LogLevels) -> FlushGuard {
+        self.update_levels(|current| *current = levels)
+    }
+
+    /// Change the default level while keeping levels of individual modules
+    pub fn set_level(&self, level: Level) -> FlushGuard {
+        self.update_levels(|levels| levels.default = level)
+    }
+
+    /// Change the level of `module`, `None` makes the module use the default level again
+    pub fn set_module_level(&self, module: &str, level: Option<Level>) -> FlushGuard {
+        self.update_levels(|levels| {
+            match level {
+                Some(level) => levels.modules.insert(module.to_string(), level),
+                None => levels.modules.remove(module),
+            };
+        })
+    }
+
+    /// Modifies the current levels by `update` and switches to filters built from the result.
+    /// The levels stay locked until the drain is switched so that concurrent updates neither
+    /// overwrite each other nor leave the drain with outdated filters.
+    fn update_levels<F: FnOnce(&mut LogLevels)>(&self, update: F) -> FlushGuard {
+        let mut levels = self.lock_levels();
+        update(&mut levels);
+        let filters = levels.to_filters();
         self.switch_target(Some(filters))
     }
 
@@ -373,8 +573,17 @@ impl GuardedLogger {
             None => self.switch_drain(Discard, filters),
             Stderr => self.switch_drain(get_terminal_drain(true), filters),
             Stdout => self.switch_drain(get_terminal_drain(false), filters),
-            StdoutJson => self.switch_drain(get_json_drain(), filters),
+            StdoutJson => self.switch_drain(get_json_drain(io::stdout()), filters),
+            StderrJson => self.switch_drain(get_json_drain(io::stderr()), filters),
             File(path) => self.switch_drain(get_file_drain(path), filters),
+            FileJson(path) => self.switch_drain(get_json_drain(open_log_file(path)), filters),
+            RotatingFile(path, rotation) => {
+                self.switch_drain(get_rotating_file_drain(path, rotation), filters)
+            }
+            #[cfg(feature = "syslog")]
+            Syslog(config) => self.switch_drain(get_syslog_drain(config), filters),
+            #[cfg(feature = "journald")]
+            Journald => self.switch_drain(get_journald_drain(), filters),
         }
     }
 
@@ -385,8 +594,17 @@ impl GuardedLogger {
             None => Self::with_discard(),
             Stderr => Self::with_drain(config, get_terminal_drain(true)),
             Stdout => Self::with_drain(config, get_terminal_drain(false)),
-            StdoutJson => Self::with_drain(config, get_json_drain()),
+            StdoutJson => Self::with_drain(config, get_json_drain(io::stdout())),
+            StderrJson => Self::with_drain(config, get_json_drain(io::stderr())),
             File(path) => Self::with_drain(config, get_file_drain(path)),
+            FileJson(path) => Self::with_drain(config, get_json_drain(open_log_file(path))),
+            RotatingFile(path, rotation) => {
+                Self::with_drain(config, get_rotating_file_drain(path, rotation))
+            }
+            #[cfg(feature = "syslog")]
+            Syslog(syslog_config) => Self::with_drain(config, get_syslog_drain(syslog_config)),
+            #[cfg(feature = "journald")]
+            Journald => Self::with_drain(config, get_journald_drain()),
         }
     }
 
@@ -406,6 +624,7 @@ impl GuardedLogger {
             drain_switch_ctrl,
             guard: Mutex::new(FlushGuard(Some(guard))),
             current_config: config.clone(),
+            levels: Mutex::new(initial_levels(config.level)),
         }
     }
 
@@ -415,6 +634,7 @@ impl GuardedLogger {
             drain_switch_ctrl: None,
             guard: Mutex::new(FlushGuard(None)),
             current_config: LoggingConfig::no_logging(),
+            levels: Mutex::new(LogLevels::new(Level::Error)),
         }
     }
 
@@ -531,4 +751,5 @@ macro_rules! trace(
 /// inclusion in user code. Usage: `use logging::macros::*;`.
 pub mod macros {
     pub use super::{crit, debug, error, info, trace, warn};
+    pub use super::{debug_limited, error_limited, info_limited, log_limited, warn_limited};
 }
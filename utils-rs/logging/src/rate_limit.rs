@@ -0,0 +1,203 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Rate limiting of log records, see `log_limited!` and the level specific variants
+
+use std::collections::BTreeMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Records logged per key in the current period
+struct Window {
+    start: Instant,
+    logged: u32,
+    suppressed: u32,
+}
+
+/// Allows at most `max_records` records per `period` for each key (typically a peer address).
+/// Each call site of the rate limited macros has its own limiter.
+pub struct RateLimiter {
+    max_records: u32,
+    period: Duration,
+    windows: Mutex<BTreeMap<String, Window>>,
+}
+
+impl RateLimiter {
+    pub const DEFAULT_MAX_RECORDS: u32 = 5;
+    pub const DEFAULT_PERIOD: Duration = Duration::from_secs(60);
+    /// Expired windows are pruned once there are more keys than this
+    const PRUNE_THRESHOLD: usize = 1024;
+
+    pub const fn new(max_records: u32, period: Duration) -> Self {
+        Self {
+            max_records,
+            period,
+            windows: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Accounts a record for `key`. Returns `None` when the record is to be suppressed,
+    /// otherwise the number of records suppressed for `key` in the previous period.
+    pub fn check(&self, key: &str) -> Option<u32> {
+        self.check_at(key, Instant::now())
+    }
+
+    fn check_at(&self, key: &str, now: Instant) -> Option<u32> {
+        let mut windows = self
+            .windows
+            .lock()
+            .expect("BUG: Poisoned rate limiter mutex");
+        if windows.len() > Self::PRUNE_THRESHOLD {
+            let period = self.period;
+            windows.retain(|_, window| now.duration_since(window.start) < period);
+        }
+        let window = windows.entry(key.to_string()).or_insert(Window {
+            start: now,
+            logged: 0,
+            suppressed: 0,
+        });
+
+        let mut previously_suppressed = 0;
+        if now.duration_since(window.start) >= self.period {
+            previously_suppressed = window.suppressed;
+            *window = Window {
+                start: now,
+                logged: 0,
+                suppressed: 0,
+            };
+        }
+        if window.logged < self.max_records {
+            window.logged += 1;
+            Some(previously_suppressed)
+        } else {
+            window.suppressed += 1;
+            None
+        }
+    }
+}
+
+/// Log a record in the global logger at most `max_records` times per `period` for each `key`,
+/// e.g. `log_limited!(Level::Warning, peer, 5, Duration::from_secs(60); "Error: {}", e)`.
+/// When `max_records` and `period` are omitted, `RateLimiter::DEFAULT_MAX_RECORDS` per
+/// `RateLimiter::DEFAULT_PERIOD` is used. The number of suppressed records is reported once
+/// the next period begins.
+#[macro_export]
+macro_rules! log_limited(
+    ($lvl:expr, $key:expr, $max_records:expr, $period:expr; $($args:tt)+) => {{
+        static LIMITER: $crate::RateLimiter = $crate::RateLimiter::new($max_records, $period);
+        if let Some(suppressed) = LIMITER.check(&$key.to_string()) {
+            if suppressed > 0 {
+                $crate::slog::slog_log!(
+                    $crate::LOGGER,
+                    $lvl,
+                    "",
+                    "Suppressed {} records like the following one", suppressed;
+                    "key" => %$key
+                );
+            }
+            $crate::slog::slog_log!($crate::LOGGER, $lvl, "", $($args)+);
+        }
+    }};
+    ($lvl:expr, $key:expr; $($args:tt)+) => {
+        $crate::log_limited!(
+            $lvl,
+            $key,
+            $crate::RateLimiter::DEFAULT_MAX_RECORDS,
+            $crate::RateLimiter::DEFAULT_PERIOD;
+            $($args)+
+        )
+    };
+);
+
+/// Log a rate limited error level record in the global logger, see `log_limited!`
+#[macro_export]
+macro_rules! error_limited(
+    ($($args:tt)+) => {
+        $crate::log_limited!($crate::Level::Error, $($args)+)
+    };
+);
+
+/// Log a rate limited warning level record in the global logger, see `log_limited!`
+#[macro_export]
+macro_rules! warn_limited(
+    ($($args:tt)+) => {
+        $crate::log_limited!($crate::Level::Warning, $($args)+)
+    };
+);
+
+/// Log a rate limited info level record in the global logger, see `log_limited!`
+#[macro_export]
+macro_rules! info_limited(
+    ($($args:tt)+) => {
+        $crate::log_limited!($crate::Level::Info, $($args)+)
+    };
+);
+
+/// Log a rate limited debug level record in the global logger, see `log_limited!`
+#[macro_export]
+macro_rules! debug_limited(
+    ($($args:tt)+) => {
+        $crate::log_limited!($crate::Level::Debug, $($args)+)
+    };
+);
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rate_limiter() {
+        let limiter = RateLimiter::new(2, Duration::from_secs(60));
+        let start = Instant::now();
+
+        assert_eq!(limiter.check_at("a", start), Some(0));
+        assert_eq!(limiter.check_at("a", start), Some(0));
+        assert_eq!(limiter.check_at("a", start), None);
+        assert_eq!(limiter.check_at("a", start), None);
+        // Keys are limited independently
+        assert_eq!(limiter.check_at("b", start), Some(0));
+
+        let later = start + Duration::from_secs(60);
+        assert_eq!(limiter.check_at("a", later), Some(2));
+        assert_eq!(limiter.check_at("a", later), Some(0));
+        assert_eq!(limiter.check_at("a", later), None);
+        assert_eq!(limiter.check_at("b", later), Some(0));
+    }
+
+    #[test]
+    fn test_rate_limiter_prune() {
+        let limiter = RateLimiter::new(1, Duration::from_secs(60));
+        let start = Instant::now();
+        for key in 0..=RateLimiter::PRUNE_THRESHOLD {
+            limiter.check_at(&key.to_string(), start);
+        }
+        limiter.check_at("last", start + Duration::from_secs(60));
+        assert_eq!(
+            limiter
+                .windows
+                .lock()
+                .expect("BUG: Poisoned rate limiter mutex")
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,217 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Log file that is rotated once it exceeds a configured size or age. Rotated files are named
+//! after the log file with a numeric suffix (`proxy.log.1` being the most recent one) and are
+//! optionally compressed by gzip (`proxy.log.1.gz`).
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+use flate2::write::GzEncoder;
+use flate2::Compression;
+
+/// When and how log files are rotated
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FileRotation {
+    /// Rotate the log file once it reaches this size in bytes
+    pub max_size: u64,
+    /// Optionally rotate the log file once it has been written to for this long
+    pub max_age: Option<Duration>,
+    /// Number of rotated files that are kept, the oldest ones are removed
+    pub max_files: usize,
+    /// Compress rotated files by gzip
+    pub compress: bool,
+}
+
+impl FileRotation {
+    pub const DEFAULT_MAX_SIZE: u64 = 10 * 1024 * 1024;
+    pub const DEFAULT_MAX_FILES: usize = 5;
+}
+
+impl Default for FileRotation {
+    fn default() -> Self {
+        Self {
+            max_size: Self::DEFAULT_MAX_SIZE,
+            max_age: None,
+            max_files: Self::DEFAULT_MAX_FILES,
+            compress: false,
+        }
+    }
+}
+
+/// Writer of a log file that is rotated according to `FileRotation`. Rotation is performed
+/// on flush so that a single record never spans multiple files.
+pub struct RotatingFile {
+    path: PathBuf,
+    rotation: FileRotation,
+    file: File,
+    /// Size of the current log file
+    size: u64,
+    /// Time when the current log file has been opened
+    opened: Instant,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, rotation: FileRotation) -> io::Result<Self> {
+        let file = Self::open_file(path)?;
+        let size = file.metadata()?.len();
+        Ok(Self {
+            path: path.to_path_buf(),
+            rotation,
+            file,
+            size,
+            opened: Instant::now(),
+        })
+    }
+
+    fn open_file(path: &Path) -> io::Result<File> {
+        OpenOptions::new().create(true).append(true).open(path)
+    }
+
+    /// Path of the rotated file with `index` (starting from 1)
+    fn rotated_path(&self, index: usize) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(format!(".{}", index));
+        if self.rotation.compress {
+            path.push(".gz");
+        }
+        path.into()
+    }
+
+    fn needs_rotation(&self) -> bool {
+        self.size >= self.rotation.max_size
+            || matches!(self.rotation.max_age, Some(max_age) if self.size > 0
+                && self.opened.elapsed() >= max_age)
+    }
+
+    /// Shifts all rotated files by one, moves the current file in place of the most recent
+    /// rotated file and starts a new log file
+    fn rotate(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.rotation.max_files == 0 {
+            fs::remove_file(&self.path)?;
+        } else {
+            let oldest = self.rotated_path(self.rotation.max_files);
+            if oldest.exists() {
+                fs::remove_file(&oldest)?;
+            }
+            for index in (1..self.rotation.max_files).rev() {
+                let rotated = self.rotated_path(index);
+                if rotated.exists() {
+                    fs::rename(&rotated, self.rotated_path(index + 1))?;
+                }
+            }
+            if self.rotation.compress {
+                let mut encoder =
+                    GzEncoder::new(File::create(self.rotated_path(1))?, Compression::default());
+                io::copy(&mut File::open(&self.path)?, &mut encoder)?;
+                encoder.finish()?;
+                fs::remove_file(&self.path)?;
+            } else {
+                fs::rename(&self.path, self.rotated_path(1))?;
+            }
+        }
+        self.file = Self::open_file(&self.path)?;
+        self.size = 0;
+        self.opened = Instant::now();
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let written = self.file.write(buf)?;
+        self.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()?;
+        if self.needs_rotation() {
+            self.rotate()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+
+    fn write_record(file: &mut RotatingFile, record: &str) {
+        file.write_all(record.as_bytes())
+            .expect("BUG: Cannot write record");
+        file.flush().expect("BUG: Cannot flush record");
+    }
+
+    #[test]
+    fn test_rotation_by_size() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("test.log");
+        let rotation = FileRotation {
+            max_size: 10,
+            max_files: 2,
+            ..Default::default()
+        };
+        let mut file = RotatingFile::open(&path, rotation).expect("BUG: Cannot open log file");
+
+        write_record(&mut file, "first\n");
+        write_record(&mut file, "second\n");
+        write_record(&mut file, "third\n");
+        write_record(&mut file, "fourth\n");
+        write_record(&mut file, "fifth\n");
+
+        let read = |path: PathBuf| fs::read_to_string(path).expect("BUG: Cannot read log file");
+        assert_eq!(read(path.clone()), "fifth\n");
+        assert_eq!(read(dir.path().join("test.log.1")), "third\nfourth\n");
+        assert_eq!(read(dir.path().join("test.log.2")), "first\nsecond\n");
+        assert!(!dir.path().join("test.log.3").exists());
+    }
+
+    #[test]
+    fn test_rotation_by_age_with_compression() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("test.log");
+        let rotation = FileRotation {
+            max_age: Some(Duration::from_secs(0)),
+            compress: true,
+            ..Default::default()
+        };
+        let mut file = RotatingFile::open(&path, rotation).expect("BUG: Cannot open log file");
+
+        write_record(&mut file, "record\n");
+
+        let mut content = String::new();
+        GzDecoder::new(
+            File::open(dir.path().join("test.log.1.gz")).expect("BUG: Rotated file missing"),
+        )
+        .read_to_string(&mut content)
+        .expect("BUG: Cannot decompress rotated file");
+        assert_eq!(content, "record\n");
+        assert_eq!(fs::metadata(&path).expect("BUG: Log file missing").len(), 0);
+    }
+}
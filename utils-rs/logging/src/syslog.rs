@@ -0,0 +1,248 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Drain that sends records to a syslog daemon formatted according to RFC 5424
+
+use std::fmt::Write as _;
+use std::io;
+use std::net::{SocketAddr, UdpSocket};
+use std::os::unix::net::UnixDatagram;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use slog::{Drain, OwnedKVList, Record};
+
+use crate::system::{kv_pairs, program_name, severity};
+
+/// Where to send syslog messages
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SyslogAddress {
+    /// Remote or local syslog daemon listening on UDP
+    Udp(SocketAddr),
+    /// Local syslog daemon listening on a Unix datagram socket
+    Unix(PathBuf),
+}
+
+impl SyslogAddress {
+    pub const DEFAULT_UNIX_PATH: &'static str = "/dev/log";
+}
+
+impl Default for SyslogAddress {
+    fn default() -> Self {
+        Self::Unix(Self::DEFAULT_UNIX_PATH.into())
+    }
+}
+
+/// Parses either a socket address (`127.0.0.1:514`) for UDP or a path to a Unix socket
+impl FromStr for SyslogAddress {
+    type Err = std::convert::Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s.parse::<SocketAddr>() {
+            Ok(addr) => Self::Udp(addr),
+            Err(_) => Self::Unix(s.into()),
+        })
+    }
+}
+
+/// Syslog facility of the sent messages
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Facility {
+    User,
+    Daemon,
+    Local0,
+    Local1,
+    Local2,
+    Local3,
+    Local4,
+    Local5,
+    Local6,
+    Local7,
+}
+
+impl Facility {
+    fn code(self) -> u8 {
+        match self {
+            Self::User => 1,
+            Self::Daemon => 3,
+            Self::Local0 => 16,
+            Self::Local1 => 17,
+            Self::Local2 => 18,
+            Self::Local3 => 19,
+            Self::Local4 => 20,
+            Self::Local5 => 21,
+            Self::Local6 => 22,
+            Self::Local7 => 23,
+        }
+    }
+}
+
+/// Syslog target configuration
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SyslogConfig {
+    pub address: SyslogAddress,
+    pub facility: Facility,
+    /// `APP-NAME` field of the messages, name of the executable is used when not specified
+    pub app_name: Option<String>,
+}
+
+impl Default for SyslogConfig {
+    fn default() -> Self {
+        Self {
+            address: Default::default(),
+            facility: Facility::Daemon,
+            app_name: None,
+        }
+    }
+}
+
+enum Socket {
+    Udp(UdpSocket),
+    Unix(UnixDatagram),
+}
+
+/// Drain that sends each record as a single datagram. Key-value pairs of the record are
+/// appended to the message text as `key=value`.
+pub struct SyslogDrain {
+    socket: Socket,
+    facility: Facility,
+    hostname: String,
+    app_name: String,
+    pid: u32,
+}
+
+impl SyslogDrain {
+    pub fn connect(config: &SyslogConfig) -> io::Result<Self> {
+        let socket = match &config.address {
+            SyslogAddress::Udp(addr) => {
+                let local_addr: SocketAddr = if addr.is_ipv4() {
+                    ([0, 0, 0, 0], 0).into()
+                } else {
+                    ([0u16; 8], 0).into()
+                };
+                let socket = UdpSocket::bind(local_addr)?;
+                socket.connect(addr)?;
+                Socket::Udp(socket)
+            }
+            SyslogAddress::Unix(path) => {
+                let socket = UnixDatagram::unbound()?;
+                socket.connect(path)?;
+                Socket::Unix(socket)
+            }
+        };
+        Ok(Self {
+            socket,
+            facility: config.facility,
+            hostname: hostname::get()
+                .ok()
+                .and_then(|hostname| hostname.into_string().ok())
+                .unwrap_or_else(|| "-".to_string()),
+            app_name: config.app_name.clone().unwrap_or_else(program_name),
+            pid: std::process::id(),
+        })
+    }
+
+    /// Formats the record as `<PRI>1 TIMESTAMP HOSTNAME APP-NAME PROCID MSGID SD MSG`, message
+    /// ID is the module of the record and structured data are left out
+    fn format(&self, record: &Record, values: &OwnedKVList) -> String {
+        let mut message = format!(
+            "<{}>1 {} {} {} {} {} - {}",
+            self.facility.code() * 8 + severity(record.level()),
+            chrono::Local::now().to_rfc3339_opts(chrono::SecondsFormat::Micros, false),
+            self.hostname,
+            self.app_name,
+            self.pid,
+            record.module(),
+            record.msg()
+        );
+        for (key, value) in kv_pairs(record, values) {
+            write!(message, " {}={}", key, value).expect("BUG: Cannot format syslog message");
+        }
+        message
+    }
+}
+
+impl Drain for SyslogDrain {
+    type Ok = ();
+    type Err = io::Error;
+
+    fn log(&self, record: &Record, values: &OwnedKVList) -> io::Result<()> {
+        let message = self.format(record, values);
+        match &self.socket {
+            Socket::Udp(socket) => socket.send(message.as_bytes()),
+            Socket::Unix(socket) => socket.send(message.as_bytes()),
+        }
+        .map(|_| ())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use slog::{info, o, Logger};
+
+    #[test]
+    fn test_syslog_udp() {
+        let server = UdpSocket::bind("127.0.0.1:0").expect("BUG: Cannot bind server");
+        let config = SyslogConfig {
+            address: SyslogAddress::Udp(server.local_addr().expect("BUG: No local address")),
+            facility: Facility::Local0,
+            app_name: Some("test-app".to_string()),
+        };
+        let drain = SyslogDrain::connect(&config).expect("BUG: Cannot connect");
+        let logger = Logger::root(drain.ignore_res(), o!("conn" => 7));
+
+        info!(logger, "Hello syslog"; "peer" => "127.0.0.1:3336");
+
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).expect("BUG: Cannot receive");
+        let message = String::from_utf8_lossy(&buf[..len]);
+        let fields: Vec<_> = message.splitn(8, ' ').collect();
+        assert_eq!(fields[0], "<134>1");
+        assert_eq!(fields[3], "test-app");
+        assert_eq!(fields[4], std::process::id().to_string());
+        assert_eq!(fields[5], module_path!());
+        assert_eq!(fields[6], "-");
+        assert_eq!(fields[7], "Hello syslog peer=127.0.0.1:3336 conn=7");
+    }
+
+    #[test]
+    fn test_syslog_unix() {
+        let dir = tempfile::tempdir().expect("BUG: Cannot create directory");
+        let path = dir.path().join("log.sock");
+        let server = UnixDatagram::bind(&path).expect("BUG: Cannot bind server");
+        let drain = SyslogDrain::connect(&SyslogConfig {
+            address: path.to_str().expect("BUG: Invalid path").parse().unwrap(),
+            ..Default::default()
+        })
+        .expect("BUG: Cannot connect");
+        let logger = Logger::root(drain.ignore_res(), o!());
+
+        slog::error!(logger, "Failure");
+
+        let mut buf = [0u8; 1024];
+        let len = server.recv(&mut buf).expect("BUG: Cannot receive");
+        let message = String::from_utf8_lossy(&buf[..len]);
+        assert!(message.starts_with("<27>1 "), "{}", message);
+        assert!(message.ends_with(" - Failure"), "{}", message);
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright (C) 2021  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Helpers shared by drains that pass records to system logging facilities
+
+use std::env;
+use std::fmt;
+
+use slog::{Key, Level, OwnedKVList, Record, Serializer, KV};
+
+/// Name of the running program used to identify its records in system logs
+pub(crate) fn program_name() -> String {
+    env::current_exe()
+        .ok()
+        .and_then(|path| {
+            path.file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        })
+        .unwrap_or_else(|| "-".to_string())
+}
+
+/// Maps slog levels to syslog severities (also used by journald), there is no counterpart of
+/// `Notice`
+pub(crate) fn severity(level: Level) -> u8 {
+    match level {
+        Level::Critical => 2,
+        Level::Error => 3,
+        Level::Warning => 4,
+        Level::Info => 6,
+        Level::Debug | Level::Trace => 7,
+    }
+}
+
+/// Collects key-value pairs of the record and the logger (in this order) as strings, the drains
+/// cannot represent them natively
+pub(crate) fn kv_pairs(record: &Record, values: &OwnedKVList) -> Vec<(String, String)> {
+    let mut collector = Collector(Vec::new());
+    // Formatting into a string never fails
+    let _ = record.kv().serialize(record, &mut collector);
+    let _ = values.serialize(record, &mut collector);
+    collector.0
+}
+
+struct Collector(Vec<(String, String)>);
+
+impl Serializer for Collector {
+    fn emit_arguments(&mut self, key: Key, val: &fmt::Arguments) -> slog::Result {
+        self.0.push((key.to_string(), val.to_string()));
+        Ok(())
+    }
+}
@@ -0,0 +1,70 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Test of JSON logging output.
+//!
+//! **Warning**: Each logging test needs to be in a separate files
+//! due to global LOGGER initialization
+
+use std::env;
+use std::fs;
+
+use ii_logging::macros::*;
+use ii_logging::{self, Level, LoggingConfig, LoggingTarget, LOGGER};
+
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_logging_json() {
+    const LOG_MSG: &str = "Hello, JSON!";
+
+    env::set_var("RUST_LOG", "");
+
+    let temp_file = NamedTempFile::new().expect("Could not create temporary file");
+    let config = LoggingConfig {
+        target: LoggingTarget::FileJson(temp_file.path().into()),
+        level: Level::Trace,
+        drain_channel_size: LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE,
+    };
+
+    ii_logging::set_logger_config(config);
+    let flush_guard = LOGGER.take_guard();
+
+    info!("{}", LOG_MSG; "peer" => "10.0.0.1:3333", "shares" => 5);
+    drop(flush_guard);
+
+    let log_contents = fs::read_to_string(temp_file.path()).expect("Could not read back log file");
+    let record: serde_json::Value = serde_json::from_str(
+        log_contents
+            .lines()
+            .next()
+            .expect("No record has been logged"),
+    )
+    .expect("Log record is not a valid JSON");
+
+    assert_eq!(record["msg"], LOG_MSG);
+    assert_eq!(record["level"], "INFO");
+    assert_eq!(record["target"], "json");
+    assert_eq!(record["peer"], "10.0.0.1:3333");
+    assert_eq!(record["shares"], 5);
+    assert!(record["ts"].is_string());
+}
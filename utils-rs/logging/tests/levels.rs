@@ -0,0 +1,125 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+
+//! Test of changing log levels at runtime.
+//!
+//! **Warning**: Each logging test needs to be in a separate files
+//! due to global LOGGER initialization
+
+use std::env;
+use std::fs;
+
+use ii_logging::macros::*;
+use ii_logging::{self, Level, LogLevels, LoggingConfig, LoggingTarget, LOGGER};
+
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_logging_levels() {
+    env::set_var("RUST_LOG", "info,ii_stratum=debug");
+
+    let temp_file = NamedTempFile::new().expect("Could not create temporary file");
+    let config = LoggingConfig {
+        target: LoggingTarget::File(temp_file.path().into()),
+        level: Level::Info,
+        drain_channel_size: LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE,
+    };
+
+    ii_logging::set_logger_config(config);
+    let flush_guard = LOGGER.take_guard();
+    assert_eq!(LOGGER.levels().to_filters(), "info,ii_stratum=debug");
+
+    debug!("Hidden before module level change");
+    let flush_guard = {
+        drop(flush_guard);
+        LOGGER.set_module_level("levels", Some(Level::Debug))
+    };
+    assert_eq!(
+        LOGGER.levels().to_filters(),
+        "info,ii_stratum=debug,levels=debug"
+    );
+    debug!("Shown with module level");
+
+    let flush_guard = {
+        drop(flush_guard);
+        LOGGER.set_module_level("levels", None)
+    };
+    debug!("Hidden after module level reset");
+
+    let flush_guard = {
+        drop(flush_guard);
+        LOGGER.set_level(Level::Debug)
+    };
+    debug!("Shown with default level");
+
+    let flush_guard = {
+        drop(flush_guard);
+        LOGGER.set_filters("warn,levels=info".to_string())
+    };
+    assert_eq!(LOGGER.levels().to_filters(), "warning,levels=info");
+    debug!("Hidden after setting filters");
+    drop(flush_guard);
+
+    let log_contents = fs::read_to_string(temp_file.path()).expect("Could not read back log file");
+    assert!(!log_contents.contains("Hidden"));
+    assert!(log_contents.contains("Shown with module level"));
+    assert!(log_contents.contains("Shown with default level"));
+
+    // Concurrent changes of different modules are all kept
+    let threads: Vec<_> = (0..8)
+        .map(|i| {
+            std::thread::spawn(move || {
+                LOGGER.set_module_level(&format!("module{}", i), Some(Level::Debug))
+            })
+        })
+        .collect();
+    let flush_guards: Vec<_> = threads
+        .into_iter()
+        .map(|thread| thread.join().expect("BUG: Thread panicked"))
+        .collect();
+    let levels = LOGGER.levels();
+    for i in 0..8 {
+        assert_eq!(
+            levels.modules.get(&format!("module{}", i)),
+            Some(&Level::Debug)
+        );
+    }
+    drop(flush_guards);
+}
+
+#[test]
+fn test_levels_from_filters() {
+    let levels = LogLevels::from_filters("a=debug, b ,c=,warn,d=off,e=loud/regex", Level::Info);
+    assert_eq!(levels.default, Level::Warning);
+    assert_eq!(
+        levels.modules.into_iter().collect::<Vec<_>>(),
+        vec![
+            ("a".to_string(), Level::Debug),
+            ("b".to_string(), Level::Trace),
+            ("c".to_string(), Level::Trace),
+        ]
+    );
+    assert_eq!(
+        LogLevels::from_filters("", Level::Info),
+        LogLevels::new(Level::Info)
+    );
+}
@@ -0,0 +1,74 @@
+// Copyright (C) 2019  Braiins Systems s.r.o.
+//
+// This file is part of Braiins Open-Source Initiative (BOSI).
+//
+// BOSI is free software: you can redistribute it and/or modify
+// it under the terms of the GNU General Public License as published by
+// the Free Software Foundation, either version 3 of the License, or
+// (at your option) any later version.
+//
+// This program is distributed in the hope that it will be useful,
+// but WITHOUT ANY WARRANTY; without even the implied warranty of
+// MERCHANTABILITY or FITNESS FOR A PARTICULAR PURPOSE.  See the
+// GNU General Public License for more details.
+//
+// You should have received a copy of the GNU General Public License
+// along with this program.  If not, see <https://www.gnu.org/licenses/>.
+//
+// Please, keep in mind that we may also license BOSI or any part thereof
+// under a proprietary license. For more information on the terms and conditions
+// of such proprietary license or if you have any other questions, please
+// contact us at opensource@braiins.com.
+//! Test of rate limited logging macros.
+//!
+//! **Warning**: Each logging test needs to be in a separate files
+//! due to global LOGGER initialization
+
+use std::env;
+use std::fs;
+use std::time::Duration;
+
+use ii_logging::macros::*;
+use ii_logging::{self, Level, LoggingConfig, LoggingTarget, LOGGER};
+
+use tempfile::NamedTempFile;
+
+#[test]
+fn test_logging_rate_limit() {
+    env::set_var("RUST_LOG", "");
+
+    let temp_file = NamedTempFile::new().expect("Could not create temporary file");
+    let config = LoggingConfig {
+        target: LoggingTarget::File(temp_file.path().into()),
+        level: Level::Trace,
+        drain_channel_size: LoggingConfig::ASYNC_LOGGER_DRAIN_CHANNEL_SIZE,
+    };
+
+    ii_logging::set_logger_config(config);
+    let flush_guard = LOGGER.take_guard();
+
+    for peer in &["10.0.0.1:3333", "10.0.0.2:3333"] {
+        for i in 0..10 {
+            warn_limited!(peer, 3, Duration::from_secs(60); "Connection error {}", i; "peer" => peer);
+        }
+    }
+    for i in 0..10 {
+        info_limited!("default"; "Default limit {}", i);
+    }
+    drop(flush_guard);
+
+    let log_contents = fs::read_to_string(temp_file.path()).expect("Could not read back log file");
+    let count = |msg: &str| {
+        log_contents
+            .lines()
+            .filter(|line| line.contains(msg))
+            .count()
+    };
+    assert_eq!(count("Connection error"), 6);
+    assert_eq!(count("peer: 10.0.0.1:3333"), 3);
+    assert_eq!(count("Connection error 3"), 0);
+    assert_eq!(
+        count("Default limit"),
+        ii_logging::RateLimiter::DEFAULT_MAX_RECORDS as usize
+    );
+}
@@ -26,9 +26,22 @@ use syn::parse::discouraged::Speculative;
 use syn::parse::{Parse, ParseStream};
 use syn::{parse_str, Expr, Generics, Ident, ItemEnum, ItemStruct, Lit, Result, Token, Type};
 
+/// Arguments of the `#[id]` attribute:
+/// ```ignore
+/// #[id(<expr> [type <type>] [scope <expr> [type <type>]])]
+/// ```
+///
+/// When a scope is specified, the type gets an additional `Id<(ScopeType, IdType)>`
+/// implementation so that ids that are only unique within their scope (such as message
+/// types within a protocol extension) can be told apart.
 pub struct Args {
     pub id: Expr,
     pub ty: Type,
+    pub scope: Option<(Expr, Type)>,
+}
+
+mod keyword {
+    syn::custom_keyword!(scope);
 }
 
 impl Args {
@@ -69,25 +82,41 @@ impl Args {
     }
 }
 
-impl Parse for Args {
-    fn parse(input: ParseStream) -> Result<Self> {
+impl Args {
+    /// Parses an id value followed by an optional explicit type
+    fn parse_id(input: ParseStream) -> Result<(Expr, Type)> {
         let id: Expr = input.parse()?;
 
-        if input.is_empty() {
-            let ty = match Args::infer_type(&id) {
-                Some(ty) => ty,
-                None => {
-                    return Err(input.error("Could not infer ID type. Please specify it explicitly using the #[id(<expr> type <type>)] syntax."))
-                }
-            };
-
-            Ok(Args { id, ty })
-        } else {
+        if input.peek(Token![type]) {
             input.parse::<Token![type]>()?;
             let ty: Type = input.parse()?;
 
-            Ok(Args { id, ty })
+            Ok((id, ty))
+        } else {
+            match Args::infer_type(&id) {
+                Some(ty) => Ok((id, ty)),
+                None => Err(input.error("Could not infer ID type. Please specify it explicitly using the #[id(<expr> type <type>)] syntax.")),
+            }
+        }
+    }
+}
+
+impl Parse for Args {
+    fn parse(input: ParseStream) -> Result<Self> {
+        let (id, ty) = Args::parse_id(input)?;
+
+        let scope = if input.peek(keyword::scope) {
+            input.parse::<keyword::scope>()?;
+            Some(Args::parse_id(input)?)
+        } else {
+            None
+        };
+
+        if !input.is_empty() {
+            return Err(input.error("Unexpected tokens, expected #[id(<expr> [type <type>] [scope <expr> [type <type>]])]"));
         }
+
+        Ok(Args { id, ty, scope })
     }
 }
 
@@ -138,15 +167,25 @@ impl ToTokens for Item {
 }
 
 pub fn expand(args: Args, item: Item) -> TokenStream {
-    let (id, ty) = (args.id, args.ty);
+    let (id, ty) = (&args.id, &args.ty);
     let (name, generics) = item.decl();
     let (impl_generics, ty_generics, where_clause) = generics.split_for_impl();
 
+    let scoped_impl = args.scope.map(|(scope, scope_ty)| {
+        quote!(
+            impl #impl_generics ::ii_unvariant::Id<(#scope_ty, #ty)> for #name #ty_generics #where_clause {
+                const ID: (#scope_ty, #ty) = (#scope, #id);
+            }
+        )
+    });
+
     quote!(
         #item
 
         impl #impl_generics ::ii_unvariant::Id<#ty> for #name #ty_generics #where_clause {
             const ID: #ty = #id;
         }
+
+        #scoped_impl
     )
 }
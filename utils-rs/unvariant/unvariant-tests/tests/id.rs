@@ -54,6 +54,18 @@ fn id_macro() {
     let id: &str = IdStr::ID;
     assert_eq!(id, "ii");
 
+    #[id(0x11u8 scope 0x0001u16)]
+    struct IdScoped;
+    let id: u8 = IdScoped::ID;
+    assert_eq!(id, 0x11);
+    let id: (u16, u8) = IdScoped::ID;
+    assert_eq!(id, (0x0001, 0x11));
+
+    #[id(0x11u8 type u8 scope 3 type u64)]
+    struct IdScopedExplicit;
+    let id: (u64, u8) = IdScopedExplicit::ID;
+    assert_eq!(id, (3, 0x11));
+
     #[id((3, "ii") type (u8, &'static str))]
     struct IdComplex;
     let id: (u8, &str) = IdComplex::ID;
@@ -20,7 +20,7 @@
 // of such proprietary license or if you have any other questions, please
 // contact us at opensource@braiins.com.
 
-use ii_unvariant::{unvariant, Id};
+use ii_unvariant::{id, unvariant, GetId, Id};
 
 mod common;
 use common::*;
@@ -70,3 +70,51 @@ fn unvariant_macro_try() {
     let res = get_value(Frame::new_unknown());
     assert_eq!(res, Err(0xff));
 }
+
+#[test]
+fn unvariant_macro_scoped() {
+    /// Frame carrying an ID that is only unique within its scope
+    struct ScopedFrame(u16, u8);
+
+    impl GetId for ScopedFrame {
+        type Id = (u16, u8);
+
+        fn get_id(&self) -> (u16, u8) {
+            (self.0, self.1)
+        }
+    }
+
+    #[id(0x00u8 scope 0x0000u16)]
+    struct Base;
+
+    #[id(0x00u8 scope 0x0001u16)]
+    struct Extension;
+
+    impl From<ScopedFrame> for Base {
+        fn from(_frame: ScopedFrame) -> Self {
+            Self
+        }
+    }
+
+    impl From<ScopedFrame> for Extension {
+        fn from(_frame: ScopedFrame) -> Self {
+            Self
+        }
+    }
+
+    let get_value = |frame: ScopedFrame| -> Result<&str, (u16, u8)> {
+        unvariant!(frame {
+            _base: Base => Ok("base"),
+            _ext: Extension => Ok("extension"),
+            id: _ => Err(id),
+        })
+    };
+
+    assert_eq!(get_value(ScopedFrame(0x0000, 0x00)), Ok("base"));
+    assert_eq!(get_value(ScopedFrame(0x0001, 0x00)), Ok("extension"));
+    assert_eq!(get_value(ScopedFrame(0x0002, 0x00)), Err((0x0002, 0x00)));
+
+    // The flat id is still available
+    let id: u8 = Extension::ID;
+    assert_eq!(id, 0x00);
+}
@@ -34,6 +34,19 @@
 //! The `Id` trait is used to mark specific Rust types with an ID.
 //! There is also a helper macro `#[id]` which derives the `Id` trait for a type.
 //!
+//! IDs don't have to come from a single flat namespace. When an ID is only unique
+//! within some scope (eg. a message type within a protocol extension),
+//! the `#[id]` macro can be given a scope:
+//!
+//! ```ignore
+//! #[id(0x00u8 scope 0x0001u16)]
+//! struct Foo;
+//! ```
+//!
+//! This implements both `Id<u8>` and `Id<(u16, u8)>` for `Foo`. A variant type
+//! whose `GetId::Id` is the `(scope, id)` tuple then dispatches on the pair
+//! and types sharing an ID in different scopes don't collide.
+//!
 //! ## `unvariant!()`
 //!
 //! The `unvariant!()` macro works like a `match` statement (it is an expression).